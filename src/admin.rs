@@ -0,0 +1,679 @@
+//! Admin HTTP API, served on its own listener next to the proxy.
+//!
+//! The listener is meant for a private address. With a token set, every request must
+//! carry it as `Authorization: Bearer <token>` or is answered 401.
+//!
+//! Routes:
+//! - `GET /conflicts`: recent provider detection conflicts, most recent first
+//! - `GET /billing/periods`: per-tenant billing period totals, ended periods first
+//! - `POST /billing/tenants/{tenant}/close`: freeze the totals of the tenant's most
+//!   recent ended billing period for export
+//! - `GET /config/versions`: kept config versions with their diffs, applied one first
+//! - `GET /config/diff`: what the applied config version changed
+//! - `POST /config/reload`: re-read the config file and apply it, or answer 422 with
+//!   what is wrong in it
+//! - `POST /config/rollback`: discard the applied config version and apply the
+//!   previous one again
+//! - `GET /config/tenants`: the budgets, rate limits and virtual keys of the applied
+//!   config, in the form `PUT /config/tenants` takes
+//! - `PUT /config/tenants`: replace the budgets, rate limits and virtual keys of the
+//!   applied config with the JSON body and apply it as a new version, or answer 422
+//!   with what is wrong in it; the next config file reload replaces them
+//! - `POST /config/tenants/dry-run`: check the JSON body like `PUT /config/tenants`
+//!   and answer with the diff it would apply, without applying it
+//! - `POST /capabilities/tenants/{tenant}`: mint a capability token for the tenant;
+//!   the JSON body is the scope (`models`, `max_tokens`, `ttl_secs`, `tags`)
+//! - `GET /capture/windows`: open payload capture windows
+//! - `POST /capture/windows`: open a capture window; the JSON body is its filter
+//!   (`tenant`, `route`), `sample_rate` and `duration_secs`
+//! - `DELETE /capture/windows/{id}`: close a capture window before it expires
+//! - `GET /keys`: in-flight requests, recent error rate and token consumption and
+//!   last request of each client key
+//! - `GET /keys/idle`: keys without requests for the idle period, longest idle first
+//! - `GET /budgets`: spend within each budget of every tenant with spend counted
+//! - `GET /budgets/tenants/{tenant}`: spend within each budget of the tenant
+//! - `GET /providers`: the applied provider registry version, with its custom
+//!   providers and detection tuning
+//! - `PUT /providers`: compile the JSON body (`providers`, `detection`) into a new
+//!   provider registry and apply it, or answer 422 with what is wrong in it; the next
+//!   config file reload replaces it
+//! - `GET /pools`: upstream pools with their paths and current canary share
+//! - `PUT /pools/{pool}/canary`: set the share of the pool's requests sent to its
+//!   canary pool to the JSON body's `percent`; the next config file reload replaces it
+//! - `GET /providers/versions`: kept provider registry versions, applied one first
+//! - `POST /providers/rollback`: discard the applied provider registry version and
+//!   apply the previous one again
+//! - `GET /virtual-keys`: accepted virtual keys with their models, without secrets
+//! - `POST /virtual-keys/issue`: generate a virtual key; the answer has the key, to
+//!   hand to the client, and the `key_hash` to declare it with in the config file
+//! - `GET /upstreams`: upstreams with their health, weight, latency and concurrency
+//! - `GET /upstreams/{address}`: one upstream
+//! - `DELETE /upstreams/{address}`: stop sending new requests to the upstream, letting
+//!   those in flight complete
+//! - `POST /upstreams`: send new requests to the upstream of the JSON body's `address`
+//!   again; only upstreams the gateway was started with can be added
+//! - `PUT /upstreams/{address}/weight`: set the share of its traffic the upstream
+//!   keeps to the JSON body's `weight`, from 0 to 1
+//! - `POST /caches/flush`: drop every response of the response and semantic caches
+//! - `GET /maintenance`: whether the gateway is in maintenance mode
+//! - `PUT /maintenance`: turn maintenance mode on or off with the JSON body's
+//!   `enabled`; in maintenance mode proxied requests are answered 503
+
+use async_trait::async_trait;
+use http::{Response, StatusCode, header};
+use pingora::apps::http_app::ServeHttp;
+use pingora::protocols::http::ServerSession;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::billing::BillingLedger;
+use crate::budget::SpendBudgets;
+#[cfg(feature = "config")]
+use crate::config::{ConfigError, ConfigStore, TenantSections};
+use crate::crypto::constant_time_eq;
+use crate::key_stats::KeyStats;
+use crate::pipeline::Pipeline;
+use crate::pipeline::response_cache::ResponseCache;
+use crate::pipeline::semantic_cache::SemanticCache;
+use crate::provider::conflicts::ConflictLog;
+use crate::provider::snapshots::{RegistryConfig, RegistryError, RegistrySource};
+#[cfg(feature = "capability")]
+use crate::proxy::capability::{CapabilityError, CapabilityGrant, CapabilityTokens};
+use crate::proxy::capture::{CaptureRequest, PayloadCapture};
+#[cfg(feature = "virtual-keys")]
+use crate::proxy::virtual_keys::VirtualKeys;
+use crate::upstream::{PoolError, Upstream};
+
+pub struct AdminApp {
+    conflicts: Arc<ConflictLog>,
+    billing: Option<Arc<BillingLedger>>,
+    #[cfg(feature = "config")]
+    config: Option<Arc<ConfigStore>>,
+    #[cfg(feature = "capability")]
+    capabilities: Option<Arc<CapabilityTokens>>,
+    capture: Option<Arc<PayloadCapture>>,
+    key_stats: Option<Arc<KeyStats>>,
+    budgets: Option<Arc<SpendBudgets>>,
+    pipeline: Option<Arc<Pipeline>>,
+    upstreams: Vec<Arc<Upstream>>,
+    response_cache: Option<Arc<ResponseCache>>,
+    semantic_cache: Option<Arc<SemanticCache>>,
+    /// Bearer token every request must carry, when set
+    token: Option<String>,
+}
+
+/// Largest request body the admin API reads
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+impl AdminApp {
+    pub fn new(conflicts: Arc<ConflictLog>) -> Self {
+        Self {
+            conflicts,
+            billing: None,
+            #[cfg(feature = "config")]
+            config: None,
+            #[cfg(feature = "capability")]
+            capabilities: None,
+            capture: None,
+            key_stats: None,
+            budgets: None,
+            pipeline: None,
+            upstreams: Vec::new(),
+            response_cache: None,
+            semantic_cache: None,
+            token: None,
+        }
+    }
+
+    pub fn with_billing(mut self, billing: Arc<BillingLedger>) -> Self {
+        self.billing = Some(billing);
+        self
+    }
+
+    #[cfg(feature = "config")]
+    pub fn with_config(mut self, config: Arc<ConfigStore>) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    #[cfg(feature = "capability")]
+    pub fn with_capabilities(mut self, capabilities: Arc<CapabilityTokens>) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    pub fn with_payload_capture(mut self, capture: Arc<PayloadCapture>) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    pub fn with_key_stats(mut self, stats: Arc<KeyStats>) -> Self {
+        self.key_stats = Some(stats);
+        self
+    }
+
+    pub fn with_spend_budgets(mut self, budgets: Arc<SpendBudgets>) -> Self {
+        self.budgets = Some(budgets);
+        self
+    }
+
+    /// Serve and replace the provider registry of this pipeline, and list its virtual
+    /// keys
+    pub fn with_pipeline(mut self, pipeline: Arc<Pipeline>) -> Self {
+        self.pipeline = Some(pipeline);
+        self
+    }
+
+    pub fn with_upstreams(mut self, upstreams: Vec<Arc<Upstream>>) -> Self {
+        self.upstreams = upstreams;
+        self
+    }
+
+    pub fn with_response_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.response_cache = Some(cache);
+        self
+    }
+
+    pub fn with_semantic_cache(mut self, cache: Arc<SemanticCache>) -> Self {
+        self.semantic_cache = Some(cache);
+        self
+    }
+
+    /// Require `Authorization: Bearer <token>` on every request
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Whether a request with this `Authorization` header value may use the API: any
+    /// request when no token is set, else one bearing the token
+    pub fn is_authorized(&self, authorization: Option<&[u8]>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        authorization
+            .and_then(|value| value.strip_prefix(b"Bearer "))
+            .is_some_and(|presented| constant_time_eq(presented.trim_ascii(), token.as_bytes()))
+    }
+
+    /// Route a request without a body to its handler
+    pub fn handle(&self, method: &str, path: &str) -> Response<Vec<u8>> {
+        self.handle_request(method, path, &[])
+    }
+
+    /// Route a request to its handler
+    pub fn handle_request(&self, method: &str, path: &str, body: &[u8]) -> Response<Vec<u8>> {
+        if let Some(rest) = path.strip_prefix("/capabilities/") {
+            return self.handle_capabilities(method, rest, body);
+        }
+        if let Some(rest) = path.strip_prefix("/capture/") {
+            return self.handle_capture(method, rest, body);
+        }
+        if path == "/keys" || path.starts_with("/keys/") {
+            return self.handle_keys(method, &path[5..]);
+        }
+        if let Some(rest) = path.strip_prefix("/budgets") {
+            return self.handle_budgets(method, rest);
+        }
+        if let Some(rest) = path.strip_prefix("/billing/") {
+            return self.handle_billing(method, rest);
+        }
+        if let Some(rest) = path.strip_prefix("/config/") {
+            return self.handle_config(method, rest, body);
+        }
+        if let Some(rest) = path.strip_prefix("/pools") {
+            return self.handle_pools(method, rest, body);
+        }
+        if let Some(rest) = path.strip_prefix("/providers") {
+            return self.handle_providers(method, rest, body);
+        }
+        if let Some(rest) = path.strip_prefix("/virtual-keys") {
+            return self.handle_virtual_keys(method, rest);
+        }
+        if let Some(rest) = path.strip_prefix("/upstreams") {
+            return self.handle_upstreams(method, rest, body);
+        }
+        match (method, path) {
+            ("GET", "/conflicts") => json(StatusCode::OK, &self.conflicts.recent()),
+            ("POST", "/caches/flush") => self.flush_caches(),
+            ("GET" | "PUT", "/maintenance") => self.handle_maintenance(method, body),
+            (_, "/conflicts" | "/caches/flush" | "/maintenance") => {
+                text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    fn handle_upstreams(&self, method: &str, path: &str, body: &[u8]) -> Response<Vec<u8>> {
+        let find = |address: &str| {
+            self.upstreams
+                .iter()
+                .find(|upstream| upstream.address() == address)
+        };
+        let upstream = path
+            .strip_prefix('/')
+            .filter(|address| !address.is_empty() && !address.contains('/'));
+        let weight = path
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_suffix("/weight"))
+            .filter(|address| !address.is_empty() && !address.contains('/'));
+        match (method, path, upstream, weight) {
+            ("GET", "", _, _) => {
+                let statuses: Vec<_> = self.upstreams.iter().map(|u| u.status()).collect();
+                json(StatusCode::OK, &statuses)
+            }
+            ("POST", "", _, _) => {
+                let request: UpstreamRequest = match serde_json::from_slice(body) {
+                    Ok(request) => request,
+                    Err(e) => return text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+                };
+                let Some(upstream) = find(&request.address) else {
+                    return text(
+                        StatusCode::NOT_FOUND,
+                        "not an upstream the gateway was started with",
+                    );
+                };
+                upstream.set_enabled(true);
+                json(StatusCode::OK, &upstream.status())
+            }
+            ("GET", _, Some(address), _) => match find(address) {
+                Some(upstream) => json(StatusCode::OK, &upstream.status()),
+                None => text(StatusCode::NOT_FOUND, "no such upstream"),
+            },
+            ("DELETE", _, Some(address), _) => match find(address) {
+                Some(upstream) => {
+                    upstream.set_enabled(false);
+                    json(StatusCode::OK, &upstream.status())
+                }
+                None => text(StatusCode::NOT_FOUND, "no such upstream"),
+            },
+            ("PUT", _, _, Some(address)) => {
+                let Some(upstream) = find(address) else {
+                    return text(StatusCode::NOT_FOUND, "no such upstream");
+                };
+                let request: WeightRequest = match serde_json::from_slice(body) {
+                    Ok(request) => request,
+                    Err(e) => return text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+                };
+                if !(0.0..=1.0).contains(&request.weight) {
+                    return text(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        "weight must be from 0 to 1",
+                    );
+                }
+                upstream.set_weight(request.weight);
+                json(StatusCode::OK, &upstream.status())
+            }
+            (_, "", _, _) | (_, _, Some(_), _) | (_, _, _, Some(_)) => {
+                text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    fn flush_caches(&self) -> Response<Vec<u8>> {
+        if self.response_cache.is_none() && self.semantic_cache.is_none() {
+            return text(StatusCode::NOT_FOUND, "response caching is not enabled");
+        }
+        let flush = CacheFlush {
+            response_cache: self.response_cache.as_ref().map(|cache| cache.clear()),
+            semantic_cache: self.semantic_cache.as_ref().map(|cache| cache.clear()),
+        };
+        json(StatusCode::OK, &flush)
+    }
+
+    fn handle_maintenance(&self, method: &str, body: &[u8]) -> Response<Vec<u8>> {
+        let Some(pipeline) = &self.pipeline else {
+            return text(StatusCode::NOT_FOUND, "maintenance mode is not served");
+        };
+        if method == "PUT" {
+            let request: Maintenance = match serde_json::from_slice(body) {
+                Ok(request) => request,
+                Err(e) => return text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+            };
+            pipeline.set_maintenance(request.enabled);
+        }
+        let maintenance = Maintenance {
+            enabled: pipeline.in_maintenance(),
+        };
+        json(StatusCode::OK, &maintenance)
+    }
+
+    fn handle_billing(&self, method: &str, path: &str) -> Response<Vec<u8>> {
+        let Some(billing) = &self.billing else {
+            return text(StatusCode::NOT_FOUND, "billing periods are not enabled");
+        };
+        let close = path
+            .strip_prefix("tenants/")
+            .and_then(|rest| rest.strip_suffix("/close"))
+            .filter(|tenant| !tenant.is_empty() && !tenant.contains('/'));
+        match (method, path, close) {
+            ("GET", "periods", _) => json(StatusCode::OK, &billing.periods(billing.now())),
+            ("POST", _, Some(tenant)) => match billing.close_period(tenant, billing.now()) {
+                Ok(rollup) => json(StatusCode::OK, &rollup),
+                Err(e) => text(StatusCode::CONFLICT, &e.to_string()),
+            },
+            (_, "periods", _) | (_, _, Some(_)) => {
+                text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    fn handle_keys(&self, method: &str, path: &str) -> Response<Vec<u8>> {
+        let Some(stats) = &self.key_stats else {
+            return text(StatusCode::NOT_FOUND, "key statistics are not enabled");
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        match (method, path) {
+            ("GET", "") => json(StatusCode::OK, &stats.snapshot(now)),
+            ("GET", "/idle") => json(StatusCode::OK, &stats.idle(now)),
+            (_, "" | "/idle") => text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"),
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    fn handle_budgets(&self, method: &str, path: &str) -> Response<Vec<u8>> {
+        let Some(budgets) = &self.budgets else {
+            return text(StatusCode::NOT_FOUND, "spend budgets are not enabled");
+        };
+        let tenant = path
+            .strip_prefix("/tenants/")
+            .filter(|tenant| !tenant.is_empty() && !tenant.contains('/'));
+        match (method, path, tenant) {
+            ("GET", "", _) => json(StatusCode::OK, &budgets.status(budgets.now())),
+            ("GET", _, Some(tenant)) => json(
+                StatusCode::OK,
+                &budgets.tenant_status(tenant, budgets.now()),
+            ),
+            (_, "", _) | (_, _, Some(_)) => {
+                text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    fn handle_capture(&self, method: &str, path: &str, body: &[u8]) -> Response<Vec<u8>> {
+        let Some(capture) = &self.capture else {
+            return text(StatusCode::NOT_FOUND, "payload capture is not enabled");
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let window = path
+            .strip_prefix("windows/")
+            .filter(|id| !id.is_empty() && !id.contains('/'));
+        match (method, path, window) {
+            ("GET", "windows", _) => json(StatusCode::OK, &capture.windows(now)),
+            ("POST", "windows", _) => {
+                let request: CaptureRequest = match serde_json::from_slice(body) {
+                    Ok(request) => request,
+                    Err(e) => return text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+                };
+                match capture.open(&request, now) {
+                    Ok(window) => json(StatusCode::OK, &window),
+                    Err(e) => text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+                }
+            }
+            ("DELETE", _, Some(id)) => match capture.close(id) {
+                Some(window) => json(StatusCode::OK, &window),
+                None => text(StatusCode::NOT_FOUND, "no such capture window"),
+            },
+            (_, "windows", _) | (_, _, Some(_)) => {
+                text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    fn handle_pools(&self, method: &str, path: &str, body: &[u8]) -> Response<Vec<u8>> {
+        let Some(pipeline) = &self.pipeline else {
+            return text(StatusCode::NOT_FOUND, "upstream pools are not served");
+        };
+        let pools = pipeline.upstream_pools();
+        let canary = path
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_suffix("/canary"))
+            .filter(|pool| !pool.is_empty() && !pool.contains('/'));
+        match (method, path, canary) {
+            ("GET", "", _) => {
+                let configs: Vec<_> = pools.pools().iter().map(|pool| pool.config()).collect();
+                json(StatusCode::OK, &configs)
+            }
+            ("PUT", _, Some(name)) => {
+                let request: CanaryRequest = match serde_json::from_slice(body) {
+                    Ok(request) => request,
+                    Err(e) => return text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+                };
+                match pools.set_canary_percent(name, request.percent) {
+                    Ok(()) => match pools.get(name) {
+                        Some(pool) => json(StatusCode::OK, &pool.config()),
+                        None => text(StatusCode::NOT_FOUND, "no such upstream pool"),
+                    },
+                    Err(e @ (PoolError::UnknownPool(_) | PoolError::NoCanary(_))) => {
+                        text(StatusCode::NOT_FOUND, &e.to_string())
+                    }
+                    Err(e) => text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+                }
+            }
+            (_, "", _) | (_, _, Some(_)) => {
+                text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    fn handle_providers(&self, method: &str, path: &str, body: &[u8]) -> Response<Vec<u8>> {
+        let Some(pipeline) = &self.pipeline else {
+            return text(StatusCode::NOT_FOUND, "provider registry is not served");
+        };
+        let snapshots = pipeline.provider_registry_snapshots();
+        match (method, path) {
+            ("GET", "") => json(StatusCode::OK, &snapshots.current()),
+            ("PUT", "") => {
+                let config: RegistryConfig = match serde_json::from_slice(body) {
+                    Ok(config) => config,
+                    Err(e) => return text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+                };
+                match config.compile() {
+                    Ok(registry) => json(
+                        StatusCode::OK,
+                        &pipeline.set_provider_registry(registry, RegistrySource::Admin),
+                    ),
+                    Err(e) => text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+                }
+            }
+            ("GET", "/versions") => json(StatusCode::OK, &snapshots.versions()),
+            ("POST", "/rollback") => match snapshots.rollback() {
+                Ok(rollback) => json(StatusCode::OK, &rollback),
+                Err(e @ RegistryError::NoPreviousVersion) => {
+                    text(StatusCode::CONFLICT, &e.to_string())
+                }
+                Err(e) => text(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+            },
+            (_, "" | "/versions" | "/rollback") => {
+                text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    #[cfg(feature = "config")]
+    fn handle_config(&self, method: &str, path: &str, body: &[u8]) -> Response<Vec<u8>> {
+        let Some(config) = &self.config else {
+            return text(StatusCode::NOT_FOUND, "config reloading is not enabled");
+        };
+        let import = |dry_run| {
+            let sections: TenantSections = match serde_json::from_slice(body) {
+                Ok(sections) => sections,
+                Err(e) => return text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+            };
+            match config.import_tenants(sections, dry_run) {
+                Ok(import) => json(StatusCode::OK, &import),
+                Err(e) => text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+            }
+        };
+        match (method, path) {
+            ("GET", "versions") => json(StatusCode::OK, &config.versions()),
+            ("GET", "diff") => json(StatusCode::OK, &config.current().diff),
+            ("POST", "reload") => match config.reload() {
+                Ok(version) => json(StatusCode::OK, &version),
+                Err(e) => text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+            },
+            ("POST", "rollback") => match config.rollback() {
+                Ok(rollback) => json(StatusCode::OK, &rollback),
+                Err(e @ ConfigError::NoPreviousVersion) => {
+                    text(StatusCode::CONFLICT, &e.to_string())
+                }
+                Err(e) => text(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+            },
+            ("GET", "tenants") => json(StatusCode::OK, &config.current().config.tenant_sections()),
+            ("PUT", "tenants") => import(false),
+            ("POST", "tenants/dry-run") => import(true),
+            (_, "versions" | "diff" | "reload" | "rollback" | "tenants" | "tenants/dry-run") => {
+                text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    #[cfg(not(feature = "config"))]
+    fn handle_config(&self, _method: &str, _path: &str, _body: &[u8]) -> Response<Vec<u8>> {
+        text(StatusCode::NOT_FOUND, "config reloading is not enabled")
+    }
+
+    #[cfg(feature = "virtual-keys")]
+    fn handle_virtual_keys(&self, method: &str, path: &str) -> Response<Vec<u8>> {
+        match (method, path) {
+            ("GET", "") => match &self.pipeline {
+                Some(pipeline) => json(StatusCode::OK, &pipeline.virtual_keys().summaries()),
+                None => text(StatusCode::NOT_FOUND, "virtual keys are not served"),
+            },
+            // Issuing needs no state: the key is only accepted once it is configured
+            ("POST", "/issue") => json(StatusCode::OK, &VirtualKeys::issue()),
+            (_, "" | "/issue") => text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"),
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    #[cfg(not(feature = "virtual-keys"))]
+    fn handle_virtual_keys(&self, _method: &str, _path: &str) -> Response<Vec<u8>> {
+        text(StatusCode::NOT_FOUND, "virtual keys are not enabled")
+    }
+
+    #[cfg(feature = "capability")]
+    fn handle_capabilities(&self, method: &str, path: &str, body: &[u8]) -> Response<Vec<u8>> {
+        let Some(capabilities) = &self.capabilities else {
+            return text(StatusCode::NOT_FOUND, "capability tokens are not enabled");
+        };
+        let Some(tenant) = path
+            .strip_prefix("tenants/")
+            .filter(|tenant| !tenant.is_empty() && !tenant.contains('/'))
+        else {
+            return text(StatusCode::NOT_FOUND, "not found");
+        };
+        if method != "POST" {
+            return text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+        }
+        let grant = match body.trim_ascii() {
+            b"" => CapabilityGrant::default(),
+            body => match serde_json::from_slice(body) {
+                Ok(grant) => grant,
+                Err(e) => return text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+            },
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        match capabilities.mint(tenant, &grant, now) {
+            Ok(minted) => json(StatusCode::OK, &minted),
+            Err(e @ CapabilityError::UnknownTenant(_)) => {
+                text(StatusCode::NOT_FOUND, &e.to_string())
+            }
+            Err(e) => text(StatusCode::UNPROCESSABLE_ENTITY, &e.to_string()),
+        }
+    }
+
+    #[cfg(not(feature = "capability"))]
+    fn handle_capabilities(&self, _method: &str, _path: &str, _body: &[u8]) -> Response<Vec<u8>> {
+        text(StatusCode::NOT_FOUND, "capability tokens are not enabled")
+    }
+}
+
+#[async_trait]
+impl ServeHttp for AdminApp {
+    async fn response(&self, http_session: &mut ServerSession) -> Response<Vec<u8>> {
+        let mut body = Vec::new();
+        loop {
+            match http_session.read_request_body().await {
+                Ok(Some(chunk)) if body.len() + chunk.len() <= MAX_BODY_BYTES => {
+                    body.extend_from_slice(&chunk)
+                }
+                Ok(Some(_)) => return text(StatusCode::PAYLOAD_TOO_LARGE, "body too large"),
+                Ok(None) => break,
+                Err(e) => return text(StatusCode::BAD_REQUEST, &e.to_string()),
+            }
+        }
+        let request = http_session.req_header();
+        let authorization = request.headers.get(header::AUTHORIZATION);
+        if !self.is_authorized(authorization.map(|value| value.as_bytes())) {
+            return text(StatusCode::UNAUTHORIZED, "unauthorized");
+        }
+        self.handle_request(request.method.as_str(), request.uri.path(), &body)
+    }
+}
+
+/// Body of `PUT /pools/{pool}/canary`
+#[derive(Deserialize)]
+struct CanaryRequest {
+    percent: f64,
+}
+
+/// Body of `POST /upstreams`
+#[derive(Deserialize)]
+struct UpstreamRequest {
+    address: String,
+}
+
+/// Body of `PUT /upstreams/{address}/weight`
+#[derive(Deserialize)]
+struct WeightRequest {
+    weight: f64,
+}
+
+/// Body of `PUT /maintenance` and answer of both maintenance routes
+#[derive(Serialize, Deserialize)]
+struct Maintenance {
+    enabled: bool,
+}
+
+/// Answer of `POST /caches/flush`: responses dropped from each enabled cache
+#[derive(Serialize)]
+struct CacheFlush {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_cache: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    semantic_cache: Option<usize>,
+}
+
+fn json<T: Serialize>(status: StatusCode, value: &T) -> Response<Vec<u8>> {
+    match serde_json::to_vec_pretty(value) {
+        Ok(body) => respond(status, "application/json", body),
+        Err(e) => text(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+fn text(status: StatusCode, body: &str) -> Response<Vec<u8>> {
+    respond(status, "text/plain", body.as_bytes().to_vec())
+}
+
+fn respond(status: StatusCode, content_type: &str, body: Vec<u8>) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, body.len())
+        .body(body)
+        .expect("static response parts are valid")
+}
@@ -0,0 +1,108 @@
+//! Operational alerts delivered to a webhook.
+
+use crate::http_client::HttpClient;
+#[cfg(feature = "tls")]
+use crate::upstream::TlsConfigError;
+use crate::upstream::Upstream;
+#[cfg(feature = "tls")]
+use crate::upstream::tls::load_certificates;
+use chrono::{SecondsFormat, Utc};
+use log::warn;
+use serde::Serialize;
+#[cfg(feature = "tls")]
+use std::path::Path;
+use std::sync::Arc;
+
+/// An event that needs operator attention
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    /// Event type, e.g. `credential_quarantined`
+    pub event: &'static str,
+    pub timestamp: String,
+    pub upstream: String,
+    /// Id of the credential involved, if any (never the secret)
+    pub credential: Option<String>,
+    pub status: Option<u16>,
+    pub message: String,
+    /// Gateway instance that raised the alert (`cluster/instance`), if configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+}
+
+impl Alert {
+    pub fn new(
+        event: &'static str,
+        upstream: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            event,
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            upstream: upstream.into(),
+            credential: None,
+            status: None,
+            message: message.into(),
+            instance: None,
+        }
+    }
+}
+
+/// Webhook receiving alerts as JSON `POST`s. Delivery is fire-and-forget: it runs in
+/// the background and failures are only logged, so alerting never delays requests.
+pub struct AlertWebhook {
+    server: Upstream,
+    path: String,
+    client: HttpClient,
+}
+
+impl AlertWebhook {
+    /// Webhook URL, e.g. `https://hooks.example.com/langspec`
+    pub fn new(url: &str) -> Self {
+        let (base, path) = split_url(url);
+        Self {
+            server: Upstream::new(base),
+            path: path.to_string(),
+            client: HttpClient::new(),
+        }
+    }
+
+    /// Verify the webhook server against this PEM CA bundle instead of the system roots
+    #[cfg(feature = "tls")]
+    pub fn with_ca_file(mut self, path: impl AsRef<Path>) -> Result<Self, TlsConfigError> {
+        self.client.set_ca(load_certificates(path.as_ref())?);
+        Ok(self)
+    }
+
+    /// Deliver an alert in the background
+    pub fn fire(self: &Arc<Self>, alert: Alert) {
+        let webhook = Arc::clone(self);
+        tokio::spawn(async move {
+            if let Err(e) = webhook.send(&alert).await {
+                warn!("Failed to deliver {} alert: {}", alert.event, e);
+            }
+        });
+    }
+
+    /// Deliver an alert and wait for the webhook to accept it
+    pub async fn send(&self, alert: &Alert) -> Result<(), String> {
+        let body = serde_json::to_vec(alert).map_err(|e| e.to_string())?;
+        let headers = [("content-type", "application/json".to_string())];
+        let (status, _) = self
+            .client
+            .request(&self.server, "POST", &self.path, &headers, Some(body))
+            .await?;
+        if !(200..300).contains(&status) {
+            return Err(format!("webhook returned status {}", status));
+        }
+        Ok(())
+    }
+}
+
+/// Split `scheme://authority/path` into the base URL and the path (default `/`)
+pub(crate) fn split_url(url: &str) -> (&str, &str) {
+    let authority_start = url.find("://").map_or(0, |index| index + 3);
+    match url[authority_start..].find('/') {
+        Some(index) => url.split_at(authority_start + index),
+        None => (url, "/"),
+    }
+}
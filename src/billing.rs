@@ -0,0 +1,315 @@
+//! Per-tenant usage totals by billing (invoice) period.
+//!
+//! Each tenant's usage accumulates in its open period. Once time passes the period's
+//! end, the period closes into a rollup row, and a new period opens with the next
+//! request. Usage is attributed to the period in which its request started, so a
+//! stream running across the boundary still counts toward the closed period. Closing
+//! a period through the admin API freezes its row: totals no longer change and the
+//! row is final for export; late usage then counts toward the following period.
+
+use crate::pipeline::usage::Usage;
+use crate::quota::WindowClock;
+use chrono::{DateTime, Datelike, Months, NaiveDate, SecondsFormat};
+use log::info;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// When a tenant's billing periods start
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BillingAnchor {
+    /// First day of each month (UTC)
+    #[default]
+    CalendarMonth,
+    /// This day of each month (UTC), or the month's last day in shorter months
+    DayOfMonth(u32),
+}
+
+impl BillingAnchor {
+    fn day(&self) -> u32 {
+        match self {
+            BillingAnchor::CalendarMonth => 1,
+            BillingAnchor::DayOfMonth(day) => *day,
+        }
+    }
+
+    /// Start and end (since the Unix epoch) of the period containing `at`
+    pub fn period_containing(&self, at: Duration) -> (Duration, Duration) {
+        let date = DateTime::from_timestamp(at.as_secs() as i64, 0)
+            .expect("time is within chrono's range")
+            .date_naive();
+        let this_month = first_of_month(date);
+        let start = match self.anchor_in(this_month) {
+            anchor if anchor <= date => anchor,
+            _ => self.anchor_in(this_month - Months::new(1)),
+        };
+        let end = self.anchor_in(first_of_month(start) + Months::new(1));
+        (to_epoch(start), to_epoch(end))
+    }
+
+    /// Anchor day in the month starting at `month`
+    fn anchor_in(&self, month: NaiveDate) -> NaiveDate {
+        let last_day = (month + Months::new(1)).pred_opt().expect("date is valid");
+        month
+            .with_day(self.day().clamp(1, last_day.day()))
+            .expect("day is within the month")
+    }
+}
+
+fn first_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).expect("every month has a first day")
+}
+
+fn to_epoch(date: NaiveDate) -> Duration {
+    let seconds = date
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is valid")
+        .and_utc()
+        .timestamp();
+    Duration::from_secs(seconds.max(0) as u64)
+}
+
+fn to_rfc3339(at: Duration) -> String {
+    DateTime::from_timestamp(at.as_secs() as i64, 0)
+        .expect("time is within chrono's range")
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Usage accumulated over a billing period
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct PeriodTotals {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Cost of the requests that were priced
+    pub cost_usd: f64,
+}
+
+impl PeriodTotals {
+    fn add(&mut self, usage: &Usage, cost: Option<f64>) {
+        self.requests += 1;
+        self.prompt_tokens += usage.prompt_tokens;
+        self.completion_tokens += usage.completion_tokens;
+        self.cost_usd += cost.unwrap_or(0.0);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PeriodStatus {
+    /// Current period, still accumulating
+    Open,
+    /// Ended; late usage from requests started in the period is still added
+    Closed,
+    /// Closed through the admin API; totals are final
+    Frozen,
+}
+
+/// A tenant's totals for one billing period, as exported by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct PeriodRollup {
+    pub tenant: String,
+    pub period_start: String,
+    pub period_end: String,
+    pub status: PeriodStatus,
+    #[serde(flatten)]
+    pub totals: PeriodTotals,
+}
+
+#[derive(Debug)]
+pub enum BillingError {
+    /// The tenant has no ended period to close
+    NoClosedPeriod(String),
+}
+
+impl fmt::Display for BillingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BillingError::NoClosedPeriod(tenant) => {
+                write!(
+                    f,
+                    "tenant '{}' has no ended billing period to close",
+                    tenant
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BillingError {}
+
+#[derive(Debug, Clone)]
+struct Period {
+    tenant: String,
+    start: Duration,
+    end: Duration,
+    status: PeriodStatus,
+    totals: PeriodTotals,
+}
+
+impl Period {
+    fn rollup(&self) -> PeriodRollup {
+        PeriodRollup {
+            tenant: self.tenant.clone(),
+            period_start: to_rfc3339(self.start),
+            period_end: to_rfc3339(self.end),
+            status: self.status,
+            totals: self.totals,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LedgerState {
+    /// Open period per tenant
+    open: HashMap<String, Period>,
+    /// Ended periods, in the order they closed
+    closed: Vec<Period>,
+}
+
+/// Usage totals per tenant and billing period.
+#[derive(Debug)]
+pub struct BillingLedger {
+    default_anchor: BillingAnchor,
+    anchors: HashMap<String, BillingAnchor>,
+    clock: WindowClock,
+    state: Mutex<LedgerState>,
+}
+
+impl BillingLedger {
+    pub fn new(default_anchor: BillingAnchor) -> Self {
+        Self {
+            default_anchor,
+            anchors: HashMap::new(),
+            clock: WindowClock::new(),
+            state: Mutex::new(LedgerState::default()),
+        }
+    }
+
+    /// Billing periods of `tenant` start on `anchor` instead of the default
+    pub fn with_tenant_anchor(mut self, tenant: impl Into<String>, anchor: BillingAnchor) -> Self {
+        self.anchors.insert(tenant.into(), anchor);
+        self
+    }
+
+    pub fn anchor_for(&self, tenant: &str) -> BillingAnchor {
+        self.anchors
+            .get(tenant)
+            .copied()
+            .unwrap_or(self.default_anchor)
+    }
+
+    /// Current time since the Unix epoch, from a clock that ignores wall-clock jumps
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    /// Add a request's usage to the period in which it started
+    pub fn record(&self, tenant: &str, started_at: Duration, usage: &Usage, cost: Option<f64>) {
+        let mut state = self.state.lock().unwrap();
+        Self::roll_over_locked(&mut state, started_at);
+
+        let mut at = started_at;
+        if let Some(period) = state.closed.iter_mut().rev().find(|period| {
+            period.tenant == tenant && period.start <= started_at && started_at < period.end
+        }) {
+            if period.status == PeriodStatus::Closed {
+                period.totals.add(usage, cost);
+                return;
+            }
+            info!(
+                "Usage of tenant '{}' for a frozen billing period counts toward the next period",
+                tenant
+            );
+            at = period.end;
+        }
+
+        let (start, end) = self.anchor_for(tenant).period_containing(at);
+        let period = state
+            .open
+            .entry(tenant.to_string())
+            .or_insert_with(|| Period {
+                tenant: tenant.to_string(),
+                start,
+                end,
+                status: PeriodStatus::Open,
+                totals: PeriodTotals::default(),
+            });
+        period.totals.add(usage, cost);
+    }
+
+    /// Close every open period that ended by `now` into a rollup row
+    pub fn roll_over(&self, now: Duration) {
+        Self::roll_over_locked(&mut self.state.lock().unwrap(), now);
+    }
+
+    fn roll_over_locked(state: &mut LedgerState, now: Duration) {
+        let mut ended: Vec<String> = state
+            .open
+            .iter()
+            .filter(|(_, period)| period.end <= now)
+            .map(|(tenant, _)| tenant.clone())
+            .collect();
+        ended.sort_unstable();
+        for tenant in ended {
+            let mut period = state
+                .open
+                .remove(&tenant)
+                .expect("tenant has an open period");
+            info!(
+                "Billing period {} - {} of tenant '{}' closed: {} requests, ${:.6}",
+                to_rfc3339(period.start),
+                to_rfc3339(period.end),
+                tenant,
+                period.totals.requests,
+                period.totals.cost_usd
+            );
+            period.status = PeriodStatus::Closed;
+            state.closed.push(period);
+        }
+    }
+
+    /// Rows of every ended period, then of the open periods, as of `now`
+    pub fn periods(&self, now: Duration) -> Vec<PeriodRollup> {
+        let mut state = self.state.lock().unwrap();
+        Self::roll_over_locked(&mut state, now);
+        let mut open: Vec<&Period> = state.open.values().collect();
+        open.sort_by(|a, b| a.tenant.cmp(&b.tenant));
+        state
+            .closed
+            .iter()
+            .chain(open)
+            .map(Period::rollup)
+            .collect()
+    }
+
+    /// Freeze the totals of the tenant's most recent ended period
+    pub fn close_period(&self, tenant: &str, now: Duration) -> Result<PeriodRollup, BillingError> {
+        let mut state = self.state.lock().unwrap();
+        Self::roll_over_locked(&mut state, now);
+        let period = state
+            .closed
+            .iter_mut()
+            .rev()
+            .find(|period| period.tenant == tenant)
+            .ok_or_else(|| BillingError::NoClosedPeriod(tenant.to_string()))?;
+        if period.status != PeriodStatus::Frozen {
+            info!(
+                "Froze billing period {} - {} of tenant '{}'",
+                to_rfc3339(period.start),
+                to_rfc3339(period.end),
+                tenant
+            );
+            period.status = PeriodStatus::Frozen;
+        }
+        Ok(period.rollup())
+    }
+}
+
+impl Default for BillingLedger {
+    fn default() -> Self {
+        Self::new(BillingAnchor::CalendarMonth)
+    }
+}
@@ -0,0 +1,297 @@
+//! Spend budgets per tenant.
+//!
+//! A budget caps what a tenant may spend in USD within a fixed window aligned to the
+//! Unix epoch, e.g. per hour or per UTC day. The tenant is the one a request was
+//! authenticated as (by its listener, client certificate, signing key, capability
+//! token, virtual key or JWT); a tenant a client merely names is never charged. Spend
+//! is the cost of each request, known once its usage is; a request is rejected once
+//! the tenant's spend in any of its windows reached the limit. Requests already in
+//! flight when the limit is reached still complete, so a budget can be overrun by
+//! their cost.
+
+use crate::quota::{QuotaWindow, WindowClock};
+use chrono::{DateTime, SecondsFormat};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// Spend is counted in millionths of a USD
+const MICROS_PER_USD: f64 = 1_000_000.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetError {
+    /// A budget's limit is negative or not a number
+    InvalidLimit(String, f64),
+    /// A budget's window is zero
+    InvalidWindow(String),
+}
+
+impl fmt::Display for BudgetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetError::InvalidLimit(tenant, limit) => write!(
+                f,
+                "budget of tenant '{}' must have a non-negative limit, got {}",
+                tenant, limit
+            ),
+            BudgetError::InvalidWindow(tenant) => {
+                write!(
+                    f,
+                    "budget of tenant '{}' must have a positive window",
+                    tenant
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BudgetError {}
+
+/// A budget in the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BudgetConfig {
+    /// Tenant the budget applies to, or `*` for every tenant without its own budget
+    pub tenant: String,
+    pub limit_usd: f64,
+    /// Length of the budget's window, e.g. 86400 for a UTC day
+    pub window_secs: u64,
+}
+
+/// Most a tenant may spend within a window
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpendBudget {
+    /// `None` for the default budget of tenants without their own
+    pub tenant: Option<String>,
+    pub limit_usd: f64,
+    pub window: Duration,
+}
+
+impl SpendBudget {
+    pub fn compile(config: &BudgetConfig) -> Result<Self, BudgetError> {
+        if !(config.limit_usd.is_finite() && config.limit_usd >= 0.0) {
+            return Err(BudgetError::InvalidLimit(
+                config.tenant.clone(),
+                config.limit_usd,
+            ));
+        }
+        if config.window_secs == 0 {
+            return Err(BudgetError::InvalidWindow(config.tenant.clone()));
+        }
+        Ok(Self {
+            tenant: Some(config.tenant.clone()).filter(|tenant| tenant != "*"),
+            limit_usd: config.limit_usd,
+            window: Duration::from_secs(config.window_secs),
+        })
+    }
+}
+
+/// Budgets per tenant
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BudgetPolicy {
+    pub budgets: Vec<SpendBudget>,
+}
+
+impl BudgetPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cap the spend of `tenant` within every `window` to `limit_usd`
+    pub fn with_budget(
+        mut self,
+        tenant: impl Into<String>,
+        limit_usd: f64,
+        window: Duration,
+    ) -> Self {
+        assert!(!window.is_zero(), "Budget window must be positive");
+        self.budgets.push(SpendBudget {
+            tenant: Some(tenant.into()),
+            limit_usd,
+            window,
+        });
+        self
+    }
+
+    /// Budget of every tenant without its own budget
+    pub fn with_default_budget(mut self, limit_usd: f64, window: Duration) -> Self {
+        assert!(!window.is_zero(), "Budget window must be positive");
+        self.budgets.push(SpendBudget {
+            tenant: None,
+            limit_usd,
+            window,
+        });
+        self
+    }
+
+    /// Policy of the budgets declared in the config file
+    pub fn compile(budgets: &[BudgetConfig]) -> Result<Self, BudgetError> {
+        let budgets = budgets
+            .iter()
+            .map(SpendBudget::compile)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { budgets })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.budgets.is_empty()
+    }
+
+    /// The tenant's own budgets, or the default ones if it has none
+    pub fn budgets_for(&self, tenant: &str) -> Vec<&SpendBudget> {
+        let own: Vec<&SpendBudget> = self
+            .budgets
+            .iter()
+            .filter(|budget| budget.tenant.as_deref() == Some(tenant))
+            .collect();
+        match own.is_empty() {
+            true => self
+                .budgets
+                .iter()
+                .filter(|budget| budget.tenant.is_none())
+                .collect(),
+            false => own,
+        }
+    }
+}
+
+/// Spend of a tenant within one of its budgets
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BudgetStatus {
+    pub tenant: String,
+    pub window_secs: u64,
+    pub limit_usd: f64,
+    pub spent_usd: f64,
+    pub remaining_usd: f64,
+    /// RFC 3339 time the window ends and spend resets
+    pub resets_at: String,
+}
+
+impl BudgetStatus {
+    pub fn is_exhausted(&self) -> bool {
+        self.spent_usd >= self.limit_usd
+    }
+}
+
+/// Budget policy and the spend counted against it.
+///
+/// Spend is kept per tenant and window length, so a reloaded policy with the same
+/// windows keeps counting the spend so far.
+#[derive(Debug)]
+pub struct SpendBudgets {
+    policy: RwLock<Arc<BudgetPolicy>>,
+    clock: WindowClock,
+    spend: Mutex<HashMap<(String, Duration), Arc<QuotaWindow>>>,
+}
+
+impl SpendBudgets {
+    pub fn new(policy: BudgetPolicy) -> Self {
+        Self {
+            policy: RwLock::new(Arc::new(policy)),
+            clock: WindowClock::new(),
+            spend: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn policy(&self) -> Arc<BudgetPolicy> {
+        Arc::clone(&self.policy.read().unwrap())
+    }
+
+    /// Apply `policy` to new requests
+    pub fn set_policy(&self, policy: BudgetPolicy) {
+        *self.policy.write().unwrap() = Arc::new(policy);
+    }
+
+    /// Current time since the Unix epoch, from a clock that ignores wall-clock jumps
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    fn window(&self, tenant: &str, length: Duration) -> Arc<QuotaWindow> {
+        let mut spend = self.spend.lock().unwrap();
+        let window = spend
+            .entry((tenant.to_string(), length))
+            .or_insert_with(|| Arc::new(QuotaWindow::new(length)));
+        Arc::clone(window)
+    }
+
+    fn status_of(&self, tenant: &str, budget: &SpendBudget, now: Duration) -> BudgetStatus {
+        let window = self.window(tenant, budget.window);
+        let spent_usd = window.used(now) as f64 / MICROS_PER_USD;
+        let resets_at = window.window_start(now) + budget.window;
+        BudgetStatus {
+            tenant: tenant.to_string(),
+            window_secs: budget.window.as_secs(),
+            limit_usd: budget.limit_usd,
+            spent_usd,
+            remaining_usd: (budget.limit_usd - spent_usd).max(0.0),
+            resets_at: DateTime::from_timestamp(resets_at.as_secs() as i64, 0)
+                .expect("time is within chrono's range")
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+
+    /// The first of the tenant's budgets it has exhausted at `now`, if any
+    pub fn check(&self, tenant: &str, now: Duration) -> Option<BudgetStatus> {
+        let policy = self.policy();
+        policy
+            .budgets_for(tenant)
+            .into_iter()
+            .map(|budget| self.status_of(tenant, budget, now))
+            .find(BudgetStatus::is_exhausted)
+    }
+
+    /// Count a request's cost against every budget of the tenant
+    pub fn record(&self, tenant: &str, cost_usd: f64, now: Duration) {
+        let policy = self.policy();
+        let micros = (cost_usd.max(0.0) * MICROS_PER_USD).round() as u64;
+        for budget in policy.budgets_for(tenant) {
+            let window = self.window(tenant, budget.window);
+            let before = window.used(now);
+            window.record(now, micros);
+            if (before as f64) < budget.limit_usd * MICROS_PER_USD
+                && (before + micros) as f64 >= budget.limit_usd * MICROS_PER_USD
+            {
+                info!(
+                    "Tenant '{}' exhausted its ${} budget of {}s",
+                    tenant,
+                    budget.limit_usd,
+                    budget.window.as_secs()
+                );
+            }
+        }
+    }
+
+    /// Spend within each budget of `tenant` at `now`
+    pub fn tenant_status(&self, tenant: &str, now: Duration) -> Vec<BudgetStatus> {
+        let policy = self.policy();
+        policy
+            .budgets_for(tenant)
+            .into_iter()
+            .map(|budget| self.status_of(tenant, budget, now))
+            .collect()
+    }
+
+    /// Spend within each budget of every tenant with spend counted, by tenant
+    pub fn status(&self, now: Duration) -> Vec<BudgetStatus> {
+        let mut tenants: Vec<String> = {
+            let spend = self.spend.lock().unwrap();
+            spend.keys().map(|(tenant, _)| tenant.clone()).collect()
+        };
+        tenants.sort_unstable();
+        tenants.dedup();
+        tenants
+            .iter()
+            .flat_map(|tenant| self.tenant_status(tenant, now))
+            .collect()
+    }
+}
+
+impl Default for SpendBudgets {
+    fn default() -> Self {
+        Self::new(BudgetPolicy::new())
+    }
+}
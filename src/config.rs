@@ -0,0 +1,877 @@
+//! Gateway config file.
+//!
+//! A YAML file, named by `LANGSPEC_CONFIG` when serving, declaring what can be
+//! configured without writing Rust: custom provider rules and extra hosts and paths of
+//! built-in providers, compiled into the [`ProviderRegistry`], deprecated models with
+//! their sunset policy, the prices turning usage into cost, spend budgets per tenant,
+//! request rate limits, mock routes answered without an upstream, transforms
+//! reshaping responses, provider keys injected from a secret store, headers stripped
+//! from or added to requests and responses, CORS routes for browser clients, upstream
+//! pools routed by path, JWT validation and virtual keys.
+//!
+//! The file can be reloaded at runtime through the admin API. A version is applied only
+//! once every section compiles and the references between sections hold (prices, rate
+//! limits and credentials name known providers, budgets have prices to count, replacement models
+//! are priced and not deprecated themselves, pools name the gateway's upstreams); otherwise the reload is rejected with
+//! every broken reference and the applied version stays in place.
+//!
+//! [`ConfigStore`] keeps the last versions in memory with a structured diff of what
+//! each one changed, so a reload that misbehaves can be rolled back to the previous
+//! version.
+//!
+//! The tenant sections (budgets, rate limits and virtual keys) can also be exported and
+//! imported as a whole as [`TenantSections`], so fleets of gateways can be provisioned
+//! from an external source of truth. An import is checked like a reload, and can be
+//! tried as a dry run that only reports its diff.
+
+use crate::budget::{BudgetConfig, BudgetError, BudgetPolicy};
+use crate::pipeline::Pipeline;
+use crate::pipeline::deprecation::{DeprecationError, DeprecationPolicy, ModelDeprecationConfig};
+use crate::pipeline::mock::{MockError, MockRouteConfig, MockRoutes};
+use crate::pipeline::pricing::{Pricing, PricingConfig, PricingError};
+use crate::pipeline::rate_limit::{RateLimitConfig, RateLimitError, RateLimitPolicy};
+use crate::pipeline::transform::{ResponseTransformConfig, ResponseTransforms, TransformError};
+use crate::provider::ProviderRegistry;
+use crate::provider::custom::{CustomProviderConfig, CustomProviderError};
+use crate::provider::snapshots::{RegistryConfig, RegistryError, RegistrySource};
+use crate::provider::tuning::DetectionTuning;
+use crate::proxy::cors::{CorsError, CorsRouteConfig, CorsRules};
+use crate::proxy::headers::{HeaderRuleError, HeaderRules, HeaderRulesConfig};
+#[cfg(feature = "jwt")]
+use crate::proxy::jwt::{JwtConfig, JwtConfigError, JwtValidator};
+#[cfg(feature = "virtual-keys")]
+use crate::proxy::virtual_keys::{VirtualKeyConfig, VirtualKeyError, VirtualKeys};
+use crate::upstream::{
+    CredentialRouteConfig, CredentialRouteError, CredentialRoutes, PoolError, UpstreamPoolConfig,
+    UpstreamPools,
+};
+use chrono::{SecondsFormat, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{fs, io};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Yaml(serde_yaml::Error),
+    Provider(CustomProviderError),
+    DuplicateProvider(String),
+    /// Invalid detection tuning of a built-in provider
+    Detection(RegistryError),
+    Deprecation(DeprecationError),
+    Pricing(PricingError),
+    Mock(MockError),
+    Transform(TransformError),
+    Budget(BudgetError),
+    RateLimit(RateLimitError),
+    Credentials(CredentialRouteError),
+    Headers(HeaderRuleError),
+    Cors(CorsError),
+    Pools(PoolError),
+    #[cfg(feature = "jwt")]
+    Jwt(JwtConfigError),
+    #[cfg(feature = "virtual-keys")]
+    VirtualKey(VirtualKeyError),
+    /// Sections that compile but reference each other inconsistently, e.g. prices of
+    /// an unknown provider
+    References(Vec<ConfigIssue>),
+    /// Rollback with only one config version kept
+    NoPreviousVersion,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "cannot read '{}': {}", path.display(), e),
+            ConfigError::Yaml(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Provider(e) => write!(f, "invalid config: {}", e),
+            ConfigError::DuplicateProvider(name) => {
+                write!(f, "invalid config: provider '{}' is declared twice", name)
+            }
+            ConfigError::Detection(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Deprecation(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Pricing(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Mock(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Transform(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Budget(e) => write!(f, "invalid config: {}", e),
+            ConfigError::RateLimit(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Credentials(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Headers(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Cors(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Pools(e) => write!(f, "invalid config: {}", e),
+            #[cfg(feature = "jwt")]
+            ConfigError::Jwt(e) => write!(f, "invalid config: {}", e),
+            #[cfg(feature = "virtual-keys")]
+            ConfigError::VirtualKey(e) => write!(f, "invalid config: {}", e),
+            ConfigError::References(issues) => {
+                write!(f, "invalid config: {} broken references", issues.len())?;
+                for issue in issues {
+                    write!(f, "\n- {}", issue)?;
+                }
+                Ok(())
+            }
+            ConfigError::NoPreviousVersion => {
+                write!(f, "no previous config version to roll back to")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<RegistryError> for ConfigError {
+    fn from(e: RegistryError) -> Self {
+        match e {
+            RegistryError::Provider(e) => ConfigError::Provider(e),
+            RegistryError::DuplicateProvider(name) => ConfigError::DuplicateProvider(name),
+            e => ConfigError::Detection(e),
+        }
+    }
+}
+
+/// The budgets, rate limits and virtual keys of a config, exported and imported
+/// together
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantSections {
+    #[serde(default)]
+    pub budgets: Vec<BudgetConfig>,
+    #[serde(default)]
+    pub rate_limits: Vec<RateLimitConfig>,
+    #[cfg(feature = "virtual-keys")]
+    #[serde(default)]
+    pub virtual_keys: Vec<VirtualKeyConfig>,
+}
+
+/// A setting referencing something the config does not provide
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConfigIssue {
+    /// Setting at fault, addressed like [`ConfigChange::path`]
+    pub path: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GatewayConfig {
+    /// Additional providers, detected ahead of the built-in ones
+    #[serde(default)]
+    pub providers: Vec<CustomProviderConfig>,
+    /// Extra hosts and paths of built-in providers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub detection: Vec<DetectionTuning>,
+    /// Deprecated models, with their sunset date and replacement
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deprecations: Vec<ModelDeprecationConfig>,
+    /// Model prices, per tenant if needed; requests are not priced without them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<PricingConfig>,
+    /// Routes answered with canned completions instead of forwarding to an upstream
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mocks: Vec<MockRouteConfig>,
+    /// Reshaping of non-streaming JSON responses per route
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub response_transforms: Vec<ResponseTransformConfig>,
+    /// Spend limits per tenant and window, enforced on priced requests
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub budgets: Vec<BudgetConfig>,
+    /// Request rate limits per client key pattern, per provider or global
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rate_limits: Vec<RateLimitConfig>,
+    /// Provider keys the gateway sends in place of the client's, per provider and path
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub credentials: Vec<CredentialRouteConfig>,
+    /// Request headers stripped before forwarding, response headers removed, and
+    /// static headers added per route
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headers: Option<HeaderRulesConfig>,
+    /// Routes browser apps on other origins may call, with the preflights the gateway
+    /// answers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cors: Vec<CorsRouteConfig>,
+    /// Subsets of the gateway's upstreams serving some request paths
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub upstream_pools: Vec<UpstreamPoolConfig>,
+    /// Client JWTs checked against an identity provider's keys
+    #[cfg(feature = "jwt")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwt: Option<JwtConfig>,
+    /// Gateway-issued client keys, with their models, upstream key, rate limit and
+    /// budget
+    #[cfg(feature = "virtual-keys")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub virtual_keys: Vec<VirtualKeyConfig>,
+}
+
+impl GatewayConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.into(), e))?;
+        Self::from_yaml(&contents)
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<Self, ConfigError> {
+        serde_yaml::from_str(yaml).map_err(ConfigError::Yaml)
+    }
+
+    /// The `providers` and `detection` sections
+    pub fn registry_config(&self) -> RegistryConfig {
+        RegistryConfig {
+            providers: self.providers.clone(),
+            detection: self.detection.clone(),
+        }
+    }
+
+    /// The `budgets`, `rate_limits` and `virtual_keys` sections
+    pub fn tenant_sections(&self) -> TenantSections {
+        TenantSections {
+            budgets: self.budgets.clone(),
+            rate_limits: self.rate_limits.clone(),
+            #[cfg(feature = "virtual-keys")]
+            virtual_keys: self.virtual_keys.clone(),
+        }
+    }
+
+    /// This config with its tenant sections replaced by `sections`
+    pub fn with_tenant_sections(mut self, sections: TenantSections) -> Self {
+        self.budgets = sections.budgets;
+        self.rate_limits = sections.rate_limits;
+        #[cfg(feature = "virtual-keys")]
+        {
+            self.virtual_keys = sections.virtual_keys;
+        }
+        self
+    }
+
+    /// The built-in providers, tuned, plus the declared ones
+    pub fn provider_registry(&self) -> Result<ProviderRegistry, ConfigError> {
+        Ok(self.registry_config().compile()?)
+    }
+
+    pub fn deprecation_policy(&self) -> Result<DeprecationPolicy, ConfigError> {
+        DeprecationPolicy::compile(&self.deprecations).map_err(ConfigError::Deprecation)
+    }
+
+    pub fn pricing(&self) -> Result<Option<Pricing>, ConfigError> {
+        self.pricing
+            .as_ref()
+            .map(PricingConfig::compile)
+            .transpose()
+            .map_err(ConfigError::Pricing)
+    }
+
+    pub fn mock_routes(&self) -> Result<MockRoutes, ConfigError> {
+        MockRoutes::compile(&self.mocks).map_err(ConfigError::Mock)
+    }
+
+    pub fn response_transforms(&self) -> Result<ResponseTransforms, ConfigError> {
+        ResponseTransforms::compile(&self.response_transforms).map_err(ConfigError::Transform)
+    }
+
+    /// The `budgets` section, plus the budgets of virtual keys
+    pub fn budget_policy(&self) -> Result<BudgetPolicy, ConfigError> {
+        BudgetPolicy::compile(&self.all_budgets()).map_err(ConfigError::Budget)
+    }
+
+    /// The `rate_limits` section, after the rate limits of virtual keys: only the first
+    /// key limit matching a client applies, so a key's own limit goes first
+    pub fn rate_limit_policy(&self) -> Result<RateLimitPolicy, ConfigError> {
+        #[cfg(feature = "virtual-keys")]
+        let limits: Vec<RateLimitConfig> = self
+            .virtual_keys
+            .iter()
+            .filter_map(VirtualKeyConfig::rate_limit)
+            .chain(self.rate_limits.iter().cloned())
+            .collect();
+        #[cfg(not(feature = "virtual-keys"))]
+        let limits = self.rate_limits.clone();
+        RateLimitPolicy::compile(&limits).map_err(ConfigError::RateLimit)
+    }
+
+    pub fn credential_routes(&self) -> Result<CredentialRoutes, ConfigError> {
+        CredentialRoutes::compile(&self.credentials).map_err(ConfigError::Credentials)
+    }
+
+    pub fn header_rules(&self) -> Result<HeaderRules, ConfigError> {
+        self.headers
+            .as_ref()
+            .map_or_else(|| Ok(HeaderRules::new()), HeaderRules::compile)
+            .map_err(ConfigError::Headers)
+    }
+
+    pub fn cors_rules(&self) -> Result<CorsRules, ConfigError> {
+        CorsRules::compile(&self.cors).map_err(ConfigError::Cors)
+    }
+
+    pub fn upstream_pools(&self) -> Result<UpstreamPools, ConfigError> {
+        UpstreamPools::compile(&self.upstream_pools).map_err(ConfigError::Pools)
+    }
+
+    #[cfg(feature = "jwt")]
+    pub fn jwt_validator(&self) -> Result<Option<JwtValidator>, ConfigError> {
+        self.jwt
+            .as_ref()
+            .map(JwtValidator::compile)
+            .transpose()
+            .map_err(ConfigError::Jwt)
+    }
+
+    #[cfg(feature = "virtual-keys")]
+    pub fn virtual_keys(&self) -> Result<VirtualKeys, ConfigError> {
+        VirtualKeys::compile(&self.virtual_keys).map_err(ConfigError::VirtualKey)
+    }
+
+    fn all_budgets(&self) -> Vec<BudgetConfig> {
+        #[cfg(feature = "virtual-keys")]
+        let budgets = self
+            .budgets
+            .iter()
+            .cloned()
+            .chain(
+                self.virtual_keys
+                    .iter()
+                    .filter_map(VirtualKeyConfig::budget),
+            )
+            .collect();
+        #[cfg(not(feature = "virtual-keys"))]
+        let budgets = self.budgets.clone();
+        budgets
+    }
+
+    /// Compile every section, then check the references between them and to the
+    /// gateway's `upstreams` (unchecked when empty), reporting all broken ones at once
+    fn compile(&self, upstreams: &[String]) -> Result<CompiledConfig, ConfigError> {
+        let compiled = CompiledConfig {
+            provider_registry: self.provider_registry()?,
+            deprecation_policy: self.deprecation_policy()?,
+            pricing: self.pricing()?,
+            mock_routes: self.mock_routes()?,
+            response_transforms: self.response_transforms()?,
+            budget_policy: self.budget_policy()?,
+            rate_limit_policy: self.rate_limit_policy()?,
+            credential_routes: self.credential_routes()?,
+            header_rules: self.header_rules()?,
+            cors_rules: self.cors_rules()?,
+            upstream_pools: self.upstream_pools()?,
+            #[cfg(feature = "jwt")]
+            jwt: self.jwt_validator()?,
+            #[cfg(feature = "virtual-keys")]
+            virtual_keys: self.virtual_keys()?,
+        };
+        let issues = self.check_references(&compiled, upstreams);
+        match issues.is_empty() {
+            true => Ok(compiled),
+            false => Err(ConfigError::References(issues)),
+        }
+    }
+
+    /// Settings naming providers that are neither built in nor declared, budgets that
+    /// cannot count spend without prices, deprecated models rewritten to a model that
+    /// is unpriced or itself deprecated, and pools of upstreams the gateway does not
+    /// proxy to
+    fn check_references(
+        &self,
+        compiled: &CompiledConfig,
+        upstreams: &[String],
+    ) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let providers = compiled.provider_registry.provider_names();
+        let unknown_provider = |provider: &str| format!("unknown provider '{}'", provider);
+
+        if let Some(pricing) = &self.pricing {
+            for model in &pricing.models {
+                if !providers.contains(&model.provider.as_str()) {
+                    issues.push(ConfigIssue::new(
+                        format!("pricing.models.{}", model.model),
+                        unknown_provider(&model.provider),
+                    ));
+                }
+            }
+            for tenant in &pricing.tenants {
+                for model in &tenant.models {
+                    if !providers.contains(&model.provider.as_str()) {
+                        issues.push(ConfigIssue::new(
+                            format!("pricing.tenants.{}.models.{}", tenant.name, model.model),
+                            unknown_provider(&model.provider),
+                        ));
+                    }
+                }
+            }
+        }
+
+        for limit in &self.rate_limits {
+            if let Some(provider) = &limit.provider
+                && !providers.contains(&provider.as_str())
+            {
+                issues.push(ConfigIssue::new(
+                    format!("rate_limits.{}.provider", limit.name),
+                    unknown_provider(provider),
+                ));
+            }
+        }
+
+        for route in &self.credentials {
+            if let Some(provider) = &route.provider
+                && !providers.contains(&provider.as_str())
+            {
+                issues.push(ConfigIssue::new(
+                    format!("credentials.{}.provider", route.name),
+                    unknown_provider(provider),
+                ));
+            }
+        }
+
+        if !upstreams.is_empty() {
+            for pool in compiled.upstream_pools.pools() {
+                for upstream in pool.upstreams.iter().filter(|u| !upstreams.contains(u)) {
+                    issues.push(ConfigIssue::new(
+                        format!("upstream_pools.{}.upstreams", pool.name),
+                        format!("unknown upstream '{}'", upstream),
+                    ));
+                }
+            }
+        }
+
+        if compiled.pricing.is_none() {
+            for budget in &self.budgets {
+                issues.push(ConfigIssue::new(
+                    format!("budgets.{}", budget.tenant),
+                    "budgets count the cost of requests, but no pricing is configured",
+                ));
+            }
+            #[cfg(feature = "virtual-keys")]
+            for key in self.virtual_keys.iter().filter(|key| key.budget.is_some()) {
+                issues.push(ConfigIssue::new(
+                    format!("virtual_keys.{}.budget", key.name),
+                    "budgets count the cost of requests, but no pricing is configured",
+                ));
+            }
+        }
+
+        for deprecation in &self.deprecations {
+            let Some(replacement) = &deprecation.replacement else {
+                continue;
+            };
+            let path = format!("deprecations.{}.replacement", deprecation.model);
+            if let Some(other) = compiled.deprecation_policy.lookup(replacement) {
+                issues.push(ConfigIssue::new(
+                    path.clone(),
+                    format!(
+                        "replacement '{}' is itself deprecated (as '{}')",
+                        replacement, other.model
+                    ),
+                ));
+            }
+            if let Some(pricing) = &compiled.pricing
+                && !pricing.has_price(replacement)
+            {
+                issues.push(ConfigIssue::new(
+                    path,
+                    format!("replacement '{}' has no price", replacement),
+                ));
+            }
+        }
+        issues
+    }
+}
+
+/// What a valid config applies to the pipeline
+struct CompiledConfig {
+    provider_registry: ProviderRegistry,
+    deprecation_policy: DeprecationPolicy,
+    pricing: Option<Pricing>,
+    mock_routes: MockRoutes,
+    response_transforms: ResponseTransforms,
+    budget_policy: BudgetPolicy,
+    rate_limit_policy: RateLimitPolicy,
+    credential_routes: CredentialRoutes,
+    header_rules: HeaderRules,
+    cors_rules: CorsRules,
+    upstream_pools: UpstreamPools,
+    #[cfg(feature = "jwt")]
+    jwt: Option<JwtValidator>,
+    #[cfg(feature = "virtual-keys")]
+    virtual_keys: VirtualKeys,
+}
+
+impl CompiledConfig {
+    fn apply(self, pipeline: &Pipeline) {
+        pipeline.set_provider_registry(self.provider_registry, RegistrySource::Config);
+        pipeline.set_deprecation_policy(self.deprecation_policy);
+        pipeline.set_pricing(self.pricing);
+        pipeline.set_mock_routes(self.mock_routes);
+        pipeline.set_response_transforms(self.response_transforms);
+        pipeline.spend_budgets().set_policy(self.budget_policy);
+        pipeline.rate_limiter().set_policy(self.rate_limit_policy);
+        pipeline.set_credential_routes(self.credential_routes);
+        pipeline.set_header_rules(self.header_rules);
+        pipeline.set_cors_rules(self.cors_rules);
+        pipeline.set_upstream_pools(self.upstream_pools);
+        #[cfg(feature = "jwt")]
+        pipeline.set_jwt(self.jwt);
+        #[cfg(feature = "virtual-keys")]
+        pipeline.set_virtual_keys(self.virtual_keys);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// One changed setting, e.g. `providers.acme-llm.hosts`. Entries of lists of named
+/// items are addressed by name (providers) or model (deprecations).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigChange {
+    pub path: String,
+    pub change: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<Value>,
+}
+
+/// What changed between two config versions
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct ConfigDiff {
+    pub changes: Vec<ConfigChange>,
+}
+
+impl ConfigDiff {
+    pub fn between(before: &GatewayConfig, after: &GatewayConfig) -> Self {
+        let to_value = |config| serde_json::to_value(config).expect("config is serializable");
+        let mut changes = Vec::new();
+        diff_values(
+            String::new(),
+            to_value(before),
+            to_value(after),
+            &mut changes,
+        );
+        Self { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+fn diff_values(path: String, before: Value, after: Value, changes: &mut Vec<ConfigChange>) {
+    let child = |key: &str| match path.as_str() {
+        "" => key.to_string(),
+        _ => format!("{}.{}", path, key),
+    };
+    match (before, after) {
+        (Value::Object(mut before), Value::Object(mut after)) => {
+            let keys: Vec<String> = before.keys().chain(after.keys()).cloned().collect();
+            let mut seen = HashSet::new();
+            for key in keys.iter().filter(|key| seen.insert(*key)) {
+                let path = child(key);
+                match (before.remove(key), after.remove(key)) {
+                    (Some(before), Some(after)) => diff_values(path, before, after, changes),
+                    (Some(before), None) => changes.push(ConfigChange {
+                        path,
+                        change: ChangeKind::Removed,
+                        before: Some(before),
+                        after: None,
+                    }),
+                    (None, Some(after)) => changes.push(ConfigChange {
+                        path,
+                        change: ChangeKind::Added,
+                        before: None,
+                        after: Some(after),
+                    }),
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Array(before), Value::Array(after))
+            if before
+                .iter()
+                .chain(&after)
+                .all(|item| item_name(item).is_some()) =>
+        {
+            let by_name = |items: Vec<Value>| -> serde_json::Map<String, Value> {
+                items
+                    .into_iter()
+                    .map(|item| (item_name(&item).expect("item is named").to_string(), item))
+                    .collect()
+            };
+            diff_values(
+                path,
+                Value::Object(by_name(before)),
+                Value::Object(by_name(after)),
+                changes,
+            );
+        }
+        (before, after) => {
+            if before != after {
+                changes.push(ConfigChange {
+                    path,
+                    change: ChangeKind::Changed,
+                    before: Some(before),
+                    after: Some(after),
+                });
+            }
+        }
+    }
+}
+
+fn item_name(item: &Value) -> Option<&str> {
+    item.get("name").or_else(|| item.get("model"))?.as_str()
+}
+
+/// A config as it was applied
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigVersion {
+    /// Increases with every applied reload, starting at 1 for the startup config
+    pub version: u64,
+    /// RFC 3339 time the version was applied
+    pub loaded_at: String,
+    /// Changes from the previous version (from an empty config for the startup config)
+    pub diff: ConfigDiff,
+    pub config: GatewayConfig,
+}
+
+/// Outcome of rolling back to the previous config version
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigRollback {
+    /// The discarded version
+    pub from: u64,
+    /// The version applied again
+    pub to: u64,
+    /// Changes applied by the rollback
+    pub diff: ConfigDiff,
+}
+
+/// Outcome of importing tenant sections
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantImport {
+    /// Whether the import was only checked, not applied
+    pub dry_run: bool,
+    /// The version applied afterwards: the new one, or the unchanged current one for a
+    /// dry run or an import changing nothing
+    pub version: u64,
+    /// Changes the import makes (or would make) to the applied config
+    pub diff: ConfigDiff,
+}
+
+#[derive(Debug)]
+struct StoreState {
+    /// Oldest first; the last one is applied
+    versions: VecDeque<ConfigVersion>,
+    next_version: u64,
+}
+
+/// The gateway config file and its recently applied versions.
+///
+/// Applying a config swaps the provider registry, deprecation policy, pricing, mock
+/// routes, response transforms, budget and rate limit policies, header rules, upstream
+/// pools and virtual keys of the pipeline; requests already in flight finish with those
+/// they started with.
+pub struct ConfigStore {
+    path: PathBuf,
+    max_versions: usize,
+    pipeline: Arc<Pipeline>,
+    /// Upstream addresses pools may name; any when empty
+    upstreams: Vec<String>,
+    state: Mutex<StoreState>,
+}
+
+impl ConfigStore {
+    pub const DEFAULT_MAX_VERSIONS: usize = 10;
+
+    /// Load the config file and apply it to `pipeline` as version 1
+    pub fn load(path: impl Into<PathBuf>, pipeline: Arc<Pipeline>) -> Result<Self, ConfigError> {
+        Self::load_for_upstreams(path, pipeline, Vec::new())
+    }
+
+    /// [`load`](Self::load) for a gateway proxying to `upstreams`, the only addresses
+    /// upstream pools may name in this version and the ones reloaded later
+    pub fn load_for_upstreams(
+        path: impl Into<PathBuf>,
+        pipeline: Arc<Pipeline>,
+        upstreams: Vec<String>,
+    ) -> Result<Self, ConfigError> {
+        let path = path.into();
+        let config = GatewayConfig::load(&path)?;
+        config.compile(&upstreams)?.apply(&pipeline);
+        let version = ConfigVersion {
+            version: 1,
+            loaded_at: now_rfc3339(),
+            diff: ConfigDiff::between(&GatewayConfig::default(), &config),
+            config,
+        };
+        Ok(Self {
+            path,
+            max_versions: Self::DEFAULT_MAX_VERSIONS,
+            pipeline,
+            upstreams,
+            state: Mutex::new(StoreState {
+                versions: VecDeque::from([version]),
+                next_version: 2,
+            }),
+        })
+    }
+
+    /// Keep this many versions (at least 2, so there is one to roll back to)
+    pub fn with_max_versions(mut self, max_versions: usize) -> Self {
+        assert!(max_versions >= 2, "At least 2 config versions must be kept");
+        self.max_versions = max_versions;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The applied version
+    pub fn current(&self) -> ConfigVersion {
+        let state = self.state.lock().unwrap();
+        state.versions.back().expect("a version is applied").clone()
+    }
+
+    /// Kept versions, most recent (applied) first
+    pub fn versions(&self) -> Vec<ConfigVersion> {
+        let state = self.state.lock().unwrap();
+        state.versions.iter().rev().cloned().collect()
+    }
+
+    /// Re-read the config file and apply it as a new version. An invalid file leaves
+    /// the applied version in place; an unchanged one returns it without adding a
+    /// version.
+    pub fn reload(&self) -> Result<ConfigVersion, ConfigError> {
+        let config = GatewayConfig::load(&self.path)?;
+        let compiled = config.compile(&self.upstreams)?;
+
+        let mut state = self.state.lock().unwrap();
+        let current = state.versions.back().expect("a version is applied");
+        let diff = ConfigDiff::between(&current.config, &config);
+        if diff.is_empty() {
+            info!(
+                "Config '{}' unchanged, keeping version {}",
+                self.path.display(),
+                current.version
+            );
+            return Ok(current.clone());
+        }
+
+        compiled.apply(&self.pipeline);
+        let version = self.push_version(&mut state, config, diff);
+        info!(
+            "Applied config version {} from '{}' ({} changes)",
+            version.version,
+            self.path.display(),
+            version.diff.changes.len()
+        );
+        Ok(version)
+    }
+
+    /// Replace the budgets, rate limits and virtual keys of the applied config with
+    /// `sections` and apply the result as a new version, all at once or not at all.
+    /// With `dry_run`, the result is only checked and its diff returned. The next
+    /// reload of the config file replaces the imported sections again.
+    pub fn import_tenants(
+        &self,
+        sections: TenantSections,
+        dry_run: bool,
+    ) -> Result<TenantImport, ConfigError> {
+        let mut state = self.state.lock().unwrap();
+        let current = state.versions.back().expect("a version is applied");
+        let config = current.config.clone().with_tenant_sections(sections);
+        let compiled = config.compile(&self.upstreams)?;
+        let diff = ConfigDiff::between(&current.config, &config);
+        if dry_run || diff.is_empty() {
+            return Ok(TenantImport {
+                dry_run,
+                version: current.version,
+                diff,
+            });
+        }
+
+        compiled.apply(&self.pipeline);
+        let version = self.push_version(&mut state, config, diff);
+        info!(
+            "Applied config version {} from a tenant import ({} changes)",
+            version.version,
+            version.diff.changes.len()
+        );
+        Ok(TenantImport {
+            dry_run,
+            version: version.version,
+            diff: version.diff,
+        })
+    }
+
+    /// Record an applied config as the next version, dropping the oldest kept ones
+    fn push_version(
+        &self,
+        state: &mut StoreState,
+        config: GatewayConfig,
+        diff: ConfigDiff,
+    ) -> ConfigVersion {
+        let version = ConfigVersion {
+            version: state.next_version,
+            loaded_at: now_rfc3339(),
+            diff,
+            config,
+        };
+        state.next_version += 1;
+        state.versions.push_back(version.clone());
+        while state.versions.len() > self.max_versions {
+            state.versions.pop_front();
+        }
+        version
+    }
+
+    /// Discard the applied version and apply the previous one again
+    pub fn rollback(&self) -> Result<ConfigRollback, ConfigError> {
+        let mut state = self.state.lock().unwrap();
+        let [.., previous, current] = state.versions.make_contiguous() else {
+            return Err(ConfigError::NoPreviousVersion);
+        };
+        // Kept versions were valid when applied, and compile the same way again
+        previous
+            .config
+            .compile(&self.upstreams)?
+            .apply(&self.pipeline);
+        let rollback = ConfigRollback {
+            from: current.version,
+            to: previous.version,
+            diff: ConfigDiff::between(&current.config, &previous.config),
+        };
+        state.versions.pop_back();
+        info!(
+            "Rolled config back from version {} to version {}",
+            rollback.from, rollback.to
+        );
+        Ok(rollback)
+    }
+}
+
+fn now_rfc3339() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
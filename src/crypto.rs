@@ -0,0 +1,32 @@
+//! Byte helpers shared by the admin API, request signing and provenance records.
+
+#[cfg(any(feature = "signing", feature = "provenance"))]
+use std::fmt::Write;
+
+/// Whether `a` and `b` are equal, in time independent of where they differ, for
+/// comparing secrets
+#[cfg(any(feature = "admin", feature = "signing"))]
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Lowercase hex of `bytes`
+#[cfg(any(feature = "signing", feature = "provenance"))]
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+/// Bytes of a hex string, either case; `None` if it is not hex
+#[cfg(any(feature = "signing", feature = "provenance"))]
+pub(crate) fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
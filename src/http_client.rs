@@ -0,0 +1,107 @@
+//! Minimal HTTP(S) client for the gateway's own outbound calls (service registries,
+//! alert webhooks), built on Pingora's connector so it shares the TLS stack used for
+//! upstreams.
+
+use crate::upstream::Upstream;
+use bytes::Bytes;
+use pingora::connectors::http::Connector;
+use pingora::http::RequestHeader;
+#[cfg(feature = "tls")]
+use pingora::tls::x509::X509;
+use pingora::upstreams::peer::HttpPeer;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+pub(crate) struct HttpClient {
+    connector: Connector,
+    /// CA bundle the server certificate is verified against, instead of the system roots
+    #[cfg(feature = "tls")]
+    ca: Option<Arc<Box<[X509]>>>,
+}
+
+impl HttpClient {
+    pub(crate) fn new() -> Self {
+        Self {
+            connector: Connector::new(None),
+            #[cfg(feature = "tls")]
+            ca: None,
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn set_ca(&mut self, ca: Vec<X509>) {
+        self.ca = Some(Arc::new(ca.into_boxed_slice()));
+    }
+
+    /// Send a request to the server at `base` (scheme, host and port of a URL) with an
+    /// optional body, returning the status and response body. Errors are connection or
+    /// protocol failures, described as text.
+    pub(crate) async fn request(
+        &self,
+        base: &Upstream,
+        method: &str,
+        path: &str,
+        headers: &[(&str, String)],
+        body: Option<Vec<u8>>,
+    ) -> Result<(u16, Vec<u8>), String> {
+        let error = |e: Box<pingora::Error>| e.to_string();
+        let addr = tokio::net::lookup_host(base.address())
+            .await
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("cannot resolve {}", base.address()))?;
+        let peer = HttpPeer::new(addr, base.tls(), base.sni().to_string());
+        #[cfg(feature = "tls")]
+        let peer = self.with_ca(peer);
+
+        let mut request = RequestHeader::build(method, path.as_bytes(), None).map_err(error)?;
+        request.insert_header("host", base.sni()).map_err(error)?;
+        request
+            .insert_header("connection", "close")
+            .map_err(error)?;
+        if let Some(body) = &body {
+            request
+                .insert_header("content-length", body.len())
+                .map_err(error)?;
+        }
+        for (name, value) in headers {
+            request
+                .insert_header(name.to_string(), value.as_str())
+                .map_err(error)?;
+        }
+
+        let (mut session, _) = self
+            .connector
+            .get_http_session(&peer)
+            .await
+            .map_err(error)?;
+        session
+            .write_request_header(Box::new(request))
+            .await
+            .map_err(error)?;
+        if let Some(body) = body {
+            session
+                .write_request_body(Bytes::from(body), true)
+                .await
+                .map_err(error)?;
+        }
+        session.finish_request_body().await.map_err(error)?;
+        session.read_response_header().await.map_err(error)?;
+        let status = session
+            .response_header()
+            .map(|header| header.status.as_u16())
+            .unwrap_or_default();
+
+        let mut response = Vec::new();
+        while let Some(chunk) = session.read_response_body().await.map_err(error)? {
+            response.extend_from_slice(&chunk);
+        }
+        Ok((status, response))
+    }
+
+    #[cfg(feature = "tls")]
+    fn with_ca(&self, mut peer: HttpPeer) -> HttpPeer {
+        peer.options.ca = self.ca.clone();
+        peer
+    }
+}
@@ -0,0 +1,259 @@
+//! Per-key request statistics shared by the gateway's subsystems.
+//!
+//! One [`KeyStats`] tracks, for each client key (the tenant a request authenticated
+//! as), its in-flight requests, its error rate and token consumption over a rolling
+//! window, and when it was last seen. The per-key concurrency limit, the admin API and idle-key
+//! reporting all read the same entries instead of each keeping its own map.
+//!
+//! Keys are spread over shards, each behind its own lock that is only taken for
+//! writing when a key is seen for the first time; counters are updated under a
+//! per-key lock, so requests of different keys never contend.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// Shards of the key map
+const SHARDS: usize = 16;
+/// Buckets the rolling window is divided into
+const BUCKETS: u64 = 6;
+
+/// Statistics of every key seen, by key
+#[derive(Debug)]
+pub struct KeyStats {
+    shards: Vec<RwLock<HashMap<String, Arc<KeyEntry>>>>,
+    hasher: RandomState,
+    window: Duration,
+    idle_after: Duration,
+    max_keys: usize,
+    keys: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct KeyEntry {
+    in_flight: AtomicU64,
+    /// Unix time of the last request
+    last_seen: AtomicU64,
+    buckets: Mutex<[Bucket; BUCKETS as usize]>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Bucket {
+    /// Bucket index since the Unix epoch the counts belong to
+    slot: u64,
+    requests: u64,
+    errors: u64,
+    tokens: u64,
+}
+
+/// Statistics of one key at a point in time
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeySnapshot {
+    pub key: String,
+    pub in_flight: u64,
+    /// Requests completed within the window
+    pub requests: u64,
+    /// Requests within the window that failed
+    pub errors: u64,
+    /// Share of requests within the window that failed, 0 without requests
+    pub error_rate: f64,
+    /// Tokens consumed within the window
+    pub tokens: u64,
+    /// Unix time of the last request
+    pub last_seen: u64,
+}
+
+impl Default for KeyStats {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+            hasher: RandomState::new(),
+            window: Duration::from_secs(60),
+            idle_after: Duration::from_secs(3600),
+            max_keys: 10_000,
+            keys: AtomicU64::new(0),
+        }
+    }
+}
+
+impl KeyStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Window error rates and token consumption are computed over (60s by default,
+    /// at least 6s)
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window.max(Duration::from_secs(BUCKETS));
+        self
+    }
+
+    /// How long a key goes without requests before it is reported idle (1h by default)
+    pub fn with_idle_after(mut self, idle_after: Duration) -> Self {
+        self.idle_after = idle_after;
+        self
+    }
+
+    /// Most keys tracked; requests of further keys are not counted (10,000 by
+    /// default), so a client cycling through made-up keys cannot grow the map
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    fn bucket_secs(&self) -> u64 {
+        self.window.as_secs() / BUCKETS
+    }
+
+    fn shard(&self, key: &str) -> &RwLock<HashMap<String, Arc<KeyEntry>>> {
+        &self.shards[self.hasher.hash_one(key) as usize % SHARDS]
+    }
+
+    fn entry(&self, key: &str) -> Option<Arc<KeyEntry>> {
+        let shard = self.shard(key);
+        if let Some(entry) = shard.read().unwrap().get(key) {
+            return Some(Arc::clone(entry));
+        }
+        let mut entries = shard.write().unwrap();
+        if let Some(entry) = entries.get(key) {
+            return Some(Arc::clone(entry));
+        }
+        if self.keys.load(Ordering::Relaxed) >= self.max_keys as u64 {
+            return None;
+        }
+        self.keys.fetch_add(1, Ordering::Relaxed);
+        let entry = Arc::<KeyEntry>::default();
+        entries.insert(key.to_string(), Arc::clone(&entry));
+        Some(entry)
+    }
+
+    /// Start a request of `key` at `now` (Unix time). The request counts as in flight
+    /// until the returned guard is dropped; `None` once `max_keys` other keys are
+    /// tracked.
+    pub fn start(&self, key: &str, now: u64) -> Option<KeyRequest> {
+        let entry = self.entry(key)?;
+        entry.in_flight.fetch_add(1, Ordering::AcqRel);
+        entry.last_seen.fetch_max(now, Ordering::AcqRel);
+        Some(KeyRequest {
+            entry,
+            bucket_secs: self.bucket_secs(),
+        })
+    }
+
+    /// Requests of `key` in flight
+    pub fn in_flight(&self, key: &str) -> u64 {
+        let shard = self.shard(key).read().unwrap();
+        shard
+            .get(key)
+            .map_or(0, |entry| entry.in_flight.load(Ordering::Acquire))
+    }
+
+    /// Statistics of `key` at `now`, if it was seen
+    pub fn get(&self, key: &str, now: u64) -> Option<KeySnapshot> {
+        let entry = Arc::clone(self.shard(key).read().unwrap().get(key)?);
+        Some(self.snapshot_of(key, &entry, now))
+    }
+
+    /// Statistics of every key at `now`, by key
+    pub fn snapshot(&self, now: u64) -> Vec<KeySnapshot> {
+        let mut snapshots: Vec<KeySnapshot> = self
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                let entries = shard.read().unwrap();
+                entries
+                    .iter()
+                    .map(|(key, entry)| self.snapshot_of(key, entry, now))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        snapshots.sort_by(|a, b| a.key.cmp(&b.key));
+        snapshots
+    }
+
+    /// Keys without a request in flight or within `idle_after` of `now`, longest idle
+    /// first, e.g. keys to revoke
+    pub fn idle(&self, now: u64) -> Vec<KeySnapshot> {
+        let cutoff = now.saturating_sub(self.idle_after.as_secs());
+        let mut idle: Vec<KeySnapshot> = self
+            .snapshot(now)
+            .into_iter()
+            .filter(|key| key.in_flight == 0 && key.last_seen <= cutoff)
+            .collect();
+        idle.sort_by_key(|key| key.last_seen);
+        idle
+    }
+
+    fn snapshot_of(&self, key: &str, entry: &KeyEntry, now: u64) -> KeySnapshot {
+        let current = now / self.bucket_secs();
+        let (requests, errors, tokens) = entry
+            .buckets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|bucket| bucket.slot + BUCKETS > current)
+            .fold((0, 0, 0), |(requests, errors, tokens), bucket| {
+                (
+                    requests + bucket.requests,
+                    errors + bucket.errors,
+                    tokens + bucket.tokens,
+                )
+            });
+        KeySnapshot {
+            key: key.to_string(),
+            in_flight: entry.in_flight.load(Ordering::Acquire),
+            requests,
+            errors,
+            error_rate: match requests {
+                0 => 0.0,
+                requests => errors as f64 / requests as f64,
+            },
+            tokens,
+            last_seen: entry.last_seen.load(Ordering::Acquire),
+        }
+    }
+}
+
+/// A request of a key in flight, counted as such until dropped
+#[derive(Debug)]
+pub struct KeyRequest {
+    entry: Arc<KeyEntry>,
+    bucket_secs: u64,
+}
+
+impl KeyRequest {
+    /// Requests of the key in flight, this one included
+    pub fn in_flight(&self) -> u64 {
+        self.entry.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Record the request's outcome and the tokens it consumed at `now`
+    pub fn finish(self, error: bool, tokens: u64, now: u64) {
+        let slot = now / self.bucket_secs;
+        let mut buckets = self.entry.buckets.lock().unwrap();
+        let bucket = &mut buckets[(slot % BUCKETS) as usize];
+        if bucket.slot != slot {
+            *bucket = Bucket {
+                slot,
+                ..Bucket::default()
+            };
+        }
+        bucket.requests += 1;
+        bucket.errors += u64::from(error);
+        bucket.tokens += tokens;
+    }
+}
+
+impl Drop for KeyRequest {
+    fn drop(&mut self) {
+        self.entry.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
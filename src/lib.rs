@@ -1,8 +1,50 @@
+//! LLM-aware HTTP gateway built on Pingora.
+//!
+//! The `proxy` feature (on by default) provides the Pingora runtime: `GatewayProxy` and
+//! upstream connections. On top of it, `admin` adds the admin API, `tls` adds TLS and
+//! mTLS to upstreams and client certificate authentication on a TLS listener,
+//! `discovery` follows upstreams in Kubernetes or Consul and `snapshot` persists
+//! limiter state across restarts; `egress` tunnels upstream connections through an
+//! egress proxy and `translate` rewrites OpenAI requests for Bedrock and Anthropic
+//! upstreams; `provenance` attaches provenance records to completions, `signing`
+//! verifies signed client requests, `capability` mints and enforces scoped tokens for
+//! browser-side calls, `jwt` validates client JWTs against an identity provider's keys
+//! and `virtual-keys` maps gateway-issued client keys to provider credentials and
+//! policies; `config` reads the YAML gateway config file, `fixtures` adds YAML
+//! detection fixtures and `stub` serves a deterministic stub provider to load-test
+//! against. With `default-features = false` the request pipeline, provider detection
+//! and header policies can be embedded in other HTTP services (axum, hyper, ...): build
+//! a `pingora_http::RequestHeader` from the incoming request and run it through
+//! [`pipeline::Pipeline`] or [`ProviderRegistry`] directly.
+
+#[cfg(feature = "admin")]
+pub mod admin;
+#[cfg(feature = "proxy")]
+pub mod alerts;
+pub mod billing;
+pub mod budget;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(any(feature = "admin", feature = "signing", feature = "provenance"))]
+mod crypto;
+#[cfg(feature = "proxy")]
+mod http_client;
+pub mod key_stats;
+pub mod metrics;
 pub mod pipeline;
 pub mod provider;
 pub mod proxy;
+pub mod quota;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+#[cfg(feature = "stub")]
+pub mod stub;
+#[cfg(feature = "translate")]
+pub mod translate;
+pub mod upstream;
 
 // Stable public API re-exports
 pub use pipeline::views::RequestView;
 pub use provider::{ProviderKind, ProviderRegistry};
+#[cfg(feature = "proxy")]
 pub use proxy::GatewayProxy;
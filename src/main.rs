@@ -1,33 +1,832 @@
+use langspec::pipeline::injection::{InjectionAction, PromptInjectionConfig};
+use langspec::pipeline::moderation::{ModerationAction, ModerationConfig, ModerationPolicy};
+use langspec::pipeline::response_cache::ResponseCacheConfig;
+use langspec::pipeline::semantic_cache::SemanticCacheConfig;
+use langspec::pipeline::tokenizer::BpeTokenizer;
+use langspec::pipeline::usage::UsageConfig;
 use langspec::proxy::GatewayProxy;
+use langspec::proxy::capture::PayloadCapture;
+#[cfg(feature = "tls")]
+use langspec::proxy::client_certs::{ClientCertTenants, ClientCerts};
+use langspec::proxy::headers::{ForwardedFor, HeaderPolicy};
+use langspec::proxy::identity::InstanceIdentity;
+use langspec::proxy::listeners::{ListenerAddr, TenantListeners};
+use langspec::proxy::non_http;
+#[cfg(feature = "provenance")]
+use langspec::proxy::provenance::{ProvenanceConfig, ProvenanceKey};
+use langspec::proxy::stages::StagePolicies;
+#[cfg(feature = "stub")]
+use langspec::stub::{StubConfig, StubProvider};
+#[cfg(feature = "translate")]
+use langspec::translate::unified::UnifiedApi;
+use langspec::upstream::{
+    AdmissionConfig, CapOverflow, ConcurrencyCaps, PreflightConfig, RetryPolicy,
+};
+#[cfg(feature = "egress")]
+use langspec::upstream::{EgressConfig, EgressProxy};
 use log::info;
+use pingora::apps::HttpServerOptions;
+use pingora::http::RequestHeader;
+#[cfg(feature = "tls")]
+use pingora::listeners::tls::TlsSettings;
 use pingora::prelude::*;
+use pingora::services::listening::Service;
 
 fn main() {
-    // Set up logging
-    env_logger::init();
+    // Set up logging from RUST_LOG, with credentials masked; connections sending
+    // non-HTTP bytes are counted whatever it filters
+    non_http::Logger::new(langspec::proxy::scrub::logger().build()).init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("detect") => {
+            #[cfg(feature = "fixtures")]
+            std::process::exit(detect(&args[1..]));
+            #[cfg(not(feature = "fixtures"))]
+            {
+                eprintln!("langspec detect requires the `fixtures` feature");
+                std::process::exit(2);
+            }
+        }
+        Some("explain") => std::process::exit(explain(&args[1..])),
+        _ => {}
+    }
 
     // Create the server with configuration
     let mut server = Server::new(None).unwrap();
     server.bootstrap();
 
+    // Create proxy instance
+    let gateway = gateway();
+    // LANGSPEC_ADMIN_LISTEN: serve the admin API there instead of 127.0.0.1:9090;
+    // LANGSPEC_ADMIN_TOKEN: require it as a bearer token on every admin request
+    #[cfg(feature = "admin")]
+    let admin = {
+        let addr = admin_listen();
+        let mut app = gateway.admin_app();
+        if let Ok(token) = std::env::var("LANGSPEC_ADMIN_TOKEN") {
+            app = app.with_token(token);
+        }
+        let mut admin = Service::new("Admin API".to_string(), app);
+        admin.add_tcp(&addr);
+        info!("Admin API listening on {}", addr);
+        admin
+    };
+    // Prometheus scrape endpoint, on its own port so it can be exposed separately:
+    // LANGSPEC_METRICS_LISTEN instead of 127.0.0.1:9091, or `off` to only answer scrapes
+    // on the proxy's LANGSPEC_METRICS_PATH
+    let metrics = match std::env::var("LANGSPEC_METRICS_LISTEN").as_deref() {
+        Ok("off") => None,
+        addr => {
+            let addr = addr.unwrap_or("127.0.0.1:9091").to_string();
+            let mut metrics = Service::prometheus_http_service();
+            metrics.add_tcp(&addr);
+            Some((metrics, addr))
+        }
+    };
+    // LANGSPEC_STUB_ADDR: serve the stub provider there, for upstreams to point at in
+    // load tests
+    #[cfg(feature = "stub")]
+    let stub = std::env::var("LANGSPEC_STUB_ADDR").ok().map(|addr| {
+        let mut stub = Service::new(
+            "Stub provider".to_string(),
+            StubProvider::new(stub_config()),
+        );
+        stub.add_tcp(&addr);
+        (stub, addr)
+    });
+    let dns_refresh = gateway.dns_refresh_service();
+    #[cfg(feature = "discovery")]
+    let discovery = gateway.discovery_service();
+    #[cfg(feature = "snapshot")]
+    let snapshot = gateway.snapshot_service();
+    #[cfg(feature = "egress")]
+    let egress = gateway.egress_relay_service();
+    let tenant_listeners = gateway.tenant_listeners().cloned().unwrap_or_default();
+    #[cfg(feature = "tls")]
+    let client_certs = gateway.client_certs().cloned();
+    let upstreams: Vec<&str> = gateway
+        .upstreams()
+        .iter()
+        .map(|upstream| upstream.address())
+        .collect();
+    let upstreams = upstreams.join(", ");
+    let grpc = gateway.grpc_enabled();
+    let mut proxy = http_proxy_service(&server.configuration, gateway);
+    if grpc {
+        let mut options = HttpServerOptions::default();
+        options.h2c = true;
+        if let Some(app) = proxy.app_logic_mut() {
+            app.server_options = Some(options);
+        }
+    }
+
+    // Add listening address
+    let addr = "127.0.0.1:8080";
+    proxy.add_tcp(addr);
+    info!("Listening on {}", addr);
+    // LANGSPEC_TLS_LISTEN: also serve TLS there, with the PEM certificate chain
+    // LANGSPEC_TLS_CERT and key LANGSPEC_TLS_KEY
+    #[cfg(feature = "tls")]
+    if let Ok(addr) = std::env::var("LANGSPEC_TLS_LISTEN") {
+        let (Ok(cert), Ok(key)) = (
+            std::env::var("LANGSPEC_TLS_CERT"),
+            std::env::var("LANGSPEC_TLS_KEY"),
+        ) else {
+            eprintln!("LANGSPEC_TLS_LISTEN requires LANGSPEC_TLS_CERT and LANGSPEC_TLS_KEY");
+            std::process::exit(1);
+        };
+        let ca = std::env::var("LANGSPEC_TLS_CLIENT_CA").ok();
+        let settings = match (client_certs, ca) {
+            (Some(client_certs), Some(ca)) => client_certs.tls_settings(&cert, &key, &ca),
+            _ => TlsSettings::intermediate(&cert, &key),
+        };
+        match settings {
+            Ok(mut settings) => {
+                if grpc {
+                    settings.enable_h2();
+                }
+                proxy.add_tls_with_settings(&addr, None, settings);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        }
+        info!("Listening for TLS on {}", addr);
+    }
+    for listener in &tenant_listeners.listeners {
+        match &listener.addr {
+            ListenerAddr::Tcp(addr) => proxy.add_tcp(&addr.to_string()),
+            ListenerAddr::Unix(path) => proxy.add_uds(&path.to_string_lossy(), None),
+        }
+        info!(
+            "Listening for tenant '{}' on {}",
+            listener.tenant, listener.addr
+        );
+    }
+
+    // Add the service to the server
+    server.add_service(proxy);
+    #[cfg(feature = "admin")]
+    server.add_service(admin);
+    if let Some((metrics, addr)) = metrics {
+        server.add_service(metrics);
+        info!("Prometheus metrics on {}", addr);
+    }
+    #[cfg(feature = "stub")]
+    if let Some((stub, addr)) = stub {
+        server.add_service(stub);
+        info!("Stub provider listening on {}", addr);
+    }
+    if let Some(dns_refresh) = dns_refresh {
+        server.add_service(background_service("DNS refresh", dns_refresh));
+    }
+    #[cfg(feature = "discovery")]
+    if let Some(discovery) = discovery {
+        server.add_service(background_service("Service discovery", discovery));
+    }
+    #[cfg(feature = "snapshot")]
+    if let Some(snapshot) = snapshot {
+        server.add_service(background_service("State snapshots", snapshot));
+    }
+    #[cfg(feature = "egress")]
+    if let Some(egress) = egress {
+        server.add_service(background_service("Egress relay", egress));
+    }
+
+    // Run the server
+    info!("Starting proxy server");
+    info!("Configured upstreams: {}", upstreams);
+    server.run_forever();
+}
+
+/// The gateway as configured for serving, shared with `langspec explain`
+fn gateway() -> GatewayProxy {
     // Define upstream servers
     let upstreams = vec![
         "127.0.0.1:8001".to_string(),
         "127.0.0.1:8002".to_string(),
         "127.0.0.1:8003".to_string(),
     ];
+    // LANGSPEC_BEDROCK_FACADE: a Bedrock runtime endpoint (e.g.
+    // `https://bedrock-runtime.us-east-1.amazonaws.com`) serving the OpenAI API alone,
+    // with the `alias=model-id` pairs of LANGSPEC_BEDROCK_MODELS as its listed models
+    #[cfg(feature = "translate")]
+    let facade = std::env::var("LANGSPEC_BEDROCK_FACADE").ok();
+    #[cfg(feature = "translate")]
+    let upstreams = facade.clone().map_or(upstreams, |endpoint| vec![endpoint]);
+    let gateway = GatewayProxy::new(upstreams).with_identity(InstanceIdentity::from_env());
+    #[cfg(feature = "translate")]
+    let gateway = match facade {
+        Some(_) => {
+            let models = std::env::var("LANGSPEC_BEDROCK_MODELS").unwrap_or_default();
+            let models = models
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(|entry| match entry.split_once('=') {
+                    Some((alias, model)) => (alias.trim(), model.trim()),
+                    None => {
+                        eprintln!("invalid LANGSPEC_BEDROCK_MODELS entry '{}'", entry);
+                        std::process::exit(1);
+                    }
+                });
+            let api = UnifiedApi::bedrock_facade(gateway.upstreams()[0].address(), models);
+            gateway.with_unified_api(api)
+        }
+        None => gateway,
+    };
+    #[cfg(feature = "egress")]
+    let gateway = match std::env::var("LANGSPEC_EGRESS_PROXY") {
+        Ok(url) => {
+            // Bypass rules from LANGSPEC_EGRESS_NO_PROXY, or the conventional NO_PROXY
+            let no_proxy = ["LANGSPEC_EGRESS_NO_PROXY", "NO_PROXY", "no_proxy"]
+                .into_iter()
+                .find_map(|name| std::env::var(name).ok())
+                .unwrap_or_default();
+            let config = EgressProxy::parse(&url)
+                .and_then(|proxy| EgressConfig::new(proxy).with_no_proxy(&no_proxy));
+            match config {
+                Ok(config) => gateway.with_egress_proxy(config),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Err(_) => gateway,
+    };
+    // LANGSPEC_TOKENIZER: a `.tiktoken` vocabulary (e.g. cl100k_base.tiktoken) to count
+    // tokens the provider does not report; enables usage tracking
+    let gateway = match std::env::var("LANGSPEC_TOKENIZER") {
+        Ok(path) => {
+            // Named after the encoding, e.g. `cl100k_base`; built once for the process
+            let name = std::path::Path::new(&path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("bpe");
+            let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+            match BpeTokenizer::from_tiktoken_file(name, &path) {
+                Ok(tokenizer) => {
+                    gateway.with_usage_tracking(UsageConfig::new().with_tokenizer(tokenizer))
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Err(_) => gateway,
+    };
+    // LANGSPEC_TRUSTED_PROXIES: addresses and CIDR ranges of the proxies in front of the
+    // gateway, whose X-Forwarded-For names the client; LANGSPEC_FORWARDED_FOR: forward
+    // client addresses to upstreams in `x-forwarded-for`, `forwarded` or `both` headers
+    let gateway = match (
+        std::env::var("LANGSPEC_FORWARDED_FOR"),
+        std::env::var("LANGSPEC_TRUSTED_PROXIES"),
+    ) {
+        (Err(_), Err(_)) => gateway,
+        (mode, proxies) => {
+            let mode = mode.unwrap_or_else(|_| "off".to_string());
+            let Some(forwarded_for) = ForwardedFor::parse(mode.trim()) else {
+                eprintln!(
+                    "invalid LANGSPEC_FORWARDED_FOR '{}': use x-forwarded-for, forwarded, both or off",
+                    mode
+                );
+                std::process::exit(1);
+            };
+            match HeaderPolicy::new()
+                .with_forwarded_for(forwarded_for)
+                .with_trusted_proxy_list(&proxies.unwrap_or_default())
+            {
+                Ok(policy) => gateway.with_header_policy(policy),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+    // LANGSPEC_METRICS_PATH: answer scrapes of that path (e.g. `/metrics`) on the proxy
+    // listeners instead of proxying them
+    let gateway = match std::env::var("LANGSPEC_METRICS_PATH") {
+        Ok(path) if path.starts_with('/') => gateway.with_metrics_path(path),
+        Ok(path) => {
+            eprintln!(
+                "invalid LANGSPEC_METRICS_PATH '{}': use a path starting with /",
+                path
+            );
+            std::process::exit(1);
+        }
+        Err(_) => gateway,
+    };
+    // LANGSPEC_PROVENANCE: `header`, `audit` or both, comma separated
+    #[cfg(feature = "provenance")]
+    let gateway = match std::env::var("LANGSPEC_PROVENANCE") {
+        Ok(modes) => {
+            let mut config = ProvenanceConfig::new();
+            for mode in modes
+                .split(',')
+                .map(str::trim)
+                .filter(|mode| !mode.is_empty())
+            {
+                config = match mode {
+                    "header" => config.with_response_header(),
+                    "audit" => config.with_audit(),
+                    _ => {
+                        eprintln!("invalid LANGSPEC_PROVENANCE mode '{}'", mode);
+                        std::process::exit(2);
+                    }
+                };
+            }
+            if let Ok(secret) = std::env::var("LANGSPEC_PROVENANCE_KEY") {
+                config = config.with_signing_key(ProvenanceKey::new(secret));
+            }
+            gateway.with_provenance(config)
+        }
+        Err(_) => gateway,
+    };
+    // LANGSPEC_CAPTURE_DIR: where payloads sampled by capture windows (opened through
+    // the admin API) are written
+    let gateway = match std::env::var("LANGSPEC_CAPTURE_DIR") {
+        Ok(dir) => gateway.with_payload_capture(PayloadCapture::new(dir)),
+        Err(_) => gateway,
+    };
+    // LANGSPEC_GRPC=1: pass gRPC calls through to the upstreams over HTTP/2, the
+    // listeners accepting HTTP/2 (h2c, or ALPN on TLS)
+    let gateway = match std::env::var("LANGSPEC_GRPC").is_ok_and(|v| v == "1") {
+        true => gateway.with_grpc(),
+        false => gateway,
+    };
+    // LANGSPEC_TENANT_LISTENERS: `tenant=ip:port` or `tenant=unix:/path` listeners,
+    // comma-separated, whose requests are made as the tenant; with
+    // LANGSPEC_TENANT_LISTENERS_EXCLUSIVE=1 those tenants can only use their listener
+    let gateway =
+        match std::env::var("LANGSPEC_TENANT_LISTENERS").map(|v| TenantListeners::parse(&v)) {
+            Ok(Ok(listeners)) => gateway.with_tenant_listeners(listeners.with_exclusive(
+                std::env::var("LANGSPEC_TENANT_LISTENERS_EXCLUSIVE").is_ok_and(|v| v == "1"),
+            )),
+            Ok(Err(e)) => {
+                eprintln!("invalid LANGSPEC_TENANT_LISTENERS: {}", e);
+                std::process::exit(1);
+            }
+            Err(_) => gateway,
+        };
+    // LANGSPEC_TLS_CLIENT_CA: verify client certificates on the TLS listener against this
+    // PEM CA bundle, making requests as the tenant LANGSPEC_CLIENT_CERT_TENANTS maps the
+    // certificate to (`tenant=cn:...`, `tenant=o:...` or `tenant=san:...`,
+    // comma-separated); with LANGSPEC_CLIENT_CERT_REQUIRED=1 connections without one
+    // are refused
+    #[cfg(feature = "tls")]
+    let gateway = match std::env::var("LANGSPEC_TLS_CLIENT_CA") {
+        Ok(_) => {
+            let rules = std::env::var("LANGSPEC_CLIENT_CERT_TENANTS").unwrap_or_default();
+            match ClientCertTenants::parse(&rules) {
+                Ok(tenants) => gateway.with_client_certs(std::sync::Arc::new(
+                    ClientCerts::new(tenants).with_required(
+                        std::env::var("LANGSPEC_CLIENT_CERT_REQUIRED").is_ok_and(|v| v == "1"),
+                    ),
+                )),
+                Err(e) => {
+                    eprintln!("invalid LANGSPEC_CLIENT_CERT_TENANTS: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Err(_) => gateway,
+    };
+    // LANGSPEC_STAGE_POLICIES: `stage=open` or `stage=closed` overrides of what a request
+    // does when a stage panics or errors, comma-separated
+    let gateway = match std::env::var("LANGSPEC_STAGE_POLICIES").map(|v| StagePolicies::parse(&v)) {
+        Ok(Ok(policies)) => gateway.with_stage_policies(policies),
+        Ok(Err(e)) => {
+            eprintln!("invalid LANGSPEC_STAGE_POLICIES: {}", e);
+            std::process::exit(1);
+        }
+        Err(_) => gateway,
+    };
+    // LANGSPEC_PREFLIGHT_MIN_TOKENS: check an upstream is up before sending it prompts of
+    // at least this many (estimated) tokens
+    let gateway = match std::env::var("LANGSPEC_PREFLIGHT_MIN_TOKENS").map(|v| v.parse()) {
+        Ok(Ok(min_prompt_tokens)) => gateway.with_preflight_checks(
+            PreflightConfig::new().with_min_prompt_tokens(min_prompt_tokens),
+        ),
+        Ok(Err(e)) => {
+            eprintln!("invalid LANGSPEC_PREFLIGHT_MIN_TOKENS: {}", e);
+            std::process::exit(1);
+        }
+        Err(_) => gateway,
+    };
+    // LANGSPEC_UPSTREAM_MAX_IN_FLIGHT: cap on the requests in flight to every upstream
+    // and/or `host:port=N` caps of single upstreams, comma-separated
+    let gateway = match std::env::var("LANGSPEC_UPSTREAM_MAX_IN_FLIGHT") {
+        Ok(list) => gateway.with_concurrency_caps(concurrency_caps(&list)),
+        Err(_) => gateway,
+    };
+    // LANGSPEC_ADMISSION_QUEUE_DEPTH: queue up to this many requests finding no upstream
+    // with room, each for up to LANGSPEC_ADMISSION_QUEUE_TIMEOUT_MS (1000 by default)
+    let gateway = match std::env::var("LANGSPEC_ADMISSION_QUEUE_DEPTH").map(|v| v.parse()) {
+        Ok(Ok(max_depth)) if max_depth > 0 => {
+            let timeout_ms =
+                match std::env::var("LANGSPEC_ADMISSION_QUEUE_TIMEOUT_MS").map(|v| v.parse()) {
+                    Ok(Ok(timeout_ms)) => timeout_ms,
+                    Ok(Err(e)) => {
+                        eprintln!("invalid LANGSPEC_ADMISSION_QUEUE_TIMEOUT_MS: {}", e);
+                        std::process::exit(1);
+                    }
+                    Err(_) => 1000,
+                };
+            let timeout = std::time::Duration::from_millis(timeout_ms);
+            gateway.with_admission_queue(AdmissionConfig::new(max_depth, timeout))
+        }
+        Ok(_) => {
+            eprintln!("invalid LANGSPEC_ADMISSION_QUEUE_DEPTH: use a positive number");
+            std::process::exit(1);
+        }
+        Err(_) => gateway,
+    };
+    // LANGSPEC_RETRY_MAX_ATTEMPTS: retry requests upstreams fail, up to this many
+    // attempts in all, with backoff
+    let gateway = match std::env::var("LANGSPEC_RETRY_MAX_ATTEMPTS") {
+        Ok(max_attempts) => gateway.with_retry_policy(retry_policy(&max_attempts)),
+        Err(_) => gateway,
+    };
+    // LANGSPEC_RESPONSE_CACHE_TTL_SECS: answer identical non-streaming completions from
+    // memory for this long; LANGSPEC_RESPONSE_CACHE_SAMPLED=1 also caches requests with
+    // a temperature above 0
+    let gateway = match std::env::var("LANGSPEC_RESPONSE_CACHE_TTL_SECS").map(|v| v.parse()) {
+        Ok(Ok(ttl)) => gateway.with_response_cache(
+            ResponseCacheConfig::new()
+                .with_ttl(std::time::Duration::from_secs(ttl))
+                .with_cache_sampled(
+                    std::env::var("LANGSPEC_RESPONSE_CACHE_SAMPLED").is_ok_and(|v| v == "1"),
+                ),
+        ),
+        Ok(Err(e)) => {
+            eprintln!("invalid LANGSPEC_RESPONSE_CACHE_TTL_SECS: {}", e);
+            std::process::exit(1);
+        }
+        Err(_) => gateway,
+    };
+    // LANGSPEC_SEMANTIC_CACHE_EMBEDDINGS_URL: answer completions whose conversation is
+    // similar to a cached one, embedding it with this OpenAI-compatible endpoint
+    // (LANGSPEC_SEMANTIC_CACHE_EMBEDDINGS_MODEL, bearer LANGSPEC_SEMANTIC_CACHE_API_KEY)
+    // and serving at LANGSPEC_SEMANTIC_CACHE_THRESHOLD cosine similarity or above
+    let gateway = match std::env::var("LANGSPEC_SEMANTIC_CACHE_EMBEDDINGS_URL") {
+        Ok(url) => {
+            let mut config = SemanticCacheConfig::new(url);
+            if let Ok(model) = std::env::var("LANGSPEC_SEMANTIC_CACHE_EMBEDDINGS_MODEL") {
+                config = config.with_embeddings_model(model);
+            }
+            if let Ok(api_key) = std::env::var("LANGSPEC_SEMANTIC_CACHE_API_KEY") {
+                config = config.with_embeddings_api_key(api_key);
+            }
+            match std::env::var("LANGSPEC_SEMANTIC_CACHE_THRESHOLD").map(|v| v.parse::<f32>()) {
+                Ok(Ok(threshold)) if threshold > 0.0 && threshold <= 1.0 => {
+                    config = config.with_threshold(threshold);
+                }
+                Ok(_) => {
+                    eprintln!("invalid LANGSPEC_SEMANTIC_CACHE_THRESHOLD: use a number in (0, 1]");
+                    std::process::exit(1);
+                }
+                Err(_) => {}
+            }
+            gateway.with_semantic_cache(config)
+        }
+        Err(_) => gateway,
+    };
+    // LANGSPEC_PROMPT_INJECTION=tag|log|block: screen prompts for prompt injection with
+    // the built-in patterns and, with LANGSPEC_PROMPT_INJECTION_CLASSIFIER_URL (bearer
+    // LANGSPEC_PROMPT_INJECTION_CLASSIFIER_API_KEY), a classifier flagging scores of
+    // LANGSPEC_PROMPT_INJECTION_THRESHOLD or above
+    let gateway = match std::env::var("LANGSPEC_PROMPT_INJECTION") {
+        Ok(action) => {
+            let Some(action) = InjectionAction::parse(&action) else {
+                eprintln!("invalid LANGSPEC_PROMPT_INJECTION: use tag, log or block");
+                std::process::exit(1);
+            };
+            let mut config = PromptInjectionConfig::new().with_action(action);
+            if let Ok(url) = std::env::var("LANGSPEC_PROMPT_INJECTION_CLASSIFIER_URL") {
+                config = config.with_classifier(url);
+            }
+            if let Ok(api_key) = std::env::var("LANGSPEC_PROMPT_INJECTION_CLASSIFIER_API_KEY") {
+                config = config.with_classifier_api_key(api_key);
+            }
+            match std::env::var("LANGSPEC_PROMPT_INJECTION_THRESHOLD").map(|v| v.parse::<f32>()) {
+                Ok(Ok(threshold)) if threshold > 0.0 && threshold <= 1.0 => {
+                    config = config.with_threshold(threshold);
+                }
+                Ok(_) => {
+                    eprintln!(
+                        "invalid LANGSPEC_PROMPT_INJECTION_THRESHOLD: use a number in (0, 1]"
+                    );
+                    std::process::exit(1);
+                }
+                Err(_) => {}
+            }
+            gateway.with_prompt_injection(config)
+        }
+        Err(_) => gateway,
+    };
+    // LANGSPEC_MODERATION_URL: moderate prompts with this OpenAI-compatible moderations
+    // endpoint (LANGSPEC_MODERATION_MODEL, bearer LANGSPEC_MODERATION_API_KEY), taking
+    // LANGSPEC_MODERATION_ACTION (annotate or block) on flagged ones; with
+    // LANGSPEC_MODERATION_COMPLETIONS=1 completions are moderated too. Routes take
+    // their own action from LANGSPEC_MODERATION_ROUTES (`prefix=annotate|block|off`,
+    // comma-separated).
+    let gateway = match std::env::var("LANGSPEC_MODERATION_URL") {
+        Ok(url) => {
+            let mut policy = ModerationPolicy::new().with_completions(
+                std::env::var("LANGSPEC_MODERATION_COMPLETIONS").is_ok_and(|v| v == "1"),
+            );
+            if let Ok(action) = std::env::var("LANGSPEC_MODERATION_ACTION") {
+                let Some(action) = ModerationAction::parse(&action) else {
+                    eprintln!("invalid LANGSPEC_MODERATION_ACTION: use annotate or block");
+                    std::process::exit(1);
+                };
+                policy = policy.with_action(action);
+            }
+            let mut config = ModerationConfig::new(url).with_default(policy);
+            if let Ok(model) = std::env::var("LANGSPEC_MODERATION_MODEL") {
+                config = config.with_model(model);
+            }
+            if let Ok(api_key) = std::env::var("LANGSPEC_MODERATION_API_KEY") {
+                config = config.with_api_key(api_key);
+            }
+            if let Ok(routes) = std::env::var("LANGSPEC_MODERATION_ROUTES") {
+                config = match config.with_route_list(&routes) {
+                    Ok(config) => config,
+                    Err(e) => {
+                        eprintln!("invalid LANGSPEC_MODERATION_ROUTES: {}", e);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            gateway.with_moderation(config)
+        }
+        Err(_) => gateway,
+    };
+    #[cfg(feature = "config")]
+    if let Ok(path) = std::env::var("LANGSPEC_CONFIG") {
+        match gateway.with_config_file(path) {
+            Ok(gateway) => return gateway,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+    gateway
+}
 
-    // Create proxy instance
-    let mut proxy = http_proxy_service(&server.configuration, GatewayProxy::new(upstreams));
+/// Upstream concurrency caps from a LANGSPEC_UPSTREAM_MAX_IN_FLIGHT list, handling
+/// requests over a cap as LANGSPEC_UPSTREAM_CAP_OVERFLOW says: `reroute` (default),
+/// `reject`, or `queue:<ms>` to wait up to that long for a slot
+fn concurrency_caps(list: &str) -> ConcurrencyCaps {
+    let invalid = |name: &str, value: &str| -> ! {
+        eprintln!("invalid {}: '{}'", name, value);
+        std::process::exit(1);
+    };
+    let mut caps = ConcurrencyCaps::new();
+    for entry in list
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (address, cap) = match entry.rsplit_once('=') {
+            Some((address, cap)) => (Some(address.trim()), cap.trim()),
+            None => (None, entry),
+        };
+        let cap = match cap.parse::<usize>() {
+            Ok(cap) if cap > 0 => cap,
+            _ => invalid("LANGSPEC_UPSTREAM_MAX_IN_FLIGHT", entry),
+        };
+        caps = match address {
+            Some(address) => caps.with_upstream(address, cap),
+            None => caps.with_default(cap),
+        };
+    }
+    let overflow = std::env::var("LANGSPEC_UPSTREAM_CAP_OVERFLOW").unwrap_or_default();
+    let overflow = match overflow.as_str() {
+        "" | "reroute" => CapOverflow::Reroute,
+        "reject" => CapOverflow::Reject,
+        queue => match queue.strip_prefix("queue:").map(str::parse) {
+            Some(Ok(ms)) => CapOverflow::Queue(std::time::Duration::from_millis(ms)),
+            _ => invalid("LANGSPEC_UPSTREAM_CAP_OVERFLOW", queue),
+        },
+    };
+    caps.with_overflow(overflow)
+}
 
-    // Add listening address
-    proxy.add_tcp("127.0.0.1:8080");
+/// Retry policy of up to `max_attempts` attempts, retrying the statuses of
+/// LANGSPEC_RETRY_STATUSES (429,502,503,504 by default) after a backoff from
+/// LANGSPEC_RETRY_BACKOFF_MS doubling up to LANGSPEC_RETRY_MAX_BACKOFF_MS; with
+/// LANGSPEC_RETRY_NON_IDEMPOTENT=1 POST requests are retried once sent too
+fn retry_policy(max_attempts: &str) -> RetryPolicy {
+    let invalid = |name: &str, value: &dyn std::fmt::Display| -> ! {
+        eprintln!("invalid {}: {}", name, value);
+        std::process::exit(1);
+    };
+    let millis = |name: &str| match std::env::var(name).map(|v| v.parse::<u64>()) {
+        Ok(Ok(ms)) => Some(std::time::Duration::from_millis(ms)),
+        Ok(Err(e)) => invalid(name, &e),
+        Err(_) => None,
+    };
+    let policy = match max_attempts.parse::<usize>() {
+        Ok(max_attempts) if max_attempts > 0 => RetryPolicy::new().with_max_attempts(max_attempts),
+        _ => invalid("LANGSPEC_RETRY_MAX_ATTEMPTS", &"use a positive number"),
+    };
+    let policy = match std::env::var("LANGSPEC_RETRY_STATUSES") {
+        Ok(list) => policy
+            .with_status_list(&list)
+            .unwrap_or_else(|e| invalid("LANGSPEC_RETRY_STATUSES", &e)),
+        Err(_) => policy,
+    };
+    let base_backoff = millis("LANGSPEC_RETRY_BACKOFF_MS").unwrap_or(policy.base_backoff);
+    let max_backoff = millis("LANGSPEC_RETRY_MAX_BACKOFF_MS").unwrap_or(policy.max_backoff);
+    policy
+        .with_backoff(base_backoff, max_backoff)
+        .with_non_idempotent(std::env::var("LANGSPEC_RETRY_NON_IDEMPOTENT").is_ok_and(|v| v == "1"))
+}
 
-    // Add the service to the server
-    server.add_service(proxy);
+/// Admin API address from LANGSPEC_ADMIN_LISTEN, 127.0.0.1:9090 by default. It must
+/// be a private address, and one off the loopback interface needs LANGSPEC_ADMIN_TOKEN.
+#[cfg(feature = "admin")]
+fn admin_listen() -> String {
+    use std::net::{IpAddr, SocketAddr};
 
-    // Run the server
-    info!("Starting proxy server on 127.0.0.1:8080");
-    info!("Configured upstreams: 127.0.0.1:8001, 127.0.0.1:8002, 127.0.0.1:8003");
-    server.run_forever();
+    let addr =
+        std::env::var("LANGSPEC_ADMIN_LISTEN").unwrap_or_else(|_| "127.0.0.1:9090".to_string());
+    let ip = addr.parse::<SocketAddr>().map(|addr| addr.ip());
+    let (private, loopback) = match ip {
+        Ok(IpAddr::V4(ip)) => (ip.is_private() || ip.is_link_local(), ip.is_loopback()),
+        Ok(IpAddr::V6(ip)) => (
+            ip.is_unique_local() || ip.is_unicast_link_local(),
+            ip.is_loopback(),
+        ),
+        Err(_) => {
+            eprintln!("invalid LANGSPEC_ADMIN_LISTEN '{}': use ip:port", addr);
+            std::process::exit(1);
+        }
+    };
+    if !private && !loopback {
+        eprintln!(
+            "invalid LANGSPEC_ADMIN_LISTEN '{}': the admin API must listen on a loopback or private address",
+            addr
+        );
+        std::process::exit(1);
+    }
+    if !loopback && std::env::var("LANGSPEC_ADMIN_TOKEN").is_err() {
+        eprintln!("LANGSPEC_ADMIN_LISTEN off the loopback interface requires LANGSPEC_ADMIN_TOKEN");
+        std::process::exit(1);
+    }
+    addr
+}
+
+/// Stub provider completions as set by LANGSPEC_STUB_SEED, LANGSPEC_STUB_TOKENS and
+/// LANGSPEC_STUB_TOKENS_PER_SEC
+#[cfg(feature = "stub")]
+fn stub_config() -> StubConfig {
+    let var = |name: &str| match std::env::var(name).map(|v| v.parse::<u64>()) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(e)) => {
+            eprintln!("invalid {}: {}", name, e);
+            std::process::exit(1);
+        }
+        Err(_) => None,
+    };
+    let config = StubConfig::new();
+    let config = match var("LANGSPEC_STUB_SEED") {
+        Some(seed) => config.with_seed(seed),
+        None => config,
+    };
+    let config = match var("LANGSPEC_STUB_TOKENS") {
+        Some(tokens) => config.with_completion_tokens(tokens),
+        None => config,
+    };
+    match var("LANGSPEC_STUB_TOKENS_PER_SEC") {
+        Some(rate) => config.with_tokens_per_sec(rate),
+        None => config,
+    }
+}
+
+/// `langspec explain --url <url> [--method M] [--header 'Name: value']... [--body @file|text]`:
+/// print how the gateway would handle a request, without sending any traffic.
+fn explain(args: &[String]) -> i32 {
+    const USAGE: &str = "usage: langspec explain --url <url> [--method M] [--header 'Name: value']... [--body @file|text]";
+
+    let mut method = None;
+    let mut url = None;
+    let mut headers = Vec::new();
+    let mut body = None;
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let Some(value) = args.next() else {
+            eprintln!("{}", USAGE);
+            return 2;
+        };
+        match flag.as_str() {
+            "--method" => method = Some(value.to_ascii_uppercase()),
+            "--url" => url = Some(value),
+            "--header" => match value.split_once(':') {
+                Some((name, value)) => headers.push((name.trim(), value.trim())),
+                None => {
+                    eprintln!("invalid header '{}', expected 'Name: value'", value);
+                    return 2;
+                }
+            },
+            "--body" => match value.strip_prefix('@') {
+                Some(path) => match std::fs::read(path) {
+                    Ok(contents) => body = Some(contents),
+                    Err(e) => {
+                        eprintln!("cannot read '{}': {}", path, e);
+                        return 2;
+                    }
+                },
+                None => body = Some(value.as_bytes().to_vec()),
+            },
+            _ => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        }
+    }
+    let Some(url) = url else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+    let Ok(uri) = url.parse::<http::Uri>() else {
+        eprintln!("invalid url '{}'", url);
+        return 2;
+    };
+
+    // POST when a body is given, like curl
+    let method = method.unwrap_or_else(|| if body.is_some() { "POST" } else { "GET" }.to_string());
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    let request =
+        RequestHeader::build(method.as_str(), path.as_bytes(), None).and_then(|mut request| {
+            if let Some(authority) = uri.authority() {
+                request.insert_header("host", authority.as_str())?;
+            }
+            for (name, value) in &headers {
+                request.insert_header(name.to_string(), *value)?;
+            }
+            Ok(request)
+        });
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("invalid request: {}", e);
+            return 2;
+        }
+    };
+
+    print!("{}", gateway().explain(&request, body.as_deref()));
+    0
+}
+
+/// `langspec detect --fixture <file|dir>`: run detection against YAML fixtures and
+/// report mismatches, exiting non-zero if any fixture fails.
+#[cfg(feature = "fixtures")]
+fn detect(args: &[String]) -> i32 {
+    use langspec::provider::ProviderRegistry;
+    use langspec::provider::fixtures::DetectionFixture;
+
+    let path = match args {
+        [flag, path] if flag == "--fixture" => path,
+        _ => {
+            eprintln!("usage: langspec detect --fixture <file|dir>");
+            return 2;
+        }
+    };
+    let fixtures = match DetectionFixture::load(path) {
+        Ok(fixtures) => fixtures,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let registry = ProviderRegistry::new();
+    let mut failed = 0;
+    for fixture in &fixtures {
+        match fixture.detect(&registry) {
+            Ok(detected) if fixture.matches(&detected) => println!("ok    {}", fixture.name),
+            Ok(detected) => {
+                failed += 1;
+                println!(
+                    "FAIL  {}: expected {}, detected {}",
+                    fixture.name, fixture.expect, detected
+                );
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAIL  {}", e);
+            }
+        }
+    }
+
+    println!("{} fixtures, {} failed", fixtures.len(), failed);
+    if failed > 0 { 1 } else { 0 }
 }
@@ -0,0 +1,644 @@
+//! Gateway metrics registered in the default Prometheus registry.
+//!
+//! Metrics are created lazily on first use so that subsystems which are not
+//! enabled never register series. The binary serves the registry on a dedicated
+//! port in the Prometheus text format, and the proxy can serve it on a path of its own
+//! listeners too.
+
+use prometheus::{
+    CounterVec, Encoder, Gauge, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+    register_counter_vec, register_gauge, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec,
+};
+use std::collections::HashSet;
+use std::sync::{LazyLock, Mutex};
+
+/// Distinct `model` label values before further models are counted as `other`: model
+/// names come from clients, so they must not grow the series without bound
+pub const MAX_MODEL_LABELS: usize = 256;
+
+static MODEL_LABELS: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Content type of [`exposition`]
+pub const EXPOSITION_CONTENT_TYPE: &str = prometheus::TEXT_FORMAT;
+
+/// Every registered metric in the Prometheus text format, as a scrape gets it
+pub fn exposition() -> Vec<u8> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("metric families encode");
+    buffer
+}
+
+/// `model` label of a request: its model, `none` without one, or `other` once
+/// [`MAX_MODEL_LABELS`] models have been seen
+pub fn model_label(model: Option<&str>) -> String {
+    let Some(model) = model else {
+        return "none".to_string();
+    };
+    let mut labels = MODEL_LABELS.lock().unwrap();
+    if labels.contains(model) || labels.len() < MAX_MODEL_LABELS {
+        labels.insert(model.to_string());
+        return model.to_string();
+    }
+    "other".to_string()
+}
+
+/// Proxied requests by provider, upstream, model and response status (0 when no
+/// response was sent)
+pub static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_requests_total",
+        "Proxied requests by provider, upstream, model and response status",
+        &["provider", "upstream", "model", "status"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests that failed with an error, by provider, upstream, model and error type (the
+/// failure class for upstream failures); divide by `langspec_requests_total` for the
+/// error rate
+pub static REQUEST_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_request_errors_total",
+        "Proxied requests that failed with an error, by error type",
+        &["provider", "upstream", "model", "error"]
+    )
+    .expect("metric can be registered")
+});
+
+/// End-to-end request latency, from the request arriving to the response completing
+pub static REQUEST_DURATION_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "langspec_request_duration_seconds",
+        "Time from receiving a request to completing its response",
+        &["provider", "upstream", "model", "status"],
+        vec![
+            0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0
+        ]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests currently sent to an upstream
+pub static REQUESTS_IN_FLIGHT: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "langspec_requests_in_flight",
+        "Requests currently proxied to an upstream",
+        &["provider", "upstream"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Failures reaching or reading from an upstream, by upstream and failure class (see
+/// `proxy::upstream_errors::UpstreamFailure`)
+pub static UPSTREAM_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_upstream_failures_total",
+        "Failures reaching or reading from an upstream by failure class",
+        &["upstream", "failure"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Pre-flight checks of upstreams before expensive requests, by outcome (healthy,
+/// throttled, down) and whether the result was cached
+pub static PREFLIGHT_CHECKS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_preflight_checks_total",
+        "Pre-flight upstream checks before expensive requests by outcome",
+        &["upstream", "outcome", "cached"]
+    )
+    .expect("metric can be registered")
+});
+
+/// A request counted in [`REQUESTS_IN_FLIGHT`] until dropped
+#[derive(Debug)]
+pub struct InFlight(IntGauge);
+
+impl InFlight {
+    pub fn new(provider: &str, upstream: &str) -> Self {
+        let gauge = REQUESTS_IN_FLIGHT.with_label_values(&[provider, upstream]);
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Identity of this gateway instance, always 1; join on `instance` to attribute other
+/// series to a cluster
+pub static GATEWAY_INFO: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "langspec_gateway_info",
+        "Gateway instance identity",
+        &["cluster", "instance", "version"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Current adaptive concurrency limit per upstream
+pub static UPSTREAM_CONCURRENCY_LIMIT: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "langspec_upstream_concurrency_limit",
+        "Current adaptive concurrency limit per upstream",
+        &["upstream"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests that hit an upstream while it was cold (scaled to zero or idle)
+pub static UPSTREAM_COLD_STARTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_upstream_cold_starts_total",
+        "Requests served by an upstream that was cold",
+        &["upstream"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Time to first byte of cold-start requests, tracked apart from warm latency
+pub static UPSTREAM_COLD_START_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "langspec_upstream_cold_start_seconds",
+        "Time to first byte for requests that hit a cold upstream",
+        &["upstream"],
+        vec![0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0]
+    )
+    .expect("metric can be registered")
+});
+
+/// Times an upstream was ejected by passive health checking
+pub static UPSTREAM_EJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_upstream_ejections_total",
+        "Times an upstream was ejected from the rotation by outlier detection",
+        &["upstream"]
+    )
+    .expect("metric can be registered")
+});
+
+/// New connections to TLS upstreams, each with a full TLS handshake. Pingora does not
+/// resume TLS sessions on upstream connections, so reusing pooled connections is what
+/// saves handshakes.
+pub static UPSTREAM_TLS_HANDSHAKES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_upstream_tls_handshakes_total",
+        "Full TLS handshakes on new upstream connections, per negotiated TLS version",
+        &["upstream", "version"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests sent over a pooled TLS upstream connection, without a handshake
+pub static UPSTREAM_TLS_REUSES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_upstream_tls_connection_reuses_total",
+        "Requests sent over a pooled TLS upstream connection, skipping the handshake",
+        &["upstream"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Provider detection conflicts, per pair of disagreeing providers (sorted)
+pub static PROVIDER_CONFLICTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_provider_conflicts_total",
+        "Requests on which provider detectors disagreed, per provider pair",
+        &["provider_a", "provider_b"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Per-request time spent in each gateway phase (see `proxy::timing::PHASES`)
+pub static REQUEST_PHASE_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "langspec_request_phase_seconds",
+        "Time spent in each phase of a proxied request",
+        &["phase"],
+        vec![
+            0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0, 120.0
+        ]
+    )
+    .expect("metric can be registered")
+});
+
+/// Wall-clock jumps detected by quota window clocks
+pub static CLOCK_SKEW_EVENTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_clock_skew_events_total",
+        "Wall-clock jumps beyond tolerance detected against the monotonic clock",
+        &["direction"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Current wall-clock offset from the trusted quota clock, in seconds
+pub static CLOCK_SKEW_SECONDS: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge!(
+        "langspec_clock_skew_seconds",
+        "Wall-clock offset from the monotonic-anchored quota clock (positive = ahead)"
+    )
+    .expect("metric can be registered")
+});
+
+/// Provider keys quarantined after the upstream rejected them (401/403)
+pub static CREDENTIAL_QUARANTINES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_credential_quarantines_total",
+        "Provider credentials quarantined after authentication failures",
+        &["upstream", "credential"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Tokens used per provider, by direction (prompt/completion) and whether the provider
+/// reported them or the gateway estimated them
+pub static TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_tokens_total",
+        "Tokens used by streamed requests",
+        &["provider", "direction", "source"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Cost of requests in USD per provider and tenant (`none` without a tenant)
+pub static COST_USD: LazyLock<CounterVec> = LazyLock::new(|| {
+    register_counter_vec!(
+        "langspec_cost_usd_total",
+        "Cost of streamed requests in USD",
+        &["provider", "tenant"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Streams cut short by the gateway at an output token cap
+pub static OUTPUT_TOKEN_CAPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_output_token_caps_total",
+        "Streamed responses terminated at an output token cap",
+        &["provider"]
+    )
+    .expect("metric can be registered")
+});
+
+/// PII found in prompts, by pattern and action
+pub static PII_MATCHES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_pii_matches_total",
+        "PII found in prompts by pattern and action (mask, reject)",
+        &["pattern", "action"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests screened for prompt injection, by outcome
+pub static PROMPT_INJECTION_SCREENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_prompt_injection_screens_total",
+        "Requests screened for prompt injection by outcome (clean, tagged, logged, blocked, not_screened, classifier_error)",
+        &["outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Prompts and completions moderated, by outcome
+pub static MODERATIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_moderations_total",
+        "Prompts and completions moderated by target (prompt, completion) and outcome (passed, annotated, blocked, flagged, not_moderated, error)",
+        &["target", "outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Stop sequences and content filters matched in streamed output, by action
+pub static OUTPUT_FILTER_MATCHES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_output_filter_matches_total",
+        "Stop sequence and content filter matches in streamed output",
+        &["action"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests per detected prompt language (`unknown` when it could not be detected)
+pub static REQUEST_LANGUAGES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_request_languages_total",
+        "Requests by detected prompt language",
+        &["provider", "language"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests translated to another provider dialect, by target dialect and outcome
+pub static TRANSLATIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_translations_total",
+        "Requests translated to another provider dialect",
+        &["dialect", "outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Unified API requests, by the model route that served them and outcome
+pub static UNIFIED_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_unified_requests_total",
+        "Unified API requests by model route and outcome",
+        &["route", "outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests for deprecated models by model, tenant and outcome (deprecated, rewritten,
+/// rejected)
+pub static DEPRECATED_MODEL_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_deprecated_model_requests_total",
+        "Requests for deprecated models by model, tenant and outcome",
+        &["model", "tenant", "outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Responses of requests matching a response transform by transform and outcome
+pub static RESPONSE_TRANSFORMS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_response_transforms_total",
+        "Responses of requests matching a response transform by transform and outcome",
+        &["transform", "outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests matching a credential route by route and outcome
+pub static CREDENTIAL_INJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_credential_injections_total",
+        "Requests matching a credential route by route and outcome",
+        &["route", "outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests over connections with a verified client certificate, by outcome
+pub static CLIENT_CERT_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_client_cert_requests_total",
+        "Requests over connections with a verified client certificate, by outcome",
+        &["outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests checked for a JWT by outcome
+pub static JWT_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_jwt_requests_total",
+        "Requests checked for a JWT by outcome",
+        &["outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests presenting a virtual key by outcome
+pub static VIRTUAL_KEY_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_virtual_key_requests_total",
+        "Requests presenting a virtual key by outcome",
+        &["outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests presenting a capability token by outcome
+pub static CAPABILITY_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_capability_requests_total",
+        "Requests presenting a capability token by outcome",
+        &["outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Signed client requests by verification outcome
+pub static SIGNED_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_signed_requests_total",
+        "Signed client requests by verification outcome",
+        &["outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests rejected for exceeding their key's concurrency limit, per tenant
+pub static KEY_CONCURRENCY_REJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_key_concurrency_rejections_total",
+        "Requests rejected because their key had too many requests in flight",
+        &["tenant"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests answered by a mock route, per route
+pub static MOCK_RESPONSES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_mock_responses_total",
+        "Requests answered by a config-defined mock route instead of an upstream",
+        &["route"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests rejected because their tenant exhausted a spend budget, per tenant
+pub static BUDGET_REJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_budget_rejections_total",
+        "Requests rejected because their tenant exhausted a spend budget",
+        &["tenant"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests rejected by a rate limit, per limit
+pub static RATE_LIMITED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_rate_limited_total",
+        "Requests rejected with 429 because a rate limit was exceeded",
+        &["limit", "scope"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests that found their upstream at its concurrency cap, per upstream and what
+/// was done with them
+pub static UPSTREAM_CAP_OVERFLOWS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_upstream_cap_overflows_total",
+        "Requests that found their upstream at its concurrency cap (reroute, queue, reject, queue_timeout)",
+        &["upstream", "action"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests routed to an upstream pool, per pool and canary split arm
+pub static UPSTREAM_POOL_ROUTES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_upstream_pool_routes_total",
+        "Requests routed to an upstream pool, per pool and arm (stable, canary)",
+        &["pool", "arm"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests retried on an upstream failure, per reason
+pub static UPSTREAM_RETRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_upstream_retries_total",
+        "Requests retried under the retry policy, per reason (status code or failure class)",
+        &["reason"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests sent on to a fallback pool, per pool left, pool fallen back to and reason
+pub static UPSTREAM_POOL_FALLBACKS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_upstream_pool_fallbacks_total",
+        "Requests sent on to a fallback pool, per pool left, fallback pool and reason (status code or failure class)",
+        &["pool", "fallback", "reason"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests waiting in the admission queue for an upstream with room
+pub static ADMISSION_QUEUE_DEPTH: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "langspec_admission_queue_depth",
+        "Requests waiting in the admission queue for an upstream with room"
+    )
+    .expect("metric can be registered")
+});
+
+/// Requests that went through the admission queue, by outcome
+pub static ADMISSION_QUEUE_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_admission_queue_requests_total",
+        "Requests that found no upstream with room, by outcome (admitted, timeout, full)",
+        &["outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Time requests waited in the admission queue, by outcome
+pub static ADMISSION_QUEUE_WAIT_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "langspec_admission_queue_wait_seconds",
+        "Time requests waited in the admission queue, by outcome (admitted, timeout)",
+        &["outcome"],
+        vec![
+            0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0
+        ]
+    )
+    .expect("metric can be registered")
+});
+
+/// Response cache lookups by outcome: hit, miss, stored, or why the request bypassed
+/// the cache
+pub static RESPONSE_CACHE: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_response_cache_total",
+        "Response cache lookups by outcome (hit, miss, stored, not_cacheable, stream, sampled, requested, anonymous)",
+        &["outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Semantic cache lookups by outcome: hit, miss, stored, embedding_error, or why the
+/// request bypassed the cache
+pub static SEMANTIC_CACHE: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_semantic_cache_total",
+        "Semantic cache lookups by outcome (hit, miss, stored, embedding_error, not_cacheable, stream, sampled, requested, anonymous)",
+        &["outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Request bodies changed by each body rewrite, and requests failed because their
+/// body could not be rewritten, by reason
+pub static BODY_REWRITES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_body_rewrites_total",
+        "Request body rewrites by rewrite and outcome (applied, unsupported_encoding, invalid_encoding, too_large, rejected)",
+        &["rewrite", "outcome"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Request stages that panicked or errored, by stage, kind and the policy applied
+pub static STAGE_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_stage_failures_total",
+        "Request stages that panicked or errored, by stage, kind (error, panic) and policy (fail_open, fail_closed)",
+        &["stage", "kind", "policy"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Connections closed for sending bytes that are not an HTTP request, by kind (tls,
+/// http2, malformed)
+pub static NON_HTTP_CONNECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_non_http_connections_total",
+        "Connections closed for sending non-HTTP bytes by kind (tls, http2, malformed)",
+        &["kind"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Lifetime of proxied WebSockets, from the upstream accepting the upgrade to the socket
+/// closing, by provider and model
+pub static WEBSOCKET_SESSION_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "langspec_websocket_session_seconds",
+        "Lifetime of proxied WebSockets by provider and model",
+        &["provider", "model"],
+        vec![
+            1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0
+        ]
+    )
+    .expect("metric can be registered")
+});
+
+/// Messages relayed over proxied WebSockets, by provider and direction (client,
+/// upstream)
+pub static WEBSOCKET_MESSAGES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_websocket_messages_total",
+        "Messages relayed over proxied WebSockets by provider and direction (client, upstream)",
+        &["provider", "direction"]
+    )
+    .expect("metric can be registered")
+});
+
+/// gRPC calls passed through, by service, method and the `grpc-status` they ended with
+/// (`none` when the upstream sent none)
+pub static GRPC_CALLS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "langspec_grpc_calls_total",
+        "gRPC calls passed through by service, method and grpc-status",
+        &["service", "method", "status"]
+    )
+    .expect("metric can be registered")
+});
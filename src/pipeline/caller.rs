@@ -0,0 +1,96 @@
+//! Who a request is made by, keeping apart the responses the gateway stores for one
+//! client and replays to others.
+//!
+//! A caller is the principal an auth stage established for the request (the tenant it
+//! is made as) or, without one, a hash of the credentials the client presented, taken
+//! before any stage strips or replaces them. A request with neither is anonymous:
+//! nothing tells its client apart from any other, so its responses are neither stored
+//! nor replayed.
+//!
+//! Credentials are hashed with keyed BLAKE2b-256 under a key drawn at startup: two
+//! callers share a hash only if their credentials do (barring a 256-bit collision), and
+//! a hash seen in a cache key or log cannot be checked against guessed credentials.
+
+use crate::pipeline::views::RequestView;
+use blake2::Blake2bMac;
+use blake2::digest::consts::U32;
+use blake2::digest::{KeyInit, Mac};
+use std::fmt;
+use std::sync::LazyLock;
+
+/// Key credentials are hashed under, random per process
+static CREDENTIAL_KEY: LazyLock<[u8; 32]> = LazyLock::new(rand::random);
+
+/// Headers clients present credentials in, to a provider or to the gateway
+const CREDENTIAL_HEADERS: &[&str] = &[
+    "authorization",
+    "x-api-key",
+    "api-key",
+    "x-goog-api-key",
+    "x-langspec-key",
+    "x-langspec-capability",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Caller {
+    /// Hash of the credentials the request arrived with
+    credential: Option<String>,
+    /// Tenant (or other principal) an auth stage made the request as
+    principal: Option<String>,
+}
+
+impl Caller {
+    /// Caller of a request as it arrived, known by the credentials it presents
+    pub fn from_request(request_view: &RequestView) -> Self {
+        let credentials: Vec<(&str, &str)> = CREDENTIAL_HEADERS
+            .iter()
+            .filter_map(|name| Some((*name, request_view.header(name)?)))
+            .collect();
+        if credentials.is_empty() {
+            return Self::default();
+        }
+        let mut mac = <Blake2bMac<U32> as KeyInit>::new_from_slice(&*CREDENTIAL_KEY)
+            .expect("32-byte keys are valid");
+        // Header names and values cannot contain NUL, so it separates them unambiguously
+        for (name, value) in credentials {
+            mac.update(name.as_bytes());
+            mac.update(&[0]);
+            mac.update(value.as_bytes());
+            mac.update(&[0]);
+        }
+        let credential = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Self {
+            credential: Some(credential),
+            principal: None,
+        }
+    }
+
+    /// The request was authenticated as `principal`, which then names the caller
+    pub fn authenticate(&mut self, principal: impl Into<String>) {
+        self.principal = Some(principal.into());
+    }
+
+    pub fn principal(&self) -> Option<&str> {
+        self.principal.as_deref()
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        self.principal.is_none() && self.credential.is_none()
+    }
+}
+
+/// `tenant:{principal}` or `key:{credential hash}`; empty for an anonymous caller
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.principal, &self.credential) {
+            (Some(principal), _) => write!(f, "tenant:{}", principal),
+            (None, Some(credential)) => write!(f, "key:{}", credential),
+            (None, None) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::pipeline::caller::Caller;
+use crate::pipeline::views::RequestView;
+use bytes::{Bytes, BytesMut};
+use pingora_http::ResponseHeader;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Deduplication of client-retried requests keyed on an event ID header.
+///
+/// Protects expensive model calls from at-least-once webhook sources: a request carrying
+/// an event ID already seen within `window` is answered with the stored original response
+/// instead of being forwarded again.
+#[derive(Debug, Clone)]
+pub struct DedupConfig {
+    /// Request header carrying the client-supplied event ID (lowercase)
+    pub header: String,
+    /// How long a completed response is kept for replay
+    pub window: Duration,
+    /// Responses with larger bodies are not stored (the duplicate is forwarded instead)
+    pub max_body_bytes: usize,
+    /// How long a duplicate waits for the original request to finish
+    pub wait_timeout: Duration,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            header: "x-event-id".to_string(),
+            window: Duration::from_secs(600),
+            max_body_bytes: 1024 * 1024,
+            wait_timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+/// A response stored for replay
+#[derive(Debug)]
+pub struct StoredResponse {
+    pub header: ResponseHeader,
+    pub body: Bytes,
+}
+
+/// Outcome of claiming an event ID
+#[derive(Debug)]
+pub enum Claim {
+    /// First request for this event ID: forward it and report the outcome
+    Owner,
+    /// Duplicate of a completed request: replay the stored response
+    Replay(Arc<StoredResponse>),
+    /// Duplicate of a request that is still in flight and did not finish in time
+    Busy,
+}
+
+enum Entry {
+    /// Waiters subscribe to the sender; dropping it wakes them up
+    InFlight(watch::Sender<()>),
+    Complete {
+        response: Arc<StoredResponse>,
+        stored_at: Instant,
+    },
+}
+
+pub struct DedupStore {
+    config: DedupConfig,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl DedupStore {
+    pub fn new(config: DedupConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &DedupConfig {
+        &self.config
+    }
+
+    /// Dedup key for a request, or None if it carries no event ID or its caller is
+    /// anonymous. The caller, method and path are part of the key so an event ID only
+    /// matches the same call of the same client.
+    pub fn key(&self, request_view: &RequestView, caller: &Caller) -> Option<String> {
+        let event_id = request_view.header(&self.config.header)?;
+        if caller.is_anonymous() {
+            return None;
+        }
+        Some(format!(
+            "{} {} {} {}",
+            caller,
+            request_view.method(),
+            request_view.path(),
+            event_id
+        ))
+    }
+
+    /// Claim an event ID, waiting for an in-flight original if there is one.
+    pub async fn claim(&self, key: &str) -> Claim {
+        let deadline = Instant::now() + self.config.wait_timeout;
+        loop {
+            let mut waiter = {
+                let mut entries = self.entries.lock().unwrap();
+                match entries.get(key) {
+                    Some(Entry::Complete {
+                        response,
+                        stored_at,
+                    }) if stored_at.elapsed() < self.config.window => {
+                        return Claim::Replay(Arc::clone(response));
+                    }
+                    Some(Entry::InFlight(sender)) => sender.subscribe(),
+                    _ => {
+                        self.purge_expired(&mut entries);
+                        entries.insert(key.to_string(), Entry::InFlight(watch::channel(()).0));
+                        return Claim::Owner;
+                    }
+                }
+            };
+
+            // The original either completed (replay) or was abandoned (take ownership)
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if tokio::time::timeout(remaining, waiter.changed())
+                .await
+                .is_err()
+            {
+                return Claim::Busy;
+            }
+        }
+    }
+
+    /// Store the response of an owned request for replay
+    pub fn complete(&self, key: &str, response: StoredResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(
+            key.to_string(),
+            Entry::Complete {
+                response: Arc::new(response),
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Release an owned request without storing a response (e.g. it failed)
+    pub fn abandon(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if matches!(entries.get(key), Some(Entry::InFlight(_))) {
+            entries.remove(key);
+        }
+    }
+
+    fn purge_expired(&self, entries: &mut HashMap<String, Entry>) {
+        let window = self.config.window;
+        entries.retain(|_, entry| match entry {
+            Entry::InFlight(_) => true,
+            Entry::Complete { stored_at, .. } => stored_at.elapsed() < window,
+        });
+    }
+}
+
+/// Response being captured for a request that owns an event ID
+#[derive(Debug)]
+pub struct DedupCapture {
+    header: ResponseHeader,
+    body: BytesMut,
+    max_body_bytes: usize,
+    overflowed: bool,
+}
+
+impl DedupCapture {
+    pub fn new(header: ResponseHeader, max_body_bytes: usize) -> Self {
+        Self {
+            header,
+            body: BytesMut::new(),
+            max_body_bytes,
+            overflowed: false,
+        }
+    }
+
+    pub fn append(&mut self, chunk: &[u8]) {
+        if self.body.len() + chunk.len() > self.max_body_bytes {
+            self.overflowed = true;
+            self.body.clear();
+        }
+        if !self.overflowed {
+            self.body.extend_from_slice(chunk);
+        }
+    }
+
+    /// The stored response, if it is worth replaying: complete, within the size limit
+    /// and not a server error (which the client should be allowed to retry).
+    pub fn finish(self) -> Option<StoredResponse> {
+        if self.overflowed || self.header.status.as_u16() >= 500 {
+            return None;
+        }
+        Some(StoredResponse {
+            header: self.header,
+            body: self.body.freeze(),
+        })
+    }
+}
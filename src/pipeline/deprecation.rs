@@ -0,0 +1,285 @@
+//! Model deprecation and sunset policy.
+//!
+//! Deprecated models keep working until their sunset date, with `Deprecation` and
+//! `Sunset` response headers telling clients to migrate. After the sunset, requests are
+//! rewritten to the model's replacement or rejected, so a model can be retired across
+//! the fleet from the gateway instead of in every client.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeprecationError {
+    /// A date is neither `YYYY-MM-DD` nor RFC 3339
+    InvalidDate(String, String),
+    DuplicateModel(String),
+    /// `on_sunset: rewrite` without a replacement model
+    NoReplacement(String),
+}
+
+impl fmt::Display for DeprecationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeprecationError::InvalidDate(model, date) => write!(
+                f,
+                "deprecated model '{}' has an invalid date '{}': use YYYY-MM-DD or RFC 3339",
+                model, date
+            ),
+            DeprecationError::DuplicateModel(model) => {
+                write!(f, "model '{}' is deprecated twice", model)
+            }
+            DeprecationError::NoReplacement(model) => write!(
+                f,
+                "deprecated model '{}' is rewritten after its sunset but has no replacement",
+                model
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DeprecationError {}
+
+/// What happens to requests for a model after its sunset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SunsetAction {
+    /// Send the request to the replacement model instead
+    Rewrite,
+    /// Answer `410 Gone`
+    Reject,
+}
+
+/// A deprecated model as declared in the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelDeprecationConfig {
+    /// Model name, or a prefix ending in `*`
+    pub model: String,
+    /// When the model was deprecated (`YYYY-MM-DD` or RFC 3339)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated_since: Option<String>,
+    /// When the model stops being served (`YYYY-MM-DD` or RFC 3339)
+    pub sunset: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<String>,
+    /// `rewrite` or `reject`; rewrites when a replacement is given, rejects otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_sunset: Option<SunsetAction>,
+    /// Migration guide, sent as a `Link` with `rel="deprecation"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+/// A deprecated model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelDeprecation {
+    /// Model name, or a prefix ending in `*`
+    pub model: String,
+    pub deprecated_since: Option<DateTime<Utc>>,
+    pub sunset: DateTime<Utc>,
+    pub replacement: Option<String>,
+    pub on_sunset: SunsetAction,
+    pub link: Option<String>,
+}
+
+/// How a request for a deprecated model is handled at a point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeprecationOutcome<'a> {
+    /// Before the sunset: served, with deprecation headers
+    Deprecated,
+    /// After the sunset: served by the replacement model
+    Rewritten(&'a str),
+    /// After the sunset: refused
+    Rejected,
+}
+
+impl DeprecationOutcome<'_> {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeprecationOutcome::Deprecated => "deprecated",
+            DeprecationOutcome::Rewritten(_) => "rewritten",
+            DeprecationOutcome::Rejected => "rejected",
+        }
+    }
+}
+
+impl ModelDeprecation {
+    /// A model rejected after `sunset`
+    pub fn new(model: impl Into<String>, sunset: DateTime<Utc>) -> Self {
+        Self {
+            model: model.into(),
+            deprecated_since: None,
+            sunset,
+            replacement: None,
+            on_sunset: SunsetAction::Reject,
+            link: None,
+        }
+    }
+
+    /// Rewrite requests to `model` after the sunset
+    pub fn with_replacement(mut self, model: impl Into<String>) -> Self {
+        self.replacement = Some(model.into());
+        self.on_sunset = SunsetAction::Rewrite;
+        self
+    }
+
+    pub fn with_sunset_action(mut self, action: SunsetAction) -> Self {
+        self.on_sunset = action;
+        self
+    }
+
+    pub fn with_deprecated_since(mut self, since: DateTime<Utc>) -> Self {
+        self.deprecated_since = Some(since);
+        self
+    }
+
+    pub fn with_link(mut self, link: impl Into<String>) -> Self {
+        self.link = Some(link.into());
+        self
+    }
+
+    pub fn compile(config: &ModelDeprecationConfig) -> Result<Self, DeprecationError> {
+        let date = |value: &str| {
+            parse_date(value)
+                .ok_or_else(|| DeprecationError::InvalidDate(config.model.clone(), value.into()))
+        };
+        let on_sunset = match (config.on_sunset, &config.replacement) {
+            (Some(SunsetAction::Rewrite), None) => {
+                return Err(DeprecationError::NoReplacement(config.model.clone()));
+            }
+            (Some(action), _) => action,
+            (None, Some(_)) => SunsetAction::Rewrite,
+            (None, None) => SunsetAction::Reject,
+        };
+        Ok(Self {
+            model: config.model.clone(),
+            deprecated_since: config.deprecated_since.as_deref().map(date).transpose()?,
+            sunset: date(&config.sunset)?,
+            replacement: config.replacement.clone(),
+            on_sunset,
+            link: config.link.clone(),
+        })
+    }
+
+    pub fn matches(&self, model: &str) -> bool {
+        match self.model.strip_suffix('*') {
+            Some(prefix) => model.starts_with(prefix),
+            None => self.model == model,
+        }
+    }
+
+    pub fn outcome(&self, now: DateTime<Utc>) -> DeprecationOutcome<'_> {
+        if now < self.sunset {
+            return DeprecationOutcome::Deprecated;
+        }
+        match (self.on_sunset, &self.replacement) {
+            (SunsetAction::Rewrite, Some(replacement)) => {
+                DeprecationOutcome::Rewritten(replacement)
+            }
+            _ => DeprecationOutcome::Rejected,
+        }
+    }
+
+    /// `Deprecation` header value (RFC 9745): when the model was deprecated, as a
+    /// structured field date, or `true` when not given
+    pub fn deprecation_header(&self) -> String {
+        self.deprecated_since.map_or("true".to_string(), |since| {
+            format!("@{}", since.timestamp())
+        })
+    }
+
+    /// `Sunset` header value (RFC 8594), an HTTP date
+    pub fn sunset_header(&self) -> String {
+        self.sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+    }
+
+    /// `Link` header value pointing to the migration guide
+    pub fn link_header(&self) -> Option<String> {
+        self.link
+            .as_ref()
+            .map(|link| format!("<{}>; rel=\"deprecation\"", link))
+    }
+
+    /// OpenAI-style error body for a request refused after the sunset
+    pub fn sunset_error(&self, model: &str) -> Value {
+        let mut message = format!(
+            "The model `{}` was retired on {}",
+            model,
+            self.sunset.format("%Y-%m-%d")
+        );
+        if let Some(replacement) = &self.replacement {
+            message.push_str(&format!("; use `{}` instead", replacement));
+        }
+        serde_json::json!({"error": {
+            "message": message,
+            "type": "invalid_request_error",
+            "param": "model",
+            "code": "model_sunset"
+        }})
+    }
+}
+
+/// Deprecated models, matched in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeprecationPolicy {
+    pub models: Vec<ModelDeprecation>,
+}
+
+impl DeprecationPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_model(mut self, deprecation: ModelDeprecation) -> Self {
+        self.models.push(deprecation);
+        self
+    }
+
+    /// Policy of the models declared in the config file
+    pub fn compile(models: &[ModelDeprecationConfig]) -> Result<Self, DeprecationError> {
+        let mut seen = HashSet::new();
+        let mut policy = Self::new();
+        for config in models {
+            if !seen.insert(config.model.as_str()) {
+                return Err(DeprecationError::DuplicateModel(config.model.clone()));
+            }
+            policy = policy.with_model(ModelDeprecation::compile(config)?);
+        }
+        Ok(policy)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+
+    /// Deprecation of the first entry matching a model
+    pub fn lookup(&self, model: &str) -> Option<&ModelDeprecation> {
+        self.models
+            .iter()
+            .find(|deprecation| deprecation.matches(model))
+    }
+}
+
+/// `YYYY-MM-DD` (midnight UTC) or an RFC 3339 timestamp
+fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Some(date.and_hms_opt(0, 0, 0)?.and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// A JSON request body with its top-level `model` replaced; `None` when the body is not
+/// a JSON object
+pub fn rewrite_request_model(body: &[u8], model: &str) -> Option<Vec<u8>> {
+    let mut request: Value = serde_json::from_slice(body).ok()?;
+    request
+        .as_object_mut()?
+        .insert("model".into(), model.into());
+    Some(serde_json::to_vec(&request).expect("JSON values serialize"))
+}
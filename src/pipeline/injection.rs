@@ -0,0 +1,332 @@
+//! Prompt injection screening.
+//!
+//! [`PromptInjectionScreen`] looks for prompt injection in the prompt text of JSON
+//! request bodies before they are forwarded: heuristic patterns (instructions to ignore
+//! earlier instructions, to reveal the system prompt, role overrides and chat template
+//! delimiters) and, optionally, a classifier endpoint scoring the text. Instructions the
+//! application itself sends (system and developer messages, `system`, `instructions`
+//! and `preamble` fields) are not screened. A suspicious request is tagged for the
+//! upstream, only logged, or blocked.
+
+use crate::pipeline::usage::PROMPT_KEYS;
+use regex::Regex;
+use serde_json::Value;
+use std::fmt;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+static IGNORE_INSTRUCTIONS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\b(ignore|disregard|forget|override)\b.{0,40}\b(previous|prior|above|earlier|preceding|all)\b.{0,40}\b(instructions?|prompts?|rules|directions|guidelines)\b",
+    )
+    .expect("pattern is valid")
+});
+
+static SYSTEM_PROMPT_LEAK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\b(reveal|print|show|repeat|output|display|leak)\b.{0,40}\b(system|initial|hidden|original)\s+(prompt|instructions|message)",
+    )
+    .expect("pattern is valid")
+});
+
+static ROLE_OVERRIDE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\b(developer mode|jailbreak|jailbroken|do anything now|DAN mode)\b|\b(you are now|from now on,? you are|pretend (to be|you are))\b.{0,60}\b(unrestricted|unfiltered|uncensored|without (any )?(restrictions|rules|limits|filters))",
+    )
+    .expect("pattern is valid")
+});
+
+/// Chat template tokens and headers a prompt has no business carrying
+static DELIMITER_INJECTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?im)<\|(im_start|im_end|system|endoftext)\|>|\[/?INST\]|<</?SYS>>|^\s*#{2,}\s*(system|instructions?)\s*:?\s*$",
+    )
+    .expect("pattern is valid")
+});
+
+/// Message roles whose content is the application's own instructions
+const INSTRUCTION_ROLES: &[&str] = &["system", "developer"];
+
+/// Top-level fields carrying the application's own instructions
+const INSTRUCTION_KEYS: &[&str] = &["system", "instructions", "preamble"];
+
+/// What happens to a request suspected of prompt injection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InjectionAction {
+    /// Forward the request with [`PromptInjectionScreen::HEADER`] naming the signals
+    #[default]
+    Tag,
+    /// Forward the request unchanged; the suspicion is only logged and counted
+    Log,
+    /// Fail the request with 400
+    Block,
+}
+
+impl InjectionAction {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InjectionAction::Tag => "tagged",
+            InjectionAction::Log => "logged",
+            InjectionAction::Block => "blocked",
+        }
+    }
+
+    /// `tag`, `log` or `block`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "tag" => Some(InjectionAction::Tag),
+            "log" => Some(InjectionAction::Log),
+            "block" => Some(InjectionAction::Block),
+            _ => None,
+        }
+    }
+}
+
+/// A heuristic signal of prompt injection
+#[derive(Debug, Clone)]
+pub struct InjectionPattern {
+    pub name: String,
+    pub regex: Regex,
+}
+
+impl InjectionPattern {
+    /// "Ignore all previous instructions" and the like
+    pub fn ignore_instructions() -> Self {
+        Self::custom("ignore_instructions", IGNORE_INSTRUCTIONS.clone())
+    }
+
+    /// Requests to reveal the system prompt
+    pub fn system_prompt_leak() -> Self {
+        Self::custom("system_prompt_leak", SYSTEM_PROMPT_LEAK.clone())
+    }
+
+    /// Jailbreak personas: developer mode, "you are now unrestricted"
+    pub fn role_override() -> Self {
+        Self::custom("role_override", ROLE_OVERRIDE.clone())
+    }
+
+    /// Chat template delimiters such as `<|im_start|>` and `[INST]`
+    pub fn delimiter_injection() -> Self {
+        Self::custom("delimiter_injection", DELIMITER_INJECTION.clone())
+    }
+
+    pub fn custom(name: impl Into<String>, regex: Regex) -> Self {
+        Self {
+            name: name.into(),
+            regex,
+        }
+    }
+}
+
+/// Prompt injection screening of requests.
+///
+/// The classifier endpoint is sent `{"input": "<prompt text>"}` and answers with a JSON
+/// object whose `score` (0 to 1) is the likelihood of injection. It is only asked about
+/// prompts no pattern matched.
+#[derive(Clone)]
+pub struct PromptInjectionConfig {
+    pub patterns: Vec<InjectionPattern>,
+    pub action: InjectionAction,
+    pub classifier_url: Option<String>,
+    /// Sent as a bearer token to the classifier endpoint
+    pub classifier_api_key: Option<String>,
+    /// How long a classifier request may take before the request is screened by the
+    /// patterns alone
+    pub classifier_timeout: Duration,
+    /// Least classifier score of a suspicious prompt
+    pub threshold: f32,
+}
+
+impl fmt::Debug for PromptInjectionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PromptInjectionConfig")
+            .field("patterns", &self.patterns)
+            .field("action", &self.action)
+            .field("classifier_url", &self.classifier_url)
+            .field("classifier_timeout", &self.classifier_timeout)
+            .field("threshold", &self.threshold)
+            .finish()
+    }
+}
+
+impl Default for PromptInjectionConfig {
+    /// The built-in patterns, tagging suspicious requests
+    fn default() -> Self {
+        Self {
+            patterns: vec![
+                InjectionPattern::ignore_instructions(),
+                InjectionPattern::system_prompt_leak(),
+                InjectionPattern::role_override(),
+                InjectionPattern::delimiter_injection(),
+            ],
+            action: InjectionAction::default(),
+            classifier_url: None,
+            classifier_api_key: None,
+            classifier_timeout: Duration::from_secs(1),
+            threshold: 0.9,
+        }
+    }
+}
+
+impl PromptInjectionConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also look for `pattern`
+    pub fn with_pattern(mut self, pattern: InjectionPattern) -> Self {
+        assert!(
+            self.patterns.iter().all(|other| other.name != pattern.name),
+            "prompt injection pattern '{}' is added twice",
+            pattern.name
+        );
+        self.patterns.push(pattern);
+        self
+    }
+
+    /// Screen with `patterns` instead of the built-in ones
+    pub fn with_patterns(mut self, patterns: Vec<InjectionPattern>) -> Self {
+        self.patterns = Vec::new();
+        for pattern in patterns {
+            self = self.with_pattern(pattern);
+        }
+        self
+    }
+
+    pub fn with_action(mut self, action: InjectionAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Score prompts with a classifier endpoint, e.g. `http://classifier:8000/score`
+    pub fn with_classifier(mut self, url: impl Into<String>) -> Self {
+        self.classifier_url = Some(url.into());
+        self
+    }
+
+    pub fn with_classifier_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.classifier_api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn with_classifier_timeout(mut self, timeout: Duration) -> Self {
+        self.classifier_timeout = timeout;
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+}
+
+/// Why a request is suspected of prompt injection
+#[derive(Debug, Clone, PartialEq)]
+pub struct InjectionVerdict {
+    /// Names of the patterns that matched
+    pub signals: Vec<String>,
+    /// Classifier score, when the classifier was asked
+    pub score: Option<f32>,
+}
+
+impl InjectionVerdict {
+    /// Value of [`PromptInjectionScreen::HEADER`]: the matched patterns, or `classifier`
+    pub fn label(&self) -> String {
+        match self.signals.is_empty() {
+            true => "classifier".to_string(),
+            false => self.signals.join(","),
+        }
+    }
+}
+
+impl fmt::Display for InjectionVerdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.score {
+            Some(score) if self.signals.is_empty() => write!(f, "classifier score {:.3}", score),
+            _ => write!(f, "matched {}", self.signals.join(", ")),
+        }
+    }
+}
+
+pub struct PromptInjectionScreen {
+    config: PromptInjectionConfig,
+}
+
+impl PromptInjectionScreen {
+    /// Request header naming the signals of a tagged request
+    pub const HEADER: &'static str = "X-Langspec-Prompt-Injection";
+
+    pub fn new(config: PromptInjectionConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &PromptInjectionConfig {
+        &self.config
+    }
+
+    /// Prompt text of a JSON request body the application does not send as its own
+    /// instructions; `None` for bodies that are not JSON
+    pub fn prompt(&self, body: &[u8]) -> Option<String> {
+        fn collect<'a>(value: &'a Value, is_prompt: bool, out: &mut Vec<&'a str>) {
+            match value {
+                Value::String(text) if is_prompt => out.push(text),
+                Value::Array(items) => {
+                    for item in items {
+                        collect(item, is_prompt, out);
+                    }
+                }
+                Value::Object(fields) => {
+                    let role = fields.get("role").and_then(Value::as_str);
+                    if role.is_some_and(|role| INSTRUCTION_ROLES.contains(&role)) {
+                        return;
+                    }
+                    for (key, value) in fields {
+                        collect(value, PROMPT_KEYS.contains(&key.as_str()), out);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let Value::Object(fields) = serde_json::from_slice::<Value>(body).ok()? else {
+            return None;
+        };
+        let mut texts = Vec::new();
+        for (key, value) in &fields {
+            if !INSTRUCTION_KEYS.contains(&key.as_str()) {
+                collect(value, PROMPT_KEYS.contains(&key.as_str()), &mut texts);
+            }
+        }
+        Some(texts.join("\n"))
+    }
+
+    /// Names of the patterns matching `text`
+    pub fn signals(&self, text: &str) -> Vec<String> {
+        self.config
+            .patterns
+            .iter()
+            .filter(|pattern| pattern.regex.is_match(text))
+            .map(|pattern| pattern.name.clone())
+            .collect()
+    }
+
+    /// Body of a classifier request for `text`
+    pub fn classifier_request(&self, text: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({"input": text})).expect("JSON values serialize")
+    }
+
+    /// Verdict on a prompt from its signals and classifier score; `None` when it is
+    /// not suspicious
+    pub fn verdict(&self, signals: Vec<String>, score: Option<f32>) -> Option<InjectionVerdict> {
+        let flagged = score.is_some_and(|score| score >= self.config.threshold);
+        (!signals.is_empty() || flagged).then_some(InjectionVerdict { signals, score })
+    }
+}
+
+/// Score of a classifier response, `{"score": 0.97}`
+pub fn parse_classifier_score(body: &[u8]) -> Option<f32> {
+    let response: Value = serde_json::from_slice(body).ok()?;
+    let score = response.get("score")?.as_f64()? as f32;
+    score.is_finite().then_some(score)
+}
@@ -0,0 +1,270 @@
+/// Most frequent character trigrams of languages written in Latin script, most
+/// frequent first. Words are padded with spaces, so `" th"` is a word start.
+const PROFILES: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            " th", "the", "he ", "ing", "nd ", " an", "and", "ion", " of", "of ", " to", "to ",
+            "ed ", "tio", "ent", " in", "er ", "is ", "in ", "at ", "re ", "on ", "hat", " is",
+            "for", "es ", " fo", "ly ", "ou ", "you", " yo", "her", "ter", "tha", " wi", "wit",
+            "ith", "ll ", "are", " be",
+        ],
+    ),
+    (
+        "es",
+        &[
+            " de", "de ", "os ", " la", "la ", "el ", " el", "es ", " qu", "que", "ue ", " en",
+            "en ", "as ", "ión", "ent", " co", "ado", "ón ", "aci", "ien", " se", "ar ", "con",
+            "par", " pa", "ara", "los", " lo", "del", "er ", "an ", "cio", "res", "ra ", "est",
+            " es", "una", " un", "por",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            " de", "es ", "de ", "le ", " le", "ent", " la", "la ", "nt ", "ion", "les", " et",
+            "et ", "re ", " qu", "que", "ue ", "tio", " pa", "ne ", " en", "des", "e d", " co",
+            "eur", " un", "our", "ous", "ait", "ans", "men", " pr", "est", " es", "une", "par",
+            "pou", " po", " vo", "vou",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "en ", "er ", " de", "der", "ie ", "ich", "ein", " di", "die", "sch", "ch ", " un",
+            "und", "nd ", " ei", "ine", "che", "den", "cht", "in ", "te ", "gen", "ung", " ge",
+            "ten", "ist", " is", "st ", "es ", "eit", " zu", "nde", "ter", "ber", "auf", " au",
+            " da", "das", "nic", "sie",
+        ],
+    ),
+    (
+        "it",
+        &[
+            " di", "di ", "to ", "la ", " la", "re ", "ell", " co", "che", " ch", "he ", "ion",
+            "one", "no ", "del", " de", "le ", "ne ", "ato", "ent", "per", " pe", "zio", " in",
+            "are", "con", "lla", " il", "il ", "ta ", "ti ", "na ", "tto", "gli", " un", "non",
+            " no", "ono", " so", "sta",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            " de", "de ", "os ", " qu", "que", "ue ", "ão ", "ção", " co", "do ", "da ", " a ",
+            "as ", "ent", " se", "ar ", "com", " pa", "par", "ra ", "est", "ado", "nte", " do",
+            " da", "uma", " um", "não", " nã", "em ", " em", "men", "ica", "ões", "ser", "por",
+            " po", "ara", "o d", "es ",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "en ", " de", "de ", "et ", "het", " he", "an ", "van", " va", "een", " ee", "er ",
+            "ing", "ng ", " en", "nd ", "ijk", " in", "ie ", "aar", "oor", "te ", "ver", " ve",
+            "ken", "ge ", "gen", " ge", "cht", "sch", "nie", " ni", "zij", "ij ", "ik ", " ik",
+            "dat", " da", "wor", " wo",
+        ],
+    ),
+];
+
+/// Letters needed before a script decides the language
+const MIN_SCRIPT_LETTERS: usize = 4;
+
+/// Fast language detection of prompt text.
+///
+/// Scripts used by a single major language (kana, Hangul, Cyrillic, Arabic, ...)
+/// decide on their own; Latin-script text is matched against the most frequent
+/// character trigrams of English, Spanish, French, German, Italian, Portuguese and
+/// Dutch. Only the start of long prompts is looked at. Returns an ISO 639-1 code, or
+/// None when the text is too short or matches no language well enough.
+#[derive(Debug, Clone)]
+pub struct LanguageDetector {
+    /// Letters needed before guessing a Latin-script language; scripts that decide
+    /// the language on their own need only a few
+    pub min_letters: usize,
+    /// Characters of the text looked at
+    pub max_chars: usize,
+}
+
+impl LanguageDetector {
+    pub const DEFAULT_MIN_LETTERS: usize = 20;
+    pub const DEFAULT_MAX_CHARS: usize = 2048;
+
+    pub fn new() -> Self {
+        Self {
+            min_letters: Self::DEFAULT_MIN_LETTERS,
+            max_chars: Self::DEFAULT_MAX_CHARS,
+        }
+    }
+
+    pub fn with_min_letters(mut self, min_letters: usize) -> Self {
+        self.min_letters = min_letters;
+        self
+    }
+
+    pub fn with_max_chars(mut self, max_chars: usize) -> Self {
+        self.max_chars = max_chars;
+        self
+    }
+
+    /// Language of `text` as an ISO 639-1 code
+    pub fn detect(&self, text: &str) -> Option<&'static str> {
+        let mut scripts = ScriptCounts::default();
+        let mut latin = String::from(" ");
+        for c in text.chars().take(self.max_chars) {
+            scripts.count(c);
+            if c.is_alphabetic() && is_latin(c) {
+                latin.extend(c.to_lowercase());
+            } else if !latin.ends_with(' ') {
+                latin.push(' ');
+            }
+        }
+        if scripts.letters < MIN_SCRIPT_LETTERS {
+            return None;
+        }
+
+        // Japanese mixes kana with kanji, so any amount of kana decides it
+        if scripts.kana * 20 >= scripts.letters {
+            return Some("ja");
+        }
+        let (script, count) = scripts.dominant();
+        if count * 2 < scripts.letters {
+            return None;
+        }
+        match script {
+            Script::Latin if scripts.letters < self.min_letters => None,
+            Script::Latin => {
+                if !latin.ends_with(' ') {
+                    latin.push(' ');
+                }
+                trigram_language(&latin)
+            }
+            Script::Han => Some("zh"),
+            Script::Hangul => Some("ko"),
+            // Letters only Ukrainian uses among Cyrillic languages
+            Script::Cyrillic if scripts.ukrainian > 0 => Some("uk"),
+            Script::Cyrillic => Some("ru"),
+            Script::Greek => Some("el"),
+            Script::Arabic => Some("ar"),
+            Script::Hebrew => Some("he"),
+            Script::Devanagari => Some("hi"),
+            Script::Thai => Some("th"),
+        }
+    }
+}
+
+impl Default for LanguageDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Script {
+    Latin,
+    Han,
+    Hangul,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Devanagari,
+    Thai,
+}
+
+#[derive(Debug, Default)]
+struct ScriptCounts {
+    letters: usize,
+    kana: usize,
+    ukrainian: usize,
+    latin: usize,
+    han: usize,
+    hangul: usize,
+    cyrillic: usize,
+    greek: usize,
+    arabic: usize,
+    hebrew: usize,
+    devanagari: usize,
+    thai: usize,
+}
+
+impl ScriptCounts {
+    fn count(&mut self, c: char) {
+        if !c.is_alphabetic() {
+            return;
+        }
+        self.letters += 1;
+        let counter = match c as u32 {
+            0x3040..=0x30FF | 0x31F0..=0x31FF | 0xFF66..=0xFF9F => &mut self.kana,
+            0x4E00..=0x9FFF | 0x3400..=0x4DBF | 0xF900..=0xFAFF => &mut self.han,
+            0xAC00..=0xD7AF | 0x1100..=0x11FF | 0x3130..=0x318F => &mut self.hangul,
+            0x0400..=0x04FF => {
+                if matches!(c, 'і' | 'ї' | 'є' | 'ґ' | 'І' | 'Ї' | 'Є' | 'Ґ') {
+                    self.ukrainian += 1;
+                }
+                &mut self.cyrillic
+            }
+            0x0370..=0x03FF | 0x1F00..=0x1FFF => &mut self.greek,
+            0x0600..=0x06FF | 0x0750..=0x077F => &mut self.arabic,
+            0x0590..=0x05FF => &mut self.hebrew,
+            0x0900..=0x097F => &mut self.devanagari,
+            0x0E00..=0x0E7F => &mut self.thai,
+            _ if is_latin(c) => &mut self.latin,
+            _ => return,
+        };
+        *counter += 1;
+    }
+
+    /// Script with the most letters
+    fn dominant(&self) -> (Script, usize) {
+        [
+            (Script::Latin, self.latin),
+            (Script::Han, self.han),
+            (Script::Hangul, self.hangul),
+            (Script::Cyrillic, self.cyrillic),
+            (Script::Greek, self.greek),
+            (Script::Arabic, self.arabic),
+            (Script::Hebrew, self.hebrew),
+            (Script::Devanagari, self.devanagari),
+            (Script::Thai, self.thai),
+        ]
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .expect("script list is not empty")
+    }
+}
+
+fn is_latin(c: char) -> bool {
+    c.is_ascii_alphabetic() || matches!(c as u32, 0x00C0..=0x024F if c != '×' && c != '÷')
+}
+
+/// Best matching Latin-script language of lowercased, space-separated words: each
+/// trigram of the text scores by its rank in a language's profile
+fn trigram_language(text: &str) -> Option<&'static str> {
+    let chars: Vec<char> = text.chars().collect();
+    let trigrams: Vec<String> = chars
+        .windows(3)
+        .filter(|window| window[1] != ' ')
+        .map(|window| window.iter().collect())
+        .collect();
+
+    let (language, score, hits) = PROFILES
+        .iter()
+        .map(|(language, profile)| {
+            let ranks: Vec<usize> = trigrams
+                .iter()
+                .filter_map(|trigram| profile.iter().position(|entry| entry == trigram))
+                .collect();
+            let score: usize = ranks.iter().map(|rank| profile.len() - rank).sum();
+            (*language, score, ranks.len())
+        })
+        .fold(("", 0, 0), |best, candidate| {
+            if candidate.1 > best.1 {
+                candidate
+            } else {
+                best
+            }
+        });
+    // Require a share of the trigrams to be common in the language, so unrelated
+    // Latin-script text (code, other languages) is not forced into a profile
+    (score > 0 && hits >= 3 && hits * 6 >= trigrams.len()).then_some(language)
+}
@@ -0,0 +1,342 @@
+use crate::pipeline::tokenizer::{ApproximateTokenizer, Tokenizer};
+use crate::pipeline::usage::{Usage, prompt_text};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockError {
+    DuplicateName(String),
+    /// The path does not start with `/`
+    InvalidPath(String, String),
+    /// The status is not a valid HTTP status
+    InvalidStatus(String, u16),
+    /// A canned `response` body with `stream: true`; only generated content streams
+    StreamedResponse(String),
+}
+
+impl fmt::Display for MockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MockError::DuplicateName(name) => write!(f, "mock '{}' is declared twice", name),
+            MockError::InvalidPath(name, path) => {
+                write!(f, "mock '{}' has an invalid path '{}'", name, path)
+            }
+            MockError::InvalidStatus(name, status) => {
+                write!(f, "mock '{}' has an invalid status {}", name, status)
+            }
+            MockError::StreamedResponse(name) => write!(
+                f,
+                "mock '{}' streams but sends a canned response; use content instead",
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MockError {}
+
+/// A mock route in the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MockRouteConfig {
+    /// Name used in logs and metrics
+    pub name: String,
+    /// Request path answered, e.g. `/v1/chat/completions`
+    pub path: String,
+    /// Model answered, or a prefix ending in `*`; any model when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Assistant message of the generated completion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// Body sent as is instead of a generated completion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    /// Response status, 200 by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Stream the completion as server-sent events; follows the request's `stream`
+    /// when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// Delay before answering, to mimic a provider's latency
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+}
+
+/// A route answered with a canned response instead of an upstream's
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockRoute {
+    pub name: String,
+    pub path: String,
+    /// Model name, or a prefix ending in `*`; `None` matches any model
+    pub model: Option<String>,
+    pub content: String,
+    pub response: Option<Value>,
+    pub status: u16,
+    pub stream: Option<bool>,
+    pub latency: Duration,
+}
+
+/// What a mock route answers a request with
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockResponse {
+    /// A JSON body
+    Json(u16, Value),
+    /// Server-sent events, `[DONE]` included
+    Stream(Vec<String>),
+}
+
+impl MockRoute {
+    /// A route answering `path` with a completion of `content`
+    pub fn new(
+        name: impl Into<String>,
+        path: impl Into<String>,
+        content: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            path: path.into(),
+            model: None,
+            content: content.into(),
+            response: None,
+            status: 200,
+            stream: None,
+            latency: Duration::ZERO,
+        }
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn with_response(mut self, status: u16, response: Value) -> Self {
+        self.status = status;
+        self.response = Some(response);
+        self
+    }
+
+    pub fn with_stream(mut self, stream: bool) -> Self {
+        self.stream = Some(stream);
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn compile(config: &MockRouteConfig) -> Result<Self, MockError> {
+        if !config.path.starts_with('/') {
+            return Err(MockError::InvalidPath(
+                config.name.clone(),
+                config.path.clone(),
+            ));
+        }
+        let status = config.status.unwrap_or(200);
+        if !(100..=599).contains(&status) {
+            return Err(MockError::InvalidStatus(config.name.clone(), status));
+        }
+        if config.response.is_some() && config.stream == Some(true) {
+            return Err(MockError::StreamedResponse(config.name.clone()));
+        }
+        Ok(Self {
+            name: config.name.clone(),
+            path: config.path.clone(),
+            model: config.model.clone(),
+            content: config.content.clone().unwrap_or_default(),
+            response: config.response.clone(),
+            status,
+            stream: config.stream,
+            latency: Duration::from_millis(config.latency_ms.unwrap_or(0)),
+        })
+    }
+
+    pub fn matches(&self, path: &str, model: Option<&str>) -> bool {
+        if self.path != path {
+            return false;
+        }
+        match (&self.model, model) {
+            (None, _) => true,
+            (Some(pattern), Some(model)) => match pattern.strip_suffix('*') {
+                Some(prefix) => model.starts_with(prefix),
+                None => pattern == model,
+            },
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Response to a request with `body`, with its (estimated) usage
+    pub fn respond(&self, body: &[u8]) -> (MockResponse, Usage) {
+        let request: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+        let tokenizer = ApproximateTokenizer::new();
+        let usage = Usage {
+            prompt_tokens: tokenizer.count_tokens(&prompt_text(body)),
+            completion_tokens: tokenizer.count_tokens(&self.content),
+            estimated: true,
+        };
+        if let Some(response) = &self.response {
+            return (MockResponse::Json(self.status, response.clone()), usage);
+        }
+
+        let model = request
+            .get("model")
+            .and_then(Value::as_str)
+            .or(self.model.as_deref().filter(|model| !model.ends_with('*')))
+            .unwrap_or("mock");
+        let completion =
+            GeneratedCompletion::new(format!("chatcmpl-mock-{}", self.name), model, usage);
+        let stream = self.stream.unwrap_or_else(|| {
+            request
+                .get("stream")
+                .and_then(Value::as_bool)
+                .unwrap_or(false)
+        });
+        if !stream {
+            return (
+                MockResponse::Json(self.status, completion.json(&self.content)),
+                usage,
+            );
+        }
+
+        // One event per word, keeping the whitespace, as providers stream tokens
+        let mut pieces = Vec::new();
+        let mut rest = self.content.as_str();
+        while !rest.is_empty() {
+            let start = rest.len() - rest.trim_start().len();
+            let end = rest[start..]
+                .find(char::is_whitespace)
+                .map_or(rest.len(), |end| start + end);
+            pieces.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+        (MockResponse::Stream(completion.events(&pieces)), usage)
+    }
+}
+
+/// An OpenAI chat completion made up by the gateway, answered as a JSON body or as
+/// server-sent events
+#[derive(Debug, Clone)]
+pub struct GeneratedCompletion {
+    pub id: String,
+    pub model: String,
+    /// Unix time of the completion
+    pub created: u64,
+    pub usage: Usage,
+}
+
+impl GeneratedCompletion {
+    pub fn new(id: impl Into<String>, model: impl Into<String>, usage: Usage) -> Self {
+        Self {
+            id: id.into(),
+            model: model.into(),
+            created: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            usage,
+        }
+    }
+
+    fn usage_json(&self) -> Value {
+        serde_json::json!({
+            "prompt_tokens": self.usage.prompt_tokens,
+            "completion_tokens": self.usage.completion_tokens,
+            "total_tokens": self.usage.total_tokens()
+        })
+    }
+
+    /// `chat.completion` body with `content` as the assistant message
+    pub fn json(&self, content: &str) -> Value {
+        serde_json::json!({
+            "id": self.id,
+            "object": "chat.completion",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": "stop"
+            }],
+            "usage": self.usage_json()
+        })
+    }
+
+    fn event(&self, choices: Value, usage: Option<Value>) -> String {
+        let mut chunk = serde_json::json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": choices
+        });
+        if let Some(usage) = usage {
+            chunk["usage"] = usage;
+        }
+        format!("data: {}\n\n", chunk)
+    }
+
+    /// Server-sent events streaming `pieces` as content deltas: the role, one event
+    /// per piece, the finish reason, the usage and `[DONE]`
+    pub fn events(&self, pieces: &[&str]) -> Vec<String> {
+        let delta = |delta: Value, finish_reason: Option<&str>| serde_json::json!([{"index": 0, "delta": delta, "finish_reason": finish_reason}]);
+        let mut events =
+            vec![self.event(delta(serde_json::json!({"role": "assistant"}), None), None)];
+        events.extend(
+            pieces
+                .iter()
+                .map(|piece| self.event(delta(serde_json::json!({"content": piece}), None), None)),
+        );
+        events.push(self.event(delta(serde_json::json!({}), Some("stop")), None));
+        events.push(self.event(serde_json::json!([]), Some(self.usage_json())));
+        events.push("data: [DONE]\n\n".to_string());
+        events
+    }
+}
+
+/// Mock routes, matched in order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MockRoutes {
+    pub routes: Vec<MockRoute>,
+}
+
+impl MockRoutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_route(mut self, route: MockRoute) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Routes declared in the config file
+    pub fn compile(routes: &[MockRouteConfig]) -> Result<Self, MockError> {
+        let mut seen = HashSet::new();
+        let mut mocks = Self::new();
+        for config in routes {
+            if !seen.insert(config.name.as_str()) {
+                return Err(MockError::DuplicateName(config.name.clone()));
+            }
+            mocks = mocks.with_route(MockRoute::compile(config)?);
+        }
+        Ok(mocks)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Whether a route answers `path`, for some model
+    pub fn has_path(&self, path: &str) -> bool {
+        self.routes.iter().any(|route| route.path == path)
+    }
+
+    /// The first route matching a request
+    pub fn lookup(&self, path: &str, model: Option<&str>) -> Option<&MockRoute> {
+        self.routes.iter().find(|route| route.matches(path, model))
+    }
+}
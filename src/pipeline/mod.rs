@@ -1,31 +1,295 @@
+use crate::budget::SpendBudgets;
 use crate::provider::ProviderRegistry;
+use crate::provider::snapshots::{RegistrySnapshots, RegistrySource, RegistryVersion};
+use crate::proxy::cors::CorsRules;
 use crate::proxy::ctx::Ctx;
-use pingora::http::{RequestHeader, ResponseHeader};
-use std::time::Instant;
+use crate::proxy::headers::HeaderRules;
+#[cfg(feature = "jwt")]
+use crate::proxy::jwt::JwtValidator;
+#[cfg(feature = "virtual-keys")]
+use crate::proxy::virtual_keys::VirtualKeys;
+use crate::proxy::websocket::WebSocketSession;
+use crate::upstream::{CredentialRoutes, UpstreamPools};
+use pingora_http::{RequestHeader, ResponseHeader};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 
+pub mod caller;
+pub mod dedup;
+pub mod deprecation;
+pub mod injection;
+pub mod language;
+pub mod mock;
+pub mod moderation;
+pub mod output_filter;
+pub mod pii;
+pub mod pricing;
+pub mod rate_limit;
+pub mod response_cache;
+pub mod rewrite;
+pub mod semantic_cache;
+pub mod tokenizer;
+pub mod transform;
+pub mod usage;
 pub mod views;
 
+use deprecation::DeprecationPolicy;
+use mock::MockRoutes;
+use pricing::Pricing;
+use rate_limit::RateLimiter;
+use transform::ResponseTransforms;
+use usage::Usage;
 use views::RequestView;
 
 pub struct Pipeline {
-    provider_registry: ProviderRegistry,
+    /// Swapped as a whole when the config is reloaded or through the admin API, with
+    /// the registries it replaced
+    provider_registry: RegistrySnapshots,
+    /// Swapped as a whole when the config is reloaded
+    deprecation_policy: RwLock<Arc<DeprecationPolicy>>,
+    /// Swapped as a whole when the config is reloaded
+    pricing: RwLock<Option<Arc<Pricing>>>,
+    /// Swapped as a whole when the config is reloaded
+    mock_routes: RwLock<Arc<MockRoutes>>,
+    /// Swapped as a whole when the config is reloaded
+    response_transforms: RwLock<Arc<ResponseTransforms>>,
+    /// Budget policy (swapped when the config is reloaded) and the spend against it
+    spend_budgets: Arc<SpendBudgets>,
+    /// Rate limit policy (swapped when the config is reloaded) and its token buckets
+    rate_limiter: RateLimiter,
+    /// Swapped as a whole when the config is reloaded
+    credential_routes: RwLock<Arc<CredentialRoutes>>,
+    /// Swapped as a whole when the config is reloaded
+    header_rules: RwLock<Arc<HeaderRules>>,
+    /// Swapped as a whole when the config is reloaded
+    cors_rules: RwLock<Arc<CorsRules>>,
+    /// Swapped as a whole when the config is reloaded
+    upstream_pools: RwLock<Arc<UpstreamPools>>,
+    /// Swapped as a whole when the config is reloaded
+    #[cfg(feature = "jwt")]
+    jwt: RwLock<Option<Arc<JwtValidator>>>,
+    /// Swapped as a whole when the config is reloaded
+    #[cfg(feature = "virtual-keys")]
+    virtual_keys: RwLock<Arc<VirtualKeys>>,
+    /// Toggled through the admin API; kept across config reloads
+    maintenance: AtomicBool,
 }
 
 impl Pipeline {
     pub fn new() -> Self {
+        Self::with_provider_registry(ProviderRegistry::new())
+    }
+
+    pub fn with_provider_registry(provider_registry: ProviderRegistry) -> Self {
         Self {
-            provider_registry: ProviderRegistry::new(),
+            provider_registry: RegistrySnapshots::new(Arc::new(provider_registry)),
+            deprecation_policy: RwLock::new(Arc::new(DeprecationPolicy::new())),
+            pricing: RwLock::new(None),
+            mock_routes: RwLock::new(Arc::new(MockRoutes::new())),
+            response_transforms: RwLock::new(Arc::new(ResponseTransforms::new())),
+            spend_budgets: Arc::new(SpendBudgets::default()),
+            rate_limiter: RateLimiter::default(),
+            credential_routes: RwLock::new(Arc::new(CredentialRoutes::new())),
+            header_rules: RwLock::new(Arc::new(HeaderRules::new())),
+            cors_rules: RwLock::new(Arc::new(CorsRules::new())),
+            upstream_pools: RwLock::new(Arc::new(UpstreamPools::new())),
+            #[cfg(feature = "jwt")]
+            jwt: RwLock::new(None),
+            #[cfg(feature = "virtual-keys")]
+            virtual_keys: RwLock::new(Arc::new(VirtualKeys::new())),
+            maintenance: AtomicBool::new(false),
         }
     }
 
+    pub fn provider_registry(&self) -> Arc<ProviderRegistry> {
+        self.provider_registry.registry()
+    }
+
+    /// Detect providers of new requests with `provider_registry`, which keeps
+    /// recording conflicts in the current registry's log
+    pub fn set_provider_registry(
+        &self,
+        provider_registry: ProviderRegistry,
+        source: RegistrySource,
+    ) -> RegistryVersion {
+        let conflicts = Arc::clone(self.provider_registry().conflict_log());
+        let provider_registry = provider_registry.with_conflict_log(conflicts);
+        self.provider_registry
+            .apply(Arc::new(provider_registry), source)
+    }
+
+    /// Applied and previous provider registries, for rollbacks
+    pub fn provider_registry_snapshots(&self) -> &RegistrySnapshots {
+        &self.provider_registry
+    }
+
+    pub fn deprecation_policy(&self) -> Arc<DeprecationPolicy> {
+        Arc::clone(&self.deprecation_policy.read().unwrap())
+    }
+
+    /// Apply `policy` to new requests
+    pub fn set_deprecation_policy(&self, policy: DeprecationPolicy) {
+        *self.deprecation_policy.write().unwrap() = Arc::new(policy);
+    }
+
+    pub fn pricing(&self) -> Option<Arc<Pricing>> {
+        self.pricing.read().unwrap().clone()
+    }
+
+    /// Price new requests with `pricing`, or stop pricing them
+    pub fn set_pricing(&self, pricing: Option<Pricing>) {
+        *self.pricing.write().unwrap() = pricing.map(Arc::new);
+    }
+
+    pub fn mock_routes(&self) -> Arc<MockRoutes> {
+        Arc::clone(&self.mock_routes.read().unwrap())
+    }
+
+    /// Answer new requests matching `routes` with their canned responses
+    pub fn set_mock_routes(&self, routes: MockRoutes) {
+        *self.mock_routes.write().unwrap() = Arc::new(routes);
+    }
+
+    pub fn response_transforms(&self) -> Arc<ResponseTransforms> {
+        Arc::clone(&self.response_transforms.read().unwrap())
+    }
+
+    /// Reshape the responses of new requests matching `transforms`
+    pub fn set_response_transforms(&self, transforms: ResponseTransforms) {
+        *self.response_transforms.write().unwrap() = Arc::new(transforms);
+    }
+
+    pub fn spend_budgets(&self) -> &Arc<SpendBudgets> {
+        &self.spend_budgets
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    pub fn credential_routes(&self) -> Arc<CredentialRoutes> {
+        Arc::clone(&self.credential_routes.read().unwrap())
+    }
+
+    /// Send the provider keys of `routes` with new requests
+    pub fn set_credential_routes(&self, routes: CredentialRoutes) {
+        *self.credential_routes.write().unwrap() = Arc::new(routes);
+    }
+
+    pub fn header_rules(&self) -> Arc<HeaderRules> {
+        Arc::clone(&self.header_rules.read().unwrap())
+    }
+
+    /// Strip, remove and add the headers of `rules` on new requests
+    pub fn set_header_rules(&self, rules: HeaderRules) {
+        *self.header_rules.write().unwrap() = Arc::new(rules);
+    }
+
+    pub fn cors_rules(&self) -> Arc<CorsRules> {
+        Arc::clone(&self.cors_rules.read().unwrap())
+    }
+
+    /// Answer the preflights and add the CORS headers of `rules` from now on
+    pub fn set_cors_rules(&self, rules: CorsRules) {
+        *self.cors_rules.write().unwrap() = Arc::new(rules);
+    }
+
+    pub fn upstream_pools(&self) -> Arc<UpstreamPools> {
+        Arc::clone(&self.upstream_pools.read().unwrap())
+    }
+
+    /// Route new requests to the upstreams of `pools` by path
+    pub fn set_upstream_pools(&self, pools: UpstreamPools) {
+        *self.upstream_pools.write().unwrap() = Arc::new(pools);
+    }
+
+    /// Whether the gateway is in maintenance mode, turning proxied requests away
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    pub fn set_maintenance(&self, maintenance: bool) {
+        self.maintenance.store(maintenance, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "jwt")]
+    pub fn jwt(&self) -> Option<Arc<JwtValidator>> {
+        self.jwt.read().unwrap().clone()
+    }
+
+    /// Require JWTs checked by `validator` on new requests, or stop checking them
+    #[cfg(feature = "jwt")]
+    pub fn set_jwt(&self, validator: Option<JwtValidator>) {
+        *self.jwt.write().unwrap() = validator.map(Arc::new);
+    }
+
+    #[cfg(feature = "virtual-keys")]
+    pub fn virtual_keys(&self) -> Arc<VirtualKeys> {
+        Arc::clone(&self.virtual_keys.read().unwrap())
+    }
+
+    /// Accept `keys` on new requests, and only them
+    #[cfg(feature = "virtual-keys")]
+    pub fn set_virtual_keys(&self, keys: VirtualKeys) {
+        *self.virtual_keys.write().unwrap() = Arc::new(keys);
+    }
+
     pub fn on_request(&self, request_header: &RequestHeader, ctx: &mut Ctx) {
+        ctx.mark("detect_start");
         let request_view = RequestView::new(request_header);
-        ctx.provider = self.provider_registry.detect(&request_view);
-        ctx.start = Some(Instant::now());
+        let provider_registry = self.provider_registry();
+        ctx.provider = provider_registry.detect(&request_view);
+        // Bedrock names the model in the path, OpenAI Realtime in the query; a model in
+        // the body replaces it
+        ctx.model = request_view
+            .path_model()
+            .or(request_view.query_model())
+            .map(str::to_string);
+        ctx.stream_format = provider_registry.stream_format(ctx.provider, &request_view);
+        ctx.websocket = request_view
+            .is_websocket_upgrade()
+            .then(WebSocketSession::new);
+        ctx.mark("detect_done");
     }
 
-    pub fn on_response(&self, _response_header: &ResponseHeader, _ctx: &mut Ctx) {
-        // Placeholder for future usage parsing
+    /// Record usage Bedrock reports in the headers of non-streamed `InvokeModel`
+    /// responses, whatever the model's body looks like
+    pub fn on_response(&self, response_header: &ResponseHeader, ctx: &mut Ctx) {
+        let count = |name: &str| {
+            response_header
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok())
+        };
+        if let (Some(prompt_tokens), Some(completion_tokens)) = (
+            count("x-amzn-bedrock-input-token-count"),
+            count("x-amzn-bedrock-output-token-count"),
+        ) {
+            ctx.usage = Some(Usage {
+                prompt_tokens,
+                completion_tokens,
+                estimated: false,
+            });
+        }
+    }
+
+    /// Feed a chunk of a JSON response to usage tracking; once the body is complete,
+    /// its usage replaces any reported in headers unless it had to be estimated
+    pub fn on_response_body(&self, chunk: Option<&[u8]>, end_of_stream: bool, ctx: &mut Ctx) {
+        let Some(response) = ctx.response_usage.as_mut() else {
+            return;
+        };
+        if let Some(chunk) = chunk {
+            response.feed(chunk);
+        }
+        if end_of_stream && let Some(response) = ctx.response_usage.take() {
+            let (usage, estimate) = response.finish_with_estimate(ctx.prompt_tokens);
+            if !usage.estimated || ctx.usage.is_none() {
+                ctx.usage = Some(usage);
+            }
+            ctx.usage_estimate = estimate;
+        }
     }
 }
 
@@ -0,0 +1,305 @@
+//! Content moderation of prompts and completions.
+//!
+//! Text is sent to a moderation endpoint speaking the OpenAI moderations API (OpenAI's
+//! `/v1/moderations` or an internal service answering the same way) under a policy
+//! chosen per route. A flagged prompt is blocked before it reaches the provider, or
+//! forwarded annotated with its categories. Completions are moderated once delivered,
+//! so a flagged completion is only logged and counted.
+
+use crate::pipeline::views::RequestView;
+use serde_json::Value;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModerationError {
+    /// An entry is not `prefix=action`
+    InvalidRoute(String),
+    /// The action is not `annotate`, `block` or `off`
+    UnknownAction(String, String),
+}
+
+impl fmt::Display for ModerationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModerationError::InvalidRoute(entry) => {
+                write!(f, "invalid moderation route '{}': use prefix=action", entry)
+            }
+            ModerationError::UnknownAction(entry, action) => write!(
+                f,
+                "unknown moderation action '{}' in '{}': use annotate, block or off",
+                action, entry
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ModerationError {}
+
+/// What happens to a request whose prompt is flagged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ModerationAction {
+    /// Forward the request, with [`ModerationConfig::HEADER`] naming the flagged
+    /// categories on the request and on its response
+    #[default]
+    Annotate,
+    /// Fail the request with 400
+    Block,
+}
+
+impl ModerationAction {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationAction::Annotate => "annotated",
+            ModerationAction::Block => "blocked",
+        }
+    }
+
+    /// `annotate` or `block`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "annotate" => Some(ModerationAction::Annotate),
+            "block" => Some(ModerationAction::Block),
+            _ => None,
+        }
+    }
+}
+
+/// How the requests of a route are moderated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModerationPolicy {
+    /// Moderate prompts before they are forwarded
+    pub prompts: bool,
+    /// Moderate complete (non-streamed) JSON completions once delivered
+    pub completions: bool,
+    pub action: ModerationAction,
+    /// Categories acted on; any flagged category when empty
+    pub categories: Vec<String>,
+}
+
+impl Default for ModerationPolicy {
+    /// Annotating prompts flagged in any category
+    fn default() -> Self {
+        Self {
+            prompts: true,
+            completions: false,
+            action: ModerationAction::default(),
+            categories: Vec::new(),
+        }
+    }
+}
+
+impl ModerationPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moderating nothing, e.g. for a route excepted from a default policy
+    pub fn off() -> Self {
+        Self::new().with_prompts(false)
+    }
+
+    pub fn with_prompts(mut self, prompts: bool) -> Self {
+        self.prompts = prompts;
+        self
+    }
+
+    pub fn with_completions(mut self, completions: bool) -> Self {
+        self.completions = completions;
+        self
+    }
+
+    pub fn with_action(mut self, action: ModerationAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Only act on this category (e.g. `violence`), besides the ones added before
+    pub fn with_category(mut self, category: impl Into<String>) -> Self {
+        self.categories.push(category.into());
+        self
+    }
+
+    /// Categories of a moderation result the policy acts on; `None` when it does not
+    /// act on the result
+    pub fn violations(&self, result: &ModerationResult) -> Option<Vec<String>> {
+        if !result.flagged {
+            return None;
+        }
+        if self.categories.is_empty() {
+            return Some(result.categories.clone());
+        }
+        let violations: Vec<String> = result
+            .categories
+            .iter()
+            .filter(|category| self.categories.contains(category))
+            .cloned()
+            .collect();
+        (!violations.is_empty()).then_some(violations)
+    }
+}
+
+/// What the moderation endpoint said about a text
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModerationResult {
+    pub flagged: bool,
+    /// Flagged categories, sorted
+    pub categories: Vec<String>,
+}
+
+/// Moderation endpoint and the policies of the routes it moderates
+#[derive(Clone)]
+pub struct ModerationConfig {
+    /// Moderations endpoint, e.g. `https://api.openai.com/v1/moderations`
+    pub url: String,
+    /// Sent as a bearer token to the moderation endpoint
+    pub api_key: Option<String>,
+    /// Moderation model, e.g. `omni-moderation-latest`; the endpoint's default if unset
+    pub model: Option<String>,
+    /// How long a moderation request may take before the moderation stage fails
+    pub timeout: Duration,
+    /// Policy of requests no route matches
+    pub default: Option<ModerationPolicy>,
+    /// Path prefixes (routes) with their policy; the longest matching prefix wins
+    pub routes: Vec<(String, ModerationPolicy)>,
+    /// Completions with larger bodies are not moderated
+    pub max_completion_bytes: usize,
+}
+
+impl fmt::Debug for ModerationConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModerationConfig")
+            .field("url", &self.url)
+            .field("model", &self.model)
+            .field("timeout", &self.timeout)
+            .field("default", &self.default)
+            .field("routes", &self.routes)
+            .field("max_completion_bytes", &self.max_completion_bytes)
+            .finish()
+    }
+}
+
+impl ModerationConfig {
+    /// Header naming the flagged categories of an annotated request
+    pub const HEADER: &'static str = "X-Langspec-Moderation";
+
+    /// Moderation with `url`, under no policy until one is added
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            api_key: None,
+            model: None,
+            timeout: Duration::from_secs(2),
+            default: None,
+            routes: Vec::new(),
+            max_completion_bytes: 1024 * 1024,
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_default(mut self, policy: ModerationPolicy) -> Self {
+        self.default = Some(policy);
+        self
+    }
+
+    pub fn with_route(mut self, prefix: impl Into<String>, policy: ModerationPolicy) -> Self {
+        self.routes.push((prefix.into(), policy));
+        self
+    }
+
+    /// Routes from a comma-separated list of `prefix=action`, with action `annotate`,
+    /// `block` or `off`, e.g. `/v1/chat/completions=block,/v1/embeddings=off`. Routes
+    /// moderate what the default policy does.
+    pub fn with_route_list(mut self, list: &str) -> Result<Self, ModerationError> {
+        let base = self.default.clone().unwrap_or_default();
+        for entry in list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (prefix, action) = entry
+                .split_once('=')
+                .ok_or_else(|| ModerationError::InvalidRoute(entry.to_string()))?;
+            let policy = match action.trim() {
+                "annotate" => base.clone().with_action(ModerationAction::Annotate),
+                "block" => base.clone().with_action(ModerationAction::Block),
+                "off" => ModerationPolicy::off(),
+                action => {
+                    return Err(ModerationError::UnknownAction(
+                        entry.to_string(),
+                        action.to_string(),
+                    ));
+                }
+            };
+            self = self.with_route(prefix.trim(), policy);
+        }
+        Ok(self)
+    }
+
+    pub fn with_max_completion_bytes(mut self, max_completion_bytes: usize) -> Self {
+        self.max_completion_bytes = max_completion_bytes;
+        self
+    }
+
+    /// Policy of the request's route, if it is moderated
+    pub fn policy_for(&self, request_view: &RequestView) -> Option<&ModerationPolicy> {
+        let path = request_view.path();
+        self.routes
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, policy)| policy)
+            .or(self.default.as_ref())
+    }
+
+    /// Body of a moderation request for `text`
+    pub fn moderation_request(&self, text: &str) -> Vec<u8> {
+        let mut request = serde_json::json!({"input": text});
+        if let Some(model) = &self.model {
+            request["model"] = Value::from(model.as_str());
+        }
+        serde_json::to_vec(&request).expect("JSON values serialize")
+    }
+}
+
+/// Result of a moderations response: flagged when any of its results is, with the
+/// categories flagged in any of them
+pub fn parse_moderation(body: &[u8]) -> Option<ModerationResult> {
+    let response: Value = serde_json::from_slice(body).ok()?;
+    let mut result = ModerationResult::default();
+    for item in response.get("results")?.as_array()? {
+        result.flagged |= item.get("flagged")?.as_bool()?;
+        if let Some(categories) = item.get("categories").and_then(Value::as_object) {
+            for (category, flagged) in categories {
+                if flagged.as_bool() == Some(true) && !result.categories.contains(category) {
+                    result.categories.push(category.clone());
+                }
+            }
+        }
+    }
+    result.categories.sort();
+    Some(result)
+}
+
+/// Value of [`ModerationConfig::HEADER`] for flagged `categories`
+pub fn moderation_label(categories: &[String]) -> String {
+    match categories.is_empty() {
+        true => "flagged".to_string(),
+        false => categories.join(","),
+    }
+}
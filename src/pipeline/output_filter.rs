@@ -0,0 +1,372 @@
+use crate::metrics::OUTPUT_FILTER_MATCHES;
+use crate::pipeline::usage::text_pointers;
+use crate::provider::{FinishReason, StreamFormat};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// What a content filter does with matched output
+#[derive(Debug, Clone)]
+pub enum FilterAction {
+    /// Replace the match and keep streaming
+    Mask(String),
+    /// End the stream before the match (`finish_reason: content_filter`)
+    Truncate,
+}
+
+#[derive(Debug, Clone)]
+pub struct ContentFilter {
+    pub pattern: Regex,
+    pub action: FilterAction,
+}
+
+/// Gateway-level post-processing of streamed output: stop sequences end the stream
+/// where they appear (`finish_reason: stop`), content filters mask or truncate regex
+/// matches, before the text reaches the client.
+///
+/// Matches may span chunk (and event) boundaries: the last `window` characters of
+/// output are held back until more text arrives or the stream ends, which delays the
+/// stream by that much. Matches longer than the window can slip through partially.
+/// Applies to SSE and JSON-lines streams; AWS event streams pass through unfiltered.
+#[derive(Debug, Clone)]
+pub struct OutputFilterConfig {
+    pub stop_sequences: Vec<String>,
+    pub filters: Vec<ContentFilter>,
+    /// Characters held back to catch matches across chunk boundaries
+    pub window: usize,
+}
+
+impl OutputFilterConfig {
+    pub const DEFAULT_WINDOW: usize = 64;
+
+    pub fn new() -> Self {
+        Self {
+            stop_sequences: Vec::new(),
+            filters: Vec::new(),
+            window: Self::DEFAULT_WINDOW,
+        }
+    }
+
+    pub fn with_stop_sequence(mut self, sequence: impl Into<String>) -> Self {
+        let sequence = sequence.into();
+        assert!(!sequence.is_empty(), "Stop sequences cannot be empty");
+        self.stop_sequences.push(sequence);
+        self
+    }
+
+    /// Replace matches of `pattern` with `replacement`
+    pub fn with_mask(
+        mut self,
+        pattern: &str,
+        replacement: impl Into<String>,
+    ) -> Result<Self, regex::Error> {
+        self.filters.push(ContentFilter {
+            pattern: Regex::new(pattern)?,
+            action: FilterAction::Mask(replacement.into()),
+        });
+        Ok(self)
+    }
+
+    /// End the stream before the first match of `pattern`
+    pub fn with_truncate(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.filters.push(ContentFilter {
+            pattern: Regex::new(pattern)?,
+            action: FilterAction::Truncate,
+        });
+        Ok(self)
+    }
+
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Earliest match in `text` as (start, end, action); stop sequences are checked
+    /// first, so they win a tie
+    fn earliest_match<'a>(&'a self, text: &str) -> Option<(usize, usize, Hit<'a>)> {
+        let stops = self.stop_sequences.iter().filter_map(|sequence| {
+            text.find(sequence.as_str())
+                .map(|start| (start, start + sequence.len(), Hit::Stop))
+        });
+        let filters = self.filters.iter().filter_map(|filter| {
+            let found = filter.pattern.find(text)?;
+            let hit = match &filter.action {
+                FilterAction::Mask(replacement) => Hit::Mask(replacement),
+                FilterAction::Truncate => Hit::Truncate,
+            };
+            Some((found.start(), found.end(), hit))
+        });
+        stops
+            .chain(filters)
+            .fold(None, |earliest, hit| match earliest {
+                Some(current) if current.0 <= hit.0 => Some(current),
+                _ => Some(hit),
+            })
+    }
+}
+
+impl Default for OutputFilterConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum Hit<'a> {
+    Stop,
+    Mask(&'a str),
+    Truncate,
+}
+
+/// A unit of the stream: one SSE event or one JSON line, split around its JSON payload
+#[derive(Debug, Clone)]
+struct Unit {
+    prefix: String,
+    event: Value,
+    suffix: String,
+}
+
+impl Unit {
+    fn parse(format: StreamFormat, bytes: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(bytes).ok()?;
+        let (start, end) = match format {
+            StreamFormat::ServerSentEvents => {
+                let line_start = text
+                    .match_indices("data:")
+                    .map(|(index, _)| index)
+                    .find(|&index| index == 0 || text[..index].ends_with('\n'))?;
+                let start = line_start + "data:".len();
+                let end = text[start..]
+                    .find('\n')
+                    .map_or(text.len(), |end| start + end);
+                (start, end)
+            }
+            _ => (0, text.trim_end().len()),
+        };
+        let event = serde_json::from_str(text[start..end].trim()).ok()?;
+        let prefix = match format {
+            StreamFormat::ServerSentEvents => format!("{} ", text[..start].trim_end()),
+            _ => String::new(),
+        };
+        Some(Self {
+            prefix,
+            event,
+            suffix: text[end..].to_string(),
+        })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.prefix.as_bytes());
+        out.extend_from_slice(self.event.to_string().as_bytes());
+        out.extend_from_slice(self.suffix.as_bytes());
+    }
+}
+
+#[derive(Debug, Default)]
+struct Held {
+    text: String,
+    /// Last event that carried text for this choice, reused to release held text
+    /// when the stream moves on to events without text
+    template: Option<Unit>,
+}
+
+/// Output filtering of one streamed response, fed chunk by chunk.
+#[derive(Debug)]
+pub struct OutputFilter {
+    config: Arc<OutputFilterConfig>,
+    format: StreamFormat,
+    /// Bytes of an incomplete event carried over to the next chunk
+    pending: Vec<u8>,
+    /// Text held back per choice
+    held: BTreeMap<u64, Held>,
+    ended: Option<FinishReason>,
+}
+
+impl OutputFilter {
+    pub fn new(config: Arc<OutputFilterConfig>, format: StreamFormat) -> Self {
+        Self {
+            config,
+            format,
+            pending: Vec::new(),
+            held: BTreeMap::new(),
+            ended: None,
+        }
+    }
+
+    pub fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    /// Set once a stop sequence or truncating filter ended the stream; the caller
+    /// appends the provider's stop event and stops forwarding
+    pub fn ended(&self) -> Option<FinishReason> {
+        self.ended
+    }
+
+    /// Filter a chunk of the response body, returning what to forward to the client
+    pub fn feed(&mut self, chunk: &[u8], end_of_stream: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        if self.ended.is_some() {
+            return out;
+        }
+        self.pending.extend_from_slice(chunk);
+
+        let delimiter: &[u8] = match self.format {
+            StreamFormat::ServerSentEvents => b"\n\n",
+            _ => b"\n",
+        };
+        while let Some(position) = self
+            .pending
+            .windows(delimiter.len())
+            .position(|window| window == delimiter)
+        {
+            let unit: Vec<u8> = self.pending.drain(..position + delimiter.len()).collect();
+            self.process(&unit, &mut out);
+            if self.ended.is_some() {
+                self.pending.clear();
+                return out;
+            }
+        }
+
+        if end_of_stream {
+            self.flush(&mut out);
+            if self.ended.is_none() {
+                out.append(&mut self.pending);
+            }
+        }
+        out
+    }
+
+    fn process(&mut self, bytes: &[u8], out: &mut Vec<u8>) {
+        let Some(mut unit) = Unit::parse(self.format, bytes) else {
+            self.flush(out);
+            out.extend_from_slice(bytes);
+            return;
+        };
+        let pointers = text_pointers(&unit.event);
+        if pointers.is_empty() {
+            // Release held text before events that end the output (finish reasons,
+            // usage, `[DONE]`)
+            self.flush(out);
+            if self.ended.is_none() {
+                unit.write(out);
+            }
+            return;
+        }
+
+        for (index, pointer) in &pointers {
+            let Some(Value::String(text)) = unit.event.pointer_mut(pointer) else {
+                continue;
+            };
+            let held = self.held.entry(*index).or_default();
+            held.text.push_str(text);
+            let (released, ended) = release(&self.config, &mut held.text, false);
+            *text = released;
+            self.ended = self.ended.or(ended);
+        }
+        for (index, _) in &pointers {
+            if let Some(held) = self.held.get_mut(index) {
+                held.template = Some(unit.clone());
+            }
+        }
+        unit.write(out);
+    }
+
+    /// Release all held text, in copies of the last event that carried it
+    fn flush(&mut self, out: &mut Vec<u8>) {
+        for (index, held) in &mut self.held {
+            if self.ended.is_some() {
+                return;
+            }
+            if held.text.is_empty() {
+                continue;
+            }
+            let (released, ended) = release(&self.config, &mut held.text, true);
+            self.ended = ended;
+            let Some(mut unit) = held.template.clone() else {
+                continue;
+            };
+            for (other, pointer) in text_pointers(&unit.event) {
+                if let Some(Value::String(text)) = unit.event.pointer_mut(&pointer) {
+                    *text = if other == *index {
+                        released.clone()
+                    } else {
+                        String::new()
+                    };
+                }
+            }
+            unit.write(out);
+        }
+    }
+}
+
+/// Apply stop sequences and filters to held text and return the part that can be
+/// released: everything but the last `window` characters, or all of it at the end of
+/// the stream. Masked matches that may still grow with more text are held back whole.
+fn release(
+    config: &OutputFilterConfig,
+    held: &mut String,
+    all: bool,
+) -> (String, Option<FinishReason>) {
+    let mut limit = if all {
+        held.len()
+    } else {
+        config.window.checked_sub(1).map_or(held.len(), |back| {
+            held.char_indices()
+                .nth_back(back)
+                .map_or(0, |(index, _)| index)
+        })
+    };
+
+    let mut released = String::new();
+    let mut position = 0;
+    while let Some((start, end, hit)) = config.earliest_match(&held[position..]) {
+        let (start, end) = (position + start, position + end);
+        // Stop sequences and truncation end the stream as soon as they match; a mask
+        // waits until its match leaves the window, since more text may extend it
+        if matches!(hit, Hit::Mask(_)) && !all {
+            if start >= limit {
+                break;
+            }
+            if end > limit {
+                limit = start;
+                break;
+            }
+        }
+        released.push_str(&held[position..start]);
+        let reason = match hit {
+            Hit::Stop => Some(FinishReason::Stop),
+            Hit::Truncate => Some(FinishReason::ContentFilter),
+            Hit::Mask(replacement) => {
+                released.push_str(replacement);
+                None
+            }
+        };
+        let action = match hit {
+            Hit::Stop => "stop",
+            Hit::Mask(_) => "mask",
+            Hit::Truncate => "truncate",
+        };
+        OUTPUT_FILTER_MATCHES.with_label_values(&[action]).inc();
+        if reason.is_some() {
+            held.clear();
+            return (released, reason);
+        }
+        position = end;
+        if start == end {
+            // Empty match: move past one character
+            match held[position..].chars().next() {
+                Some(next) if position < limit => {
+                    released.push(next);
+                    position += next.len_utf8();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    let limit = limit.max(position);
+    released.push_str(&held[position..limit]);
+    held.drain(..limit);
+    (released, None)
+}
@@ -0,0 +1,282 @@
+//! PII guardrail for prompts.
+//!
+//! [`PiiRedaction`] is a [`BodyRewrite`] scanning the prompt text of JSON request
+//! bodies (the strings under the keys prompt text is counted from, so model names and
+//! parameters are left alone) for email addresses, US social security numbers, payment
+//! card numbers and custom patterns. Matches are masked before the request is forwarded,
+//! or the request is rejected. Redaction events are logged and counted by pattern,
+//! never with the matched text.
+
+use crate::metrics::PII_MATCHES;
+use crate::pipeline::rewrite::{BodyRewrite, RewriteError};
+use crate::pipeline::usage::PROMPT_KEYS;
+use crate::pipeline::views::RequestView;
+use log::info;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}")
+        .expect("pattern is valid")
+});
+
+static SSN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b\d{3}-\d{2}-\d{4}\b").expect("pattern is valid"));
+
+/// 13 to 19 digits, optionally grouped with spaces or dashes
+static CREDIT_CARD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").expect("pattern is valid"));
+
+/// What happens to a request whose prompt matches a pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PiiAction {
+    /// Replace each match with `[REDACTED:<pattern>]` and forward the request
+    #[default]
+    Mask,
+    /// Fail the request with 400
+    Reject,
+}
+
+impl PiiAction {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PiiAction::Mask => "mask",
+            PiiAction::Reject => "reject",
+        }
+    }
+}
+
+/// A kind of PII, found by a regex and, for built-in kinds, a check of the match
+#[derive(Debug, Clone)]
+pub struct PiiPattern {
+    pub name: String,
+    pub regex: Regex,
+    /// Tells matches that are PII from look-alikes, e.g. card numbers by checksum
+    check: Option<fn(&str) -> bool>,
+}
+
+impl PiiPattern {
+    /// Email addresses
+    pub fn email() -> Self {
+        Self {
+            name: "email".into(),
+            regex: EMAIL.clone(),
+            check: None,
+        }
+    }
+
+    /// US social security numbers (`123-45-6789`), skipping numbers never issued
+    pub fn ssn() -> Self {
+        Self {
+            name: "ssn".into(),
+            regex: SSN.clone(),
+            check: Some(valid_ssn),
+        }
+    }
+
+    /// Payment card numbers passing the Luhn checksum
+    pub fn credit_card() -> Self {
+        Self {
+            name: "credit_card".into(),
+            regex: CREDIT_CARD.clone(),
+            check: Some(luhn),
+        }
+    }
+
+    /// Matches of `regex`, e.g. internal account numbers
+    pub fn custom(name: impl Into<String>, regex: Regex) -> Self {
+        Self {
+            name: name.into(),
+            regex,
+            check: None,
+        }
+    }
+
+    fn is_pii(&self, text: &str) -> bool {
+        self.check.is_none_or(|check| check(text))
+    }
+}
+
+fn valid_ssn(ssn: &str) -> bool {
+    let mut parts = ssn.split('-');
+    let (Some(area), Some(group), Some(serial)) = (parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    area != "000" && area != "666" && !area.starts_with('9') && group != "00" && serial != "0000"
+}
+
+fn luhn(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(position, &digit)| match position % 2 {
+            1 if digit * 2 > 9 => digit * 2 - 9,
+            1 => digit * 2,
+            _ => digit,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Masks or rejects PII in prompts
+#[derive(Debug, Clone, Default)]
+pub struct PiiRedaction {
+    patterns: Vec<PiiPattern>,
+    action: PiiAction,
+}
+
+impl PiiRedaction {
+    /// No patterns yet, masking matches
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Email addresses, social security numbers and payment card numbers
+    pub fn with_builtin_patterns(self) -> Self {
+        self.with_pattern(PiiPattern::email())
+            .with_pattern(PiiPattern::ssn())
+            .with_pattern(PiiPattern::credit_card())
+    }
+
+    /// Also look for `pattern`, after the patterns added before it
+    pub fn with_pattern(mut self, pattern: PiiPattern) -> Self {
+        assert!(
+            self.patterns.iter().all(|other| other.name != pattern.name),
+            "PII pattern '{}' is added twice",
+            pattern.name
+        );
+        self.patterns.push(pattern);
+        self
+    }
+
+    pub fn with_action(mut self, action: PiiAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    pub fn patterns(&self) -> &[PiiPattern] {
+        &self.patterns
+    }
+
+    /// Mask matches in `text`, counting them by pattern. Returns the masked text when
+    /// anything matched.
+    fn mask(&self, text: &str, matches: &mut BTreeMap<String, u64>) -> Option<String> {
+        let mut masked: Option<String> = None;
+        for pattern in &self.patterns {
+            let current = masked.as_deref().unwrap_or(text);
+            let mut count = 0;
+            let replaced = pattern
+                .regex
+                .replace_all(current, |captures: &regex::Captures| {
+                    let found = &captures[0];
+                    match pattern.is_pii(found) {
+                        true => {
+                            count += 1;
+                            format!("[REDACTED:{}]", pattern.name)
+                        }
+                        false => found.to_string(),
+                    }
+                });
+            if count > 0 {
+                *matches.entry(pattern.name.clone()).or_default() += count;
+                masked = Some(replaced.into_owned());
+            }
+        }
+        masked
+    }
+
+    /// Name of the first pattern with a match in `text`
+    fn find(&self, text: &str) -> Option<&str> {
+        self.patterns
+            .iter()
+            .find(|pattern| {
+                pattern
+                    .regex
+                    .find_iter(text)
+                    .any(|found| pattern.is_pii(found.as_str()))
+            })
+            .map(|pattern| pattern.name.as_str())
+    }
+
+    /// Apply the action to every prompt string under `value`
+    fn scan(
+        &self,
+        value: &mut Value,
+        is_prompt: bool,
+        matches: &mut BTreeMap<String, u64>,
+    ) -> Result<(), String> {
+        match value {
+            Value::String(text) if is_prompt => match self.action {
+                PiiAction::Mask => {
+                    if let Some(masked) = self.mask(text, matches) {
+                        *text = masked;
+                    }
+                }
+                PiiAction::Reject => {
+                    if let Some(name) = self.find(text) {
+                        *matches.entry(name.to_string()).or_default() += 1;
+                        return Err(name.to_string());
+                    }
+                }
+            },
+            Value::Array(items) => {
+                for item in items {
+                    self.scan(item, is_prompt, matches)?;
+                }
+            }
+            Value::Object(fields) => {
+                for (key, value) in fields {
+                    self.scan(value, PROMPT_KEYS.contains(&key.as_str()), matches)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl BodyRewrite for PiiRedaction {
+    fn name(&self) -> &'static str {
+        "pii_redaction"
+    }
+
+    fn rewrite(
+        &self,
+        request_view: &RequestView,
+        request: &mut Map<String, Value>,
+    ) -> Result<bool, RewriteError> {
+        let mut matches = BTreeMap::new();
+        let mut result = Ok(());
+        for (key, value) in request.iter_mut() {
+            result = self.scan(value, PROMPT_KEYS.contains(&key.as_str()), &mut matches);
+            if result.is_err() {
+                break;
+            }
+        }
+        for (pattern, count) in &matches {
+            PII_MATCHES
+                .with_label_values(&[pattern, self.action.as_str()])
+                .inc_by(*count);
+            info!(
+                "PII redaction: {} {} match(es) of '{}' in {} (tenant {})",
+                self.action.as_str(),
+                count,
+                pattern,
+                request_view.path(),
+                request_view.tenant().unwrap_or("none")
+            );
+        }
+        match result {
+            Ok(()) => Ok(!matches.is_empty()),
+            Err(pattern) => Err(RewriteError::Rejected(
+                self.name(),
+                format!("prompt contains {}", pattern),
+            )),
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use crate::pipeline::usage::Usage;
+use crate::provider::ProviderKind;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PricingError {
+    /// A price of a provider's model is negative or not a number
+    InvalidPrice(String, String),
+    /// A tenant's markup is -100% or less
+    InvalidMarkup(String, f64),
+    DuplicateTenant(String),
+}
+
+impl fmt::Display for PricingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PricingError::InvalidPrice(provider, model) => write!(
+                f,
+                "price of {} model '{}' must be a non-negative number",
+                provider, model
+            ),
+            PricingError::InvalidMarkup(tenant, percent) => write!(
+                f,
+                "markup of tenant '{}' must be greater than -100%, got {}%",
+                tenant, percent
+            ),
+            PricingError::DuplicateTenant(tenant) => {
+                write!(f, "tenant '{}' is priced twice", tenant)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PricingError {}
+
+/// Price of a model in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl ModelPrice {
+    pub fn new(prompt: f64, completion: f64) -> Self {
+        Self { prompt, completion }
+    }
+
+    /// Cost of a request's usage in USD
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt
+            + usage.completion_tokens as f64 * self.completion)
+            / 1_000_000.0
+    }
+}
+
+/// Prices keyed by provider name and model prefix
+#[derive(Debug, Clone, Default)]
+struct PriceList {
+    prices: Vec<(String, String, ModelPrice)>,
+}
+
+impl PriceList {
+    fn insert(&mut self, provider: String, model: String, price: ModelPrice) {
+        self.prices
+            .retain(|(other, prefix, _)| *other != provider || *prefix != model);
+        self.prices.push((provider, model, price));
+    }
+
+    /// Price of the longest model prefix listed for the provider, so `gpt-4o` covers
+    /// `gpt-4o-2024-08-06` while `gpt-4o-mini` can be priced separately
+    fn get(&self, provider: ProviderKind, model: &str) -> Option<ModelPrice> {
+        self.prices
+            .iter()
+            .filter(|(other, prefix, _)| {
+                other == provider.as_str() && model.starts_with(prefix.as_str())
+            })
+            .max_by_key(|(_, prefix, _)| prefix.len())
+            .map(|(_, _, price)| *price)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct TenantPricing {
+    prices: PriceList,
+    /// Percentage added to base prices, e.g. 20.0 for +20%
+    markup_percent: f64,
+}
+
+/// Cost of requests from their token usage, for chargeback and reselling.
+///
+/// The base table prices models per provider. A tenant (`X-Langspec-Tenant`) can have
+/// its own prices for some models, which replace the base price as is, and a
+/// percentage markup applied on top of the base prices of all other models. Requests
+/// whose model has no price have no cost.
+#[derive(Debug, Clone, Default)]
+pub struct Pricing {
+    base: PriceList,
+    tenants: HashMap<String, TenantPricing>,
+}
+
+impl Pricing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Base price of `provider` models starting with `model`
+    pub fn with_price(
+        mut self,
+        provider: ProviderKind,
+        model: impl Into<String>,
+        price: ModelPrice,
+    ) -> Self {
+        self.base
+            .insert(provider.as_str().to_string(), model.into(), price);
+        self
+    }
+
+    /// Base prices of popular hosted models, as listed by their providers, in USD per
+    /// million tokens. Later prices of the same model replace them.
+    pub fn with_default_prices(self) -> Self {
+        const DEFAULT_PRICES: &[(ProviderKind, &str, f64, f64)] = &[
+            (ProviderKind::OpenAI, "gpt-4o", 2.5, 10.0),
+            (ProviderKind::OpenAI, "gpt-4o-mini", 0.15, 0.6),
+            (ProviderKind::OpenAI, "gpt-4.1", 2.0, 8.0),
+            (ProviderKind::OpenAI, "gpt-4.1-mini", 0.4, 1.6),
+            (ProviderKind::OpenAI, "gpt-4.1-nano", 0.1, 0.4),
+            (ProviderKind::OpenAI, "gpt-4-turbo", 10.0, 30.0),
+            (ProviderKind::OpenAI, "gpt-3.5-turbo", 0.5, 1.5),
+            (ProviderKind::OpenAI, "o1", 15.0, 60.0),
+            (ProviderKind::OpenAI, "o3-mini", 1.1, 4.4),
+            (ProviderKind::OpenAI, "text-embedding-3-small", 0.02, 0.0),
+            (ProviderKind::OpenAI, "text-embedding-3-large", 0.13, 0.0),
+            (
+                ProviderKind::Bedrock,
+                "anthropic.claude-3-5-sonnet",
+                3.0,
+                15.0,
+            ),
+            (
+                ProviderKind::Bedrock,
+                "anthropic.claude-3-5-haiku",
+                0.8,
+                4.0,
+            ),
+            (
+                ProviderKind::Bedrock,
+                "anthropic.claude-3-haiku",
+                0.25,
+                1.25,
+            ),
+            (ProviderKind::Bedrock, "anthropic.claude-3-opus", 15.0, 75.0),
+            (ProviderKind::Bedrock, "meta.llama3-1-70b", 0.72, 0.72),
+            (ProviderKind::Bedrock, "amazon.titan-text-express", 0.2, 0.6),
+            (ProviderKind::Cohere, "command-r", 0.15, 0.6),
+            (ProviderKind::Cohere, "command-r-plus", 2.5, 10.0),
+            (ProviderKind::DeepSeek, "deepseek-chat", 0.27, 1.1),
+            (ProviderKind::DeepSeek, "deepseek-reasoner", 0.55, 2.19),
+        ];
+        DEFAULT_PRICES
+            .iter()
+            .fold(self, |pricing, &(provider, model, prompt, completion)| {
+                pricing.with_price(provider, model, ModelPrice::new(prompt, completion))
+            })
+    }
+
+    /// Price a tenant pays for `provider` models starting with `model`, instead of the
+    /// marked-up base price
+    pub fn with_tenant_price(
+        mut self,
+        tenant: impl Into<String>,
+        provider: ProviderKind,
+        model: impl Into<String>,
+        price: ModelPrice,
+    ) -> Self {
+        self.tenants
+            .entry(tenant.into())
+            .or_default()
+            .prices
+            .insert(provider.as_str().to_string(), model.into(), price);
+        self
+    }
+
+    /// Percentage added to base prices for a tenant (negative for a discount)
+    pub fn with_tenant_markup(mut self, tenant: impl Into<String>, percent: f64) -> Self {
+        assert!(percent > -100.0, "Markup must be greater than -100%");
+        self.tenants
+            .entry(tenant.into())
+            .or_default()
+            .markup_percent = percent;
+        self
+    }
+
+    /// Whether some provider's base prices cover `model`
+    pub fn has_price(&self, model: &str) -> bool {
+        self.base
+            .prices
+            .iter()
+            .any(|(_, prefix, _)| model.starts_with(prefix.as_str()))
+    }
+
+    /// Price a tenant pays for a model, if it is priced
+    pub fn price(
+        &self,
+        tenant: Option<&str>,
+        provider: ProviderKind,
+        model: &str,
+    ) -> Option<ModelPrice> {
+        let tenant = tenant.and_then(|tenant| self.tenants.get(tenant));
+        if let Some(price) = tenant.and_then(|tenant| tenant.prices.get(provider, model)) {
+            return Some(price);
+        }
+        let base = self.base.get(provider, model)?;
+        let factor = 1.0 + tenant.map_or(0.0, |tenant| tenant.markup_percent) / 100.0;
+        Some(ModelPrice::new(
+            base.prompt * factor,
+            base.completion * factor,
+        ))
+    }
+
+    /// Cost of a request's usage in USD, if its model is priced
+    pub fn cost(
+        &self,
+        tenant: Option<&str>,
+        provider: ProviderKind,
+        model: &str,
+        usage: &Usage,
+    ) -> Option<f64> {
+        self.price(tenant, provider, model)
+            .map(|price| price.cost(usage))
+    }
+}
+
+/// Price of a model in the config file, in USD per million tokens
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelPriceConfig {
+    /// Provider name, e.g. `openai` or a custom provider's name
+    pub provider: String,
+    /// Model name prefix
+    pub model: String,
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+/// Prices of a tenant in the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantPricingConfig {
+    pub name: String,
+    /// Percentage added to base prices (negative for a discount)
+    #[serde(default)]
+    pub markup_percent: f64,
+    /// Prices replacing the marked-up base prices
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<ModelPriceConfig>,
+}
+
+/// The `pricing` section of the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PricingConfig {
+    /// Start from [`Pricing::with_default_prices`]; listed models replace its prices
+    #[serde(default = "default_prices")]
+    pub default_prices: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<ModelPriceConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tenants: Vec<TenantPricingConfig>,
+}
+
+fn default_prices() -> bool {
+    true
+}
+
+impl Default for PricingConfig {
+    fn default() -> Self {
+        Self {
+            default_prices: true,
+            models: Vec::new(),
+            tenants: Vec::new(),
+        }
+    }
+}
+
+impl ModelPriceConfig {
+    fn compile(&self) -> Result<(String, String, ModelPrice), PricingError> {
+        let valid = |price: f64| price.is_finite() && price >= 0.0;
+        if !valid(self.prompt) || !valid(self.completion) {
+            return Err(PricingError::InvalidPrice(
+                self.provider.clone(),
+                self.model.clone(),
+            ));
+        }
+        Ok((
+            self.provider.clone(),
+            self.model.clone(),
+            ModelPrice::new(self.prompt, self.completion),
+        ))
+    }
+}
+
+impl PricingConfig {
+    pub fn compile(&self) -> Result<Pricing, PricingError> {
+        let mut pricing = match self.default_prices {
+            true => Pricing::new().with_default_prices(),
+            false => Pricing::new(),
+        };
+        for model in &self.models {
+            let (provider, model, price) = model.compile()?;
+            pricing.base.insert(provider, model, price);
+        }
+        let mut seen = HashSet::new();
+        for tenant in &self.tenants {
+            if !seen.insert(tenant.name.as_str()) {
+                return Err(PricingError::DuplicateTenant(tenant.name.clone()));
+            }
+            if !(tenant.markup_percent > -100.0 && tenant.markup_percent.is_finite()) {
+                return Err(PricingError::InvalidMarkup(
+                    tenant.name.clone(),
+                    tenant.markup_percent,
+                ));
+            }
+            let entry = pricing.tenants.entry(tenant.name.clone()).or_default();
+            entry.markup_percent = tenant.markup_percent;
+            for model in &tenant.models {
+                let (provider, model, price) = model.compile()?;
+                entry.prices.insert(provider, model, price);
+            }
+        }
+        Ok(pricing)
+    }
+}
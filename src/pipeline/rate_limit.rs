@@ -0,0 +1,334 @@
+//! Request rate limits.
+//!
+//! Each limit is a token bucket refilled at a steady rate and holding up to a burst of
+//! requests. A limit applies to one of three scopes:
+//! - a client key pattern: every client matching it gets its own bucket. A client is
+//!   the tenant it authenticated as, else `key:` and a hash of the credentials it
+//!   presents, else its IP address. Only the first key limit matching a client
+//!   applies, so specific patterns go first.
+//! - a provider: one bucket shared by all requests detected as that provider, e.g. to
+//!   stay under the provider's own rate limit
+//! - global: one bucket shared by every request
+//!
+//! A request takes a token from each limit applying to it and is rejected, taking
+//! none, if any bucket is empty.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateLimitError {
+    DuplicateName(String),
+    /// The rate is zero, negative or not a number
+    InvalidRate(String, f64),
+    /// The limit names both a key pattern and a provider
+    AmbiguousScope(String),
+}
+
+impl fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateLimitError::DuplicateName(name) => {
+                write!(f, "rate limit '{}' is declared twice", name)
+            }
+            RateLimitError::InvalidRate(name, rate) => write!(
+                f,
+                "rate limit '{}' must have a positive rate, got {}",
+                name, rate
+            ),
+            RateLimitError::AmbiguousScope(name) => write!(
+                f,
+                "rate limit '{}' applies to either a key or a provider, not both",
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RateLimitError {}
+
+/// A rate limit in the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Name used in logs and metrics
+    pub name: String,
+    /// Client key (or IP address) limited, or a prefix ending in `*`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Provider limited, e.g. `openai`; without `key` or `provider` the limit is global
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    pub requests_per_minute: f64,
+    /// Requests allowed at once after a quiet period; one second's worth by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+}
+
+/// What a rate limit applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitScope {
+    /// Each client matching the pattern (a prefix if it ends in `*`)
+    Key(String),
+    /// Requests to the provider
+    Provider(String),
+    Global,
+}
+
+impl fmt::Display for RateLimitScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateLimitScope::Key(pattern) => write!(f, "key:{}", pattern),
+            RateLimitScope::Provider(provider) => write!(f, "provider:{}", provider),
+            RateLimitScope::Global => write!(f, "global"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    pub name: String,
+    pub scope: RateLimitScope,
+    pub requests_per_sec: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// A limit of `requests_per_sec` with a burst of one second's worth
+    pub fn new(name: impl Into<String>, scope: RateLimitScope, requests_per_sec: f64) -> Self {
+        assert!(
+            requests_per_sec.is_finite() && requests_per_sec > 0.0,
+            "Rate limit must be positive"
+        );
+        Self {
+            name: name.into(),
+            scope,
+            requests_per_sec,
+            burst: requests_per_sec.ceil() as u32,
+        }
+    }
+
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    pub fn compile(config: &RateLimitConfig) -> Result<Self, RateLimitError> {
+        let scope = match (&config.key, &config.provider) {
+            (Some(_), Some(_)) => return Err(RateLimitError::AmbiguousScope(config.name.clone())),
+            (Some(key), None) => RateLimitScope::Key(key.clone()),
+            (None, Some(provider)) => RateLimitScope::Provider(provider.clone()),
+            (None, None) => RateLimitScope::Global,
+        };
+        let rate = config.requests_per_minute;
+        if !(rate.is_finite() && rate > 0.0) {
+            return Err(RateLimitError::InvalidRate(config.name.clone(), rate));
+        }
+        let limit = Self::new(config.name.clone(), scope, rate / 60.0);
+        Ok(match config.burst {
+            Some(burst) => limit.with_burst(burst),
+            None => limit,
+        })
+    }
+
+    fn matches_key(&self, client: &str) -> bool {
+        match &self.scope {
+            RateLimitScope::Key(pattern) => match pattern.strip_suffix('*') {
+                Some(prefix) => client.starts_with(prefix),
+                None => pattern == client,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Rate limits, key limits matched in order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimitPolicy {
+    pub limits: Vec<RateLimit>,
+}
+
+impl RateLimitPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limit(mut self, limit: RateLimit) -> Self {
+        self.limits.push(limit);
+        self
+    }
+
+    /// Policy of the rate limits declared in the config file
+    pub fn compile(limits: &[RateLimitConfig]) -> Result<Self, RateLimitError> {
+        let mut seen = HashSet::new();
+        let mut policy = Self::new();
+        for config in limits {
+            if !seen.insert(config.name.as_str()) {
+                return Err(RateLimitError::DuplicateName(config.name.clone()));
+            }
+            policy = policy.with_limit(RateLimit::compile(config)?);
+        }
+        Ok(policy)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    /// Limits applying to a request of `client` to `provider`: the first key limit
+    /// matching the client, the provider's limits and the global ones
+    pub fn limits_for(&self, client: &str, provider: &str) -> Vec<&RateLimit> {
+        let key = self.limits.iter().find(|limit| limit.matches_key(client));
+        key.into_iter()
+            .chain(self.limits.iter().filter(|limit| match &limit.scope {
+                RateLimitScope::Key(_) => false,
+                RateLimitScope::Provider(name) => name == provider,
+                RateLimitScope::Global => true,
+            }))
+            .collect()
+    }
+}
+
+/// A request over a rate limit
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimited {
+    /// Name of the limit exceeded
+    pub limit: String,
+    pub scope: String,
+    /// Time until the limit allows the request
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    /// `Retry-After` value: whole seconds, rounded up
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.requests_per_sec).min(f64::from(limit.burst));
+        self.updated = self.updated.max(now);
+    }
+}
+
+/// Rate limit policy and the token buckets of its limits.
+///
+/// Buckets are kept per limit name and client, so a reloaded policy keeps the tokens
+/// taken so far from the limits it still has.
+#[derive(Debug)]
+pub struct RateLimiter {
+    policy: RwLock<Arc<RateLimitPolicy>>,
+    buckets: Mutex<HashMap<(String, String), Bucket>>,
+    max_buckets: usize,
+}
+
+impl RateLimiter {
+    pub fn new(policy: RateLimitPolicy) -> Self {
+        Self {
+            policy: RwLock::new(Arc::new(policy)),
+            buckets: Mutex::new(HashMap::new()),
+            max_buckets: 100_000,
+        }
+    }
+
+    /// Most buckets kept (100,000 by default); beyond that, full buckets are dropped,
+    /// which forgets nothing since a new bucket starts full
+    pub fn with_max_buckets(mut self, max_buckets: usize) -> Self {
+        self.max_buckets = max_buckets;
+        self
+    }
+
+    pub fn policy(&self) -> Arc<RateLimitPolicy> {
+        Arc::clone(&self.policy.read().unwrap())
+    }
+
+    /// Apply `policy` to new requests
+    pub fn set_policy(&self, policy: RateLimitPolicy) {
+        *self.policy.write().unwrap() = Arc::new(policy);
+    }
+
+    /// Take a token from each limit applying to a request of `client` to `provider`,
+    /// or none if one of them is exhausted
+    pub fn check(&self, client: &str, provider: &str, now: Instant) -> Result<(), RateLimited> {
+        let policy = self.policy();
+        let limits = policy.limits_for(client, provider);
+        if limits.is_empty() {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= self.max_buckets {
+            Self::evict_full(&mut buckets, &policy, now);
+        }
+        let keys: Vec<(String, String)> = limits
+            .iter()
+            .map(|limit| {
+                let client = match limit.scope {
+                    RateLimitScope::Key(_) => client,
+                    _ => "",
+                };
+                (limit.name.clone(), client.to_string())
+            })
+            .collect();
+        for (limit, key) in limits.iter().zip(&keys) {
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: f64::from(limit.burst),
+                updated: now,
+            });
+            bucket.refill(limit, now);
+            if bucket.tokens < 1.0 {
+                return Err(RateLimited {
+                    limit: limit.name.clone(),
+                    scope: limit.scope.to_string(),
+                    retry_after: Duration::from_secs_f64(
+                        (1.0 - bucket.tokens) / limit.requests_per_sec,
+                    ),
+                });
+            }
+        }
+        for key in &keys {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the buckets that refilled completely
+    fn evict_full(
+        buckets: &mut HashMap<(String, String), Bucket>,
+        policy: &RateLimitPolicy,
+        now: Instant,
+    ) {
+        let limits: HashMap<&str, &RateLimit> = policy
+            .limits
+            .iter()
+            .map(|limit| (limit.name.as_str(), limit))
+            .collect();
+        buckets.retain(|(name, _), bucket| match limits.get(name.as_str()) {
+            Some(limit) => {
+                bucket.refill(limit, now);
+                bucket.tokens < f64::from(limit.burst)
+            }
+            None => false,
+        });
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimitPolicy::new())
+    }
+}
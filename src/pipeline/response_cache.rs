@@ -0,0 +1,237 @@
+use crate::pipeline::caller::Caller;
+use crate::pipeline::dedup::StoredResponse;
+use crate::pipeline::views::RequestView;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Request header overriding the cache for one request: `force` caches a sampled
+/// request, `bypass` skips the cache
+pub const CACHE_HEADER: &str = "x-langspec-cache";
+
+/// Largest request body looked up in the cache
+pub const MAX_REQUEST_BYTES: usize = 64 * 1024;
+
+/// Exact-match caching of completions.
+///
+/// Identical non-streaming requests (same caller, path, model, messages and parameters,
+/// whatever their JSON key order) are answered from memory within `ttl` instead of being
+/// sent upstream again. Requests of anonymous callers are never cached. Sampled requests (`temperature` above 0, or unset, which
+/// providers default to 1) are expected to vary and bypass the cache unless
+/// `cache_sampled` is set or the request asks for it with `X-Langspec-Cache: force`.
+#[derive(Debug, Clone)]
+pub struct ResponseCacheConfig {
+    /// How long a response is served from the cache
+    pub ttl: Duration,
+    /// Most responses kept; the oldest are dropped beyond it
+    pub max_entries: usize,
+    /// Responses with larger bodies are not cached
+    pub max_body_bytes: usize,
+    /// Also cache requests with `temperature` above 0
+    pub cache_sampled: bool,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(300),
+            max_entries: 10_000,
+            max_body_bytes: 1024 * 1024,
+            cache_sampled: false,
+        }
+    }
+}
+
+impl ResponseCacheConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn with_max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    pub fn with_cache_sampled(mut self, cache_sampled: bool) -> Self {
+        self.cache_sampled = cache_sampled;
+        self
+    }
+}
+
+/// Why a request is not looked up in the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheBypass {
+    /// Not a JSON request naming a model
+    NotCacheable,
+    Stream,
+    /// `temperature` above 0 (or unset)
+    Sampled,
+    /// `X-Langspec-Cache: bypass`
+    Requested,
+    /// Neither authenticated nor presenting credentials
+    Anonymous,
+}
+
+impl CacheBypass {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheBypass::NotCacheable => "not_cacheable",
+            CacheBypass::Stream => "stream",
+            CacheBypass::Sampled => "sampled",
+            CacheBypass::Requested => "requested",
+            CacheBypass::Anonymous => "anonymous",
+        }
+    }
+}
+
+struct CachedResponse {
+    response: Arc<StoredResponse>,
+    stored_at: Instant,
+}
+
+pub struct ResponseCache {
+    config: ResponseCacheConfig,
+    entries: Mutex<HashMap<String, CachedResponse>>,
+}
+
+impl ResponseCache {
+    pub fn new(config: ResponseCacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &ResponseCacheConfig {
+        &self.config
+    }
+
+    /// Cache key of a request with `body` made by `caller`: a hash of the caller, the
+    /// method, path and normalized body, or why the request bypasses the cache
+    pub fn key(
+        &self,
+        request_view: &RequestView,
+        caller: &Caller,
+        body: &[u8],
+    ) -> Result<String, CacheBypass> {
+        let header = request_view
+            .header(CACHE_HEADER)
+            .map(|value| value.trim().to_ascii_lowercase());
+        if header.as_deref() == Some("bypass") {
+            return Err(CacheBypass::Requested);
+        }
+        if caller.is_anonymous() {
+            return Err(CacheBypass::Anonymous);
+        }
+        let Ok(Value::Object(mut request)) = serde_json::from_slice::<Value>(body) else {
+            return Err(CacheBypass::NotCacheable);
+        };
+        if !request.get("model").is_some_and(Value::is_string) {
+            return Err(CacheBypass::NotCacheable);
+        }
+        if request.get("stream").and_then(Value::as_bool) == Some(true) {
+            return Err(CacheBypass::Stream);
+        }
+        let temperature = request
+            .get("temperature")
+            .map_or(Some(1.0), Value::as_f64)
+            .unwrap_or(1.0);
+        if temperature > 0.0 && !self.config.cache_sampled && header.as_deref() != Some("force") {
+            return Err(CacheBypass::Sampled);
+        }
+
+        // `0` and `0.0` are the same temperature
+        if request.contains_key("temperature") {
+            request.insert("temperature".to_string(), temperature.into());
+        }
+        // Fields that do not change the completion
+        request.remove("user");
+        request.remove("stream");
+        request.remove("stream_options");
+        // Objects serialize with sorted keys, so key order does not matter
+        let normalized = serde_json::to_string(&request).expect("JSON values serialize");
+        let identity = (
+            caller.to_string(),
+            request_view.method(),
+            request_view.path(),
+            normalized,
+        );
+        // Two differently seeded 64-bit hashes, so unrelated requests never collide
+        let hashes: Vec<u64> = [0u8, 1]
+            .iter()
+            .map(|seed| {
+                let mut hasher = DefaultHasher::new();
+                seed.hash(&mut hasher);
+                identity.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect();
+        Ok(format!("{:016x}{:016x}", hashes[0], hashes[1]))
+    }
+
+    /// The response cached under `key`, unless expired
+    pub fn get(&self, key: &str) -> Option<Arc<StoredResponse>> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|entry| entry.stored_at.elapsed() < self.config.ttl)
+            .map(|entry| Arc::clone(&entry.response))
+    }
+
+    /// Cache the response of the request with `key`
+    pub fn insert(&self, key: String, response: StoredResponse) {
+        if self.config.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.config.max_entries && !entries.contains_key(&key) {
+            let ttl = self.config.ttl;
+            entries.retain(|_, entry| entry.stored_at.elapsed() < ttl);
+            if entries.len() >= self.config.max_entries
+                && let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.stored_at)
+                    .map(|(key, _)| key.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            CachedResponse {
+                response: Arc::new(response),
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Responses cached, expired ones included until they are purged
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every cached response, returning how many there were
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let cleared = entries.len();
+        entries.clear();
+        cleared
+    }
+}
@@ -0,0 +1,367 @@
+//! Request body rewriting.
+//!
+//! Features that change what is sent upstream (model aliases, parameter clamps, system
+//! prompts, PII redaction, a deprecated model's replacement) implement [`BodyRewrite`]
+//! on the parsed JSON request instead of editing bytes themselves. [`BodyRewrites`]
+//! decodes the client's body once (`Content-Encoding: gzip` or `deflate`), runs every
+//! rewrite, and serializes the result only when one changed it; [`set_body_headers`]
+//! then fixes the framing headers of the upstream request. Rewritten bodies are always
+//! sent uncompressed, with a `Content-Length` instead of chunked encoding.
+
+use crate::pipeline::views::RequestView;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use pingora_http::RequestHeader;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+
+/// Largest request body read ahead to be rewritten
+pub const MAX_REQUEST_BYTES: usize = 64 * 1024;
+
+/// Largest request body rewritten, once decoded
+pub const MAX_DECODED_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum RewriteError {
+    /// A `Content-Encoding` other than gzip, deflate or identity
+    UnsupportedEncoding(String),
+    /// A compressed body that does not decode
+    Decode(std::io::Error),
+    /// Decoded body larger than [`MAX_DECODED_BYTES`]
+    TooLarge,
+    /// A rewrite refused the request
+    Rejected(&'static str, String),
+}
+
+impl RewriteError {
+    /// Status the request is failed with
+    pub fn status(&self) -> u16 {
+        match self {
+            RewriteError::UnsupportedEncoding(_) => 415,
+            RewriteError::Decode(_) | RewriteError::Rejected(..) => 400,
+            RewriteError::TooLarge => 413,
+        }
+    }
+
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RewriteError::UnsupportedEncoding(_) => "unsupported_encoding",
+            RewriteError::Decode(_) => "invalid_encoding",
+            RewriteError::TooLarge => "too_large",
+            RewriteError::Rejected(..) => "rejected",
+        }
+    }
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RewriteError::UnsupportedEncoding(encoding) => {
+                write!(
+                    f,
+                    "cannot rewrite a request body with encoding '{}'",
+                    encoding
+                )
+            }
+            RewriteError::Decode(e) => write!(f, "request body does not decode: {}", e),
+            RewriteError::TooLarge => write!(
+                f,
+                "request body is larger than {} bytes once decoded",
+                MAX_DECODED_BYTES
+            ),
+            RewriteError::Rejected(rewrite, reason) => {
+                write!(f, "request rejected by {}: {}", rewrite, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RewriteError {}
+
+/// A change to JSON request bodies before they are sent upstream
+pub trait BodyRewrite: Send + Sync {
+    /// Name in logs and metrics
+    fn name(&self) -> &'static str;
+
+    /// Change `request` in place, returning whether anything changed
+    fn rewrite(
+        &self,
+        request_view: &RequestView,
+        request: &mut Map<String, Value>,
+    ) -> Result<bool, RewriteError>;
+}
+
+/// A rewritten request body
+#[derive(Debug, Clone)]
+pub struct RewrittenBody {
+    /// Uncompressed JSON
+    pub body: Vec<u8>,
+    /// Names of the rewrites that changed it, in order
+    pub applied: Vec<&'static str>,
+}
+
+/// Rewrites applied in order to every JSON request body
+#[derive(Clone, Default)]
+pub struct BodyRewrites {
+    rewrites: Vec<Arc<dyn BodyRewrite>>,
+}
+
+impl BodyRewrites {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, rewrite: impl BodyRewrite + 'static) -> Self {
+        self.rewrites.push(Arc::new(rewrite));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rewrites.is_empty()
+    }
+
+    /// Names of the rewrites, in order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.rewrites.iter().map(|rewrite| rewrite.name()).collect()
+    }
+
+    /// Run the rewrites over `body`, sent with `content_encoding`. `None` when the body
+    /// is not a JSON object or no rewrite changed it, so it is sent as received.
+    pub fn apply(
+        &self,
+        request_view: &RequestView,
+        body: &[u8],
+        content_encoding: Option<&str>,
+    ) -> Result<Option<RewrittenBody>, RewriteError> {
+        if self.rewrites.is_empty() {
+            return Ok(None);
+        }
+        let decoded = decode_body(body, content_encoding)?;
+        let Ok(Value::Object(mut request)) = serde_json::from_slice::<Value>(&decoded) else {
+            return Ok(None);
+        };
+        let mut applied = Vec::new();
+        for rewrite in &self.rewrites {
+            if rewrite.rewrite(request_view, &mut request)? {
+                applied.push(rewrite.name());
+            }
+        }
+        if applied.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(RewrittenBody {
+            body: serde_json::to_vec(&request).expect("JSON values serialize"),
+            applied,
+        }))
+    }
+}
+
+/// A request body without its `Content-Encoding`, up to [`MAX_DECODED_BYTES`]
+pub fn decode_body<'a>(
+    body: &'a [u8],
+    content_encoding: Option<&str>,
+) -> Result<Cow<'a, [u8]>, RewriteError> {
+    let encoding = content_encoding.map(|encoding| encoding.trim().to_ascii_lowercase());
+    let reader: Box<dyn Read + 'a> = match encoding.as_deref() {
+        None | Some("") | Some("identity") => {
+            if body.len() > MAX_DECODED_BYTES {
+                return Err(RewriteError::TooLarge);
+            }
+            return Ok(Cow::Borrowed(body));
+        }
+        Some("gzip" | "x-gzip") => Box::new(GzDecoder::new(body)),
+        Some("deflate") => Box::new(ZlibDecoder::new(body)),
+        Some(_) => {
+            return Err(RewriteError::UnsupportedEncoding(
+                content_encoding.unwrap_or_default().trim().to_string(),
+            ));
+        }
+    };
+    let mut decoded = Vec::new();
+    reader
+        .take(MAX_DECODED_BYTES as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(RewriteError::Decode)?;
+    if decoded.len() > MAX_DECODED_BYTES {
+        return Err(RewriteError::TooLarge);
+    }
+    Ok(Cow::Owned(decoded))
+}
+
+/// Frame an upstream request for a replaced body of `len` bytes: uncompressed, with a
+/// `Content-Length` instead of chunked encoding
+pub fn set_body_headers(request: &mut RequestHeader, len: usize) -> pingora_error::Result<()> {
+    request.remove_header(&http::header::TRANSFER_ENCODING);
+    request.remove_header(&http::header::CONTENT_ENCODING);
+    request.insert_header(http::header::CONTENT_LENGTH, len)
+}
+
+/// Send every request to one model, e.g. a deprecated model's replacement
+pub struct SetModel {
+    model: String,
+}
+
+impl SetModel {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+        }
+    }
+}
+
+impl BodyRewrite for SetModel {
+    fn name(&self) -> &'static str {
+        "set_model"
+    }
+
+    fn rewrite(
+        &self,
+        _request_view: &RequestView,
+        request: &mut Map<String, Value>,
+    ) -> Result<bool, RewriteError> {
+        if request.get("model").and_then(Value::as_str) == Some(self.model.as_str()) {
+            return Ok(false);
+        }
+        request.insert("model".into(), self.model.as_str().into());
+        Ok(true)
+    }
+}
+
+/// Model names clients may use in place of the model they stand for
+#[derive(Debug, Clone, Default)]
+pub struct ModelAliases {
+    aliases: HashMap<String, String>,
+}
+
+impl ModelAliases {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_alias(mut self, alias: impl Into<String>, model: impl Into<String>) -> Self {
+        self.aliases.insert(alias.into(), model.into());
+        self
+    }
+}
+
+impl BodyRewrite for ModelAliases {
+    fn name(&self) -> &'static str {
+        "model_alias"
+    }
+
+    fn rewrite(
+        &self,
+        _request_view: &RequestView,
+        request: &mut Map<String, Value>,
+    ) -> Result<bool, RewriteError> {
+        let Some(model) = request
+            .get("model")
+            .and_then(Value::as_str)
+            .and_then(|alias| self.aliases.get(alias))
+        else {
+            return Ok(false);
+        };
+        request.insert("model".into(), model.as_str().into());
+        Ok(true)
+    }
+}
+
+/// Keep a numeric top-level parameter within bounds, e.g. `max_tokens` at most 4096.
+/// Requests without the parameter are left alone.
+#[derive(Debug, Clone)]
+pub struct ClampParameter {
+    name: String,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl ClampParameter {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            min: None,
+            max: None,
+        }
+    }
+
+    pub fn with_min(mut self, min: f64) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    pub fn with_max(mut self, max: f64) -> Self {
+        self.max = Some(max);
+        self
+    }
+}
+
+impl BodyRewrite for ClampParameter {
+    fn name(&self) -> &'static str {
+        "clamp_parameter"
+    }
+
+    fn rewrite(
+        &self,
+        _request_view: &RequestView,
+        request: &mut Map<String, Value>,
+    ) -> Result<bool, RewriteError> {
+        let Some(value) = request.get(&self.name) else {
+            return Ok(false);
+        };
+        let Some(number) = value.as_f64() else {
+            return Err(RewriteError::Rejected(
+                self.name(),
+                format!("'{}' is not a number", self.name),
+            ));
+        };
+        let clamped = match (self.min, self.max) {
+            (Some(min), _) if number < min => min,
+            (_, Some(max)) if number > max => max,
+            _ => return Ok(false),
+        };
+        // Integer parameters (`max_tokens`, `n`) stay integers
+        let clamped = match value.is_f64() {
+            false if clamped.fract() == 0.0 => Value::from(clamped as i64),
+            _ => Value::from(clamped),
+        };
+        request.insert(self.name.clone(), clamped);
+        Ok(true)
+    }
+}
+
+/// Put a system message ahead of the conversation of Chat Completions requests
+#[derive(Debug, Clone)]
+pub struct SystemPrompt {
+    content: String,
+}
+
+impl SystemPrompt {
+    pub fn new(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+        }
+    }
+}
+
+impl BodyRewrite for SystemPrompt {
+    fn name(&self) -> &'static str {
+        "system_prompt"
+    }
+
+    fn rewrite(
+        &self,
+        _request_view: &RequestView,
+        request: &mut Map<String, Value>,
+    ) -> Result<bool, RewriteError> {
+        let Some(Value::Array(messages)) = request.get_mut("messages") else {
+            return Ok(false);
+        };
+        let message = serde_json::json!({"role": "system", "content": self.content});
+        messages.insert(0, message);
+        Ok(true)
+    }
+}
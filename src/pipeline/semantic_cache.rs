@@ -0,0 +1,350 @@
+use crate::pipeline::caller::Caller;
+use crate::pipeline::dedup::StoredResponse;
+use crate::pipeline::response_cache::{CACHE_HEADER, CacheBypass};
+use crate::pipeline::usage::prompt_text;
+use crate::pipeline::views::RequestView;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Similarity-based caching of completions.
+///
+/// The conversation of a non-streaming request is embedded with an OpenAI-compatible
+/// embeddings endpoint, and a cached response is served when a request with the same
+/// caller, path, model, parameters and system prompt had a conversation whose
+/// embedding has at least `threshold` cosine similarity. Sampled requests and anonymous
+/// callers bypass the cache as they do the exact-match response cache.
+#[derive(Clone)]
+pub struct SemanticCacheConfig {
+    /// Embeddings endpoint, e.g. `https://api.openai.com/v1/embeddings`
+    pub embeddings_url: String,
+    pub embeddings_model: String,
+    /// Sent as a bearer token to the embeddings endpoint
+    pub embeddings_api_key: Option<String>,
+    /// How long an embeddings request may take before the request skips the cache
+    pub embeddings_timeout: Duration,
+    /// Least cosine similarity (up to 1.0) of a cached conversation to be served
+    pub threshold: f32,
+    /// How long a response is served from the cache
+    pub ttl: Duration,
+    /// Most responses kept; the oldest are dropped beyond it. Lookups compare against
+    /// every entry of the request's partition.
+    pub max_entries: usize,
+    /// Responses with larger bodies are not cached
+    pub max_body_bytes: usize,
+    /// Also cache requests with `temperature` above 0
+    pub cache_sampled: bool,
+}
+
+impl fmt::Debug for SemanticCacheConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SemanticCacheConfig")
+            .field("embeddings_url", &self.embeddings_url)
+            .field("embeddings_model", &self.embeddings_model)
+            .field("embeddings_timeout", &self.embeddings_timeout)
+            .field("threshold", &self.threshold)
+            .field("ttl", &self.ttl)
+            .field("max_entries", &self.max_entries)
+            .field("max_body_bytes", &self.max_body_bytes)
+            .field("cache_sampled", &self.cache_sampled)
+            .finish()
+    }
+}
+
+impl SemanticCacheConfig {
+    pub fn new(embeddings_url: impl Into<String>) -> Self {
+        Self {
+            embeddings_url: embeddings_url.into(),
+            embeddings_model: "text-embedding-3-small".to_string(),
+            embeddings_api_key: None,
+            embeddings_timeout: Duration::from_secs(2),
+            threshold: 0.95,
+            ttl: Duration::from_secs(3600),
+            max_entries: 2_000,
+            max_body_bytes: 1024 * 1024,
+            cache_sampled: false,
+        }
+    }
+
+    pub fn with_embeddings_model(mut self, model: impl Into<String>) -> Self {
+        self.embeddings_model = model.into();
+        self
+    }
+
+    pub fn with_embeddings_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.embeddings_api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn with_embeddings_timeout(mut self, timeout: Duration) -> Self {
+        self.embeddings_timeout = timeout;
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn with_max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    pub fn with_cache_sampled(mut self, cache_sampled: bool) -> Self {
+        self.cache_sampled = cache_sampled;
+        self
+    }
+}
+
+/// What a request is looked up by: the hash of everything that must match exactly,
+/// and the conversation compared by meaning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SemanticPrompt {
+    pub partition: String,
+    pub text: String,
+}
+
+/// A request that missed the cache, with the embedding its response is cached under
+#[derive(Debug, Clone)]
+pub struct SemanticKey {
+    pub partition: String,
+    pub embedding: Vec<f32>,
+}
+
+/// A cached response close enough to a request
+#[derive(Debug, Clone)]
+pub struct SemanticHit {
+    pub response: Arc<StoredResponse>,
+    pub similarity: f32,
+}
+
+/// Message roles that instruct the model rather than converse with it; they are part
+/// of the partition, so a different system prompt never shares responses
+const INSTRUCTION_ROLES: &[&str] = &["system", "developer"];
+
+struct SemanticEntry {
+    partition: String,
+    /// Unit length, so cosine similarity is a dot product
+    embedding: Vec<f32>,
+    response: Arc<StoredResponse>,
+    stored_at: Instant,
+}
+
+pub struct SemanticCache {
+    config: SemanticCacheConfig,
+    entries: Mutex<Vec<SemanticEntry>>,
+}
+
+impl SemanticCache {
+    pub fn new(config: SemanticCacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn config(&self) -> &SemanticCacheConfig {
+        &self.config
+    }
+
+    /// Partition and conversation of a request with `body` made by `caller`, or why the
+    /// request bypasses the cache
+    pub fn prompt(
+        &self,
+        request_view: &RequestView,
+        caller: &Caller,
+        body: &[u8],
+    ) -> Result<SemanticPrompt, CacheBypass> {
+        let header = request_view
+            .header(CACHE_HEADER)
+            .map(|value| value.trim().to_ascii_lowercase());
+        if header.as_deref() == Some("bypass") {
+            return Err(CacheBypass::Requested);
+        }
+        if caller.is_anonymous() {
+            return Err(CacheBypass::Anonymous);
+        }
+        let Ok(Value::Object(mut request)) = serde_json::from_slice::<Value>(body) else {
+            return Err(CacheBypass::NotCacheable);
+        };
+        if !request.get("model").is_some_and(Value::is_string) {
+            return Err(CacheBypass::NotCacheable);
+        }
+        if request.get("stream").and_then(Value::as_bool) == Some(true) {
+            return Err(CacheBypass::Stream);
+        }
+        let temperature = request
+            .get("temperature")
+            .map_or(Some(1.0), Value::as_f64)
+            .unwrap_or(1.0);
+        if temperature > 0.0 && !self.config.cache_sampled && header.as_deref() != Some("force") {
+            return Err(CacheBypass::Sampled);
+        }
+
+        let mut conversation = Map::new();
+        if let Some(Value::Array(messages)) = request.remove("messages") {
+            let (instructions, messages): (Vec<Value>, Vec<Value>) =
+                messages.into_iter().partition(|message| {
+                    message
+                        .get("role")
+                        .and_then(Value::as_str)
+                        .is_some_and(|role| INSTRUCTION_ROLES.contains(&role))
+                });
+            request.insert("messages".to_string(), Value::Array(instructions));
+            conversation.insert("messages".to_string(), Value::Array(messages));
+        }
+        for key in ["prompt", "input"] {
+            if let Some(value) = request.remove(key) {
+                conversation.insert(key.to_string(), value);
+            }
+        }
+        let text = prompt_text(&serde_json::to_vec(&conversation).expect("JSON values serialize"));
+        if text.trim().is_empty() {
+            return Err(CacheBypass::NotCacheable);
+        }
+
+        if request.contains_key("temperature") {
+            request.insert("temperature".to_string(), temperature.into());
+        }
+        request.remove("user");
+        request.remove("stream");
+        request.remove("stream_options");
+        let normalized = serde_json::to_string(&request).expect("JSON values serialize");
+        let identity = (
+            caller.to_string(),
+            request_view.method(),
+            request_view.path(),
+            normalized,
+        );
+        // Two differently seeded 64-bit hashes, so callers' partitions never collide
+        let hashes: Vec<u64> = [0u8, 1]
+            .iter()
+            .map(|seed| {
+                let mut hasher = DefaultHasher::new();
+                seed.hash(&mut hasher);
+                identity.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect();
+        Ok(SemanticPrompt {
+            partition: format!("{:016x}{:016x}", hashes[0], hashes[1]),
+            text,
+        })
+    }
+
+    /// Body of the embeddings request for a conversation
+    pub fn embeddings_request(&self, text: &str) -> Vec<u8> {
+        let request = serde_json::json!({
+            "model": self.config.embeddings_model,
+            "input": text,
+        });
+        serde_json::to_vec(&request).expect("JSON values serialize")
+    }
+
+    /// The cached response in `partition` most similar to `embedding`, if at least as
+    /// similar as the threshold and not expired
+    pub fn lookup(&self, partition: &str, embedding: &[f32]) -> Option<SemanticHit> {
+        let embedding = normalized(embedding)?;
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .filter(|entry| {
+                entry.partition == partition && entry.stored_at.elapsed() < self.config.ttl
+            })
+            .filter_map(|entry| {
+                let similarity = dot(&entry.embedding, &embedding)?;
+                (similarity >= self.config.threshold).then(|| SemanticHit {
+                    response: Arc::clone(&entry.response),
+                    similarity,
+                })
+            })
+            .max_by(|a, b| a.similarity.total_cmp(&b.similarity))
+    }
+
+    /// Cache the response of a request that missed the cache
+    pub fn insert(&self, key: SemanticKey, response: StoredResponse) {
+        let Some(embedding) = normalized(&key.embedding) else {
+            return;
+        };
+        if self.config.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.config.max_entries {
+            let ttl = self.config.ttl;
+            entries.retain(|entry| entry.stored_at.elapsed() < ttl);
+        }
+        // Entries are kept oldest first
+        if entries.len() >= self.config.max_entries {
+            let excess = entries.len() + 1 - self.config.max_entries;
+            entries.drain(..excess);
+        }
+        entries.push(SemanticEntry {
+            partition: key.partition,
+            embedding,
+            response: Arc::new(response),
+            stored_at: Instant::now(),
+        });
+    }
+
+    /// Responses cached, expired ones included until they are purged
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every cached response, returning how many there were
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let cleared = entries.len();
+        entries.clear();
+        cleared
+    }
+}
+
+/// Embedding of the first input in an OpenAI-compatible embeddings response
+pub fn parse_embedding(body: &[u8]) -> Option<Vec<f32>> {
+    let response: Value = serde_json::from_slice(body).ok()?;
+    response
+        .get("data")?
+        .get(0)?
+        .get("embedding")?
+        .as_array()?
+        .iter()
+        .map(|value| value.as_f64().map(|value| value as f32))
+        .collect()
+}
+
+/// Cosine similarity of two embeddings; 0 when their dimensions differ or either is
+/// all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    match (normalized(a), normalized(b)) {
+        (Some(a), Some(b)) => dot(&a, &b).unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
+
+fn normalized(embedding: &[f32]) -> Option<Vec<f32>> {
+    let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    (norm > 0.0 && norm.is_finite()).then(|| embedding.iter().map(|x| x / norm).collect())
+}
+
+fn dot(a: &[f32], b: &[f32]) -> Option<f32> {
+    (a.len() == b.len()).then(|| a.iter().zip(b).map(|(x, y)| x * y).sum())
+}
@@ -0,0 +1,221 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Counts tokens in text, for estimating usage the provider does not report.
+///
+/// [`BpeTokenizer`] counts as OpenAI models do given a tiktoken vocabulary; implement
+/// this to plug in another model's tokenizer. [`ApproximateTokenizer`] is used when
+/// none is configured.
+pub trait Tokenizer: Send + Sync {
+    /// Name reported alongside estimates, e.g. `approximate` or `cl100k_base`
+    fn name(&self) -> &'static str;
+
+    fn count_tokens(&self, text: &str) -> u64;
+}
+
+/// Character-ratio estimate (~4 characters per token for English text on common BPE
+/// vocabularies). Non-empty text always counts as at least one token, so counting
+/// streamed deltas one by one stays close to the provider's count.
+#[derive(Debug, Clone, Copy)]
+pub struct ApproximateTokenizer {
+    chars_per_token: f64,
+}
+
+impl ApproximateTokenizer {
+    pub const DEFAULT_CHARS_PER_TOKEN: f64 = 4.0;
+
+    pub fn new() -> Self {
+        Self {
+            chars_per_token: Self::DEFAULT_CHARS_PER_TOKEN,
+        }
+    }
+
+    /// Tune the ratio, e.g. lower for code or CJK-heavy traffic
+    pub fn with_chars_per_token(mut self, chars_per_token: f64) -> Self {
+        assert!(chars_per_token > 0.0, "chars_per_token must be positive");
+        self.chars_per_token = chars_per_token;
+        self
+    }
+}
+
+impl Default for ApproximateTokenizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tokenizer for ApproximateTokenizer {
+    fn name(&self) -> &'static str {
+        "approximate"
+    }
+
+    fn count_tokens(&self, text: &str) -> u64 {
+        if text.is_empty() {
+            return 0;
+        }
+        let chars = text.chars().count() as f64;
+        ((chars / self.chars_per_token).ceil() as u64).max(1)
+    }
+}
+
+#[derive(Debug)]
+pub enum TokenizerError {
+    Io(std::io::Error),
+    /// A vocabulary line is not `<base64 token> <rank>`
+    InvalidVocabulary {
+        line: usize,
+    },
+    InvalidPattern(regex::Error),
+}
+
+impl fmt::Display for TokenizerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenizerError::Io(e) => write!(f, "failed to read tokenizer vocabulary: {}", e),
+            TokenizerError::InvalidVocabulary { line } => {
+                write!(f, "invalid tokenizer vocabulary at line {}", line)
+            }
+            TokenizerError::InvalidPattern(e) => write!(f, "invalid pre-tokenizer pattern: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TokenizerError {}
+
+/// Byte-pair encoding tokenizer with a tiktoken vocabulary (`cl100k_base.tiktoken`,
+/// `o200k_base.tiktoken`, ...), counting tokens as OpenAI models do.
+///
+/// Text is split into pieces with the vocabulary's pre-tokenizer pattern, then each
+/// piece's bytes are merged pairwise in rank order until no ranked pair remains.
+/// Vocabularies are not bundled; load the file the model's encoding uses.
+pub struct BpeTokenizer {
+    name: &'static str,
+    ranks: HashMap<Vec<u8>, u32>,
+    pattern: Regex,
+}
+
+impl BpeTokenizer {
+    /// Pre-tokenizer of `cl100k_base` (GPT-4, GPT-3.5). tiktoken's pattern also has
+    /// `\s+(?!\S)`, which leaves the last space of a run to the word after it; the
+    /// regex crate has no lookahead, so [`pieces`](Self::pieces) does that instead.
+    pub const CL100K_PATTERN: &'static str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+";
+
+    /// Tokenizer from the contents of a `.tiktoken` file: one `<base64 token> <rank>`
+    /// per line; uses the `cl100k_base` pre-tokenizer
+    pub fn from_tiktoken(name: &'static str, vocabulary: &str) -> Result<Self, TokenizerError> {
+        let ranks = vocabulary
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                let invalid = || TokenizerError::InvalidVocabulary { line: index + 1 };
+                let (token, rank) = line.trim().split_once(' ').ok_or_else(invalid)?;
+                let token = BASE64.decode(token).map_err(|_| invalid())?;
+                let rank = rank.parse::<u32>().map_err(|_| invalid())?;
+                Ok((token, rank))
+            })
+            .collect::<Result<_, TokenizerError>>()?;
+        Ok(Self {
+            name,
+            ranks,
+            pattern: Regex::new(Self::CL100K_PATTERN).expect("pattern is valid"),
+        })
+    }
+
+    pub fn from_tiktoken_file(
+        name: &'static str,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, TokenizerError> {
+        let vocabulary = std::fs::read_to_string(path).map_err(TokenizerError::Io)?;
+        Self::from_tiktoken(name, &vocabulary)
+    }
+
+    /// Split text with another pre-tokenizer pattern (regex crate syntax)
+    pub fn with_pattern(mut self, pattern: &str) -> Result<Self, TokenizerError> {
+        self.pattern = Regex::new(pattern).map_err(TokenizerError::InvalidPattern)?;
+        Ok(self)
+    }
+
+    /// Pieces the text is split into before merging
+    fn pieces<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while let Some(found) = self.pattern.find_at(text, start) {
+            if found.is_empty() {
+                break;
+            }
+            // Text the pattern skipped is a piece of its own
+            if found.start() > start {
+                pieces.push(&text[start..found.start()]);
+            }
+            let piece = found.as_str();
+            let mut end = found.end();
+            // `\s+(?!\S)`: a run of spaces before a word leaves its last space to it
+            if piece.chars().nth(1).is_some()
+                && piece.chars().all(char::is_whitespace)
+                && !piece.ends_with(['\r', '\n'])
+                && text[end..]
+                    .chars()
+                    .next()
+                    .is_some_and(|c| !c.is_whitespace())
+            {
+                end -= piece.chars().next_back().map_or(0, char::len_utf8);
+            }
+            pieces.push(&text[found.start()..end]);
+            start = end;
+        }
+        if start < text.len() {
+            pieces.push(&text[start..]);
+        }
+        pieces
+    }
+
+    /// Tokens of one piece: its bytes merged pairwise, lowest rank first
+    fn count_piece(&self, piece: &[u8]) -> u64 {
+        if piece.len() <= 1 || self.ranks.contains_key(piece) {
+            return piece.len().min(1) as u64;
+        }
+        // Token boundaries; merging removes the boundary between two adjacent tokens
+        let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+        loop {
+            let best = (0..bounds.len().saturating_sub(2))
+                .filter_map(|i| {
+                    let rank = self.ranks.get(&piece[bounds[i]..bounds[i + 2]])?;
+                    Some((*rank, i))
+                })
+                .min();
+            match best {
+                Some((_, i)) => {
+                    bounds.remove(i + 1);
+                }
+                None => return bounds.len() as u64 - 1,
+            }
+        }
+    }
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn count_tokens(&self, text: &str) -> u64 {
+        self.pieces(text)
+            .iter()
+            .map(|piece| self.count_piece(piece.as_bytes()))
+            .sum()
+    }
+}
+
+impl fmt::Debug for BpeTokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BpeTokenizer")
+            .field("name", &self.name)
+            .field("vocabulary", &self.ranks.len())
+            .finish()
+    }
+}
@@ -0,0 +1,493 @@
+//! Response transformations.
+//!
+//! A transform reshapes the JSON body of non-streaming responses to matching requests,
+//! for clients expecting a house-standard shape rather than the provider's. Its steps
+//! run in order on the parsed body:
+//! - `remove: $.path` drops the fields or elements at a path
+//! - `rename: {from: $.path, to: $.path}` moves a value
+//! - `set: {path: $.path, value: ...}` sets a value, creating missing objects on the
+//!   way; strings in the value are templates of the request's `{tenant}`,
+//!   `{provider}`, `{model}`, `{client_ip}` and `{now_rfc3339}`
+//! - `wrap: field` puts the whole body under `field` of a new object, the envelope
+//!   later `set` steps add gateway metadata to
+//!
+//! Paths are `$` for the body, followed by `.field`, `[index]` or `[*]` for every
+//! element of an array (or value of an object), e.g. `$.choices[*].logprobs`.
+
+use crate::proxy::template::{Template, TemplateError, TemplateVars};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// Largest response body transformed; larger ones are sent as received
+pub const MAX_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransformError {
+    DuplicateName(String),
+    /// The request path does not start with `/`
+    InvalidPath(String, String),
+    /// A step's JSON path does not parse, with why
+    InvalidJsonPath(String, String, String),
+    /// A step removes or moves the whole body, or moves from or to several places
+    InvalidStep(String, String),
+    InvalidTemplate(String, TemplateError),
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransformError::DuplicateName(name) => {
+                write!(f, "response transform '{}' is declared twice", name)
+            }
+            TransformError::InvalidPath(name, path) => write!(
+                f,
+                "response transform '{}' has an invalid path '{}'",
+                name, path
+            ),
+            TransformError::InvalidJsonPath(name, path, reason) => write!(
+                f,
+                "response transform '{}' has an invalid JSON path '{}': {}",
+                name, path, reason
+            ),
+            TransformError::InvalidStep(name, reason) => {
+                write!(f, "response transform '{}': {}", name, reason)
+            }
+            TransformError::InvalidTemplate(name, e) => {
+                write!(f, "response transform '{}': {}", name, e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransformError {}
+
+/// A response transform in the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResponseTransformConfig {
+    /// Name used in logs and metrics
+    pub name: String,
+    /// Request path whose responses are transformed, or a prefix ending in `*`
+    pub path: String,
+    /// Tenant whose responses are transformed; any tenant when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Model, or a prefix ending in `*`; any model when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub steps: Vec<TransformStepConfig>,
+}
+
+/// A step of a response transform in the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum TransformStepConfig {
+    Remove(String),
+    Rename { from: String, to: String },
+    Set { path: String, value: Value },
+    Wrap(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PathSegment {
+    Field(String),
+    Index(usize),
+    /// Every element of an array or value of an object
+    Every,
+}
+
+/// A location in a JSON body, e.g. `$.choices[0].message`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    segments: Vec<PathSegment>,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let mut rest = path
+            .trim()
+            .strip_prefix('$')
+            .ok_or("paths start with '$'")?;
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                if end == 0 {
+                    return Err("empty field name".to_string());
+                }
+                segments.push(PathSegment::Field(after[..end].to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let (index, after) = after.split_once(']').ok_or("unterminated '['")?;
+                segments.push(match index.trim() {
+                    "*" => PathSegment::Every,
+                    index => PathSegment::Index(
+                        index
+                            .parse()
+                            .map_err(|_| format!("invalid index '{}'", index))?,
+                    ),
+                });
+                rest = after;
+            } else {
+                return Err(format!("unexpected '{}'", rest));
+            }
+        }
+        Ok(Self { segments })
+    }
+
+    /// Whether the path is the whole body
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Whether the path can match several places
+    pub fn has_wildcard(&self) -> bool {
+        self.segments.contains(&PathSegment::Every)
+    }
+
+    /// Remove what the path matches, returning whether anything was removed
+    pub fn remove(&self, body: &mut Value) -> bool {
+        let Some((last, parents)) = self.segments.split_last() else {
+            return false;
+        };
+        let mut removed = false;
+        for parent in matches(body, parents, false) {
+            removed |= match (last, parent) {
+                (PathSegment::Field(name), Value::Object(map)) => map.remove(name).is_some(),
+                (PathSegment::Index(index), Value::Array(array)) if *index < array.len() => {
+                    array.remove(*index);
+                    true
+                }
+                (PathSegment::Every, Value::Array(array)) => {
+                    let any = !array.is_empty();
+                    array.clear();
+                    any
+                }
+                (PathSegment::Every, Value::Object(map)) => {
+                    let any = !map.is_empty();
+                    map.clear();
+                    any
+                }
+                _ => false,
+            };
+        }
+        removed
+    }
+
+    /// Remove and return the value at a path without wildcards
+    pub fn take(&self, body: &mut Value) -> Option<Value> {
+        let (last, parents) = self.segments.split_last()?;
+        let parent = matches(body, parents, false).into_iter().next()?;
+        match (last, parent) {
+            (PathSegment::Field(name), Value::Object(map)) => map.remove(name),
+            (PathSegment::Index(index), Value::Array(array)) if *index < array.len() => {
+                Some(array.remove(*index))
+            }
+            _ => None,
+        }
+    }
+
+    /// Set what the path matches to `value`. Missing fields are created, as objects
+    /// on the way; missing array elements are not.
+    pub fn set(&self, body: &mut Value, value: Value) {
+        let Some((last, parents)) = self.segments.split_last() else {
+            *body = value;
+            return;
+        };
+        let create = matches!(last, PathSegment::Field(_));
+        for parent in matches(body, parents, create) {
+            match last {
+                PathSegment::Field(name) => {
+                    if parent.is_null() {
+                        *parent = Value::Object(Map::new());
+                    }
+                    if let Value::Object(map) = parent {
+                        map.insert(name.clone(), value.clone());
+                    }
+                }
+                PathSegment::Index(index) => {
+                    if let Some(slot) = parent.as_array_mut().and_then(|a| a.get_mut(*index)) {
+                        *slot = value.clone();
+                    }
+                }
+                PathSegment::Every => match parent {
+                    Value::Array(array) => array.iter_mut().for_each(|slot| *slot = value.clone()),
+                    Value::Object(map) => map.values_mut().for_each(|slot| *slot = value.clone()),
+                    _ => {}
+                },
+            }
+        }
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("$")?;
+        for segment in &self.segments {
+            match segment {
+                PathSegment::Field(name) => write!(f, ".{}", name)?,
+                PathSegment::Index(index) => write!(f, "[{}]", index)?,
+                PathSegment::Every => f.write_str("[*]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Values at `segments` below `value`; with `create`, missing fields of objects (or
+/// nulls) are created as nulls, which callers fill in
+fn matches<'v>(value: &'v mut Value, segments: &[PathSegment], create: bool) -> Vec<&'v mut Value> {
+    let Some((first, rest)) = segments.split_first() else {
+        return vec![value];
+    };
+    match first {
+        PathSegment::Field(name) => {
+            if create && value.is_null() {
+                *value = Value::Object(Map::new());
+            }
+            let Value::Object(map) = value else {
+                return Vec::new();
+            };
+            // Only fields can be created below a created field
+            let create_child = create && matches!(rest.first(), None | Some(PathSegment::Field(_)));
+            let child = match create_child {
+                true => Some(map.entry(name.clone()).or_insert(Value::Null)),
+                false => map.get_mut(name),
+            };
+            child.map_or_else(Vec::new, |child| matches(child, rest, create))
+        }
+        PathSegment::Index(index) => value
+            .as_array_mut()
+            .and_then(|array| array.get_mut(*index))
+            .map_or_else(Vec::new, |child| matches(child, rest, create)),
+        PathSegment::Every => match value {
+            Value::Array(array) => array
+                .iter_mut()
+                .flat_map(|child| matches(child, rest, create))
+                .collect(),
+            Value::Object(map) => map
+                .values_mut()
+                .flat_map(|child| matches(child, rest, create))
+                .collect(),
+            _ => Vec::new(),
+        },
+    }
+}
+
+/// A JSON value whose strings are templates
+#[derive(Debug, Clone, PartialEq)]
+enum ValueTemplate {
+    Text(Template),
+    Array(Vec<ValueTemplate>),
+    Object(Vec<(String, ValueTemplate)>),
+    Literal(Value),
+}
+
+impl ValueTemplate {
+    fn compile(value: &Value) -> Result<Self, TemplateError> {
+        Ok(match value {
+            Value::String(text) => ValueTemplate::Text(Template::parse(text)?),
+            Value::Array(items) => ValueTemplate::Array(
+                items
+                    .iter()
+                    .map(ValueTemplate::compile)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Object(map) => ValueTemplate::Object(
+                map.iter()
+                    .map(|(key, value)| Ok((key.clone(), ValueTemplate::compile(value)?)))
+                    .collect::<Result<_, _>>()?,
+            ),
+            value => ValueTemplate::Literal(value.clone()),
+        })
+    }
+
+    fn render(&self, vars: &TemplateVars) -> Value {
+        match self {
+            ValueTemplate::Text(template) => Value::String(template.render(vars)),
+            ValueTemplate::Array(items) => items.iter().map(|item| item.render(vars)).collect(),
+            ValueTemplate::Object(fields) => fields
+                .iter()
+                .map(|(key, value)| (key.clone(), value.render(vars)))
+                .collect::<Map<_, _>>()
+                .into(),
+            ValueTemplate::Literal(value) => value.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TransformStep {
+    Remove(JsonPath),
+    Rename {
+        from: JsonPath,
+        to: JsonPath,
+    },
+    Set {
+        path: JsonPath,
+        value: ValueTemplate,
+    },
+    Wrap(String),
+}
+
+/// Steps reshaping the responses of matching requests
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseTransform {
+    pub name: String,
+    /// Request path, or a prefix ending in `*`
+    pub path: String,
+    pub tenant: Option<String>,
+    /// Model name, or a prefix ending in `*`; `None` matches any model
+    pub model: Option<String>,
+    steps: Vec<TransformStep>,
+}
+
+impl ResponseTransform {
+    pub fn compile(config: &ResponseTransformConfig) -> Result<Self, TransformError> {
+        let name = &config.name;
+        if !config.path.starts_with('/') {
+            return Err(TransformError::InvalidPath(
+                name.clone(),
+                config.path.clone(),
+            ));
+        }
+        let path = |path: &str| {
+            JsonPath::parse(path)
+                .map_err(|e| TransformError::InvalidJsonPath(name.clone(), path.to_string(), e))
+        };
+        let invalid = |reason: &str| TransformError::InvalidStep(name.clone(), reason.to_string());
+        let mut steps = Vec::new();
+        for step in &config.steps {
+            steps.push(match step {
+                TransformStepConfig::Remove(remove) => {
+                    let remove = path(remove)?;
+                    if remove.is_root() {
+                        return Err(invalid("cannot remove the whole body"));
+                    }
+                    TransformStep::Remove(remove)
+                }
+                TransformStepConfig::Rename { from, to } => {
+                    let (from, to) = (path(from)?, path(to)?);
+                    if from.is_root() {
+                        return Err(invalid("cannot rename the whole body"));
+                    }
+                    if from.has_wildcard() || to.has_wildcard() {
+                        return Err(invalid("renames move one value, without '[*]'"));
+                    }
+                    TransformStep::Rename { from, to }
+                }
+                TransformStepConfig::Set { path: set, value } => TransformStep::Set {
+                    path: path(set)?,
+                    value: ValueTemplate::compile(value)
+                        .map_err(|e| TransformError::InvalidTemplate(name.clone(), e))?,
+                },
+                TransformStepConfig::Wrap(field) => {
+                    if field.is_empty() {
+                        return Err(invalid("wrap needs a field name"));
+                    }
+                    TransformStep::Wrap(field.clone())
+                }
+            });
+        }
+        Ok(Self {
+            name: name.clone(),
+            path: config.path.clone(),
+            tenant: config.tenant.clone(),
+            model: config.model.clone(),
+            steps,
+        })
+    }
+
+    pub fn matches(&self, path: &str, tenant: Option<&str>, model: Option<&str>) -> bool {
+        let path_matches = match self.path.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => self.path == path,
+        };
+        let model_matches = match (&self.model, model) {
+            (None, _) => true,
+            (Some(pattern), Some(model)) => match pattern.strip_suffix('*') {
+                Some(prefix) => model.starts_with(prefix),
+                None => pattern == model,
+            },
+            (Some(_), None) => false,
+        };
+        path_matches
+            && model_matches
+            && self
+                .tenant
+                .as_deref()
+                .is_none_or(|expected| tenant == Some(expected))
+    }
+
+    /// Run the steps over `body`, rendering templates with `vars`
+    pub fn apply(&self, body: &mut Value, vars: &TemplateVars) {
+        for step in &self.steps {
+            match step {
+                TransformStep::Remove(path) => {
+                    path.remove(body);
+                }
+                TransformStep::Rename { from, to } => {
+                    if let Some(value) = from.take(body) {
+                        to.set(body, value);
+                    }
+                }
+                TransformStep::Set { path, value } => path.set(body, value.render(vars)),
+                TransformStep::Wrap(field) => {
+                    let inner = body.take();
+                    *body = Value::Object(Map::from_iter([(field.clone(), inner)]));
+                }
+            }
+        }
+    }
+
+    /// Transform a response body, `None` when it is not JSON
+    pub fn apply_to_body(&self, body: &[u8], vars: &TemplateVars) -> Option<Vec<u8>> {
+        let mut body: Value = serde_json::from_slice(body).ok()?;
+        self.apply(&mut body, vars);
+        Some(serde_json::to_vec(&body).expect("JSON values serialize"))
+    }
+}
+
+/// Response transforms; the first matching a request applies
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResponseTransforms {
+    pub transforms: Vec<Arc<ResponseTransform>>,
+}
+
+impl ResponseTransforms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transforms declared in the config file
+    pub fn compile(configs: &[ResponseTransformConfig]) -> Result<Self, TransformError> {
+        let mut seen = HashSet::new();
+        let mut transforms = Vec::new();
+        for config in configs {
+            if !seen.insert(config.name.as_str()) {
+                return Err(TransformError::DuplicateName(config.name.clone()));
+            }
+            transforms.push(Arc::new(ResponseTransform::compile(config)?));
+        }
+        Ok(Self { transforms })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    pub fn lookup(
+        &self,
+        path: &str,
+        tenant: Option<&str>,
+        model: Option<&str>,
+    ) -> Option<Arc<ResponseTransform>> {
+        self.transforms
+            .iter()
+            .find(|transform| transform.matches(path, tenant, model))
+            .cloned()
+    }
+}
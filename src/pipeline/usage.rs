@@ -0,0 +1,524 @@
+use crate::pipeline::tokenizer::{ApproximateTokenizer, Tokenizer};
+use crate::provider::StreamFormat;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use regex::bytes::Regex;
+use serde_json::Value;
+use std::fmt;
+use std::sync::{Arc, LazyLock};
+
+/// Token usage of a single request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Counted by the gateway's tokenizer because the provider did not report
+    /// (all of) the usage
+    pub estimated: bool,
+}
+
+impl Usage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "prompt={} completion={}",
+            self.prompt_tokens, self.completion_tokens
+        )?;
+        if self.estimated {
+            write!(f, " (estimated)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Usage tracking for streamed and JSON responses.
+#[derive(Clone)]
+pub struct UsageConfig {
+    /// Counts tokens when the provider does not report usage
+    pub tokenizer: Arc<dyn Tokenizer>,
+    /// Request bodies are buffered up to this size to estimate prompt tokens; only
+    /// the prefix of a larger body is counted
+    pub max_request_bytes: usize,
+    /// JSON response bodies are buffered up to this size to read their `usage`; only
+    /// the end of a larger body is searched
+    pub max_response_bytes: usize,
+}
+
+impl UsageConfig {
+    pub fn new() -> Self {
+        Self {
+            tokenizer: Arc::new(ApproximateTokenizer::new()),
+            max_request_bytes: 1024 * 1024,
+            max_response_bytes: 1024 * 1024,
+        }
+    }
+
+    pub fn with_tokenizer(mut self, tokenizer: impl Tokenizer + 'static) -> Self {
+        self.tokenizer = Arc::new(tokenizer);
+        self
+    }
+}
+
+impl Default for UsageConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for UsageConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UsageConfig")
+            .field("tokenizer", &self.tokenizer.name())
+            .field("max_request_bytes", &self.max_request_bytes)
+            .field("max_response_bytes", &self.max_response_bytes)
+            .finish()
+    }
+}
+
+/// JSON keys whose string values are prompt text (messages, system prompts, inputs)
+pub(crate) const PROMPT_KEYS: &[&str] = &[
+    "content",
+    "text",
+    "prompt",
+    "input",
+    "message",
+    "preamble",
+    "system",
+    "instructions",
+];
+
+/// Prompt text of a request body: the strings under prompt-bearing keys of a JSON
+/// body (so model names, roles and parameters are not counted), or the whole body
+/// when it is not JSON.
+pub fn prompt_text(body: &[u8]) -> String {
+    json_prompt_text(body).unwrap_or_else(|| String::from_utf8_lossy(body).into_owned())
+}
+
+/// Prompt text of a JSON body, as [`prompt_text`]; `None` when the body is not JSON
+pub fn json_prompt_text(body: &[u8]) -> Option<String> {
+    fn collect<'a>(value: &'a Value, is_prompt: bool, out: &mut Vec<&'a str>) {
+        match value {
+            Value::String(text) if is_prompt => out.push(text),
+            Value::Array(items) => {
+                for item in items {
+                    collect(item, is_prompt, out);
+                }
+            }
+            Value::Object(fields) => {
+                for (key, value) in fields {
+                    collect(value, PROMPT_KEYS.contains(&key.as_str()), out);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let json = serde_json::from_slice::<Value>(body).ok()?;
+    let mut texts = Vec::new();
+    collect(&json, false, &mut texts);
+    Some(texts.join("\n"))
+}
+
+/// Request bodies are buffered up to this size to find the model when usage is not
+/// tracked
+pub const MODEL_BODY_BYTES: usize = 64 * 1024;
+
+/// A top-level `"model"` string preceded only by keys with scalar values
+static LEADING_MODEL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"^\s*\{\s*(?:"(?:[^"\\]|\\.)*"\s*:\s*(?:"(?:[^"\\]|\\.)*"|[^,{}\[\]"]*)\s*,\s*)*"model"\s*:\s*"((?:[^"\\]|\\.)*)""#,
+    )
+    .expect("pattern is valid")
+});
+
+/// Model named in a JSON request body. A body cut off at a buffering limit is not valid
+/// JSON; its model is still found when the field comes before any nested value, as
+/// clients usually send it.
+pub fn request_model(body: &[u8]) -> Option<String> {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(json) => json.get("model")?.as_str().map(str::to_string),
+        Err(_) => {
+            let model = LEADING_MODEL.captures(body)?.get(1)?;
+            String::from_utf8(model.as_bytes().to_vec()).ok()
+        }
+    }
+}
+
+/// Estimated prompt tokens of a request body
+pub fn estimate_prompt_tokens(tokenizer: &dyn Tokenizer, body: &[u8]) -> u64 {
+    tokenizer.count_tokens(&prompt_text(body))
+}
+
+/// Usage of a streamed response, fed chunk by chunk as it passes through.
+///
+/// Usage the provider reports in the stream (the final OpenAI chunk with
+/// `stream_options.include_usage`, Cohere `message-end`/`stream-end`, Bedrock Converse
+/// `metadata` events and invocation metrics, TGI `details`) wins; output tokens are
+/// otherwise counted from the streamed text deltas with the tokenizer, and the prompt
+/// falls back to the estimate from the request body.
+pub struct StreamUsage {
+    format: StreamFormat,
+    tokenizer: Arc<dyn Tokenizer>,
+    /// Bytes of an incomplete line or frame carried over to the next chunk
+    pending: Vec<u8>,
+    counted_completion: u64,
+    reported_prompt: Option<u64>,
+    reported_completion: Option<u64>,
+}
+
+impl StreamUsage {
+    pub fn new(format: StreamFormat, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        Self {
+            format,
+            tokenizer,
+            pending: Vec::new(),
+            counted_completion: 0,
+            reported_prompt: None,
+            reported_completion: None,
+        }
+    }
+
+    pub fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    /// Bytes at the end of the body fed so far that do not complete a line or frame
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Feed a chunk of the response body
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.pending.extend_from_slice(chunk);
+        match self.format {
+            StreamFormat::ServerSentEvents | StreamFormat::JsonLines => self.feed_lines(),
+            StreamFormat::AwsEventStream => self.feed_frames(),
+        }
+    }
+
+    /// Output tokens so far: reported by the provider if it did, counted otherwise
+    pub fn completion_tokens(&self) -> u64 {
+        self.reported_completion.unwrap_or(self.counted_completion)
+    }
+
+    /// Usage at the end of the stream, given the prompt estimate from the request
+    pub fn finish(self, prompt_estimate: Option<u64>) -> Usage {
+        self.finish_with_estimate(prompt_estimate).0
+    }
+
+    /// Usage at the end of the stream, and the gateway's own count of it (the prompt
+    /// estimate and the output tokens counted from the deltas) whatever the provider
+    /// reported
+    pub fn finish_with_estimate(mut self, prompt_estimate: Option<u64>) -> (Usage, Usage) {
+        // A final line without a trailing newline
+        if self.format != StreamFormat::AwsEventStream && !self.pending.is_empty() {
+            self.pending.push(b'\n');
+            self.feed_lines();
+        }
+        let usage = Usage {
+            prompt_tokens: self.reported_prompt.or(prompt_estimate).unwrap_or(0),
+            completion_tokens: self.completion_tokens(),
+            estimated: self.reported_prompt.is_none() || self.reported_completion.is_none(),
+        };
+        let estimate = Usage {
+            prompt_tokens: prompt_estimate.unwrap_or(0),
+            completion_tokens: self.counted_completion,
+            estimated: true,
+        };
+        (usage, estimate)
+    }
+
+    fn feed_lines(&mut self) {
+        let Some(end) = self.pending.iter().rposition(|&b| b == b'\n') else {
+            return;
+        };
+        let complete: Vec<u8> = self.pending.drain(..=end).collect();
+        for line in complete.split(|&b| b == b'\n') {
+            let line = line.trim_ascii();
+            let payload = match self.format {
+                StreamFormat::ServerSentEvents => match line.strip_prefix(b"data:") {
+                    Some(data) => data.trim_ascii(),
+                    None => continue,
+                },
+                _ => line,
+            };
+            if payload.is_empty() || payload == b"[DONE]" {
+                continue;
+            }
+            if let Ok(event) = serde_json::from_slice::<Value>(payload) {
+                self.observe(&event);
+            }
+        }
+    }
+
+    /// `application/vnd.amazon.eventstream`: 12-byte prelude (total length, headers
+    /// length, CRC), headers, JSON payload and a trailing CRC
+    fn feed_frames(&mut self) {
+        while self.pending.len() >= 12 {
+            let total = u32::from_be_bytes(self.pending[0..4].try_into().unwrap()) as usize;
+            let headers = u32::from_be_bytes(self.pending[4..8].try_into().unwrap()) as usize;
+            if total < 16 + headers {
+                // Not an event stream after all; stop parsing rather than misread it
+                self.pending.clear();
+                return;
+            }
+            if self.pending.len() < total {
+                return;
+            }
+            let frame: Vec<u8> = self.pending.drain(..total).collect();
+            let Ok(event) = serde_json::from_slice::<Value>(&frame[12 + headers..total - 4]) else {
+                continue;
+            };
+            // InvokeModelWithResponseStream wraps the model's own event in base64
+            match event.get("bytes").and_then(Value::as_str) {
+                Some(encoded) => {
+                    if let Some(inner) = BASE64
+                        .decode(encoded)
+                        .ok()
+                        .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok())
+                    {
+                        self.observe(&inner);
+                    }
+                }
+                None => self.observe(&event),
+            }
+        }
+    }
+
+    fn observe(&mut self, event: &Value) {
+        for text in delta_texts(event) {
+            self.counted_completion += self.tokenizer.count_tokens(text);
+        }
+        let (prompt, completion) = reported_usage(event);
+        if prompt.is_some() {
+            self.reported_prompt = prompt;
+        }
+        if completion.is_some() {
+            self.reported_completion = completion;
+        }
+    }
+}
+
+impl fmt::Debug for StreamUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamUsage")
+            .field("format", &self.format)
+            .field("tokenizer", &self.tokenizer.name())
+            .field("completion_tokens", &self.completion_tokens())
+            .finish()
+    }
+}
+
+/// The end of a JSON response larger than the buffering limit is kept up to this size
+/// to find its `usage`, which providers send after the output
+const RESPONSE_TAIL_BYTES: usize = 8 * 1024;
+
+/// A `"usage"` object, with objects nested at most one level deep
+static USAGE_OBJECT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#""usage"\s*:\s*(\{(?:[^{}]|\{[^{}]*\})*\})"#).expect("pattern is valid")
+});
+
+/// Usage of a JSON (non-streamed) response, fed chunk by chunk as it passes through.
+///
+/// The `usage` object the provider reports (OpenAI `prompt_tokens`/`completion_tokens`,
+/// Anthropic and Bedrock Converse `input_tokens`/`inputTokens`, ...) wins; output
+/// tokens are otherwise counted from the response's text with the tokenizer, and the
+/// prompt falls back to the estimate from the request body.
+pub struct ResponseUsage {
+    tokenizer: Arc<dyn Tokenizer>,
+    max_bytes: usize,
+    body: Vec<u8>,
+    /// Set once the body outgrew `max_bytes`; only its tail is kept from then on
+    truncated: bool,
+}
+
+impl ResponseUsage {
+    pub fn new(tokenizer: Arc<dyn Tokenizer>, max_bytes: usize) -> Self {
+        Self {
+            tokenizer,
+            max_bytes,
+            body: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    /// Feed a chunk of the response body
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.body.extend_from_slice(chunk);
+        if self.body.len() > self.max_bytes.max(RESPONSE_TAIL_BYTES) {
+            self.truncated = true;
+        }
+        if self.truncated && self.body.len() > RESPONSE_TAIL_BYTES {
+            self.body.drain(..self.body.len() - RESPONSE_TAIL_BYTES);
+        }
+    }
+
+    /// Usage at the end of the response, given the prompt estimate from the request
+    pub fn finish(self, prompt_estimate: Option<u64>) -> Usage {
+        self.finish_with_estimate(prompt_estimate).0
+    }
+
+    /// Usage at the end of the response, and the gateway's own count of it (the prompt
+    /// estimate and the output tokens counted from the response's text) whatever the
+    /// provider reported; no count for a body too large to parse
+    pub fn finish_with_estimate(self, prompt_estimate: Option<u64>) -> (Usage, Option<Usage>) {
+        let (reported_prompt, reported_completion, counted_completion) = if self.truncated {
+            // Too large to parse whole: search the tail for the usage object alone
+            let (prompt, completion) = USAGE_OBJECT
+                .captures_iter(&self.body)
+                .filter_map(|usage| serde_json::from_slice::<Value>(&usage[1]).ok())
+                .map(|usage| usage_counts(&usage))
+                .last()
+                .unwrap_or_default();
+            (prompt, completion, None)
+        } else {
+            match serde_json::from_slice::<Value>(&self.body) {
+                Ok(response) => {
+                    let (prompt, completion) = reported_usage(&response);
+                    // The generated text sits under the same keys as prompt text
+                    // (`message.content`, `content[].text`, ...)
+                    let counted = self.tokenizer.count_tokens(&prompt_text(&self.body));
+                    (prompt, completion, Some(counted))
+                }
+                Err(_) => (None, None, None),
+            }
+        };
+        let usage = Usage {
+            prompt_tokens: reported_prompt.or(prompt_estimate).unwrap_or(0),
+            completion_tokens: reported_completion.or(counted_completion).unwrap_or(0),
+            estimated: reported_prompt.is_none() || reported_completion.is_none(),
+        };
+        let estimate = counted_completion.map(|completion_tokens| Usage {
+            prompt_tokens: prompt_estimate.unwrap_or(0),
+            completion_tokens,
+            estimated: true,
+        });
+        (usage, estimate)
+    }
+}
+
+impl fmt::Debug for ResponseUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseUsage")
+            .field("tokenizer", &self.tokenizer.name())
+            .field("buffered", &self.body.len())
+            .field("truncated", &self.truncated)
+            .finish()
+    }
+}
+
+/// Generated text carried by a streamed event
+fn delta_texts(event: &Value) -> Vec<&str> {
+    text_pointers(event)
+        .iter()
+        .filter_map(|(_, pointer)| event.pointer(pointer).and_then(Value::as_str))
+        .collect()
+}
+
+/// JSON pointers to the generated text of a streamed event, with the index of the
+/// choice (output stream) the text belongs to
+pub(crate) fn text_pointers(event: &Value) -> Vec<(u64, String)> {
+    let is_text = |pointer: &str| event.pointer(pointer).is_some_and(Value::is_string);
+
+    // OpenAI chat and completions
+    if let Some(choices) = event.get("choices").and_then(Value::as_array) {
+        return choices
+            .iter()
+            .enumerate()
+            .filter_map(|(position, choice)| {
+                let index = choice
+                    .get("index")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(position as u64);
+                ["delta/content", "text"]
+                    .into_iter()
+                    .map(|field| format!("/choices/{}/{}", position, field))
+                    .find(|pointer| is_text(pointer))
+                    .map(|pointer| (index, pointer))
+            })
+            .collect();
+    }
+
+    let event_type = event
+        .get("type")
+        .or_else(|| event.get("event_type"))
+        .and_then(Value::as_str);
+    let pointer = match event_type {
+        // OpenAI Responses API
+        Some("response.output_text.delta") => Some("/delta"),
+        // Cohere v2
+        Some("content-delta") => Some("/delta/message/content/text"),
+        // Cohere v1
+        Some("text-generation") => Some("/text"),
+        // Anthropic messages (also via Bedrock) and Bedrock Converse, Ollama chat and
+        // generate, then TGI `generate_stream`
+        _ => [
+            "/delta/text",
+            "/message/content",
+            "/response",
+            "/token/text",
+        ]
+        .into_iter()
+        .find(|pointer| is_text(pointer)),
+    };
+    pointer
+        .filter(|pointer| is_text(pointer))
+        .map(|pointer| vec![(0, pointer.to_string())])
+        .unwrap_or_default()
+}
+
+/// Usage reported in a streamed event or JSON response as (prompt, completion) tokens
+pub(crate) fn reported_usage(event: &Value) -> (Option<u64>, Option<u64>) {
+    const LOCATIONS: &[&str] = &[
+        "/usage",
+        "/response/usage",
+        "/message/usage",
+        "/metadata/usage",
+        "/delta/usage/billed_units",
+        "/response/meta/billed_units",
+        "/amazon-bedrock-invocationMetrics",
+        "/details",
+    ];
+
+    // Ollama reports counts on the final object itself
+    if event.get("done").is_some() {
+        let count = |key: &str| event.get(key).and_then(Value::as_u64);
+        return (count("prompt_eval_count"), count("eval_count"));
+    }
+    LOCATIONS
+        .iter()
+        .filter_map(|location| event.pointer(location).filter(|usage| usage.is_object()))
+        .map(usage_counts)
+        .find(|(prompt, completion)| prompt.is_some() || completion.is_some())
+        .unwrap_or_default()
+}
+
+/// (prompt, completion) tokens of a usage object, whichever provider's field names it
+/// uses
+fn usage_counts(usage: &Value) -> (Option<u64>, Option<u64>) {
+    const PROMPT: &[&str] = &[
+        "prompt_tokens",
+        "input_tokens",
+        "inputTokens",
+        "inputTokenCount",
+    ];
+    const COMPLETION: &[&str] = &[
+        "completion_tokens",
+        "output_tokens",
+        "outputTokens",
+        "outputTokenCount",
+        "generated_tokens",
+    ];
+
+    let count = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| usage.get(*key).and_then(Value::as_u64))
+    };
+    (count(PROMPT), count(COMPLETION))
+}
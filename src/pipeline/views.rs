@@ -1,4 +1,4 @@
-use pingora::http::RequestHeader;
+use pingora_http::RequestHeader;
 
 /// A read-only wrapper around Pingora's RequestHeader to decouple provider code from Pingora types
 pub struct RequestView<'a> {
@@ -36,6 +36,40 @@ impl<'a> RequestView<'a> {
         self.header("authorization")
     }
 
+    /// Get the tenant identifier from the X-Langspec-Tenant header
+    pub fn tenant(&self) -> Option<&str> {
+        self.header("x-langspec-tenant")
+    }
+
+    /// Get the model identifier from a `/model/{id}/...` path segment (Bedrock style)
+    pub fn path_model(&self) -> Option<&str> {
+        let rest = self.path().split_once("/model/")?.1;
+        let model = rest.split('/').next()?;
+        (!model.is_empty()).then_some(model)
+    }
+
+    /// Get the model identifier from a `model` query parameter (OpenAI Realtime style)
+    pub fn query_model(&self) -> Option<&str> {
+        self.inner
+            .uri
+            .query()?
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("model="))
+            .filter(|model| !model.is_empty())
+    }
+
+    /// Check if this asks to upgrade the connection to a WebSocket
+    pub fn is_websocket_upgrade(&self) -> bool {
+        let has_token = |key: &str, token: &str| {
+            self.header(key).is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|item| item.trim().eq_ignore_ascii_case(token))
+            })
+        };
+        has_token("upgrade", "websocket") && has_token("connection", "upgrade")
+    }
+
     /// Check if this looks like AWS SigV4 authentication
     pub fn has_aws_sigv4(&self) -> bool {
         self.authorization()
@@ -1,5 +1,5 @@
 use crate::pipeline::views::RequestView;
-use crate::provider::{DetectionResult, Provider, ProviderKind};
+use crate::provider::{DetectionResult, Provider, ProviderKind, StreamFormat};
 
 /// AWS Bedrock provider detection using Chain-of-Responsibility approach.
 ///
@@ -82,4 +82,8 @@ impl Provider for BedrockProvider {
 
         None
     }
+
+    fn stream_format(&self, _request_view: &RequestView) -> StreamFormat {
+        StreamFormat::AwsEventStream
+    }
 }
@@ -0,0 +1,106 @@
+use crate::pipeline::views::RequestView;
+use crate::provider::{DetectionResult, FinishReason, Provider, ProviderKind, StreamFormat};
+
+/// Cohere API provider detection using Chain-of-Responsibility approach.
+///
+/// Detection Order (early exit on High confidence):
+/// 1. Host match: `api.cohere.com` / `api.cohere.ai` (High confidence)
+/// 2. Auth + corroboration: Bearer token + Cohere path (High confidence)
+/// 3. Path patterns: `/v2/chat`, `/v1/generate` (Medium confidence)
+///
+/// Cohere's v1 endpoints stream newline-delimited JSON rather than SSE.
+pub struct CohereProvider;
+
+impl CohereProvider {
+    fn is_cohere_host(host: &str) -> bool {
+        host == "api.cohere.com" || host == "api.cohere.ai"
+    }
+
+    /// Exact endpoints only: `/v2/chat/completions` is OpenAI-compatible, not Cohere
+    fn is_cohere_path(path: &str) -> bool {
+        matches!(path.trim_end_matches('/'), "/v2/chat" | "/v1/generate")
+    }
+}
+
+impl Provider for CohereProvider {
+    fn id(&self) -> &'static str {
+        "cohere"
+    }
+
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Cohere
+    }
+
+    fn detect(&self, request_view: &RequestView) -> Option<DetectionResult> {
+        // 1. Explicit override (handled at registry level)
+
+        // 2. Host match (High confidence)
+        if let Some(host) = request_view.host()
+            && Self::is_cohere_host(host)
+        {
+            return Some(DetectionResult::high_confidence(
+                ProviderKind::Cohere,
+                "Cohere API host",
+                "host",
+            ));
+        }
+
+        let has_cohere_path = Self::is_cohere_path(request_view.path());
+
+        // 3. Auth scheme + corroboration (High confidence)
+        if request_view.has_bearer_auth() && has_cohere_path {
+            return Some(DetectionResult::high_confidence(
+                ProviderKind::Cohere,
+                "bearer token with Cohere path",
+                "auth",
+            ));
+        }
+
+        // 4. Path namespace (Medium confidence)
+        if has_cohere_path {
+            return Some(DetectionResult::medium_confidence(
+                ProviderKind::Cohere,
+                "/v2/chat or /v1/generate endpoint",
+                "path",
+            ));
+        }
+
+        None
+    }
+
+    fn stream_format(&self, request_view: &RequestView) -> StreamFormat {
+        if request_view.path().starts_with("/v1/") {
+            StreamFormat::JsonLines
+        } else {
+            StreamFormat::ServerSentEvents
+        }
+    }
+
+    fn stop_event(&self, format: StreamFormat, reason: FinishReason) -> Option<Vec<u8>> {
+        match format {
+            StreamFormat::ServerSentEvents => {
+                let reason = match reason {
+                    FinishReason::Length => "MAX_TOKENS",
+                    FinishReason::Stop => "STOP_SEQUENCE",
+                    FinishReason::ContentFilter => "ERROR",
+                };
+                Some(format!(
+                    "event: message-end\ndata: {{\"type\":\"message-end\",\"delta\":{{\"finish_reason\":\"{}\"}}}}\n\n",
+                    reason
+                ).into_bytes())
+            }
+            StreamFormat::JsonLines => {
+                let reason = match reason {
+                    FinishReason::Length => "MAX_TOKENS",
+                    FinishReason::Stop => "COMPLETE",
+                    FinishReason::ContentFilter => "ERROR_TOXIC",
+                };
+                Some(format!(
+                    "{{\"is_finished\":true,\"event_type\":\"stream-end\",\"finish_reason\":\"{}\"}}\n",
+                    reason
+                ).into_bytes())
+            }
+            StreamFormat::AwsEventStream => None,
+        }
+    }
+}
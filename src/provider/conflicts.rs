@@ -0,0 +1,99 @@
+use crate::metrics::PROVIDER_CONFLICTS;
+use crate::pipeline::views::RequestView;
+use crate::provider::DetectionResult;
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// One provider candidate involved in a conflict
+#[derive(Debug, Clone, Serialize)]
+pub struct ConflictCandidate {
+    pub provider: &'static str,
+    pub confidence: &'static str,
+    pub signal: &'static str,
+    pub reason: &'static str,
+}
+
+impl From<&DetectionResult> for ConflictCandidate {
+    fn from(result: &DetectionResult) -> Self {
+        Self {
+            provider: result.kind.as_str(),
+            confidence: result.confidence.as_str(),
+            signal: result.signal,
+            reason: result.reason,
+        }
+    }
+}
+
+/// Fingerprint of a request on which providers disagreed.
+///
+/// Only the method, host and path are kept (never the query string or credentials),
+/// which is enough to reproduce the detection and improve the heuristics.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConflictRecord {
+    pub timestamp: String,
+    pub method: String,
+    pub host: Option<String>,
+    pub path: String,
+    pub candidates: Vec<ConflictCandidate>,
+}
+
+/// Ring buffer of recent detection conflicts, exposed through the admin API.
+#[derive(Debug)]
+pub struct ConflictLog {
+    capacity: usize,
+    records: Mutex<VecDeque<ConflictRecord>>,
+}
+
+impl ConflictLog {
+    pub const DEFAULT_CAPACITY: usize = 100;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Count a conflict per provider pair and keep the request fingerprint
+    pub fn record(&self, request_view: &RequestView, candidates: &[DetectionResult]) {
+        for (i, first) in candidates.iter().enumerate() {
+            for second in &candidates[i + 1..] {
+                if first.kind != second.kind {
+                    let mut pair = [first.kind.as_str(), second.kind.as_str()];
+                    pair.sort_unstable();
+                    PROVIDER_CONFLICTS.with_label_values(&pair).inc();
+                }
+            }
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+        let record = ConflictRecord {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            method: request_view.method().to_string(),
+            host: request_view.host().map(str::to_string),
+            path: request_view.path().to_string(),
+            candidates: candidates.iter().map(ConflictCandidate::from).collect(),
+        };
+
+        let mut records = self.records.lock().unwrap();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Recorded conflicts, most recent first
+    pub fn recent(&self) -> Vec<ConflictRecord> {
+        self.records.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
+
+impl Default for ConflictLog {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
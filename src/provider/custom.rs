@@ -0,0 +1,288 @@
+use crate::pipeline::views::RequestView;
+use crate::provider::{Confidence, DetectionResult, Provider, ProviderKind};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{LazyLock, Mutex};
+
+/// Names of the built-in providers and override values, which custom providers cannot
+/// take
+pub(crate) const RESERVED_NAMES: &[&str] = &[
+    "openai",
+    "bedrock",
+    "cohere",
+    "deepseek",
+    "ollama",
+    "self-hosted",
+    "unknown",
+];
+
+#[derive(Debug)]
+pub enum CustomProviderError {
+    /// Names must be non-empty lowercase ASCII letters, digits, `-` and `_`
+    InvalidName(String),
+    /// The name of a built-in provider
+    ReservedName(String),
+    InvalidPath(String, String, regex::Error),
+    /// Neither host, auth nor path rules are given
+    NoRules(String),
+}
+
+impl fmt::Display for CustomProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CustomProviderError::InvalidName(name) => write!(
+                f,
+                "invalid provider name '{}': use lowercase letters, digits, '-' and '_'",
+                name
+            ),
+            CustomProviderError::ReservedName(name) => {
+                write!(
+                    f,
+                    "provider name '{}' is taken by a built-in provider",
+                    name
+                )
+            }
+            CustomProviderError::InvalidPath(name, pattern, e) => write!(
+                f,
+                "provider '{}' has an invalid path pattern '{}': {}",
+                name, pattern, e
+            ),
+            CustomProviderError::NoRules(name) => {
+                write!(f, "provider '{}' has no host, auth or path rules", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CustomProviderError {}
+
+/// A header whose presence identifies requests to the provider. With a prefix, the
+/// value must start with it; for `Authorization`, after the `Bearer ` scheme.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthHint {
+    pub header: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+}
+
+impl AuthHint {
+    fn matches(&self, request_view: &RequestView) -> bool {
+        let Some(value) = request_view.header(&self.header) else {
+            return false;
+        };
+        let value = if self.header.eq_ignore_ascii_case("authorization") {
+            value.strip_prefix("Bearer ").unwrap_or(value)
+        } else {
+            value
+        };
+        self.prefix
+            .as_deref()
+            .is_none_or(|prefix| value.starts_with(prefix))
+    }
+}
+
+/// Confidence of a detection by each kind of rule
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RuleConfidence {
+    #[serde(default = "high")]
+    pub host: Confidence,
+    #[serde(default = "medium")]
+    pub auth: Confidence,
+    #[serde(default = "medium")]
+    pub path: Confidence,
+}
+
+fn high() -> Confidence {
+    Confidence::High
+}
+
+fn medium() -> Confidence {
+    Confidence::Medium
+}
+
+impl Default for RuleConfidence {
+    fn default() -> Self {
+        Self {
+            host: Confidence::High,
+            auth: Confidence::Medium,
+            path: Confidence::Medium,
+        }
+    }
+}
+
+/// A provider declared in the gateway config file, e.g.
+///
+/// ```yaml
+/// providers:
+///   - name: acme-llm
+///     hosts: [llm.acme.internal, "*.llm.acme.internal"]
+///     paths: ['^/api/v\d+/generate$']
+///     auth:
+///       - header: x-acme-key
+///       - header: authorization
+///         prefix: acme_
+///     confidence:
+///       path: low
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomProviderConfig {
+    pub name: String,
+    /// Hosts (optionally with port); `*.domain` matches any subdomain
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Regular expressions matched against the request path
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub auth: Vec<AuthHint>,
+    #[serde(default)]
+    pub confidence: RuleConfidence,
+}
+
+/// Provider detection compiled from a [`CustomProviderConfig`], so private or internal
+/// LLM APIs can be detected without writing Rust.
+///
+/// Every matching rule is a candidate; the one with the highest configured confidence
+/// wins, host before auth before path on ties. Custom providers run ahead of the
+/// built-in ones, so a High confidence host rule claims requests that also carry, say,
+/// an OpenAI-style bearer token.
+#[derive(Debug, Clone)]
+pub struct CustomProvider {
+    name: &'static str,
+    hosts: Vec<String>,
+    paths: Vec<Regex>,
+    auth: Vec<AuthHint>,
+    confidence: RuleConfidence,
+}
+
+impl CustomProvider {
+    /// Compile a declared provider. Its name lives for the rest of the process; each
+    /// distinct name is kept once however often the registry is reloaded.
+    pub fn compile(config: CustomProviderConfig) -> Result<Self, CustomProviderError> {
+        let name = config.name;
+        let valid = !name.is_empty()
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_');
+        if !valid {
+            return Err(CustomProviderError::InvalidName(name));
+        }
+        if RESERVED_NAMES.contains(&name.as_str()) {
+            return Err(CustomProviderError::ReservedName(name));
+        }
+        if config.hosts.is_empty() && config.paths.is_empty() && config.auth.is_empty() {
+            return Err(CustomProviderError::NoRules(name));
+        }
+
+        let mut paths = Vec::with_capacity(config.paths.len());
+        for pattern in config.paths {
+            match Regex::new(&pattern) {
+                Ok(regex) => paths.push(regex),
+                Err(e) => return Err(CustomProviderError::InvalidPath(name, pattern, e)),
+            }
+        }
+        Ok(Self {
+            name: intern(name),
+            hosts: config
+                .hosts
+                .into_iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+            paths,
+            auth: config.auth,
+            confidence: config.confidence,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+fn intern(name: String) -> &'static str {
+    static NAMES: LazyLock<Mutex<HashSet<&'static str>>> = LazyLock::new(Default::default);
+    let mut names = NAMES.lock().unwrap();
+    match names.get(name.as_str()) {
+        Some(interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(name.into_boxed_str());
+            names.insert(interned);
+            interned
+        }
+    }
+}
+
+/// Whether a Host header matches one of the (lowercase) host patterns; `*.domain`
+/// matches any subdomain, and patterns without a port match any port
+pub(crate) fn matches_host(patterns: &[String], host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let name = host
+        .rsplit_once(':')
+        .map_or(host.as_str(), |(name, _)| name);
+    patterns.iter().any(|pattern| {
+        let pattern_matches = |candidate: &str| match pattern.strip_prefix("*.") {
+            Some(domain) => candidate
+                .strip_suffix(domain)
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => candidate == pattern,
+        };
+        pattern_matches(&host) || pattern_matches(name)
+    })
+}
+
+impl Provider for CustomProvider {
+    fn id(&self) -> &'static str {
+        self.name
+    }
+
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Custom(self.name)
+    }
+
+    fn detect(&self, request_view: &RequestView) -> Option<DetectionResult> {
+        // 1. Explicit override (handled at registry level)
+
+        let kind = self.kind();
+        let host = request_view
+            .host()
+            .is_some_and(|host| matches_host(&self.hosts, host));
+        let auth = self.auth.iter().any(|hint| hint.matches(request_view));
+        let path = self
+            .paths
+            .iter()
+            .any(|regex| regex.is_match(request_view.path()));
+
+        // 2. Host, auth and path rules, highest configured confidence first
+        [
+            (
+                host,
+                self.confidence.host,
+                "custom provider host rule",
+                "host",
+            ),
+            (
+                auth,
+                self.confidence.auth,
+                "custom provider auth rule",
+                "auth",
+            ),
+            (
+                path,
+                self.confidence.path,
+                "custom provider path rule",
+                "path",
+            ),
+        ]
+        .into_iter()
+        .filter(|(matched, ..)| *matched)
+        .reduce(|best, rule| if rule.1 > best.1 { rule } else { best })
+        .map(|(_, confidence, reason, signal)| DetectionResult {
+            kind,
+            confidence,
+            reason,
+            signal,
+        })
+    }
+}
@@ -0,0 +1,69 @@
+use crate::pipeline::views::RequestView;
+use crate::provider::{DetectionResult, Provider, ProviderKind};
+
+/// DeepSeek API provider detection using Chain-of-Responsibility approach.
+///
+/// Detection Order (early exit on High confidence):
+/// 1. Host match: `api.deepseek.com` (High confidence)
+/// 2. Path patterns: the `/beta` endpoints (chat prefix completion, FIM) (Medium
+///    confidence), then `/chat/completions` without the `/v1` prefix (Low confidence)
+///
+/// DeepSeek's API is OpenAI-compatible, so this provider runs ahead of OpenAI: the
+/// DeepSeek host wins over OpenAI's bearer token and path signals.
+pub struct DeepSeekProvider;
+
+impl DeepSeekProvider {
+    fn is_beta_path(path: &str) -> bool {
+        matches!(
+            path.trim_end_matches('/'),
+            "/beta/completions" | "/beta/chat/completions"
+        )
+    }
+}
+
+impl Provider for DeepSeekProvider {
+    fn id(&self) -> &'static str {
+        "deepseek"
+    }
+
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::DeepSeek
+    }
+
+    fn detect(&self, request_view: &RequestView) -> Option<DetectionResult> {
+        // 1. Explicit override (handled at registry level)
+
+        // 2. Host match (High confidence)
+        if let Some(host) = request_view.host()
+            && host == "api.deepseek.com"
+        {
+            return Some(DetectionResult::high_confidence(
+                ProviderKind::DeepSeek,
+                "api.deepseek.com exact match",
+                "host",
+            ));
+        }
+
+        // 3. Path namespace (Medium confidence)
+        let path = request_view.path();
+        if Self::is_beta_path(path) {
+            return Some(DetectionResult::medium_confidence(
+                ProviderKind::DeepSeek,
+                "/beta/ completion endpoints",
+                "path",
+            ));
+        }
+
+        // 4. Unversioned OpenAI-style path, which DeepSeek documents as its base URL
+        // (Low confidence)
+        if path.trim_end_matches('/') == "/chat/completions" {
+            return Some(DetectionResult::low_confidence(
+                ProviderKind::DeepSeek,
+                "/chat/completions without /v1",
+                "path",
+            ));
+        }
+
+        None
+    }
+}
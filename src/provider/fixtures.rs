@@ -0,0 +1,190 @@
+use crate::pipeline::views::RequestView;
+use crate::provider::ProviderRegistry;
+use pingora_http::RequestHeader;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+#[derive(Debug)]
+pub enum FixtureError {
+    Io(PathBuf, io::Error),
+    Yaml(PathBuf, serde_yaml::Error),
+    InvalidRequest(String, String),
+}
+
+impl fmt::Display for FixtureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixtureError::Io(path, e) => write!(f, "cannot read '{}': {}", path.display(), e),
+            FixtureError::Yaml(path, e) => {
+                write!(f, "invalid fixture file '{}': {}", path.display(), e)
+            }
+            FixtureError::InvalidRequest(name, e) => {
+                write!(f, "fixture '{}' has an invalid request: {}", name, e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FixtureError {}
+
+/// The request a fixture feeds to detection. Only what the heuristics look at is
+/// described: method, path, Host and the remaining headers.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FixtureRequest {
+    #[serde(default = "default_method")]
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+/// Expected detection outcome. `confidence` and `signal` are only checked when
+/// given; a request no provider matches is expected as `provider: unknown` alone.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FixtureExpectation {
+    pub provider: String,
+    #[serde(default)]
+    pub confidence: Option<String>,
+    #[serde(default)]
+    pub signal: Option<String>,
+}
+
+impl fmt::Display for FixtureExpectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.provider)?;
+        match (&self.confidence, &self.signal) {
+            (Some(confidence), Some(signal)) => write!(f, " ({} via {})", confidence, signal),
+            (Some(confidence), None) => write!(f, " ({})", confidence),
+            (None, Some(signal)) => write!(f, " (via {})", signal),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// One entry of a detection fixture file: a request description and the detection
+/// it must produce. Fixture files are YAML lists of these, e.g.
+///
+/// ```yaml
+/// - name: openai host
+///   request:
+///     path: /v1/chat/completions
+///     host: api.openai.com
+///   expect:
+///     provider: openai
+///     confidence: high
+///     signal: host
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct DetectionFixture {
+    pub name: String,
+    pub request: FixtureRequest,
+    pub expect: FixtureExpectation,
+}
+
+/// What detection actually produced for a fixture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detected {
+    pub provider: &'static str,
+    pub confidence: Option<&'static str>,
+    pub signal: Option<&'static str>,
+}
+
+impl fmt::Display for Detected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.provider)?;
+        if let (Some(confidence), Some(signal)) = (self.confidence, self.signal) {
+            write!(f, " ({} via {})", confidence, signal)?;
+        }
+        Ok(())
+    }
+}
+
+impl DetectionFixture {
+    /// Load fixtures from a YAML file, or from every `.yaml`/`.yml` file in a
+    /// directory (in file name order).
+    pub fn load(path: impl AsRef<Path>) -> Result<Vec<Self>, FixtureError> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return Self::load_file(path);
+        }
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(path).map_err(|e| FixtureError::Io(path.to_path_buf(), e))? {
+            let file = entry
+                .map_err(|e| FixtureError::Io(path.to_path_buf(), e))?
+                .path();
+            if file
+                .extension()
+                .is_some_and(|ext| ext == "yaml" || ext == "yml")
+            {
+                files.push(file);
+            }
+        }
+        files.sort();
+
+        let mut fixtures = Vec::new();
+        for file in files {
+            fixtures.extend(Self::load_file(&file)?);
+        }
+        Ok(fixtures)
+    }
+
+    fn load_file(path: &Path) -> Result<Vec<Self>, FixtureError> {
+        let contents = fs::read(path).map_err(|e| FixtureError::Io(path.to_path_buf(), e))?;
+        serde_yaml::from_slice(&contents).map_err(|e| FixtureError::Yaml(path.to_path_buf(), e))
+    }
+
+    pub fn request_header(&self) -> Result<RequestHeader, FixtureError> {
+        let invalid = |e: pingora_error::BError| {
+            FixtureError::InvalidRequest(self.name.clone(), e.to_string())
+        };
+        let request = &self.request;
+        let mut header =
+            RequestHeader::build(request.method.as_str(), request.path.as_bytes(), None)
+                .map_err(invalid)?;
+        if let Some(host) = &request.host {
+            header.insert_header("host", host).map_err(invalid)?;
+        }
+        for (name, value) in &request.headers {
+            header
+                .insert_header(name.clone(), value.as_str())
+                .map_err(invalid)?;
+        }
+        Ok(header)
+    }
+
+    /// Run detection on the fixture request
+    pub fn detect(&self, registry: &ProviderRegistry) -> Result<Detected, FixtureError> {
+        let header = self.request_header()?;
+        let result = registry.detect_result(&RequestView::new(&header));
+        Ok(Detected {
+            provider: result.as_ref().map(|r| r.kind).unwrap_or_default().as_str(),
+            confidence: result.as_ref().map(|r| r.confidence.as_str()),
+            signal: result.as_ref().map(|r| r.signal),
+        })
+    }
+
+    /// Whether the detected outcome satisfies the expectation
+    pub fn matches(&self, detected: &Detected) -> bool {
+        let expect = &self.expect;
+        expect.provider.eq_ignore_ascii_case(detected.provider)
+            && expect.confidence.as_deref().is_none_or(|confidence| {
+                detected
+                    .confidence
+                    .is_some_and(|d| d.eq_ignore_ascii_case(confidence))
+            })
+            && expect
+                .signal
+                .as_deref()
+                .is_none_or(|signal| detected.signal == Some(signal))
+    }
+}
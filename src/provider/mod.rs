@@ -1,20 +1,55 @@
 use crate::pipeline::views::RequestView;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum ProviderKind {
     OpenAI,
     Bedrock,
+    Cohere,
+    DeepSeek,
+    Ollama,
+    /// Self-hosted inference servers (vLLM, HuggingFace TGI)
+    SelfHosted,
+    /// A provider declared in the gateway config file, by name
+    Custom(&'static str),
     #[default]
     Unknown,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+impl ProviderKind {
+    /// Lowercase identifier, matching the values accepted by the X-Langspec-Provider override
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAI => "openai",
+            ProviderKind::Bedrock => "bedrock",
+            ProviderKind::Cohere => "cohere",
+            ProviderKind::DeepSeek => "deepseek",
+            ProviderKind::Ollama => "ollama",
+            ProviderKind::SelfHosted => "self-hosted",
+            ProviderKind::Custom(name) => name,
+            ProviderKind::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Confidence {
     Low,
     Medium,
     High,
 }
 
+impl Confidence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DetectionResult {
     pub kind: ProviderKind,
@@ -71,14 +106,89 @@ impl DetectionResult {
     }
 }
 
+/// Wire format of a provider's streamed responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// `text/event-stream` (OpenAI, Cohere v2)
+    ServerSentEvents,
+    /// One JSON object per line (Cohere v1, Ollama)
+    JsonLines,
+    /// `application/vnd.amazon.eventstream` binary frames (Bedrock)
+    AwsEventStream,
+}
+
+impl StreamFormat {
+    /// Stream format announced by a response `Content-Type`; None for responses that
+    /// are not streamed
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let media_type = content_type.split(';').next()?.trim();
+        match media_type.to_ascii_lowercase().as_str() {
+            "text/event-stream" => Some(StreamFormat::ServerSentEvents),
+            "application/x-ndjson" | "application/stream+json" | "application/jsonl" => {
+                Some(StreamFormat::JsonLines)
+            }
+            "application/vnd.amazon.eventstream" => Some(StreamFormat::AwsEventStream),
+            _ => None,
+        }
+    }
+}
+
+/// Why the gateway ended a stream early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinishReason {
+    /// Output token cap reached
+    Length,
+    /// A gateway stop sequence was generated
+    Stop,
+    /// A content filter truncated the output
+    ContentFilter,
+}
+
+/// Final events of an OpenAI-compatible chat stream, used for OpenAI and for
+/// providers that were not recognised
+pub fn openai_stop_event(reason: FinishReason) -> Vec<u8> {
+    let reason = match reason {
+        FinishReason::Length => "length",
+        FinishReason::Stop => "stop",
+        FinishReason::ContentFilter => "content_filter",
+    };
+    format!(
+        "data: {{\"object\":\"chat.completion.chunk\",\"choices\":[{{\"index\":0,\"delta\":{{}},\"finish_reason\":\"{}\"}}]}}\n\ndata: [DONE]\n\n",
+        reason
+    )
+    .into_bytes()
+}
+
 pub trait Provider: Send + Sync {
     fn id(&self) -> &'static str;
     fn kind(&self) -> ProviderKind;
     fn detect(&self, request_view: &RequestView) -> Option<DetectionResult>;
+
+    /// Format of streamed responses for a request to this provider
+    fn stream_format(&self, _request_view: &RequestView) -> StreamFormat {
+        StreamFormat::ServerSentEvents
+    }
+
+    /// Events that end a stream in this format with the given finish reason, sent when
+    /// the gateway cuts a stream short. None when the format has no such event the
+    /// gateway can produce.
+    fn stop_event(&self, format: StreamFormat, reason: FinishReason) -> Option<Vec<u8>> {
+        (format == StreamFormat::ServerSentEvents).then(|| openai_stop_event(reason))
+    }
 }
 
 pub mod bedrock;
+pub mod cohere;
+pub mod conflicts;
+pub mod custom;
+pub mod deepseek;
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
+pub mod ollama;
 pub mod openai;
 pub mod registry;
+pub mod self_hosted;
+pub mod snapshots;
+pub mod tuning;
 
 pub use registry::ProviderRegistry;
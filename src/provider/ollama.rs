@@ -0,0 +1,96 @@
+use crate::pipeline::views::RequestView;
+use crate::provider::{
+    DetectionResult, FinishReason, Provider, ProviderKind, StreamFormat, openai_stop_event,
+};
+
+/// Ollama (local model runtime) provider detection using Chain-of-Responsibility approach.
+///
+/// Detection Order (early exit on High confidence):
+/// 1. Host match: Ollama's default port `11434` (High confidence)
+/// 2. Path patterns: `/api/(chat|generate|embeddings)` without credentials (Medium confidence)
+///
+/// Local runtimes take no credentials, so a request carrying any is left to the hosted
+/// providers. Ollama's native API streams newline-delimited JSON; its OpenAI-compatible
+/// `/v1/` endpoints stream SSE.
+pub struct OllamaProvider;
+
+impl OllamaProvider {
+    const DEFAULT_PORT: &'static str = "11434";
+
+    fn is_ollama_path(path: &str) -> bool {
+        matches!(
+            path.trim_end_matches('/'),
+            "/api/chat" | "/api/generate" | "/api/embeddings" | "/api/embed"
+        )
+    }
+
+    fn has_credentials(request_view: &RequestView) -> bool {
+        request_view.authorization().is_some()
+            || request_view.header("x-api-key").is_some()
+            || request_view.header("openai-organization").is_some()
+    }
+}
+
+impl Provider for OllamaProvider {
+    fn id(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Ollama
+    }
+
+    fn detect(&self, request_view: &RequestView) -> Option<DetectionResult> {
+        // 1. Explicit override (handled at registry level)
+
+        // 2. Host match (High confidence)
+        if let Some(host) = request_view.host()
+            && host
+                .rsplit_once(':')
+                .is_some_and(|(_, port)| port == Self::DEFAULT_PORT)
+        {
+            return Some(DetectionResult::high_confidence(
+                ProviderKind::Ollama,
+                "Ollama default port 11434",
+                "host",
+            ));
+        }
+
+        // 3. Path namespace without credentials (Medium confidence)
+        if Self::is_ollama_path(request_view.path()) && !Self::has_credentials(request_view) {
+            return Some(DetectionResult::medium_confidence(
+                ProviderKind::Ollama,
+                "/api/ native endpoint without credentials",
+                "path",
+            ));
+        }
+
+        None
+    }
+
+    fn stream_format(&self, request_view: &RequestView) -> StreamFormat {
+        if request_view.path().starts_with("/v1/") {
+            StreamFormat::ServerSentEvents
+        } else {
+            StreamFormat::JsonLines
+        }
+    }
+
+    fn stop_event(&self, format: StreamFormat, reason: FinishReason) -> Option<Vec<u8>> {
+        match format {
+            StreamFormat::ServerSentEvents => Some(openai_stop_event(reason)),
+            // Carries both the chat (`message`) and generate (`response`) fields
+            StreamFormat::JsonLines => {
+                let reason = match reason {
+                    FinishReason::Length => "length",
+                    FinishReason::Stop | FinishReason::ContentFilter => "stop",
+                };
+                Some(format!(
+                    "{{\"message\":{{\"role\":\"assistant\",\"content\":\"\"}},\"response\":\"\",\"done\":true,\"done_reason\":\"{}\"}}\n",
+                    reason
+                ).into_bytes())
+            }
+            StreamFormat::AwsEventStream => None,
+        }
+    }
+}
@@ -6,7 +6,7 @@ use crate::provider::{DetectionResult, Provider, ProviderKind};
 /// Detection Order (early exit on High confidence):
 /// 1. Host match: `api.openai.com` (High confidence)
 /// 2. Auth + corroboration: Bearer token + (host OR path) (High confidence)
-/// 3. Path patterns: `/v1/(chat|completions|responses)` (Medium confidence)
+/// 3. Path patterns: `/v1/(chat|completions|responses|realtime)` (Medium confidence)
 /// 4. Headers: `OpenAI-Organization` (Low confidence)
 ///
 /// Conservative bias: Prefers false negatives over false positives.
@@ -46,7 +46,8 @@ impl Provider for OpenAIProvider {
             let has_openai_path = request_view.path().starts_with("/v1/")
                 && (request_view.path().contains("/chat")
                     || request_view.path().contains("/completions")
-                    || request_view.path().contains("/responses"));
+                    || request_view.path().contains("/responses")
+                    || request_view.path().contains("/realtime"));
 
             if has_openai_host || has_openai_path {
                 return Some(DetectionResult::high_confidence(
@@ -62,7 +63,8 @@ impl Provider for OpenAIProvider {
         if path.starts_with("/v1/")
             && (path.contains("/chat")
                 || path.contains("/completions")
-                || path.contains("/responses"))
+                || path.contains("/responses")
+                || path.contains("/realtime"))
         {
             return Some(DetectionResult::medium_confidence(
                 ProviderKind::OpenAI,
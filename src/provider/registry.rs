@@ -1,41 +1,208 @@
 use crate::pipeline::views::RequestView;
 use crate::provider::bedrock::BedrockProvider;
+use crate::provider::cohere::CohereProvider;
+use crate::provider::conflicts::ConflictLog;
+use crate::provider::custom::{CustomProvider, RESERVED_NAMES};
+use crate::provider::deepseek::DeepSeekProvider;
+use crate::provider::ollama::OllamaProvider;
 use crate::provider::openai::OpenAIProvider;
-use crate::provider::{DetectionResult, Provider, ProviderKind};
+use crate::provider::self_hosted::SelfHostedProvider;
+use crate::provider::snapshots::{RegistryConfig, RegistryError};
+use crate::provider::tuning::{DetectionTuning, TunedProvider};
+use crate::provider::{
+    DetectionResult, FinishReason, Provider, ProviderKind, StreamFormat, openai_stop_event,
+};
 use log::info;
+use std::sync::Arc;
 
 pub struct ProviderRegistry {
-    providers: &'static [&'static dyn Provider],
+    providers: Vec<Box<dyn Provider>>,
+    conflicts: Arc<ConflictLog>,
+    /// Custom providers and tuning the registry was compiled from
+    config: RegistryConfig,
 }
 
 impl ProviderRegistry {
     pub fn new() -> Self {
         // Chain-of-Responsibility order: Override > Host > Auth > Path > Headers
         // Each provider implements this chain internally
-        static PROVIDERS: &[&dyn Provider] = &[&OpenAIProvider, &BedrockProvider];
-
         Self {
-            providers: PROVIDERS,
+            providers: vec![
+                Box::new(SelfHostedProvider::new()),
+                Box::new(DeepSeekProvider),
+                Box::new(OpenAIProvider),
+                Box::new(BedrockProvider),
+                Box::new(CohereProvider),
+                Box::new(OllamaProvider),
+            ],
+            conflicts: Arc::new(ConflictLog::default()),
+            config: RegistryConfig::default(),
+        }
+    }
+
+    /// Detect requests to these hosts (optionally with port) as self-hosted inference
+    /// servers, including their OpenAI-compatible `/v1` API
+    pub fn with_self_hosted_hosts<I, S>(mut self, hosts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for provider in &mut self.providers {
+            if provider.kind() == ProviderKind::SelfHosted {
+                *provider = Box::new(SelfHostedProvider::with_hosts(hosts));
+                break;
+            }
+        }
+        self
+    }
+
+    /// Detect a provider declared in the config file. Custom providers run ahead of the
+    /// built-in ones, in the order they are added, and are accepted as
+    /// `X-Langspec-Provider` override values.
+    pub fn with_custom_provider(mut self, provider: CustomProvider) -> Self {
+        assert!(
+            self.providers
+                .iter()
+                .all(|other| other.id() != provider.id()),
+            "Provider '{}' is already registered",
+            provider.id()
+        );
+        let position = self
+            .providers
+            .iter()
+            .take_while(|other| matches!(other.kind(), ProviderKind::Custom(_)))
+            .count();
+        self.providers.insert(position, Box::new(provider));
+        self
+    }
+
+    /// Add the hosts and paths of `tuning` to the detection of a built-in provider
+    pub fn with_detection_tuning(
+        mut self,
+        tuning: &DetectionTuning,
+    ) -> Result<Self, RegistryError> {
+        let builtin = RESERVED_NAMES.contains(&tuning.provider.as_str());
+        let Some(index) = self
+            .providers
+            .iter()
+            .position(|provider| builtin && provider.id() == tuning.provider)
+        else {
+            return Err(RegistryError::UnknownProvider(tuning.provider.clone()));
+        };
+        let provider = self.providers.remove(index);
+        let tuned = TunedProvider::compile(provider, tuning).map_err(|(pattern, e)| {
+            RegistryError::InvalidPath(tuning.provider.clone(), pattern, e)
+        })?;
+        self.providers.insert(index, Box::new(tuned));
+        Ok(self)
+    }
+
+    pub(crate) fn with_config(mut self, config: RegistryConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Custom providers and detection tuning the registry was compiled from; empty
+    /// for a registry built in code
+    pub fn config(&self) -> &RegistryConfig {
+        &self.config
+    }
+
+    /// Record detection conflicts in `conflicts`, e.g. the log of the registry this one
+    /// replaces
+    pub fn with_conflict_log(mut self, conflicts: Arc<ConflictLog>) -> Self {
+        self.conflicts = conflicts;
+        self
+    }
+
+    /// Names of the registered providers, as accepted by `X-Langspec-Provider`
+    pub fn provider_names(&self) -> Vec<&'static str> {
+        self.providers
+            .iter()
+            .map(|provider| provider.kind().as_str())
+            .collect()
+    }
+
+    /// Recent detection conflicts, shared with the admin API
+    pub fn conflict_log(&self) -> &Arc<ConflictLog> {
+        &self.conflicts
+    }
+
+    /// Run every provider on the request, without the early exit or override of
+    /// [`detect`](Self::detect), for tracing how a decision was reached.
+    pub fn evaluate(
+        &self,
+        request_view: &RequestView,
+    ) -> Vec<(&'static str, Option<DetectionResult>)> {
+        self.providers
+            .iter()
+            .map(|provider| (provider.id(), provider.detect(request_view)))
+            .collect()
+    }
+
+    /// Stream format of a request to the given provider (SSE for unknown providers)
+    pub fn stream_format(&self, kind: ProviderKind, request_view: &RequestView) -> StreamFormat {
+        self.providers
+            .iter()
+            .find(|provider| provider.kind() == kind)
+            .map_or(StreamFormat::ServerSentEvents, |provider| {
+                provider.stream_format(request_view)
+            })
+    }
+
+    /// Events ending a stream of the given provider early (OpenAI-compatible for
+    /// unknown providers)
+    pub fn stop_event(
+        &self,
+        kind: ProviderKind,
+        format: StreamFormat,
+        reason: FinishReason,
+    ) -> Option<Vec<u8>> {
+        match self
+            .providers
+            .iter()
+            .find(|provider| provider.kind() == kind)
+        {
+            Some(provider) => provider.stop_event(format, reason),
+            None => (format == StreamFormat::ServerSentEvents).then(|| openai_stop_event(reason)),
         }
     }
 
     pub fn detect(&self, request_view: &RequestView) -> ProviderKind {
+        self.detect_result(request_view)
+            .map(|result| result.kind)
+            .unwrap_or_default()
+    }
+
+    /// Like [`detect`](Self::detect), but returns the winning detection with its
+    /// confidence and signal. An explicit override is reported as a High confidence
+    /// result with the `override` signal; `None` means no provider matched.
+    pub fn detect_result(&self, request_view: &RequestView) -> Option<DetectionResult> {
         // 1. Explicit override (highest confidence)
         if let Some(override_provider) = request_view.header("x-langspec-provider") {
-            match override_provider.to_lowercase().as_str() {
-                "openai" => {
-                    info!("Provider override: OpenAI (X-Langspec-Provider header)");
-                    return ProviderKind::OpenAI;
+            let overridden =
+                match override_provider.to_lowercase().as_str() {
+                    "openai" => Some(ProviderKind::OpenAI),
+                    "bedrock" => Some(ProviderKind::Bedrock),
+                    "cohere" => Some(ProviderKind::Cohere),
+                    "deepseek" => Some(ProviderKind::DeepSeek),
+                    "ollama" => Some(ProviderKind::Ollama),
+                    "self-hosted" => Some(ProviderKind::SelfHosted),
+                    "unknown" => Some(ProviderKind::Unknown),
+                    name => self.providers.iter().map(|provider| provider.kind()).find(
+                        |kind| matches!(kind, ProviderKind::Custom(custom) if *custom == name),
+                    ),
+                };
+            match overridden {
+                Some(kind) => {
+                    info!("Provider override: {:?} (X-Langspec-Provider header)", kind);
+                    return Some(DetectionResult::high_confidence(
+                        kind,
+                        "X-Langspec-Provider header",
+                        "override",
+                    ));
                 }
-                "bedrock" => {
-                    info!("Provider override: Bedrock (X-Langspec-Provider header)");
-                    return ProviderKind::Bedrock;
-                }
-                "unknown" => {
-                    info!("Provider override: Unknown (X-Langspec-Provider header)");
-                    return ProviderKind::Unknown;
-                }
-                _ => {
+                None => {
                     info!(
                         "Invalid provider override '{}', continuing with detection",
                         override_provider
@@ -48,7 +215,7 @@ impl ProviderRegistry {
         let mut all_results: Vec<DetectionResult> = Vec::new();
         let mut best_result: Option<DetectionResult> = None;
 
-        for provider in self.providers {
+        for provider in &self.providers {
             if let Some(result) = provider.detect(request_view) {
                 // Log all detections for observability
                 info!(
@@ -66,7 +233,7 @@ impl ProviderRegistry {
                         "Decisive detection: {:?} via {} ({})",
                         result.kind, result.signal, result.reason
                     );
-                    return result.kind;
+                    return Some(result);
                 }
 
                 // Accumulate for conflict detection
@@ -84,7 +251,7 @@ impl ProviderRegistry {
             }
         }
 
-        // Detect and log conflicts between providers
+        // Detect, log and record conflicts between providers
         if all_results.len() > 1 {
             let mut conflicts: Vec<(&DetectionResult, &DetectionResult)> = Vec::new();
             for (i, result1) in all_results.iter().enumerate() {
@@ -103,23 +270,21 @@ impl ProviderRegistry {
                         r1.kind, r1.confidence, r1.signal, r2.kind, r2.confidence, r2.signal
                     );
                 }
+                self.conflicts.record(request_view, &all_results);
             }
         }
 
-        // Return best result found, or Unknown
-        match best_result {
+        // Return best result found, if any
+        match &best_result {
             Some(result) => {
                 info!(
                     "Final detection: {:?} (confidence: {:?}, signal: {}, reason: {})",
                     result.kind, result.confidence, result.signal, result.reason
                 );
-                result.kind
-            }
-            None => {
-                info!("No provider detected, defaulting to Unknown");
-                ProviderKind::Unknown
             }
+            None => info!("No provider detected, defaulting to Unknown"),
         }
+        best_result
     }
 }
 
@@ -0,0 +1,96 @@
+use crate::pipeline::views::RequestView;
+use crate::provider::{DetectionResult, Provider, ProviderKind};
+
+/// Self-hosted inference servers (vLLM, HuggingFace TGI) provider detection using
+/// Chain-of-Responsibility approach.
+///
+/// Detection Order (early exit on High confidence):
+/// 1. Host match: a host on the configured allowlist (High confidence)
+/// 2. Path patterns: the native `/generate` and `/generate_stream` endpoints (Medium
+///    confidence)
+///
+/// Both servers also expose an OpenAI-compatible `/v1` API, which cannot be told apart
+/// from OpenAI by the request alone; only allowlisted hosts claim it. This provider
+/// runs ahead of the hosted providers so that an allowlisted host wins even when the
+/// request carries a bearer token (`vllm serve --api-key`). Streams are SSE in both
+/// APIs; when the gateway ends one early it sends an OpenAI-style final chunk.
+#[derive(Debug, Clone, Default)]
+pub struct SelfHostedProvider {
+    /// Hosts (optionally with port) of self-hosted inference servers
+    hosts: Vec<String>,
+}
+
+impl SelfHostedProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_hosts<I, S>(hosts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            hosts: hosts
+                .into_iter()
+                .map(|host| host.into().to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    pub fn hosts(&self) -> &[String] {
+        &self.hosts
+    }
+
+    /// Whether a Host header names an allowlisted server; entries without a port match
+    /// any port
+    fn is_allowlisted(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        let name = host
+            .rsplit_once(':')
+            .map_or(host.as_str(), |(name, _)| name);
+        self.hosts
+            .iter()
+            .any(|allowed| *allowed == host || *allowed == name)
+    }
+
+    fn is_native_path(path: &str) -> bool {
+        matches!(path.trim_end_matches('/'), "/generate" | "/generate_stream")
+    }
+}
+
+impl Provider for SelfHostedProvider {
+    fn id(&self) -> &'static str {
+        "self-hosted"
+    }
+
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::SelfHosted
+    }
+
+    fn detect(&self, request_view: &RequestView) -> Option<DetectionResult> {
+        // 1. Explicit override (handled at registry level)
+
+        // 2. Host match (High confidence)
+        if let Some(host) = request_view.host()
+            && self.is_allowlisted(host)
+        {
+            return Some(DetectionResult::high_confidence(
+                ProviderKind::SelfHosted,
+                "self-hosted inference host allowlist",
+                "host",
+            ));
+        }
+
+        // 3. Native endpoints (Medium confidence)
+        if Self::is_native_path(request_view.path()) {
+            return Some(DetectionResult::medium_confidence(
+                ProviderKind::SelfHosted,
+                "vLLM/TGI /generate endpoint",
+                "path",
+            ));
+        }
+
+        None
+    }
+}
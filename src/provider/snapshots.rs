@@ -0,0 +1,248 @@
+//! Runtime-reloadable provider registry.
+//!
+//! The custom providers and built-in detection tuning of a [`RegistryConfig`] compile
+//! into a [`ProviderRegistry`] that replaces the one requests are detected with, from
+//! a config file reload or the admin API. [`RegistrySnapshots`] keeps the last
+//! registries with the config each was compiled from, so a change that misdetects
+//! traffic can be rolled back without recompiling anything.
+
+use crate::provider::ProviderRegistry;
+use crate::provider::custom::{CustomProvider, CustomProviderConfig, CustomProviderError};
+use crate::provider::tuning::DetectionTuning;
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Provider(CustomProviderError),
+    DuplicateProvider(String),
+    /// Detection tuning of a provider that is not built in
+    UnknownProvider(String),
+    /// A built-in provider tuned twice
+    DuplicateTuning(String),
+    InvalidPath(String, String, regex::Error),
+    /// Rollback with only one registry version kept
+    NoPreviousVersion,
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::Provider(e) => write!(f, "{}", e),
+            RegistryError::DuplicateProvider(name) => {
+                write!(f, "provider '{}' is declared twice", name)
+            }
+            RegistryError::UnknownProvider(name) => {
+                write!(
+                    f,
+                    "detection tuning of unknown built-in provider '{}'",
+                    name
+                )
+            }
+            RegistryError::DuplicateTuning(name) => {
+                write!(f, "detection of provider '{}' is tuned twice", name)
+            }
+            RegistryError::InvalidPath(name, pattern, e) => write!(
+                f,
+                "detection tuning of provider '{}' has an invalid path pattern '{}': {}",
+                name, pattern, e
+            ),
+            RegistryError::NoPreviousVersion => {
+                write!(f, "no previous provider registry version to roll back to")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// What a provider registry is compiled from: the `providers` and `detection`
+/// sections of the config file, or the body of `PUT /providers`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryConfig {
+    /// Additional providers, detected ahead of the built-in ones
+    #[serde(default)]
+    pub providers: Vec<CustomProviderConfig>,
+    /// Extra hosts and paths of built-in providers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub detection: Vec<DetectionTuning>,
+}
+
+impl RegistryConfig {
+    /// The built-in providers, tuned, plus the declared ones
+    pub fn compile(&self) -> Result<ProviderRegistry, RegistryError> {
+        let mut names = HashSet::new();
+        let mut registry = ProviderRegistry::new();
+        for provider in &self.providers {
+            if !names.insert(provider.name.as_str()) {
+                return Err(RegistryError::DuplicateProvider(provider.name.clone()));
+            }
+            let provider =
+                CustomProvider::compile(provider.clone()).map_err(RegistryError::Provider)?;
+            registry = registry.with_custom_provider(provider);
+        }
+        let mut tuned = HashSet::new();
+        for tuning in &self.detection {
+            if !tuned.insert(tuning.provider.as_str()) {
+                return Err(RegistryError::DuplicateTuning(tuning.provider.clone()));
+            }
+            registry = registry.with_detection_tuning(tuning)?;
+        }
+        Ok(registry.with_config(self.clone()))
+    }
+}
+
+/// What replaced the provider registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrySource {
+    /// The registry the gateway was built with
+    Startup,
+    /// A config file load or reload
+    Config,
+    /// `PUT /providers`
+    Admin,
+}
+
+/// A provider registry as it was applied
+#[derive(Debug, Clone, Serialize)]
+pub struct RegistryVersion {
+    /// Increases with every applied change, starting at 1 for the startup registry
+    pub version: u64,
+    /// RFC 3339 time the version was applied
+    pub loaded_at: String,
+    pub source: RegistrySource,
+    /// Providers in detection order, as accepted by `X-Langspec-Provider`
+    pub provider_names: Vec<&'static str>,
+    pub config: RegistryConfig,
+}
+
+/// Outcome of rolling back to the previous registry version
+#[derive(Debug, Clone, Serialize)]
+pub struct RegistryRollback {
+    /// The discarded version
+    pub from: u64,
+    /// The version applied again
+    pub to: u64,
+}
+
+struct SnapshotState {
+    /// Oldest first; the last one is applied
+    versions: VecDeque<(RegistryVersion, Arc<ProviderRegistry>)>,
+    next_version: u64,
+}
+
+/// The applied provider registry and the ones it replaced.
+///
+/// Replacing the registry with one compiled from the same config keeps the version,
+/// so reloading a config file whose other sections changed does not add one.
+pub struct RegistrySnapshots {
+    max_versions: usize,
+    state: Mutex<SnapshotState>,
+}
+
+impl RegistrySnapshots {
+    pub const DEFAULT_MAX_VERSIONS: usize = 10;
+
+    /// Start at version 1 with `registry`
+    pub fn new(registry: Arc<ProviderRegistry>) -> Self {
+        Self {
+            max_versions: Self::DEFAULT_MAX_VERSIONS,
+            state: Mutex::new(SnapshotState {
+                versions: VecDeque::from([(
+                    version(1, RegistrySource::Startup, &registry),
+                    registry,
+                )]),
+                next_version: 2,
+            }),
+        }
+    }
+
+    /// Keep this many versions (at least 2, so there is one to roll back to)
+    pub fn with_max_versions(mut self, max_versions: usize) -> Self {
+        assert!(
+            max_versions >= 2,
+            "At least 2 registry versions must be kept"
+        );
+        self.max_versions = max_versions;
+        self
+    }
+
+    /// The applied registry
+    pub fn registry(&self) -> Arc<ProviderRegistry> {
+        let state = self.state.lock().unwrap();
+        Arc::clone(&state.versions.back().expect("a version is applied").1)
+    }
+
+    /// The applied version
+    pub fn current(&self) -> RegistryVersion {
+        let state = self.state.lock().unwrap();
+        state
+            .versions
+            .back()
+            .expect("a version is applied")
+            .0
+            .clone()
+    }
+
+    /// Kept versions, most recent (applied) first
+    pub fn versions(&self) -> Vec<RegistryVersion> {
+        let state = self.state.lock().unwrap();
+        state
+            .versions
+            .iter()
+            .rev()
+            .map(|(version, _)| version.clone())
+            .collect()
+    }
+
+    /// Apply `registry`, as a new version unless it was compiled from the applied
+    /// config
+    pub fn apply(
+        &self,
+        registry: Arc<ProviderRegistry>,
+        source: RegistrySource,
+    ) -> RegistryVersion {
+        let mut state = self.state.lock().unwrap();
+        let current = state.versions.back_mut().expect("a version is applied");
+        if current.0.config == *registry.config() {
+            current.1 = registry;
+            return current.0.clone();
+        }
+        let applied = version(state.next_version, source, &registry);
+        state.next_version += 1;
+        state.versions.push_back((applied.clone(), registry));
+        while state.versions.len() > self.max_versions {
+            state.versions.pop_front();
+        }
+        applied
+    }
+
+    /// Discard the applied version and apply the previous one again
+    pub fn rollback(&self) -> Result<RegistryRollback, RegistryError> {
+        let mut state = self.state.lock().unwrap();
+        let [.., (previous, _), (current, _)] = state.versions.make_contiguous() else {
+            return Err(RegistryError::NoPreviousVersion);
+        };
+        let rollback = RegistryRollback {
+            from: current.version,
+            to: previous.version,
+        };
+        state.versions.pop_back();
+        Ok(rollback)
+    }
+}
+
+fn version(number: u64, source: RegistrySource, registry: &ProviderRegistry) -> RegistryVersion {
+    RegistryVersion {
+        version: number,
+        loaded_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        source,
+        provider_names: registry.provider_names(),
+        config: registry.config().clone(),
+    }
+}
@@ -0,0 +1,101 @@
+use crate::pipeline::views::RequestView;
+use crate::provider::custom::matches_host;
+use crate::provider::{DetectionResult, FinishReason, Provider, ProviderKind, StreamFormat};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Extra detection rules for a built-in provider, e.g. the hosts of an internal
+/// OpenAI-compatible deployment:
+///
+/// ```yaml
+/// detection:
+///   - provider: openai
+///     hosts: [openai.proxy.internal]
+///     paths: ['^/openai/v1/']
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DetectionTuning {
+    /// Name of the built-in provider
+    pub provider: String,
+    /// Hosts (optionally with port) detected with High confidence; `*.domain` matches
+    /// any subdomain
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    /// Regular expressions matched against the request path, detected with Medium
+    /// confidence
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+}
+
+/// A built-in provider with the rules of a [`DetectionTuning`] ahead of its own:
+/// a tuned host claims the request, a tuned path counts as a Medium confidence
+/// candidate next to whatever the provider itself detects.
+pub struct TunedProvider {
+    inner: Box<dyn Provider>,
+    hosts: Vec<String>,
+    paths: Vec<Regex>,
+}
+
+impl TunedProvider {
+    /// Tune `inner`; fails with the first invalid path pattern
+    pub fn compile(
+        inner: Box<dyn Provider>,
+        tuning: &DetectionTuning,
+    ) -> Result<Self, (String, regex::Error)> {
+        let mut paths = Vec::with_capacity(tuning.paths.len());
+        for pattern in &tuning.paths {
+            paths.push(Regex::new(pattern).map_err(|e| (pattern.clone(), e))?);
+        }
+        Ok(Self {
+            inner,
+            hosts: tuning
+                .hosts
+                .iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+            paths,
+        })
+    }
+}
+
+impl Provider for TunedProvider {
+    fn id(&self) -> &'static str {
+        self.inner.id()
+    }
+
+    fn kind(&self) -> ProviderKind {
+        self.inner.kind()
+    }
+
+    fn detect(&self, request_view: &RequestView) -> Option<DetectionResult> {
+        if request_view
+            .host()
+            .is_some_and(|host| matches_host(&self.hosts, host))
+        {
+            return Some(DetectionResult::high_confidence(
+                self.kind(),
+                "tuned host rule",
+                "host",
+            ));
+        }
+        let detected = self.inner.detect(request_view);
+        let tuned_path = self
+            .paths
+            .iter()
+            .any(|regex| regex.is_match(request_view.path()))
+            .then(|| DetectionResult::medium_confidence(self.kind(), "tuned path rule", "path"));
+        match (detected, tuned_path) {
+            (Some(detected), Some(path)) if path.is_better_than(&detected) => Some(path),
+            (detected, path) => detected.or(path),
+        }
+    }
+
+    fn stream_format(&self, request_view: &RequestView) -> StreamFormat {
+        self.inner.stream_format(request_view)
+    }
+
+    fn stop_event(&self, format: StreamFormat, reason: FinishReason) -> Option<Vec<u8>> {
+        self.inner.stop_event(format, reason)
+    }
+}
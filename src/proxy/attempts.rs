@@ -0,0 +1,133 @@
+use crate::proxy::scrub::scrub;
+use serde::Serialize;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// One try at getting a response from an upstream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpstreamAttempt {
+    /// Provider the attempt was sent as (the translated dialect's, when translated)
+    pub provider: &'static str,
+    pub upstream: String,
+    /// Response status, when the upstream answered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Why the attempt failed without a response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time from selecting the upstream to its response header or failure
+    #[serde(rename = "latency_ms", serialize_with = "as_millis")]
+    pub latency: Duration,
+}
+
+impl fmt::Display for UpstreamAttempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}=", self.provider, self.upstream)?;
+        match (self.status, &self.error) {
+            (Some(status), _) => write!(f, "{}", status)?,
+            (None, Some(_)) => f.write_str("error")?,
+            (None, None) => f.write_str("none")?,
+        }
+        write!(f, "/{}ms", self.latency.as_millis())
+    }
+}
+
+fn as_millis<S: serde::Serializer>(latency: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(latency.as_millis() as u64)
+}
+
+/// Upstream attempts of a request, in order.
+///
+/// Pingora calls `upstream_peer` again for every retry, which may pick another
+/// upstream; each call starts an attempt, and the response header, a connection or
+/// proxying error, or the end of the request closes it. A request served on the first
+/// try has a single attempt, so extra attempts explain a request costing more than its
+/// usage suggests.
+#[derive(Debug, Clone, Default)]
+pub struct AttemptTrace {
+    attempts: Vec<UpstreamAttempt>,
+    /// Start of the last attempt, while it is open
+    started: Option<Instant>,
+}
+
+impl AttemptTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start an attempt, closing an open one as failed without a reason
+    pub fn start(&mut self, provider: &'static str, upstream: impl Into<String>) {
+        self.close(None, None);
+        self.attempts.push(UpstreamAttempt {
+            provider,
+            upstream: upstream.into(),
+            status: None,
+            error: None,
+            latency: Duration::ZERO,
+        });
+        self.started = Some(Instant::now());
+    }
+
+    /// Set the provider of the open attempt, e.g. once its request is translated
+    pub fn set_provider(&mut self, provider: &'static str) {
+        if let Some(attempt) = self.open_attempt() {
+            attempt.provider = provider;
+        }
+    }
+
+    /// Close the open attempt with the upstream's response status
+    pub fn respond(&mut self, status: u16) {
+        self.close(Some(status), None);
+    }
+
+    /// Close the open attempt as failed, with credentials in the error masked
+    pub fn fail(&mut self, error: impl fmt::Display) {
+        self.close(None, Some(scrub(&error.to_string()).into_owned()));
+    }
+
+    fn open_attempt(&mut self) -> Option<&mut UpstreamAttempt> {
+        self.started?;
+        self.attempts.last_mut()
+    }
+
+    fn close(&mut self, status: Option<u16>, error: Option<String>) {
+        let Some(started) = self.started.take() else {
+            return;
+        };
+        if let Some(attempt) = self.attempts.last_mut() {
+            attempt.status = status;
+            attempt.error = error;
+            attempt.latency = started.elapsed();
+        }
+    }
+
+    pub fn attempts(&self) -> &[UpstreamAttempt] {
+        &self.attempts
+    }
+
+    pub fn len(&self) -> usize {
+        self.attempts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attempts.is_empty()
+    }
+
+    /// Whether the request took more than one attempt
+    pub fn is_retried(&self) -> bool {
+        self.attempts.len() > 1
+    }
+}
+
+/// `provider@upstream=status/latency` of each attempt, comma separated
+impl fmt::Display for AttemptTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, attempt) in self.attempts.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", attempt)?;
+        }
+        Ok(())
+    }
+}
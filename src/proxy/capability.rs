@@ -0,0 +1,348 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use blake2::digest::consts::U32;
+use blake2::digest::{Digest, KeyInit, Mac};
+use blake2::{Blake2b, Blake2bMac};
+use pingora_http::RequestHeader;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+type Blake2b256 = Blake2b<U32>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CapabilityError {
+    /// The token is not `lsc_<scope>.<signature>` with a JSON scope
+    Malformed,
+    /// No key is configured for the tenant the token names
+    UnknownTenant(String),
+    BadSignature,
+    Expired,
+    /// The request names another tenant than the token's
+    TenantMismatch,
+    /// The token does not allow the requested model (or the request names none)
+    ModelNotAllowed(String),
+    /// The request asks for more output tokens than the token allows
+    MaxTokensExceeded {
+        requested: u64,
+        allowed: u64,
+    },
+    /// A token was requested for longer than tokens may live
+    TtlTooLong {
+        max: u64,
+    },
+}
+
+impl CapabilityError {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CapabilityError::Malformed => "malformed",
+            CapabilityError::UnknownTenant(_) => "unknown_tenant",
+            CapabilityError::BadSignature => "bad_signature",
+            CapabilityError::Expired => "expired",
+            CapabilityError::TenantMismatch => "tenant_mismatch",
+            CapabilityError::ModelNotAllowed(_) => "model_not_allowed",
+            CapabilityError::MaxTokensExceeded { .. } => "max_tokens_exceeded",
+            CapabilityError::TtlTooLong { .. } => "ttl_too_long",
+        }
+    }
+
+    /// Status a request presenting the token is rejected with: 401 for a token that
+    /// is not valid, 403 for a request outside a valid token's scope
+    pub fn status(&self) -> u16 {
+        match self {
+            CapabilityError::Malformed
+            | CapabilityError::UnknownTenant(_)
+            | CapabilityError::BadSignature
+            | CapabilityError::Expired => 401,
+            CapabilityError::TenantMismatch
+            | CapabilityError::ModelNotAllowed(_)
+            | CapabilityError::MaxTokensExceeded { .. } => 403,
+            CapabilityError::TtlTooLong { .. } => 422,
+        }
+    }
+}
+
+impl fmt::Display for CapabilityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CapabilityError::Malformed => write!(f, "malformed capability token"),
+            CapabilityError::UnknownTenant(tenant) => {
+                write!(f, "no capability key for tenant '{}'", tenant)
+            }
+            CapabilityError::BadSignature => write!(f, "capability token signature does not match"),
+            CapabilityError::Expired => write!(f, "capability token expired"),
+            CapabilityError::TenantMismatch => {
+                write!(f, "capability token belongs to another tenant")
+            }
+            CapabilityError::ModelNotAllowed(model) => {
+                write!(f, "capability token does not allow model '{}'", model)
+            }
+            CapabilityError::MaxTokensExceeded { requested, allowed } => write!(
+                f,
+                "capability token allows {} output tokens, {} requested",
+                allowed, requested
+            ),
+            CapabilityError::TtlTooLong { max } => {
+                write!(f, "capability tokens live at most {}s", max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CapabilityError {}
+
+/// Tenant secret capability tokens are derived from; the secret itself never leaves
+/// the gateway
+#[derive(Clone)]
+pub struct TenantKey([u8; 32]);
+
+impl TenantKey {
+    /// Key derived from a secret of any length
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self(Blake2b256::digest(secret.as_ref()).into())
+    }
+
+    /// Keyed BLAKE2b-256 of a token's encoded scope, under a key derived from the
+    /// tenant key for capability tokens alone
+    fn sign(&self, scope: &[u8]) -> Blake2bMac<U32> {
+        let mut derive = mac(&self.0);
+        derive.update(b"langspec-capability-v1");
+        let mut sign = mac(&derive.finalize().into_bytes());
+        sign.update(scope);
+        sign
+    }
+}
+
+impl fmt::Debug for TenantKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TenantKey(..)")
+    }
+}
+
+fn mac(key: &[u8]) -> Blake2bMac<U32> {
+    <Blake2bMac<U32> as KeyInit>::new_from_slice(key).expect("32-byte keys are valid")
+}
+
+/// What a capability token lets its holder do, embedded in the token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapabilityScope {
+    /// Tenant (`X-Langspec-Tenant`) requests are made as
+    pub tenant: String,
+    /// Models that may be requested, exact or a prefix ending in `*`; any when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+    /// Output tokens a request may ask for, and the cap on streamed output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    /// Unix time (seconds) the token stops being accepted
+    pub expires_at: u64,
+    /// Labels attached to requests made with the token (e.g. the frontend or feature
+    /// it was minted for), logged with each request
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+}
+
+impl CapabilityScope {
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.models.is_empty()
+            || self
+                .models
+                .iter()
+                .any(|allowed| match allowed.strip_suffix('*') {
+                    Some(prefix) => model.starts_with(prefix),
+                    None => allowed == model,
+                })
+    }
+
+    /// Check a request's model and the output tokens it asks for against the scope
+    pub fn authorize(
+        &self,
+        model: Option<&str>,
+        requested_max_tokens: Option<u64>,
+    ) -> Result<(), CapabilityError> {
+        if !self.models.is_empty() {
+            match model {
+                Some(model) if self.allows_model(model) => {}
+                model => {
+                    return Err(CapabilityError::ModelNotAllowed(
+                        model.unwrap_or_default().to_string(),
+                    ));
+                }
+            }
+        }
+        if let (Some(allowed), Some(requested)) = (self.max_tokens, requested_max_tokens)
+            && requested > allowed
+        {
+            return Err(CapabilityError::MaxTokensExceeded { requested, allowed });
+        }
+        Ok(())
+    }
+}
+
+/// Scope asked for when minting a token (`POST /capabilities/tenants/{tenant}`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CapabilityGrant {
+    #[serde(default)]
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    /// Lifetime in seconds; the configured default when unset
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+}
+
+/// A minted token with the scope it carries
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MintedToken {
+    pub token: String,
+    pub scope: CapabilityScope,
+}
+
+/// Short-lived, scoped tokens the gateway mints from tenant keys, which frontends can
+/// embed for browser-side calls through the gateway without holding a real key.
+///
+/// A token is `lsc_<scope>.<signature>`: the base64url JSON scope and a keyed
+/// BLAKE2b-256 signature of it, under a key derived from the tenant's key. Clients send
+/// it as `Authorization: Bearer lsc_...` (what OpenAI SDKs send as the API key) or in
+/// `X-Langspec-Capability`. The gateway checks it, strips it, makes the request as the
+/// token's tenant and enforces the scope; the upstream credentials are the gateway's.
+#[derive(Debug, Clone)]
+pub struct CapabilityTokens {
+    pub tenant_keys: HashMap<String, TenantKey>,
+    /// Lifetime of tokens minted without one
+    pub default_ttl: Duration,
+    /// Longest lifetime a token can be minted for
+    pub max_ttl: Duration,
+}
+
+impl CapabilityTokens {
+    pub const HEADER: &'static str = "X-Langspec-Capability";
+    pub const PREFIX: &'static str = "lsc_";
+
+    pub fn new() -> Self {
+        Self {
+            tenant_keys: HashMap::new(),
+            default_ttl: Duration::from_secs(15 * 60),
+            max_ttl: Duration::from_secs(60 * 60),
+        }
+    }
+
+    pub fn with_tenant_key(mut self, tenant: impl Into<String>, key: TenantKey) -> Self {
+        self.tenant_keys.insert(tenant.into(), key);
+        self
+    }
+
+    pub fn with_default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = ttl;
+        self
+    }
+
+    pub fn with_max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = ttl;
+        self
+    }
+
+    /// Mint a token for `tenant` at `now` (unix seconds)
+    pub fn mint(
+        &self,
+        tenant: &str,
+        grant: &CapabilityGrant,
+        now: u64,
+    ) -> Result<MintedToken, CapabilityError> {
+        let key = self
+            .tenant_keys
+            .get(tenant)
+            .ok_or_else(|| CapabilityError::UnknownTenant(tenant.to_string()))?;
+        let ttl = grant.ttl_secs.unwrap_or(self.default_ttl.as_secs());
+        if ttl > self.max_ttl.as_secs() {
+            return Err(CapabilityError::TtlTooLong {
+                max: self.max_ttl.as_secs(),
+            });
+        }
+        let scope = CapabilityScope {
+            tenant: tenant.to_string(),
+            models: grant.models.clone(),
+            max_tokens: grant.max_tokens,
+            expires_at: now + ttl,
+            tags: grant.tags.clone(),
+        };
+        let encoded =
+            BASE64URL.encode(serde_json::to_vec(&scope).expect("capability scopes serialize"));
+        let signature = BASE64URL.encode(key.sign(encoded.as_bytes()).finalize().into_bytes());
+        Ok(MintedToken {
+            token: format!("{}{}.{}", Self::PREFIX, encoded, signature),
+            scope,
+        })
+    }
+
+    /// Scope of a token, once its signature and expiry are checked at `now`
+    pub fn validate(&self, token: &str, now: u64) -> Result<CapabilityScope, CapabilityError> {
+        let (encoded, signature) = token
+            .strip_prefix(Self::PREFIX)
+            .and_then(|token| token.split_once('.'))
+            .ok_or(CapabilityError::Malformed)?;
+        let scope: CapabilityScope = BASE64URL
+            .decode(encoded)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or(CapabilityError::Malformed)?;
+        let signature = BASE64URL
+            .decode(signature)
+            .map_err(|_| CapabilityError::Malformed)?;
+        let key = self
+            .tenant_keys
+            .get(&scope.tenant)
+            .ok_or_else(|| CapabilityError::UnknownTenant(scope.tenant.clone()))?;
+        key.sign(encoded.as_bytes())
+            .verify_slice(&signature)
+            .map_err(|_| CapabilityError::BadSignature)?;
+        if now >= scope.expires_at {
+            return Err(CapabilityError::Expired);
+        }
+        Ok(scope)
+    }
+}
+
+impl Default for CapabilityTokens {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Capability token a request presents, in `X-Langspec-Capability` or as a bearer
+/// token
+pub fn request_token(request: &RequestHeader) -> Option<&str> {
+    let header = |name: &str| request.headers.get(name)?.to_str().ok();
+    header(CapabilityTokens::HEADER).or_else(|| {
+        header("authorization")?
+            .strip_prefix("Bearer ")
+            .map(str::trim)
+            .filter(|token| token.starts_with(CapabilityTokens::PREFIX))
+    })
+}
+
+/// Output tokens a JSON request body asks for, whichever provider's parameter names it
+/// uses
+pub fn requested_max_tokens(body: &[u8]) -> Option<u64> {
+    const POINTERS: &[&str] = &[
+        "/max_tokens",
+        "/max_completion_tokens",
+        "/max_output_tokens",
+        "/max_tokens_to_sample",
+        "/inferenceConfig/maxTokens",
+        "/options/num_predict",
+        "/parameters/max_new_tokens",
+    ];
+    let request: Value = serde_json::from_slice(body).ok()?;
+    POINTERS
+        .iter()
+        .filter_map(|pointer| request.pointer(pointer).and_then(Value::as_u64))
+        .max()
+}
@@ -0,0 +1,386 @@
+use crate::proxy::scrub::{scrub, scrub_header};
+use log::info;
+use pingora_http::{RequestHeader, ResponseHeader};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum CaptureError {
+    /// The sample rate is not in `(0, 1]`
+    InvalidSampleRate(f64),
+    /// The window is empty or longer than allowed
+    InvalidDuration { max_secs: u64 },
+    /// A capture file could not be written
+    Io(PathBuf, std::io::Error),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::InvalidSampleRate(rate) => {
+                write!(f, "sample rate {} is not in (0, 1]", rate)
+            }
+            CaptureError::InvalidDuration { max_secs } => {
+                write!(f, "capture windows last between 1 and {} seconds", max_secs)
+            }
+            CaptureError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+/// A capture window as requested through the admin API
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureRequest {
+    /// Only capture this tenant's requests (`X-Langspec-Tenant`)
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Only capture requests whose path starts with this prefix
+    #[serde(default)]
+    pub route: Option<String>,
+    /// Fraction of matching requests captured
+    #[serde(default = "CaptureRequest::default_sample_rate")]
+    pub sample_rate: f64,
+    pub duration_secs: u64,
+}
+
+impl CaptureRequest {
+    fn default_sample_rate() -> f64 {
+        1.0
+    }
+}
+
+/// A time-bounded capture of sampled traffic
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CaptureWindow {
+    pub id: String,
+    pub tenant: Option<String>,
+    pub route: Option<String>,
+    pub sample_rate: f64,
+    /// Unix time (seconds) the window opened
+    pub started_at: u64,
+    /// Unix time (seconds) the window closes
+    pub expires_at: u64,
+    /// Requests sampled so far
+    pub captured: u64,
+}
+
+impl CaptureWindow {
+    fn matches(&self, tenant: Option<&str>, path: &str) -> bool {
+        self.tenant
+            .as_deref()
+            .is_none_or(|want| tenant == Some(want))
+            && self
+                .route
+                .as_deref()
+                .is_none_or(|prefix| path.starts_with(prefix))
+    }
+}
+
+/// Full request/response payloads of sampled traffic, for reproducing provider bugs.
+///
+/// Capture is off until a window is opened (through the admin API) for a tenant and/or
+/// route; while it is open, a sampled fraction of the matching requests is written to
+/// the capture directory as one JSON file per request, under a directory per window.
+/// Windows close on their own once they expire. Credentials are masked in the captured
+/// headers and bodies, but bodies are otherwise stored as they were sent: the
+/// directory holds prompts and completions and must be protected accordingly.
+#[derive(Debug)]
+pub struct PayloadCapture {
+    dir: PathBuf,
+    max_window: Duration,
+    max_payload_bytes: usize,
+    windows: Mutex<Vec<CaptureWindow>>,
+}
+
+impl PayloadCapture {
+    /// Longest window by default
+    pub const DEFAULT_MAX_WINDOW: Duration = Duration::from_secs(60 * 60);
+    /// Bytes of each body kept by default; the rest is dropped and marked truncated
+    pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 1024 * 1024;
+
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_window: Self::DEFAULT_MAX_WINDOW,
+            max_payload_bytes: Self::DEFAULT_MAX_PAYLOAD_BYTES,
+            windows: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn with_max_window(mut self, max_window: Duration) -> Self {
+        self.max_window = max_window;
+        self
+    }
+
+    pub fn with_max_payload_bytes(mut self, max_payload_bytes: usize) -> Self {
+        self.max_payload_bytes = max_payload_bytes;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Open a capture window at `now` (Unix seconds)
+    pub fn open(&self, request: &CaptureRequest, now: u64) -> Result<CaptureWindow, CaptureError> {
+        if !(request.sample_rate > 0.0 && request.sample_rate <= 1.0) {
+            return Err(CaptureError::InvalidSampleRate(request.sample_rate));
+        }
+        let max_secs = self.max_window.as_secs();
+        if request.duration_secs == 0 || request.duration_secs > max_secs {
+            return Err(CaptureError::InvalidDuration { max_secs });
+        }
+        let window = CaptureWindow {
+            id: format!("{:016x}", rand::random::<u64>()),
+            tenant: request.tenant.clone(),
+            route: request.route.clone(),
+            sample_rate: request.sample_rate,
+            started_at: now,
+            expires_at: now + request.duration_secs,
+            captured: 0,
+        };
+        info!(
+            "Capture window {} opened for {}s (tenant: {}, route: {}, sample rate: {})",
+            window.id,
+            request.duration_secs,
+            window.tenant.as_deref().unwrap_or("any"),
+            window.route.as_deref().unwrap_or("any"),
+            window.sample_rate
+        );
+        self.windows.lock().unwrap().push(window.clone());
+        Ok(window)
+    }
+
+    /// Close a window before it expires
+    pub fn close(&self, id: &str) -> Option<CaptureWindow> {
+        let mut windows = self.windows.lock().unwrap();
+        let index = windows.iter().position(|window| window.id == id)?;
+        let window = windows.remove(index);
+        info!(
+            "Capture window {} closed after {} captures",
+            window.id, window.captured
+        );
+        Some(window)
+    }
+
+    /// Open windows at `now`
+    pub fn windows(&self, now: u64) -> Vec<CaptureWindow> {
+        let mut windows = self.windows.lock().unwrap();
+        expire(&mut windows, now);
+        windows.clone()
+    }
+
+    /// Decide whether to capture a request at `now`; the recorder collects its payloads
+    pub fn sample(&self, request: &RequestHeader, now: u64) -> Option<PayloadRecorder> {
+        let mut windows = self.windows.lock().unwrap();
+        expire(&mut windows, now);
+        let tenant = request
+            .headers
+            .get("x-langspec-tenant")
+            .and_then(|value| value.to_str().ok());
+        let window = windows
+            .iter_mut()
+            .find(|window| window.matches(tenant, request.uri.path()))?;
+        if rand::random::<f64>() >= window.sample_rate {
+            return None;
+        }
+        window.captured += 1;
+        Some(PayloadRecorder::new(
+            window.id.clone(),
+            self.max_payload_bytes,
+            request,
+            now,
+        ))
+    }
+
+    /// Write a captured exchange to `<dir>/<window>/<request id>.json`
+    pub fn store(&self, exchange: &CapturedExchange) -> Result<PathBuf, CaptureError> {
+        let dir = self.dir.join(&exchange.window);
+        std::fs::create_dir_all(&dir).map_err(|e| CaptureError::Io(dir.clone(), e))?;
+        let path = dir.join(format!("{}.json", exchange.request_id));
+        let contents = serde_json::to_vec_pretty(exchange).expect("captures serialize");
+        std::fs::write(&path, contents).map_err(|e| CaptureError::Io(path.clone(), e))?;
+        Ok(path)
+    }
+}
+
+fn expire(windows: &mut Vec<CaptureWindow>, now: u64) {
+    windows.retain(|window| {
+        let open = now < window.expires_at;
+        if !open {
+            info!(
+                "Capture window {} ended after {} captures",
+                window.id, window.captured
+            );
+        }
+        open
+    });
+}
+
+/// A body as captured: JSON when it parses, text otherwise
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CapturedBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Bytes received, including any beyond the capture limit
+    pub bytes: usize,
+    pub truncated: bool,
+}
+
+/// A sampled request and its response, as written to the capture directory
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CapturedExchange {
+    /// Capture window the request was sampled by
+    pub window: String,
+    pub request_id: String,
+    /// Unix time (seconds) the request was sampled
+    pub timestamp: u64,
+    pub method: String,
+    pub uri: String,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: CapturedBody,
+    /// Body sent upstream in place of the client's (translated or rewritten)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarded_body: Option<CapturedBody>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub response_headers: Vec<(String, String)>,
+    /// Body as sent to the client
+    pub response_body: CapturedBody,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Payloads of a sampled request, collected as it is proxied
+#[derive(Debug)]
+pub struct PayloadRecorder {
+    exchange: CapturedExchange,
+    max_bytes: usize,
+    request_body: Vec<u8>,
+    response_body: Vec<u8>,
+}
+
+impl PayloadRecorder {
+    pub fn new(window: String, max_bytes: usize, request: &RequestHeader, now: u64) -> Self {
+        Self {
+            exchange: CapturedExchange {
+                window,
+                request_id: format!("{}-{:016x}", now, rand::random::<u64>()),
+                timestamp: now,
+                method: request.method.to_string(),
+                uri: request.uri.to_string(),
+                request_headers: redacted_headers(&request.headers),
+                request_body: CapturedBody::default(),
+                forwarded_body: None,
+                provider: None,
+                upstream: None,
+                status: None,
+                response_headers: Vec::new(),
+                response_body: CapturedBody::default(),
+                error: None,
+            },
+            max_bytes,
+            request_body: Vec::new(),
+            response_body: Vec::new(),
+        }
+    }
+
+    /// Window the request was sampled by
+    pub fn window(&self) -> &str {
+        &self.exchange.window
+    }
+
+    pub fn request_body(&mut self, chunk: &[u8]) {
+        append(
+            &mut self.request_body,
+            &mut self.exchange.request_body,
+            chunk,
+            self.max_bytes,
+        );
+    }
+
+    /// Record the body sent upstream when it differs from the client's
+    pub fn forwarded_body(&mut self, body: &[u8]) {
+        let mut forwarded = CapturedBody::default();
+        let mut buffer = Vec::new();
+        append(&mut buffer, &mut forwarded, body, self.max_bytes);
+        set_content(&mut forwarded, buffer);
+        self.exchange.forwarded_body = Some(forwarded);
+    }
+
+    /// Record where the request was sent
+    pub fn upstream(&mut self, provider: &str, upstream: &str) {
+        self.exchange.provider = Some(provider.to_string());
+        self.exchange.upstream = Some(upstream.to_string());
+    }
+
+    /// Record the response header as sent to the client
+    pub fn response(&mut self, response: &ResponseHeader) {
+        self.exchange.status = Some(response.status.as_u16());
+        self.exchange.response_headers = redacted_headers(&response.headers);
+    }
+
+    pub fn response_body(&mut self, chunk: &[u8]) {
+        append(
+            &mut self.response_body,
+            &mut self.exchange.response_body,
+            chunk,
+            self.max_bytes,
+        );
+    }
+
+    /// The captured exchange, ending with `error` if the request failed
+    pub fn finish(mut self, error: Option<String>) -> CapturedExchange {
+        set_content(&mut self.exchange.request_body, self.request_body);
+        set_content(&mut self.exchange.response_body, self.response_body);
+        self.exchange.error = error;
+        self.exchange
+    }
+}
+
+fn append(buffer: &mut Vec<u8>, body: &mut CapturedBody, chunk: &[u8], max_bytes: usize) {
+    body.bytes += chunk.len();
+    let room = max_bytes.saturating_sub(buffer.len());
+    if chunk.len() > room {
+        body.truncated = true;
+    }
+    buffer.extend_from_slice(&chunk[..chunk.len().min(room)]);
+}
+
+fn set_content(body: &mut CapturedBody, buffer: Vec<u8>) {
+    if buffer.is_empty() {
+        return;
+    }
+    let text = String::from_utf8_lossy(&buffer);
+    let text = scrub(&text);
+    match serde_json::from_str(&text).ok().filter(|_| !body.truncated) {
+        Some(json) => body.json = Some(json),
+        None => body.text = Some(text.into_owned()),
+    }
+}
+
+fn redacted_headers(headers: &http::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            (
+                name.as_str().to_string(),
+                scrub_header(name.as_str(), &value).into_owned(),
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,312 @@
+//! Client certificate (mTLS) authentication on the downstream TLS listener.
+//!
+//! The listener verifies client certificates against a CA bundle during the handshake,
+//! and can require one. The identity of a verified certificate (subject common name,
+//! organization and subject alternative names) is mapped to a tenant by
+//! [`ClientCertTenants`]: requests over the connection are made as that tenant whatever
+//! `X-Langspec-Tenant` they carry, and requests presenting a verified certificate no
+//! rule maps are rejected.
+//!
+//! Pingora only hands the request a digest of the peer certificate, so identities are
+//! read in the handshake's verify callback and kept by certificate digest in
+//! [`ClientCerts`] until the request looks them up. The listener does not resume TLS
+//! sessions: a resumed handshake skips the callback, and could present a certificate
+//! whose identity was forgotten since.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientCertError {
+    /// An entry is not `tenant=field:value`
+    InvalidEntry(String),
+    /// The field is not `cn`, `o` or `san`
+    UnknownField(String, String),
+}
+
+impl fmt::Display for ClientCertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientCertError::InvalidEntry(entry) => write!(
+                f,
+                "invalid client certificate rule '{}': use tenant=field:value",
+                entry
+            ),
+            ClientCertError::UnknownField(entry, field) => write!(
+                f,
+                "unknown certificate field '{}' in '{}': use cn, o or san",
+                field, entry
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ClientCertError {}
+
+/// What a verified client certificate says about its holder
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ClientIdentity {
+    /// Subject common name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub common_name: Option<String>,
+    /// Subject organization
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+    /// DNS names, email addresses and URIs of the subject alternative names
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sans: Vec<String>,
+    /// Tenant the identity maps to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+/// Certificate field a rule matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertField {
+    CommonName,
+    Organization,
+    /// Any of the subject alternative names
+    San,
+}
+
+/// Maps certificates whose `field` matches `pattern` to `tenant`. A pattern starting
+/// with `*` matches values ending with the rest, e.g. `*.acme.example`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertRule {
+    pub tenant: String,
+    pub field: CertField,
+    pub pattern: String,
+}
+
+impl ClientCertRule {
+    fn matches(&self, identity: &ClientIdentity) -> bool {
+        let matches = |value: &str| match self.pattern.strip_prefix('*') {
+            Some(suffix) => value.ends_with(suffix),
+            None => value == self.pattern,
+        };
+        match self.field {
+            CertField::CommonName => identity.common_name.as_deref().is_some_and(matches),
+            CertField::Organization => identity.organization.as_deref().is_some_and(matches),
+            CertField::San => identity.sans.iter().any(|san| matches(san)),
+        }
+    }
+}
+
+/// Rules mapping client certificates to tenants; the first matching rule applies
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientCertTenants {
+    pub rules: Vec<ClientCertRule>,
+}
+
+impl ClientCertTenants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(
+        mut self,
+        tenant: impl Into<String>,
+        field: CertField,
+        pattern: impl Into<String>,
+    ) -> Self {
+        self.rules.push(ClientCertRule {
+            tenant: tenant.into(),
+            field,
+            pattern: pattern.into(),
+        });
+        self
+    }
+
+    /// Rules from a comma-separated list of `tenant=field:value`, with field `cn`, `o`
+    /// or `san`, e.g. `acme=san:*.acme.example,globex=cn:globex-batch`
+    pub fn parse(list: &str) -> Result<Self, ClientCertError> {
+        let mut tenants = Self::new();
+        for entry in list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let invalid = || ClientCertError::InvalidEntry(entry.to_string());
+            let (tenant, rule) = entry.split_once('=').ok_or_else(invalid)?;
+            let (field, pattern) = rule.split_once(':').ok_or_else(invalid)?;
+            let (tenant, field, pattern) = (tenant.trim(), field.trim(), pattern.trim());
+            if tenant.is_empty() || pattern.is_empty() {
+                return Err(invalid());
+            }
+            let field = match field {
+                "cn" => CertField::CommonName,
+                "o" => CertField::Organization,
+                "san" => CertField::San,
+                _ => {
+                    return Err(ClientCertError::UnknownField(
+                        entry.to_string(),
+                        field.to_string(),
+                    ));
+                }
+            };
+            tenants = tenants.with_rule(tenant, field, pattern);
+        }
+        Ok(tenants)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn tenant_for(&self, identity: &ClientIdentity) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(identity))
+            .map(|rule| rule.tenant.as_str())
+    }
+}
+
+/// Client certificate settings of the TLS listener, and the identities of certificates
+/// verified by it
+#[derive(Debug, Default)]
+pub struct ClientCerts {
+    tenants: ClientCertTenants,
+    required: bool,
+    /// Identities by SHA-256 digest of the certificate, with when they were last
+    /// verified
+    identities: Mutex<HashMap<Vec<u8>, (ClientIdentity, Instant)>>,
+}
+
+impl ClientCerts {
+    /// Identities kept; the least recently verified one is forgotten for a new one
+    pub const MAX_IDENTITIES: usize = 10_000;
+
+    pub fn new(tenants: ClientCertTenants) -> Self {
+        Self {
+            tenants,
+            ..Self::default()
+        }
+    }
+
+    /// Fail handshakes without a client certificate, instead of serving them without a
+    /// certificate identity
+    pub fn with_required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    pub fn tenants(&self) -> &ClientCertTenants {
+        &self.tenants
+    }
+
+    /// Keep the identity of a verified certificate, with the tenant it maps to
+    pub fn remember(&self, digest: Vec<u8>, mut identity: ClientIdentity) {
+        identity.tenant = self.tenants.tenant_for(&identity).map(str::to_string);
+        let mut identities = self.identities.lock().unwrap();
+        if identities.len() >= Self::MAX_IDENTITIES
+            && !identities.contains_key(&digest)
+            && let Some(oldest) = identities
+                .iter()
+                .min_by_key(|(_, (_, verified_at))| *verified_at)
+                .map(|(digest, _)| digest.clone())
+        {
+            identities.remove(&oldest);
+        }
+        identities.insert(digest, (identity, Instant::now()));
+    }
+
+    /// Identity of the verified certificate with this SHA-256 digest
+    pub fn identity(&self, digest: &[u8]) -> Option<ClientIdentity> {
+        let identities = self.identities.lock().unwrap();
+        identities.get(digest).map(|(identity, _)| identity.clone())
+    }
+}
+
+#[cfg(feature = "tls")]
+mod tls {
+    use super::{ClientCerts, ClientIdentity};
+    use pingora::listeners::tls::TlsSettings;
+    use pingora::tls::hash::MessageDigest;
+    use pingora::tls::nid::Nid;
+    use pingora::tls::ssl::{SslOptions, SslSessionCacheMode, SslVerifyMode};
+    use pingora::tls::x509::{X509NameRef, X509Ref};
+    use std::sync::Arc;
+
+    impl ClientCerts {
+        /// Settings of a TLS listener serving the PEM certificate chain and key, which
+        /// verifies client certificates against the PEM CA bundle and remembers their
+        /// identities in `self`. Every connection makes a full handshake.
+        pub fn tls_settings(
+            self: &Arc<Self>,
+            cert_path: &str,
+            key_path: &str,
+            ca_path: &str,
+        ) -> pingora::Result<TlsSettings> {
+            use pingora::{ErrorType, OrErr};
+            const TLS_CONF_ERR: ErrorType = ErrorType::Custom("TLSConfigError");
+
+            let mut settings = TlsSettings::intermediate(cert_path, key_path)?;
+            settings
+                .set_ca_file(ca_path)
+                .or_err_with(TLS_CONF_ERR, || {
+                    format!("fail to read client CA file {}", ca_path)
+                })?;
+            let client_cas = pingora::tls::x509::X509Name::load_client_ca_file(ca_path)
+                .or_err_with(TLS_CONF_ERR, || {
+                    format!("fail to read client CA names from {}", ca_path)
+                })?;
+            settings.set_client_ca_list(client_cas);
+            // No session cache or tickets, so every handshake runs the verify callback
+            settings.set_session_cache_mode(SslSessionCacheMode::OFF);
+            settings.set_options(SslOptions::NO_TICKET);
+            settings
+                .set_num_tickets(0)
+                .or_err(TLS_CONF_ERR, "fail to disable TLS session tickets")?;
+
+            let mut mode = SslVerifyMode::PEER;
+            if self.required {
+                mode |= SslVerifyMode::FAIL_IF_NO_PEER_CERT;
+            }
+            let certs = Arc::clone(self);
+            settings.set_verify_callback(mode, move |verified, store| {
+                // The leaf is checked last, once its chain is verified
+                if verified
+                    && store.error_depth() == 0
+                    && let Some(cert) = store.current_cert()
+                    && let Ok(digest) = cert.digest(MessageDigest::sha256())
+                {
+                    certs.remember(digest.to_vec(), identity(cert));
+                }
+                verified
+            });
+            Ok(settings)
+        }
+    }
+
+    fn identity(cert: &X509Ref) -> ClientIdentity {
+        let sans = cert
+            .subject_alt_names()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| name.dnsname().or(name.email()).or(name.uri()))
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        ClientIdentity {
+            common_name: entry(cert.subject_name(), Nid::COMMONNAME),
+            organization: entry(cert.subject_name(), Nid::ORGANIZATIONNAME),
+            sans,
+            tenant: None,
+        }
+    }
+
+    fn entry(name: &X509NameRef, nid: Nid) -> Option<String> {
+        name.entries_by_nid(nid)
+            .next()
+            .and_then(|entry| entry.data().to_string().ok())
+    }
+}
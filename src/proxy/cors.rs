@@ -0,0 +1,304 @@
+//! CORS, so browser-based apps can call the gateway directly.
+//!
+//! The config file's `cors` section lists routes (a request path, or a prefix ending in
+//! `*`) with the origins allowed to call them; the first matching route applies. The
+//! gateway answers the preflight `OPTIONS` requests of those routes itself, 204 with
+//! the route's `Access-Control-*` headers for an allowed origin and method and 403
+//! otherwise, and adds the headers to proxied responses to allowed origins. Requests of
+//! other routes pass unchanged.
+
+use http::{HeaderName, Method, header};
+use pingora_error::Result;
+use pingora_http::{RequestHeader, ResponseHeader};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorsError {
+    /// The route path does not start with `/`
+    InvalidPath(String),
+    /// An origin that is neither `*` nor `scheme://host[:port]`
+    InvalidOrigin(String),
+    InvalidMethod(String),
+    InvalidHeader(String),
+    /// A route allowing any origin with credentials, which browsers refuse
+    WildcardCredentials(String),
+}
+
+impl fmt::Display for CorsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CorsError::InvalidPath(path) => write!(f, "invalid CORS route path '{}'", path),
+            CorsError::InvalidOrigin(origin) => write!(
+                f,
+                "invalid CORS origin '{}': use * or scheme://host[:port]",
+                origin
+            ),
+            CorsError::InvalidMethod(method) => write!(f, "invalid CORS method '{}'", method),
+            CorsError::InvalidHeader(name) => write!(f, "invalid CORS header '{}'", name),
+            CorsError::WildcardCredentials(path) => write!(
+                f,
+                "CORS route '{}' allows credentials from any origin: list the origins",
+                path
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CorsError {}
+
+/// A route of the config file's `cors` section
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorsRouteConfig {
+    /// Request path, or a prefix ending in `*`
+    pub path: String,
+    /// Origins allowed to call the route, e.g. `https://app.example.com`, or `*`
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed; GET and POST when unset
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_methods: Vec<String>,
+    /// Request headers clients may send, or `*` for any; `authorization` and
+    /// `content-type` when unset
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_headers: Vec<String>,
+    /// Response headers scripts may read besides the safelisted ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expose_headers: Vec<String>,
+    /// Allow requests with cookies or HTTP authentication
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct CorsRoute {
+    path: String,
+    /// Any origin when `None`
+    origins: Option<Vec<String>>,
+    methods: Vec<Method>,
+    /// Any header when `None`
+    headers: Option<Vec<HeaderName>>,
+    expose_headers: Vec<HeaderName>,
+    credentials: bool,
+    max_age_secs: Option<u64>,
+}
+
+impl CorsRoute {
+    fn matches(&self, path: &str) -> bool {
+        match self.path.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => self.path == path,
+        }
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.origins
+            .as_ref()
+            .is_none_or(|origins| origins.iter().any(|allowed| allowed == origin))
+    }
+
+    /// `Access-Control-Allow-Origin` and the headers of every response to `origin`
+    fn apply(&self, origin: &str, response: &mut ResponseHeader) -> Result<()> {
+        match &self.origins {
+            None => response.insert_header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")?,
+            Some(_) => {
+                response.insert_header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)?;
+                response.append_header(header::VARY, "Origin")?;
+            }
+        }
+        if self.credentials {
+            response.insert_header(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true")?;
+        }
+        if !self.expose_headers.is_empty() {
+            response.insert_header(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                join(&self.expose_headers),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compiled `cors` section of the config file
+#[derive(Debug, Clone, Default)]
+pub struct CorsRules {
+    routes: Vec<CorsRoute>,
+}
+
+impl CorsRules {
+    /// No routes: the gateway adds no CORS headers and forwards preflights
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compile(config: &[CorsRouteConfig]) -> Result<Self, CorsError> {
+        let mut routes = Vec::with_capacity(config.len());
+        for route in config {
+            if !route.path.starts_with('/') {
+                return Err(CorsError::InvalidPath(route.path.clone()));
+            }
+            let origins = match route.allowed_origins.iter().any(|origin| origin == "*") {
+                true => None,
+                false => Some(
+                    route
+                        .allowed_origins
+                        .iter()
+                        .map(|origin| match is_origin(origin) {
+                            true => Ok(origin.clone()),
+                            false => Err(CorsError::InvalidOrigin(origin.clone())),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?,
+                ),
+            };
+            if origins.is_none() && route.allow_credentials {
+                return Err(CorsError::WildcardCredentials(route.path.clone()));
+            }
+            let methods = match route.allowed_methods.is_empty() {
+                true => vec![Method::GET, Method::POST],
+                false => route
+                    .allowed_methods
+                    .iter()
+                    .map(|method| {
+                        Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                            .map_err(|_| CorsError::InvalidMethod(method.clone()))
+                    })
+                    .collect::<Result<_, _>>()?,
+            };
+            let headers = match route.allowed_headers.iter().any(|name| name == "*") {
+                true => None,
+                false if route.allowed_headers.is_empty() => {
+                    Some(vec![header::AUTHORIZATION, header::CONTENT_TYPE])
+                }
+                false => Some(header_names(&route.allowed_headers)?),
+            };
+            routes.push(CorsRoute {
+                path: route.path.clone(),
+                origins,
+                methods,
+                headers,
+                expose_headers: header_names(&route.expose_headers)?,
+                credentials: route.allow_credentials,
+                max_age_secs: route.max_age_secs,
+            });
+        }
+        Ok(Self { routes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    fn route(&self, path: &str) -> Option<&CorsRoute> {
+        self.routes.iter().find(|route| route.matches(path))
+    }
+
+    /// Answer to a CORS preflight of a configured route: 204 with the route's headers
+    /// when it allows the origin and method, else 403. `None` for other requests,
+    /// which are forwarded.
+    pub fn preflight_response(&self, request: &RequestHeader) -> Option<Result<ResponseHeader>> {
+        if request.method != Method::OPTIONS {
+            return None;
+        }
+        let origin = header_str(request, header::ORIGIN)?;
+        let method = header_str(request, header::ACCESS_CONTROL_REQUEST_METHOD)?;
+        let route = self.route(request.uri.path())?;
+        Some(preflight(route, origin, method, request))
+    }
+
+    /// Add the `Access-Control-*` headers to the response to a request from an origin
+    /// its route allows
+    pub fn apply(&self, request: &RequestHeader, response: &mut ResponseHeader) -> Result<()> {
+        let Some(origin) = header_str(request, header::ORIGIN) else {
+            return Ok(());
+        };
+        match self.route(request.uri.path()) {
+            Some(route) if route.allows_origin(origin) => route.apply(origin, response),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn preflight(
+    route: &CorsRoute,
+    origin: &str,
+    method: &str,
+    request: &RequestHeader,
+) -> Result<ResponseHeader> {
+    let requested_headers = header_str(request, header::ACCESS_CONTROL_REQUEST_HEADERS);
+    let allowed = route.allows_origin(origin)
+        && route
+            .methods
+            .iter()
+            .any(|allowed| allowed.as_str() == method)
+        && route.headers.as_ref().is_none_or(|allowed| {
+            requested_headers
+                .into_iter()
+                .flat_map(|names| names.split(','))
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .all(|name| {
+                    allowed
+                        .iter()
+                        .any(|allowed| name.eq_ignore_ascii_case(allowed.as_str()))
+                })
+        });
+    if !allowed {
+        let mut response = ResponseHeader::build(403, Some(1))?;
+        response.insert_header(header::CONTENT_LENGTH, 0)?;
+        return Ok(response);
+    }
+    let mut response = ResponseHeader::build(204, Some(8))?;
+    route.apply(origin, &mut response)?;
+    response.insert_header(header::ACCESS_CONTROL_ALLOW_METHODS, join(&route.methods))?;
+    match (&route.headers, requested_headers) {
+        (Some(headers), _) => {
+            response.insert_header(header::ACCESS_CONTROL_ALLOW_HEADERS, join(headers))?
+        }
+        // Any header: the ones asked for
+        (None, Some(requested)) => {
+            response.insert_header(header::ACCESS_CONTROL_ALLOW_HEADERS, requested)?
+        }
+        (None, None) => {}
+    }
+    if let Some(max_age) = route.max_age_secs {
+        response.insert_header(header::ACCESS_CONTROL_MAX_AGE, max_age)?;
+    }
+    Ok(response)
+}
+
+/// `scheme://host[:port]`, as browsers send in `Origin`
+fn is_origin(origin: &str) -> bool {
+    origin.split_once("://").is_some_and(|(scheme, authority)| {
+        !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+            && !authority.is_empty()
+            && !authority.contains(['/', '?', '#', ' '])
+    })
+}
+
+fn header_names(names: &[String]) -> Result<Vec<HeaderName>, CorsError> {
+    names
+        .iter()
+        .map(|name| {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| CorsError::InvalidHeader(name.clone()))
+        })
+        .collect()
+}
+
+fn header_str(request: &RequestHeader, name: HeaderName) -> Option<&str> {
+    request.headers.get(name)?.to_str().ok()
+}
+
+fn join<T: AsRef<str>>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,17 +1,265 @@
-use crate::provider::ProviderKind;
-use std::time::Instant;
+use crate::key_stats::KeyRequest;
+use crate::metrics::InFlight;
+use crate::pipeline::caller::Caller;
+use crate::pipeline::dedup::DedupCapture;
+use crate::pipeline::deprecation::ModelDeprecation;
+use crate::pipeline::injection::InjectionVerdict;
+use crate::pipeline::output_filter::OutputFilter;
+use crate::pipeline::semantic_cache::SemanticKey;
+use crate::pipeline::transform::ResponseTransform;
+use crate::pipeline::usage::{ResponseUsage, StreamUsage, Usage};
+use crate::provider::{ProviderKind, StreamFormat};
+use crate::proxy::attempts::AttemptTrace;
+#[cfg(feature = "capability")]
+use crate::proxy::capability::CapabilityScope;
+use crate::proxy::capture::PayloadRecorder;
+use crate::proxy::client_certs::ClientIdentity;
+use crate::proxy::grpc::GrpcCall;
+#[cfg(feature = "jwt")]
+use crate::proxy::jwt::JwtClaims;
+#[cfg(feature = "provenance")]
+use crate::proxy::provenance::{ContentHasher, ProvenanceRecord};
+use crate::proxy::scrub::BodyScrubber;
+#[cfg(feature = "signing")]
+use crate::proxy::signing::BodyCheck;
+use crate::proxy::timing::PhaseTimer;
+#[cfg(feature = "virtual-keys")]
+use crate::proxy::virtual_keys::VirtualKey;
+use crate::proxy::websocket::WebSocketSession;
+#[cfg(feature = "translate")]
+use crate::translate::stream::StreamTranslator;
+#[cfg(feature = "translate")]
+use crate::translate::{Dialect, Endpoint};
+use crate::upstream::{CapPermit, Credential, LimiterPermit, PoolRoute, Upstream};
+use bytes::Bytes;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub struct Ctx {
     pub provider: ProviderKind,
-    pub start: Option<Instant>,
+    /// Format the provider streams responses in
+    pub stream_format: StreamFormat,
+    /// Allowlisted non-LLM request forwarded without the LLM pipeline
+    pub passthrough: bool,
+    /// WebSocket the client asked to open (e.g. OpenAI Realtime), with the messages
+    /// and usage seen over its lifetime once the upstream accepted it
+    pub websocket: Option<WebSocketSession>,
+    /// gRPC call forwarded as passthrough traffic, with the status it ended with
+    pub grpc: Option<GrpcCall>,
+    /// Phase marks recorded by each stage of the request
+    pub timer: PhaseTimer,
+    /// Upstreams tried for the request, in order
+    pub attempts: AttemptTrace,
+    /// Upstream pool and canary split arm the request is routed to, chosen once so
+    /// retries stay on the same arm
+    pub pool: Option<PoolRoute>,
+    /// Backoff before the next attempt of a request retried under the retry policy
+    pub retry_backoff: Option<Duration>,
+    /// When the upstream last sent part of the response body, for the pool's idle
+    /// timeout
+    pub upstream_read: Option<Instant>,
+    /// Upstream selected for this request
+    pub upstream: Option<Arc<Upstream>>,
+    /// Provider key the gateway sent upstream, if it manages credentials
+    pub credential: Option<Arc<Credential>>,
+    /// Provider key of the credential route matching the request, loaded from the
+    /// secret store
+    pub injected_credential: Option<Arc<Credential>>,
+    /// Whether the selected upstream was cold when the request was routed
+    pub cold_start: bool,
+    /// Concurrency slot held on the selected upstream for the lifetime of the request
+    pub concurrency_permit: Option<LimiterPermit>,
+    /// Slot held under the selected upstream's concurrency cap until the request ends
+    pub cap_permit: Option<CapPermit>,
+    /// Counts the request as in flight to its upstream until released
+    pub in_flight: Option<InFlight>,
+    /// Counts the request as in flight for its client key until finished
+    pub key_request: Option<KeyRequest>,
+    /// Client the request is made by, keying the responses stored for replay
+    pub caller: Caller,
+    /// Dedup key owned by this request; its response is stored for replay
+    pub dedup_key: Option<String>,
+    /// Response captured for dedup replay
+    pub dedup_capture: Option<DedupCapture>,
+    /// Response cache key of a request that missed the cache; its response is cached
+    pub cache_key: Option<String>,
+    /// Response captured for the response cache
+    pub cache_capture: Option<DedupCapture>,
+    /// Semantic cache key of a request that missed the semantic cache; its response is
+    /// cached under it
+    pub semantic_key: Option<SemanticKey>,
+    /// Response captured for the semantic cache
+    pub semantic_capture: Option<DedupCapture>,
+    /// Credential masking of an upstream error body relayed to the client
+    pub error_scrubber: Option<BodyScrubber>,
+    /// Response transform of the request, with the response body buffered for it
+    pub response_transform: Option<(Arc<ResponseTransform>, Vec<u8>)>,
+    /// ISO 639-1 code of the prompt language, when language detection is enabled and
+    /// found one
+    pub language: Option<&'static str>,
+    /// Why the request is suspected of prompt injection, when screened and suspicious
+    pub prompt_injection: Option<InjectionVerdict>,
+    /// Categories the prompt was flagged in, when moderation annotated the request
+    pub moderation: Option<Vec<String>>,
+    /// Completion captured for moderation once delivered
+    pub moderation_capture: Option<DedupCapture>,
+    /// Request body buffered to estimate prompt tokens and find the model
+    pub request_body: Vec<u8>,
+    /// Model named in the request body or path (Bedrock `/model/{id}/`)
+    pub model: Option<String>,
+    /// Estimated prompt tokens, once the request body is complete
+    pub prompt_tokens: Option<u64>,
+    /// Usage tracking of a streamed response
+    pub stream_usage: Option<StreamUsage>,
+    /// Usage tracking of a JSON (non-streamed) response
+    pub response_usage: Option<ResponseUsage>,
+    /// Token usage, once the response is complete
+    pub usage: Option<Usage>,
+    /// The gateway's own token count of the request, with the configured tokenizer,
+    /// kept apart from `usage` so estimates can be compared with reported usage
+    pub usage_estimate: Option<Usage>,
+    /// Cost of the usage in USD, when pricing is configured and the model is priced
+    pub cost: Option<f64>,
+    /// Stop sequences and content filters applied to a streamed response
+    pub output_filter: Option<OutputFilter>,
+    /// Output token cap of this request
+    pub output_token_cap: Option<u64>,
+    /// Set once the gateway ended a streamed response early (output token cap, stop
+    /// sequence or content filter)
+    pub stream_ended: bool,
+    /// Payloads of a request sampled by a capture window
+    pub payload_capture: Option<PayloadRecorder>,
+    /// Deprecation of the requested model, reported in the response headers
+    pub deprecation: Option<ModelDeprecation>,
+    /// Body the gateway rewrote (e.g. to a deprecated model's replacement), sent and
+    /// translated uncompressed in place of the client's
+    pub rewritten_body: Option<Bytes>,
+    /// Dialect the request was translated to for its upstream
+    #[cfg(feature = "translate")]
+    pub translation: Option<Dialect>,
+    /// OpenAI endpoint of the translated request
+    #[cfg(feature = "translate")]
+    pub translated_endpoint: Option<Endpoint>,
+    /// Translated body, sent upstream in place of the client's
+    #[cfg(feature = "translate")]
+    pub translated_body: Option<Bytes>,
+    /// Response body buffered to be translated back to the OpenAI API
+    #[cfg(feature = "translate")]
+    pub translated_response: Option<Vec<u8>>,
+    /// Status and upstream error type of a buffered error response
+    #[cfg(feature = "translate")]
+    pub translated_error: Option<(u16, Option<String>)>,
+    /// Streamed response being re-emitted as Chat Completions chunks
+    #[cfg(feature = "translate")]
+    pub translated_stream: Option<StreamTranslator>,
+    /// Index of the unified API model route serving the request
+    #[cfg(feature = "translate")]
+    pub model_route: Option<usize>,
+    /// Hash of a signed request's body, checked against its signature at the end
+    #[cfg(feature = "signing")]
+    pub body_check: Option<BodyCheck>,
+    /// Identity of the verified client certificate of the connection
+    pub client_identity: Option<ClientIdentity>,
+    /// Scope of the capability token the request was made with
+    #[cfg(feature = "capability")]
+    pub capability: Option<CapabilityScope>,
+    /// Subject and organization of the JWT the request was made with
+    #[cfg(feature = "jwt")]
+    pub jwt: Option<JwtClaims>,
+    /// Virtual key the request was made with
+    #[cfg(feature = "virtual-keys")]
+    pub virtual_key: Option<Arc<VirtualKey>>,
+    /// Provenance of the response, audited once it completes
+    #[cfg(feature = "provenance")]
+    pub provenance: Option<ProvenanceRecord>,
+    /// Hash of the response body sent so far, for the provenance audit entry
+    #[cfg(feature = "provenance")]
+    pub content_hasher: Option<ContentHasher>,
 }
 
 impl Default for Ctx {
     fn default() -> Self {
         Self {
             provider: ProviderKind::Unknown,
-            start: None,
+            stream_format: StreamFormat::ServerSentEvents,
+            passthrough: false,
+            websocket: None,
+            grpc: None,
+            timer: PhaseTimer::new(),
+            attempts: AttemptTrace::new(),
+            pool: None,
+            retry_backoff: None,
+            upstream_read: None,
+            upstream: None,
+            credential: None,
+            injected_credential: None,
+            cold_start: false,
+            concurrency_permit: None,
+            cap_permit: None,
+            in_flight: None,
+            key_request: None,
+            caller: Caller::default(),
+            dedup_key: None,
+            dedup_capture: None,
+            cache_key: None,
+            cache_capture: None,
+            semantic_key: None,
+            semantic_capture: None,
+            error_scrubber: None,
+            response_transform: None,
+            language: None,
+            prompt_injection: None,
+            moderation: None,
+            moderation_capture: None,
+            request_body: Vec::new(),
+            model: None,
+            prompt_tokens: None,
+            stream_usage: None,
+            response_usage: None,
+            usage: None,
+            usage_estimate: None,
+            cost: None,
+            output_filter: None,
+            output_token_cap: None,
+            stream_ended: false,
+            payload_capture: None,
+            deprecation: None,
+            rewritten_body: None,
+            #[cfg(feature = "translate")]
+            translation: None,
+            #[cfg(feature = "translate")]
+            translated_endpoint: None,
+            #[cfg(feature = "translate")]
+            translated_body: None,
+            #[cfg(feature = "translate")]
+            translated_response: None,
+            #[cfg(feature = "translate")]
+            translated_error: None,
+            #[cfg(feature = "translate")]
+            translated_stream: None,
+            #[cfg(feature = "translate")]
+            model_route: None,
+            #[cfg(feature = "signing")]
+            body_check: None,
+            client_identity: None,
+            #[cfg(feature = "capability")]
+            capability: None,
+            #[cfg(feature = "jwt")]
+            jwt: None,
+            #[cfg(feature = "virtual-keys")]
+            virtual_key: None,
+            #[cfg(feature = "provenance")]
+            provenance: None,
+            #[cfg(feature = "provenance")]
+            content_hasher: None,
         }
     }
 }
+
+impl Ctx {
+    /// Record a phase mark, e.g. `ctx.mark("detect_done")`
+    pub fn mark(&mut self, name: &'static str) {
+        self.timer.mark(name);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::provider::DetectionResult;
+use std::fmt;
+
+/// Rough prompt size estimate: ~4 bytes of JSON per token. No tokenizer is involved,
+/// so this is only meant for spotting unexpectedly large requests.
+pub const BYTES_PER_TOKEN: usize = 4;
+
+/// Offline trace of how the gateway would handle a request: detection, policies and
+/// routing, computed without sending any traffic. Built by `GatewayProxy::explain`
+/// and printed by `langspec explain`.
+#[derive(Debug, Clone, Default)]
+pub struct Explanation {
+    pub method: String,
+    pub path: String,
+    pub host: Option<String>,
+    /// Matched the passthrough allowlist (the rest of the pipeline is skipped)
+    pub passthrough: bool,
+    /// Result of each provider, in chain order
+    pub candidates: Vec<(&'static str, Option<DetectionResult>)>,
+    /// Winning detection (including an explicit override)
+    pub decision: Option<DetectionResult>,
+    /// Status strict mode would reject the request with
+    pub rejected: Option<u16>,
+    /// Upstream pool the request path is routed to
+    pub pool: Option<String>,
+    /// Upstreams in the order they would be tried, with notes on their state
+    pub upstreams: Vec<(String, Vec<&'static str>)>,
+    /// Headers of the request as it would be sent upstream
+    pub upstream_headers: Vec<(String, String)>,
+    /// Request body size in bytes, when a body was given
+    pub body_bytes: Option<usize>,
+}
+
+impl Explanation {
+    /// Estimated prompt tokens for the request body
+    pub fn estimated_tokens(&self) -> Option<usize> {
+        self.body_bytes.map(|bytes| bytes.div_ceil(BYTES_PER_TOKEN))
+    }
+}
+
+fn describe(result: &DetectionResult) -> String {
+    format!(
+        "{} ({} via {}: {})",
+        result.kind.as_str(),
+        result.confidence.as_str(),
+        result.signal,
+        result.reason
+    )
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request:   {} {}", self.method, self.path)?;
+        match &self.host {
+            Some(host) => writeln!(f, " (host {})", host)?,
+            None => writeln!(f, " (no host)")?,
+        }
+
+        if self.passthrough {
+            writeln!(
+                f,
+                "Pipeline:  skipped, request matches the passthrough allowlist"
+            )?;
+        } else {
+            writeln!(f, "Detection:")?;
+            for (provider, result) in &self.candidates {
+                match result {
+                    Some(result) if result.is_decisive() => {
+                        writeln!(f, "  {:<10} {} [decisive]", provider, describe(result))?
+                    }
+                    Some(result) => writeln!(f, "  {:<10} {}", provider, describe(result))?,
+                    None => writeln!(f, "  {:<10} no match", provider)?,
+                }
+            }
+            match &self.decision {
+                Some(result) => writeln!(f, "Provider:  {}", describe(result))?,
+                None => writeln!(f, "Provider:  unknown (no provider matched)")?,
+            }
+        }
+
+        if let Some(status) = self.rejected {
+            writeln!(f, "Strict:    rejected with {}, not forwarded", status)?;
+            return Ok(());
+        }
+
+        match &self.pool {
+            Some(pool) => writeln!(f, "Routing:   pool {}", pool)?,
+            None => writeln!(f, "Routing:")?,
+        }
+        for (index, (upstream, notes)) in self.upstreams.iter().enumerate() {
+            write!(f, "  {}. {}", index + 1, upstream)?;
+            if !notes.is_empty() {
+                write!(f, " ({})", notes.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, "Upstream request headers:")?;
+        for (name, value) in &self.upstream_headers {
+            writeln!(f, "  {}: {}", name, value)?;
+        }
+
+        if let (Some(bytes), Some(tokens)) = (self.body_bytes, self.estimated_tokens()) {
+            writeln!(
+                f,
+                "Body:      {} bytes, ~{} prompt tokens (estimate)",
+                bytes, tokens
+            )?;
+        }
+        Ok(())
+    }
+}
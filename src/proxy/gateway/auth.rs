@@ -0,0 +1,435 @@
+//! Authentication stages: attributing a request to its tenant by listener or client
+//! certificate, and verifying signatures, capability tokens, virtual keys and JWTs.
+
+#[cfg(any(feature = "capability", feature = "jwt", feature = "virtual-keys"))]
+use log::info;
+use log::warn;
+use pingora::prelude::*;
+use pingora::proxy::Session;
+#[cfg(feature = "jwt")]
+use std::sync::Arc;
+
+#[cfg(any(feature = "capability", feature = "virtual-keys"))]
+use super::read_body_ahead;
+use super::{GatewayProxy, reject};
+#[cfg(feature = "jwt")]
+use crate::alerts::split_url;
+#[cfg(feature = "capability")]
+use crate::metrics::CAPABILITY_REQUESTS;
+use crate::metrics::CLIENT_CERT_REQUESTS;
+#[cfg(feature = "jwt")]
+use crate::metrics::JWT_REQUESTS;
+#[cfg(feature = "signing")]
+use crate::metrics::SIGNED_REQUESTS;
+#[cfg(feature = "virtual-keys")]
+use crate::metrics::VIRTUAL_KEY_REQUESTS;
+#[cfg(any(feature = "capability", feature = "virtual-keys"))]
+use crate::pipeline::usage::{MODEL_BODY_BYTES, request_model};
+#[cfg(any(feature = "capability", feature = "jwt", feature = "virtual-keys"))]
+use crate::pipeline::views::RequestView;
+#[cfg(feature = "capability")]
+use crate::proxy::capability::{self, CapabilityError, CapabilityTokens};
+use crate::proxy::ctx::Ctx;
+#[cfg(feature = "jwt")]
+use crate::proxy::jwt::{self, Jwks, JwtError, JwtValidator};
+use crate::proxy::listeners::ListenerAddr;
+#[cfg(feature = "signing")]
+use crate::proxy::signing::{SignatureError, SignatureVerifier};
+#[cfg(feature = "virtual-keys")]
+use crate::proxy::virtual_keys::{self, KeyRejection, VirtualKeys};
+#[cfg(feature = "jwt")]
+use crate::upstream::Upstream;
+
+impl GatewayProxy {
+    /// Set the tenant of a request from the listener it arrived on. With exclusive
+    /// listeners, a request whose client claims a tenant with its own listener
+    /// (`claimed`) on another listener is rejected with 403. Returns whether the request
+    /// was rejected.
+    pub(super) async fn attribute_listener(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        claimed: Option<&str>,
+    ) -> Result<bool> {
+        let Some(listeners) = &self.tenant_listeners else {
+            return Ok(false);
+        };
+        let local = session.server_addr().and_then(|addr| match addr.as_inet() {
+            Some(addr) => Some(ListenerAddr::Tcp(*addr)),
+            None => addr
+                .as_unix()
+                .and_then(|addr| addr.as_pathname())
+                .map(|path| ListenerAddr::Unix(path.to_path_buf())),
+        });
+        let listener_tenant = local
+            .as_ref()
+            .and_then(|local| listeners.tenant_for(local))
+            .map(str::to_string);
+        if let Some(tenant) = listener_tenant {
+            session
+                .req_header_mut()
+                .insert_header("X-Langspec-Tenant", tenant.as_str())?;
+            ctx.caller.authenticate(tenant);
+            return Ok(false);
+        }
+        let Some(tenant) =
+            claimed.filter(|tenant| listeners.exclusive && listeners.has_listener(tenant))
+        else {
+            return Ok(false);
+        };
+        warn!(
+            "Rejecting request as tenant '{}' outside of its listener",
+            tenant
+        );
+        reject(
+            session,
+            403,
+            "tenant_listener_required",
+            &format!("Tenant '{}' must use its dedicated listener", tenant),
+        )
+        .await
+    }
+
+    /// Set the tenant of a request from the verified client certificate of its
+    /// connection. Requests with a certificate no rule maps to a tenant, or whose
+    /// identity is no longer known, are rejected with 403. Returns whether the request
+    /// was rejected.
+    pub(super) async fn attribute_client_cert(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> Result<bool> {
+        let Some(certs) = &self.client_certs else {
+            return Ok(false);
+        };
+        let Some(digest) = session
+            .digest()
+            .and_then(|digest| digest.ssl_digest.as_ref())
+            .map(|ssl| ssl.cert_digest.clone())
+            .filter(|digest| !digest.is_empty())
+        else {
+            return Ok(false);
+        };
+        let (outcome, message, code) = match certs.identity(&digest) {
+            Some(identity) if identity.tenant.is_some() || certs.tenants().is_empty() => {
+                if let Some(tenant) = &identity.tenant {
+                    session
+                        .req_header_mut()
+                        .insert_header("X-Langspec-Tenant", tenant.as_str())?;
+                    ctx.caller.authenticate(tenant.as_str());
+                }
+                CLIENT_CERT_REQUESTS.with_label_values(&["accepted"]).inc();
+                ctx.client_identity = Some(identity);
+                return Ok(false);
+            }
+            Some(_) => (
+                "unmapped",
+                "Client certificate is not mapped to a tenant",
+                "client_certificate_unmapped",
+            ),
+            // Verified, but forgotten since: a new handshake verifies it again
+            None => (
+                "unknown",
+                "Client certificate is no longer known, reconnect",
+                "client_certificate_unknown",
+            ),
+        };
+        CLIENT_CERT_REQUESTS.with_label_values(&[outcome]).inc();
+        warn!(
+            "Rejecting request: {}: {} {}",
+            message,
+            session.req_header().method,
+            session.req_header().uri.path()
+        );
+        reject(session, 403, code, message).await
+    }
+
+    /// Check a request's capability token and its scope, make the request as the token's
+    /// tenant and strip the token. Returns whether the request was rejected.
+    #[cfg(feature = "capability")]
+    pub(super) async fn enforce_capability(
+        &self,
+        tokens: &CapabilityTokens,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> Result<bool> {
+        let Some(token) = capability::request_token(session.req_header()) else {
+            return Ok(false);
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let scope = tokens.validate(token, now).and_then(|scope| {
+            match RequestView::new(session.req_header()).tenant() {
+                Some(tenant) if tenant != scope.tenant => Err(CapabilityError::TenantMismatch),
+                _ => Ok(scope),
+            }
+        });
+        let scope = match scope {
+            Ok(scope) => scope,
+            Err(e) => return reject_capability(session, e).await,
+        };
+
+        // The model and output tokens asked for are in the body, unless the path names
+        // the model (Bedrock); a gRPC call's protobuf body names neither
+        let restricted = !scope.models.is_empty() || scope.max_tokens.is_some();
+        let body = if restricted && ctx.grpc.is_none() && !session.as_mut().is_body_empty() {
+            let Some(body) = read_body_ahead(session, MODEL_BODY_BYTES).await? else {
+                CAPABILITY_REQUESTS.with_label_values(&["too_large"]).inc();
+                return Err(Error::explain(
+                    HTTPStatus(413),
+                    "request body too large (or of unknown length) to check against its capability token",
+                ));
+            };
+            Some(body)
+        } else {
+            None
+        };
+        let model = body
+            .as_deref()
+            .and_then(request_model)
+            .or_else(|| ctx.model.clone());
+        let requested = body.as_deref().and_then(capability::requested_max_tokens);
+        if let Err(e) = scope.authorize(model.as_deref(), requested) {
+            return reject_capability(session, e).await;
+        }
+
+        let request = session.req_header_mut();
+        request.remove_header(CapabilityTokens::HEADER);
+        if request
+            .headers
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| token.trim().starts_with(CapabilityTokens::PREFIX))
+        {
+            request.remove_header(&http::header::AUTHORIZATION);
+        }
+        request.insert_header("X-Langspec-Tenant", scope.tenant.as_str())?;
+        ctx.caller.authenticate(scope.tenant.as_str());
+        CAPABILITY_REQUESTS.with_label_values(&["accepted"]).inc();
+        ctx.capability = Some(scope);
+        Ok(false)
+    }
+
+    /// Check a request's virtual key and the model it asks for, make the request as the
+    /// key's tenant and strip the key. Returns whether the request was rejected.
+    #[cfg(feature = "virtual-keys")]
+    pub(super) async fn enforce_virtual_key(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> Result<bool> {
+        let keys = self.pipeline.virtual_keys();
+        if keys.is_empty() {
+            return Ok(false);
+        }
+        let Some(presented) = virtual_keys::request_key(session.req_header()) else {
+            return Ok(false);
+        };
+        let key = keys.authenticate(presented).and_then(|key| {
+            match RequestView::new(session.req_header()).tenant() {
+                Some(tenant) if tenant != key.name => Err(KeyRejection::TenantMismatch),
+                _ => Ok(key),
+            }
+        });
+        let key = match key {
+            Ok(key) => key,
+            Err(e) => return reject_virtual_key(session, e).await,
+        };
+
+        // The model is in the body, unless the path names it (Bedrock); a gRPC call's
+        // protobuf body names none
+        let body = if !key.models.is_empty()
+            && ctx.grpc.is_none()
+            && !session.as_mut().is_body_empty()
+        {
+            let Some(body) = read_body_ahead(session, MODEL_BODY_BYTES).await? else {
+                VIRTUAL_KEY_REQUESTS.with_label_values(&["too_large"]).inc();
+                return Err(Error::explain(
+                    HTTPStatus(413),
+                    "request body too large (or of unknown length) to check against its virtual key",
+                ));
+            };
+            Some(body)
+        } else {
+            None
+        };
+        let model = body
+            .as_deref()
+            .and_then(request_model)
+            .or_else(|| ctx.model.clone());
+        if let Err(e) = key.authorize(model.as_deref()) {
+            return reject_virtual_key(session, e).await;
+        }
+
+        let request = session.req_header_mut();
+        request.remove_header(VirtualKeys::HEADER);
+        if request
+            .headers
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| token.trim().starts_with(VirtualKeys::PREFIX))
+        {
+            request.remove_header(&http::header::AUTHORIZATION);
+        }
+        request.insert_header("X-Langspec-Tenant", key.name.as_str())?;
+        ctx.caller.authenticate(key.name.as_str());
+        VIRTUAL_KEY_REQUESTS.with_label_values(&["accepted"]).inc();
+        ctx.virtual_key = Some(key);
+        Ok(false)
+    }
+
+    /// Check the request's JWT, then strip it and make the request as the token's
+    /// organization. Requests authenticated with a capability token or virtual key need
+    /// no JWT.
+    #[cfg(feature = "jwt")]
+    pub(super) async fn enforce_jwt(&self, session: &mut Session, ctx: &mut Ctx) -> Result<bool> {
+        let Some(validator) = self.pipeline.jwt() else {
+            return Ok(false);
+        };
+        let Some(token) = jwt::request_token(session.req_header()).map(str::to_string) else {
+            #[cfg(feature = "capability")]
+            let capability = ctx.capability.is_some();
+            #[cfg(not(feature = "capability"))]
+            let capability = false;
+            #[cfg(feature = "virtual-keys")]
+            let virtual_key = ctx.virtual_key.is_some();
+            #[cfg(not(feature = "virtual-keys"))]
+            let virtual_key = false;
+            if !validator.config.required || capability || virtual_key {
+                return Ok(false);
+            }
+            return reject_jwt(session, JwtError::Missing).await;
+        };
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let claims = self
+            .jwt_keys(&validator, &token)
+            .await
+            .and_then(|keys| validator.validate(&token, &keys, now))
+            .and_then(
+                |claims| match RequestView::new(session.req_header()).tenant() {
+                    Some(tenant) if claims.org.as_deref().is_some_and(|org| org != tenant) => {
+                        Err(JwtError::TenantMismatch)
+                    }
+                    _ => Ok(claims),
+                },
+            );
+        let claims = match claims {
+            Ok(claims) => claims,
+            Err(e) => return reject_jwt(session, e).await,
+        };
+
+        let request = session.req_header_mut();
+        request.remove_header(&http::header::AUTHORIZATION);
+        if let Some(org) = &claims.org {
+            request.insert_header("X-Langspec-Tenant", org.as_str())?;
+            ctx.caller.authenticate(org.as_str());
+        }
+        JWT_REQUESTS.with_label_values(&["accepted"]).inc();
+        ctx.jwt = Some(claims);
+        Ok(false)
+    }
+
+    /// Keys to check `token` with: the cached JWKS while it is fresh and has the token's
+    /// key, fetched again otherwise. When the fetch fails the cached keys are used.
+    #[cfg(feature = "jwt")]
+    async fn jwt_keys(&self, validator: &JwtValidator, token: &str) -> Result<Arc<Jwks>, JwtError> {
+        let (kid, algorithm) = JwtValidator::header(token)?;
+        if let Some(keys) = validator.fresh_keys()
+            && (keys.find(kid.as_deref(), algorithm).is_some() || !validator.may_refetch())
+        {
+            return Ok(keys);
+        }
+        let url = &validator.config.jwks_url;
+        let (base, path) = split_url(url);
+        let headers = [("accept", "application/json".to_string())];
+        let fetched = self
+            .jwks_client
+            .request(&Upstream::new(base), "GET", path, &headers, None)
+            .await
+            .and_then(|(status, body)| match status {
+                200 => Jwks::from_json(&body),
+                status => Err(format!("status {}", status)),
+            });
+        match fetched {
+            Ok(keys) => Ok(validator.set_keys(keys)),
+            Err(e) => {
+                warn!("Failed to fetch JWKS from {}: {}", url, e);
+                validator.cached_keys().ok_or(JwtError::KeysUnavailable(e))
+            }
+        }
+    }
+}
+
+/// Verify a request's signature, returning the tenant its key is bound to; a signed
+/// body is checked as it is forwarded
+#[cfg(feature = "signing")]
+pub(super) fn verify_signature(
+    verifier: &SignatureVerifier,
+    session: &mut Session,
+    ctx: &mut Ctx,
+) -> std::result::Result<Option<String>, SignatureError> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let Some(signature) = verifier.verify(session.req_header(), now)? else {
+        SIGNED_REQUESTS.with_label_values(&["unsigned"]).inc();
+        return Ok(None);
+    };
+    let check = signature.body_check();
+    // An upgrade request has no body, only the frames that follow it
+    if ctx.websocket.is_some() || session.as_mut().is_body_empty() {
+        check.finish()?;
+        SIGNED_REQUESTS.with_label_values(&["verified"]).inc();
+    } else {
+        ctx.body_check = Some(check);
+    }
+    Ok(signature.tenant)
+}
+
+/// Reject a request whose capability token is not valid or does not cover it
+#[cfg(feature = "capability")]
+async fn reject_capability(session: &mut Session, e: CapabilityError) -> Result<bool> {
+    info!(
+        "Rejecting request: {}: {} {}",
+        e,
+        session.req_header().method,
+        session.req_header().uri.path()
+    );
+    CAPABILITY_REQUESTS.with_label_values(&[e.as_str()]).inc();
+    session.respond_error(e.status()).await?;
+    Ok(true)
+}
+
+/// Reject a request without a valid JWT
+#[cfg(feature = "jwt")]
+async fn reject_jwt(session: &mut Session, e: JwtError) -> Result<bool> {
+    info!(
+        "Rejecting request: {}: {} {}",
+        e,
+        session.req_header().method,
+        session.req_header().uri.path()
+    );
+    JWT_REQUESTS.with_label_values(&[e.as_str()]).inc();
+    session.respond_error(e.status()).await?;
+    Ok(true)
+}
+
+/// Reject a request whose virtual key is not valid or does not cover it
+#[cfg(feature = "virtual-keys")]
+async fn reject_virtual_key(session: &mut Session, e: KeyRejection) -> Result<bool> {
+    info!(
+        "Rejecting request: {}: {} {}",
+        e,
+        session.req_header().method,
+        session.req_header().uri.path()
+    );
+    VIRTUAL_KEY_REQUESTS.with_label_values(&[e.as_str()]).inc();
+    session.respond_error(e.status()).await?;
+    Ok(true)
+}
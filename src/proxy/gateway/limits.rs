@@ -0,0 +1,125 @@
+//! Admission limits: per-caller rate limits, per-tenant concurrency limits and spend
+//! budgets.
+
+use bytes::Bytes;
+use log::{info, warn};
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+use pingora::proxy::Session;
+use std::time::Instant;
+
+use super::{GatewayProxy, error_body, reject};
+use crate::metrics::{BUDGET_REJECTIONS, KEY_CONCURRENCY_REJECTIONS, RATE_LIMITED};
+use crate::proxy::ctx::Ctx;
+
+impl GatewayProxy {
+    /// Take a token from each rate limit applying to the request, rejecting it with 429
+    /// once one is exhausted. Returns whether the request was rejected.
+    pub(super) async fn enforce_rate_limit(
+        &self,
+        session: &mut Session,
+        ctx: &Ctx,
+    ) -> Result<bool> {
+        let limiter = self.pipeline.rate_limiter();
+        if limiter.policy().is_empty() {
+            return Ok(false);
+        }
+        // Clients are told apart by the tenant they authenticated as, else by the
+        // credentials they present, else by IP address
+        let client = match ctx.caller.principal() {
+            Some(tenant) => tenant.to_string(),
+            None if !ctx.caller.is_anonymous() => ctx.caller.to_string(),
+            None => self
+                .client_ip(session)
+                .map_or_else(String::new, |ip| ip.to_string()),
+        };
+        let Err(limited) = limiter.check(&client, ctx.provider.as_str(), Instant::now()) else {
+            return Ok(false);
+        };
+        info!(
+            "Rejecting request of '{}': rate limit '{}' exceeded, retry in {:?}",
+            client, limited.limit, limited.retry_after
+        );
+        RATE_LIMITED
+            .with_label_values(&[limited.limit.as_str(), limited.scope.as_str()])
+            .inc();
+        let message = format!(
+            "Rate limit '{}' exceeded; retry in {}s",
+            limited.limit,
+            limited.retry_after_secs()
+        );
+        let body = error_body(429, "rate_limit_exceeded", &message);
+        let body = Bytes::from(serde_json::to_vec(&body).expect("JSON values serialize"));
+        let mut header = ResponseHeader::build(429, Some(3))?;
+        header.insert_header(http::header::CONTENT_TYPE, "application/json")?;
+        header.insert_header(http::header::CONTENT_LENGTH, body.len())?;
+        header.insert_header(http::header::RETRY_AFTER, limited.retry_after_secs())?;
+        session
+            .write_response_header(Box::new(header), false)
+            .await?;
+        session.write_response_body(Some(body), true).await?;
+        Ok(true)
+    }
+
+    /// Count the request as in flight for the tenant it authenticated as, rejecting it
+    /// with 429 once the tenant is over its concurrency limit. Returns whether the
+    /// request was rejected.
+    pub(super) async fn track_key(&self, session: &mut Session, ctx: &mut Ctx) -> Result<bool> {
+        let Some(tenant) = ctx.caller.principal().map(str::to_string) else {
+            return Ok(false);
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let Some(request) = self.key_stats.start(&tenant, now) else {
+            return Ok(false);
+        };
+        if let Some(limit) = self.key_concurrency_limit
+            && request.in_flight() > limit
+        {
+            drop(request);
+            warn!(
+                "Rejecting request of tenant '{}': {} requests already in flight",
+                tenant, limit
+            );
+            KEY_CONCURRENCY_REJECTIONS
+                .with_label_values(&[tenant.as_str()])
+                .inc();
+            let message = format!("Too many concurrent requests (limit {})", limit);
+            return reject(session, 429, "key_concurrency_limit", &message).await;
+        }
+        ctx.key_request = Some(request);
+        Ok(false)
+    }
+
+    /// Reject a request of a tenant that exhausted one of its spend budgets. Returns
+    /// whether the request was rejected.
+    pub(super) async fn enforce_budget(&self, session: &mut Session, ctx: &Ctx) -> Result<bool> {
+        let budgets = self.pipeline.spend_budgets();
+        if budgets.policy().is_empty() {
+            return Ok(false);
+        }
+        // Only a tenant the request was authenticated as is charged
+        let Some(tenant) = ctx.caller.principal() else {
+            return Ok(false);
+        };
+        let Some(exhausted) = budgets.check(tenant, budgets.now()) else {
+            return Ok(false);
+        };
+        info!(
+            "Rejecting request of tenant '{}': spent ${:.6} of its ${} budget until {}",
+            tenant, exhausted.spent_usd, exhausted.limit_usd, exhausted.resets_at
+        );
+        BUDGET_REJECTIONS.with_label_values(&[tenant]).inc();
+        reject(
+            session,
+            402,
+            "budget_exceeded",
+            &format!(
+                "Spend budget of ${} exhausted; it resets at {}",
+                exhausted.limit_usd, exhausted.resets_at
+            ),
+        )
+        .await
+    }
+}
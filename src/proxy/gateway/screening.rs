@@ -0,0 +1,189 @@
+//! Prompt screening: the prompt injection classifier and content moderation of
+//! requests.
+
+use log::{info, warn};
+use pingora::prelude::*;
+use pingora::proxy::Session;
+
+use super::{GatewayProxy, JsonEndpoint, reject, request_body_ahead};
+use crate::metrics::{MODERATIONS, PROMPT_INJECTION_SCREENS};
+use crate::pipeline::injection::{InjectionAction, PromptInjectionScreen, parse_classifier_score};
+use crate::pipeline::moderation::{
+    ModerationAction, ModerationConfig, moderation_label, parse_moderation,
+};
+use crate::pipeline::rewrite::MAX_REQUEST_BYTES as MAX_REWRITTEN_REQUEST_BYTES;
+use crate::pipeline::usage::json_prompt_text;
+use crate::pipeline::views::RequestView;
+use crate::proxy::ctx::Ctx;
+
+impl GatewayProxy {
+    /// Screen the prompt of a request for prompt injection, by the patterns and then the
+    /// classifier, and tag, log or block a suspicious request. Bodies that are not JSON
+    /// or too large to read ahead are not screened; when the classifier fails, the
+    /// patterns alone decide. Returns whether the request was blocked.
+    pub(super) async fn screen_prompt(
+        &self,
+        (screen, classifier): &(PromptInjectionScreen, Option<JsonEndpoint>),
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> Result<bool> {
+        // Only the gateway tags requests
+        session
+            .req_header_mut()
+            .remove_header(PromptInjectionScreen::HEADER);
+        if ctx.passthrough || session.as_mut().is_body_empty() {
+            return Ok(false);
+        }
+        let body = request_body_ahead(session, ctx, MAX_REWRITTEN_REQUEST_BYTES).await?;
+        let Some(prompt) = body.as_deref().and_then(|body| screen.prompt(body)) else {
+            PROMPT_INJECTION_SCREENS
+                .with_label_values(&["not_screened"])
+                .inc();
+            return Ok(false);
+        };
+
+        let signals = screen.signals(&prompt);
+        let score = match classifier {
+            Some(classifier) if signals.is_empty() => {
+                let score = classifier
+                    .post(screen.classifier_request(&prompt))
+                    .await
+                    .and_then(|response| {
+                        parse_classifier_score(&response)
+                            .ok_or_else(|| "no score in the response".into())
+                    });
+                match score {
+                    Ok(score) => Some(score),
+                    Err(e) => {
+                        warn!(
+                            "Screening for prompt injection without the classifier: {}",
+                            e
+                        );
+                        PROMPT_INJECTION_SCREENS
+                            .with_label_values(&["classifier_error"])
+                            .inc();
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        let Some(verdict) = screen.verdict(signals, score) else {
+            PROMPT_INJECTION_SCREENS.with_label_values(&["clean"]).inc();
+            return Ok(false);
+        };
+
+        let action = screen.config().action;
+        let request_view = RequestView::new(session.req_header());
+        info!(
+            "Prompt injection suspected in {} (tenant {}), {}: {}",
+            request_view.path(),
+            request_view.tenant().unwrap_or("none"),
+            action.as_str(),
+            verdict
+        );
+        PROMPT_INJECTION_SCREENS
+            .with_label_values(&[action.as_str()])
+            .inc();
+        match action {
+            InjectionAction::Block => {
+                return reject(
+                    session,
+                    400,
+                    "prompt_injection_detected",
+                    "Request blocked: the prompt looks like a prompt injection attempt",
+                )
+                .await;
+            }
+            InjectionAction::Tag => {
+                session
+                    .req_header_mut()
+                    .insert_header(PromptInjectionScreen::HEADER, verdict.label())?;
+            }
+            InjectionAction::Log => {}
+        }
+        ctx.prompt_injection = Some(verdict);
+        Ok(false)
+    }
+
+    /// Moderate the prompt of a request under its route's policy, and block or annotate
+    /// it when flagged. Bodies that are not JSON or too large to read ahead are not
+    /// moderated; a failing moderation endpoint fails the stage. Returns whether the
+    /// request was blocked.
+    pub(super) async fn moderate_prompt(
+        &self,
+        (config, endpoint): &(ModerationConfig, JsonEndpoint),
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> Result<bool> {
+        // Only the gateway annotates requests
+        session
+            .req_header_mut()
+            .remove_header(ModerationConfig::HEADER);
+        let Some(policy) = config
+            .policy_for(&RequestView::new(session.req_header()))
+            .filter(|policy| policy.prompts)
+        else {
+            return Ok(false);
+        };
+        if ctx.passthrough || session.as_mut().is_body_empty() {
+            return Ok(false);
+        }
+        let body = request_body_ahead(session, ctx, MAX_REWRITTEN_REQUEST_BYTES).await?;
+        let Some(prompt) = body.as_deref().and_then(json_prompt_text) else {
+            MODERATIONS
+                .with_label_values(&["prompt", "not_moderated"])
+                .inc();
+            return Ok(false);
+        };
+        if prompt.is_empty() {
+            return Ok(false);
+        }
+
+        let result = endpoint
+            .post(config.moderation_request(&prompt))
+            .await
+            .and_then(|response| {
+                parse_moderation(&response).ok_or_else(|| "no results in the response".into())
+            })
+            .map_err(|e| {
+                MODERATIONS.with_label_values(&["prompt", "error"]).inc();
+                Error::explain(ErrorType::Custom("ModerationError"), e)
+            })?;
+        let Some(categories) = policy.violations(&result) else {
+            MODERATIONS.with_label_values(&["prompt", "passed"]).inc();
+            return Ok(false);
+        };
+
+        let label = moderation_label(&categories);
+        let request_view = RequestView::new(session.req_header());
+        info!(
+            "Prompt to {} (tenant {}) flagged by moderation ({}), {}",
+            request_view.path(),
+            request_view.tenant().unwrap_or("none"),
+            label,
+            policy.action.as_str()
+        );
+        MODERATIONS
+            .with_label_values(&["prompt", policy.action.as_str()])
+            .inc();
+        match policy.action {
+            ModerationAction::Block => {
+                reject(
+                    session,
+                    400,
+                    "content_moderation",
+                    &format!("Request blocked by content moderation: {}", label),
+                )
+                .await
+            }
+            ModerationAction::Annotate => {
+                session
+                    .req_header_mut()
+                    .insert_header(ModerationConfig::HEADER, label)?;
+                ctx.moderation = Some(categories);
+                Ok(false)
+            }
+        }
+    }
+}
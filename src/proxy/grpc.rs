@@ -0,0 +1,151 @@
+//! gRPC passthrough, for providers and inference servers exposing gRPC (Triton, Vertex
+//! AI).
+//!
+//! With gRPC enabled the proxy listeners also accept HTTP/2 (h2c on plaintext, ALPN on
+//! TLS). A request with a gRPC content type is forwarded like allowlisted passthrough
+//! traffic, since its body is protobuf rather than LLM JSON, to an upstream spoken to
+//! over HTTP/2 only. gRPC answers 200 whatever the outcome and sends its status in the
+//! response trailers (or in the headers of a trailers-only response), so [`GrpcCall`]
+//! reads `grpc-status` and `grpc-message` from both for the access log and metrics.
+//! gRPC-Web, which works over HTTP/1.1, is not a gRPC call here.
+
+use http::HeaderMap;
+use pingora_http::RequestHeader;
+use std::fmt;
+
+/// Status code of a gRPC call, from its `grpc-status` trailer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GrpcStatus(pub u32);
+
+impl GrpcStatus {
+    /// Canonical name, e.g. `UNAVAILABLE`; codes outside the spec are `UNKNOWN`
+    pub fn name(&self) -> &'static str {
+        match self.0 {
+            0 => "OK",
+            1 => "CANCELLED",
+            3 => "INVALID_ARGUMENT",
+            4 => "DEADLINE_EXCEEDED",
+            5 => "NOT_FOUND",
+            6 => "ALREADY_EXISTS",
+            7 => "PERMISSION_DENIED",
+            8 => "RESOURCE_EXHAUSTED",
+            9 => "FAILED_PRECONDITION",
+            10 => "ABORTED",
+            11 => "OUT_OF_RANGE",
+            12 => "UNIMPLEMENTED",
+            13 => "INTERNAL",
+            14 => "UNAVAILABLE",
+            15 => "DATA_LOSS",
+            16 => "UNAUTHENTICATED",
+            _ => "UNKNOWN",
+        }
+    }
+}
+
+impl fmt::Display for GrpcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.0, self.name())
+    }
+}
+
+/// A gRPC call passing through the gateway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrpcCall {
+    /// Fully qualified service, e.g. `inference.GRPCInferenceService`
+    pub service: String,
+    pub method: String,
+    /// Status the upstream ended the call with, once received
+    pub status: Option<GrpcStatus>,
+    /// Percent-decoded `grpc-message` sent with the status
+    pub message: Option<String>,
+}
+
+impl GrpcCall {
+    /// The call a request makes: `POST /{service}/{method}` with an `application/grpc`
+    /// (or `application/grpc+{format}`) content type; `None` for other requests
+    pub fn from_request(request: &RequestHeader) -> Option<Self> {
+        let content_type = request
+            .headers
+            .get(http::header::CONTENT_TYPE)?
+            .to_str()
+            .ok()?;
+        let media_type = content_type.split(';').next()?.trim();
+        let subtype = media_type
+            .get(..16)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("application/grpc"))
+            .map(|_| &media_type[16..])?;
+        if !(subtype.is_empty() || subtype.starts_with('+')) {
+            return None;
+        }
+        let (service, method) = request
+            .uri
+            .path()
+            .trim_start_matches('/')
+            .rsplit_once('/')?;
+        Some(Self {
+            service: service.to_string(),
+            method: method.to_string(),
+            status: None,
+            message: None,
+        })
+    }
+
+    /// Record the status carried by response headers or trailers, if any
+    pub fn observe(&mut self, headers: &HeaderMap) {
+        let Some(status) = headers
+            .get("grpc-status")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+        else {
+            return;
+        };
+        self.status = Some(GrpcStatus(status));
+        self.message = headers
+            .get("grpc-message")
+            .map(|value| percent_decode(value.as_bytes()));
+    }
+
+    /// Metrics label of the call's status: its name, or `none` for a call that ended
+    /// without one (e.g. cut off)
+    pub fn status_label(&self) -> &'static str {
+        self.status.map_or("none", |status| status.name())
+    }
+}
+
+impl fmt::Display for GrpcCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.service, self.method)?;
+        match self.status {
+            Some(status) => write!(f, " status: {}", status)?,
+            None => write!(f, " status: none")?,
+        }
+        if let Some(message) = &self.message {
+            write!(f, " message: {:?}", message)?;
+        }
+        Ok(())
+    }
+}
+
+/// `grpc-message` is percent-encoded UTF-8; invalid escapes are kept as they are
+fn percent_decode(value: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        let escaped = (value[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(value[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
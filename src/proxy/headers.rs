@@ -1,17 +1,221 @@
-use pingora::http::{RequestHeader, ResponseHeader};
-use pingora::prelude::*;
-use std::net::IpAddr;
+use crate::proxy::template::{Template, TemplateError, TemplateVars};
+use http::{HeaderName, HeaderValue};
+use pingora_error::Result;
+use pingora_http::{RequestHeader, ResponseHeader};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// Headers the gateway itself sets from the body it forwards, which rules cannot strip
+const FRAMING_HEADERS: &[&str] = &["host", "content-length", "transfer-encoding"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderRuleError {
+    InvalidName(String),
+    /// A static header value that cannot be sent, with the header's name
+    InvalidValue(String, String),
+    /// Stripping or removing a header the gateway needs to frame bodies
+    Framing(String),
+    /// The route path does not start with `/`
+    InvalidPath(String),
+    /// A trusted proxy that is neither an IP address nor a CIDR range
+    InvalidTrustedProxy(String),
+}
+
+impl fmt::Display for HeaderRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderRuleError::InvalidName(name) => write!(f, "invalid header name '{}'", name),
+            HeaderRuleError::InvalidValue(name, value) => {
+                write!(f, "invalid value '{}' of header '{}'", value, name)
+            }
+            HeaderRuleError::Framing(name) => {
+                write!(f, "header '{}' frames the body and cannot be removed", name)
+            }
+            HeaderRuleError::InvalidPath(path) => {
+                write!(f, "invalid header route path '{}'", path)
+            }
+            HeaderRuleError::InvalidTrustedProxy(proxy) => write!(
+                f,
+                "invalid trusted proxy '{}': use an IP address or CIDR range",
+                proxy
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HeaderRuleError {}
+
+/// The `headers` section of the config file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderRulesConfig {
+    /// Client request headers not forwarded upstream, e.g. `x-debug-token`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub strip_request: Vec<String>,
+    /// Upstream response headers not relayed to clients, e.g. `openai-organization`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove_response: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<HeaderRouteConfig>,
+}
+
+/// Static headers added to the requests and responses of a route
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderRouteConfig {
+    /// Request path, or a prefix ending in `*`
+    pub path: String,
+    /// Headers set on upstream requests
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub request: BTreeMap<String, String>,
+    /// Headers set on responses to clients
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub response: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone)]
+struct HeaderRoute {
+    path: String,
+    request: Vec<(HeaderName, HeaderValue)>,
+    response: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl HeaderRoute {
+    fn matches(&self, path: &str) -> bool {
+        match self.path.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => self.path == path,
+        }
+    }
+}
+
+/// Compiled header rules of the config file, applied by [`HeaderPolicy`]
+#[derive(Debug, Clone, Default)]
+pub struct HeaderRules {
+    strip_request: Vec<HeaderName>,
+    remove_response: Vec<HeaderName>,
+    routes: Vec<HeaderRoute>,
+}
+
+impl HeaderRules {
+    /// No rules: requests and responses keep their headers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compile(config: &HeaderRulesConfig) -> Result<Self, HeaderRuleError> {
+        let removable = |name: &String| {
+            let name = header_name(name)?;
+            match FRAMING_HEADERS.contains(&name.as_str()) {
+                true => Err(HeaderRuleError::Framing(name.to_string())),
+                false => Ok(name),
+            }
+        };
+        let headers = |headers: &BTreeMap<String, String>| {
+            headers
+                .iter()
+                .map(|(name, value)| {
+                    let value = HeaderValue::from_str(value)
+                        .map_err(|_| HeaderRuleError::InvalidValue(name.clone(), value.clone()))?;
+                    Ok((header_name(name)?, value))
+                })
+                .collect::<Result<Vec<_>, HeaderRuleError>>()
+        };
+        let mut routes = Vec::with_capacity(config.routes.len());
+        for route in &config.routes {
+            if !route.path.starts_with('/') {
+                return Err(HeaderRuleError::InvalidPath(route.path.clone()));
+            }
+            routes.push(HeaderRoute {
+                path: route.path.clone(),
+                request: headers(&route.request)?,
+                response: headers(&route.response)?,
+            });
+        }
+        Ok(Self {
+            strip_request: config
+                .strip_request
+                .iter()
+                .map(removable)
+                .collect::<Result<_, _>>()?,
+            remove_response: config
+                .remove_response
+                .iter()
+                .map(removable)
+                .collect::<Result<_, _>>()?,
+            routes,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strip_request.is_empty() && self.remove_response.is_empty() && self.routes.is_empty()
+    }
+
+    fn routes<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a HeaderRoute> {
+        self.routes.iter().filter(move |route| route.matches(path))
+    }
+}
+
+fn header_name(name: &str) -> Result<HeaderName, HeaderRuleError> {
+    HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| HeaderRuleError::InvalidName(name.to_string()))
+}
+
+/// Headers forwarding the client's address to upstreams
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ForwardedFor {
+    /// None: client forwarding headers pass unchanged
+    #[default]
+    Off,
+    /// `X-Forwarded-For`
+    XForwardedFor,
+    /// `Forwarded` (RFC 7239)
+    Forwarded,
+    /// Both `X-Forwarded-For` and `Forwarded`
+    Both,
+}
+
+impl ForwardedFor {
+    /// `off`, `x-forwarded-for`, `forwarded` or `both`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(ForwardedFor::Off),
+            "x-forwarded-for" => Some(ForwardedFor::XForwardedFor),
+            "forwarded" => Some(ForwardedFor::Forwarded),
+            "both" => Some(ForwardedFor::Both),
+            _ => None,
+        }
+    }
+
+    fn x_forwarded_for(&self) -> bool {
+        matches!(self, ForwardedFor::XForwardedFor | ForwardedFor::Both)
+    }
+
+    fn forwarded(&self) -> bool {
+        matches!(self, ForwardedFor::Forwarded | ForwardedFor::Both)
+    }
+}
 
 /// Centralized header mutation policies for the langspec gateway.
 ///
 /// This module encapsulates all header manipulation logic to:
 /// - Prevent scattered header mutations across the codebase
 /// - Provide consistent header policies
+/// - Apply the config file's [`HeaderRules`] (stripped, removed and static headers)
 /// - Enable easy addition of new headers (X-Forwarded-For, X-Request-Id, etc.)
 /// - Maintain observability and security best practices
 pub struct HeaderPolicy {
     gateway_name: &'static str,
     proxy_name: &'static str,
+    /// Named headers rendered per request from templates, e.g. `X-Tenant: {tenant}`
+    request_templates: Vec<(String, Template)>,
+    response_templates: Vec<(String, Template)>,
+    forwarded_for: ForwardedFor,
+    /// Networks (address and prefix length) of the proxies in front of the gateway,
+    /// whose forwarding headers are kept and believed
+    trusted_proxies: Vec<(IpAddr, u8)>,
 }
 
 impl HeaderPolicy {
@@ -19,7 +223,106 @@ impl HeaderPolicy {
         Self {
             gateway_name: "langspec-gateway",
             proxy_name: "langspec",
+            request_templates: Vec::new(),
+            response_templates: Vec::new(),
+            forwarded_for: ForwardedFor::default(),
+            trusted_proxies: Vec::new(),
+        }
+    }
+
+    /// Forward the address of each request's client to upstreams in these headers.
+    /// Headers a trusted proxy sent are appended to; any others are replaced, since
+    /// clients could put anything in them.
+    pub fn with_forwarded_for(mut self, forwarded_for: ForwardedFor) -> Self {
+        self.forwarded_for = forwarded_for;
+        self
+    }
+
+    /// Trust the forwarding headers of requests received from `proxy`, an IP address or
+    /// a CIDR range such as `10.0.0.0/8`
+    pub fn with_trusted_proxy(mut self, proxy: &str) -> Result<Self, HeaderRuleError> {
+        let invalid = || HeaderRuleError::InvalidTrustedProxy(proxy.to_string());
+        let (ip, prefix) = match proxy.split_once('/') {
+            Some((ip, prefix)) => {
+                let ip = ip.parse::<IpAddr>().map_err(|_| invalid())?;
+                let prefix = prefix.parse::<u8>().map_err(|_| invalid())?;
+                (ip, prefix)
+            }
+            None => {
+                let ip = proxy.parse::<IpAddr>().map_err(|_| invalid())?;
+                (ip, max_prefix(ip))
+            }
+        };
+        if prefix > max_prefix(ip) {
+            return Err(invalid());
+        }
+        self.trusted_proxies.push((ip, prefix));
+        Ok(self)
+    }
+
+    /// Trusted proxies from a comma-separated list, e.g. `10.0.0.0/8,192.168.1.7`
+    pub fn with_trusted_proxy_list(mut self, list: &str) -> Result<Self, HeaderRuleError> {
+        for proxy in list
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+        {
+            self = self.with_trusted_proxy(proxy)?;
+        }
+        Ok(self)
+    }
+
+    /// Whether `ip` is one of the trusted proxies
+    pub fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.trusted_proxies
+            .iter()
+            .any(|(network, prefix)| in_network(ip, *network, *prefix))
+    }
+
+    /// Address of the client of a request received from `peer`: the peer itself,
+    /// unless it is a trusted proxy. Then it is the last `X-Forwarded-For` address not
+    /// added by a trusted proxy, or the first one if trusted proxies added them all.
+    pub fn client_ip(&self, request: &RequestHeader, peer: IpAddr) -> IpAddr {
+        let mut client = peer.to_canonical();
+        if !self.is_trusted_proxy(peer) {
+            return client;
+        }
+        let chain = header_list(request, "X-Forwarded-For");
+        for entry in chain.iter().rev() {
+            let Some(ip) = parse_forwarded_ip(entry) else {
+                break;
+            };
+            client = ip;
+            if !self.is_trusted_proxy(ip) {
+                break;
+            }
         }
+        client
+    }
+
+    /// Add a header rendered from a template to every upstream request.
+    /// Fails on unknown template variables.
+    pub fn with_request_template(
+        mut self,
+        name: &str,
+        template: &str,
+    ) -> Result<Self, TemplateError> {
+        self.request_templates
+            .push((name.to_string(), Template::parse(template)?));
+        Ok(self)
+    }
+
+    /// Add a header rendered from a template to every downstream response.
+    /// Fails on unknown template variables.
+    pub fn with_response_template(
+        mut self,
+        name: &str,
+        template: &str,
+    ) -> Result<Self, TemplateError> {
+        self.response_templates
+            .push((name.to_string(), Template::parse(template)?));
+        Ok(self)
     }
 
     /// Apply all upstream request header mutations.
@@ -29,12 +332,25 @@ impl HeaderPolicy {
     /// 1. Add method to HeaderPolicy (see examples below)
     /// 2. Call it here - NO changes needed to ProxyHttp
     /// 3. Add test coverage
-    pub fn apply_upstream_request_headers(&self, request: &mut RequestHeader) -> Result<()> {
+    ///
+    /// `peer` is the address the request was received from, when known.
+    pub fn apply_upstream_request_headers(
+        &self,
+        request: &mut RequestHeader,
+        peer: Option<IpAddr>,
+    ) -> Result<()> {
         // Core forwarding headers
         self.add_forwarded_by_header(request)?;
+        if let Some(peer) = peer {
+            if self.forwarded_for.x_forwarded_for() {
+                self.add_forwarded_for_header(request, peer)?;
+            }
+            if self.forwarded_for.forwarded() {
+                self.add_forwarded_header(request, peer)?;
+            }
+        }
 
         // Future headers will be added here:
-        // self.add_forwarded_for_header(request, client_ip)?;
         // self.add_request_id_header(request)?;
         // self.add_trace_headers(request)?;
 
@@ -60,6 +376,80 @@ impl HeaderPolicy {
         Ok(())
     }
 
+    /// Apply the config file's header rules to an upstream request for `path`: strip
+    /// the listed client headers, then set the static headers of every matching
+    /// route, later routes overriding earlier ones.
+    /// Call before the gateway adds its own headers (credentials), which stripping
+    /// would otherwise remove.
+    pub fn apply_request_rules(
+        &self,
+        request: &mut RequestHeader,
+        rules: &HeaderRules,
+        path: &str,
+    ) -> Result<()> {
+        for name in &rules.strip_request {
+            request.remove_header(name);
+        }
+        for route in rules.routes(path) {
+            for (name, value) in &route.request {
+                request.insert_header(name.clone(), value.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the config file's header rules to the response to a request for `path`:
+    /// remove the listed upstream headers, then set the static headers of every
+    /// matching route.
+    pub fn apply_response_rules(
+        &self,
+        response: &mut ResponseHeader,
+        rules: &HeaderRules,
+        path: &str,
+    ) -> Result<()> {
+        for name in &rules.remove_response {
+            response.remove_header(name);
+        }
+        for route in rules.routes(path) {
+            for (name, value) in &route.response {
+                response.insert_header(name.clone(), value.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Render and insert the templated request headers.
+    /// Headers whose template renders empty (all variables missing) are skipped.
+    pub fn apply_request_templates(
+        &self,
+        request: &mut RequestHeader,
+        vars: &TemplateVars,
+    ) -> Result<()> {
+        for (name, template) in &self.request_templates {
+            let value = template.render(vars);
+            if !value.is_empty() {
+                request.insert_header(name.clone(), value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Render and insert the templated response headers.
+    /// Headers whose template renders empty (all variables missing) are skipped.
+    pub fn apply_response_templates(
+        &self,
+        response: &mut ResponseHeader,
+        vars: &TemplateVars,
+    ) -> Result<()> {
+        for (name, template) in &self.response_templates {
+            let value = template.render(vars);
+            if !value.is_empty() {
+                response.insert_header(name.clone(), value)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Add X-Forwarded-By header to identify the gateway
     fn add_forwarded_by_header(&self, request: &mut RequestHeader) -> Result<()> {
         request.insert_header("X-Forwarded-By", self.gateway_name)?;
@@ -72,23 +462,30 @@ impl HeaderPolicy {
         Ok(())
     }
 
-    /// Future: Add X-Forwarded-For header with client IP
-    #[allow(dead_code)]
-    fn add_forwarded_for_header(
-        &self,
-        request: &mut RequestHeader,
-        client_ip: IpAddr,
-    ) -> Result<()> {
-        // Check if X-Forwarded-For already exists and append, or create new
-        if let Some(existing) = request.headers.get("X-Forwarded-For") {
-            if let Ok(existing_str) = existing.to_str() {
-                let new_value = format!("{}, {}", existing_str, client_ip);
-                request.remove_header("X-Forwarded-For");
-                request.insert_header("X-Forwarded-For", &new_value)?;
-            }
-        } else {
-            request.insert_header("X-Forwarded-For", client_ip.to_string())?;
-        }
+    /// Add X-Forwarded-For header with the peer's address, appended to the header a
+    /// trusted proxy sent or replacing the client's own
+    fn add_forwarded_for_header(&self, request: &mut RequestHeader, peer: IpAddr) -> Result<()> {
+        let mut chain = match self.is_trusted_proxy(peer) {
+            true => header_list(request, "X-Forwarded-For"),
+            false => Vec::new(),
+        };
+        chain.push(peer.to_canonical().to_string());
+        request.insert_header("X-Forwarded-For", chain.join(", "))?;
+        Ok(())
+    }
+
+    /// Add Forwarded header (RFC 7239) with the peer's address, appended to the header
+    /// a trusted proxy sent or replacing the client's own
+    fn add_forwarded_header(&self, request: &mut RequestHeader, peer: IpAddr) -> Result<()> {
+        let mut elements = match self.is_trusted_proxy(peer) {
+            true => header_list(request, "Forwarded"),
+            false => Vec::new(),
+        };
+        elements.push(match peer.to_canonical() {
+            IpAddr::V4(ip) => format!("for={}", ip),
+            IpAddr::V6(ip) => format!("for=\"[{}]\"", ip),
+        });
+        request.insert_header("Forwarded", elements.join(", "))?;
         Ok(())
     }
 
@@ -132,6 +529,56 @@ impl Default for HeaderPolicy {
     }
 }
 
+/// Comma-separated entries of every value of a request header, in order
+fn header_list(request: &RequestHeader, name: &str) -> Vec<String> {
+    request
+        .headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Address of an `X-Forwarded-For` entry, which may carry a port
+fn parse_forwarded_ip(entry: &str) -> Option<IpAddr> {
+    let ip = entry
+        .parse::<IpAddr>()
+        .or_else(|_| entry.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()?;
+    Some(ip.to_canonical())
+}
+
+/// Longest prefix length of a network of `ip`'s family
+pub(crate) fn max_prefix(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Whether `ip` is in the network of `network` and `prefix` length
+pub(crate) fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    let mask = |bits: u32| match prefix {
+        0 => 0u128,
+        prefix => u128::MAX << (bits - prefix as u32),
+    };
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = mask(32) as u32;
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = mask(128);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
 /// Legacy function for backward compatibility
 /// TODO: Remove once all callers use HeaderPolicy
 pub fn add_forwarded_headers(request: &mut RequestHeader) -> Result<()> {
@@ -0,0 +1,64 @@
+use std::env;
+
+/// Identity of a gateway instance within a multi-instance deployment, so behaviour can
+/// be attributed to a specific replica during incidents.
+///
+/// The identity appears in the access log (with the request's usage), in alerts, in the
+/// `langspec_gateway_info` metric and, when enabled, in an `X-Langspec-Instance`
+/// response header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceIdentity {
+    pub cluster: Option<String>,
+    pub instance: String,
+    /// Add the `X-Langspec-Instance` response header
+    pub response_header: bool,
+}
+
+impl InstanceIdentity {
+    pub const RESPONSE_HEADER: &'static str = "X-Langspec-Instance";
+
+    pub fn new(instance: impl Into<String>) -> Self {
+        let instance = instance.into();
+        assert!(!instance.is_empty(), "Instance id cannot be empty");
+        Self {
+            cluster: None,
+            instance,
+            response_header: false,
+        }
+    }
+
+    /// Identity from `LANGSPEC_INSTANCE_ID` (falling back to `HOSTNAME`, which is the
+    /// pod name on Kubernetes) and `LANGSPEC_CLUSTER_ID`
+    pub fn from_env() -> Self {
+        let var = |name| {
+            env::var(name)
+                .ok()
+                .filter(|value: &String| !value.is_empty())
+        };
+        let instance = var("LANGSPEC_INSTANCE_ID")
+            .or_else(|| var("HOSTNAME"))
+            .unwrap_or_else(|| "unknown".to_string());
+        Self {
+            cluster: var("LANGSPEC_CLUSTER_ID"),
+            ..Self::new(instance)
+        }
+    }
+
+    pub fn with_cluster(mut self, cluster: impl Into<String>) -> Self {
+        self.cluster = Some(cluster.into());
+        self
+    }
+
+    pub fn with_response_header(mut self) -> Self {
+        self.response_header = true;
+        self
+    }
+
+    /// `cluster/instance`, or the instance alone without a cluster
+    pub fn label(&self) -> String {
+        match &self.cluster {
+            Some(cluster) => format!("{}/{}", cluster, self.instance),
+            None => self.instance.clone(),
+        }
+    }
+}
@@ -0,0 +1,529 @@
+//! JSON Web Tokens issued by an identity provider, validated on ingress.
+//!
+//! Clients send the token as `Authorization: Bearer <jwt>`. The gateway checks its
+//! signature against the provider's JWKS (RS256 or ES256 keys), its issuer, audience
+//! and validity window, strips it, and keeps the `sub` and organization claims in the
+//! request context. A token with an organization makes its request as that tenant.
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use log::info;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Public};
+use openssl::rsa::Rsa;
+use openssl::sign::Verifier;
+use pingora_http::RequestHeader;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JwtError {
+    /// The request carries no token
+    Missing,
+    /// The token is not three base64url segments with a JSON header and claims
+    Malformed,
+    /// An algorithm other than RS256 and ES256, `none` included
+    UnsupportedAlgorithm(String),
+    /// No key of the JWKS has the token's key ID and algorithm
+    UnknownKey(Option<String>),
+    BadSignature,
+    Expired,
+    NotYetValid,
+    WrongIssuer,
+    WrongAudience,
+    /// The request names another tenant than the token's organization
+    TenantMismatch,
+    /// The JWKS could not be fetched and no earlier copy is cached
+    KeysUnavailable(String),
+}
+
+impl JwtError {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JwtError::Missing => "missing",
+            JwtError::Malformed => "malformed",
+            JwtError::UnsupportedAlgorithm(_) => "unsupported_algorithm",
+            JwtError::UnknownKey(_) => "unknown_key",
+            JwtError::BadSignature => "bad_signature",
+            JwtError::Expired => "expired",
+            JwtError::NotYetValid => "not_yet_valid",
+            JwtError::WrongIssuer => "wrong_issuer",
+            JwtError::WrongAudience => "wrong_audience",
+            JwtError::TenantMismatch => "tenant_mismatch",
+            JwtError::KeysUnavailable(_) => "keys_unavailable",
+        }
+    }
+
+    /// Status a request presenting the token is rejected with: 401 for a token that
+    /// is not valid, 403 for a valid token of another tenant, 503 when the token cannot
+    /// be checked at all
+    pub fn status(&self) -> u16 {
+        match self {
+            JwtError::TenantMismatch => 403,
+            JwtError::KeysUnavailable(_) => 503,
+            _ => 401,
+        }
+    }
+}
+
+impl fmt::Display for JwtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtError::Missing => write!(f, "missing bearer token"),
+            JwtError::Malformed => write!(f, "malformed JWT"),
+            JwtError::UnsupportedAlgorithm(alg) => {
+                write!(f, "JWT algorithm '{}' is not accepted", alg)
+            }
+            JwtError::UnknownKey(Some(kid)) => write!(f, "no JWKS key with ID '{}'", kid),
+            JwtError::UnknownKey(None) => write!(f, "no JWKS key for a JWT without key ID"),
+            JwtError::BadSignature => write!(f, "JWT signature does not match"),
+            JwtError::Expired => write!(f, "JWT expired"),
+            JwtError::NotYetValid => write!(f, "JWT is not valid yet"),
+            JwtError::WrongIssuer => write!(f, "JWT was issued by another issuer"),
+            JwtError::WrongAudience => write!(f, "JWT is meant for another audience"),
+            JwtError::TenantMismatch => write!(f, "JWT belongs to another tenant"),
+            JwtError::KeysUnavailable(e) => write!(f, "JWKS unavailable: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for JwtError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JwtConfigError {
+    /// The JWKS URL is not `http://` or `https://`
+    InvalidJwksUrl(String),
+    EmptyIssuer,
+    EmptyAudience,
+}
+
+impl fmt::Display for JwtConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtConfigError::InvalidJwksUrl(url) => {
+                write!(f, "JWKS URL '{}' is not an http(s) URL", url)
+            }
+            JwtConfigError::EmptyIssuer => write!(f, "JWT issuer is empty"),
+            JwtConfigError::EmptyAudience => write!(f, "JWT audience is empty"),
+        }
+    }
+}
+
+impl std::error::Error for JwtConfigError {}
+
+/// JWT validation settings, the `jwt` section of the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtConfig {
+    /// Where the identity provider publishes its signing keys, e.g.
+    /// `https://idp.example.com/.well-known/jwks.json`
+    pub jwks_url: String,
+    /// Required `iss` claim
+    pub issuer: String,
+    /// Required `aud` claim (or one of them)
+    pub audience: String,
+    /// Claim naming the organization, used as the request's tenant
+    #[serde(default = "default_org_claim")]
+    pub org_claim: String,
+    /// Clock skew allowed when checking `exp` and `nbf`
+    #[serde(default = "default_leeway_secs")]
+    pub leeway_secs: u64,
+    /// How long fetched keys are used before the JWKS is fetched again
+    #[serde(default = "default_refresh_secs")]
+    pub refresh_secs: u64,
+    /// Reject requests without a token; otherwise only tokens that are present are
+    /// checked. Requests made with a capability token or virtual key need none.
+    #[serde(default = "default_required")]
+    pub required: bool,
+}
+
+fn default_org_claim() -> String {
+    "org".to_string()
+}
+
+fn default_leeway_secs() -> u64 {
+    60
+}
+
+fn default_refresh_secs() -> u64 {
+    300
+}
+
+fn default_required() -> bool {
+    true
+}
+
+impl JwtConfig {
+    pub fn new(
+        jwks_url: impl Into<String>,
+        issuer: impl Into<String>,
+        audience: impl Into<String>,
+    ) -> Self {
+        Self {
+            jwks_url: jwks_url.into(),
+            issuer: issuer.into(),
+            audience: audience.into(),
+            org_claim: default_org_claim(),
+            leeway_secs: default_leeway_secs(),
+            refresh_secs: default_refresh_secs(),
+            required: default_required(),
+        }
+    }
+
+    pub fn with_org_claim(mut self, claim: impl Into<String>) -> Self {
+        self.org_claim = claim.into();
+        self
+    }
+
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway_secs = leeway.as_secs();
+        self
+    }
+
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh_secs = refresh.as_secs();
+        self
+    }
+
+    pub fn with_required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+}
+
+/// Signature algorithms tokens may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    Rs256,
+    Es256,
+}
+
+impl JwtAlgorithm {
+    pub fn parse(alg: &str) -> Option<Self> {
+        match alg {
+            "RS256" => Some(JwtAlgorithm::Rs256),
+            "ES256" => Some(JwtAlgorithm::Es256),
+            _ => None,
+        }
+    }
+}
+
+/// A signing key of the JWKS
+#[derive(Clone)]
+pub struct Jwk {
+    pub kid: Option<String>,
+    pub algorithm: JwtAlgorithm,
+    key: PKey<Public>,
+}
+
+impl Jwk {
+    /// An RSA key from its base64url modulus and exponent
+    pub fn rsa(kid: Option<String>, n: &str, e: &str) -> Option<Self> {
+        let n = BigNum::from_slice(&BASE64URL.decode(n).ok()?).ok()?;
+        let e = BigNum::from_slice(&BASE64URL.decode(e).ok()?).ok()?;
+        let key = PKey::from_rsa(Rsa::from_public_components(n, e).ok()?).ok()?;
+        Some(Self {
+            kid,
+            algorithm: JwtAlgorithm::Rs256,
+            key,
+        })
+    }
+
+    /// A P-256 key from its base64url coordinates
+    pub fn p256(kid: Option<String>, x: &str, y: &str) -> Option<Self> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).ok()?;
+        let x = BigNum::from_slice(&BASE64URL.decode(x).ok()?).ok()?;
+        let y = BigNum::from_slice(&BASE64URL.decode(y).ok()?).ok()?;
+        let key = EcKey::from_public_key_affine_coordinates(&group, &x, &y).ok()?;
+        key.check_key().ok()?;
+        Some(Self {
+            kid,
+            algorithm: JwtAlgorithm::Es256,
+            key: PKey::from_ec_key(key).ok()?,
+        })
+    }
+
+    /// Whether `signature` is this key's signature of `message`
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let der;
+        let signature = match self.algorithm {
+            JwtAlgorithm::Rs256 => signature,
+            // JWS carries the raw `r || s`, OpenSSL verifies DER
+            JwtAlgorithm::Es256 => {
+                if signature.len() != 64 {
+                    return false;
+                }
+                let (r, s) = signature.split_at(32);
+                let Some(encoded) = BigNum::from_slice(r)
+                    .and_then(|r| Ok((r, BigNum::from_slice(s)?)))
+                    .and_then(|(r, s)| EcdsaSig::from_private_components(r, s))
+                    .and_then(|sig| sig.to_der())
+                    .ok()
+                else {
+                    return false;
+                };
+                der = encoded;
+                &der
+            }
+        };
+        Verifier::new(MessageDigest::sha256(), &self.key)
+            .and_then(|mut verifier| verifier.verify_oneshot(signature, message))
+            .unwrap_or(false)
+    }
+}
+
+impl fmt::Debug for Jwk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Jwk")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+/// The signing keys an identity provider publishes
+#[derive(Debug, Clone, Default)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+impl Jwks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_key(mut self, key: Jwk) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Keys of a JWKS document. Keys that are not for signatures, or of a type or
+    /// curve tokens cannot use, are skipped.
+    pub fn from_json(json: &[u8]) -> Result<Self, String> {
+        #[derive(Deserialize)]
+        struct Document {
+            keys: Vec<Value>,
+        }
+        let document: Document = serde_json::from_slice(json).map_err(|e| e.to_string())?;
+        fn field<'a>(key: &'a Value, name: &str) -> Option<&'a str> {
+            key.get(name).and_then(Value::as_str)
+        }
+        let keys = document
+            .keys
+            .iter()
+            .filter(|key| field(key, "use").is_none_or(|usage| usage == "sig"))
+            .filter_map(|key| {
+                let kid = field(key, "kid").map(str::to_string);
+                match (field(key, "kty"), field(key, "crv")) {
+                    (Some("RSA"), _) => Jwk::rsa(kid, field(key, "n")?, field(key, "e")?),
+                    (Some("EC"), Some("P-256")) => {
+                        Jwk::p256(kid, field(key, "x")?, field(key, "y")?)
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        Ok(Self { keys })
+    }
+
+    /// The key a token with `kid` and `algorithm` is verified with. Tokens without a
+    /// key ID are only accepted from a JWKS with a single key of their algorithm.
+    pub fn find(&self, kid: Option<&str>, algorithm: JwtAlgorithm) -> Option<&Jwk> {
+        let mut candidates = self.keys.iter().filter(|key| key.algorithm == algorithm);
+        match kid {
+            Some(kid) => candidates.find(|key| key.kid.as_deref() == Some(kid)),
+            None => match (candidates.next(), candidates.next()) {
+                (Some(key), None) => Some(key),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Identity of a validated token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JwtClaims {
+    pub sub: Option<String>,
+    /// The organization claim
+    pub org: Option<String>,
+}
+
+/// Validates tokens against a JWKS, keeping the last fetched keys
+pub struct JwtValidator {
+    pub config: JwtConfig,
+    /// Last fetched keys, and when
+    keys: RwLock<Option<(Arc<Jwks>, Instant)>>,
+}
+
+impl JwtValidator {
+    /// Least time between two fetches of the JWKS, when a token names an unknown key
+    pub const MIN_REFETCH: Duration = Duration::from_secs(30);
+
+    pub fn compile(config: &JwtConfig) -> Result<Self, JwtConfigError> {
+        if !config.jwks_url.starts_with("https://") && !config.jwks_url.starts_with("http://") {
+            return Err(JwtConfigError::InvalidJwksUrl(config.jwks_url.clone()));
+        }
+        if config.issuer.is_empty() {
+            return Err(JwtConfigError::EmptyIssuer);
+        }
+        if config.audience.is_empty() {
+            return Err(JwtConfigError::EmptyAudience);
+        }
+        Ok(Self {
+            config: config.clone(),
+            keys: RwLock::new(None),
+        })
+    }
+
+    /// Keys to verify with, unless none were fetched or they are older than the
+    /// refresh interval
+    pub fn fresh_keys(&self) -> Option<Arc<Jwks>> {
+        let refresh = Duration::from_secs(self.config.refresh_secs);
+        self.keys
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|(_, fetched_at)| fetched_at.elapsed() < refresh)
+            .map(|(keys, _)| Arc::clone(keys))
+    }
+
+    /// The last fetched keys, however old
+    pub fn cached_keys(&self) -> Option<Arc<Jwks>> {
+        self.keys
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|(keys, _)| Arc::clone(keys))
+    }
+
+    /// Whether the keys may be fetched again for a token naming a key they lack
+    pub fn may_refetch(&self) -> bool {
+        self.keys
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_none_or(|(_, fetched_at)| fetched_at.elapsed() >= Self::MIN_REFETCH)
+    }
+
+    pub fn set_keys(&self, keys: Jwks) -> Arc<Jwks> {
+        let keys = Arc::new(keys);
+        let count = keys.keys.len();
+        let previous = self
+            .keys
+            .write()
+            .unwrap()
+            .replace((Arc::clone(&keys), Instant::now()));
+        if previous.is_none_or(|(previous, _)| previous.keys.len() != count) {
+            info!(
+                "Loaded {} JWT signing keys from {}",
+                count, self.config.jwks_url
+            );
+        }
+        keys
+    }
+
+    /// The key ID and algorithm of a token, read before its keys are looked up
+    pub fn header(token: &str) -> Result<(Option<String>, JwtAlgorithm), JwtError> {
+        #[derive(Deserialize)]
+        struct Header {
+            alg: String,
+            kid: Option<String>,
+        }
+        let header = token.split('.').next().ok_or(JwtError::Malformed)?;
+        let header: Header = BASE64URL
+            .decode(header)
+            .ok()
+            .and_then(|header| serde_json::from_slice(&header).ok())
+            .ok_or(JwtError::Malformed)?;
+        let algorithm =
+            JwtAlgorithm::parse(&header.alg).ok_or(JwtError::UnsupportedAlgorithm(header.alg))?;
+        Ok((header.kid, algorithm))
+    }
+
+    /// Check `token` with `keys` at `now` (unix seconds)
+    pub fn validate(&self, token: &str, keys: &Jwks, now: u64) -> Result<JwtClaims, JwtError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(JwtError::Malformed);
+        };
+        let (kid, algorithm) = Self::header(token)?;
+        let key = keys
+            .find(kid.as_deref(), algorithm)
+            .ok_or(JwtError::UnknownKey(kid))?;
+        let signature = BASE64URL
+            .decode(signature)
+            .map_err(|_| JwtError::Malformed)?;
+        let signed = &token[..header.len() + 1 + payload.len()];
+        if !key.verify(signed.as_bytes(), &signature) {
+            return Err(JwtError::BadSignature);
+        }
+
+        let claims: serde_json::Map<String, Value> = BASE64URL
+            .decode(payload)
+            .ok()
+            .and_then(|payload| serde_json::from_slice(&payload).ok())
+            .ok_or(JwtError::Malformed)?;
+        let leeway = self.config.leeway_secs;
+        match claims.get("exp").and_then(Value::as_u64) {
+            Some(exp) if exp.saturating_add(leeway) > now => {}
+            _ => return Err(JwtError::Expired),
+        }
+        if claims
+            .get("nbf")
+            .and_then(Value::as_u64)
+            .is_some_and(|nbf| nbf > now.saturating_add(leeway))
+        {
+            return Err(JwtError::NotYetValid);
+        }
+        if claims.get("iss").and_then(Value::as_str) != Some(self.config.issuer.as_str()) {
+            return Err(JwtError::WrongIssuer);
+        }
+        let audience = self.config.audience.as_str();
+        let audience_matches = match claims.get("aud") {
+            Some(Value::String(aud)) => aud == audience,
+            Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+            _ => false,
+        };
+        if !audience_matches {
+            return Err(JwtError::WrongAudience);
+        }
+
+        let claim = |name: &str| claims.get(name).and_then(Value::as_str).map(str::to_string);
+        Ok(JwtClaims {
+            sub: claim("sub"),
+            org: claim(&self.config.org_claim),
+        })
+    }
+}
+
+impl fmt::Debug for JwtValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtValidator")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// The JWT a request carries as `Authorization: Bearer <jwt>`. Bearer values that are
+/// not three dot-separated segments (provider API keys, capability tokens, virtual
+/// keys) are not JWTs.
+pub fn request_token(request: &RequestHeader) -> Option<&str> {
+    let token = request
+        .headers
+        .get(http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?
+        .trim();
+    (token.split('.').count() == 3).then_some(token)
+}
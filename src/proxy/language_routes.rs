@@ -0,0 +1,46 @@
+use crate::pipeline::language::LanguageDetector;
+use std::collections::HashMap;
+
+/// Routing of requests by the language of their prompt, e.g. Japanese prompts to an
+/// upstream serving a model that handles Japanese better.
+///
+/// The prompt is read from the request body before an upstream is selected, which only
+/// happens for bodies with a `Content-Length` up to [`MAX_BODY_BYTES`](Self::MAX_BODY_BYTES)
+/// (the body is held in memory and replayed upstream); other requests have no
+/// language. Upstreams routed for the detected language are preferred in the listed
+/// order, and requests fall back to the remaining upstreams when none of them is
+/// available. With no routes, the language is only detected, for metrics and logs.
+#[derive(Debug, Clone, Default)]
+pub struct LanguageRoutes {
+    pub detector: LanguageDetector,
+    /// Upstream addresses per ISO 639-1 language code
+    pub routes: HashMap<String, Vec<String>>,
+}
+
+impl LanguageRoutes {
+    /// Largest body read for detection: what the proxy can buffer and replay upstream
+    pub const MAX_BODY_BYTES: usize = 64 * 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_detector(mut self, detector: LanguageDetector) -> Self {
+        self.detector = detector;
+        self
+    }
+
+    /// Prefer `upstream` for prompts in `language`; repeat to add more upstreams
+    pub fn with_route(mut self, language: impl Into<String>, upstream: impl Into<String>) -> Self {
+        self.routes
+            .entry(language.into().to_ascii_lowercase())
+            .or_default()
+            .push(upstream.into());
+        self
+    }
+
+    /// Upstream addresses preferred for a language, in order
+    pub fn upstreams_for(&self, language: &str) -> &[String] {
+        self.routes.get(language).map_or(&[], Vec::as_slice)
+    }
+}
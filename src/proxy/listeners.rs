@@ -0,0 +1,172 @@
+//! Listeners dedicated to a tenant.
+//!
+//! In network-segmented deployments each tenant reaches the gateway on its own port
+//! (or Unix socket), and that is what identifies it: requests arriving on a tenant's
+//! listener are attributed to the tenant whatever `X-Langspec-Tenant` they carry, so
+//! attribution does not depend on credentials in the request. Signed requests and
+//! capability tokens of another tenant are rejected as tenant mismatches.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenerError {
+    /// An entry is not `tenant=address`
+    InvalidEntry(String),
+    /// The address is neither `ip:port` nor `unix:/path`
+    InvalidAddress(String, String),
+    /// Two tenants listen on the same address
+    DuplicateAddress(String),
+}
+
+impl fmt::Display for ListenerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenerError::InvalidEntry(entry) => {
+                write!(f, "invalid tenant listener '{}': use tenant=address", entry)
+            }
+            ListenerError::InvalidAddress(tenant, address) => write!(
+                f,
+                "invalid listener address '{}' of tenant '{}': use ip:port or unix:/path",
+                address, tenant
+            ),
+            ListenerError::DuplicateAddress(address) => {
+                write!(f, "listener address '{}' is used twice", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ListenerError {}
+
+/// Address a listener is bound to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenerAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl ListenerAddr {
+    /// `ip:port`, or `unix:/path` for a Unix socket
+    pub fn parse(address: &str) -> Option<Self> {
+        match address.strip_prefix("unix:") {
+            Some(path) if path.starts_with('/') => Some(ListenerAddr::Unix(path.into())),
+            Some(_) => None,
+            None => address.parse().ok().map(ListenerAddr::Tcp),
+        }
+    }
+
+    /// Whether a connection accepted at `local` came through this listener. A listener
+    /// bound to an unspecified IP (`0.0.0.0`, `::`) accepts on every local IP.
+    pub fn accepts(&self, local: &ListenerAddr) -> bool {
+        match (self, local) {
+            (ListenerAddr::Tcp(bound), ListenerAddr::Tcp(local)) => {
+                bound.port() == local.port()
+                    && (bound.ip().is_unspecified() || bound.ip() == local.ip())
+            }
+            (ListenerAddr::Unix(bound), ListenerAddr::Unix(local)) => bound == local,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ListenerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenerAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenerAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A listener whose requests are attributed to a tenant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TenantListener {
+    pub tenant: String,
+    pub addr: ListenerAddr,
+}
+
+/// Tenant listeners, served next to the shared proxy listener
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TenantListeners {
+    pub listeners: Vec<TenantListener>,
+    /// Reject requests naming a tenant with its own listener on any other listener
+    pub exclusive: bool,
+}
+
+impl TenantListeners {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_listener(mut self, tenant: impl Into<String>, addr: ListenerAddr) -> Self {
+        assert!(
+            self.listeners.iter().all(|listener| listener.addr != addr),
+            "Listener address {} is used twice",
+            addr
+        );
+        self.listeners.push(TenantListener {
+            tenant: tenant.into(),
+            addr,
+        });
+        self
+    }
+
+    /// Only accept requests of tenants with their own listener on that listener, so
+    /// they cannot be made as the tenant from the shared listener
+    pub fn with_exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// Listeners from a comma-separated list of `tenant=address`, e.g.
+    /// `acme=0.0.0.0:8443,globex=unix:/run/langspec/globex.sock`
+    pub fn parse(list: &str) -> Result<Self, ListenerError> {
+        let mut listeners = Self::new();
+        for entry in list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let Some((tenant, address)) = entry
+                .split_once('=')
+                .map(|(tenant, address)| (tenant.trim(), address.trim()))
+                .filter(|(tenant, _)| !tenant.is_empty())
+            else {
+                return Err(ListenerError::InvalidEntry(entry.to_string()));
+            };
+            let addr = ListenerAddr::parse(address).ok_or_else(|| {
+                ListenerError::InvalidAddress(tenant.to_string(), address.to_string())
+            })?;
+            if listeners
+                .listeners
+                .iter()
+                .any(|listener| listener.addr == addr)
+            {
+                return Err(ListenerError::DuplicateAddress(address.to_string()));
+            }
+            listeners = listeners.with_listener(tenant, addr);
+        }
+        Ok(listeners)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    /// Tenant of the listener a connection accepted at `local` came through
+    pub fn tenant_for(&self, local: &ListenerAddr) -> Option<&str> {
+        self.listeners
+            .iter()
+            .find(|listener| listener.addr.accepts(local))
+            .map(|listener| listener.tenant.as_str())
+    }
+
+    /// Whether `tenant` has a listener of its own
+    pub fn has_listener(&self, tenant: &str) -> bool {
+        self.listeners
+            .iter()
+            .any(|listener| listener.tenant == tenant)
+    }
+}
@@ -0,0 +1,110 @@
+//! Non-HTTP traffic on the gateway's listeners: TLS handshakes sent to a plaintext
+//! port, HTTP/2 prefaces to a listener without h2c, and scanners' garbage.
+//!
+//! Pingora answers a request it cannot parse with 400 and closes the connection, then
+//! logs the bytes it read as an error, before any proxy phase runs. [`Logger`] wraps the
+//! gateway's logger to catch those records ahead of its filter: every such connection
+//! is counted by kind whatever `RUST_LOG` lets through, and written as one short line
+//! at debug level rather than an error carrying the raw bytes.
+
+use crate::metrics::NON_HTTP_CONNECTIONS;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Target of the line logged for a connection closed for sending non-HTTP bytes
+const TARGET: &str = "langspec::proxy::non_http";
+
+/// Start of the record the proxy logs for a request that failed to parse, before the
+/// escaped bytes it read
+const PARSE_FAILURE: &str = "Fail to proxy: Downstream InvalidHTTPHeader context: buf: ";
+
+/// Kind of non-HTTP traffic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonHttp {
+    /// A TLS handshake on a plaintext listener
+    Tls,
+    /// An HTTP/2 connection preface on a listener without h2c
+    Http2,
+    /// Anything else that does not parse as an HTTP/1 request
+    Malformed,
+}
+
+impl NonHttp {
+    /// Kind of traffic `record` reports a parse failure for; `None` for any other record
+    pub fn from_record(record: &Record) -> Option<Self> {
+        if record.level() != Level::Error || !record.target().starts_with("pingora_proxy") {
+            return None;
+        }
+        let message = record.args().to_string();
+        let buf = message.strip_prefix(PARSE_FAILURE)?;
+        // A TLS handshake record: content type 0x16, then protocol version 3.x
+        if buf.starts_with(r"\u{16}\u{3}") {
+            Some(NonHttp::Tls)
+        } else if buf.starts_with("PRI * HTTP/2.0") {
+            Some(NonHttp::Http2)
+        } else {
+            Some(NonHttp::Malformed)
+        }
+    }
+
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NonHttp::Tls => "tls",
+            NonHttp::Http2 => "http2",
+            NonHttp::Malformed => "malformed",
+        }
+    }
+}
+
+/// Logger counting the parse failures of non-HTTP traffic before handing records to
+/// `inner`, which filters and writes them
+pub struct Logger {
+    inner: env_logger::Logger,
+}
+
+impl Logger {
+    pub fn new(inner: env_logger::Logger) -> Self {
+        Self { inner }
+    }
+
+    /// Install as the global logger. Errors are let through to it even when the filter
+    /// drops them, so that parse failures are counted.
+    pub fn init(self) {
+        let max_level = self.inner.filter().max(LevelFilter::Error);
+        log::set_boxed_logger(Box::new(self)).expect("no logger is installed yet");
+        log::set_max_level(max_level);
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let Some(kind) = NonHttp::from_record(record) else {
+            self.inner.log(record);
+            return;
+        };
+        NON_HTTP_CONNECTIONS
+            .with_label_values(&[kind.as_str()])
+            .inc();
+        self.inner.log(
+            &Record::builder()
+                .args(format_args!(
+                    "Closed a connection sending non-HTTP bytes ({})",
+                    kind.as_str()
+                ))
+                .level(Level::Debug)
+                .target(TARGET)
+                .module_path_static(Some(module_path!()))
+                .file_static(Some(file!()))
+                .line(Some(line!()))
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
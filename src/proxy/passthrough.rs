@@ -0,0 +1,31 @@
+use crate::pipeline::views::RequestView;
+
+/// Explicit allowlist of non-LLM traffic (health checks, static metadata endpoints)
+/// that is forwarded untouched: no provider detection, strict mode, dedup or header
+/// policy. Everything else goes through the full LLM pipeline.
+///
+/// Requests are matched on their path alone: the `Host` header is the client's to set.
+/// Allowlisted requests are still authenticated like any other.
+#[derive(Debug, Clone, Default)]
+pub struct PassthroughAllowlist {
+    /// Path prefixes that pass through, e.g. `/healthz`
+    pub path_prefixes: Vec<String>,
+}
+
+impl PassthroughAllowlist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.path_prefixes.push(prefix.into());
+        self
+    }
+
+    pub fn matches(&self, request_view: &RequestView) -> bool {
+        let path = request_view.path();
+        self.path_prefixes
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+}
@@ -0,0 +1,65 @@
+//! `/healthz` and `/readyz`: liveness and readiness probes for orchestrators.
+//!
+//! Served by the proxy itself, on every listener, so probes never reach an LLM
+//! upstream. The gateway is live as long as it answers. It is ready unless it is in
+//! maintenance mode or every upstream was removed through the admin API; ejected
+//! upstreams are reported but keep it ready, since every replica shares them and taking
+//! all of them out of rotation would not help.
+
+use crate::upstream::Upstream;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Path liveness is served on
+pub const HEALTHZ_PATH: &str = "/healthz";
+
+/// Path readiness is served on
+pub const READYZ_PATH: &str = "/readyz";
+
+/// Body of the readiness endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    /// Why the gateway is not ready
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+    /// Upstreams new requests are sent to
+    pub enabled_upstreams: usize,
+    /// Enabled upstreams passive health checking ejected
+    pub ejected_upstreams: usize,
+}
+
+impl Readiness {
+    /// Readiness of a gateway over `upstreams`, in maintenance mode or not
+    pub fn check(maintenance: bool, upstreams: &[Arc<Upstream>]) -> Self {
+        let enabled: Vec<_> = upstreams
+            .iter()
+            .filter(|upstream| upstream.is_enabled())
+            .collect();
+        let ejected = enabled
+            .iter()
+            .filter(|upstream| upstream.health().is_some_and(|health| health.is_ejected()))
+            .count();
+        let reason = if maintenance {
+            Some("maintenance")
+        } else if enabled.is_empty() {
+            Some("no upstreams")
+        } else {
+            None
+        };
+        Self {
+            ready: reason.is_none(),
+            reason,
+            enabled_upstreams: enabled.len(),
+            ejected_upstreams: ejected,
+        }
+    }
+
+    /// 200 when ready, 503 otherwise
+    pub fn status(&self) -> u16 {
+        match self.ready {
+            true => 200,
+            false => 503,
+        }
+    }
+}
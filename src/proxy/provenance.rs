@@ -0,0 +1,204 @@
+use crate::crypto::{hex, unhex};
+use crate::proxy::attempts::UpstreamAttempt;
+use blake2::digest::consts::U32;
+use blake2::digest::{Digest, KeyInit, Mac};
+use blake2::{Blake2b, Blake2bMac};
+use serde::Serialize;
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type Blake2b256 = Blake2b<U32>;
+
+/// Where a completion came from: the model and provider that generated it and the
+/// gateway request that carried it, so downstream systems can trace generated content
+/// back to its source.
+///
+/// The record is sent in the `X-Langspec-Provenance` response header and/or written as
+/// a JSON audit entry (log target [`AUDIT_TARGET`](Self::AUDIT_TARGET)) once the
+/// response is complete. Headers are sent before the body, so only the audit entry
+/// carries the hash of the content; both share the request ID. With a signing key,
+/// each carries a keyed BLAKE2b signature that [`ProvenanceConfig::verify`] checks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProvenanceRecord {
+    /// Gateway-generated ID of the request
+    pub request_id: String,
+    pub model: Option<String>,
+    pub provider: String,
+    /// Unix time (seconds) the response started
+    pub timestamp: u64,
+    /// Gateway instance label, with an identity configured
+    pub instance: Option<String>,
+    /// `blake2b-256:<hex>` of the response body as sent to the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    /// Upstreams tried before and including the one that answered, when there were
+    /// several; only in the audit entry
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<UpstreamAttempt>,
+    /// Hex keyed BLAKE2b-256 of the other fields
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl ProvenanceRecord {
+    pub const RESPONSE_HEADER: &'static str = "X-Langspec-Provenance";
+    pub const AUDIT_TARGET: &'static str = "langspec::provenance";
+
+    /// Record for a response starting now, with a new request ID
+    pub fn new(model: Option<String>, provider: impl Into<String>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Self {
+            request_id: format!("{:032x}", rand::random::<u128>()),
+            model,
+            provider: provider.into(),
+            timestamp,
+            instance: None,
+            content_hash: None,
+            attempts: Vec::new(),
+            signature: None,
+        }
+    }
+
+    pub fn with_instance(mut self, instance: impl Into<String>) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    /// `X-Langspec-Provenance` value: `key=value` pairs separated by `; `
+    pub fn header_value(&self) -> String {
+        let mut value = format!("request-id={}", self.request_id);
+        if let Some(model) = &self.model {
+            let _ = write!(value, "; model=\"{}\"", model.replace('"', ""));
+        }
+        let _ = write!(
+            value,
+            "; provider={}; timestamp={}",
+            self.provider, self.timestamp
+        );
+        if let Some(instance) = &self.instance {
+            let _ = write!(value, "; instance=\"{}\"", instance.replace('"', ""));
+        }
+        if let Some(signature) = &self.signature {
+            let _ = write!(value, "; signature={}", signature);
+        }
+        value
+    }
+
+    /// JSON audit entry
+    pub fn audit_entry(&self) -> String {
+        serde_json::to_string(self).expect("provenance records serialize")
+    }
+
+    /// Signed fields, newline separated; absent fields are empty. Attempts, when
+    /// recorded, follow one per line.
+    fn signed_content(&self) -> String {
+        let mut content = [
+            self.request_id.as_str(),
+            self.model.as_deref().unwrap_or_default(),
+            &self.provider,
+            &self.timestamp.to_string(),
+            self.instance.as_deref().unwrap_or_default(),
+            self.content_hash.as_deref().unwrap_or_default(),
+        ]
+        .join("\n");
+        for attempt in &self.attempts {
+            let _ = write!(content, "\n{}", attempt);
+        }
+        content
+    }
+}
+
+/// Secret provenance signatures are keyed with
+#[derive(Clone)]
+pub struct ProvenanceKey([u8; 32]);
+
+impl ProvenanceKey {
+    /// Key derived from a secret of any length
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self(Blake2b256::digest(secret.as_ref()).into())
+    }
+
+    fn mac(&self) -> Blake2bMac<U32> {
+        <Blake2bMac<U32> as KeyInit>::new_from_slice(&self.0).expect("32-byte keys are valid")
+    }
+}
+
+impl std::fmt::Debug for ProvenanceKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProvenanceKey(..)")
+    }
+}
+
+/// Provenance records attached to completions
+#[derive(Debug, Clone, Default)]
+pub struct ProvenanceConfig {
+    /// Add the `X-Langspec-Provenance` response header
+    pub response_header: bool,
+    /// Write an audit entry, with the content hash, once each response completes
+    pub audit: bool,
+    pub key: Option<ProvenanceKey>,
+}
+
+impl ProvenanceConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_response_header(mut self) -> Self {
+        self.response_header = true;
+        self
+    }
+
+    pub fn with_audit(mut self) -> Self {
+        self.audit = true;
+        self
+    }
+
+    pub fn with_signing_key(mut self, key: ProvenanceKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Set the record's signature, with a signing key configured
+    pub fn sign(&self, record: &mut ProvenanceRecord) {
+        record.signature = self.key.as_ref().map(|key| {
+            let mut mac = key.mac();
+            mac.update(record.signed_content().as_bytes());
+            hex(&mac.finalize().into_bytes())
+        });
+    }
+
+    /// Whether the record's signature matches its fields; always false without a key
+    pub fn verify(&self, record: &ProvenanceRecord) -> bool {
+        let (Some(key), Some(signature)) = (&self.key, &record.signature) else {
+            return false;
+        };
+        let Some(signature) = unhex(signature) else {
+            return false;
+        };
+        let mut mac = key.mac();
+        mac.update(record.signed_content().as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+/// Hash of a response body, fed chunk by chunk as it is sent
+#[derive(Debug, Clone, Default)]
+pub struct ContentHasher(Blake2b256);
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.0.update(chunk);
+    }
+
+    /// `blake2b-256:<hex>` of everything fed
+    pub fn finish(self) -> String {
+        format!("blake2b-256:{}", hex(&self.0.finalize()))
+    }
+}
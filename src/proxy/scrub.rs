@@ -0,0 +1,154 @@
+//! Masking of credentials in text the gateway writes out.
+//!
+//! [`scrub`] masks the values of credential headers (`Authorization`, `x-api-key`,
+//! ...), bearer tokens and key-like tokens (provider API keys, AWS access key IDs,
+//! JWTs, the gateway's own virtual keys and capability tokens) wherever they appear in
+//! a text. The gateway's log lines go through it via [`logger`], as do upstream
+//! failures recorded for audit entries and the error bodies of upstream responses
+//! relayed to clients ([`BodyScrubber`]), since providers echo a rejected key back in
+//! their error messages.
+
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::io::Write;
+use std::sync::LazyLock;
+
+/// What a masked value is replaced with
+pub const REDACTED: &str = "[redacted]";
+
+/// Headers carrying credentials, lowercase
+pub const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "api-key",
+    "x-goog-api-key",
+    "x-amz-security-token",
+    "x-langspec-capability",
+];
+
+/// A credential header name followed by its value, as in an HTTP header, a JSON field
+/// or a query parameter
+static HEADER_VALUE: LazyLock<Regex> = LazyLock::new(|| {
+    let names = SENSITIVE_HEADERS
+        .iter()
+        .map(|name| regex::escape(name))
+        .collect::<Vec<_>>()
+        .join("|");
+    Regex::new(&format!(
+        r#"(?i)\b({})(["']?\s*[:=]\s*["']?)(?:(?:bearer|basic)\s+)?[^\s"',;&}}]+"#,
+        names
+    ))
+    .expect("pattern is valid")
+});
+
+static BEARER_TOKEN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(bearer)\s+[A-Za-z0-9._~+/=-]{8,}").expect("pattern is valid")
+});
+
+/// Provider API keys, AWS access key IDs, JWTs and the gateway's own client keys
+static KEY_TOKEN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"\b(?:",
+        r"sk-[A-Za-z0-9_-]{16,}",
+        r"|(?:xai|pplx)-[A-Za-z0-9]{16,}",
+        r"|(?:gsk|hf|ghp|gho|github_pat)_[A-Za-z0-9_]{16,}",
+        r"|AIza[0-9A-Za-z_-]{35}",
+        r"|(?:AKIA|ASIA)[0-9A-Z]{16}",
+        r"|eyJ[A-Za-z0-9_-]{8,}\.eyJ[A-Za-z0-9_-]{8,}\.[A-Za-z0-9_-]{8,}",
+        r"|lsk_[A-Za-z0-9_-]{16,}",
+        r"|lsc_[A-Za-z0-9_.-]{16,}",
+        r")"
+    ))
+    .expect("pattern is valid")
+});
+
+/// `text` with credentials masked; borrowed when there was nothing to mask
+pub fn scrub(text: &str) -> Cow<'_, str> {
+    let mut scrubbed = Cow::Borrowed(text);
+    let header_value = HEADER_VALUE.replace_all(&scrubbed, |captures: &Captures| {
+        format!("{}{}{}", &captures[1], &captures[2], REDACTED)
+    });
+    if let Cow::Owned(masked) = header_value {
+        scrubbed = Cow::Owned(masked);
+    }
+    let bearer = BEARER_TOKEN.replace_all(&scrubbed, |captures: &Captures| {
+        format!("{} {}", &captures[1], REDACTED)
+    });
+    if let Cow::Owned(masked) = bearer {
+        scrubbed = Cow::Owned(masked);
+    }
+    if let Cow::Owned(masked) = KEY_TOKEN.replace_all(&scrubbed, REDACTED) {
+        scrubbed = Cow::Owned(masked);
+    }
+    scrubbed
+}
+
+/// A header value as it may be written out: masked for credential headers, scrubbed
+/// otherwise
+pub fn scrub_header<'a>(name: &str, value: &'a str) -> Cow<'a, str> {
+    match SENSITIVE_HEADERS
+        .iter()
+        .any(|sensitive| name.eq_ignore_ascii_case(sensitive))
+    {
+        true => Cow::Borrowed(REDACTED),
+        false => scrub(value),
+    }
+}
+
+/// Logger configured from `RUST_LOG` like `env_logger::init`, writing every record
+/// scrubbed
+pub fn logger() -> env_logger::Builder {
+    let mut builder = env_logger::Builder::from_default_env();
+    builder.format(|buf, record| {
+        let message = record.args().to_string();
+        writeln!(
+            buf,
+            "[{} {:<5} {}] {}",
+            buf.timestamp(),
+            record.level(),
+            record.target(),
+            scrub(&message)
+        )
+    });
+    builder
+}
+
+/// Scrubs a response body passing through in chunks. The body is held back until it
+/// ends so no credential is split across chunks, up to `max_bytes`; a larger body is
+/// released as it arrives, each chunk scrubbed on its own. Bodies that are not UTF-8
+/// pass unchanged.
+#[derive(Debug)]
+pub struct BodyScrubber {
+    buffer: Vec<u8>,
+    max_bytes: usize,
+    overflowed: bool,
+}
+
+impl BodyScrubber {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_bytes,
+            overflowed: false,
+        }
+    }
+
+    /// Feed a chunk of the body, getting what to send on in its place
+    pub fn feed(&mut self, chunk: &[u8], end_of_stream: bool) -> Vec<u8> {
+        self.buffer.extend_from_slice(chunk);
+        if self.buffer.len() > self.max_bytes {
+            self.overflowed = true;
+        }
+        if !end_of_stream && !self.overflowed {
+            return Vec::new();
+        }
+        let body = std::mem::take(&mut self.buffer);
+        match std::str::from_utf8(&body).map(scrub) {
+            Ok(Cow::Owned(masked)) => masked.into_bytes(),
+            _ => body,
+        }
+    }
+}
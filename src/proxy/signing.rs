@@ -0,0 +1,427 @@
+use blake2::Blake2b;
+use blake2::digest::Digest;
+use blake2::digest::consts::U32;
+use pingora_http::RequestHeader;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::crypto::{constant_time_eq, hex, unhex};
+use crate::pipeline::views::RequestView;
+
+type Blake2b256 = Blake2b<U32>;
+
+/// BLAKE2b input block size, the HMAC block size
+const BLOCK_BYTES: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    /// The tenant requires signed requests and the request is not signed
+    Missing,
+    /// The signature header is not `key-id=..., timestamp=..., ...`
+    Malformed(&'static str),
+    UnknownKey(String),
+    /// The timestamp is outside the accepted clock skew
+    Expired,
+    BadSignature,
+    /// The key is bound to another tenant than the request's
+    TenantMismatch,
+    /// The signature was already used
+    Replayed,
+    /// Too many signatures within the tolerance window to remember another one
+    ReplayCacheFull,
+    /// The body does not hash to the signed content hash
+    BodyMismatch,
+}
+
+impl SignatureError {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignatureError::Missing => "missing",
+            SignatureError::Malformed(_) => "malformed",
+            SignatureError::UnknownKey(_) => "unknown_key",
+            SignatureError::Expired => "expired",
+            SignatureError::BadSignature => "bad_signature",
+            SignatureError::TenantMismatch => "tenant_mismatch",
+            SignatureError::Replayed => "replayed",
+            SignatureError::ReplayCacheFull => "replay_cache_full",
+            SignatureError::BodyMismatch => "body_mismatch",
+        }
+    }
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "request signature required"),
+            SignatureError::Malformed(field) => {
+                write!(f, "malformed request signature: '{}'", field)
+            }
+            SignatureError::UnknownKey(id) => write!(f, "unknown signing key '{}'", id),
+            SignatureError::Expired => write!(f, "request signature timestamp out of range"),
+            SignatureError::BadSignature => write!(f, "request signature does not match"),
+            SignatureError::TenantMismatch => {
+                write!(f, "signing key does not belong to the request's tenant")
+            }
+            SignatureError::Replayed => write!(f, "request signature already used"),
+            SignatureError::ReplayCacheFull => write!(f, "too many signed requests to verify"),
+            SignatureError::BodyMismatch => {
+                write!(f, "request body does not match its signed content hash")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Shared secret a client signs requests with, optionally bound to a tenant
+#[derive(Clone)]
+pub struct SigningKey {
+    secret: Vec<u8>,
+    /// Tenant requests signed with the key are made as, unless an earlier stage made
+    /// them as another (rejected); none when unset
+    pub tenant: Option<String>,
+}
+
+impl SigningKey {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            tenant: None,
+        }
+    }
+
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// `X-Langspec-Signature` value for a request, as a client computes it
+    pub fn sign(
+        &self,
+        key_id: &str,
+        method: &str,
+        path: &str,
+        timestamp: u64,
+        nonce: &str,
+        body: &[u8],
+    ) -> String {
+        let content_hash = hex(&Blake2b256::digest(body));
+        let signature = hex(&hmac(
+            &self.secret,
+            string_to_sign(key_id, timestamp, nonce, method, path, &content_hash).as_bytes(),
+        ));
+        format!(
+            "key-id={}, timestamp={}, nonce={}, content-hash={}, signature={}",
+            key_id, timestamp, nonce, content_hash, signature
+        )
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("tenant", &self.tenant)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Signed client requests, for tenants that do not want bearer keys alone to protect
+/// their LLM spend.
+///
+/// Clients send `X-Langspec-Signature: key-id=<id>, timestamp=<unix seconds>,
+/// nonce=<random>, content-hash=<hex>, signature=<hex>`, where the content hash is the
+/// BLAKE2b-256 of the body and the signature is the HMAC-BLAKE2b-256 (RFC 2104, with
+/// BLAKE2b-256 as the hash) of the key's secret over
+///
+/// ```text
+/// LANGSPEC-HMAC-BLAKE2B
+/// <key id>
+/// <timestamp>
+/// <nonce>
+/// <METHOD>
+/// <path and query>
+/// <content hash>
+/// ```
+///
+/// Timestamps more than [`tolerance`](Self::tolerance) away from the gateway's clock
+/// are rejected, and each signature is accepted once: signatures are remembered until
+/// their timestamp expires. The body is hashed as it is forwarded; a body that does not
+/// match is cut off before its end reaches the upstream. Unsigned requests pass unless
+/// their tenant is listed as requiring signatures.
+#[derive(Debug, Clone)]
+pub struct SigningConfig {
+    pub keys: HashMap<String, SigningKey>,
+    /// Tenants whose requests must be signed
+    pub required_tenants: HashSet<String>,
+    /// Accepted clock skew between clients and the gateway
+    pub tolerance: Duration,
+    /// Signatures remembered at most; further signed requests are rejected until
+    /// remembered ones expire
+    pub replay_capacity: usize,
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        Self {
+            keys: HashMap::new(),
+            required_tenants: HashSet::new(),
+            tolerance: Duration::from_secs(300),
+            replay_capacity: 100_000,
+        }
+    }
+}
+
+impl SigningConfig {
+    pub const HEADER: &'static str = "X-Langspec-Signature";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_key(mut self, id: impl Into<String>, key: SigningKey) -> Self {
+        self.keys.insert(id.into(), key);
+        self
+    }
+
+    /// Reject unsigned requests of `tenant`
+    pub fn with_required_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.required_tenants.insert(tenant.into());
+        self
+    }
+
+    pub fn with_tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_replay_capacity(mut self, capacity: usize) -> Self {
+        self.replay_capacity = capacity;
+        self
+    }
+}
+
+/// A verified signature header; the body remains to be checked against it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedSignature {
+    pub key_id: String,
+    /// Tenant the key is bound to
+    pub tenant: Option<String>,
+    content_hash: String,
+}
+
+impl VerifiedSignature {
+    /// Hasher the request body is fed to as it is forwarded
+    pub fn body_check(&self) -> BodyCheck {
+        BodyCheck {
+            expected: self.content_hash.clone(),
+            hasher: Blake2b256::new(),
+        }
+    }
+}
+
+/// Verifies signed requests and remembers their signatures
+#[derive(Debug)]
+pub struct SignatureVerifier {
+    config: SigningConfig,
+    /// Signature → unix time it stops being accepted anyway
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl SignatureVerifier {
+    pub fn new(config: SigningConfig) -> Self {
+        Self {
+            config,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &SigningConfig {
+        &self.config
+    }
+
+    /// Verify a request's signature header at `now` (unix seconds), for the tenant an
+    /// earlier stage made the request as, if any. `Ok(None)` for an unsigned request
+    /// that does not need to be signed.
+    pub fn verify(
+        &self,
+        request: &RequestHeader,
+        now: u64,
+    ) -> Result<Option<VerifiedSignature>, SignatureError> {
+        let view = RequestView::new(request);
+        let tenant = view.tenant();
+        let Some(header) = view.header(SigningConfig::HEADER) else {
+            return match tenant.is_some_and(|tenant| self.config.required_tenants.contains(tenant))
+            {
+                true => Err(SignatureError::Missing),
+                false => Ok(None),
+            };
+        };
+
+        let params = SignatureParams::parse(header)?;
+        let key = self
+            .config
+            .keys
+            .get(params.key_id)
+            .ok_or_else(|| SignatureError::UnknownKey(params.key_id.to_string()))?;
+        if let (Some(bound), Some(tenant)) = (key.tenant.as_deref(), tenant)
+            && bound != tenant
+        {
+            return Err(SignatureError::TenantMismatch);
+        }
+        if now.abs_diff(params.timestamp) > self.config.tolerance.as_secs() {
+            return Err(SignatureError::Expired);
+        }
+
+        let path = request
+            .uri
+            .path_and_query()
+            .map_or("/", |path| path.as_str());
+        let expected = hmac(
+            &key.secret,
+            string_to_sign(
+                params.key_id,
+                params.timestamp,
+                params.nonce,
+                request.method.as_str(),
+                path,
+                params.content_hash,
+            )
+            .as_bytes(),
+        );
+        let signature = unhex(params.signature).ok_or(SignatureError::Malformed("signature"))?;
+        if !constant_time_eq(&signature, &expected) {
+            return Err(SignatureError::BadSignature);
+        }
+
+        self.remember(
+            params.signature,
+            params.timestamp + self.config.tolerance.as_secs(),
+            now,
+        )?;
+        Ok(Some(VerifiedSignature {
+            key_id: params.key_id.to_string(),
+            tenant: key.tenant.clone(),
+            content_hash: params.content_hash.to_ascii_lowercase(),
+        }))
+    }
+
+    /// Signatures currently remembered
+    pub fn remembered(&self) -> usize {
+        self.seen.lock().unwrap().len()
+    }
+
+    fn remember(&self, signature: &str, expires: u64, now: u64) -> Result<(), SignatureError> {
+        let signature = signature.to_ascii_lowercase();
+        let mut seen = self.seen.lock().unwrap();
+        if seen.get(&signature).is_some_and(|&until| until >= now) {
+            return Err(SignatureError::Replayed);
+        }
+        if seen.len() >= self.config.replay_capacity {
+            seen.retain(|_, until| *until >= now);
+            if seen.len() >= self.config.replay_capacity {
+                return Err(SignatureError::ReplayCacheFull);
+            }
+        }
+        seen.insert(signature, expires);
+        Ok(())
+    }
+}
+
+/// Hash of a signed request's body, fed chunk by chunk as it is forwarded
+#[derive(Debug, Clone)]
+pub struct BodyCheck {
+    expected: String,
+    hasher: Blake2b256,
+}
+
+impl BodyCheck {
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    /// Whether the body fed matches the signed content hash
+    pub fn finish(self) -> Result<(), SignatureError> {
+        match hex(&self.hasher.finalize()) == self.expected {
+            true => Ok(()),
+            false => Err(SignatureError::BodyMismatch),
+        }
+    }
+}
+
+struct SignatureParams<'a> {
+    key_id: &'a str,
+    timestamp: u64,
+    nonce: &'a str,
+    content_hash: &'a str,
+    signature: &'a str,
+}
+
+impl<'a> SignatureParams<'a> {
+    fn parse(header: &'a str) -> Result<Self, SignatureError> {
+        let mut params = HashMap::new();
+        for param in header.split(',') {
+            let (name, value) = param
+                .split_once('=')
+                .ok_or(SignatureError::Malformed("header"))?;
+            params.insert(name.trim(), value.trim());
+        }
+        let param = |name: &'static str| {
+            params
+                .get(name)
+                .copied()
+                .filter(|value| !value.is_empty())
+                .ok_or(SignatureError::Malformed(name))
+        };
+        Ok(Self {
+            key_id: param("key-id")?,
+            timestamp: param("timestamp")?
+                .parse()
+                .map_err(|_| SignatureError::Malformed("timestamp"))?,
+            nonce: param("nonce")?,
+            content_hash: param("content-hash")?,
+            signature: param("signature")?,
+        })
+    }
+}
+
+fn string_to_sign(
+    key_id: &str,
+    timestamp: u64,
+    nonce: &str,
+    method: &str,
+    path: &str,
+    content_hash: &str,
+) -> String {
+    format!(
+        "LANGSPEC-HMAC-BLAKE2B\n{}\n{}\n{}\n{}\n{}\n{}",
+        key_id,
+        timestamp,
+        nonce,
+        method.to_ascii_uppercase(),
+        path,
+        content_hash.to_ascii_lowercase()
+    )
+}
+
+/// HMAC (RFC 2104) with BLAKE2b-256
+fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = [0u8; BLOCK_BYTES];
+    if key.len() > BLOCK_BYTES {
+        block[..32].copy_from_slice(&Blake2b256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = Blake2b256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Blake2b256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .to_vec()
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// A step of request handling that can fail on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Tenant attribution by listener
+    Listener,
+    /// Tenant attribution by client certificate
+    ClientCert,
+    /// Provider detection and request enrichment (`Pipeline::on_request`)
+    Detection,
+    /// Capability token checks
+    Capability,
+    /// Virtual key checks
+    VirtualKey,
+    /// JWT validation
+    Jwt,
+    /// Provider keys injected from the secret store
+    Credentials,
+    /// Payload capture sampling
+    PayloadCapture,
+    RateLimit,
+    /// Per-key concurrency and request tracking
+    KeyConcurrency,
+    Mock,
+    Budget,
+    Deprecation,
+    /// Request body rewrites
+    BodyRewrite,
+    /// Prompt injection screening
+    PromptInjection,
+    /// Content moderation of prompts
+    Moderation,
+    Language,
+    ResponseCache,
+    SemanticCache,
+    /// Usage read from responses (`Pipeline::on_response`, `Pipeline::on_response_body`)
+    Usage,
+    /// Provenance audit entries
+    Audit,
+}
+
+impl Stage {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Listener => "listener",
+            Stage::ClientCert => "client_cert",
+            Stage::Detection => "detection",
+            Stage::Capability => "capability",
+            Stage::VirtualKey => "virtual_key",
+            Stage::Jwt => "jwt",
+            Stage::Credentials => "credentials",
+            Stage::PayloadCapture => "payload_capture",
+            Stage::RateLimit => "rate_limit",
+            Stage::KeyConcurrency => "key_concurrency",
+            Stage::Mock => "mock",
+            Stage::Budget => "budget",
+            Stage::Deprecation => "deprecation",
+            Stage::BodyRewrite => "body_rewrite",
+            Stage::PromptInjection => "prompt_injection",
+            Stage::Moderation => "moderation",
+            Stage::Language => "language",
+            Stage::ResponseCache => "response_cache",
+            Stage::SemanticCache => "semantic_cache",
+            Stage::Usage => "usage",
+            Stage::Audit => "audit",
+        }
+    }
+
+    pub const ALL: [Stage; 21] = [
+        Stage::Listener,
+        Stage::ClientCert,
+        Stage::Detection,
+        Stage::Capability,
+        Stage::VirtualKey,
+        Stage::Jwt,
+        Stage::Credentials,
+        Stage::PayloadCapture,
+        Stage::RateLimit,
+        Stage::KeyConcurrency,
+        Stage::Mock,
+        Stage::Budget,
+        Stage::Deprecation,
+        Stage::BodyRewrite,
+        Stage::PromptInjection,
+        Stage::Moderation,
+        Stage::Language,
+        Stage::ResponseCache,
+        Stage::SemanticCache,
+        Stage::Usage,
+        Stage::Audit,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|stage| stage.as_str() == name)
+    }
+
+    /// Authentication and limits fail closed; everything else only observes or
+    /// enriches the request and fails open
+    pub fn default_policy(&self) -> FailurePolicy {
+        match self {
+            Stage::Listener
+            | Stage::ClientCert
+            | Stage::Capability
+            | Stage::VirtualKey
+            | Stage::Jwt
+            | Stage::Credentials
+            | Stage::RateLimit
+            | Stage::KeyConcurrency
+            | Stage::Budget => FailurePolicy::FailClosed,
+            _ => FailurePolicy::FailOpen,
+        }
+    }
+}
+
+/// What happens to a request when a stage panics or errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Log the failure and continue with the request as if the stage had passed
+    FailOpen,
+    /// Fail the request with a 500
+    FailClosed,
+}
+
+impl FailurePolicy {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailurePolicy::FailOpen => "fail_open",
+            FailurePolicy::FailClosed => "fail_closed",
+        }
+    }
+
+    /// `open` or `closed`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "open" => Some(FailurePolicy::FailOpen),
+            "closed" => Some(FailurePolicy::FailClosed),
+            _ => None,
+        }
+    }
+}
+
+/// How a stage failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageFailure {
+    Error,
+    Panic,
+}
+
+impl StageFailure {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StageFailure::Error => "error",
+            StageFailure::Panic => "panic",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StagePolicyError {
+    /// An entry is not `stage=open` or `stage=closed`
+    InvalidEntry(String),
+    UnknownStage(String),
+}
+
+impl fmt::Display for StagePolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StagePolicyError::InvalidEntry(entry) => {
+                write!(
+                    f,
+                    "invalid stage policy '{}': use stage=open or stage=closed",
+                    entry
+                )
+            }
+            StagePolicyError::UnknownStage(stage) => write!(f, "unknown stage '{}'", stage),
+        }
+    }
+}
+
+impl std::error::Error for StagePolicyError {}
+
+/// Failure policy per stage: the stage's default unless overridden.
+///
+/// Rejections a stage decides on (a 429 from the rate limiter, a 401 from the
+/// capability check) are not failures; only unexpected errors and panics are.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StagePolicies {
+    overrides: HashMap<Stage, FailurePolicy>,
+}
+
+impl StagePolicies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_policy(mut self, stage: Stage, policy: FailurePolicy) -> Self {
+        self.overrides.insert(stage, policy);
+        self
+    }
+
+    /// Comma-separated `stage=open` or `stage=closed` overrides, e.g.
+    /// `usage=closed,budget=open`
+    pub fn parse(value: &str) -> Result<Self, StagePolicyError> {
+        let mut policies = Self::new();
+        for entry in value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let invalid = || StagePolicyError::InvalidEntry(entry.to_string());
+            let (stage, policy) = entry.split_once('=').ok_or_else(invalid)?;
+            let policy = FailurePolicy::parse(policy.trim()).ok_or_else(invalid)?;
+            let stage = Stage::parse(stage.trim())
+                .ok_or_else(|| StagePolicyError::UnknownStage(stage.trim().to_string()))?;
+            policies = policies.with_policy(stage, policy);
+        }
+        Ok(policies)
+    }
+
+    pub fn policy(&self, stage: Stage) -> FailurePolicy {
+        self.overrides
+            .get(&stage)
+            .copied()
+            .unwrap_or_else(|| stage.default_policy())
+    }
+}
@@ -0,0 +1,54 @@
+/// Status returned for traffic rejected by strict mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RejectStatus {
+    /// 421: the request reached a gateway that does not serve it
+    #[default]
+    MisdirectedRequest,
+    /// 400: the request is not valid LLM traffic
+    BadRequest,
+}
+
+impl RejectStatus {
+    pub fn code(&self) -> u16 {
+        match self {
+            RejectStatus::MisdirectedRequest => 421,
+            RejectStatus::BadRequest => 400,
+        }
+    }
+}
+
+/// Strict mode: requests whose provider is still `Unknown` after detection are
+/// rejected instead of forwarded, for deployments that must only carry LLM traffic.
+///
+/// Applies to every request on the listener, or only to the configured path prefixes.
+#[derive(Debug, Clone, Default)]
+pub struct StrictMode {
+    pub status: RejectStatus,
+    /// Path prefixes (routes) strict mode applies to; empty means all paths
+    pub path_prefixes: Vec<String>,
+}
+
+impl StrictMode {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_status(mut self, status: RejectStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Restrict strict mode to a route
+    pub fn with_path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.path_prefixes.push(prefix.into());
+        self
+    }
+
+    pub fn applies_to(&self, path: &str) -> bool {
+        self.path_prefixes.is_empty()
+            || self
+                .path_prefixes
+                .iter()
+                .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+}
@@ -0,0 +1,137 @@
+use chrono::{SecondsFormat, Utc};
+use std::fmt;
+use std::net::IpAddr;
+
+/// Request-derived values available to header templates.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateVars<'a> {
+    pub client_ip: Option<IpAddr>,
+    pub tenant: Option<&'a str>,
+    pub provider: Option<&'a str>,
+    pub model: Option<&'a str>,
+}
+
+/// A template variable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    ClientIp,
+    Tenant,
+    Provider,
+    Model,
+    NowRfc3339,
+}
+
+impl Variable {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "client_ip" => Some(Variable::ClientIp),
+            "tenant" => Some(Variable::Tenant),
+            "provider" => Some(Variable::Provider),
+            "model" => Some(Variable::Model),
+            "now_rfc3339" => Some(Variable::NowRfc3339),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Variable(Variable),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    UnknownVariable(String),
+    Unterminated,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnknownVariable(name) => {
+                write!(f, "unknown template variable '{{{}}}'", name)
+            }
+            TemplateError::Unterminated => write!(f, "unterminated '{{' in template"),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// A header value template such as `tenant={tenant};provider={provider}`.
+///
+/// Variables are written as `{name}`; `{{` and `}}` produce literal braces.
+/// Templates are parsed once at startup so unknown variables fail fast, and
+/// rendered per request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError::Unterminated),
+                        }
+                    }
+                    let variable =
+                        Variable::parse(name.trim()).ok_or(TemplateError::UnknownVariable(name))?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Variable(variable));
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self { segments })
+    }
+
+    /// Render the template. Variables without a value render as empty strings.
+    pub fn render(&self, vars: &TemplateVars) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => out.push_str(text),
+                Segment::Variable(variable) => match variable {
+                    Variable::ClientIp => {
+                        if let Some(ip) = vars.client_ip {
+                            out.push_str(&ip.to_string());
+                        }
+                    }
+                    Variable::Tenant => out.push_str(vars.tenant.unwrap_or_default()),
+                    Variable::Provider => out.push_str(vars.provider.unwrap_or_default()),
+                    Variable::Model => out.push_str(vars.model.unwrap_or_default()),
+                    Variable::NowRfc3339 => {
+                        out.push_str(&Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true))
+                    }
+                },
+            }
+        }
+        out
+    }
+}
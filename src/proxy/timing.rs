@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+/// Implicit mark for the moment the request context was created
+pub const REQUEST_START: &str = "request_start";
+
+/// Phases reported in the per-request breakdown: `(phase, from mark, to mark)`.
+/// A phase is only reported once both of its marks have been recorded.
+pub const PHASES: &[(&str, &str, &str)] = &[
+    ("detect", "detect_start", "detect_done"),
+    ("queue", REQUEST_START, "upstream_selected"),
+    (
+        "upstream_connect",
+        "upstream_selected",
+        "upstream_connected",
+    ),
+    ("ttfb", "upstream_selected", "response_header"),
+    ("stream", "response_header", "response_done"),
+];
+
+/// Named timestamps recorded as a request moves through the gateway.
+///
+/// Stages call `mark` at their boundaries; the breakdown of `PHASES` derived from the
+/// marks feeds `Server-Timing`, the access log and the phase histograms.
+#[derive(Debug, Clone)]
+pub struct PhaseTimer {
+    start: Instant,
+    marks: Vec<(&'static str, Instant)>,
+}
+
+impl PhaseTimer {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            marks: Vec::new(),
+        }
+    }
+
+    /// Record a mark at the current time. Marking again (e.g. on a retry) moves it.
+    pub fn mark(&mut self, name: &'static str) {
+        let now = Instant::now();
+        match self.marks.iter_mut().find(|(mark, _)| *mark == name) {
+            Some((_, at)) => *at = now,
+            None => self.marks.push((name, now)),
+        }
+    }
+
+    /// When a mark was recorded
+    pub fn at(&self, name: &str) -> Option<Instant> {
+        if name == REQUEST_START {
+            return Some(self.start);
+        }
+        self.marks
+            .iter()
+            .find(|(mark, _)| *mark == name)
+            .map(|(_, at)| *at)
+    }
+
+    /// Time between two marks, if both were recorded
+    pub fn between(&self, from: &str, to: &str) -> Option<Duration> {
+        Some(self.at(to)?.saturating_duration_since(self.at(from)?))
+    }
+
+    /// Time elapsed since a mark, if it was recorded
+    pub fn since(&self, name: &str) -> Option<Duration> {
+        self.at(name).map(|at| at.elapsed())
+    }
+
+    /// Durations of every phase whose marks have been recorded, in `PHASES` order
+    pub fn breakdown(&self) -> Vec<(&'static str, Duration)> {
+        PHASES
+            .iter()
+            .filter_map(|(phase, from, to)| Some((*phase, self.between(from, to)?)))
+            .collect()
+    }
+}
+
+impl Default for PhaseTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builder for a `Server-Timing` response header value.
+///
+/// Each phase is rendered as `name;dur=<milliseconds>`, e.g.
+/// `detect;dur=0.041, queue;dur=0.310, upstream_connect;dur=12.502, ttfb;dur=431.907`.
+#[derive(Debug, Clone, Default)]
+pub struct ServerTiming {
+    entries: Vec<(&'static str, Duration)>,
+}
+
+impl ServerTiming {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a phase; phases that were not measured are skipped
+    pub fn add(&mut self, name: &'static str, duration: Option<Duration>) -> &mut Self {
+        if let Some(duration) = duration {
+            self.entries.push((name, duration));
+        }
+        self
+    }
+
+    /// Every phase measured so far
+    pub fn from_timer(timer: &PhaseTimer) -> Self {
+        Self {
+            entries: timer.breakdown(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn header_value(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(name, duration)| format!("{};dur={:.3}", name, duration.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
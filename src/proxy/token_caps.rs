@@ -0,0 +1,54 @@
+use crate::pipeline::views::RequestView;
+use std::collections::HashMap;
+
+/// Caps on the output tokens of a single streamed response, bounding the worst-case
+/// cost of a request.
+///
+/// A cap can be set per tenant (`X-Langspec-Tenant`), per route (path prefix) and as a
+/// default; when several apply, the lowest wins. Once a stream reaches its cap the
+/// gateway ends it toward the client with a synthetic length stop event
+/// (`finish_reason: length`) and cancels the upstream.
+#[derive(Debug, Clone, Default)]
+pub struct OutputTokenCaps {
+    /// Cap for every request
+    pub default: Option<u64>,
+    pub tenants: HashMap<String, u64>,
+    /// Path prefixes (routes) with their cap
+    pub routes: Vec<(String, u64)>,
+}
+
+impl OutputTokenCaps {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_default(mut self, cap: u64) -> Self {
+        self.default = Some(cap);
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: impl Into<String>, cap: u64) -> Self {
+        self.tenants.insert(tenant.into(), cap);
+        self
+    }
+
+    pub fn with_route(mut self, prefix: impl Into<String>, cap: u64) -> Self {
+        self.routes.push((prefix.into(), cap));
+        self
+    }
+
+    /// Lowest cap that applies to the request, if any
+    pub fn cap_for(&self, request_view: &RequestView) -> Option<u64> {
+        let path = request_view.path();
+        let tenant = request_view
+            .tenant()
+            .and_then(|tenant| self.tenants.get(tenant).copied());
+        let route = self
+            .routes
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .map(|(_, cap)| *cap)
+            .min();
+        [self.default, tenant, route].into_iter().flatten().min()
+    }
+}
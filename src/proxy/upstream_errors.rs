@@ -0,0 +1,119 @@
+use pingora_error::{Error, ErrorSource, ErrorType};
+use serde_json::Value;
+
+/// Class of a low-level upstream failure.
+///
+/// Pingora reports every failure to reach or read from an upstream as a 502; the
+/// class tells a provider outage (refused or reset connections, malformed responses)
+/// apart from network trouble on the way (timeouts, TLS failures), in metrics and in
+/// the error returned to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamFailure {
+    /// No connection within the connect timeout
+    ConnectTimeout,
+    /// The upstream refused the connection, or there is no route to it
+    ConnectRefused,
+    /// The TLS handshake failed: a TLS alert, an invalid certificate or a timeout
+    TlsHandshake,
+    /// Any other failure to connect, e.g. through the egress proxy
+    ConnectFailed,
+    /// The upstream did not answer, or stopped sending, within the read timeout
+    ReadTimeout,
+    /// The connection was reset or closed before the response started
+    ConnectionReset,
+    /// The connection was reset or closed while the response was being streamed
+    ResetMidStream,
+    /// The upstream sent something that is not valid HTTP, e.g. malformed chunked
+    /// encoding or a body shorter than its length
+    MalformedResponse,
+}
+
+impl UpstreamFailure {
+    /// Response header naming the failure class in gateway-generated errors
+    pub const RESPONSE_HEADER: &'static str = "X-Langspec-Upstream-Error";
+
+    /// Class of an error, if it is an upstream failure; `response_started` tells
+    /// whether the response header was already received
+    pub fn classify(error: &Error, response_started: bool) -> Option<Self> {
+        use ErrorType::*;
+        if error.esource() != &ErrorSource::Upstream {
+            return None;
+        }
+        let failure = match error.etype() {
+            ConnectTimedout => Self::ConnectTimeout,
+            ConnectRefused | ConnectNoRoute => Self::ConnectRefused,
+            TLSHandshakeFailure | TLSHandshakeTimedout | TLSWantX509Lookup | InvalidCert
+            | HandshakeError => Self::TlsHandshake,
+            ConnectError | ConnectProxyFailure | SocketError | BindError => Self::ConnectFailed,
+            InvalidHTTPHeader | H1Error | H2Error | InvalidH2 => Self::MalformedResponse,
+            Custom("InvalidChunk" | "PrematureBodyEnd") => Self::MalformedResponse,
+            ReadTimedout | WriteTimedout | Custom("PingTimedout") => Self::ReadTimeout,
+            ReadError | WriteError | ConnectionClosed => match response_started {
+                true => Self::ResetMidStream,
+                false => Self::ConnectionReset,
+            },
+            _ => return None,
+        };
+        Some(failure)
+    }
+
+    /// Metric label and client error code
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpstreamFailure::ConnectTimeout => "upstream_connect_timeout",
+            UpstreamFailure::ConnectRefused => "upstream_connect_refused",
+            UpstreamFailure::TlsHandshake => "upstream_tls_error",
+            UpstreamFailure::ConnectFailed => "upstream_connect_failed",
+            UpstreamFailure::ReadTimeout => "upstream_timeout",
+            UpstreamFailure::ConnectionReset => "upstream_connection_reset",
+            UpstreamFailure::ResetMidStream => "upstream_reset_mid_stream",
+            UpstreamFailure::MalformedResponse => "upstream_malformed_response",
+        }
+    }
+
+    /// Status the client gets: 504 for timeouts, 502 otherwise
+    pub fn status(&self) -> u16 {
+        match self {
+            UpstreamFailure::ConnectTimeout | UpstreamFailure::ReadTimeout => 504,
+            _ => 502,
+        }
+    }
+
+    /// Whether the failure points at the network path rather than the provider
+    pub fn is_network(&self) -> bool {
+        matches!(
+            self,
+            UpstreamFailure::ConnectTimeout
+                | UpstreamFailure::TlsHandshake
+                | UpstreamFailure::ConnectFailed
+                | UpstreamFailure::ReadTimeout
+        )
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            UpstreamFailure::ConnectTimeout => "Timed out connecting to the upstream provider",
+            UpstreamFailure::ConnectRefused => "The upstream provider refused the connection",
+            UpstreamFailure::TlsHandshake => "TLS handshake with the upstream provider failed",
+            UpstreamFailure::ConnectFailed => "Could not connect to the upstream provider",
+            UpstreamFailure::ReadTimeout => "Timed out waiting for the upstream provider",
+            UpstreamFailure::ConnectionReset => {
+                "The upstream provider closed the connection before responding"
+            }
+            UpstreamFailure::ResetMidStream => {
+                "The upstream provider closed the connection during the response"
+            }
+            UpstreamFailure::MalformedResponse => "The upstream provider sent a malformed response",
+        }
+    }
+
+    /// OpenAI-style error body for the client
+    pub fn error_body(&self) -> Value {
+        serde_json::json!({"error": {
+            "message": self.message(),
+            "type": "upstream_error",
+            "param": null,
+            "code": self.as_str()
+        }})
+    }
+}
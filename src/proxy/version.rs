@@ -0,0 +1,70 @@
+//! `/.well-known/langspec/version`: which gateway build and config an endpoint runs.
+//!
+//! Served by the proxy itself, on every listener, so automation can check the very
+//! endpoint it is about to route production traffic to: the crate version, the commit
+//! it was built from, the features compiled in and the applied config and provider
+//! registry versions.
+
+use serde::Serialize;
+
+/// Path the version is served on
+pub const VERSION_PATH: &str = "/.well-known/langspec/version";
+
+/// What this binary was built from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Commit the binary was built from, from `LANGSPEC_BUILD_HASH` at build time
+    pub build_hash: &'static str,
+    /// Cargo features compiled in, sorted
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let features = [
+            ("admin", cfg!(feature = "admin")),
+            ("capability", cfg!(feature = "capability")),
+            ("config", cfg!(feature = "config")),
+            ("discovery", cfg!(feature = "discovery")),
+            ("egress", cfg!(feature = "egress")),
+            ("fixtures", cfg!(feature = "fixtures")),
+            ("jwt", cfg!(feature = "jwt")),
+            ("provenance", cfg!(feature = "provenance")),
+            ("proxy", cfg!(feature = "proxy")),
+            ("signing", cfg!(feature = "signing")),
+            ("snapshot", cfg!(feature = "snapshot")),
+            ("stub", cfg!(feature = "stub")),
+            ("tls", cfg!(feature = "tls")),
+            ("translate", cfg!(feature = "translate")),
+            ("virtual-keys", cfg!(feature = "virtual-keys")),
+        ];
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            build_hash: option_env!("LANGSPEC_BUILD_HASH").unwrap_or("unknown"),
+            features: features
+                .into_iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(feature, _)| feature)
+                .collect(),
+        }
+    }
+}
+
+/// Body of the version endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionInfo {
+    #[serde(flatten)]
+    pub build: BuildInfo,
+    /// `cluster/instance` of the replica answering
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Applied config file version, when serving one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_version: Option<u64>,
+    /// RFC 3339 time the config version was applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_loaded_at: Option<String>,
+    /// Applied provider registry version
+    pub provider_registry_version: u64,
+}
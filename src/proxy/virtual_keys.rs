@@ -0,0 +1,394 @@
+use crate::budget::BudgetConfig;
+use crate::pipeline::rate_limit::RateLimitConfig;
+use crate::upstream::Credential;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use blake2::Blake2b;
+use blake2::digest::Digest;
+use blake2::digest::consts::U32;
+use pingora_http::RequestHeader;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+type Blake2b256 = Blake2b<U32>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VirtualKeyError {
+    /// A key's name is empty or ends in `*`, which rate limits read as a prefix
+    InvalidName(String),
+    DuplicateName(String),
+    /// A key's hash is not 64 hex digits
+    InvalidHash(String),
+    /// Two keys have the same hash
+    DuplicateHash(String),
+    /// The environment variable holding a key's upstream credential is not set
+    MissingUpstreamKey(String, String),
+}
+
+impl fmt::Display for VirtualKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VirtualKeyError::InvalidName(name) => write!(
+                f,
+                "virtual key name '{}' must be non-empty and not end in '*'",
+                name
+            ),
+            VirtualKeyError::DuplicateName(name) => {
+                write!(f, "virtual key '{}' is declared twice", name)
+            }
+            VirtualKeyError::InvalidHash(name) => write!(
+                f,
+                "virtual key '{}' must have a key_hash of 64 hex digits",
+                name
+            ),
+            VirtualKeyError::DuplicateHash(name) => {
+                write!(f, "virtual key '{}' has the key_hash of another key", name)
+            }
+            VirtualKeyError::MissingUpstreamKey(name, env) => write!(
+                f,
+                "upstream key of virtual key '{}' is read from ${}, which is not set",
+                name, env
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VirtualKeyError {}
+
+/// Why a request presenting a virtual key is rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyRejection {
+    /// No virtual key has the presented key's hash
+    UnknownKey,
+    /// The request names another tenant than the key's
+    TenantMismatch,
+    /// The key does not allow the requested model (or the request names none)
+    ModelNotAllowed(String),
+}
+
+impl KeyRejection {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyRejection::UnknownKey => "unknown_key",
+            KeyRejection::TenantMismatch => "tenant_mismatch",
+            KeyRejection::ModelNotAllowed(_) => "model_not_allowed",
+        }
+    }
+
+    /// 401 for a key that is not valid, 403 for a request the key does not cover
+    pub fn status(&self) -> u16 {
+        match self {
+            KeyRejection::UnknownKey => 401,
+            KeyRejection::TenantMismatch | KeyRejection::ModelNotAllowed(_) => 403,
+        }
+    }
+}
+
+impl fmt::Display for KeyRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyRejection::UnknownKey => write!(f, "unknown virtual key"),
+            KeyRejection::TenantMismatch => write!(f, "virtual key belongs to another tenant"),
+            KeyRejection::ModelNotAllowed(model) => {
+                write!(f, "virtual key does not allow model '{}'", model)
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeyRejection {}
+
+/// Provider credential of a virtual key in the config file, read from the environment
+/// so the config holds no secrets
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamKeyConfig {
+    /// Environment variable holding the provider key
+    pub env: String,
+    /// Header the key is sent in, e.g. `x-api-key`; `Authorization: Bearer <key>` by
+    /// default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+}
+
+/// Spend limit of a virtual key in the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyBudgetConfig {
+    pub limit_usd: f64,
+    /// Length of the budget's window, e.g. 86400 for a UTC day
+    pub window_secs: u64,
+}
+
+/// A virtual key in the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualKeyConfig {
+    /// Tenant requests made with the key are made as
+    pub name: String,
+    /// Hex BLAKE2b-256 of the key, as returned when it was issued
+    pub key_hash: String,
+    /// Models that may be requested, exact or a prefix ending in `*`; any when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+    /// Provider credential sent upstream in place of the upstream's own credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_key: Option<UpstreamKeyConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<f64>,
+    /// Requests allowed at once after a quiet period; one second's worth by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget: Option<KeyBudgetConfig>,
+}
+
+impl VirtualKeyConfig {
+    /// Rate limit of the key's tenant, ahead of the configured ones
+    pub fn rate_limit(&self) -> Option<RateLimitConfig> {
+        Some(RateLimitConfig {
+            name: format!("virtual_key:{}", self.name),
+            key: Some(self.name.clone()),
+            provider: None,
+            requests_per_minute: self.requests_per_minute?,
+            burst: self.burst,
+        })
+    }
+
+    /// Budget of the key's tenant
+    pub fn budget(&self) -> Option<BudgetConfig> {
+        let budget = self.budget.as_ref()?;
+        Some(BudgetConfig {
+            tenant: self.name.clone(),
+            limit_usd: budget.limit_usd,
+            window_secs: budget.window_secs,
+        })
+    }
+}
+
+/// A virtual key the gateway accepts
+#[derive(Debug)]
+pub struct VirtualKey {
+    pub name: String,
+    /// Models that may be requested, exact or a prefix ending in `*`; any when empty
+    pub models: Vec<String>,
+    /// Sent upstream in place of the upstream's own credentials
+    pub credential: Option<Arc<Credential>>,
+}
+
+impl VirtualKey {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            models: Vec::new(),
+            credential: None,
+        }
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.models.push(model.into());
+        self
+    }
+
+    /// Send `header: value` upstream for requests made with the key
+    pub fn with_upstream_key(
+        mut self,
+        header: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        let id = format!("virtual_key:{}", self.name);
+        self.credential = Some(Arc::new(Credential::new(id, header, value)));
+        self
+    }
+
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.models.is_empty()
+            || self
+                .models
+                .iter()
+                .any(|allowed| match allowed.strip_suffix('*') {
+                    Some(prefix) => model.starts_with(prefix),
+                    None => allowed == model,
+                })
+    }
+
+    /// Check a request's model against the key's models
+    pub fn authorize(&self, model: Option<&str>) -> Result<(), KeyRejection> {
+        match model {
+            _ if self.models.is_empty() => Ok(()),
+            Some(model) if self.allows_model(model) => Ok(()),
+            model => Err(KeyRejection::ModelNotAllowed(
+                model.unwrap_or_default().to_string(),
+            )),
+        }
+    }
+}
+
+/// What the admin API lists of a virtual key; neither the key nor its upstream
+/// credential
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VirtualKeySummary {
+    pub name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+    /// Header the upstream credential is sent in, if the key has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_header: Option<String>,
+}
+
+/// A newly issued key: handed to the client once, while only its hash goes into the
+/// config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IssuedKey {
+    pub key: String,
+    pub key_hash: String,
+}
+
+/// Gateway-issued API keys standing in for provider credentials.
+///
+/// A virtual key is `lsk_` followed by 32 random bytes, base64url; the gateway only
+/// keeps its BLAKE2b-256 hash. Clients send it as `Authorization: Bearer lsk_...` (what
+/// SDKs send as the API key) or in `X-Langspec-Key`. The gateway strips it and makes
+/// the request as the key's tenant (`X-Langspec-Tenant` is its name), so rate limits,
+/// budgets, pricing, key stats and billing attribute the request to the key; requests
+/// for models the key does not allow are rejected, and the key's upstream credential,
+/// if any, is sent in place of the upstream's own.
+#[derive(Debug, Default)]
+pub struct VirtualKeys {
+    /// By key hash
+    keys: HashMap<[u8; 32], Arc<VirtualKey>>,
+}
+
+impl VirtualKeys {
+    pub const HEADER: &'static str = "X-Langspec-Key";
+    pub const PREFIX: &'static str = "lsk_";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept `key` for `virtual_key`
+    pub fn with_key(mut self, key: &str, virtual_key: VirtualKey) -> Self {
+        self.keys
+            .insert(Blake2b256::digest(key).into(), Arc::new(virtual_key));
+        self
+    }
+
+    /// Keys of the config file, with upstream credentials read from the environment
+    pub fn compile(configs: &[VirtualKeyConfig]) -> Result<Self, VirtualKeyError> {
+        Self::compile_with_env(configs, |name| std::env::var(name).ok())
+    }
+
+    /// Keys of the config file, with upstream credentials looked up by `env`
+    pub fn compile_with_env(
+        configs: &[VirtualKeyConfig],
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, VirtualKeyError> {
+        let mut keys = HashMap::new();
+        let mut names = HashSet::new();
+        for config in configs {
+            if config.name.is_empty() || config.name.ends_with('*') {
+                return Err(VirtualKeyError::InvalidName(config.name.clone()));
+            }
+            if !names.insert(config.name.as_str()) {
+                return Err(VirtualKeyError::DuplicateName(config.name.clone()));
+            }
+            let hash = parse_hash(&config.key_hash)
+                .ok_or_else(|| VirtualKeyError::InvalidHash(config.name.clone()))?;
+            let mut key = VirtualKey::new(&config.name);
+            key.models = config.models.clone();
+            if let Some(upstream_key) = &config.upstream_key {
+                let value = env(&upstream_key.env).ok_or_else(|| {
+                    VirtualKeyError::MissingUpstreamKey(
+                        config.name.clone(),
+                        upstream_key.env.clone(),
+                    )
+                })?;
+                key = match &upstream_key.header {
+                    Some(header) => key.with_upstream_key(header, value),
+                    None => key.with_upstream_key("authorization", format!("Bearer {}", value)),
+                };
+            }
+            if keys.insert(hash, Arc::new(key)).is_some() {
+                return Err(VirtualKeyError::DuplicateHash(config.name.clone()));
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The virtual key a client presented
+    pub fn authenticate(&self, key: &str) -> Result<Arc<VirtualKey>, KeyRejection> {
+        let hash: [u8; 32] = Blake2b256::digest(key.trim()).into();
+        self.keys
+            .get(&hash)
+            .cloned()
+            .ok_or(KeyRejection::UnknownKey)
+    }
+
+    /// Every key, by name
+    pub fn summaries(&self) -> Vec<VirtualKeySummary> {
+        let mut summaries: Vec<VirtualKeySummary> = self
+            .keys
+            .values()
+            .map(|key| VirtualKeySummary {
+                name: key.name.clone(),
+                models: key.models.clone(),
+                upstream_header: key
+                    .credential
+                    .as_ref()
+                    .map(|credential| credential.header().0.to_string()),
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.name.cmp(&b.name));
+        summaries
+    }
+
+    /// A new random key and the hash to configure it with
+    pub fn issue() -> IssuedKey {
+        let key = format!(
+            "{}{}",
+            Self::PREFIX,
+            BASE64URL.encode(rand::random::<[u8; 32]>())
+        );
+        let key_hash = hash_key(&key);
+        IssuedKey { key, key_hash }
+    }
+}
+
+/// Hex BLAKE2b-256 of a key, as configured in `key_hash`
+pub fn hash_key(key: &str) -> String {
+    Blake2b256::digest(key.trim())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn parse_hash(hex: &str) -> Option<[u8; 32]> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0; 32];
+    for (byte, digits) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// Virtual key a request presents, in `X-Langspec-Key` or as a bearer token
+pub fn request_key(request: &RequestHeader) -> Option<&str> {
+    let header = |name: &str| request.headers.get(name)?.to_str().ok();
+    header(VirtualKeys::HEADER).or_else(|| {
+        header("authorization")?
+            .strip_prefix("Bearer ")
+            .map(str::trim)
+            .filter(|key| key.starts_with(VirtualKeys::PREFIX))
+    })
+}
@@ -0,0 +1,212 @@
+//! WebSocket sessions proxied for realtime APIs (e.g. OpenAI Realtime at
+//! `/v1/realtime`).
+//!
+//! Pingora relays the frames of an upgraded connection both ways once the upstream
+//! answers 101; the gateway runs the header stages (detection, auth, rate limits) on the
+//! upgrade request and leaves the body stages out. [`WebSocketSession`] reads the frames
+//! as they pass without altering them: it counts the messages sent each way and sums the
+//! usage the provider reports in its text messages (Realtime `response.done` events),
+//! logged with the socket's duration when it closes. The gateway removes
+//! `Sec-WebSocket-Extensions` from the upgrade request so frames are never compressed.
+
+use crate::pipeline::usage::{Usage, reported_usage};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+/// Text messages larger than this are relayed without being read for usage
+pub const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+
+/// A frame whose payload is being read
+#[derive(Debug)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    len: u64,
+    read: u64,
+}
+
+/// Frame header at the start of `data` and its length; `None` until all of it arrived
+fn parse_header(data: &[u8]) -> Option<(usize, Frame)> {
+    let [first, second, ..] = *data else {
+        return None;
+    };
+    let (len, mut at) = match second & 0x7f {
+        126 => (
+            u16::from_be_bytes(data.get(2..4)?.try_into().ok()?) as u64,
+            4,
+        ),
+        127 => (u64::from_be_bytes(data.get(2..10)?.try_into().ok()?), 10),
+        len => (len as u64, 2),
+    };
+    let mask = match second & 0x80 != 0 {
+        true => {
+            let key = data.get(at..at + 4)?.try_into().ok()?;
+            at += 4;
+            Some(key)
+        }
+        false => None,
+    };
+    let frame = Frame {
+        fin: first & 0x80 != 0,
+        opcode: first & 0x0f,
+        mask,
+        len,
+        read: 0,
+    };
+    Some((at, frame))
+}
+
+/// Frames of one direction of a socket, read as they pass
+#[derive(Debug, Default)]
+struct FrameReader {
+    /// Bytes of an incomplete frame header carried over to the next chunk
+    pending: Vec<u8>,
+    /// Frame whose payload continues in the next chunk
+    frame: Option<Frame>,
+    /// Unmasked payload of the text message being received; `None` while a binary or
+    /// oversized message is
+    message: Option<Vec<u8>>,
+    messages: u64,
+    closed: bool,
+}
+
+impl FrameReader {
+    /// Feed a chunk of the stream; returns the text messages it completed
+    fn feed(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        if self.closed {
+            return messages;
+        }
+        self.pending.extend_from_slice(chunk);
+        let mut pos = 0;
+        while !self.closed {
+            let mut frame = match self.frame.take() {
+                Some(frame) => frame,
+                None => {
+                    let Some((header, frame)) = parse_header(&self.pending[pos..]) else {
+                        break;
+                    };
+                    pos += header;
+                    match frame.opcode {
+                        OPCODE_TEXT => self.message = Some(Vec::new()),
+                        OPCODE_BINARY => self.message = None,
+                        _ => {}
+                    }
+                    frame
+                }
+            };
+            let available = (self.pending.len() - pos) as u64;
+            let take = (frame.len - frame.read).min(available) as usize;
+            // Control frames may come between the fragments of a message
+            if frame.opcode < OPCODE_CLOSE
+                && let Some(message) = self.message.as_mut()
+            {
+                match message.len() + take > MAX_MESSAGE_BYTES {
+                    true => self.message = None,
+                    false => {
+                        let payload = &self.pending[pos..pos + take];
+                        message.extend(payload.iter().enumerate().map(
+                            |(i, byte)| match frame.mask {
+                                Some(key) => byte ^ key[(frame.read as usize + i) % 4],
+                                None => *byte,
+                            },
+                        ));
+                    }
+                }
+            }
+            pos += take;
+            frame.read += take as u64;
+            if frame.read < frame.len {
+                self.frame = Some(frame);
+                break;
+            }
+            match frame.opcode {
+                OPCODE_CLOSE => self.closed = true,
+                OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY if frame.fin => {
+                    self.messages += 1;
+                    messages.extend(self.message.take());
+                }
+                _ => {}
+            }
+        }
+        self.pending.drain(..pos);
+        messages
+    }
+}
+
+/// Messages and usage of a proxied WebSocket
+#[derive(Debug, Default)]
+pub struct WebSocketSession {
+    client: FrameReader,
+    upstream: FrameReader,
+    /// When the upstream accepted the upgrade
+    opened: Option<Instant>,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    reported: bool,
+}
+
+impl WebSocketSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The upstream answered the upgrade with 101: frames flow from now on
+    pub fn open(&mut self) {
+        self.opened = Some(Instant::now());
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.opened.is_some()
+    }
+
+    /// How long the socket has been open
+    pub fn duration(&self) -> Option<Duration> {
+        self.opened.map(|opened| opened.elapsed())
+    }
+
+    /// Feed bytes the client sent
+    pub fn client_data(&mut self, chunk: &[u8]) {
+        self.client.feed(chunk);
+    }
+
+    /// Feed bytes the upstream sent, adding the usage its messages report
+    pub fn upstream_data(&mut self, chunk: &[u8]) {
+        for message in self.upstream.feed(chunk) {
+            let Ok(event) = serde_json::from_slice::<Value>(&message) else {
+                continue;
+            };
+            let (prompt, completion) = reported_usage(&event);
+            if prompt.is_some() || completion.is_some() {
+                self.prompt_tokens += prompt.unwrap_or(0);
+                self.completion_tokens += completion.unwrap_or(0);
+                self.reported = true;
+            }
+        }
+    }
+
+    /// Complete messages the client sent
+    pub fn client_messages(&self) -> u64 {
+        self.client.messages
+    }
+
+    /// Complete messages the upstream sent
+    pub fn upstream_messages(&self) -> u64 {
+        self.upstream.messages
+    }
+
+    /// Usage the provider reported over the socket's lifetime, if it reported any
+    pub fn usage(&self) -> Option<Usage> {
+        self.reported.then_some(Usage {
+            prompt_tokens: self.prompt_tokens,
+            completion_tokens: self.completion_tokens,
+            estimated: false,
+        })
+    }
+}
@@ -0,0 +1,182 @@
+//! Quota windows that stay correct when the wall clock jumps.
+//!
+//! Windows are aligned to wall-clock boundaries (a "daily" quota resets at midnight
+//! UTC), but time is read from a [`WindowClock`]: a wall-clock anchor advanced by the
+//! monotonic clock. An NTP step or a container clock jump therefore cannot start an
+//! extra window (doubling a tenant's quota) or push usage into a past window (wiping
+//! it out); the jump is logged and counted in `langspec_clock_skew_events_total`.
+
+use crate::metrics::{CLOCK_SKEW_EVENTS, CLOCK_SKEW_SECONDS};
+use log::{info, warn};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Wall-clock time anchored to the monotonic clock.
+///
+/// Small disagreements (clock slewing, monotonic drift) are absorbed by re-anchoring
+/// to the wall clock, never moving time backwards. A disagreement beyond the tolerance
+/// is treated as a clock jump and ignored: time keeps following the monotonic clock
+/// until the wall clock comes back within tolerance. A permanent correction (the clock
+/// was wrong at startup) therefore stays visible on `langspec_clock_skew_seconds`
+/// until the gateway restarts.
+#[derive(Debug)]
+pub struct WindowClock {
+    tolerance: Duration,
+    state: Mutex<ClockState>,
+}
+
+#[derive(Debug)]
+struct ClockState {
+    /// Wall-clock time (since the Unix epoch) at `anchor_mono`
+    anchor_wall: Duration,
+    anchor_mono: Instant,
+    /// Last time handed out, so time never goes backwards
+    last: Duration,
+    /// Whether the wall clock currently disagrees beyond tolerance
+    skewed: bool,
+}
+
+impl WindowClock {
+    pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(2);
+
+    pub fn new() -> Self {
+        Self::with_tolerance(Self::DEFAULT_TOLERANCE)
+    }
+
+    pub fn with_tolerance(tolerance: Duration) -> Self {
+        let wall = wall_clock();
+        Self {
+            tolerance,
+            state: Mutex::new(ClockState {
+                anchor_wall: wall,
+                anchor_mono: Instant::now(),
+                last: wall,
+                skewed: false,
+            }),
+        }
+    }
+
+    /// Current trusted time since the Unix epoch
+    pub fn now(&self) -> Duration {
+        self.observe(wall_clock(), Instant::now())
+    }
+
+    /// Trusted time given a wall-clock and a monotonic reading
+    pub fn observe(&self, wall: Duration, mono: Instant) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let trusted = state.anchor_wall + mono.saturating_duration_since(state.anchor_mono);
+        let skew = wall.as_secs_f64() - trusted.as_secs_f64();
+        CLOCK_SKEW_SECONDS.set(skew);
+
+        if skew.abs() <= self.tolerance.as_secs_f64() {
+            if state.skewed {
+                info!(
+                    "Wall clock back within {:?} of the quota clock",
+                    self.tolerance
+                );
+                state.skewed = false;
+            }
+            state.anchor_wall = wall;
+            state.anchor_mono = mono;
+        } else if !state.skewed {
+            // Report each jump once, not on every read while it persists
+            let direction = if skew > 0.0 { "forward" } else { "backward" };
+            warn!(
+                "Wall clock jumped {} by {:.3}s; quota windows keep following the monotonic clock",
+                direction,
+                skew.abs()
+            );
+            CLOCK_SKEW_EVENTS.with_label_values(&[direction]).inc();
+            state.skewed = true;
+        }
+
+        let now = if state.skewed { trusted } else { wall };
+        state.last = state.last.max(now);
+        state.last
+    }
+
+    /// Whether the wall clock currently disagrees with trusted time beyond tolerance
+    pub fn is_skewed(&self) -> bool {
+        self.state.lock().unwrap().skewed
+    }
+}
+
+impl Default for WindowClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn wall_clock() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Usage counter for a fixed window aligned to the Unix epoch (e.g. per minute, or
+/// per UTC day). Every method takes the current time from [`WindowClock::now`].
+/// Usage resets when time enters a later window, and never when time appears to go
+/// back to an earlier one.
+#[derive(Debug)]
+pub struct QuotaWindow {
+    length: Duration,
+    state: Mutex<WindowState>,
+}
+
+#[derive(Debug, Default)]
+struct WindowState {
+    /// Index of the current window since the epoch
+    index: u64,
+    used: u64,
+}
+
+impl QuotaWindow {
+    pub fn new(length: Duration) -> Self {
+        assert!(!length.is_zero(), "Quota window length must be positive");
+        Self {
+            length,
+            state: Mutex::new(WindowState::default()),
+        }
+    }
+
+    pub fn length(&self) -> Duration {
+        self.length
+    }
+
+    /// Add `amount` to the window if that keeps usage within `limit`
+    pub fn try_consume(&self, now: Duration, amount: u64, limit: u64) -> bool {
+        let mut state = self.current(now);
+        if state.used.saturating_add(amount) > limit {
+            return false;
+        }
+        state.used += amount;
+        true
+    }
+
+    /// Add `amount` unconditionally, e.g. usage reported after the fact
+    pub fn record(&self, now: Duration, amount: u64) {
+        let mut state = self.current(now);
+        state.used = state.used.saturating_add(amount);
+    }
+
+    /// Usage in the current window
+    pub fn used(&self, now: Duration) -> u64 {
+        self.current(now).used
+    }
+
+    /// Start of the current window (since the Unix epoch)
+    pub fn window_start(&self, now: Duration) -> Duration {
+        let index = self.current(now).index;
+        Duration::from_nanos((self.length.as_nanos() * index as u128) as u64)
+    }
+
+    fn current(&self, now: Duration) -> MutexGuard<'_, WindowState> {
+        let index = (now.as_nanos() / self.length.as_nanos()) as u64;
+        let mut state = self.state.lock().unwrap();
+        if index > state.index {
+            state.index = index;
+            state.used = 0;
+        }
+        state
+    }
+}
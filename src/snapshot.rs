@@ -0,0 +1,184 @@
+use crate::upstream::{LimiterState, Upstream};
+use async_trait::async_trait;
+use chrono::{SecondsFormat, Utc};
+use log::{info, warn};
+use pingora::server::ShutdownWatch;
+use pingora::services::background::BackgroundService;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fs, io};
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(PathBuf, io::Error),
+    Json(PathBuf, serde_json::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(path, e) => write!(f, "cannot access '{}': {}", path.display(), e),
+            SnapshotError::Json(path, e) => {
+                write!(f, "invalid snapshot '{}': {}", path.display(), e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// Runtime counters that should survive a restart, keyed by upstream address.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GatewaySnapshot {
+    /// RFC 3339 time the snapshot was taken
+    pub taken_at: String,
+    /// Adaptive concurrency limiter state per upstream
+    #[serde(default)]
+    pub limiters: BTreeMap<String, LimiterState>,
+}
+
+impl GatewaySnapshot {
+    pub fn capture(upstreams: &[Arc<Upstream>]) -> Self {
+        Self {
+            taken_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            limiters: upstreams
+                .iter()
+                .filter_map(|upstream| {
+                    let limiter = upstream.limiter()?;
+                    Some((upstream.address().to_string(), limiter.state()))
+                })
+                .collect(),
+        }
+    }
+
+    /// Apply the snapshot to the matching upstreams. Entries for upstreams that are no
+    /// longer configured are ignored. Returns the number of restored entries.
+    pub fn restore(&self, upstreams: &[Arc<Upstream>]) -> usize {
+        let mut restored = 0;
+        for upstream in upstreams {
+            if let Some(limiter) = upstream.limiter()
+                && let Some(state) = self.limiters.get(upstream.address())
+            {
+                limiter.restore(state);
+                restored += 1;
+            }
+        }
+        restored
+    }
+}
+
+/// JSON file holding the latest snapshot. Writes go to a temporary file that is
+/// renamed over the previous snapshot, so a crash mid-write never corrupts it.
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    path: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Latest snapshot, or None if none was saved yet
+    pub fn load(&self) -> Result<Option<GatewaySnapshot>, SnapshotError> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(SnapshotError::Io(self.path.clone(), e)),
+        };
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|e| SnapshotError::Json(self.path.clone(), e))
+    }
+
+    pub fn save(&self, snapshot: &GatewaySnapshot) -> Result<(), SnapshotError> {
+        let contents = serde_json::to_vec_pretty(snapshot)
+            .map_err(|e| SnapshotError::Json(self.path.clone(), e))?;
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        fs::write(&tmp, contents).map_err(|e| SnapshotError::Io(tmp.clone(), e))?;
+        fs::rename(&tmp, &self.path).map_err(|e| SnapshotError::Io(self.path.clone(), e))
+    }
+}
+
+/// Where and how often runtime state is persisted.
+#[derive(Debug, Clone)]
+pub struct SnapshotConfig {
+    pub path: PathBuf,
+    /// How often a snapshot is written while running
+    pub interval: Duration,
+}
+
+impl SnapshotConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Background service restoring state when it starts, then saving a snapshot
+/// periodically and once more on graceful shutdown.
+pub struct SnapshotService {
+    upstreams: Vec<Arc<Upstream>>,
+    store: SnapshotStore,
+    interval: Duration,
+}
+
+impl SnapshotService {
+    pub fn new(upstreams: Vec<Arc<Upstream>>, config: SnapshotConfig) -> Self {
+        Self {
+            upstreams,
+            store: SnapshotStore::new(config.path),
+            interval: config.interval,
+        }
+    }
+
+    /// Restore the latest snapshot, if any. A missing or unreadable snapshot starts
+    /// from fresh state.
+    pub fn restore(&self) {
+        match self.store.load() {
+            Ok(Some(snapshot)) => {
+                let restored = snapshot.restore(&self.upstreams);
+                info!(
+                    "Restored {} limiter states from snapshot taken at {}",
+                    restored, snapshot.taken_at
+                );
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Not restoring state: {}", e),
+        }
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = self.store.save(&GatewaySnapshot::capture(&self.upstreams)) {
+            warn!("Failed to save state snapshot: {}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for SnapshotService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        self.restore();
+        let start = tokio::time::Instant::now() + self.interval;
+        let mut ticker = tokio::time::interval_at(start, self.interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => break,
+                _ = ticker.tick() => self.save(),
+            }
+        }
+        self.save();
+    }
+}
@@ -0,0 +1,314 @@
+//! Stub provider for load testing.
+//!
+//! [`StubProvider`] is an OpenAI-compatible upstream served by the gateway binary
+//! itself (`LANGSPEC_STUB_ADDR`): pointing the gateway's upstreams at it exercises the
+//! whole pipeline (streaming, usage accounting, budgets and limits) at scale without
+//! spending on a real provider.
+//!
+//! Completions are pseudo-random words, one token each, derived from the configured
+//! seed, the request's `seed` and its body: the same request always gets the same
+//! completion, so runs are reproducible. Their length is `completion_tokens`, capped by
+//! the request's `max_tokens`, and they are generated at `tokens_per_sec`, streamed
+//! chunk by chunk when the request asks for `stream`.
+//!
+//! Routes:
+//! - `POST /v1/chat/completions`
+//! - `GET /v1/models`
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::{debug, error};
+use pingora::apps::{HttpPersistentSettings, HttpServerApp, ReusedHttpStream};
+use pingora::http::ResponseHeader;
+use pingora::protocols::http::ServerSession;
+use pingora::server::ShutdownWatch;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::pipeline::mock::GeneratedCompletion;
+use crate::pipeline::tokenizer::{ApproximateTokenizer, Tokenizer};
+use crate::pipeline::usage::{Usage, prompt_text};
+
+/// Largest request body the stub reads
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Words completions are made of
+const WORDS: &[&str] = &[
+    "the", "gateway", "routes", "each", "request", "to", "a", "model", "that", "answers", "with",
+    "tokens", "streamed", "back", "over", "time", "while", "limits", "and", "budgets", "keep",
+    "every", "tenant", "within", "its", "share", "of", "capacity", "load", "test", "stub",
+    "provider",
+];
+
+/// How the stub generates completions
+#[derive(Debug, Clone, PartialEq)]
+pub struct StubConfig {
+    /// Mixed into every completion's seed, so runs with different seeds differ
+    pub seed: u64,
+    /// Completion length in tokens, unless the request asks for fewer
+    pub completion_tokens: u64,
+    /// Generation rate; 0 answers at once
+    pub tokens_per_sec: u64,
+    /// Model reported when the request names none
+    pub model: String,
+}
+
+impl Default for StubConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            completion_tokens: 256,
+            tokens_per_sec: 50,
+            model: "stub".to_string(),
+        }
+    }
+}
+
+impl StubConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Completion length in tokens (256 by default)
+    pub fn with_completion_tokens(mut self, completion_tokens: u64) -> Self {
+        self.completion_tokens = completion_tokens;
+        self
+    }
+
+    /// Tokens generated per second (50 by default); 0 answers without delay
+    pub fn with_tokens_per_sec(mut self, tokens_per_sec: u64) -> Self {
+        self.tokens_per_sec = tokens_per_sec;
+        self
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    /// Time it takes to generate `tokens`
+    pub fn generation_time(&self, tokens: u64) -> Duration {
+        match self.tokens_per_sec {
+            0 => Duration::ZERO,
+            rate => Duration::from_secs_f64(tokens as f64 / rate as f64),
+        }
+    }
+}
+
+/// A completion generated by the stub
+#[derive(Debug, Clone)]
+pub struct StubCompletion {
+    pub completion: GeneratedCompletion,
+    /// Content, one piece per token
+    pub tokens: Vec<String>,
+    pub stream: bool,
+}
+
+impl StubCompletion {
+    pub fn content(&self) -> String {
+        self.tokens.concat()
+    }
+}
+
+/// OpenAI-compatible upstream answering with deterministic completions
+#[derive(Debug, Clone, Default)]
+pub struct StubProvider {
+    config: StubConfig,
+}
+
+impl StubProvider {
+    pub fn new(config: StubConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &StubConfig {
+        &self.config
+    }
+
+    /// Completion of a chat completion request with `body`
+    pub fn complete(&self, body: &[u8]) -> StubCompletion {
+        let request: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+        let max_tokens = ["max_completion_tokens", "max_tokens"]
+            .iter()
+            .find_map(|field| request.get(field).and_then(Value::as_u64));
+        let length = max_tokens.map_or(self.config.completion_tokens, |max| {
+            max.min(self.config.completion_tokens)
+        });
+
+        // FNV-1a of the body, so different prompts get different completions
+        let body_hash = body.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+        let mut state =
+            self.config.seed ^ request.get("seed").and_then(Value::as_u64).unwrap_or(0) ^ body_hash;
+        let tokens: Vec<String> = (0..length)
+            .map(|i| {
+                let word = WORDS[(splitmix64(&mut state) % WORDS.len() as u64) as usize];
+                match i {
+                    0 => word.to_string(),
+                    _ => format!(" {}", word),
+                }
+            })
+            .collect();
+
+        let usage = Usage {
+            prompt_tokens: ApproximateTokenizer::new().count_tokens(&prompt_text(body)),
+            completion_tokens: length,
+            estimated: false,
+        };
+        let model = request
+            .get("model")
+            .and_then(Value::as_str)
+            .unwrap_or(&self.config.model);
+        StubCompletion {
+            completion: GeneratedCompletion::new(
+                format!("chatcmpl-stub-{:016x}", body_hash),
+                model,
+                usage,
+            ),
+            tokens,
+            stream: request
+                .get("stream")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        }
+    }
+
+    async fn respond(&self, session: &mut ServerSession, body: &[u8]) -> pingora::Result<()> {
+        let request = session.req_header();
+        match (request.method.as_str(), request.uri.path()) {
+            ("POST", "/v1/chat/completions") => {}
+            ("GET", "/v1/models") => {
+                let models = serde_json::json!({
+                    "object": "list",
+                    "data": [{"id": self.config.model, "object": "model", "owned_by": "stub"}]
+                });
+                return write_json(session, 200, &models).await;
+            }
+            _ => {
+                let error = serde_json::json!({
+                    "error": {
+                        "message": "Unknown route",
+                        "type": "invalid_request_error",
+                        "param": null,
+                        "code": "not_found"
+                    }
+                });
+                return write_json(session, 404, &error).await;
+            }
+        }
+
+        let generated = self.complete(body);
+        if !generated.stream {
+            tokio::time::sleep(self.config.generation_time(generated.tokens.len() as u64)).await;
+            let completion = generated.completion.json(&generated.content());
+            return write_json(session, 200, &completion).await;
+        }
+
+        let mut header = ResponseHeader::build(200, Some(2))?;
+        header.insert_header(http::header::CONTENT_TYPE, "text/event-stream")?;
+        header.insert_header(http::header::CACHE_CONTROL, "no-cache")?;
+        session.write_response_header(Box::new(header)).await?;
+        let pieces: Vec<&str> = generated.tokens.iter().map(String::as_str).collect();
+        let events = generated.completion.events(&pieces);
+        let per_token = self.config.generation_time(1);
+        let last = events.len() - 1;
+        for (i, event) in events.into_iter().enumerate() {
+            // The role event goes out at once, each content event after its token
+            if (1..=pieces.len()).contains(&i) {
+                tokio::time::sleep(per_token).await;
+            }
+            session
+                .write_response_body(Bytes::from(event), i == last)
+                .await?;
+        }
+        session.finish_body().await
+    }
+}
+
+#[async_trait]
+impl HttpServerApp for StubProvider {
+    async fn process_new_http(
+        self: &Arc<Self>,
+        mut session: ServerSession,
+        shutdown: &ShutdownWatch,
+    ) -> Option<ReusedHttpStream> {
+        match session.read_request().await {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => {
+                debug!("Stub provider failed to read a request: {}", e);
+                return None;
+            }
+        }
+        session.set_keepalive(match *shutdown.borrow() {
+            true => None,
+            false => Some(60),
+        });
+
+        let mut body = Vec::new();
+        loop {
+            match session.read_request_body().await {
+                Ok(Some(chunk)) if body.len() + chunk.len() <= MAX_BODY_BYTES => {
+                    body.extend_from_slice(&chunk)
+                }
+                Ok(Some(_)) => {
+                    let error = serde_json::json!({
+                        "error": {
+                            "message": "Request body too large",
+                            "type": "invalid_request_error",
+                            "param": null,
+                            "code": "body_too_large"
+                        }
+                    });
+                    write_json(&mut session, 413, &error).await.ok();
+                    return None;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    debug!("Stub provider failed to read a request body: {}", e);
+                    return None;
+                }
+            }
+        }
+
+        if let Err(e) = self.respond(&mut session, &body).await {
+            error!("Stub provider failed to answer: {}", e);
+            return None;
+        }
+        let persistent_settings = HttpPersistentSettings::for_session(&session);
+        match session.finish().await {
+            Ok(stream) => {
+                stream.map(|stream| ReusedHttpStream::new(stream, Some(persistent_settings)))
+            }
+            Err(e) => {
+                debug!("Stub provider failed to finish a request: {}", e);
+                None
+            }
+        }
+    }
+}
+
+async fn write_json(session: &mut ServerSession, status: u16, body: &Value) -> pingora::Result<()> {
+    let body = Bytes::from(serde_json::to_vec(body).expect("JSON values serialize"));
+    let mut header = ResponseHeader::build(status, Some(2))?;
+    header.insert_header(http::header::CONTENT_TYPE, "application/json")?;
+    header.insert_header(http::header::CONTENT_LENGTH, body.len())?;
+    session.write_response_header(Box::new(header)).await?;
+    session.write_response_body(body, true).await
+}
+
+/// Next number of the SplitMix64 sequence at `state`
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,410 @@
+//! OpenAI Chat Completions ↔ Anthropic Messages translation.
+//!
+//! Requests:
+//!
+//! | OpenAI                                   | Messages                                   |
+//! |------------------------------------------|--------------------------------------------|
+//! | `/v1/chat/completions`                   | `/v1/messages`                             |
+//! | `Authorization: Bearer <key>`            | `x-api-key: <key>`                         |
+//! | `system` / `developer` messages          | `system` text blocks                       |
+//! | `user` / `assistant` messages            | `messages`, consecutive roles merged       |
+//! | `image_url` parts                        | `image` blocks (base64 or URL source)      |
+//! | assistant `tool_calls`                   | `tool_use` blocks                          |
+//! | `tool` messages                          | `tool_result` blocks in a user message     |
+//! | `max_tokens` / `max_completion_tokens`   | `max_tokens` (required, defaulted)         |
+//! | `temperature`, `top_p`, `stream`         | unchanged                                  |
+//! | `stop`                                   | `stop_sequences`                           |
+//! | `tools`, `tool_choice`                   | `tools`, `tool_choice`                     |
+//! | `user`                                   | `metadata.user_id`                         |
+//!
+//! Responses (`message` objects and errors) are translated back into
+//! `chat.completion` objects and OpenAI error bodies.
+
+use super::{TranslatedRequest, TranslationError};
+use serde_json::{Map, Value, json};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `max_tokens` sent when the client leaves it out; Anthropic requires one
+pub const DEFAULT_MAX_TOKENS: u64 = 4096;
+
+/// API version sent unless the client chose one
+pub const API_VERSION: &str = "2023-06-01";
+
+/// OpenAI client headers with no meaning to Anthropic
+const OPENAI_HEADERS: &[&str] = &["openai-organization", "openai-project", "openai-beta"];
+
+pub fn chat_to_messages(request: &Value) -> Result<TranslatedRequest, TranslationError> {
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .filter(|model| !model.is_empty())
+        .ok_or(TranslationError::InvalidField("model"))?;
+    if request
+        .get("n")
+        .and_then(Value::as_u64)
+        .is_some_and(|n| n > 1)
+    {
+        return Err(TranslationError::Unsupported(
+            "Messages returns a single choice (n > 1)".to_string(),
+        ));
+    }
+
+    let mut messages_request = Map::new();
+    messages_request.insert("model".to_string(), model.into());
+    let max_tokens = match request
+        .get("max_completion_tokens")
+        .or_else(|| request.get("max_tokens"))
+        .filter(|value| !value.is_null())
+    {
+        Some(max_tokens) => max_tokens
+            .as_u64()
+            .ok_or(TranslationError::InvalidField("max_tokens"))?,
+        None => DEFAULT_MAX_TOKENS,
+    };
+    messages_request.insert("max_tokens".to_string(), max_tokens.into());
+
+    let (system, messages) = messages(request)?;
+    if !system.is_empty() {
+        messages_request.insert("system".to_string(), Value::Array(system));
+    }
+    messages_request.insert("messages".to_string(), Value::Array(messages));
+
+    for field in ["temperature", "top_p"] {
+        if let Some(value) = request.get(field).filter(|value| !value.is_null()) {
+            let value = value
+                .as_f64()
+                .ok_or(TranslationError::InvalidField(field))?;
+            messages_request.insert(field.to_string(), value.into());
+        }
+    }
+    let stop = match request.get("stop") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::String(stop)) => vec![stop.clone()],
+        Some(Value::Array(stops)) => stops
+            .iter()
+            .map(|stop| stop.as_str().map(str::to_string))
+            .collect::<Option<_>>()
+            .ok_or(TranslationError::InvalidField("stop"))?,
+        Some(_) => return Err(TranslationError::InvalidField("stop")),
+    };
+    if !stop.is_empty() {
+        messages_request.insert("stop_sequences".to_string(), stop.into());
+    }
+    if let Some(stream) = request.get("stream").and_then(Value::as_bool) {
+        messages_request.insert("stream".to_string(), stream.into());
+    }
+    if let Some(user) = request.get("user").and_then(Value::as_str) {
+        messages_request.insert("metadata".to_string(), json!({"user_id": user}));
+    }
+    if let Some((tools, choice)) = tools(request)? {
+        messages_request.insert("tools".to_string(), Value::Array(tools));
+        if let Some(choice) = choice {
+            messages_request.insert("tool_choice".to_string(), choice);
+        }
+    }
+
+    Ok(TranslatedRequest {
+        path: "/v1/messages".to_string(),
+        body: serde_json::to_vec(&Value::Object(messages_request)).expect("JSON values serialize"),
+        headers: vec![("content-type", "application/json".to_string())],
+        default_headers: vec![("anthropic-version", API_VERSION.to_string())],
+        removed_headers: OPENAI_HEADERS,
+        api_key_header: Some("x-api-key"),
+        model: model.to_string(),
+    })
+}
+
+/// System blocks and conversation messages
+fn messages(request: &Value) -> Result<(Vec<Value>, Vec<Value>), TranslationError> {
+    let messages = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .filter(|messages| !messages.is_empty())
+        .ok_or(TranslationError::InvalidField("messages"))?;
+
+    let mut system = Vec::new();
+    let mut conversation: Vec<(&'static str, Vec<Value>)> = Vec::new();
+    for message in messages {
+        let role = message
+            .get("role")
+            .and_then(Value::as_str)
+            .ok_or(TranslationError::InvalidField("messages.role"))?;
+        let (role, blocks) = match role {
+            "system" | "developer" => {
+                system.extend(content_blocks(message.get("content"), false)?);
+                continue;
+            }
+            "user" => ("user", content_blocks(message.get("content"), true)?),
+            "assistant" => {
+                let mut blocks = content_blocks(message.get("content"), false)?;
+                blocks.extend(tool_uses(message)?);
+                ("assistant", blocks)
+            }
+            // Tool results go back to the model as part of a user turn
+            "tool" => ("user", vec![tool_result(message)?]),
+            other => {
+                return Err(TranslationError::Unsupported(format!(
+                    "message role '{}'",
+                    other
+                )));
+            }
+        };
+        if blocks.is_empty() {
+            continue;
+        }
+        // Messages requires alternating roles
+        match conversation.last_mut() {
+            Some((last, content)) if *last == role => content.extend(blocks),
+            _ => conversation.push((role, blocks)),
+        }
+    }
+    if conversation.is_empty() {
+        return Err(TranslationError::Unsupported(
+            "no user or assistant messages".to_string(),
+        ));
+    }
+
+    let messages = conversation
+        .into_iter()
+        .map(|(role, content)| json!({"role": role, "content": content}))
+        .collect();
+    Ok((system, messages))
+}
+
+/// Content blocks of a string or array of content parts
+fn content_blocks(content: Option<&Value>, images: bool) -> Result<Vec<Value>, TranslationError> {
+    match content {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::String(text)) if text.is_empty() => Ok(Vec::new()),
+        Some(Value::String(text)) => Ok(vec![json!({"type": "text", "text": text})]),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .map(|part| match part.get("type").and_then(Value::as_str) {
+                Some("text") => part
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .map(|text| json!({"type": "text", "text": text}))
+                    .ok_or(TranslationError::InvalidField("messages.content.text")),
+                Some("image_url") if images => image_block(part),
+                Some(other) => Err(TranslationError::Unsupported(format!(
+                    "content part type '{}'",
+                    other
+                ))),
+                None => Err(TranslationError::InvalidField("messages.content.type")),
+            })
+            .collect(),
+        Some(_) => Err(TranslationError::InvalidField("messages.content")),
+    }
+}
+
+/// `image` block of a base64 `data:` URL or a remote URL
+fn image_block(part: &Value) -> Result<Value, TranslationError> {
+    let url = part
+        .get("image_url")
+        .and_then(|image| image.get("url").or(Some(image)))
+        .and_then(Value::as_str)
+        .ok_or(TranslationError::InvalidField("messages.content.image_url"))?;
+    let source = match url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    {
+        Some((media_type, data)) => {
+            json!({"type": "base64", "media_type": media_type, "data": data})
+        }
+        None => json!({"type": "url", "url": url}),
+    };
+    Ok(json!({"type": "image", "source": source}))
+}
+
+/// `tool_use` blocks of an assistant message's `tool_calls`
+fn tool_uses(message: &Value) -> Result<Vec<Value>, TranslationError> {
+    let Some(calls) = message.get("tool_calls").and_then(Value::as_array) else {
+        return Ok(Vec::new());
+    };
+    calls
+        .iter()
+        .map(|call| {
+            let id = call.get("id").and_then(Value::as_str);
+            let function = call.get("function");
+            let name = function
+                .and_then(|function| function.get("name"))
+                .and_then(Value::as_str);
+            let (Some(id), Some(name)) = (id, name) else {
+                return Err(TranslationError::InvalidField("messages.tool_calls"));
+            };
+            // Arguments are a JSON-encoded string in OpenAI, a JSON value in Messages
+            let arguments = function
+                .and_then(|function| function.get("arguments"))
+                .and_then(Value::as_str)
+                .unwrap_or("{}");
+            let input: Value = serde_json::from_str(arguments)
+                .map_err(|_| TranslationError::InvalidField("messages.tool_calls.arguments"))?;
+            Ok(json!({"type": "tool_use", "id": id, "name": name, "input": input}))
+        })
+        .collect()
+}
+
+/// `tool_result` block of a tool message
+fn tool_result(message: &Value) -> Result<Value, TranslationError> {
+    let id = message
+        .get("tool_call_id")
+        .and_then(Value::as_str)
+        .ok_or(TranslationError::InvalidField("messages.tool_call_id"))?;
+    let content = content_blocks(message.get("content"), false)?;
+    Ok(json!({"type": "tool_result", "tool_use_id": id, "content": content}))
+}
+
+/// Tool definitions and the tool choice, if any
+type Tools = (Vec<Value>, Option<Value>);
+
+fn tools(request: &Value) -> Result<Option<Tools>, TranslationError> {
+    let Some(tools) = request
+        .get("tools")
+        .and_then(Value::as_array)
+        .filter(|tools| !tools.is_empty())
+    else {
+        return Ok(None);
+    };
+    let choice = match request.get("tool_choice") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(choice)) => match choice.as_str() {
+            "none" => Some(json!({"type": "none"})),
+            "auto" => Some(json!({"type": "auto"})),
+            "required" => Some(json!({"type": "any"})),
+            _ => return Err(TranslationError::InvalidField("tool_choice")),
+        },
+        Some(choice) => {
+            let name = choice
+                .get("function")
+                .and_then(|function| function.get("name"))
+                .and_then(Value::as_str)
+                .ok_or(TranslationError::InvalidField("tool_choice"))?;
+            Some(json!({"type": "tool", "name": name}))
+        }
+    };
+
+    let tools = tools
+        .iter()
+        .map(|tool| {
+            let function = tool
+                .get("function")
+                .filter(|_| tool.get("type").and_then(Value::as_str) == Some("function"))
+                .ok_or_else(|| {
+                    TranslationError::Unsupported("tools other than functions".to_string())
+                })?;
+            let name = function
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or(TranslationError::InvalidField("tools.function.name"))?;
+            let mut tool = Map::new();
+            tool.insert("name".to_string(), name.into());
+            if let Some(description) = function.get("description").and_then(Value::as_str) {
+                tool.insert("description".to_string(), description.into());
+            }
+            let parameters = function
+                .get("parameters")
+                .cloned()
+                .unwrap_or_else(|| json!({"type": "object", "properties": {}}));
+            tool.insert("input_schema".to_string(), parameters);
+            Ok(Value::Object(tool))
+        })
+        .collect::<Result<Vec<_>, TranslationError>>()?;
+    Ok(Some((tools, choice)))
+}
+
+/// Translate a Messages response (a `message` or an `error`) into a `chat.completion`
+/// or an OpenAI error body; `model` is reported if the message names none
+pub fn messages_to_chat(response: &Value, model: &str) -> Result<Value, TranslationError> {
+    match response.get("type").and_then(Value::as_str) {
+        Some("message") => Ok(completion(response, model)),
+        Some("error") => {
+            let error = response.get("error");
+            let field = |name| {
+                error
+                    .and_then(|error| error.get(name))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+            };
+            Ok(json!({"error": {
+                "message": field("message"),
+                "type": field("type"),
+                "param": null,
+                "code": null
+            }}))
+        }
+        _ => Err(TranslationError::InvalidField("type")),
+    }
+}
+
+fn completion(message: &Value, model: &str) -> Value {
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+    for block in message
+        .get("content")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        match block.get("type").and_then(Value::as_str) {
+            Some("text") => text.push_str(block.get("text").and_then(Value::as_str).unwrap_or("")),
+            Some("tool_use") => tool_calls.push(json!({
+                "id": block.get("id").cloned().unwrap_or(Value::Null),
+                "type": "function",
+                "function": {
+                    "name": block.get("name").cloned().unwrap_or(Value::Null),
+                    "arguments": block.get("input").unwrap_or(&json!({})).to_string()
+                }
+            })),
+            // Thinking and other blocks have no Chat Completions equivalent
+            _ => {}
+        }
+    }
+
+    let mut reply = Map::new();
+    reply.insert("role".to_string(), "assistant".into());
+    reply.insert(
+        "content".to_string(),
+        match text.is_empty() && !tool_calls.is_empty() {
+            true => Value::Null,
+            false => text.into(),
+        },
+    );
+    if !tool_calls.is_empty() {
+        reply.insert("tool_calls".to_string(), Value::Array(tool_calls));
+    }
+
+    let finish_reason = match message.get("stop_reason").and_then(Value::as_str) {
+        Some("max_tokens") => "length",
+        Some("tool_use") => "tool_calls",
+        Some("refusal") => "content_filter",
+        _ => "stop",
+    };
+    let tokens = |name| {
+        message
+            .get("usage")
+            .and_then(|usage| usage.get(name))
+            .and_then(Value::as_u64)
+            .unwrap_or(0)
+    };
+    let (prompt_tokens, completion_tokens) = (tokens("input_tokens"), tokens("output_tokens"));
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    json!({
+        "id": message.get("id").cloned().unwrap_or(Value::Null),
+        "object": "chat.completion",
+        "created": created,
+        "model": message.get("model").and_then(Value::as_str).unwrap_or(model),
+        "choices": [{
+            "index": 0,
+            "message": reply,
+            "finish_reason": finish_reason
+        }],
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens
+        }
+    })
+}
@@ -0,0 +1,590 @@
+//! OpenAI Chat Completions ↔ Bedrock `Converse` translation.
+//!
+//! | OpenAI                                   | Converse                                   |
+//! |------------------------------------------|--------------------------------------------|
+//! | `model`                                  | `/model/{modelId}/converse` path           |
+//! | `stream: true`                           | `/model/{modelId}/converse-stream` path    |
+//! | `system` / `developer` messages          | `system` blocks                            |
+//! | `user` / `assistant` messages            | `messages`, consecutive roles merged       |
+//! | `image_url` data URLs                    | `image` blocks                             |
+//! | assistant `tool_calls`                   | `toolUse` blocks                           |
+//! | `tool` messages                          | `toolResult` blocks in a user message      |
+//! | `max_tokens` / `max_completion_tokens`   | `inferenceConfig.maxTokens`                |
+//! | `temperature`, `top_p`, `stop`           | `inferenceConfig`                          |
+//! | `tools`, `tool_choice`                   | `toolConfig`                               |
+//!
+//! Fields without a Converse equivalent that only tune sampling or bookkeeping (`user`,
+//! `seed`, `logprobs`, ...) are dropped; `n` above 1 and remote image URLs are rejected.
+//! The `Authorization` header is kept: Bedrock API keys are bearer tokens.
+//!
+//! Converse responses (`output.message` objects and `{"message": ...}` errors) are
+//! translated back into `chat.completion` objects and OpenAI error bodies; Converse
+//! names no model or ID, so the requested model and a generated ID are reported.
+//! Errors get the OpenAI type of their Bedrock exception, so OpenAI SDKs handle them
+//! as they would OpenAI's (e.g. retrying a `ThrottlingException` as a rate limit).
+//!
+//! Embeddings requests become `InvokeModel` calls of a Titan (`amazon.titan-embed-*`)
+//! or Cohere (`cohere.embed-*`) embedding model. Titan embeds one input per call, so
+//! only Cohere models take a list of inputs.
+
+use super::{TranslatedRequest, TranslationError};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde_json::{Map, Value, json};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// OpenAI client headers with no meaning to Bedrock
+const OPENAI_HEADERS: &[&str] = &["openai-organization", "openai-project", "openai-beta"];
+
+/// Image formats Converse accepts
+const IMAGE_FORMATS: &[&str] = &["png", "jpeg", "gif", "webp"];
+
+pub fn chat_to_converse(request: &Value) -> Result<TranslatedRequest, TranslationError> {
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .filter(|model| !model.is_empty())
+        .ok_or(TranslationError::InvalidField("model"))?;
+    if request
+        .get("n")
+        .and_then(Value::as_u64)
+        .is_some_and(|n| n > 1)
+    {
+        return Err(TranslationError::Unsupported(
+            "Converse returns a single choice (n > 1)".to_string(),
+        ));
+    }
+    let stream = request
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    let mut converse = Map::new();
+    let (system, messages) = messages(request)?;
+    if !system.is_empty() {
+        converse.insert("system".to_string(), Value::Array(system));
+    }
+    converse.insert("messages".to_string(), Value::Array(messages));
+    if let Some(inference) = inference_config(request)? {
+        converse.insert("inferenceConfig".to_string(), inference);
+    }
+    if let Some(tools) = tool_config(request)? {
+        converse.insert("toolConfig".to_string(), tools);
+    }
+
+    let operation = if stream {
+        "converse-stream"
+    } else {
+        "converse"
+    };
+    let accept = if stream {
+        "application/vnd.amazon.eventstream"
+    } else {
+        "application/json"
+    };
+    Ok(TranslatedRequest {
+        path: format!("/model/{}/{}", encode_model_id(model), operation),
+        body: serde_json::to_vec(&Value::Object(converse)).expect("JSON values serialize"),
+        headers: vec![
+            ("content-type", "application/json".to_string()),
+            ("accept", accept.to_string()),
+        ],
+        default_headers: Vec::new(),
+        removed_headers: OPENAI_HEADERS,
+        api_key_header: None,
+        model: model.to_string(),
+    })
+}
+
+/// System blocks and conversation messages
+fn messages(request: &Value) -> Result<(Vec<Value>, Vec<Value>), TranslationError> {
+    let messages = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .filter(|messages| !messages.is_empty())
+        .ok_or(TranslationError::InvalidField("messages"))?;
+
+    let mut system = Vec::new();
+    let mut conversation: Vec<(&'static str, Vec<Value>)> = Vec::new();
+    for message in messages {
+        let role = message
+            .get("role")
+            .and_then(Value::as_str)
+            .ok_or(TranslationError::InvalidField("messages.role"))?;
+        let (role, blocks) = match role {
+            "system" | "developer" => {
+                system.extend(content_blocks(message.get("content"), false)?);
+                continue;
+            }
+            "user" => ("user", content_blocks(message.get("content"), true)?),
+            "assistant" => {
+                let mut blocks = content_blocks(message.get("content"), false)?;
+                blocks.extend(tool_uses(message)?);
+                ("assistant", blocks)
+            }
+            // Tool results go back to the model as part of a user turn
+            "tool" => ("user", vec![tool_result(message)?]),
+            other => {
+                return Err(TranslationError::Unsupported(format!(
+                    "message role '{}'",
+                    other
+                )));
+            }
+        };
+        if blocks.is_empty() {
+            continue;
+        }
+        // Converse requires alternating roles
+        match conversation.last_mut() {
+            Some((last, content)) if *last == role => content.extend(blocks),
+            _ => conversation.push((role, blocks)),
+        }
+    }
+    if conversation.is_empty() {
+        return Err(TranslationError::Unsupported(
+            "no user or assistant messages".to_string(),
+        ));
+    }
+
+    let messages = conversation
+        .into_iter()
+        .map(|(role, content)| json!({"role": role, "content": content}))
+        .collect();
+    Ok((system, messages))
+}
+
+/// Content blocks of a string or array of content parts
+fn content_blocks(content: Option<&Value>, images: bool) -> Result<Vec<Value>, TranslationError> {
+    match content {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::String(text)) if text.is_empty() => Ok(Vec::new()),
+        Some(Value::String(text)) => Ok(vec![json!({"text": text})]),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .map(|part| match part.get("type").and_then(Value::as_str) {
+                Some("text") => part
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .map(|text| json!({"text": text}))
+                    .ok_or(TranslationError::InvalidField("messages.content.text")),
+                Some("image_url") if images => image_block(part),
+                Some(other) => Err(TranslationError::Unsupported(format!(
+                    "content part type '{}'",
+                    other
+                ))),
+                None => Err(TranslationError::InvalidField("messages.content.type")),
+            })
+            .collect(),
+        Some(_) => Err(TranslationError::InvalidField("messages.content")),
+    }
+}
+
+/// `image` block of a base64 `data:` URL
+fn image_block(part: &Value) -> Result<Value, TranslationError> {
+    let url = part
+        .get("image_url")
+        .and_then(|image| image.get("url").or(Some(image)))
+        .and_then(Value::as_str)
+        .ok_or(TranslationError::InvalidField("messages.content.image_url"))?;
+    let Some((media_type, data)) = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    else {
+        return Err(TranslationError::Unsupported(
+            "image URLs other than base64 data URLs".to_string(),
+        ));
+    };
+    let format = media_type
+        .strip_prefix("image/")
+        .map(|format| if format == "jpg" { "jpeg" } else { format })
+        .filter(|format| IMAGE_FORMATS.contains(format))
+        .ok_or_else(|| TranslationError::Unsupported(format!("image type '{}'", media_type)))?;
+    // Validate here rather than have Bedrock reject the request
+    BASE64
+        .decode(data)
+        .map_err(|_| TranslationError::InvalidField("messages.content.image_url"))?;
+    Ok(json!({"image": {"format": format, "source": {"bytes": data}}}))
+}
+
+/// `toolUse` blocks of an assistant message's `tool_calls`
+fn tool_uses(message: &Value) -> Result<Vec<Value>, TranslationError> {
+    let Some(calls) = message.get("tool_calls").and_then(Value::as_array) else {
+        return Ok(Vec::new());
+    };
+    calls
+        .iter()
+        .map(|call| {
+            let id = call.get("id").and_then(Value::as_str);
+            let function = call.get("function");
+            let name = function
+                .and_then(|function| function.get("name"))
+                .and_then(Value::as_str);
+            let (Some(id), Some(name)) = (id, name) else {
+                return Err(TranslationError::InvalidField("messages.tool_calls"));
+            };
+            // Arguments are a JSON-encoded string in OpenAI, a JSON value in Converse
+            let arguments = function
+                .and_then(|function| function.get("arguments"))
+                .and_then(Value::as_str)
+                .unwrap_or("{}");
+            let input: Value = serde_json::from_str(arguments)
+                .map_err(|_| TranslationError::InvalidField("messages.tool_calls.arguments"))?;
+            Ok(json!({"toolUse": {"toolUseId": id, "name": name, "input": input}}))
+        })
+        .collect()
+}
+
+/// `toolResult` block of a tool message
+fn tool_result(message: &Value) -> Result<Value, TranslationError> {
+    let id = message
+        .get("tool_call_id")
+        .and_then(Value::as_str)
+        .ok_or(TranslationError::InvalidField("messages.tool_call_id"))?;
+    let content = content_blocks(message.get("content"), false)?;
+    Ok(json!({"toolResult": {"toolUseId": id, "content": content}}))
+}
+
+fn inference_config(request: &Value) -> Result<Option<Value>, TranslationError> {
+    let mut config = Map::new();
+    if let Some(max_tokens) = request
+        .get("max_completion_tokens")
+        .or_else(|| request.get("max_tokens"))
+        .filter(|value| !value.is_null())
+    {
+        let max_tokens = max_tokens
+            .as_u64()
+            .ok_or(TranslationError::InvalidField("max_tokens"))?;
+        config.insert("maxTokens".to_string(), max_tokens.into());
+    }
+    for (field, name) in [("temperature", "temperature"), ("top_p", "topP")] {
+        if let Some(value) = request.get(field).filter(|value| !value.is_null()) {
+            let value = value
+                .as_f64()
+                .ok_or(TranslationError::InvalidField(field))?;
+            config.insert(name.to_string(), value.into());
+        }
+    }
+    let stop = match request.get("stop") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::String(stop)) => vec![stop.clone()],
+        Some(Value::Array(stops)) => stops
+            .iter()
+            .map(|stop| stop.as_str().map(str::to_string))
+            .collect::<Option<_>>()
+            .ok_or(TranslationError::InvalidField("stop"))?,
+        Some(_) => return Err(TranslationError::InvalidField("stop")),
+    };
+    if !stop.is_empty() {
+        config.insert("stopSequences".to_string(), stop.into());
+    }
+    Ok((!config.is_empty()).then_some(Value::Object(config)))
+}
+
+fn tool_config(request: &Value) -> Result<Option<Value>, TranslationError> {
+    let Some(tools) = request
+        .get("tools")
+        .and_then(Value::as_array)
+        .filter(|tools| !tools.is_empty())
+    else {
+        return Ok(None);
+    };
+    let choice = match request.get("tool_choice") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(choice)) => match choice.as_str() {
+            // Converse has no way to offer tools but forbid their use
+            "none" => return Ok(None),
+            "auto" => Some(json!({"auto": {}})),
+            "required" => Some(json!({"any": {}})),
+            _ => return Err(TranslationError::InvalidField("tool_choice")),
+        },
+        Some(choice) => {
+            let name = choice
+                .get("function")
+                .and_then(|function| function.get("name"))
+                .and_then(Value::as_str)
+                .ok_or(TranslationError::InvalidField("tool_choice"))?;
+            Some(json!({"tool": {"name": name}}))
+        }
+    };
+
+    let specs = tools
+        .iter()
+        .map(|tool| {
+            let function = tool
+                .get("function")
+                .filter(|_| tool.get("type").and_then(Value::as_str) == Some("function"))
+                .ok_or_else(|| {
+                    TranslationError::Unsupported("tools other than functions".to_string())
+                })?;
+            let name = function
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or(TranslationError::InvalidField("tools.function.name"))?;
+            let mut spec = Map::new();
+            spec.insert("name".to_string(), name.into());
+            if let Some(description) = function.get("description").and_then(Value::as_str) {
+                spec.insert("description".to_string(), description.into());
+            }
+            let parameters = function
+                .get("parameters")
+                .cloned()
+                .unwrap_or_else(|| json!({"type": "object", "properties": {}}));
+            spec.insert("inputSchema".to_string(), json!({"json": parameters}));
+            Ok(json!({"toolSpec": spec}))
+        })
+        .collect::<Result<Vec<_>, TranslationError>>()?;
+
+    let mut config = Map::new();
+    config.insert("tools".to_string(), Value::Array(specs));
+    if let Some(choice) = choice {
+        config.insert("toolChoice".to_string(), choice);
+    }
+    Ok(Some(Value::Object(config)))
+}
+
+/// Translate a Converse response (a message or an error) into a `chat.completion` or
+/// an OpenAI error body
+pub fn converse_to_chat(response: &Value, model: &str) -> Result<Value, TranslationError> {
+    let Some(message) = response
+        .get("output")
+        .and_then(|output| output.get("message"))
+    else {
+        return error_to_chat(response, None, 500)
+            .map_err(|_| TranslationError::InvalidField("output"));
+    };
+
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+    for block in message
+        .get("content")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        if let Some(chunk) = block.get("text").and_then(Value::as_str) {
+            text.push_str(chunk);
+        } else if let Some(tool_use) = block.get("toolUse") {
+            tool_calls.push(json!({
+                "id": tool_use.get("toolUseId").cloned().unwrap_or(Value::Null),
+                "type": "function",
+                "function": {
+                    "name": tool_use.get("name").cloned().unwrap_or(Value::Null),
+                    "arguments": tool_use.get("input").unwrap_or(&json!({})).to_string()
+                }
+            }));
+        }
+        // Reasoning and other blocks have no Chat Completions equivalent
+    }
+
+    let mut reply = Map::new();
+    reply.insert("role".to_string(), "assistant".into());
+    reply.insert(
+        "content".to_string(),
+        match text.is_empty() && !tool_calls.is_empty() {
+            true => Value::Null,
+            false => text.into(),
+        },
+    );
+    if !tool_calls.is_empty() {
+        reply.insert("tool_calls".to_string(), Value::Array(tool_calls));
+    }
+
+    let finish_reason = match response.get("stopReason").and_then(Value::as_str) {
+        Some("max_tokens") => "length",
+        Some("tool_use") => "tool_calls",
+        Some("guardrail_intervened" | "content_filtered") => "content_filter",
+        _ => "stop",
+    };
+    let tokens = |name| {
+        response
+            .get("usage")
+            .and_then(|usage| usage.get(name))
+            .and_then(Value::as_u64)
+            .unwrap_or(0)
+    };
+    let (prompt_tokens, completion_tokens) = (tokens("inputTokens"), tokens("outputTokens"));
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    Ok(json!({
+        "id": format!("chatcmpl-{:016x}", rand::random::<u64>()),
+        "object": "chat.completion",
+        "created": created,
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "message": reply,
+            "finish_reason": finish_reason
+        }],
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens
+        }
+    }))
+}
+
+/// Translate a Bedrock error body into an OpenAI error body. The exception is the
+/// `X-Amzn-ErrorType` header of the response when there is one, and the HTTP status
+/// types errors of unknown exceptions.
+pub fn error_to_chat(
+    response: &Value,
+    exception: Option<&str>,
+    status: u16,
+) -> Result<Value, TranslationError> {
+    let message = response
+        .get("message")
+        .or_else(|| response.get("Message"))
+        .and_then(Value::as_str)
+        .ok_or(TranslationError::InvalidField("message"))?;
+    let exception = exception.or_else(|| response.get("__type").and_then(Value::as_str));
+    let (kind, code) = openai_error(exception, status);
+    Ok(json!({"error": {
+        "message": message,
+        "type": kind,
+        "param": null,
+        "code": code
+    }}))
+}
+
+/// OpenAI error type and code of a Bedrock exception, named as in `X-Amzn-ErrorType`
+/// (`ThrottlingException:http://...`) or in stream frames (`throttlingException`)
+pub fn openai_error(exception: Option<&str>, status: u16) -> (&'static str, Option<&'static str>) {
+    let exception = exception
+        .and_then(|exception| exception.split([':', '#']).find(|part| !part.is_empty()))
+        .unwrap_or_default();
+    let is = |name: &str| exception.eq_ignore_ascii_case(name);
+    match status {
+        _ if is("ValidationException") => ("invalid_request_error", None),
+        _ if is("ResourceNotFoundException") => ("invalid_request_error", Some("model_not_found")),
+        _ if is("AccessDeniedException") => ("permission_error", None),
+        _ if is("UnrecognizedClientException") || is("ExpiredTokenException") => {
+            ("authentication_error", Some("invalid_api_key"))
+        }
+        _ if is("ThrottlingException") || is("ServiceQuotaExceededException") => {
+            ("rate_limit_error", Some("rate_limit_exceeded"))
+        }
+        _ if is("ModelTimeoutException") => ("api_error", Some("timeout")),
+        _ if is("ModelNotReadyException") || is("ServiceUnavailableException") => {
+            ("api_error", Some("service_unavailable"))
+        }
+        400 | 404 | 413 | 422 => ("invalid_request_error", None),
+        401 => ("authentication_error", None),
+        403 => ("permission_error", None),
+        429 => ("rate_limit_error", Some("rate_limit_exceeded")),
+        _ => ("api_error", None),
+    }
+}
+
+/// Translate an OpenAI embeddings request into an `InvokeModel` request of a Titan or
+/// Cohere embedding model
+pub fn embeddings_to_invoke(request: &Value) -> Result<TranslatedRequest, TranslationError> {
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .filter(|model| !model.is_empty())
+        .ok_or(TranslationError::InvalidField("model"))?;
+    let inputs: Vec<&str> = match request.get("input") {
+        Some(Value::String(input)) => vec![input.as_str()],
+        Some(Value::Array(inputs)) => inputs
+            .iter()
+            .map(Value::as_str)
+            .collect::<Option<_>>()
+            .ok_or_else(|| {
+                TranslationError::Unsupported("Bedrock embeds text, not token arrays".to_string())
+            })?,
+        _ => return Err(TranslationError::InvalidField("input")),
+    };
+    if inputs.is_empty() {
+        return Err(TranslationError::InvalidField("input"));
+    }
+    if request
+        .get("encoding_format")
+        .and_then(Value::as_str)
+        .is_some_and(|format| format != "float")
+    {
+        return Err(TranslationError::Unsupported(
+            "Bedrock returns float embeddings only".to_string(),
+        ));
+    }
+
+    let invoke = if model.contains("cohere.embed") {
+        json!({"texts": inputs, "input_type": "search_document"})
+    } else {
+        let [input] = inputs[..] else {
+            return Err(TranslationError::Unsupported(
+                "Titan embeds a single input per request".to_string(),
+            ));
+        };
+        let mut invoke = json!({"inputText": input});
+        if let Some(dimensions) = request.get("dimensions") {
+            invoke["dimensions"] = dimensions.clone();
+        }
+        invoke
+    };
+    Ok(TranslatedRequest {
+        path: format!("/model/{}/invoke", encode_model_id(model)),
+        body: serde_json::to_vec(&invoke).expect("JSON values serialize"),
+        headers: vec![
+            ("content-type", "application/json".to_string()),
+            ("accept", "application/json".to_string()),
+        ],
+        default_headers: Vec::new(),
+        removed_headers: OPENAI_HEADERS,
+        api_key_header: None,
+        model: model.to_string(),
+    })
+}
+
+/// Translate a Titan or Cohere `InvokeModel` response (or an error) into an OpenAI
+/// embeddings list
+pub fn invoke_to_embeddings(response: &Value, model: &str) -> Result<Value, TranslationError> {
+    let embeddings: Vec<&Value> = if let Some(embedding) = response.get("embedding") {
+        vec![embedding]
+    } else if let Some(embeddings) = response.get("embeddings") {
+        // Cohere nests them by type when asked for several `embedding_types`
+        let embeddings = embeddings.get("float").unwrap_or(embeddings);
+        embeddings
+            .as_array()
+            .ok_or(TranslationError::InvalidField("embeddings"))?
+            .iter()
+            .collect()
+    } else {
+        return error_to_chat(response, None, 500)
+            .map_err(|_| TranslationError::InvalidField("embedding"));
+    };
+
+    let data: Vec<Value> = embeddings
+        .into_iter()
+        .enumerate()
+        .map(|(index, embedding)| {
+            json!({"object": "embedding", "index": index, "embedding": embedding})
+        })
+        .collect();
+    // Cohere reports no token count
+    let prompt_tokens = response
+        .get("inputTextTokenCount")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    Ok(json!({
+        "object": "list",
+        "data": data,
+        "model": model,
+        "usage": {"prompt_tokens": prompt_tokens, "total_tokens": prompt_tokens}
+    }))
+}
+
+/// Percent-encode a model ID or ARN for a path segment (ARNs contain `/`)
+fn encode_model_id(model: &str) -> String {
+    let mut encoded = String::with_capacity(model.len());
+    for byte in model.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
@@ -0,0 +1,284 @@
+//! Request translation between provider API dialects.
+//!
+//! Clients speak the OpenAI API (Chat Completions, and Embeddings for Bedrock);
+//! requests routed to an upstream that speaks another dialect are rewritten (body, path
+//! and headers) before they are sent, and their responses, JSON or [streamed](stream),
+//! are translated back. Translation is plain data in, data out, so it can be reused
+//! outside the proxy. [`unified`] routes a single OpenAI API to every provider by model.
+
+pub mod anthropic;
+pub mod bedrock;
+pub mod stream;
+pub mod unified;
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Largest request body translated: what the proxy can buffer and replay upstream
+pub const MAX_BODY_BYTES: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub enum TranslationError {
+    /// The body is not JSON
+    InvalidJson(serde_json::Error),
+    /// A required field is missing or has the wrong type
+    InvalidField(&'static str),
+    /// Valid OpenAI, but not expressible in the target dialect
+    Unsupported(String),
+}
+
+impl fmt::Display for TranslationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranslationError::InvalidJson(e) => write!(f, "request body is not JSON: {}", e),
+            TranslationError::InvalidField(field) => {
+                write!(f, "missing or invalid field '{}'", field)
+            }
+            TranslationError::Unsupported(what) => {
+                write!(f, "cannot translate request: {}", what)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TranslationError {}
+
+/// API dialect an upstream speaks, other than the OpenAI one clients use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    /// AWS Bedrock `Converse` / `ConverseStream`
+    BedrockConverse,
+    /// Anthropic Messages (`/v1/messages`)
+    AnthropicMessages,
+}
+
+impl Dialect {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Dialect::BedrockConverse => "bedrock-converse",
+            Dialect::AnthropicMessages => "anthropic-messages",
+        }
+    }
+
+    /// Provider serving the dialect, named as in provider detection
+    pub fn provider(&self) -> &'static str {
+        match self {
+            Dialect::BedrockConverse => "bedrock",
+            Dialect::AnthropicMessages => "anthropic",
+        }
+    }
+
+    /// Whether requests to an OpenAI endpoint are translated to this dialect: Anthropic
+    /// has no embeddings API
+    pub fn translates(&self, endpoint: Endpoint) -> bool {
+        match self {
+            Dialect::BedrockConverse => true,
+            Dialect::AnthropicMessages => endpoint == Endpoint::ChatCompletions,
+        }
+    }
+
+    /// Rewrite an OpenAI `/v1/chat/completions` request body for this dialect
+    pub fn translate_chat_request(
+        &self,
+        body: &[u8],
+    ) -> Result<TranslatedRequest, TranslationError> {
+        let request: Value = serde_json::from_slice(body).map_err(TranslationError::InvalidJson)?;
+        match self {
+            Dialect::BedrockConverse => bedrock::chat_to_converse(&request),
+            Dialect::AnthropicMessages => anthropic::chat_to_messages(&request),
+        }
+    }
+
+    /// Rewrite a JSON response body of this dialect as a `chat.completion` (or an
+    /// OpenAI error body); `model` is the requested model, reported when the response
+    /// does not name one
+    pub fn translate_chat_response(
+        &self,
+        body: &[u8],
+        model: &str,
+    ) -> Result<Vec<u8>, TranslationError> {
+        let response: Value =
+            serde_json::from_slice(body).map_err(TranslationError::InvalidJson)?;
+        let completion = match self {
+            Dialect::BedrockConverse => bedrock::converse_to_chat(&response, model)?,
+            Dialect::AnthropicMessages => anthropic::messages_to_chat(&response, model)?,
+        };
+        Ok(serde_json::to_vec(&completion).expect("JSON values serialize"))
+    }
+
+    /// Rewrite an OpenAI `/v1/embeddings` request body for this dialect
+    pub fn translate_embeddings_request(
+        &self,
+        body: &[u8],
+    ) -> Result<TranslatedRequest, TranslationError> {
+        let request: Value = serde_json::from_slice(body).map_err(TranslationError::InvalidJson)?;
+        match self {
+            Dialect::BedrockConverse => bedrock::embeddings_to_invoke(&request),
+            Dialect::AnthropicMessages => Err(TranslationError::Unsupported(
+                "Anthropic has no embeddings API".to_string(),
+            )),
+        }
+    }
+
+    /// Rewrite a JSON embeddings response body of this dialect as an OpenAI embeddings
+    /// list (or an OpenAI error body)
+    pub fn translate_embeddings_response(
+        &self,
+        body: &[u8],
+        model: &str,
+    ) -> Result<Vec<u8>, TranslationError> {
+        let response: Value =
+            serde_json::from_slice(body).map_err(TranslationError::InvalidJson)?;
+        let embeddings = match self {
+            Dialect::BedrockConverse => bedrock::invoke_to_embeddings(&response, model)?,
+            Dialect::AnthropicMessages => {
+                return Err(TranslationError::Unsupported(
+                    "Anthropic has no embeddings API".to_string(),
+                ));
+            }
+        };
+        Ok(serde_json::to_vec(&embeddings).expect("JSON values serialize"))
+    }
+
+    /// Rewrite an error response body of this dialect as an OpenAI error body, typed
+    /// by the upstream's error type header (Bedrock's `X-Amzn-ErrorType`) or status
+    pub fn translate_error_response(
+        &self,
+        body: &[u8],
+        error_type: Option<&str>,
+        status: u16,
+    ) -> Result<Vec<u8>, TranslationError> {
+        let response: Value =
+            serde_json::from_slice(body).map_err(TranslationError::InvalidJson)?;
+        let error = match self {
+            Dialect::BedrockConverse => bedrock::error_to_chat(&response, error_type, status)?,
+            Dialect::AnthropicMessages => anthropic::messages_to_chat(&response, "")?,
+        };
+        Ok(serde_json::to_vec(&error).expect("JSON values serialize"))
+    }
+}
+
+/// OpenAI endpoint of a translated request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    /// `/v1/chat/completions`
+    ChatCompletions,
+    /// `/v1/embeddings`
+    Embeddings,
+}
+
+impl Endpoint {
+    pub fn path(&self) -> &'static str {
+        match self {
+            Endpoint::ChatCompletions => "/v1/chat/completions",
+            Endpoint::Embeddings => "/v1/embeddings",
+        }
+    }
+
+    /// Endpoint of a request path, if it is one that is translated
+    pub fn of(path: &str) -> Option<Self> {
+        let path = path.split('?').next().unwrap_or_default();
+        let path = path.trim_end_matches('/');
+        [Endpoint::ChatCompletions, Endpoint::Embeddings]
+            .into_iter()
+            .find(|endpoint| endpoint.path() == path)
+    }
+
+    /// Endpoint of a request path, possibly under a route prefix (e.g.
+    /// `/anthropic/v1/chat/completions`)
+    pub fn under(path: &str) -> Option<Self> {
+        let path = path.split('?').next().unwrap_or_default();
+        let path = path.trim_end_matches('/');
+        [Endpoint::ChatCompletions, Endpoint::Embeddings]
+            .into_iter()
+            .find(|endpoint| path.ends_with(endpoint.path()))
+    }
+}
+
+/// A request rewritten for another dialect
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranslatedRequest {
+    /// Upstream path (without query)
+    pub path: String,
+    pub body: Vec<u8>,
+    /// Headers set on the upstream request; `Content-Length` is set from the body
+    pub headers: Vec<(&'static str, String)>,
+    /// Headers set unless the client sent them, e.g. an API version
+    pub default_headers: Vec<(&'static str, String)>,
+    /// Client headers that have no meaning for the target dialect
+    pub removed_headers: &'static [&'static str],
+    /// Header the client's `Authorization: Bearer` key moves to
+    pub api_key_header: Option<&'static str>,
+    /// Model named in the original request
+    pub model: String,
+}
+
+/// Which requests are translated, and to which dialect.
+///
+/// A route selects the dialect by the path clients call: with a route `/anthropic`,
+/// `/anthropic/v1/chat/completions` is translated whichever upstream it is sent to.
+/// Otherwise `/v1/chat/completions` is translated by its upstream: Bedrock runtime
+/// (`bedrock-runtime.*.amazonaws.com`) and Anthropic (`api.anthropic.com`) endpoints
+/// are recognised by their host, and others, such as VPC endpoints or test doubles,
+/// are declared by address.
+#[derive(Debug, Clone, Default)]
+pub struct TranslationConfig {
+    /// Dialect per upstream address, overriding host recognition
+    pub upstreams: HashMap<String, Dialect>,
+    /// Dialect per client path prefix, checked in order before upstreams
+    pub routes: Vec<(String, Dialect)>,
+}
+
+impl TranslationConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Translate requests routed to `upstream` (its `host:port` address) to `dialect`
+    pub fn with_upstream(mut self, upstream: impl Into<String>, dialect: Dialect) -> Self {
+        self.upstreams.insert(upstream.into(), dialect);
+        self
+    }
+
+    /// Translate requests under the path `prefix` (e.g. `/anthropic` for
+    /// `/anthropic/v1/chat/completions`) to `dialect`
+    pub fn with_route(mut self, prefix: impl Into<String>, dialect: Dialect) -> Self {
+        let prefix = prefix.into();
+        self.routes
+            .push((prefix.trim_end_matches('/').to_string(), dialect));
+        self
+    }
+
+    /// Dialect of an upstream, given its address and hostname
+    pub fn dialect_for(&self, address: &str, host: &str) -> Option<Dialect> {
+        if let Some(dialect) = self.upstreams.get(address) {
+            return Some(*dialect);
+        }
+        let host = host.to_ascii_lowercase();
+        if host.starts_with("bedrock-runtime") && host.ends_with(".amazonaws.com") {
+            return Some(Dialect::BedrockConverse);
+        }
+        (host == "api.anthropic.com").then_some(Dialect::AnthropicMessages)
+    }
+
+    /// Dialect a request is translated to, given its path and the upstream's address
+    /// and hostname; `None` for requests to endpoints that are not translated or to an
+    /// OpenAI-compatible upstream
+    pub fn dialect_for_request(&self, path: &str, address: &str, host: &str) -> Option<Dialect> {
+        let route = self.routes.iter().find_map(|(prefix, dialect)| {
+            let endpoint = Endpoint::of(path.strip_prefix(prefix.as_str())?)?;
+            dialect.translates(endpoint).then_some(*dialect)
+        });
+        route.or_else(|| {
+            let endpoint = Endpoint::of(path)?;
+            self.dialect_for(address, host)
+                .filter(|dialect| dialect.translates(endpoint))
+        })
+    }
+}
+
+/// Whether a request path is the OpenAI Chat Completions endpoint
+pub fn is_chat_completions(path: &str) -> bool {
+    Endpoint::of(path) == Some(Endpoint::ChatCompletions)
+}
@@ -0,0 +1,431 @@
+//! Streamed responses translated to OpenAI `chat.completion.chunk` server-sent events.
+//!
+//! | Anthropic Messages SSE            | Bedrock `ConverseStream` event     | Chunk delta                  |
+//! |-----------------------------------|------------------------------------|------------------------------|
+//! | `message_start`                   | `messageStart`                     | `role`                       |
+//! | `content_block_start` (tool)      | `contentBlockStart` (tool)         | `tool_calls` id and name     |
+//! | `content_block_delta` text        | `contentBlockDelta` text           | `content`                    |
+//! | `content_block_delta` JSON        | `contentBlockDelta` tool input     | `tool_calls` arguments       |
+//! | `message_delta` stop reason       | `messageStop`                      | `finish_reason`              |
+//! | `message_delta` / `message_start` | `metadata`                         | final chunk with `usage`     |
+//! | `error`                           | exception frames                   | `error` event (OpenAI type)  |
+//!
+//! Chunks end with `data: [DONE]`. Usage is always sent, in a last chunk without
+//! choices as with `stream_options.include_usage`, so gateway usage tracking sees the
+//! provider's counts.
+
+use super::{Dialect, bedrock};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Incremental translation of a streamed response body, fed chunk by chunk
+#[derive(Debug)]
+pub struct StreamTranslator {
+    dialect: Dialect,
+    id: String,
+    model: String,
+    created: u64,
+    /// Bytes of an incomplete line or frame carried over to the next chunk
+    pending: Vec<u8>,
+    /// Tool call index per content block index
+    tool_calls: HashMap<u64, u64>,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    done: bool,
+}
+
+impl StreamTranslator {
+    /// Translator for a stream of `dialect`; `model` is reported when the stream does
+    /// not name one
+    pub fn new(dialect: Dialect, model: impl Into<String>) -> Self {
+        Self {
+            dialect,
+            id: format!("chatcmpl-{:016x}", rand::random::<u64>()),
+            model: model.into(),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            pending: Vec::new(),
+            tool_calls: HashMap::new(),
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            done: false,
+        }
+    }
+
+    /// Feed a chunk of the upstream body; returns the translated events it completes
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(chunk);
+        let mut out = Vec::new();
+        match self.dialect {
+            Dialect::AnthropicMessages => self.feed_lines(&mut out),
+            Dialect::BedrockConverse => self.feed_frames(&mut out),
+        }
+        out
+    }
+
+    /// End of the upstream body: the final events, if the stream did not send them
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if self.dialect == Dialect::AnthropicMessages && !self.pending.is_empty() {
+            self.pending.push(b'\n');
+            self.feed_lines(&mut out);
+        }
+        self.pending.clear();
+        self.end(&mut out);
+        out
+    }
+
+    /// Whether `data: [DONE]` was sent
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn feed_lines(&mut self, out: &mut Vec<u8>) {
+        let Some(end) = self.pending.iter().rposition(|&b| b == b'\n') else {
+            return;
+        };
+        let complete: Vec<u8> = self.pending.drain(..=end).collect();
+        for line in complete.split(|&b| b == b'\n') {
+            let Some(data) = line.trim_ascii().strip_prefix(b"data:") else {
+                continue;
+            };
+            if let Ok(event) = serde_json::from_slice::<Value>(data.trim_ascii()) {
+                self.anthropic_event(&event, out);
+            }
+        }
+    }
+
+    /// `application/vnd.amazon.eventstream`: 12-byte prelude (total length, headers
+    /// length, CRC), headers, JSON payload and a trailing CRC
+    fn feed_frames(&mut self, out: &mut Vec<u8>) {
+        while self.pending.len() >= 12 {
+            let total = u32::from_be_bytes(self.pending[0..4].try_into().unwrap()) as usize;
+            let headers_len = u32::from_be_bytes(self.pending[4..8].try_into().unwrap()) as usize;
+            if total < 16 + headers_len {
+                // Not an event stream after all; stop parsing rather than misread it
+                self.pending.clear();
+                return;
+            }
+            if self.pending.len() < total {
+                return;
+            }
+            let frame: Vec<u8> = self.pending.drain(..total).collect();
+            let headers = frame_headers(&frame[12..12 + headers_len]);
+            let header = |name: &str| {
+                headers
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.as_str())
+            };
+            let payload = serde_json::from_slice::<Value>(&frame[12 + headers_len..total - 4])
+                .unwrap_or(Value::Null);
+            match header(":message-type") {
+                Some("exception") | Some("error") => {
+                    let exception = header(":exception-type").or_else(|| header(":error-code"));
+                    // Stream exceptions come after a 200 status
+                    let (kind, code) = bedrock::openai_error(exception, 500);
+                    let message = payload
+                        .get("message")
+                        .or_else(|| payload.get("Message"))
+                        .and_then(Value::as_str)
+                        .or_else(|| header(":error-message"))
+                        .unwrap_or("upstream stream failed");
+                    self.error(kind, code, message, out);
+                }
+                _ => {
+                    if let Some(event_type) = header(":event-type") {
+                        self.converse_event(event_type, &payload, out);
+                    }
+                }
+            }
+        }
+    }
+
+    fn anthropic_event(&mut self, event: &Value, out: &mut Vec<u8>) {
+        let index = event.get("index").and_then(Value::as_u64).unwrap_or(0);
+        match event.get("type").and_then(Value::as_str) {
+            Some("message_start") => {
+                let message = event.get("message");
+                if let Some(id) = message
+                    .and_then(|message| message.get("id"))
+                    .and_then(Value::as_str)
+                {
+                    self.id = id.to_string();
+                }
+                if let Some(model) = message
+                    .and_then(|message| message.get("model"))
+                    .and_then(Value::as_str)
+                {
+                    self.model = model.to_string();
+                }
+                self.observe_usage(message.and_then(|message| message.get("usage")));
+                self.chunk(json!({"role": "assistant", "content": ""}), None, out);
+            }
+            Some("content_block_start") => {
+                let block = event.get("content_block").unwrap_or(&Value::Null);
+                match block.get("type").and_then(Value::as_str) {
+                    Some("tool_use") => {
+                        self.tool_start(index, block.get("id"), block.get("name"), out);
+                    }
+                    Some("text") => {
+                        let text = block
+                            .get("text")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default();
+                        if !text.is_empty() {
+                            self.chunk(json!({"content": text}), None, out);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Some("content_block_delta") => {
+                let delta = event.get("delta");
+                let field = |name| {
+                    delta
+                        .and_then(|delta| delta.get(name))
+                        .and_then(Value::as_str)
+                };
+                match delta
+                    .and_then(|delta| delta.get("type"))
+                    .and_then(Value::as_str)
+                {
+                    Some("text_delta") => {
+                        self.chunk(json!({"content": field("text")}), None, out);
+                    }
+                    Some("input_json_delta") => {
+                        self.tool_arguments(index, field("partial_json").unwrap_or_default(), out)
+                    }
+                    // Thinking and signature deltas have no Chat Completions equivalent
+                    _ => {}
+                }
+            }
+            Some("message_delta") => {
+                self.observe_usage(event.get("usage"));
+                if let Some(reason) = event
+                    .get("delta")
+                    .and_then(|delta| delta.get("stop_reason"))
+                    .and_then(Value::as_str)
+                {
+                    let reason = match reason {
+                        "max_tokens" => "length",
+                        "tool_use" => "tool_calls",
+                        "refusal" => "content_filter",
+                        _ => "stop",
+                    };
+                    self.chunk(json!({}), Some(reason), out);
+                }
+            }
+            Some("message_stop") => self.end(out),
+            Some("error") => {
+                let error = event.get("error");
+                let field = |name| {
+                    error
+                        .and_then(|error| error.get(name))
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                };
+                self.error(field("type"), None, field("message"), out);
+            }
+            _ => {}
+        }
+    }
+
+    fn converse_event(&mut self, event_type: &str, payload: &Value, out: &mut Vec<u8>) {
+        let index = payload
+            .get("contentBlockIndex")
+            .and_then(Value::as_u64)
+            .unwrap_or(0);
+        match event_type {
+            "messageStart" => self.chunk(json!({"role": "assistant", "content": ""}), None, out),
+            "contentBlockStart" => {
+                if let Some(tool_use) = payload.get("start").and_then(|start| start.get("toolUse"))
+                {
+                    self.tool_start(index, tool_use.get("toolUseId"), tool_use.get("name"), out);
+                }
+            }
+            "contentBlockDelta" => {
+                let delta = payload.get("delta");
+                if let Some(text) = delta
+                    .and_then(|delta| delta.get("text"))
+                    .and_then(Value::as_str)
+                {
+                    self.chunk(json!({"content": text}), None, out);
+                } else if let Some(input) = delta
+                    .and_then(|delta| delta.get("toolUse"))
+                    .and_then(|tool_use| tool_use.get("input"))
+                    .and_then(Value::as_str)
+                {
+                    self.tool_arguments(index, input, out);
+                }
+            }
+            "messageStop" => {
+                let reason = match payload.get("stopReason").and_then(Value::as_str) {
+                    Some("max_tokens") => "length",
+                    Some("tool_use") => "tool_calls",
+                    Some("guardrail_intervened" | "content_filtered") => "content_filter",
+                    _ => "stop",
+                };
+                self.chunk(json!({}), Some(reason), out);
+            }
+            "metadata" => {
+                let usage = payload.get("usage");
+                let tokens = |name| {
+                    usage
+                        .and_then(|usage| usage.get(name))
+                        .and_then(Value::as_u64)
+                };
+                self.prompt_tokens = tokens("inputTokens").unwrap_or(self.prompt_tokens);
+                self.completion_tokens = tokens("outputTokens").unwrap_or(self.completion_tokens);
+                self.end(out);
+            }
+            _ => {}
+        }
+    }
+
+    fn tool_start(
+        &mut self,
+        block: u64,
+        id: Option<&Value>,
+        name: Option<&Value>,
+        out: &mut Vec<u8>,
+    ) {
+        let tool = self.tool_calls.len() as u64;
+        self.tool_calls.insert(block, tool);
+        self.chunk(
+            json!({"tool_calls": [{
+                "index": tool,
+                "id": id.cloned().unwrap_or(Value::Null),
+                "type": "function",
+                "function": {"name": name.cloned().unwrap_or(Value::Null), "arguments": ""}
+            }]}),
+            None,
+            out,
+        );
+    }
+
+    fn tool_arguments(&mut self, block: u64, arguments: &str, out: &mut Vec<u8>) {
+        let Some(tool) = self.tool_calls.get(&block).copied() else {
+            return;
+        };
+        if arguments.is_empty() {
+            return;
+        }
+        self.chunk(
+            json!({"tool_calls": [{"index": tool, "function": {"arguments": arguments}}]}),
+            None,
+            out,
+        );
+    }
+
+    fn observe_usage(&mut self, usage: Option<&Value>) {
+        let tokens = |name| {
+            usage
+                .and_then(|usage| usage.get(name))
+                .and_then(Value::as_u64)
+        };
+        if let Some(input) = tokens("input_tokens") {
+            self.prompt_tokens = input;
+        }
+        if let Some(output) = tokens("output_tokens") {
+            self.completion_tokens = output;
+        }
+    }
+
+    fn chunk(&self, delta: Value, finish_reason: Option<&str>, out: &mut Vec<u8>) {
+        if self.done {
+            return;
+        }
+        self.event(
+            &json!({
+                "id": self.id,
+                "object": "chat.completion.chunk",
+                "created": self.created,
+                "model": self.model,
+                "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+            }),
+            out,
+        );
+    }
+
+    /// Usage chunk and `[DONE]`, once
+    fn end(&mut self, out: &mut Vec<u8>) {
+        if self.done {
+            return;
+        }
+        self.event(
+            &json!({
+                "id": self.id,
+                "object": "chat.completion.chunk",
+                "created": self.created,
+                "model": self.model,
+                "choices": [],
+                "usage": {
+                    "prompt_tokens": self.prompt_tokens,
+                    "completion_tokens": self.completion_tokens,
+                    "total_tokens": self.prompt_tokens + self.completion_tokens
+                }
+            }),
+            out,
+        );
+        out.extend_from_slice(b"data: [DONE]\n\n");
+        self.done = true;
+    }
+
+    fn error(&mut self, kind: &str, code: Option<&str>, message: &str, out: &mut Vec<u8>) {
+        if self.done {
+            return;
+        }
+        self.event(
+            &json!({"error": {"message": message, "type": kind, "param": null, "code": code}}),
+            out,
+        );
+        out.extend_from_slice(b"data: [DONE]\n\n");
+        self.done = true;
+    }
+
+    fn event(&self, data: &Value, out: &mut Vec<u8>) {
+        out.extend_from_slice(b"data: ");
+        out.extend_from_slice(&serde_json::to_vec(data).expect("JSON values serialize"));
+        out.extend_from_slice(b"\n\n");
+    }
+}
+
+/// String-valued headers of an event stream frame; other header types are skipped
+fn frame_headers(mut headers: &[u8]) -> Vec<(String, String)> {
+    let mut parsed = Vec::new();
+    while let Some((&name_len, rest)) = headers.split_first() {
+        let Some((name, rest)) = rest.split_at_checked(name_len as usize) else {
+            break;
+        };
+        let Some((&kind, rest)) = rest.split_first() else {
+            break;
+        };
+        let value_len = match kind {
+            0 | 1 => 0,
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            5 | 8 => 8,
+            9 => 16,
+            6 | 7 => match rest.get(..2) {
+                Some(len) => 2 + u16::from_be_bytes([len[0], len[1]]) as usize,
+                None => break,
+            },
+            _ => break,
+        };
+        let Some(value) = rest.get(..value_len) else {
+            break;
+        };
+        if kind == 7 {
+            parsed.push((
+                String::from_utf8_lossy(name).into_owned(),
+                String::from_utf8_lossy(&value[2..]).into_owned(),
+            ));
+        }
+        headers = &rest[value_len..];
+    }
+    parsed
+}
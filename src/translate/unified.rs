@@ -0,0 +1,194 @@
+//! Unified API: one OpenAI-compatible surface in front of every provider.
+//!
+//! Clients call `/v1/chat/completions` and name a model; the model's route decides
+//! which upstreams serve it and which dialect they speak. Requests are translated for
+//! that dialect (and the model renamed, e.g. a short alias to a Bedrock model ID), and
+//! responses are normalized back to Chat Completions. `/v1/embeddings` requests are
+//! routed the same way. `GET /v1/models` lists the models clients can call.
+//!
+//! An [OpenAI-only](UnifiedApi::with_openai_only) API answers every other path with
+//! OpenAI's 404, so nothing but the OpenAI surface is exposed. The
+//! [Bedrock facade](UnifiedApi::bedrock_facade) preset serves it from Bedrock alone,
+//! for deployments standardized on AWS that run OpenAI SDK clients unchanged.
+
+use super::{Dialect, Endpoint, TranslationError};
+use serde_json::{Value, json};
+
+/// Upstreams (and dialect) serving a model of the unified API
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelRoute {
+    /// Model name clients use, or a prefix ending in `*` (e.g. `anthropic/*`)
+    pub model: String,
+    /// Addresses of the upstreams serving the model, preferred in balancing order
+    pub upstreams: Vec<String>,
+    /// Dialect of the upstreams; recognised by their host when unset, OpenAI-compatible
+    /// otherwise
+    pub dialect: Option<Dialect>,
+    /// Model name sent upstream; a `*` is replaced with what the client's model matched
+    /// after the route's prefix
+    pub upstream_model: Option<String>,
+}
+
+impl ModelRoute {
+    pub fn new(model: impl Into<String>, upstream: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            upstreams: vec![upstream.into()],
+            dialect: None,
+            upstream_model: None,
+        }
+    }
+
+    /// Also serve the model from `upstream`
+    pub fn with_upstream(mut self, upstream: impl Into<String>) -> Self {
+        self.upstreams.push(upstream.into());
+        self
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = Some(dialect);
+        self
+    }
+
+    pub fn with_upstream_model(mut self, model: impl Into<String>) -> Self {
+        self.upstream_model = Some(model.into());
+        self
+    }
+
+    /// What `model` matched after the route's prefix (empty for an exact name), or
+    /// `None` when the route does not serve it
+    fn matched<'a>(&self, model: &'a str) -> Option<&'a str> {
+        match self.model.strip_suffix('*') {
+            Some(prefix) => model.strip_prefix(prefix).filter(|rest| !rest.is_empty()),
+            None => (self.model == model).then_some(""),
+        }
+    }
+
+    pub fn matches(&self, model: &str) -> bool {
+        self.matched(model).is_some()
+    }
+
+    /// Model name sent upstream for a client's `model`, when it differs
+    pub fn upstream_model_for(&self, model: &str) -> Option<String> {
+        let upstream_model = self.upstream_model.as_deref()?;
+        let matched = self.matched(model)?;
+        let upstream_model = upstream_model.replace('*', matched);
+        (upstream_model != model).then_some(upstream_model)
+    }
+
+    /// Rewrite the `model` of a Chat Completions request body for the upstream;
+    /// `None` when it is sent unchanged
+    pub fn rewrite_request(&self, body: &[u8]) -> Result<Option<Vec<u8>>, TranslationError> {
+        let mut request: Value =
+            serde_json::from_slice(body).map_err(TranslationError::InvalidJson)?;
+        let model = request
+            .get("model")
+            .and_then(Value::as_str)
+            .ok_or(TranslationError::InvalidField("model"))?;
+        let Some(upstream_model) = self.upstream_model_for(model) else {
+            return Ok(None);
+        };
+        request["model"] = upstream_model.into();
+        Ok(Some(
+            serde_json::to_vec(&request).expect("JSON values serialize"),
+        ))
+    }
+}
+
+/// Models of the unified API, matched in order
+#[derive(Debug, Clone, Default)]
+pub struct UnifiedApi {
+    pub models: Vec<ModelRoute>,
+    /// Answer paths other than the OpenAI endpoints with 404 instead of proxying them
+    pub openai_only: bool,
+}
+
+impl UnifiedApi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// OpenAI-only API served by the Bedrock runtime endpoint `upstream`: clients name
+    /// Bedrock model IDs, or the aliases of `models` (alias, Bedrock model ID), which
+    /// are the models listed
+    pub fn bedrock_facade<'a>(
+        upstream: &str,
+        models: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Self {
+        let api = models.into_iter().fold(Self::new(), |api, (alias, model)| {
+            api.with_model(
+                ModelRoute::new(alias, upstream)
+                    .with_dialect(Dialect::BedrockConverse)
+                    .with_upstream_model(model),
+            )
+        });
+        api.with_model(ModelRoute::new("*", upstream).with_dialect(Dialect::BedrockConverse))
+            .with_openai_only()
+    }
+
+    pub fn with_model(mut self, route: ModelRoute) -> Self {
+        self.models.push(route);
+        self
+    }
+
+    /// Only expose the OpenAI endpoints (`/v1/chat/completions`, `/v1/embeddings` and
+    /// `/v1/models`), answering other paths with 404
+    pub fn with_openai_only(mut self) -> Self {
+        self.openai_only = true;
+        self
+    }
+
+    /// Whether a request to `path` is served: any path, or only the OpenAI endpoints
+    pub fn serves(&self, path: &str) -> bool {
+        !self.openai_only || Endpoint::of(path).is_some() || is_model_list(path)
+    }
+
+    /// Route of the first model matching a client's model name
+    pub fn route_for(&self, model: &str) -> Option<&ModelRoute> {
+        self.models.iter().find(|route| route.matches(model))
+    }
+
+    /// `GET /v1/models` response body listing the named (not prefixed) models
+    pub fn model_list(&self) -> Value {
+        let data: Vec<Value> = self
+            .models
+            .iter()
+            .filter(|route| !route.model.ends_with('*'))
+            .map(|route| {
+                json!({
+                    "id": route.model,
+                    "object": "model",
+                    "created": 0,
+                    "owned_by": route.dialect.map_or("openai", |dialect| dialect.provider()),
+                })
+            })
+            .collect();
+        json!({"object": "list", "data": data})
+    }
+}
+
+/// OpenAI error body for a path an OpenAI-only API does not serve
+pub fn unknown_url(method: &str, path: &str) -> Value {
+    json!({"error": {
+        "message": format!("Invalid URL ({} {})", method, path),
+        "type": "invalid_request_error",
+        "param": null,
+        "code": "unknown_url"
+    }})
+}
+
+/// OpenAI error body for a model the unified API does not serve
+pub fn model_not_found(model: &str) -> Value {
+    json!({"error": {
+        "message": format!("The model `{}` does not exist", model),
+        "type": "invalid_request_error",
+        "param": "model",
+        "code": "model_not_found"
+    }})
+}
+
+/// Whether a request path is the OpenAI model list endpoint
+pub fn is_model_list(path: &str) -> bool {
+    let path = path.split('?').next().unwrap_or_default();
+    path.trim_end_matches('/') == "/v1/models"
+}
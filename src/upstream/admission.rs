@@ -0,0 +1,77 @@
+//! Admission queue in front of upstream selection.
+//!
+//! A request that finds no upstream with room (every limiter or cap full, or every
+//! upstream ejected) waits in the queue for one to free up instead of failing at once,
+//! so bursts are absorbed rather than passed on to the backends or turned into 503s.
+//! The queue is bounded both in depth and in wait: a request arriving at a full queue,
+//! or still waiting at the timeout, is rejected with 503.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::metrics::ADMISSION_QUEUE_DEPTH;
+
+/// Bounds of the admission queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionConfig {
+    /// Most requests waiting at once
+    pub max_depth: usize,
+    /// Longest a request waits for an upstream
+    pub timeout: Duration,
+}
+
+impl AdmissionConfig {
+    pub fn new(max_depth: usize, timeout: Duration) -> Self {
+        assert!(max_depth > 0, "Admission queue depth must be positive");
+        Self { max_depth, timeout }
+    }
+}
+
+/// Requests waiting for an upstream, up to the queue's depth
+#[derive(Debug)]
+pub struct AdmissionQueue {
+    config: AdmissionConfig,
+    depth: AtomicUsize,
+}
+
+impl AdmissionQueue {
+    pub fn new(config: AdmissionConfig) -> Self {
+        Self {
+            config,
+            depth: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &AdmissionConfig {
+        &self.config
+    }
+
+    /// Requests waiting
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Acquire)
+    }
+
+    /// Take a place in the queue, or `None` when it is full
+    pub fn try_enter(&self) -> Option<QueuePlace<'_>> {
+        self.depth
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |depth| {
+                (depth < self.config.max_depth).then_some(depth + 1)
+            })
+            .ok()?;
+        ADMISSION_QUEUE_DEPTH.inc();
+        Some(QueuePlace { queue: self })
+    }
+}
+
+/// A place in the admission queue, left when dropped
+#[derive(Debug)]
+pub struct QueuePlace<'a> {
+    queue: &'a AdmissionQueue,
+}
+
+impl Drop for QueuePlace<'_> {
+    fn drop(&mut self) {
+        self.queue.depth.fetch_sub(1, Ordering::AcqRel);
+        ADMISSION_QUEUE_DEPTH.dec();
+    }
+}
@@ -0,0 +1,183 @@
+use crate::pipeline::views::RequestView;
+use crate::proxy::ctx::Ctx;
+use crate::upstream::hashing::rendezvous_rank;
+use crate::upstream::{HashKey, Upstream};
+use rand::Rng;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Upstream selection strategy.
+///
+/// The proxy owns the upstream list (and its runtime state such as latency and
+/// concurrency limits) and passes it to the balancer on every request, so custom
+/// strategies only decide *which* upstream to use. The list is never empty.
+///
+/// Embedders can supply their own strategy with `GatewayProxy::with_load_balancer`.
+pub trait LoadBalancer: Send + Sync {
+    /// Select the upstream for a request
+    fn select<'a>(
+        &self,
+        upstreams: &'a [Arc<Upstream>],
+        request_view: &RequestView,
+        ctx: &Ctx,
+    ) -> &'a Arc<Upstream>;
+
+    /// Upstreams in preference order, starting with the selection. The proxy walks this
+    /// list when the preferred upstream cannot take the request (e.g. it is at its
+    /// concurrency limit). The default keeps the remaining upstreams in list order.
+    fn candidates<'a>(
+        &self,
+        upstreams: &'a [Arc<Upstream>],
+        request_view: &RequestView,
+        ctx: &Ctx,
+    ) -> Vec<&'a Arc<Upstream>> {
+        let selected = self.select(upstreams, request_view, ctx);
+        let mut candidates = vec![selected];
+        candidates.extend(
+            upstreams
+                .iter()
+                .filter(|upstream| !Arc::ptr_eq(upstream, selected)),
+        );
+        candidates
+    }
+}
+
+/// Rotate through upstreams in order
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl RoundRobin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All upstreams starting from the next rotation position
+    fn rotation<'a>(&self, upstreams: &'a [Arc<Upstream>]) -> Vec<&'a Arc<Upstream>> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % upstreams.len();
+        (0..upstreams.len())
+            .map(|offset| &upstreams[(start + offset) % upstreams.len()])
+            .collect()
+    }
+}
+
+impl LoadBalancer for RoundRobin {
+    fn select<'a>(
+        &self,
+        upstreams: &'a [Arc<Upstream>],
+        _request_view: &RequestView,
+        _ctx: &Ctx,
+    ) -> &'a Arc<Upstream> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % upstreams.len();
+        &upstreams[index]
+    }
+
+    fn candidates<'a>(
+        &self,
+        upstreams: &'a [Arc<Upstream>],
+        _request_view: &RequestView,
+        _ctx: &Ctx,
+    ) -> Vec<&'a Arc<Upstream>> {
+        self.rotation(upstreams)
+    }
+}
+
+/// Rendezvous hashing on a request attribute so repeated requests from the same
+/// client land on the same upstream (prefix/KV cache locality). Requests without
+/// the attribute fall back to round-robin.
+#[derive(Debug)]
+pub struct ConsistentHashBalancer {
+    key: HashKey,
+    fallback: RoundRobin,
+}
+
+impl ConsistentHashBalancer {
+    pub fn new(key: HashKey) -> Self {
+        Self {
+            key,
+            fallback: RoundRobin::new(),
+        }
+    }
+}
+
+impl LoadBalancer for ConsistentHashBalancer {
+    fn select<'a>(
+        &self,
+        upstreams: &'a [Arc<Upstream>],
+        request_view: &RequestView,
+        ctx: &Ctx,
+    ) -> &'a Arc<Upstream> {
+        self.candidates(upstreams, request_view, ctx)[0]
+    }
+
+    fn candidates<'a>(
+        &self,
+        upstreams: &'a [Arc<Upstream>],
+        request_view: &RequestView,
+        ctx: &Ctx,
+    ) -> Vec<&'a Arc<Upstream>> {
+        let Some(value) = self.key.extract(request_view) else {
+            return self.fallback.candidates(upstreams, request_view, ctx);
+        };
+
+        let addresses: Vec<&str> = upstreams.iter().map(|u| u.address()).collect();
+        rendezvous_rank(value, &addresses)
+            .into_iter()
+            .map(|index| &upstreams[index])
+            .collect()
+    }
+}
+
+/// Sample two upstreams at random and pick the one with the lower EWMA latency.
+/// Round-robin ignores that some LLM backends are persistently slower; P2C avoids
+/// them without the herd behaviour of always picking the global minimum.
+#[derive(Debug, Default)]
+pub struct PowerOfTwoChoices;
+
+impl PowerOfTwoChoices {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl LoadBalancer for PowerOfTwoChoices {
+    fn select<'a>(
+        &self,
+        upstreams: &'a [Arc<Upstream>],
+        request_view: &RequestView,
+        ctx: &Ctx,
+    ) -> &'a Arc<Upstream> {
+        self.candidates(upstreams, request_view, ctx)[0]
+    }
+
+    /// The sampled pair ordered by latency, followed by the rest as fallbacks
+    fn candidates<'a>(
+        &self,
+        upstreams: &'a [Arc<Upstream>],
+        _request_view: &RequestView,
+        _ctx: &Ctx,
+    ) -> Vec<&'a Arc<Upstream>> {
+        let len = upstreams.len();
+        if len < 2 {
+            return upstreams.iter().collect();
+        }
+
+        let mut rng = rand::rng();
+        let first = rng.random_range(0..len);
+        let second = (first + rng.random_range(1..len)) % len;
+        let (best, other) = if upstreams[second].latency() < upstreams[first].latency() {
+            (second, first)
+        } else {
+            (first, second)
+        };
+
+        let mut candidates = vec![&upstreams[best], &upstreams[other]];
+        candidates.extend(
+            (0..len)
+                .filter(|&index| index != best && index != other)
+                .map(|index| &upstreams[index]),
+        );
+        candidates
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::upstream::LimiterPermit;
+
+/// What happens to a request whose upstream is at its concurrency cap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CapOverflow {
+    /// Send it to the next candidate upstream with a free slot
+    #[default]
+    Reroute,
+    /// Wait up to this long for a slot on the upstream it was routed to
+    Queue(Duration),
+    /// Fail it with 503 right away
+    Reject,
+}
+
+impl CapOverflow {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CapOverflow::Reroute => "reroute",
+            CapOverflow::Queue(_) => "queue",
+            CapOverflow::Reject => "reject",
+        }
+    }
+}
+
+/// Fixed cap on the requests in flight to each upstream.
+///
+/// Unlike the adaptive limiter, which probes for what an upstream sustains, a cap is
+/// a hard ceiling set by the operator, e.g. the stream count a backend is provisioned
+/// for, so a single slow backend cannot accumulate unbounded concurrent streams.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConcurrencyCaps {
+    /// Cap of upstreams without their own
+    pub default: Option<usize>,
+    /// Caps by upstream address (`host:port`)
+    pub upstreams: HashMap<String, usize>,
+    pub overflow: CapOverflow,
+}
+
+impl ConcurrencyCaps {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cap every upstream without its own cap at `max_in_flight`
+    pub fn with_default(mut self, max_in_flight: usize) -> Self {
+        assert!(max_in_flight > 0, "Concurrency cap must be positive");
+        self.default = Some(max_in_flight);
+        self
+    }
+
+    /// Cap the upstream at `address` (`host:port`) at `max_in_flight`
+    pub fn with_upstream(mut self, address: impl Into<String>, max_in_flight: usize) -> Self {
+        assert!(max_in_flight > 0, "Concurrency cap must be positive");
+        self.upstreams.insert(address.into(), max_in_flight);
+        self
+    }
+
+    pub fn with_overflow(mut self, overflow: CapOverflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Cap of the upstream at `address`, if capped
+    pub fn cap_for(&self, address: &str) -> Option<usize> {
+        self.upstreams.get(address).copied().or(self.default)
+    }
+}
+
+/// Requests in flight to one upstream, up to its cap
+pub struct ConcurrencyCap {
+    max_in_flight: usize,
+    in_flight: AtomicUsize,
+    /// Notified whenever a slot is released, shared by the caps of every upstream
+    released: Arc<Notify>,
+}
+
+impl ConcurrencyCap {
+    pub fn new(max_in_flight: usize, released: Arc<Notify>) -> Self {
+        Self {
+            max_in_flight,
+            in_flight: AtomicUsize::new(0),
+            released,
+        }
+    }
+
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.in_flight() >= self.max_in_flight
+    }
+
+    /// Reserve a slot, or `None` when the upstream is at its cap
+    pub fn try_acquire(self: &Arc<Self>) -> Option<CapPermit> {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < self.max_in_flight).then_some(current + 1)
+            })
+            .ok()?;
+        Some(CapPermit {
+            cap: Arc::clone(self),
+        })
+    }
+}
+
+impl fmt::Debug for ConcurrencyCap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrencyCap")
+            .field("max_in_flight", &self.max_in_flight)
+            .field("in_flight", &self.in_flight())
+            .finish()
+    }
+}
+
+/// A slot under an upstream's cap, released when dropped
+#[derive(Debug)]
+pub struct CapPermit {
+    cap: Arc<ConcurrencyCap>,
+}
+
+impl Drop for CapPermit {
+    fn drop(&mut self) {
+        self.cap.in_flight.fetch_sub(1, Ordering::AcqRel);
+        self.cap.released.notify_waiters();
+    }
+}
+
+/// Slots a request holds on the upstream it was sent to
+#[derive(Debug, Default)]
+pub struct UpstreamPermit {
+    /// Adaptive concurrency slot, when adaptive limiting is enabled
+    pub limiter: Option<LimiterPermit>,
+    /// Slot under the upstream's cap, when it is capped
+    pub cap: Option<CapPermit>,
+}
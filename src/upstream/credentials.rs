@@ -0,0 +1,317 @@
+use crate::metrics::CREDENTIAL_QUARANTINES;
+use crate::upstream::secrets::{SecretError, SecretRef};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A provider API key the gateway sends to an upstream on behalf of clients.
+pub struct Credential {
+    /// Name used in logs, metrics and alerts; the secret itself is never logged
+    id: String,
+    header: String,
+    value: String,
+    quarantined: AtomicBool,
+}
+
+impl Credential {
+    /// A key sent as `header: value`
+    pub fn new(id: impl Into<String>, header: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            header: header.into().to_ascii_lowercase(),
+            value: value.into(),
+            quarantined: AtomicBool::new(false),
+        }
+    }
+
+    /// A provider key sent in `header`, or as `Authorization: Bearer <key>` without one
+    pub fn api_key(id: impl Into<String>, header: Option<&str>, key: &str) -> Self {
+        match header {
+            Some(header) => Self::new(id, header, key),
+            None => Self::new(id, "authorization", format!("Bearer {}", key)),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Header name and value to send upstream
+    pub fn header(&self) -> (&str, &str) {
+        (&self.header, &self.value)
+    }
+
+    pub fn is_quarantined(&self) -> bool {
+        self.quarantined.load(Ordering::Acquire)
+    }
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credential")
+            .field("id", &self.id)
+            .field("header", &self.header)
+            .field("quarantined", &self.is_quarantined())
+            .finish()
+    }
+}
+
+/// Provider keys for one upstream, used in rotation.
+///
+/// A key the provider rejects with 401/403 is quarantined: it is no longer selected
+/// and requests fail over to the remaining keys. Once every key is quarantined the
+/// upstream is skipped, failing over to the other upstreams.
+#[derive(Debug, Default)]
+pub struct CredentialPool {
+    credentials: Vec<Arc<Credential>>,
+    next: AtomicUsize,
+}
+
+impl CredentialPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a key sent as `header: value`
+    pub fn with_key(
+        mut self,
+        id: impl Into<String>,
+        header: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.credentials
+            .push(Arc::new(Credential::new(id, header, value)));
+        self
+    }
+
+    /// Add a key sent as `Authorization: Bearer <token>`
+    pub fn with_bearer_token(self, id: impl Into<String>, token: impl AsRef<str>) -> Self {
+        let value = format!("Bearer {}", token.as_ref());
+        self.with_key(id, "authorization", value)
+    }
+
+    pub fn credentials(&self) -> &[Arc<Credential>] {
+        &self.credentials
+    }
+
+    /// Next key in rotation that is not quarantined
+    pub fn select(&self) -> Option<Arc<Credential>> {
+        let len = self.credentials.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..len)
+            .map(|offset| &self.credentials[(start + offset) % len])
+            .find(|credential| !credential.is_quarantined())
+            .cloned()
+    }
+
+    /// Number of keys not quarantined
+    pub fn available(&self) -> usize {
+        self.credentials
+            .iter()
+            .filter(|credential| !credential.is_quarantined())
+            .count()
+    }
+
+    /// Whether no key is left to select
+    pub fn is_exhausted(&self) -> bool {
+        self.available() == 0
+    }
+
+    /// Quarantine a key after the provider rejected it. Returns false if it was
+    /// already quarantined, so concurrent rejections alert only once.
+    pub fn quarantine(&self, upstream: &str, credential: &Credential, status: u16) -> bool {
+        if credential.quarantined.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        warn!(
+            "Quarantined credential '{}' for upstream {} after status {}",
+            credential.id, upstream, status
+        );
+        CREDENTIAL_QUARANTINES
+            .with_label_values(&[upstream, &credential.id])
+            .inc();
+        true
+    }
+
+    /// Put a quarantined key back into rotation, e.g. after it was rotated upstream.
+    /// Returns false if no quarantined key has this id.
+    pub fn release(&self, id: &str) -> bool {
+        let released = self
+            .credentials
+            .iter()
+            .filter(|credential| credential.id == id)
+            .any(|credential| credential.quarantined.swap(false, Ordering::AcqRel));
+        if released {
+            info!("Released credential '{}' from quarantine", id);
+        }
+        released
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialRouteError {
+    DuplicateName(String),
+    /// The path does not start with `/`
+    InvalidPath(String, String),
+    /// The secret reference does not parse
+    Secret(String, SecretError),
+}
+
+impl fmt::Display for CredentialRouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialRouteError::DuplicateName(name) => {
+                write!(f, "credential route '{}' is declared twice", name)
+            }
+            CredentialRouteError::InvalidPath(name, path) => write!(
+                f,
+                "credential route '{}' has an invalid path '{}'",
+                name, path
+            ),
+            CredentialRouteError::Secret(name, e) => {
+                write!(f, "credential route '{}': {}", name, e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CredentialRouteError {}
+
+/// A credential route in the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CredentialRouteConfig {
+    /// Name used in logs and metrics
+    pub name: String,
+    /// Provider whose requests get the key, e.g. `openai`; any provider when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// Request path, or a prefix ending in `*`; any path when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Where the key is loaded from: `env:NAME`, `file:/path` or `<backend>:<name>`
+    pub secret: String,
+    /// Header the key is sent in, e.g. `x-api-key`; `Authorization: Bearer <key>` by
+    /// default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+}
+
+/// Provider key injected into the requests of a route, loaded from a secret store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialRoute {
+    pub name: String,
+    pub provider: Option<String>,
+    /// Request path, or a prefix ending in `*`
+    pub path: Option<String>,
+    pub secret: SecretRef,
+    pub header: Option<String>,
+}
+
+impl CredentialRoute {
+    pub fn new(name: impl Into<String>, secret: SecretRef) -> Self {
+        Self {
+            name: name.into(),
+            provider: None,
+            path: None,
+            secret,
+            header: None,
+        }
+    }
+
+    pub fn with_provider(mut self, provider: impl Into<String>) -> Self {
+        self.provider = Some(provider.into());
+        self
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_header(mut self, header: impl Into<String>) -> Self {
+        self.header = Some(header.into());
+        self
+    }
+
+    pub fn compile(config: &CredentialRouteConfig) -> Result<Self, CredentialRouteError> {
+        if let Some(path) = config.path.as_ref().filter(|path| !path.starts_with('/')) {
+            return Err(CredentialRouteError::InvalidPath(
+                config.name.clone(),
+                path.clone(),
+            ));
+        }
+        let secret = SecretRef::parse(&config.secret)
+            .map_err(|e| CredentialRouteError::Secret(config.name.clone(), e))?;
+        Ok(Self {
+            name: config.name.clone(),
+            provider: config.provider.clone(),
+            path: config.path.clone(),
+            secret,
+            header: config.header.clone(),
+        })
+    }
+
+    pub fn matches(&self, path: &str, provider: &str) -> bool {
+        let path_matches = match self.path.as_deref() {
+            None => true,
+            Some(pattern) => match pattern.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => pattern == path,
+            },
+        };
+        path_matches && self.provider.as_deref().is_none_or(|p| p == provider)
+    }
+
+    /// The credential sent upstream once the route's secret is loaded
+    pub fn credential(&self, secret: &str) -> Credential {
+        Credential::api_key(
+            format!("route:{}", self.name),
+            self.header.as_deref(),
+            secret,
+        )
+    }
+}
+
+/// Credential routes; the first matching a request applies
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CredentialRoutes {
+    pub routes: Vec<CredentialRoute>,
+}
+
+impl CredentialRoutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_route(mut self, route: CredentialRoute) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Routes declared in the config file
+    pub fn compile(configs: &[CredentialRouteConfig]) -> Result<Self, CredentialRouteError> {
+        let mut seen = HashSet::new();
+        let mut routes = Self::new();
+        for config in configs {
+            if !seen.insert(config.name.as_str()) {
+                return Err(CredentialRouteError::DuplicateName(config.name.clone()));
+            }
+            routes = routes.with_route(CredentialRoute::compile(config)?);
+        }
+        Ok(routes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    pub fn lookup(&self, path: &str, provider: &str) -> Option<&CredentialRoute> {
+        self.routes
+            .iter()
+            .find(|route| route.matches(path, provider))
+    }
+}
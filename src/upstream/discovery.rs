@@ -0,0 +1,432 @@
+use crate::http_client::HttpClient;
+use crate::upstream::Upstream;
+use async_trait::async_trait;
+use log::{debug, warn};
+use pingora::server::ShutdownWatch;
+use pingora::services::background::BackgroundService;
+use serde::Deserialize;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fs, io};
+
+#[cfg(feature = "tls")]
+use crate::upstream::TlsConfigError;
+#[cfg(feature = "tls")]
+use crate::upstream::tls::load_certificates;
+
+#[derive(Debug)]
+pub enum DiscoveryError {
+    Io(PathBuf, io::Error),
+    Request(String),
+    Status(u16),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiscoveryError::Io(path, e) => write!(f, "cannot read '{}': {}", path.display(), e),
+            DiscoveryError::Request(e) => write!(f, "request failed: {}", e),
+            DiscoveryError::Status(status) => write!(f, "unexpected status {}", status),
+            DiscoveryError::Json(e) => write!(f, "invalid response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+/// A service registry that knows the current endpoints behind an upstream.
+///
+/// Implement this to follow registries other than Kubernetes and Consul.
+#[async_trait]
+pub trait DiscoverySource: Send + Sync {
+    /// Human-readable description for logs, e.g. `kubernetes default/inference`
+    fn describe(&self) -> String;
+
+    /// Current endpoints. An empty list means the service has no ready endpoints.
+    async fn endpoints(&self) -> Result<Vec<SocketAddr>, DiscoveryError>;
+}
+
+/// Service discovery settings: which upstreams follow which registry, and how often
+/// the registries are polled.
+#[derive(Clone)]
+pub struct DiscoveryConfig {
+    /// How often every source is polled
+    pub refresh_interval: Duration,
+    /// Timeout for a single registry request
+    pub request_timeout: Duration,
+    /// Upstream address and the source its endpoints come from
+    pub sources: Vec<(String, Arc<dyn DiscoverySource>)>,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            refresh_interval: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(5),
+            sources: Vec::new(),
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the endpoints of the upstream declared as `upstream` (its address as passed
+    /// to `GatewayProxy::new`, e.g. `inference.default.svc:8000`) from `source`.
+    pub fn with_source(
+        mut self,
+        upstream: impl Into<String>,
+        source: impl DiscoverySource + 'static,
+    ) -> Self {
+        self.sources.push((upstream.into(), Arc::new(source)));
+        self
+    }
+
+    /// Whether an upstream's endpoints come from a discovery source
+    pub fn discovers(&self, upstream: &str) -> bool {
+        self.sources.iter().any(|(address, _)| address == upstream)
+    }
+}
+
+/// Background service polling the discovery sources and replacing the endpoint set
+/// of each upstream, so the gateway follows pod churn without a restart.
+///
+/// A failed poll, or one returning no endpoints, keeps the previous set; until the
+/// first successful poll the upstream address itself is resolved.
+pub struct DiscoveryService {
+    targets: Vec<(Arc<Upstream>, Arc<dyn DiscoverySource>)>,
+    config: DiscoveryConfig,
+}
+
+impl DiscoveryService {
+    pub fn new(
+        targets: Vec<(Arc<Upstream>, Arc<dyn DiscoverySource>)>,
+        config: DiscoveryConfig,
+    ) -> Self {
+        Self { targets, config }
+    }
+
+    pub async fn refresh_all(&self) {
+        for (upstream, source) in &self.targets {
+            match tokio::time::timeout(self.config.request_timeout, source.endpoints()).await {
+                Ok(Ok(mut endpoints)) => {
+                    endpoints.sort();
+                    endpoints.dedup();
+                    if endpoints.is_empty() {
+                        warn!(
+                            "{} has no ready endpoints, keeping the previous set for {}",
+                            source.describe(),
+                            upstream.address()
+                        );
+                    }
+                    debug!(
+                        "Discovered endpoints for {} from {}: {:?}",
+                        upstream.address(),
+                        source.describe(),
+                        endpoints
+                    );
+                    upstream.set_resolved(endpoints);
+                }
+                Ok(Err(e)) => warn!("Discovery via {} failed: {}", source.describe(), e),
+                Err(_) => warn!("Discovery via {} timed out", source.describe()),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for DiscoveryService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut ticker = tokio::time::interval(self.config.refresh_interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => break,
+                _ = ticker.tick() => self.refresh_all().await,
+            }
+        }
+    }
+}
+
+/// Ready endpoints of a Kubernetes Service, read from its EndpointSlices
+/// (`discovery.k8s.io/v1`). Defaults to the in-cluster API server with the pod's
+/// service account token (see [`KubernetesSource::in_cluster`] to also trust the
+/// cluster CA); the account needs `list` on `endpointslices` in the namespace.
+pub struct KubernetesSource {
+    api_server: Upstream,
+    client: HttpClient,
+    namespace: String,
+    service: String,
+    port_name: Option<String>,
+    token_path: Option<PathBuf>,
+}
+
+impl KubernetesSource {
+    const SERVICE_ACCOUNT_DIR: &'static str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+    pub fn new(namespace: impl Into<String>, service: impl Into<String>) -> Self {
+        Self {
+            api_server: Upstream::new("https://kubernetes.default.svc"),
+            client: HttpClient::new(),
+            namespace: namespace.into(),
+            service: service.into(),
+            port_name: None,
+            token_path: Some(PathBuf::from(Self::SERVICE_ACCOUNT_DIR).join("token")),
+        }
+    }
+
+    /// In-cluster access verifying the API server with the service account's CA bundle
+    #[cfg(feature = "tls")]
+    pub fn in_cluster(
+        namespace: impl Into<String>,
+        service: impl Into<String>,
+    ) -> Result<Self, TlsConfigError> {
+        Self::new(namespace, service)
+            .with_ca_file(PathBuf::from(Self::SERVICE_ACCOUNT_DIR).join("ca.crt"))
+    }
+
+    /// API server base URL, e.g. `http://127.0.0.1:8001` for `kubectl proxy`
+    pub fn with_api_server(mut self, url: &str) -> Self {
+        self.api_server = Upstream::new(url);
+        self
+    }
+
+    /// Use the named Service port; by default the first port of each slice is used
+    pub fn with_port_name(mut self, name: impl Into<String>) -> Self {
+        self.port_name = Some(name.into());
+        self
+    }
+
+    /// Bearer token file, re-read on every poll so rotated tokens are picked up.
+    /// `None` sends no token (e.g. behind `kubectl proxy`).
+    pub fn with_token_file(mut self, path: Option<PathBuf>) -> Self {
+        self.token_path = path;
+        self
+    }
+
+    /// Verify the API server against this PEM CA bundle, e.g. the service account's
+    /// `ca.crt`
+    #[cfg(feature = "tls")]
+    pub fn with_ca_file(
+        mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Self, TlsConfigError> {
+        self.client.set_ca(load_certificates(path.as_ref())?);
+        Ok(self)
+    }
+
+    /// Ready endpoint addresses from an `EndpointSliceList` response body
+    pub fn parse_endpoints(&self, body: &[u8]) -> Result<Vec<SocketAddr>, DiscoveryError> {
+        let list: EndpointSliceList = serde_json::from_slice(body).map_err(DiscoveryError::Json)?;
+        let mut endpoints = Vec::new();
+        for slice in list.items {
+            let port = match &self.port_name {
+                Some(name) => slice.ports.iter().find(|p| p.name.as_ref() == Some(name)),
+                None => slice.ports.first(),
+            };
+            let Some(port) = port.and_then(|p| p.port) else {
+                continue;
+            };
+            for endpoint in slice.endpoints {
+                // `ready` is optional and means ready when absent
+                if endpoint.conditions.ready == Some(false) {
+                    continue;
+                }
+                endpoints.extend(
+                    endpoint
+                        .addresses
+                        .iter()
+                        .filter_map(|address| address.parse::<IpAddr>().ok())
+                        .map(|ip| SocketAddr::new(ip, port)),
+                );
+            }
+        }
+        Ok(endpoints)
+    }
+}
+
+#[async_trait]
+impl DiscoverySource for KubernetesSource {
+    fn describe(&self) -> String {
+        format!("kubernetes {}/{}", self.namespace, self.service)
+    }
+
+    async fn endpoints(&self) -> Result<Vec<SocketAddr>, DiscoveryError> {
+        let path = format!(
+            "/apis/discovery.k8s.io/v1/namespaces/{}/endpointslices?labelSelector=kubernetes.io%2Fservice-name%3D{}",
+            self.namespace, self.service
+        );
+        let mut headers = Vec::new();
+        if let Some(token_path) = &self.token_path {
+            let token = fs::read_to_string(token_path)
+                .map_err(|e| DiscoveryError::Io(token_path.clone(), e))?;
+            headers.push(("authorization", format!("Bearer {}", token.trim())));
+        }
+        let body = get_json(&self.client, &self.api_server, &path, headers).await?;
+        self.parse_endpoints(&body)
+    }
+}
+
+/// Passing instances of a Consul service, from the health endpoint of the catalog
+pub struct ConsulSource {
+    agent: Upstream,
+    client: HttpClient,
+    service: String,
+    datacenter: Option<String>,
+    tag: Option<String>,
+    token: Option<String>,
+}
+
+impl ConsulSource {
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            agent: Upstream::new("http://127.0.0.1:8500"),
+            client: HttpClient::new(),
+            service: service.into(),
+            datacenter: None,
+            tag: None,
+            token: None,
+        }
+    }
+
+    /// Consul agent base URL (default `http://127.0.0.1:8500`)
+    pub fn with_address(mut self, url: &str) -> Self {
+        self.agent = Upstream::new(url);
+        self
+    }
+
+    pub fn with_datacenter(mut self, datacenter: impl Into<String>) -> Self {
+        self.datacenter = Some(datacenter.into());
+        self
+    }
+
+    /// Only use instances carrying this tag
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// ACL token sent as `X-Consul-Token`
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Instance addresses from a `/v1/health/service` response body. The service
+    /// address is used when set, otherwise the node address.
+    pub fn parse_endpoints(&self, body: &[u8]) -> Result<Vec<SocketAddr>, DiscoveryError> {
+        let entries: Vec<ConsulEntry> =
+            serde_json::from_slice(body).map_err(DiscoveryError::Json)?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|entry| {
+                let address = Some(entry.service.address)
+                    .filter(|address| !address.is_empty())
+                    .unwrap_or(entry.node.address);
+                let ip = address.parse::<IpAddr>().ok()?;
+                Some(SocketAddr::new(ip, entry.service.port))
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl DiscoverySource for ConsulSource {
+    fn describe(&self) -> String {
+        format!("consul {}", self.service)
+    }
+
+    async fn endpoints(&self) -> Result<Vec<SocketAddr>, DiscoveryError> {
+        let mut path = format!("/v1/health/service/{}?passing=true", self.service);
+        if let Some(datacenter) = &self.datacenter {
+            path.push_str(&format!("&dc={}", datacenter));
+        }
+        if let Some(tag) = &self.tag {
+            path.push_str(&format!("&tag={}", tag));
+        }
+        let mut headers = Vec::new();
+        if let Some(token) = &self.token {
+            headers.push(("x-consul-token", token.clone()));
+        }
+        let body = get_json(&self.client, &self.agent, &path, headers).await?;
+        self.parse_endpoints(&body)
+    }
+}
+
+/// GET a registry endpoint expecting a 200 JSON response
+async fn get_json(
+    client: &HttpClient,
+    server: &Upstream,
+    path: &str,
+    mut headers: Vec<(&'static str, String)>,
+) -> Result<Vec<u8>, DiscoveryError> {
+    headers.push(("accept", "application/json".to_string()));
+    let (status, body) = client
+        .request(server, "GET", path, &headers, None)
+        .await
+        .map_err(DiscoveryError::Request)?;
+    if status != 200 {
+        return Err(DiscoveryError::Status(status));
+    }
+    Ok(body)
+}
+
+#[derive(Deserialize)]
+struct EndpointSliceList {
+    #[serde(default)]
+    items: Vec<EndpointSlice>,
+}
+
+#[derive(Deserialize)]
+struct EndpointSlice {
+    #[serde(default)]
+    endpoints: Vec<Endpoint>,
+    #[serde(default)]
+    ports: Vec<EndpointPort>,
+}
+
+#[derive(Deserialize)]
+struct Endpoint {
+    #[serde(default)]
+    addresses: Vec<String>,
+    #[serde(default)]
+    conditions: EndpointConditions,
+}
+
+#[derive(Default, Deserialize)]
+struct EndpointConditions {
+    ready: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct EndpointPort {
+    name: Option<String>,
+    port: Option<u16>,
+}
+
+#[derive(Deserialize)]
+struct ConsulEntry {
+    #[serde(rename = "Node")]
+    node: ConsulNode,
+    #[serde(rename = "Service")]
+    service: ConsulService,
+}
+
+#[derive(Deserialize)]
+struct ConsulNode {
+    #[serde(rename = "Address")]
+    address: String,
+}
+
+#[derive(Deserialize)]
+struct ConsulService {
+    #[serde(rename = "Address", default)]
+    address: String,
+    #[serde(rename = "Port")]
+    port: u16,
+}
@@ -0,0 +1,72 @@
+use crate::upstream::Upstream;
+use async_trait::async_trait;
+use log::{debug, warn};
+use pingora::server::ShutdownWatch;
+use pingora::services::background::BackgroundService;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Periodic re-resolution of hostname upstreams.
+#[derive(Debug, Clone)]
+pub struct DnsConfig {
+    /// How often hostnames are re-resolved. The system resolver does not expose record
+    /// TTLs, so this acts as the TTL for resolved addresses.
+    pub refresh_interval: Duration,
+    /// Timeout for resolving a single hostname
+    pub resolve_timeout: Duration,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            refresh_interval: Duration::from_secs(30),
+            resolve_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Background service resolving hostname upstreams asynchronously, so autoscaling
+/// behind DNS (e.g. an internal load balancer) is picked up without a restart.
+///
+/// A failed lookup keeps the previously resolved addresses.
+pub struct DnsRefreshService {
+    upstreams: Vec<Arc<Upstream>>,
+    config: DnsConfig,
+}
+
+impl DnsRefreshService {
+    pub fn new(upstreams: Vec<Arc<Upstream>>, config: DnsConfig) -> Self {
+        Self { upstreams, config }
+    }
+
+    pub async fn refresh_all(&self) {
+        for upstream in &self.upstreams {
+            let lookup = tokio::net::lookup_host(upstream.address());
+            match tokio::time::timeout(self.config.resolve_timeout, lookup).await {
+                Ok(Ok(addrs)) => {
+                    let mut addrs: Vec<_> = addrs.collect();
+                    addrs.sort();
+                    addrs.dedup();
+                    debug!("Resolved upstream {} to {:?}", upstream.address(), addrs);
+                    upstream.set_resolved(addrs);
+                }
+                Ok(Err(e)) => warn!("Failed to resolve upstream {}: {}", upstream.address(), e),
+                Err(_) => warn!("Resolving upstream {} timed out", upstream.address()),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for DnsRefreshService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        // The first tick fires immediately, resolving every hostname at startup
+        let mut ticker = tokio::time::interval(self.config.refresh_interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => break,
+                _ = ticker.tick() => self.refresh_all().await,
+            }
+        }
+    }
+}
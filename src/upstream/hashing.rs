@@ -0,0 +1,64 @@
+use crate::pipeline::views::RequestView;
+
+/// Request attribute used as the consistent-hash key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashKey {
+    /// Client credential: `Authorization`, falling back to `x-api-key`
+    ApiKey,
+    /// Value of an arbitrary request header (lowercase name)
+    Header(String),
+    /// Model identifier taken from the request path (e.g. Bedrock `/model/{id}/`)
+    Model,
+}
+
+impl HashKey {
+    /// Extract the key value from a request. Returns None when the attribute is absent,
+    /// in which case the caller falls back to its default strategy.
+    pub fn extract<'v>(&self, request_view: &'v RequestView) -> Option<&'v str> {
+        match self {
+            HashKey::ApiKey => request_view
+                .authorization()
+                .or_else(|| request_view.header("x-api-key")),
+            HashKey::Header(name) => request_view.header(name),
+            HashKey::Model => request_view.path_model(),
+        }
+    }
+}
+
+/// Rank upstreams for a key using rendezvous (highest random weight) hashing.
+///
+/// Every upstream gets a score derived from `hash(key, upstream)`; the highest score wins.
+/// Adding or removing an upstream only moves the keys that scored highest on it, and the
+/// full ranking provides a stable fallback order when the preferred upstream is unavailable.
+pub fn rendezvous_rank<S: AsRef<str>>(key: &str, upstreams: &[S]) -> Vec<usize> {
+    let key_hash = fnv1a(FNV_OFFSET, key.as_bytes());
+    let mut scored: Vec<(u64, usize)> = upstreams
+        .iter()
+        .enumerate()
+        .map(|(index, upstream)| (mix(fnv1a(key_hash, upstream.as_ref().as_bytes())), index))
+        .collect();
+
+    // Highest score first; index breaks ties deterministically
+    scored.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored.into_iter().map(|(_, index)| index).collect()
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// FNV-1a, chosen over `DefaultHasher` because it is stable across Rust releases,
+/// so every gateway replica maps a key to the same upstream.
+fn fnv1a(seed: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(seed, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// splitmix64 finalizer to spread FNV output across the full range
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
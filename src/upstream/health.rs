@@ -0,0 +1,156 @@
+use crate::metrics::UPSTREAM_EJECTIONS;
+use log::{info, warn};
+use prometheus::IntCounter;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Passive health checking: upstreams are ejected based on the outcome of real traffic.
+#[derive(Debug, Clone)]
+pub struct OutlierConfig {
+    /// Eject after this many failures in a row
+    pub consecutive_failures: u32,
+    /// Eject when the failure rate within `interval` reaches this fraction
+    pub failure_rate: f64,
+    /// Minimum requests within `interval` before the failure rate is considered
+    pub min_requests: u32,
+    /// Length of the window the failure rate is computed over
+    pub interval: Duration,
+    /// How long an ejected upstream is kept out of the rotation before it is probed
+    pub cooldown: Duration,
+}
+
+impl Default for OutlierConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: 5,
+            failure_rate: 0.5,
+            min_requests: 20,
+            interval: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    consecutive_failures: u32,
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+    /// Set while the upstream is ejected
+    ejected_until: Option<Instant>,
+    /// A single probe request is let through once the cooldown has passed
+    probe_in_flight: bool,
+}
+
+/// Tracks failures (connection errors and 5xx responses) for a single upstream and
+/// ejects it from the rotation for a cooldown period.
+///
+/// After the cooldown one probe request is admitted at a time: a success re-admits the
+/// upstream, a failure ejects it for another cooldown.
+#[derive(Debug)]
+pub struct OutlierDetector {
+    upstream: String,
+    config: OutlierConfig,
+    state: Mutex<State>,
+    ejections: IntCounter,
+}
+
+impl OutlierDetector {
+    pub fn new(upstream: &str, config: OutlierConfig) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            config,
+            state: Mutex::new(State {
+                consecutive_failures: 0,
+                window_start: Instant::now(),
+                requests: 0,
+                failures: 0,
+                ejected_until: None,
+                probe_in_flight: false,
+            }),
+            ejections: UPSTREAM_EJECTIONS.with_label_values(&[upstream]),
+        }
+    }
+
+    /// Whether the upstream is currently out of the rotation
+    pub fn is_ejected(&self) -> bool {
+        self.state.lock().unwrap().ejected_until.is_some()
+    }
+
+    /// Whether a request may be routed to this upstream. Once the cooldown has passed
+    /// this claims the probe slot, which is released by the next `record`.
+    pub fn try_admit(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.ejected_until {
+            None => true,
+            Some(until) if Instant::now() >= until && !state.probe_in_flight => {
+                state.probe_in_flight = true;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    /// Record the outcome of a request routed to this upstream
+    pub fn record(&self, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.ejected_until.is_some() {
+            if !state.probe_in_flight {
+                // Request admitted before the ejection; its outcome is already accounted for
+                return;
+            }
+            state.probe_in_flight = false;
+            if failed {
+                self.eject(&mut state, "probe failed");
+            } else {
+                info!(
+                    "Upstream {} re-admitted after successful probe",
+                    self.upstream
+                );
+                state.ejected_until = None;
+                state.consecutive_failures = 0;
+                state.window_start = Instant::now();
+                state.requests = 0;
+                state.failures = 0;
+            }
+            return;
+        }
+
+        if state.window_start.elapsed() >= self.config.interval {
+            state.window_start = Instant::now();
+            state.requests = 0;
+            state.failures = 0;
+        }
+        state.requests += 1;
+        if failed {
+            state.failures += 1;
+            state.consecutive_failures += 1;
+        } else {
+            state.consecutive_failures = 0;
+        }
+
+        if state.consecutive_failures >= self.config.consecutive_failures {
+            self.eject(&mut state, "consecutive failures");
+        } else if state.requests >= self.config.min_requests
+            && f64::from(state.failures) / f64::from(state.requests) >= self.config.failure_rate
+        {
+            self.eject(&mut state, "failure rate");
+        }
+    }
+
+    /// Number of times this upstream has been ejected
+    pub fn ejections(&self) -> u64 {
+        self.ejections.get()
+    }
+
+    fn eject(&self, state: &mut State, reason: &str) {
+        warn!(
+            "Ejecting upstream {} for {:?}: {}",
+            self.upstream, self.config.cooldown, reason
+        );
+        state.ejected_until = Some(Instant::now() + self.config.cooldown);
+        self.ejections.inc();
+    }
+}
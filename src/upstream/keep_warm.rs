@@ -0,0 +1,87 @@
+use crate::upstream::warmth::{WarmthConfig, WarmthTracker};
+use async_trait::async_trait;
+use log::warn;
+use pingora::server::ShutdownWatch;
+use pingora::services::background::BackgroundService;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Background service that pings every upstream periodically so scale-to-zero
+/// backends stay warm. A successful ping counts as activity.
+pub struct KeepWarmService {
+    trackers: Vec<Arc<WarmthTracker>>,
+    interval: Duration,
+    path: String,
+    timeout: Duration,
+}
+
+impl KeepWarmService {
+    pub fn new(
+        trackers: Vec<Arc<WarmthTracker>>,
+        interval: Duration,
+        config: &WarmthConfig,
+    ) -> Self {
+        Self {
+            trackers,
+            interval,
+            path: config.keep_warm_path.clone(),
+            timeout: config.keep_warm_timeout,
+        }
+    }
+
+    async fn ping_all(&self) {
+        for tracker in &self.trackers {
+            match tokio::time::timeout(self.timeout, ping(tracker.upstream(), &self.path)).await {
+                Ok(Ok(status)) if status < 500 => tracker.mark_active(),
+                Ok(Ok(status)) => warn!(
+                    "Keep-warm ping to {} returned status {}",
+                    tracker.upstream(),
+                    status
+                ),
+                Ok(Err(e)) => warn!("Keep-warm ping to {} failed: {}", tracker.upstream(), e),
+                Err(_) => warn!("Keep-warm ping to {} timed out", tracker.upstream()),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for KeepWarmService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => break,
+                _ = ticker.tick() => self.ping_all().await,
+            }
+        }
+    }
+}
+
+/// Send a minimal HTTP/1.1 GET and return the response status code
+async fn ping(upstream: &str, path: &str) -> io::Result<u16> {
+    let mut stream = TcpStream::connect(upstream).await?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: langspec-gateway\r\nConnection: close\r\n\r\n",
+        path, upstream
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut buf = [0u8; 32];
+    let read = stream.read(&mut buf).await?;
+    parse_status_line(&buf[..read])
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed status line"))
+}
+
+/// Parse the status code out of `HTTP/1.x NNN ...`
+fn parse_status_line(bytes: &[u8]) -> Option<u16> {
+    let line = std::str::from_utf8(bytes).ok()?;
+    let mut parts = line.split_whitespace();
+    parts
+        .next()
+        .filter(|version| version.starts_with("HTTP/"))?;
+    parts.next()?.parse().ok()
+}
@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Exponentially weighted moving average of upstream latency.
+///
+/// Stored as f64 bits in an atomic so the `logging` phase can update it without locking.
+/// An upstream that has never been observed reports zero latency, which makes latency-aware
+/// balancers try it early instead of starving it.
+#[derive(Debug)]
+pub struct LatencyEwma {
+    /// Weight of the newest sample (0.0 - 1.0)
+    alpha: f64,
+    micros: AtomicU64,
+}
+
+impl LatencyEwma {
+    pub fn new(alpha: f64) -> Self {
+        Self {
+            alpha: alpha.clamp(0.0, 1.0),
+            micros: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    /// Fold a latency sample into the average
+    pub fn observe(&self, latency: Duration) {
+        let sample = latency.as_micros() as f64;
+        let _ = self
+            .micros
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |bits| {
+                let current = f64::from_bits(bits);
+                let next = if current == 0.0 {
+                    sample
+                } else {
+                    current + self.alpha * (sample - current)
+                };
+                Some(next.to_bits())
+            });
+    }
+
+    /// Current average latency
+    pub fn get(&self) -> Duration {
+        Duration::from_micros(f64::from_bits(self.micros.load(Ordering::Acquire)) as u64)
+    }
+}
+
+impl Default for LatencyEwma {
+    fn default() -> Self {
+        Self::new(0.3)
+    }
+}
@@ -0,0 +1,202 @@
+use crate::metrics::UPSTREAM_CONCURRENCY_LIMIT;
+use prometheus::IntGauge;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Tuning knobs for the AIMD adaptive concurrency limiter.
+#[derive(Debug, Clone)]
+pub struct AimdConfig {
+    /// Limit used before any latency samples have been observed
+    pub initial_limit: usize,
+    /// The limit never drops below this value
+    pub min_limit: usize,
+    /// The limit never grows beyond this value
+    pub max_limit: usize,
+    /// Multiplicative decrease factor applied on overload (0.0 - 1.0)
+    pub backoff_ratio: f64,
+    /// How far latency may rise above the no-load baseline before it is
+    /// treated as queueing (e.g. 2.0 = twice the baseline)
+    pub latency_tolerance: f64,
+}
+
+impl Default for AimdConfig {
+    fn default() -> Self {
+        Self {
+            initial_limit: 20,
+            min_limit: 1,
+            max_limit: 1000,
+            backoff_ratio: 0.9,
+            latency_tolerance: 2.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimitState {
+    limit: f64,
+    /// Approximation of the no-load latency: tracks the minimum sample and
+    /// drifts slowly upward so capacity changes are eventually picked up
+    baseline: Option<Duration>,
+}
+
+/// Learned state of an [`AdaptiveLimiter`], persisted across restarts so a restarted
+/// gateway does not have to probe every upstream's capacity from scratch.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LimiterState {
+    pub limit: f64,
+    /// No-load latency baseline in microseconds
+    pub baseline_us: Option<u64>,
+}
+
+/// Adaptive concurrency limiter for a single upstream.
+///
+/// Probes for the sustainable concurrency of an upstream using AIMD:
+/// - Additive increase: +1 when a sample stays within tolerance of the baseline
+///   latency while the limiter is actually being used (in-flight >= limit / 2)
+/// - Multiplicative decrease: `limit * backoff_ratio` on overload signals
+///   (5xx/429, connection failures) or when the latency gradient exceeds tolerance
+///
+/// The current limit is exported as the `langspec_upstream_concurrency_limit` gauge.
+pub struct AdaptiveLimiter {
+    config: AimdConfig,
+    in_flight: AtomicUsize,
+    state: Mutex<LimitState>,
+    gauge: IntGauge,
+}
+
+impl AdaptiveLimiter {
+    pub fn new(upstream: &str, config: AimdConfig) -> Self {
+        let initial = config
+            .initial_limit
+            .clamp(config.min_limit, config.max_limit);
+        let gauge = UPSTREAM_CONCURRENCY_LIMIT.with_label_values(&[upstream]);
+        gauge.set(initial as i64);
+
+        Self {
+            config,
+            in_flight: AtomicUsize::new(0),
+            state: Mutex::new(LimitState {
+                limit: initial as f64,
+                baseline: None,
+            }),
+            gauge,
+        }
+    }
+
+    /// Current concurrency limit
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit as usize
+    }
+
+    /// Number of requests currently holding a permit
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Try to reserve a concurrency slot. Returns None when the upstream is at its limit.
+    pub fn try_acquire(self: &Arc<Self>) -> Option<LimiterPermit> {
+        let limit = self.limit();
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < limit).then_some(current + 1)
+            })
+            .ok()?;
+
+        Some(LimiterPermit {
+            limiter: Arc::clone(self),
+            start: Instant::now(),
+            observed: false,
+        })
+    }
+
+    /// Feed a latency sample into the limit calculation.
+    pub fn on_sample(&self, latency: Duration, overloaded: bool) {
+        let in_flight = self.in_flight();
+        let mut state = self.state.lock().unwrap();
+
+        let baseline = match state.baseline {
+            Some(baseline) if latency < baseline => latency,
+            // Drift 1% toward the sample so the baseline can recover after capacity changes
+            Some(baseline) => baseline + (latency - baseline) / 100,
+            None => latency,
+        };
+        state.baseline = Some(baseline);
+
+        let gradient_exceeded =
+            latency.as_secs_f64() > baseline.as_secs_f64() * self.config.latency_tolerance;
+
+        if overloaded || gradient_exceeded {
+            state.limit *= self.config.backoff_ratio;
+        } else if in_flight * 2 >= state.limit as usize {
+            state.limit += 1.0;
+        }
+
+        state.limit = state
+            .limit
+            .clamp(self.config.min_limit as f64, self.config.max_limit as f64);
+        self.gauge.set(state.limit as i64);
+    }
+
+    /// Current learned limit and baseline
+    pub fn state(&self) -> LimiterState {
+        let state = self.state.lock().unwrap();
+        LimiterState {
+            limit: state.limit,
+            baseline_us: state
+                .baseline
+                .map(|baseline| baseline.as_micros().min(u64::MAX as u128) as u64),
+        }
+    }
+
+    /// Restore previously learned state, clamped to the configured bounds
+    pub fn restore(&self, restored: &LimiterState) {
+        let mut state = self.state.lock().unwrap();
+        state.limit = restored
+            .limit
+            .clamp(self.config.min_limit as f64, self.config.max_limit as f64);
+        state.baseline = restored.baseline_us.map(Duration::from_micros);
+        self.gauge.set(state.limit as i64);
+    }
+}
+
+impl fmt::Debug for AdaptiveLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdaptiveLimiter")
+            .field("limit", &self.limit())
+            .field("in_flight", &self.in_flight())
+            .finish()
+    }
+}
+
+/// A reserved concurrency slot. The slot is released when the permit is dropped.
+#[derive(Debug)]
+pub struct LimiterPermit {
+    limiter: Arc<AdaptiveLimiter>,
+    start: Instant,
+    observed: bool,
+}
+
+impl LimiterPermit {
+    /// Record the latency since acquisition as a sample. Only the first call counts,
+    /// so the sample can be taken at time-to-first-byte while the slot stays held
+    /// for the rest of a streamed response.
+    pub fn observe(&mut self, overloaded: bool) {
+        if !self.observed {
+            self.observed = true;
+            self.limiter.on_sample(self.start.elapsed(), overloaded);
+        }
+    }
+
+    pub fn is_observed(&self) -> bool {
+        self.observed
+    }
+}
+
+impl Drop for LimiterPermit {
+    fn drop(&mut self) {
+        self.limiter.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
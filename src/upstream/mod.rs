@@ -0,0 +1,363 @@
+use serde::Serialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+
+pub mod admission;
+pub mod balancer;
+pub mod concurrency;
+pub mod credentials;
+#[cfg(feature = "discovery")]
+pub mod discovery;
+#[cfg(feature = "proxy")]
+pub mod dns;
+#[cfg(feature = "egress")]
+pub mod egress;
+pub mod hashing;
+pub mod health;
+#[cfg(feature = "proxy")]
+pub mod keep_warm;
+pub mod latency;
+pub mod limiter;
+pub mod pools;
+pub mod preflight;
+pub mod retry;
+pub mod secrets;
+pub mod slow_start;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod warmth;
+
+pub use admission::{AdmissionConfig, AdmissionQueue, QueuePlace};
+pub use balancer::{ConsistentHashBalancer, LoadBalancer, PowerOfTwoChoices, RoundRobin};
+pub use concurrency::{CapOverflow, CapPermit, ConcurrencyCap, ConcurrencyCaps, UpstreamPermit};
+pub use credentials::{
+    Credential, CredentialPool, CredentialRoute, CredentialRouteConfig, CredentialRouteError,
+    CredentialRoutes,
+};
+#[cfg(feature = "discovery")]
+pub use discovery::{
+    ConsulSource, DiscoveryConfig, DiscoveryError, DiscoveryService, DiscoverySource,
+    KubernetesSource,
+};
+#[cfg(feature = "proxy")]
+pub use dns::{DnsConfig, DnsRefreshService};
+#[cfg(feature = "egress")]
+pub use egress::{BypassRule, EgressConfig, EgressError, EgressProtocol, EgressProxy, EgressRelay};
+pub use hashing::HashKey;
+pub use health::{OutlierConfig, OutlierDetector};
+#[cfg(feature = "proxy")]
+pub use keep_warm::KeepWarmService;
+pub use latency::LatencyEwma;
+pub use limiter::{AdaptiveLimiter, AimdConfig, LimiterPermit, LimiterState};
+pub use pools::{
+    CanaryConfig, CanarySplit, PoolArm, PoolError, PoolHttp2, PoolHttp2Config, PoolRoute,
+    PoolTimeouts, PoolTimeoutsConfig, UpstreamPool, UpstreamPoolConfig, UpstreamPools,
+};
+pub use preflight::{PreflightCheck, PreflightConfig, PreflightStatus};
+pub use retry::{RetryError, RetryPolicy};
+pub use secrets::{SecretBackend, SecretError, SecretRef, SecretStore};
+pub use slow_start::{SlowStart, SlowStartConfig};
+#[cfg(feature = "tls")]
+pub use tls::{TlsConfigError, TlsConnectionReuse, UpstreamTls};
+pub use warmth::{WarmthConfig, WarmthTracker};
+
+/// A single upstream backend together with the runtime state that balancers and
+/// the proxy phases share about it.
+///
+/// Limiters, trackers and the other optional components are attached while the gateway
+/// is built, through a shared reference: an upstream already handed to the admin API
+/// or a background service gets them too. The first one attached is kept.
+pub struct Upstream {
+    /// `host:port` to connect to
+    address: String,
+    /// Whether the connection uses TLS (declared with `https://`)
+    tls: bool,
+    /// Hostname used for SNI and certificate verification on TLS upstreams
+    sni: String,
+    /// Addresses the hostname last resolved to, refreshed in the background
+    resolved: RwLock<Arc<[SocketAddr]>>,
+    /// Rotation over the resolved addresses
+    next_resolved: AtomicUsize,
+    /// EWMA latency fed from the logging phase
+    latency: LatencyEwma,
+    /// Adaptive concurrency limiter, when enabled
+    limiter: OnceLock<Arc<AdaptiveLimiter>>,
+    /// Fixed cap on requests in flight, when configured
+    cap: OnceLock<Arc<ConcurrencyCap>>,
+    /// Warm/cold tracker, when enabled
+    warmth: OnceLock<Arc<WarmthTracker>>,
+    /// Passive health checking, when enabled
+    health: OnceLock<Arc<OutlierDetector>>,
+    /// Traffic ramp-up after startup and re-admission, when enabled
+    slow_start: OnceLock<Arc<SlowStart>>,
+    /// Provider keys injected by the gateway, when configured
+    credentials: OnceLock<Arc<CredentialPool>>,
+    /// Liveness check before expensive requests, when enabled
+    preflight: OnceLock<Arc<PreflightCheck>>,
+    /// Whether new requests are sent to the upstream; cleared through the admin API to
+    /// drain it
+    enabled: AtomicBool,
+    /// Share of its traffic the upstream keeps, set through the admin API (the bits of
+    /// an `f64` from 0 to 1)
+    weight: AtomicU64,
+}
+
+impl Upstream {
+    /// Create an upstream from `host:port`, `http://host[:port]` or `https://host[:port]`.
+    /// The port defaults to 80 for `http://` and 443 for `https://`.
+    pub fn new(spec: impl Into<String>) -> Self {
+        let spec = spec.into();
+        let (tls, authority, default_port) = if let Some(rest) = spec.strip_prefix("https://") {
+            (true, rest, Some(443))
+        } else if let Some(rest) = spec.strip_prefix("http://") {
+            (false, rest, Some(80))
+        } else {
+            (false, spec.as_str(), None)
+        };
+        let authority = authority.trim_end_matches('/');
+        let (host, port) = split_host_port(authority);
+        let address = match (port, default_port) {
+            (None, Some(default_port)) => format!("{}:{}", authority, default_port),
+            _ => authority.to_string(),
+        };
+        let sni = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+
+        Self {
+            address,
+            tls,
+            sni,
+            resolved: RwLock::new(Arc::from(Vec::new())),
+            next_resolved: AtomicUsize::new(0),
+            latency: LatencyEwma::default(),
+            limiter: OnceLock::new(),
+            cap: OnceLock::new(),
+            warmth: OnceLock::new(),
+            health: OnceLock::new(),
+            slow_start: OnceLock::new(),
+            credentials: OnceLock::new(),
+            preflight: OnceLock::new(),
+            enabled: AtomicBool::new(true),
+            weight: AtomicU64::new(1.0f64.to_bits()),
+        }
+    }
+
+    /// `host:port` of the upstream
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Whether connections to this upstream use TLS
+    pub fn tls(&self) -> bool {
+        self.tls
+    }
+
+    /// Hostname for SNI and the upstream `Host` header
+    pub fn sni(&self) -> &str {
+        &self.sni
+    }
+
+    /// Whether the address is a hostname that needs DNS resolution
+    pub fn needs_resolution(&self) -> bool {
+        self.address.parse::<SocketAddr>().is_err()
+    }
+
+    /// Replace the resolved addresses (an empty list keeps the previous ones)
+    pub fn set_resolved(&self, addrs: Vec<SocketAddr>) {
+        if !addrs.is_empty() {
+            *self.resolved.write().unwrap() = Arc::from(addrs);
+        }
+    }
+
+    /// Addresses the hostname currently resolves to
+    pub fn resolved(&self) -> Arc<[SocketAddr]> {
+        Arc::clone(&self.resolved.read().unwrap())
+    }
+
+    /// Next resolved address in rotation, so traffic spreads over every IP behind the
+    /// name. None until the first resolution.
+    pub fn next_resolved_addr(&self) -> Option<SocketAddr> {
+        let resolved = self.resolved();
+        if resolved.is_empty() {
+            return None;
+        }
+        let index = self.next_resolved.fetch_add(1, Ordering::Relaxed) % resolved.len();
+        Some(resolved[index])
+    }
+
+    /// Current EWMA latency (zero until the first sample)
+    pub fn latency(&self) -> Duration {
+        self.latency.get()
+    }
+
+    /// Feed a latency sample into the EWMA
+    pub fn observe_latency(&self, latency: Duration) {
+        self.latency.observe(latency);
+    }
+
+    pub fn limiter(&self) -> Option<&Arc<AdaptiveLimiter>> {
+        self.limiter.get()
+    }
+
+    pub fn set_limiter(&self, limiter: Arc<AdaptiveLimiter>) {
+        let _ = self.limiter.set(limiter);
+    }
+
+    pub fn cap(&self) -> Option<&Arc<ConcurrencyCap>> {
+        self.cap.get()
+    }
+
+    pub fn set_cap(&self, cap: Arc<ConcurrencyCap>) {
+        let _ = self.cap.set(cap);
+    }
+
+    pub fn warmth(&self) -> Option<&Arc<WarmthTracker>> {
+        self.warmth.get()
+    }
+
+    pub fn set_warmth(&self, warmth: Arc<WarmthTracker>) {
+        let _ = self.warmth.set(warmth);
+    }
+
+    pub fn health(&self) -> Option<&Arc<OutlierDetector>> {
+        self.health.get()
+    }
+
+    pub fn set_health(&self, health: Arc<OutlierDetector>) {
+        let _ = self.health.set(health);
+    }
+
+    pub fn slow_start(&self) -> Option<&Arc<SlowStart>> {
+        self.slow_start.get()
+    }
+
+    pub fn set_slow_start(&self, slow_start: Arc<SlowStart>) {
+        let _ = self.slow_start.set(slow_start);
+    }
+
+    pub fn credentials(&self) -> Option<&Arc<CredentialPool>> {
+        self.credentials.get()
+    }
+
+    pub fn set_credentials(&self, credentials: Arc<CredentialPool>) {
+        let _ = self.credentials.set(credentials);
+    }
+
+    pub fn preflight(&self) -> Option<&Arc<PreflightCheck>> {
+        self.preflight.get()
+    }
+
+    pub fn set_preflight(&self, preflight: Arc<PreflightCheck>) {
+        let _ = self.preflight.set(preflight);
+    }
+
+    /// Whether the upstream is warm. Upstreams without warmth tracking count as warm.
+    pub fn is_warm(&self) -> bool {
+        self.warmth.get().is_none_or(|tracker| tracker.is_warm())
+    }
+
+    /// Whether new requests are sent to the upstream
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Stop sending new requests to the upstream, letting those in flight complete, or
+    /// send them to it again
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Share of the requests the balancing strategy puts first on the upstream that it
+    /// keeps, from 0 to 1 (the default); the others try it last
+    pub fn weight(&self) -> f64 {
+        f64::from_bits(self.weight.load(Ordering::Relaxed))
+    }
+
+    /// Set the weight, clamped to 0..=1
+    pub fn set_weight(&self, weight: f64) {
+        let weight = if weight.is_nan() {
+            1.0
+        } else {
+            weight.clamp(0.0, 1.0)
+        };
+        self.weight.store(weight.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Share of its traffic the upstream takes: its weight, scaled down while it ramps
+    /// up after slow start
+    pub fn traffic_weight(&self) -> f64 {
+        self.weight() * self.slow_start.get().map_or(1.0, |ramp| ramp.weight())
+    }
+
+    /// What the admin API shows of the upstream
+    pub fn status(&self) -> UpstreamStatus {
+        UpstreamStatus {
+            address: self.address.clone(),
+            tls: self.tls,
+            enabled: self.is_enabled(),
+            weight: self.weight(),
+            traffic_weight: self.traffic_weight(),
+            ejected: self.health.get().is_some_and(|health| health.is_ejected()),
+            ejections: self.health.get().map_or(0, |health| health.ejections()),
+            latency_ms: self.latency().as_secs_f64() * 1000.0,
+            concurrency_limit: self.limiter.get().map(|limiter| limiter.limit()),
+            in_flight: self.limiter.get().map(|limiter| limiter.in_flight()),
+            warm: self.is_warm(),
+        }
+    }
+}
+
+/// State of an upstream, as listed by the admin API
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UpstreamStatus {
+    pub address: String,
+    pub tls: bool,
+    /// Whether new requests are sent to it
+    pub enabled: bool,
+    /// Weight set through the admin API
+    pub weight: f64,
+    /// Weight scaled down by slow start
+    pub traffic_weight: f64,
+    /// Whether passive health checking ejected it
+    pub ejected: bool,
+    /// Times it was ejected
+    pub ejections: u64,
+    /// EWMA latency
+    pub latency_ms: f64,
+    /// Adaptive concurrency limit, when limiting is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency_limit: Option<usize>,
+    /// Requests in flight, when concurrency limiting is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_flight: Option<usize>,
+    pub warm: bool,
+}
+
+/// Split `host:port` (or `[v6]:port`) into host and optional port
+fn split_host_port(authority: &str) -> (&str, Option<&str>) {
+    if authority.starts_with('[') {
+        return match authority.rsplit_once("]:") {
+            Some((host, port)) => (&authority[..host.len() + 1], Some(port)),
+            None => (authority, None),
+        };
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => (host, Some(port)),
+        _ => (authority, None),
+    }
+}
+
+impl fmt::Debug for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upstream")
+            .field("address", &self.address)
+            .field("tls", &self.tls)
+            .field("latency", &self.latency())
+            .finish()
+    }
+}
@@ -0,0 +1,662 @@
+//! Named upstream pools routed by request path.
+//!
+//! A pool is a subset of the gateway's upstreams serving some paths, e.g. an
+//! embeddings cluster for `/v1/embeddings` and a chat cluster for `/v1/chat/*`.
+//! Requests to a pool's paths are only balanced across its upstreams; requests no
+//! pool claims are balanced across all of them.
+//!
+//! A pool can send a share of its requests to a canary pool instead, e.g. 5% to the
+//! upstreams serving a new model deployment. The share can be changed at runtime
+//! (through the admin API) until the pools are replaced by a config reload.
+//!
+//! A pool can also name fallback pools, tried in order when its upstreams answer 429
+//! or 5xx or fail before answering (a timeout, a refused connection). The request is
+//! sent again to the next pool in the chain, translated for its upstreams' dialect.
+//!
+//! Each pool can set its own upstream timeouts, since a completion may legitimately
+//! take minutes while a health endpoint should fail in milliseconds.
+//!
+//! A pool can also speak HTTP/2 to its upstreams, negotiated through ALPN (falling back
+//! to HTTP/1.1 for upstreams that do not offer it) or required, which plaintext upstreams
+//! need (h2c). Requests then share connections, up to a number of streams each.
+//! WebSocket upgrades stay on HTTP/1.1.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PoolError {
+    DuplicateName(String),
+    /// A pool with no upstreams
+    Empty(String),
+    /// The path does not start with `/`
+    InvalidPath(String, String),
+    /// A path claimed by two pools, with the pool claiming it first
+    DuplicatePath(String, String, String),
+    UnknownPool(String),
+    /// A canary pool that is not declared, or is the pool itself
+    InvalidCanary(String, String),
+    /// A pool without a canary pool to split traffic with
+    NoCanary(String),
+    /// A canary share that is not a percentage
+    InvalidPercent(String, f64),
+    /// A fallback pool that is not declared, is the pool itself or is named twice
+    InvalidFallback(String, String),
+    /// A timeout of zero, with the timeout's name
+    InvalidTimeout(String, String),
+    /// An HTTP/2 setting of zero, with the setting's name
+    InvalidHttp2(String, String),
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::DuplicateName(name) => {
+                write!(f, "upstream pool '{}' is declared twice", name)
+            }
+            PoolError::Empty(name) => write!(f, "upstream pool '{}' has no upstreams", name),
+            PoolError::InvalidPath(name, path) => {
+                write!(f, "upstream pool '{}' has an invalid path '{}'", name, path)
+            }
+            PoolError::DuplicatePath(name, path, other) => write!(
+                f,
+                "upstream pool '{}' claims path '{}' of pool '{}'",
+                name, path, other
+            ),
+            PoolError::UnknownPool(name) => write!(f, "no upstream pool '{}'", name),
+            PoolError::InvalidCanary(name, canary) => write!(
+                f,
+                "upstream pool '{}' has an invalid canary pool '{}'",
+                name, canary
+            ),
+            PoolError::NoCanary(name) => write!(f, "upstream pool '{}' has no canary", name),
+            PoolError::InvalidPercent(name, percent) => write!(
+                f,
+                "upstream pool '{}' has an invalid canary share {}: use 0 to 100",
+                name, percent
+            ),
+            PoolError::InvalidFallback(name, fallback) => write!(
+                f,
+                "upstream pool '{}' has an invalid fallback pool '{}'",
+                name, fallback
+            ),
+            PoolError::InvalidTimeout(name, timeout) => write!(
+                f,
+                "upstream pool '{}' has an invalid {} timeout: use a positive number",
+                name, timeout
+            ),
+            PoolError::InvalidHttp2(name, setting) => write!(
+                f,
+                "upstream pool '{}' has an invalid http2 {}: use a positive number",
+                name, setting
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
+/// An upstream pool in the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamPoolConfig {
+    /// Name used in config errors and `langspec explain`
+    pub name: String,
+    /// Addresses of the gateway's upstreams in the pool
+    pub upstreams: Vec<String>,
+    /// Request paths routed to the pool, or prefixes ending in `*`; none for a pool
+    /// only serving as a canary
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<CanaryConfig>,
+    /// Pools a request is sent to in turn when the pool's upstreams fail it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeouts: Option<PoolTimeoutsConfig>,
+    /// HTTP/2 to the pool's upstreams; HTTP/1.1 when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http2: Option<PoolHttp2Config>,
+}
+
+/// Upstream timeouts of a pool in the config file, in milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolTimeoutsConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttfb_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_ms: Option<u64>,
+}
+
+/// Timeouts of requests to a pool's upstreams; unset ones are not limited by the pool.
+///
+/// Pingora bounds every read from an upstream with one timeout, so the larger of `ttfb`
+/// and `idle` bounds both the wait for the response header and each wait for the body.
+/// `idle` and `total` are also checked as body chunks arrive, failing a response that
+/// went quiet for longer or has run for longer since the upstream was selected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolTimeouts {
+    /// Connecting to an upstream, TLS handshake included
+    pub connect: Option<Duration>,
+    /// Waiting for the response header once the request is sent
+    pub ttfb: Option<Duration>,
+    /// Waiting for the next chunk of the response body
+    pub idle: Option<Duration>,
+    /// Whole exchange with the upstream, streamed body included
+    pub total: Option<Duration>,
+}
+
+impl PoolTimeouts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_connect(mut self, timeout: Duration) -> Self {
+        self.connect = Some(timeout);
+        self
+    }
+
+    pub fn with_ttfb(mut self, timeout: Duration) -> Self {
+        self.ttfb = Some(timeout);
+        self
+    }
+
+    pub fn with_idle(mut self, timeout: Duration) -> Self {
+        self.idle = Some(timeout);
+        self
+    }
+
+    pub fn with_total(mut self, timeout: Duration) -> Self {
+        self.total = Some(timeout);
+        self
+    }
+
+    /// Timeout of each read from the upstream
+    pub fn read(&self) -> Option<Duration> {
+        let read = self.ttfb.max(self.idle);
+        match (read, self.total) {
+            (Some(read), Some(total)) => Some(read.min(total)),
+            (read, total) => read.or(total),
+        }
+    }
+
+    fn compile(name: &str, config: &PoolTimeoutsConfig) -> Result<Self, PoolError> {
+        let timeout = |ms: Option<u64>, kind: &str| match ms {
+            Some(0) => Err(PoolError::InvalidTimeout(
+                name.to_string(),
+                kind.to_string(),
+            )),
+            ms => Ok(ms.map(Duration::from_millis)),
+        };
+        Ok(Self {
+            connect: timeout(config.connect_ms, "connect")?,
+            ttfb: timeout(config.ttfb_ms, "ttfb")?,
+            idle: timeout(config.idle_ms, "idle")?,
+            total: timeout(config.total_ms, "total")?,
+        })
+    }
+
+    fn config(&self) -> Option<PoolTimeoutsConfig> {
+        let ms = |timeout: Option<Duration>| timeout.map(|timeout| timeout.as_millis() as u64);
+        (*self != Self::default()).then(|| PoolTimeoutsConfig {
+            connect_ms: ms(self.connect),
+            ttfb_ms: ms(self.ttfb),
+            idle_ms: ms(self.idle),
+            total_ms: ms(self.total),
+        })
+    }
+}
+
+/// HTTP/2 to a pool's upstreams in the config file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolHttp2Config {
+    /// Speak HTTP/2 without negotiating it, as plaintext (h2c) upstreams need
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub required: bool,
+    /// Concurrent requests per connection; 100 when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_streams: Option<usize>,
+    /// Ping idle connections this often, dropping those that stop answering
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ping_interval_ms: Option<u64>,
+}
+
+/// HTTP/2 settings of a pool's upstream connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolHttp2 {
+    /// HTTP/2 only, instead of HTTP/2 when the upstream offers it through ALPN
+    pub required: bool,
+    /// Requests multiplexed on one connection at most
+    pub max_streams: usize,
+    pub ping_interval: Option<Duration>,
+}
+
+impl Default for PoolHttp2 {
+    fn default() -> Self {
+        Self {
+            required: false,
+            max_streams: Self::DEFAULT_MAX_STREAMS,
+            ping_interval: None,
+        }
+    }
+}
+
+impl PoolHttp2 {
+    pub const DEFAULT_MAX_STREAMS: usize = 100;
+
+    /// HTTP/2 negotiated through ALPN, with up to 100 requests per connection
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn with_max_streams(mut self, max_streams: usize) -> Self {
+        self.max_streams = max_streams;
+        self
+    }
+
+    pub fn with_ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = Some(interval);
+        self
+    }
+
+    fn compile(name: &str, config: &PoolHttp2Config) -> Result<Self, PoolError> {
+        let invalid =
+            |setting: &str| PoolError::InvalidHttp2(name.to_string(), setting.to_string());
+        if config.max_streams == Some(0) {
+            return Err(invalid("max_streams"));
+        }
+        if config.ping_interval_ms == Some(0) {
+            return Err(invalid("ping_interval_ms"));
+        }
+        Ok(Self {
+            required: config.required,
+            max_streams: config.max_streams.unwrap_or(Self::DEFAULT_MAX_STREAMS),
+            ping_interval: config.ping_interval_ms.map(Duration::from_millis),
+        })
+    }
+
+    fn config(&self) -> PoolHttp2Config {
+        PoolHttp2Config {
+            required: self.required,
+            max_streams: (self.max_streams != Self::DEFAULT_MAX_STREAMS)
+                .then_some(self.max_streams),
+            ping_interval_ms: self
+                .ping_interval
+                .map(|interval| interval.as_millis() as u64),
+        }
+    }
+}
+
+/// Share of a pool's requests sent to a canary pool, in the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanaryConfig {
+    pub pool: String,
+    /// Percentage of the requests, e.g. `5` or `0.5`
+    pub percent: f64,
+}
+
+/// Share of a pool's requests sent to its canary pool, adjustable at runtime
+#[derive(Debug)]
+pub struct CanarySplit {
+    pool: String,
+    /// Hundredths of a percent
+    basis_points: AtomicU32,
+}
+
+impl CanarySplit {
+    /// Name of the canary pool
+    pub fn pool(&self) -> &str {
+        &self.pool
+    }
+
+    pub fn percent(&self) -> f64 {
+        f64::from(self.basis_points.load(Ordering::Relaxed)) / 100.0
+    }
+
+    fn set_percent(&self, percent: f64) {
+        self.basis_points
+            .store((percent * 100.0).round() as u32, Ordering::Relaxed);
+    }
+}
+
+/// Which side of a canary split a request is routed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolArm {
+    /// The pool claiming the request path
+    Stable,
+    /// The canary pool of the pool claiming the request path
+    Canary,
+}
+
+impl PoolArm {
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PoolArm::Stable => "stable",
+            PoolArm::Canary => "canary",
+        }
+    }
+}
+
+/// Upstream pool a request is routed to, the arm it was chosen by and how far down
+/// the fallback chain it is
+#[derive(Debug, Clone)]
+pub struct PoolRoute {
+    pub pool: Arc<UpstreamPool>,
+    pub arm: PoolArm,
+    /// 0 for the pool the request was routed to, 1 for its first fallback pool, ...
+    pub hop: usize,
+    /// Fallback pools not tried yet, in order
+    pub fallback: Vec<String>,
+}
+
+impl PoolRoute {
+    /// Response header with the hop of a request served by a fallback pool
+    pub const HOP_HEADER: &'static str = "X-Langspec-Fallback-Hop";
+
+    fn new(pool: &Arc<UpstreamPool>, arm: PoolArm) -> Self {
+        Self {
+            pool: Arc::clone(pool),
+            arm,
+            hop: 0,
+            fallback: pool.fallback.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UpstreamPool {
+    pub name: String,
+    pub upstreams: Vec<String>,
+    pub paths: Vec<String>,
+    pub canary: Option<CanarySplit>,
+    pub fallback: Vec<String>,
+    pub timeouts: PoolTimeouts,
+    /// HTTP/2 to the pool's upstreams, HTTP/1.1 when `None`
+    pub http2: Option<PoolHttp2>,
+}
+
+impl UpstreamPool {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            upstreams: Vec::new(),
+            paths: Vec::new(),
+            canary: None,
+            fallback: Vec::new(),
+            timeouts: PoolTimeouts::default(),
+            http2: None,
+        }
+    }
+
+    pub fn with_upstream(mut self, upstream: impl Into<String>) -> Self {
+        self.upstreams.push(upstream.into());
+        self
+    }
+
+    /// Route `path` to the pool: a request path, or a prefix ending in `*`
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Send `percent` of the requests to the pool named `canary` instead, which must be
+    /// added too; panics on a share that is not a percentage
+    pub fn with_canary(mut self, canary: impl Into<String>, percent: f64) -> Self {
+        if let Err(e) = check_percent(&self.name, percent) {
+            panic!("{}", e);
+        }
+        let split = CanarySplit {
+            pool: canary.into(),
+            basis_points: AtomicU32::new(0),
+        };
+        split.set_percent(percent);
+        self.canary = Some(split);
+        self
+    }
+
+    /// Send requests the pool's upstreams fail to the pool named `fallback`, which must
+    /// be added too, after the fallback pools added before
+    pub fn with_fallback(mut self, fallback: impl Into<String>) -> Self {
+        self.fallback.push(fallback.into());
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: PoolTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_http2(mut self, http2: PoolHttp2) -> Self {
+        self.http2 = Some(http2);
+        self
+    }
+
+    pub fn contains(&self, upstream: &str) -> bool {
+        self.upstreams.iter().any(|address| address == upstream)
+    }
+
+    /// How specifically the pool claims `path`: an exact path over any prefix, a longer
+    /// prefix over a shorter one; `None` when it does not
+    fn specificity(&self, path: &str) -> Option<usize> {
+        self.paths
+            .iter()
+            .filter_map(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix).then_some(prefix.len()),
+                None => (pattern == path).then_some(usize::MAX),
+            })
+            .max()
+    }
+
+    /// The pool as declared in the config file, with its current canary share
+    pub fn config(&self) -> UpstreamPoolConfig {
+        UpstreamPoolConfig {
+            name: self.name.clone(),
+            upstreams: self.upstreams.clone(),
+            paths: self.paths.clone(),
+            canary: self.canary.as_ref().map(|split| CanaryConfig {
+                pool: split.pool.clone(),
+                percent: split.percent(),
+            }),
+            fallback: self.fallback.clone(),
+            timeouts: self.timeouts.config(),
+            http2: self.http2.as_ref().map(PoolHttp2::config),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamPools {
+    pools: Vec<Arc<UpstreamPool>>,
+}
+
+impl UpstreamPools {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a pool; panics on a pool that is empty, named twice or claims a path of
+    /// another pool, as [`compile`](Self::compile) would reject it
+    pub fn with_pool(mut self, pool: UpstreamPool) -> Self {
+        if let Err(e) = self.check(&pool) {
+            panic!("{}", e);
+        }
+        self.pools.push(Arc::new(pool));
+        self
+    }
+
+    /// Pools declared in the config file
+    pub fn compile(configs: &[UpstreamPoolConfig]) -> Result<Self, PoolError> {
+        let mut pools = Self::new();
+        for config in configs {
+            let mut pool = UpstreamPool {
+                name: config.name.clone(),
+                upstreams: config.upstreams.clone(),
+                paths: config.paths.clone(),
+                canary: None,
+                fallback: config.fallback.clone(),
+                timeouts: PoolTimeouts::default(),
+                http2: None,
+            };
+            if let Some(timeouts) = &config.timeouts {
+                pool.timeouts = PoolTimeouts::compile(&pool.name, timeouts)?;
+            }
+            if let Some(http2) = &config.http2 {
+                pool.http2 = Some(PoolHttp2::compile(&pool.name, http2)?);
+            }
+            if let Some(canary) = &config.canary {
+                check_percent(&pool.name, canary.percent)?;
+                pool = pool.with_canary(canary.pool.clone(), canary.percent);
+            }
+            pools.check(&pool)?;
+            pools.pools.push(Arc::new(pool));
+        }
+        for pool in &pools.pools {
+            if let Some(split) = &pool.canary
+                && (split.pool == pool.name || pools.get(&split.pool).is_none())
+            {
+                return Err(PoolError::InvalidCanary(
+                    pool.name.clone(),
+                    split.pool.clone(),
+                ));
+            }
+            let mut seen = HashSet::new();
+            for fallback in &pool.fallback {
+                if *fallback == pool.name
+                    || pools.get(fallback).is_none()
+                    || !seen.insert(fallback.as_str())
+                {
+                    return Err(PoolError::InvalidFallback(
+                        pool.name.clone(),
+                        fallback.clone(),
+                    ));
+                }
+            }
+        }
+        Ok(pools)
+    }
+
+    fn check(&self, pool: &UpstreamPool) -> Result<(), PoolError> {
+        if self.pools.iter().any(|other| other.name == pool.name) {
+            return Err(PoolError::DuplicateName(pool.name.clone()));
+        }
+        if pool.upstreams.is_empty() {
+            return Err(PoolError::Empty(pool.name.clone()));
+        }
+        let claimed: HashMap<&str, &str> = self
+            .pools
+            .iter()
+            .flat_map(|other| {
+                other
+                    .paths
+                    .iter()
+                    .map(|path| (path.as_str(), other.name.as_str()))
+            })
+            .collect();
+        let mut seen = HashSet::new();
+        for path in &pool.paths {
+            if !path.starts_with('/') {
+                return Err(PoolError::InvalidPath(pool.name.clone(), path.clone()));
+            }
+            let other = claimed
+                .get(path.as_str())
+                .copied()
+                .or_else(|| (!seen.insert(path.as_str())).then_some(pool.name.as_str()));
+            if let Some(other) = other {
+                return Err(PoolError::DuplicatePath(
+                    pool.name.clone(),
+                    path.clone(),
+                    other.to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+
+    pub fn pools(&self) -> &[Arc<UpstreamPool>] {
+        &self.pools
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<UpstreamPool>> {
+        self.pools.iter().find(|pool| pool.name == name)
+    }
+
+    /// Send `percent` of the requests of pool `name` to its canary pool from now on
+    pub fn set_canary_percent(&self, name: &str, percent: f64) -> Result<(), PoolError> {
+        let pool = self
+            .get(name)
+            .ok_or_else(|| PoolError::UnknownPool(name.to_string()))?;
+        let split = pool
+            .canary
+            .as_ref()
+            .ok_or_else(|| PoolError::NoCanary(name.to_string()))?;
+        check_percent(name, percent)?;
+        split.set_percent(percent);
+        Ok(())
+    }
+
+    /// Pool a request for `path` is routed to: the one claiming the path, or its canary
+    /// pool for the canary's share of requests
+    pub fn route(&self, path: &str) -> Option<PoolRoute> {
+        let pool = self.pool_for(path)?;
+        let canary = pool
+            .canary
+            .as_ref()
+            .filter(|split| rand::random::<f64>() * 100.0 < split.percent())
+            .and_then(|split| self.get(&split.pool));
+        Some(match canary {
+            Some(canary) => PoolRoute::new(canary, PoolArm::Canary),
+            None => PoolRoute::new(pool, PoolArm::Stable),
+        })
+    }
+
+    /// Next pool in the fallback chain of a request routed by `route`, skipping pools
+    /// no longer declared; `None` at the end of the chain
+    pub fn fallback(&self, route: &PoolRoute) -> Option<PoolRoute> {
+        let mut remaining = route.fallback.iter();
+        let pool = remaining.by_ref().find_map(|name| self.get(name))?;
+        Some(PoolRoute {
+            pool: Arc::clone(pool),
+            arm: route.arm,
+            hop: route.hop + 1,
+            fallback: remaining.cloned().collect(),
+        })
+    }
+
+    /// Pool claiming `path` most specifically
+    pub fn pool_for(&self, path: &str) -> Option<&Arc<UpstreamPool>> {
+        self.pools
+            .iter()
+            .filter_map(|pool| Some((pool.specificity(path)?, pool)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, pool)| pool)
+    }
+}
+
+fn check_percent(name: &str, percent: f64) -> Result<(), PoolError> {
+    match (0.0..=100.0).contains(&percent) {
+        true => Ok(()),
+        false => Err(PoolError::InvalidPercent(name.to_string(), percent)),
+    }
+}
@@ -0,0 +1,154 @@
+use log::warn;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// When and how to check an upstream before sending it an expensive request
+#[derive(Debug, Clone)]
+pub struct PreflightConfig {
+    /// Requests with an estimated prompt at least this large are checked first
+    pub min_prompt_tokens: u64,
+    /// Cheap endpoint probed with a GET, e.g. the model list
+    pub path: String,
+    /// How long a check result is reused
+    pub ttl: Duration,
+    /// How long a check may take before the upstream counts as down
+    pub timeout: Duration,
+}
+
+impl Default for PreflightConfig {
+    fn default() -> Self {
+        Self {
+            min_prompt_tokens: 32_000,
+            path: "/v1/models".to_string(),
+            ttl: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+impl PreflightConfig {
+    /// Bytes per token assumed when estimating a prompt from the body size
+    pub const BYTES_PER_TOKEN: u64 = 4;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_min_prompt_tokens(mut self, min_prompt_tokens: u64) -> Self {
+        self.min_prompt_tokens = min_prompt_tokens;
+        self
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Whether a request body of `content_length` bytes is large enough to be checked.
+    /// The prompt is estimated from the size, since the body has not been read yet.
+    pub fn applies_to(&self, content_length: u64) -> bool {
+        content_length / Self::BYTES_PER_TOKEN >= self.min_prompt_tokens
+    }
+}
+
+/// Outcome of a pre-flight check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreflightStatus {
+    Healthy,
+    /// The upstream answered 429
+    Throttled,
+    /// The upstream answered 5xx, failed to answer or timed out
+    Down,
+}
+
+impl PreflightStatus {
+    /// Status of a probe response. Any answer below 500 other than 429 means the
+    /// upstream is up, including 401/404 from an endpoint that needs a key or does not
+    /// exist.
+    pub fn from_probe(status: u16) -> Self {
+        match status {
+            429 => PreflightStatus::Throttled,
+            500.. => PreflightStatus::Down,
+            _ => PreflightStatus::Healthy,
+        }
+    }
+
+    /// Metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PreflightStatus::Healthy => "healthy",
+            PreflightStatus::Throttled => "throttled",
+            PreflightStatus::Down => "down",
+        }
+    }
+
+    /// Status the request is failed with: 429 while throttled, 503 while down
+    pub fn reject_status(&self) -> Option<u16> {
+        match self {
+            PreflightStatus::Healthy => None,
+            PreflightStatus::Throttled => Some(429),
+            PreflightStatus::Down => Some(503),
+        }
+    }
+}
+
+/// Cached pre-flight check of one upstream.
+///
+/// Requests needing a check while one is running wait for its result instead of
+/// probing again, so a burst of large prompts costs a single probe per TTL.
+#[derive(Debug)]
+pub struct PreflightCheck {
+    upstream: String,
+    config: PreflightConfig,
+    last: Mutex<Option<(Instant, PreflightStatus)>>,
+}
+
+impl PreflightCheck {
+    pub fn new(upstream: &str, config: PreflightConfig) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            config,
+            last: Mutex::new(None),
+        }
+    }
+
+    pub fn config(&self) -> &PreflightConfig {
+        &self.config
+    }
+
+    /// The cached status, or the outcome of `probe` once the cached one expired.
+    /// Returns whether the status came from the cache.
+    pub async fn status<F, Fut>(&self, probe: F) -> (PreflightStatus, bool)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = PreflightStatus>,
+    {
+        let mut last = self.last.lock().await;
+        if let Some((checked_at, status)) = *last
+            && checked_at.elapsed() < self.config.ttl
+        {
+            return (status, true);
+        }
+        let status = probe().await;
+        if status != PreflightStatus::Healthy {
+            warn!(
+                "Pre-flight check of {} failed: {}",
+                self.upstream,
+                status.as_str()
+            );
+        }
+        *last = Some((Instant::now(), status));
+        (status, false)
+    }
+}
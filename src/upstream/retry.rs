@@ -0,0 +1,137 @@
+//! Retries of requests an upstream failed.
+//!
+//! Without a retry policy a request is only retried where Pingora retries it on its
+//! own, e.g. on a reused connection the upstream had closed. A [`RetryPolicy`] retries
+//! a request on another upstream when the connection fails, the upstream fails before
+//! answering or answers with a retryable status, up to a number of attempts and after
+//! an exponential backoff with full jitter. A request whose body may have reached the
+//! upstream is only retried when its method is idempotent, unless the policy allows
+//! retrying any request (e.g. for completions, which providers do not bill when they
+//! answer 429 or 5xx).
+
+use http::Method;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetryError {
+    /// A retryable status that is not a 4xx or 5xx status code
+    InvalidStatus(String),
+}
+
+impl fmt::Display for RetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetryError::InvalidStatus(status) => write!(
+                f,
+                "invalid retryable status '{}': use a 4xx or 5xx status code",
+                status
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RetryError {}
+
+/// When and how often requests are retried
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Most attempts of a request, the first one included
+    pub max_attempts: usize,
+    /// Upstream response statuses the request is retried on
+    pub statuses: Vec<u16>,
+    /// Longest backoff before the first retry; doubled for each retry after it
+    pub base_backoff: Duration,
+    /// Cap on the backoff before any retry
+    pub max_backoff: Duration,
+    /// Retry requests with non-idempotent methods (POST, PATCH) once their body may
+    /// have reached the upstream
+    pub non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts on 429, 502, 503 and 504, idempotent requests only
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            statuses: vec![429, 502, 503, 504],
+            base_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: usize) -> Self {
+        assert!(max_attempts > 0, "Retry policy needs at least one attempt");
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn with_statuses(mut self, statuses: Vec<u16>) -> Self {
+        self.statuses = statuses;
+        self
+    }
+
+    /// Statuses from a comma-separated list, e.g. `429,500,502,503`
+    pub fn with_status_list(self, list: &str) -> Result<Self, RetryError> {
+        let statuses = list
+            .split(',')
+            .map(str::trim)
+            .filter(|status| !status.is_empty())
+            .map(|status| {
+                status
+                    .parse()
+                    .ok()
+                    .filter(|status| (400..600).contains(status))
+                    .ok_or_else(|| RetryError::InvalidStatus(status.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(self.with_statuses(statuses))
+    }
+
+    pub fn with_backoff(mut self, base_backoff: Duration, max_backoff: Duration) -> Self {
+        self.base_backoff = base_backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn with_non_idempotent(mut self, non_idempotent: bool) -> Self {
+        self.non_idempotent = non_idempotent;
+        self
+    }
+
+    pub fn is_retryable_status(&self, status: u16) -> bool {
+        self.statuses.contains(&status)
+    }
+
+    /// Whether a request that took `attempts` attempts so far may be tried again;
+    /// `body_sent` when its body may have reached the upstream
+    pub fn allows(&self, attempts: usize, method: &Method, body_sent: bool) -> bool {
+        attempts < self.max_attempts && (!body_sent || self.non_idempotent || is_idempotent(method))
+    }
+
+    /// Backoff before retry number `retry` (1 for the first): a random duration up to
+    /// the base backoff doubled for each retry before it, capped
+    pub fn backoff(&self, retry: usize) -> Duration {
+        let doublings = retry.saturating_sub(1).min(31) as u32;
+        let ceiling = self
+            .base_backoff
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff);
+        ceiling.mul_f64(rand::random::<f64>())
+    }
+}
+
+/// Whether requests with `method` may be repeated without changing their effect
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
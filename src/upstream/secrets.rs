@@ -0,0 +1,185 @@
+use log::info;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretError {
+    /// A reference that is not `<source>:<name>`
+    InvalidReference(String),
+    /// The environment variable is not set (or not unicode)
+    EnvNotSet(String),
+    /// The file cannot be read
+    File(PathBuf, String),
+    /// No backend is registered under the reference's source
+    UnknownBackend(String),
+    /// The backend failed to return the secret
+    Backend(String, String),
+    /// The secret is empty
+    Empty(String),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::InvalidReference(reference) => write!(
+                f,
+                "invalid secret reference '{}', expected e.g. 'env:OPENAI_API_KEY'",
+                reference
+            ),
+            SecretError::EnvNotSet(name) => {
+                write!(f, "environment variable '{}' is not set", name)
+            }
+            SecretError::File(path, e) => {
+                write!(f, "cannot read secret file '{}': {}", path.display(), e)
+            }
+            SecretError::UnknownBackend(backend) => {
+                write!(f, "no secret backend '{}' is registered", backend)
+            }
+            SecretError::Backend(backend, e) => {
+                write!(f, "secret backend '{}' failed: {}", backend, e)
+            }
+            SecretError::Empty(reference) => write!(f, "secret '{}' is empty", reference),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+/// Where a secret is loaded from, written `env:NAME`, `file:/path` or
+/// `<backend>:<name>` for a registered [`SecretBackend`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SecretRef {
+    Env(String),
+    File(PathBuf),
+    Backend(String, String),
+}
+
+impl SecretRef {
+    pub fn parse(reference: &str) -> Result<Self, SecretError> {
+        let invalid = || SecretError::InvalidReference(reference.to_string());
+        let (source, name) = reference.trim().split_once(':').ok_or_else(invalid)?;
+        if source.is_empty() || name.is_empty() {
+            return Err(invalid());
+        }
+        Ok(match source {
+            "env" => SecretRef::Env(name.to_string()),
+            "file" => SecretRef::File(PathBuf::from(name)),
+            backend => SecretRef::Backend(backend.to_string(), name.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretRef::Env(name) => write!(f, "env:{}", name),
+            SecretRef::File(path) => write!(f, "file:{}", path.display()),
+            SecretRef::Backend(backend, name) => write!(f, "{}:{}", backend, name),
+        }
+    }
+}
+
+/// An external secret store, e.g. Vault or a cloud secret manager.
+///
+/// `fetch` runs on the request path whenever a cached secret expired, so backends that
+/// call out over the network should keep their own copy fresh in the background and
+/// answer from it.
+pub trait SecretBackend: Send + Sync {
+    fn fetch(&self, name: &str) -> Result<String, String>;
+}
+
+/// Resolves secret references, caching each secret for a while so rotated files and
+/// backend secrets are picked up without a restart.
+pub struct SecretStore {
+    backends: HashMap<String, Arc<dyn SecretBackend>>,
+    ttl: Duration,
+    cache: Mutex<HashMap<SecretRef, (Arc<str>, Instant)>>,
+}
+
+impl SecretStore {
+    pub fn new() -> Self {
+        Self {
+            backends: HashMap::new(),
+            ttl: Duration::from_secs(60),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Resolve `<name>:...` references with `backend`
+    pub fn with_backend(
+        mut self,
+        name: impl Into<String>,
+        backend: impl SecretBackend + 'static,
+    ) -> Self {
+        let name = name.into();
+        assert!(
+            name != "env" && name != "file",
+            "Secret backend '{}' would shadow a built-in source",
+            name
+        );
+        self.backends.insert(name, Arc::new(backend));
+        self
+    }
+
+    /// How long a loaded secret is used before it is loaded again
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// The secret `reference` names, without surrounding whitespace
+    pub fn resolve(&self, reference: &SecretRef) -> Result<Arc<str>, SecretError> {
+        if let Some((secret, loaded_at)) = self.cache.lock().unwrap().get(reference)
+            && loaded_at.elapsed() < self.ttl
+        {
+            return Ok(Arc::clone(secret));
+        }
+        let secret = match reference {
+            SecretRef::Env(name) => {
+                std::env::var(name).map_err(|_| SecretError::EnvNotSet(name.clone()))?
+            }
+            SecretRef::File(path) => std::fs::read_to_string(path)
+                .map_err(|e| SecretError::File(path.clone(), e.to_string()))?,
+            SecretRef::Backend(backend, name) => self
+                .backends
+                .get(backend)
+                .ok_or_else(|| SecretError::UnknownBackend(backend.clone()))?
+                .fetch(name)
+                .map_err(|e| SecretError::Backend(backend.clone(), e))?,
+        };
+        let secret = secret.trim();
+        if secret.is_empty() {
+            return Err(SecretError::Empty(reference.to_string()));
+        }
+        let secret: Arc<str> = Arc::from(secret);
+        let previous = self
+            .cache
+            .lock()
+            .unwrap()
+            .insert(reference.clone(), (Arc::clone(&secret), Instant::now()));
+        if previous.is_some_and(|(previous, _)| previous != secret) {
+            info!("Secret '{}' changed, using the new value", reference);
+        }
+        Ok(secret)
+    }
+}
+
+impl Default for SecretStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for SecretStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut backends: Vec<&String> = self.backends.keys().collect();
+        backends.sort();
+        f.debug_struct("SecretStore")
+            .field("backends", &backends)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
@@ -0,0 +1,79 @@
+use log::info;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Gradual traffic ramp-up for upstreams entering the rotation.
+#[derive(Debug, Clone)]
+pub struct SlowStartConfig {
+    /// Time for an upstream's traffic share to ramp up from `min_weight` to a full share
+    pub window: Duration,
+    /// Fraction of a full share an upstream receives at the start of the ramp
+    pub min_weight: f64,
+}
+
+impl Default for SlowStartConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(30),
+            min_weight: 0.1,
+        }
+    }
+}
+
+/// Ramps a single upstream's traffic share up linearly over the slow-start window,
+/// after gateway startup and whenever the upstream is re-admitted by passive health
+/// checking, so cold caches and freshly started servers are not hit with a full share
+/// (or a thundering herd) at once.
+#[derive(Debug)]
+pub struct SlowStart {
+    upstream: String,
+    config: SlowStartConfig,
+    /// Start of the current ramp
+    started: Mutex<Instant>,
+}
+
+impl SlowStart {
+    pub fn new(upstream: &str, config: SlowStartConfig) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&config.min_weight),
+            "Slow-start minimum weight must be between 0 and 1"
+        );
+        Self {
+            upstream: upstream.to_string(),
+            config,
+            started: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Start ramping up again, e.g. when the upstream re-enters the rotation
+    pub fn restart(&self) {
+        self.restart_at(Instant::now());
+    }
+
+    pub fn restart_at(&self, now: Instant) {
+        info!(
+            "Upstream {} ramping up over {:?}",
+            self.upstream, self.config.window
+        );
+        *self.started.lock().unwrap() = now;
+    }
+
+    /// Fraction of a full traffic share the upstream receives now
+    pub fn weight(&self) -> f64 {
+        self.weight_at(Instant::now())
+    }
+
+    pub fn weight_at(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(*self.started.lock().unwrap());
+        if self.config.window.is_zero() || elapsed >= self.config.window {
+            return 1.0;
+        }
+        let progress = elapsed.as_secs_f64() / self.config.window.as_secs_f64();
+        self.config.min_weight + (1.0 - self.config.min_weight) * progress
+    }
+
+    /// Whether the upstream is still ramping up
+    pub fn is_ramping(&self) -> bool {
+        self.weight() < 1.0
+    }
+}
@@ -0,0 +1,120 @@
+use pingora::tls::error::ErrorStack;
+use pingora::tls::pkey::PKey;
+use pingora::tls::x509::X509;
+use pingora::utils::tls::CertKey;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fs, io};
+
+#[derive(Debug)]
+pub enum TlsConfigError {
+    Io(PathBuf, io::Error),
+    Pem(PathBuf, ErrorStack),
+    NoCertificates(PathBuf),
+}
+
+impl fmt::Display for TlsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsConfigError::Io(path, e) => write!(f, "cannot read '{}': {}", path.display(), e),
+            TlsConfigError::Pem(path, e) => write!(f, "invalid PEM in '{}': {}", path.display(), e),
+            TlsConfigError::NoCertificates(path) => {
+                write!(f, "no certificates found in '{}'", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for TlsConfigError {}
+
+/// TLS settings for an upstream pool: the client certificate presented on every TLS
+/// connection (mutual TLS) and the CA bundle the upstream certificates are verified
+/// against (for internal clusters with a private CA, or corporate networks that
+/// intercept TLS). Either can be used without the other.
+#[derive(Debug, Clone)]
+pub struct UpstreamTls {
+    client_cert_key: Option<Arc<CertKey>>,
+    ca: Option<Arc<Box<[X509]>>>,
+}
+
+impl UpstreamTls {
+    /// Load a PEM certificate chain (leaf first) and its PEM private key.
+    pub fn from_pem_files(
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<Self, TlsConfigError> {
+        let certificates = load_certificates(cert_path.as_ref())?;
+        let key_path = key_path.as_ref();
+        let key = PKey::private_key_from_pem(&read(key_path)?)
+            .map_err(|e| TlsConfigError::Pem(key_path.to_path_buf(), e))?;
+
+        Ok(Self {
+            client_cert_key: Some(Arc::new(CertKey::new(certificates, key))),
+            ca: None,
+        })
+    }
+
+    /// Verify upstream certificates against this PEM CA bundle, without a client
+    /// certificate
+    pub fn from_ca_file(ca_path: impl AsRef<Path>) -> Result<Self, TlsConfigError> {
+        Self {
+            client_cert_key: None,
+            ca: None,
+        }
+        .with_ca_file(ca_path)
+    }
+
+    /// Verify upstream certificates against this PEM CA bundle instead of the system roots.
+    pub fn with_ca_file(mut self, ca_path: impl AsRef<Path>) -> Result<Self, TlsConfigError> {
+        let ca = load_certificates(ca_path.as_ref())?;
+        self.ca = Some(Arc::new(ca.into_boxed_slice()));
+        Ok(self)
+    }
+
+    pub fn client_cert_key(&self) -> Option<&Arc<CertKey>> {
+        self.client_cert_key.as_ref()
+    }
+
+    pub fn ca(&self) -> Option<&Arc<Box<[X509]>>> {
+        self.ca.as_ref()
+    }
+}
+
+/// Keeps TLS upstream connections pooled so requests skip the TLS handshake.
+///
+/// Pingora does not resume TLS sessions (tickets or session IDs) on upstream
+/// connections: every new connection is a full handshake, counted in
+/// `langspec_upstream_tls_handshakes_total`. Keeping idle connections open longer, and
+/// alive through NATs and load balancers with TCP keepalives, raises the share of
+/// requests sent over pooled connections (`langspec_upstream_tls_connection_reuses_total`).
+#[derive(Debug, Clone)]
+pub struct TlsConnectionReuse {
+    /// How long an idle pooled connection is kept open
+    pub idle_timeout: Duration,
+    /// Idle time before TCP keepalive probes are sent (disabled when None)
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl Default for TlsConnectionReuse {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(90),
+            tcp_keepalive: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, TlsConfigError> {
+    fs::read(path).map_err(|e| TlsConfigError::Io(path.to_path_buf(), e))
+}
+
+pub(crate) fn load_certificates(path: &Path) -> Result<Vec<X509>, TlsConfigError> {
+    let certificates = X509::stack_from_pem(&read(path)?)
+        .map_err(|e| TlsConfigError::Pem(path.to_path_buf(), e))?;
+    if certificates.is_empty() {
+        return Err(TlsConfigError::NoCertificates(path.to_path_buf()));
+    }
+    Ok(certificates)
+}
@@ -0,0 +1,102 @@
+use crate::metrics::{UPSTREAM_COLD_START_SECONDS, UPSTREAM_COLD_STARTS};
+use log::debug;
+use prometheus::{Histogram, IntCounter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Warm/cold tracking for scale-to-zero inference backends.
+#[derive(Debug, Clone)]
+pub struct WarmthConfig {
+    /// An upstream without successful traffic for this long is considered cold
+    pub idle_timeout: Duration,
+    /// Send a keep-warm ping to every upstream at this interval (disabled when None)
+    pub keep_warm_interval: Option<Duration>,
+    /// Path requested by keep-warm pings
+    pub keep_warm_path: String,
+    /// Connect + response timeout for a single keep-warm ping
+    pub keep_warm_timeout: Duration,
+}
+
+impl Default for WarmthConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(300),
+            keep_warm_interval: None,
+            keep_warm_path: "/health".to_string(),
+            keep_warm_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Sentinel for "no activity recorded yet"
+const NEVER: u64 = u64::MAX;
+
+/// Tracks whether a single upstream is warm and records cold-start latency.
+///
+/// An upstream is warm while it has served a response (or answered a keep-warm
+/// ping) within `idle_timeout`. Upstreams start out cold.
+#[derive(Debug)]
+pub struct WarmthTracker {
+    upstream: String,
+    idle_timeout: Duration,
+    epoch: Instant,
+    /// Milliseconds since `epoch` of the last successful response
+    last_active_ms: AtomicU64,
+    cold_starts: IntCounter,
+    cold_start_latency: Histogram,
+}
+
+impl WarmthTracker {
+    pub fn new(upstream: &str, idle_timeout: Duration) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            idle_timeout,
+            epoch: Instant::now(),
+            last_active_ms: AtomicU64::new(NEVER),
+            cold_starts: UPSTREAM_COLD_STARTS.with_label_values(&[upstream]),
+            cold_start_latency: UPSTREAM_COLD_START_SECONDS.with_label_values(&[upstream]),
+        }
+    }
+
+    pub fn upstream(&self) -> &str {
+        &self.upstream
+    }
+
+    pub fn is_warm(&self) -> bool {
+        let last = self.last_active_ms.load(Ordering::Relaxed);
+        if last == NEVER {
+            return false;
+        }
+        let idle_ms = self.elapsed_ms().saturating_sub(last);
+        idle_ms < self.idle_timeout.as_millis() as u64
+    }
+
+    /// Mark the upstream as having just served traffic
+    pub fn mark_active(&self) {
+        self.last_active_ms
+            .store(self.elapsed_ms(), Ordering::Relaxed);
+    }
+
+    /// Record a response from this upstream. `was_cold` is the warmth observed when the
+    /// request was routed; cold-start latency is tracked separately from warm latency.
+    pub fn record_response(&self, was_cold: bool, latency: Duration) {
+        if was_cold {
+            self.cold_starts.inc();
+            self.cold_start_latency.observe(latency.as_secs_f64());
+            debug!(
+                "Cold start on upstream {} took {:?}",
+                self.upstream, latency
+            );
+        }
+        self.mark_active();
+    }
+
+    /// Number of cold starts observed on this upstream
+    pub fn cold_starts(&self) -> u64 {
+        self.cold_starts.get()
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+}
@@ -0,0 +1,186 @@
+use langspec::billing::{BillingAnchor, BillingLedger, PeriodStatus};
+use langspec::budget::{BudgetPolicy, SpendBudgets};
+use langspec::pipeline::usage::Usage;
+use std::time::Duration;
+
+/// Seconds since the Unix epoch at midnight UTC of a date
+fn date(year: i32, month: u32, day: u32) -> Duration {
+    let date = chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap();
+    Duration::from_secs(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() as u64)
+}
+
+fn usage(prompt_tokens: u64, completion_tokens: u64) -> Usage {
+    Usage {
+        prompt_tokens,
+        completion_tokens,
+        estimated: false,
+    }
+}
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+#[test]
+fn test_billing_anchor_periods() {
+    let calendar = BillingAnchor::CalendarMonth;
+    assert_eq!(
+        calendar.period_containing(date(2026, 3, 15) + HOUR),
+        (date(2026, 3, 1), date(2026, 4, 1))
+    );
+    assert_eq!(
+        calendar.period_containing(date(2026, 12, 31)),
+        (date(2026, 12, 1), date(2027, 1, 1))
+    );
+
+    let fifteenth = BillingAnchor::DayOfMonth(15);
+    assert_eq!(
+        fifteenth.period_containing(date(2026, 3, 15)),
+        (date(2026, 3, 15), date(2026, 4, 15))
+    );
+    assert_eq!(
+        fifteenth.period_containing(date(2026, 1, 14)),
+        (date(2025, 12, 15), date(2026, 1, 15))
+    );
+
+    // Short months end the period on their last day
+    let last = BillingAnchor::DayOfMonth(31);
+    assert_eq!(
+        last.period_containing(date(2026, 2, 10)),
+        (date(2026, 1, 31), date(2026, 2, 28))
+    );
+    assert_eq!(
+        last.period_containing(date(2026, 3, 1)),
+        (date(2026, 2, 28), date(2026, 3, 31))
+    );
+}
+
+#[test]
+fn test_billing_ledger_rolls_up_at_period_close() {
+    let ledger = BillingLedger::new(BillingAnchor::CalendarMonth)
+        .with_tenant_anchor("acme", BillingAnchor::DayOfMonth(10));
+
+    ledger.record("acme", date(2026, 3, 9), &usage(100, 50), Some(0.5));
+    ledger.record("acme", date(2026, 3, 9) + HOUR, &usage(10, 5), None);
+    ledger.record("globex", date(2026, 3, 9), &usage(1, 1), Some(0.01));
+
+    let periods = ledger.periods(date(2026, 3, 9) + 2 * HOUR);
+    assert_eq!(periods.len(), 2);
+    assert!(periods.iter().all(|row| row.status == PeriodStatus::Open));
+
+    // acme's period ends on the 10th; globex's runs until April
+    let periods = ledger.periods(date(2026, 3, 10));
+    let acme = &periods[0];
+    assert_eq!(acme.tenant, "acme");
+    assert_eq!(acme.status, PeriodStatus::Closed);
+    assert_eq!(acme.period_start, "2026-02-10T00:00:00Z");
+    assert_eq!(acme.period_end, "2026-03-10T00:00:00Z");
+    assert_eq!(acme.totals.requests, 2);
+    assert_eq!(
+        (acme.totals.prompt_tokens, acme.totals.completion_tokens),
+        (110, 55)
+    );
+    assert_eq!(acme.totals.cost_usd, 0.5);
+    assert_eq!(periods[1].tenant, "globex");
+    assert_eq!(periods[1].status, PeriodStatus::Open);
+
+    // A request started before the close still counts toward the closed period
+    ledger.record("acme", date(2026, 3, 9) + 3 * HOUR, &usage(1, 1), None);
+    assert_eq!(ledger.periods(date(2026, 3, 10))[0].totals.requests, 3);
+}
+
+#[test]
+fn test_billing_close_period_freezes_totals() {
+    let ledger = BillingLedger::default();
+    assert!(ledger.close_period("acme", date(2026, 3, 5)).is_err());
+
+    ledger.record("acme", date(2026, 2, 20), &usage(100, 50), Some(1.0));
+    let frozen = ledger.close_period("acme", date(2026, 3, 1)).unwrap();
+    assert_eq!(frozen.status, PeriodStatus::Frozen);
+    assert_eq!(frozen.period_start, "2026-02-01T00:00:00Z");
+    assert_eq!(frozen.totals.requests, 1);
+
+    // Late usage goes to the next period instead of changing frozen totals
+    ledger.record("acme", date(2026, 2, 28), &usage(10, 10), Some(0.1));
+    let periods = ledger.periods(date(2026, 3, 1));
+    assert_eq!(periods.len(), 2);
+    assert_eq!(periods[0].totals.requests, 1);
+    assert_eq!(periods[1].period_start, "2026-03-01T00:00:00Z");
+    assert_eq!(periods[1].status, PeriodStatus::Open);
+    assert_eq!(periods[1].totals.requests, 1);
+}
+
+#[cfg(feature = "admin")]
+#[test]
+fn test_admin_billing_endpoints() {
+    use langspec::proxy::GatewayProxy;
+
+    let proxy = GatewayProxy::new(vec!["127.0.0.1:8001".to_string()]);
+    assert_eq!(
+        proxy.admin_app().handle("GET", "/billing/periods").status(),
+        404
+    );
+
+    let proxy = proxy.with_billing(BillingLedger::default());
+    let admin = proxy.admin_app();
+    let response = admin.handle("GET", "/billing/periods");
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body, serde_json::json!([]));
+
+    assert_eq!(admin.handle("POST", "/billing/periods").status(), 405);
+    assert_eq!(
+        admin.handle("POST", "/billing/tenants/acme/close").status(),
+        409
+    );
+    assert_eq!(
+        admin.handle("GET", "/billing/tenants/acme/close").status(),
+        405
+    );
+}
+
+#[test]
+fn test_spend_budgets() {
+    const HOUR: Duration = Duration::from_secs(3600);
+    const DAY: Duration = Duration::from_secs(86_400);
+    let budgets = SpendBudgets::new(
+        BudgetPolicy::new()
+            .with_budget("acme", 1.0, HOUR)
+            .with_budget("acme", 5.0, DAY)
+            .with_default_budget(0.5, DAY),
+    );
+    let now = date(2026, 3, 10) + Duration::from_secs(600);
+
+    // Tenants without their own budgets get the default one
+    assert_eq!(budgets.policy().budgets_for("acme").len(), 2);
+    assert_eq!(budgets.policy().budgets_for("globex")[0].limit_usd, 0.5);
+
+    budgets.record("acme", 0.6, now);
+    assert!(budgets.check("acme", now).is_none());
+    budgets.record("acme", 0.4, now);
+    let exhausted = budgets.check("acme", now).unwrap();
+    assert_eq!(exhausted.window_secs, 3600);
+    assert_eq!(exhausted.spent_usd, 1.0);
+    assert_eq!(exhausted.remaining_usd, 0.0);
+    assert_eq!(exhausted.resets_at, "2026-03-10T01:00:00Z");
+
+    // The hourly window resets; the daily one keeps counting
+    let later = now + HOUR;
+    assert!(budgets.check("acme", later).is_none());
+    let status = budgets.tenant_status("acme", later);
+    assert_eq!(status[0].spent_usd, 0.0);
+    assert_eq!(status[1].spent_usd, 1.0);
+    assert_eq!(status[1].resets_at, "2026-03-11T00:00:00Z");
+
+    budgets.record("globex", 0.5, now);
+    assert!(budgets.check("globex", now).is_some());
+    let tenants: Vec<_> = budgets
+        .status(now)
+        .into_iter()
+        .map(|status| status.tenant)
+        .collect();
+    assert_eq!(tenants, ["acme", "acme", "globex"]);
+
+    // A new policy with the same windows keeps the spend so far
+    budgets.set_policy(BudgetPolicy::new().with_default_budget(2.0, DAY));
+    assert_eq!(budgets.tenant_status("acme", now)[0].spent_usd, 1.0);
+    assert!(budgets.check("acme", now).is_none());
+}
@@ -0,0 +1,2122 @@
+use langspec::budget::BudgetError;
+use langspec::config::{ChangeKind, ConfigError, ConfigStore, GatewayConfig, TenantSections};
+use langspec::pipeline::Pipeline;
+use langspec::pipeline::deprecation::{DeprecationError, DeprecationOutcome, SunsetAction};
+use langspec::pipeline::mock::MockError;
+use langspec::pipeline::pricing::PricingError;
+use langspec::pipeline::rate_limit::{RateLimitError, RateLimitScope};
+use langspec::pipeline::transform::TransformError;
+use langspec::pipeline::usage::Usage;
+use langspec::pipeline::views::RequestView;
+use langspec::provider::ProviderKind;
+use langspec::proxy::template::TemplateVars;
+use pingora_http::RequestHeader;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn config_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "langspec-config-{}-{}.yaml",
+        name,
+        std::process::id()
+    ))
+}
+
+fn detect(pipeline: &Pipeline, host: &str) -> ProviderKind {
+    let mut request = RequestHeader::build("POST", b"/v1/chat/completions", None).unwrap();
+    request.insert_header("host", host).unwrap();
+    pipeline
+        .provider_registry()
+        .detect(&RequestView::new(&request))
+}
+
+#[test]
+fn test_config_custom_providers() {
+    let config = GatewayConfig::from_yaml(
+        r#"
+providers:
+  - name: acme-llm
+    hosts: [llm.acme.internal]
+    paths: ['^/api/v\d+/generate$']
+    auth:
+      - header: x-acme-key
+    confidence:
+      auth: high
+"#,
+    )
+    .unwrap();
+    let registry = config.provider_registry().unwrap();
+
+    let mut request = RequestHeader::build("POST", b"/v1/chat/completions", None).unwrap();
+    request.insert_header("host", "gateway.local").unwrap();
+    request.insert_header("x-acme-key", "secret").unwrap();
+    request
+        .insert_header("authorization", "Bearer sk-test")
+        .unwrap();
+    let result = registry.detect_result(&RequestView::new(&request)).unwrap();
+    assert_eq!(result.kind, ProviderKind::Custom("acme-llm"));
+    assert_eq!(result.signal, "auth");
+
+    let mut request = RequestHeader::build("POST", b"/v1/chat/completions", None).unwrap();
+    request.insert_header("host", "api.openai.com").unwrap();
+    assert_eq!(
+        registry.detect(&RequestView::new(&request)),
+        ProviderKind::OpenAI
+    );
+}
+
+#[test]
+fn test_config_errors() {
+    let duplicate = GatewayConfig::from_yaml(
+        "
+providers:
+  - name: acme
+    hosts: [a.internal]
+  - name: acme
+    hosts: [b.internal]
+",
+    )
+    .unwrap();
+    assert!(matches!(
+        duplicate.provider_registry(),
+        Err(ConfigError::DuplicateProvider(name)) if name == "acme"
+    ));
+
+    let invalid = GatewayConfig::from_yaml("providers:\n  - name: acme\n    paths: ['(']\n")
+        .unwrap()
+        .provider_registry()
+        .err()
+        .unwrap();
+    assert!(invalid.to_string().contains("invalid path pattern '('"));
+
+    assert!(matches!(
+        GatewayConfig::from_yaml("upstreams: []\n"),
+        Err(ConfigError::Yaml(_))
+    ));
+    assert!(matches!(
+        GatewayConfig::load("/nonexistent/langspec.yaml"),
+        Err(ConfigError::Io(..))
+    ));
+    assert!(GatewayConfig::from_yaml("{}").unwrap().providers.is_empty());
+}
+
+#[test]
+fn test_config_model_deprecations() {
+    let config = GatewayConfig::from_yaml(
+        r#"
+deprecations:
+  - model: gpt-4-0613
+    deprecated_since: 2026-01-01
+    sunset: 2026-06-30T12:00:00Z
+    replacement: gpt-4o
+    link: https://docs.example.com/migrate
+  - model: claude-2*
+    sunset: 2026-03-01
+  - model: text-davinci-003
+    sunset: 2026-03-01
+    replacement: gpt-4o-mini
+    on_sunset: reject
+"#,
+    )
+    .unwrap();
+    let policy = config.deprecation_policy().unwrap();
+    let gpt4 = policy.lookup("gpt-4-0613").unwrap();
+    assert_eq!(gpt4.on_sunset, SunsetAction::Rewrite);
+    assert_eq!(gpt4.sunset_header(), "Tue, 30 Jun 2026 12:00:00 GMT");
+    assert_eq!(
+        policy.lookup("claude-2.0").unwrap().on_sunset,
+        SunsetAction::Reject
+    );
+    let davinci = policy.lookup("text-davinci-003").unwrap();
+    assert_eq!(
+        davinci.outcome(davinci.sunset),
+        DeprecationOutcome::Rejected
+    );
+
+    let invalid = GatewayConfig::from_yaml("deprecations:\n  - model: a\n    sunset: soon\n")
+        .unwrap()
+        .deprecation_policy()
+        .unwrap_err();
+    assert!(matches!(
+        invalid,
+        ConfigError::Deprecation(DeprecationError::InvalidDate(model, date))
+            if model == "a" && date == "soon"
+    ));
+    let no_replacement = GatewayConfig::from_yaml(
+        "deprecations:\n  - model: a\n    sunset: 2026-01-01\n    on_sunset: rewrite\n",
+    )
+    .unwrap()
+    .deprecation_policy()
+    .unwrap_err();
+    assert!(no_replacement.to_string().contains("has no replacement"));
+    let duplicate = GatewayConfig::from_yaml(
+        "deprecations:\n  - model: a\n    sunset: 2026-01-01\n  - model: a\n    sunset: 2026-02-01\n",
+    )
+    .unwrap()
+    .deprecation_policy()
+    .unwrap_err();
+    assert!(matches!(
+        duplicate,
+        ConfigError::Deprecation(DeprecationError::DuplicateModel(model)) if model == "a"
+    ));
+}
+
+#[test]
+fn test_config_reload_applies_deprecations() {
+    let path = config_file("deprecations");
+    std::fs::write(&path, "providers: []\n").unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    assert!(pipeline.deprecation_policy().is_empty());
+
+    std::fs::write(
+        &path,
+        "deprecations:\n  - model: gpt-4-0613\n    sunset: 2026-06-30\n    replacement: gpt-4o\n",
+    )
+    .unwrap();
+    let version = store.reload().unwrap();
+    assert_eq!(version.diff.changes[0].path, "deprecations");
+    assert!(pipeline.deprecation_policy().lookup("gpt-4-0613").is_some());
+
+    std::fs::write(
+        &path,
+        "deprecations:\n  - model: gpt-4-0613\n    sunset: 2026-07-31\n    replacement: gpt-4o\n",
+    )
+    .unwrap();
+    let version = store.reload().unwrap();
+    assert_eq!(
+        version.diff.changes[0].path,
+        "deprecations.gpt-4-0613.sunset"
+    );
+    assert_eq!(
+        pipeline
+            .deprecation_policy()
+            .lookup("gpt-4-0613")
+            .unwrap()
+            .sunset_header(),
+        "Fri, 31 Jul 2026 00:00:00 GMT"
+    );
+
+    // An invalid policy leaves the applied one in place
+    std::fs::write(
+        &path,
+        "deprecations:\n  - model: gpt-4-0613\n    sunset: soon\n",
+    )
+    .unwrap();
+    assert!(matches!(store.reload(), Err(ConfigError::Deprecation(_))));
+    assert!(pipeline.deprecation_policy().lookup("gpt-4-0613").is_some());
+
+    store.rollback().unwrap();
+    assert_eq!(
+        pipeline
+            .deprecation_policy()
+            .lookup("gpt-4-0613")
+            .unwrap()
+            .sunset_header(),
+        "Tue, 30 Jun 2026 00:00:00 GMT"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_detection_tuning() {
+    let path = config_file("detection");
+    std::fs::write(
+        &path,
+        "detection:\n  - provider: openai\n    hosts: [openai.proxy.internal]\n",
+    )
+    .unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    assert_eq!(
+        detect(&pipeline, "openai.proxy.internal"),
+        ProviderKind::OpenAI
+    );
+    let registry = pipeline.provider_registry_snapshots().current();
+    assert_eq!(registry.config.detection[0].provider, "openai");
+
+    // Reloads tune detection without a restart, as a new registry version
+    std::fs::write(
+        &path,
+        "detection:\n  - provider: self-hosted\n    hosts: [vllm.internal]\n",
+    )
+    .unwrap();
+    store.reload().unwrap();
+    assert_eq!(detect(&pipeline, "vllm.internal"), ProviderKind::SelfHosted);
+    assert_eq!(
+        pipeline.provider_registry_snapshots().current().version,
+        registry.version + 1
+    );
+
+    std::fs::write(
+        &path,
+        "detection:\n  - provider: acme\n    hosts: [a.internal]\n",
+    )
+    .unwrap();
+    assert!(matches!(store.reload(), Err(ConfigError::Detection(_))));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_reload_diff_and_rollback() {
+    let path = config_file("reload");
+    std::fs::write(
+        &path,
+        "providers:\n  - name: acme\n    hosts: [a.internal]\n",
+    )
+    .unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline))
+        .unwrap()
+        .with_max_versions(2);
+    let acme = ProviderKind::Custom("acme");
+    assert_eq!(detect(&pipeline, "a.internal"), acme);
+    let startup = store.current();
+    assert_eq!(startup.version, 1);
+    assert_eq!(startup.diff.changes[0].path, "providers.acme");
+    assert_eq!(startup.diff.changes[0].change, ChangeKind::Added);
+
+    // Unchanged file: no new version
+    assert_eq!(store.reload().unwrap().version, 1);
+
+    std::fs::write(
+        &path,
+        "providers:\n  - name: acme\n    hosts: [b.internal]\n  - name: beta\n    paths: ['^/beta/']\n",
+    )
+    .unwrap();
+    let version = store.reload().unwrap();
+    assert_eq!(version.version, 2);
+    let changes: Vec<(&str, ChangeKind)> = version
+        .diff
+        .changes
+        .iter()
+        .map(|change| (change.path.as_str(), change.change))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            ("providers.acme.hosts", ChangeKind::Changed),
+            ("providers.beta", ChangeKind::Added)
+        ]
+    );
+    assert_eq!(
+        version.diff.changes[0].before,
+        Some(serde_json::json!(["a.internal"]))
+    );
+    assert_eq!(detect(&pipeline, "b.internal"), acme);
+    assert_eq!(detect(&pipeline, "a.internal"), ProviderKind::OpenAI);
+
+    // An invalid file keeps the applied version
+    std::fs::write(
+        &path,
+        "providers:\n  - name: openai\n    hosts: [c.internal]\n",
+    )
+    .unwrap();
+    assert!(matches!(store.reload(), Err(ConfigError::Provider(_))));
+    assert_eq!(store.current().version, 2);
+
+    let rollback = store.rollback().unwrap();
+    assert_eq!((rollback.from, rollback.to), (2, 1));
+    assert_eq!(rollback.diff.changes.len(), 2);
+    assert_eq!(detect(&pipeline, "a.internal"), acme);
+    assert_eq!(store.versions().len(), 1);
+    assert!(matches!(
+        store.rollback(),
+        Err(ConfigError::NoPreviousVersion)
+    ));
+
+    // Only the last 2 versions are kept
+    for host in ["d.internal", "e.internal", "f.internal"] {
+        std::fs::write(
+            &path,
+            format!("providers:\n  - name: acme\n    hosts: [{}]\n", host),
+        )
+        .unwrap();
+        store.reload().unwrap();
+    }
+    let versions: Vec<u64> = store.versions().iter().map(|v| v.version).collect();
+    assert_eq!(versions, [5, 4]);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "admin")]
+#[test]
+fn test_admin_config_endpoints() {
+    use langspec::proxy::GatewayProxy;
+
+    let proxy = GatewayProxy::new(vec!["127.0.0.1:8001".to_string()]);
+    assert_eq!(
+        proxy.admin_app().handle("GET", "/config/versions").status(),
+        404
+    );
+
+    let path = config_file("admin");
+    std::fs::write(&path, "providers: []\n").unwrap();
+    let proxy = proxy.with_config_file(&path).unwrap();
+    let admin = proxy.admin_app();
+    assert_eq!(admin.handle("POST", "/config/rollback").status(), 409);
+
+    std::fs::write(
+        &path,
+        "providers:\n  - name: acme\n    hosts: [a.internal]\n",
+    )
+    .unwrap();
+    let response = admin.handle("POST", "/config/reload");
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["version"], 2);
+    assert_eq!(body["diff"][0]["path"], "providers.acme");
+    assert_eq!(body["diff"][0]["change"], "added");
+
+    let response = admin.handle("GET", "/config/diff");
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body[0]["path"], "providers.acme");
+    let response = admin.handle("GET", "/config/versions");
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body.as_array().unwrap().len(), 2);
+
+    std::fs::write(&path, "upstreams: []\n").unwrap();
+    assert_eq!(admin.handle("POST", "/config/reload").status(), 422);
+    assert_eq!(admin.handle("GET", "/config/reload").status(), 405);
+
+    let response = admin.handle("POST", "/config/rollback");
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(
+        (body["from"].clone(), body["to"].clone()),
+        (2.into(), 1.into())
+    );
+    assert_eq!(body["diff"][0]["change"], "removed");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_pricing() {
+    let usage = Usage {
+        prompt_tokens: 1_000_000,
+        completion_tokens: 1_000_000,
+        estimated: false,
+    };
+    let config = GatewayConfig::from_yaml(
+        r#"
+pricing:
+  models:
+    - provider: openai
+      model: gpt-4o
+      prompt: 2.0
+      completion: 8.0
+    - provider: acme-llm
+      model: acme-large
+      prompt: 1.0
+      completion: 1.0
+  tenants:
+    - name: reseller
+      markup_percent: 50
+"#,
+    )
+    .unwrap();
+    let pricing = config.pricing().unwrap().unwrap();
+    let cost = |tenant, provider, model| pricing.cost(tenant, provider, model, &usage);
+    // Listed prices replace the defaults, which price the other models
+    assert_eq!(
+        cost(None, ProviderKind::OpenAI, "gpt-4o-2024-08-06"),
+        Some(10.0)
+    );
+    assert_eq!(cost(None, ProviderKind::OpenAI, "gpt-4o-mini"), Some(0.75));
+    assert_eq!(
+        cost(Some("reseller"), ProviderKind::OpenAI, "gpt-4o"),
+        Some(15.0)
+    );
+    assert_eq!(
+        cost(None, ProviderKind::Custom("acme-llm"), "acme-large"),
+        Some(2.0)
+    );
+
+    let without_defaults = GatewayConfig::from_yaml("pricing:\n  default_prices: false\n").unwrap();
+    let pricing = without_defaults.pricing().unwrap().unwrap();
+    assert_eq!(
+        pricing.cost(None, ProviderKind::OpenAI, "gpt-4o", &usage),
+        None
+    );
+    assert!(GatewayConfig::default().pricing().unwrap().is_none());
+
+    let invalid = |yaml| {
+        GatewayConfig::from_yaml(yaml)
+            .unwrap()
+            .pricing()
+            .unwrap_err()
+    };
+    assert!(matches!(
+        invalid("pricing:\n  models:\n    - {provider: openai, model: o1, prompt: -1, completion: 1}\n"),
+        ConfigError::Pricing(PricingError::InvalidPrice(provider, model))
+            if provider == "openai" && model == "o1"
+    ));
+    assert!(matches!(
+        invalid("pricing:\n  tenants:\n    - {name: a, markup_percent: -100}\n"),
+        ConfigError::Pricing(PricingError::InvalidMarkup(..))
+    ));
+    assert!(matches!(
+        invalid("pricing:\n  tenants:\n    - name: a\n    - name: a\n"),
+        ConfigError::Pricing(PricingError::DuplicateTenant(tenant)) if tenant == "a"
+    ));
+}
+
+#[test]
+fn test_config_reload_applies_pricing() {
+    let path = config_file("pricing");
+    std::fs::write(&path, "providers: []\n").unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    assert!(pipeline.pricing().is_none());
+
+    std::fs::write(
+        &path,
+        "pricing:\n  models:\n    - {provider: openai, model: gpt-4o, prompt: 2, completion: 8}\n",
+    )
+    .unwrap();
+    store.reload().unwrap();
+    let price = |pipeline: &Pipeline| {
+        pipeline
+            .pricing()
+            .and_then(|pricing| pricing.price(None, ProviderKind::OpenAI, "gpt-4o"))
+            .map(|price| price.prompt)
+    };
+    assert_eq!(price(&pipeline), Some(2.0));
+
+    std::fs::write(
+        &path,
+        "pricing:\n  models:\n    - {provider: openai, model: gpt-4o, prompt: 3, completion: 8}\n",
+    )
+    .unwrap();
+    let version = store.reload().unwrap();
+    assert_eq!(version.diff.changes[0].path, "pricing.models.gpt-4o.prompt");
+    assert_eq!(price(&pipeline), Some(3.0));
+
+    store.rollback().unwrap();
+    assert_eq!(price(&pipeline), Some(2.0));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_mock_routes() {
+    let config = GatewayConfig::from_yaml(
+        r#"
+mocks:
+  - name: chat
+    path: /v1/chat/completions
+    model: mock-*
+    content: Hello from the mock
+    stream: true
+    latency_ms: 250
+  - name: overloaded
+    path: /v1/chat/completions
+    status: 503
+    response: {"error": {"message": "overloaded"}}
+"#,
+    )
+    .unwrap();
+    let mocks = config.mock_routes().unwrap();
+    let chat = mocks
+        .lookup("/v1/chat/completions", Some("mock-large"))
+        .unwrap();
+    assert_eq!(chat.stream, Some(true));
+    assert_eq!(chat.latency.as_millis(), 250);
+    let overloaded = mocks
+        .lookup("/v1/chat/completions", Some("gpt-4o"))
+        .unwrap();
+    assert_eq!(overloaded.status, 503);
+
+    let invalid = |yaml| {
+        GatewayConfig::from_yaml(yaml)
+            .unwrap()
+            .mock_routes()
+            .unwrap_err()
+    };
+    assert!(matches!(
+        invalid("mocks:\n  - {name: a, path: v1/chat}\n"),
+        ConfigError::Mock(MockError::InvalidPath(..))
+    ));
+    assert!(matches!(
+        invalid("mocks:\n  - {name: a, path: /v1, status: 999}\n"),
+        ConfigError::Mock(MockError::InvalidStatus(_, 999))
+    ));
+    assert!(matches!(
+        invalid("mocks:\n  - {name: a, path: /v1, stream: true, response: {}}\n"),
+        ConfigError::Mock(MockError::StreamedResponse(_))
+    ));
+    assert!(matches!(
+        invalid("mocks:\n  - {name: a, path: /v1}\n  - {name: a, path: /v2}\n"),
+        ConfigError::Mock(MockError::DuplicateName(name)) if name == "a"
+    ));
+
+    // Applied with the config, and replaced on reload
+    let path = config_file("mocks");
+    std::fs::write(
+        &path,
+        "mocks:\n  - {name: chat, path: /v1/chat/completions}\n",
+    )
+    .unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    assert!(pipeline.mock_routes().has_path("/v1/chat/completions"));
+    std::fs::write(&path, "mocks: []\n").unwrap();
+    let version = store.reload().unwrap();
+    assert_eq!(version.diff.changes[0].path, "mocks");
+    assert!(pipeline.mock_routes().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_budgets() {
+    let config = GatewayConfig::from_yaml(
+        r#"
+budgets:
+  - tenant: acme
+    limit_usd: 100
+    window_secs: 86400
+  - tenant: "*"
+    limit_usd: 10
+    window_secs: 3600
+"#,
+    )
+    .unwrap();
+    let policy = config.budget_policy().unwrap();
+    assert_eq!(policy.budgets_for("acme")[0].limit_usd, 100.0);
+    let default = policy.budgets_for("globex")[0];
+    assert_eq!(default.tenant, None);
+    assert_eq!(default.window.as_secs(), 3600);
+
+    let invalid = |yaml| {
+        GatewayConfig::from_yaml(yaml)
+            .unwrap()
+            .budget_policy()
+            .unwrap_err()
+    };
+    assert!(matches!(
+        invalid("budgets:\n  - {tenant: a, limit_usd: -1, window_secs: 60}\n"),
+        ConfigError::Budget(BudgetError::InvalidLimit(..))
+    ));
+    assert!(matches!(
+        invalid("budgets:\n  - {tenant: a, limit_usd: 1, window_secs: 0}\n"),
+        ConfigError::Budget(BudgetError::InvalidWindow(tenant)) if tenant == "a"
+    ));
+
+    let path = config_file("budgets");
+    std::fs::write(
+        &path,
+        "pricing: {}\nbudgets:\n  - {tenant: acme, limit_usd: 1, window_secs: 60}\n",
+    )
+    .unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    assert_eq!(pipeline.spend_budgets().policy().budgets.len(), 1);
+    std::fs::write(&path, "providers: []\n").unwrap();
+    store.reload().unwrap();
+    assert!(pipeline.spend_budgets().policy().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_rate_limits() {
+    let config = GatewayConfig::from_yaml(
+        r#"
+rate_limits:
+  - name: partners
+    key: "partner-*"
+    requests_per_minute: 600
+    burst: 20
+  - name: openai
+    provider: openai
+    requests_per_minute: 3000
+  - name: gateway
+    requests_per_minute: 60000
+"#,
+    )
+    .unwrap();
+    let policy = config.rate_limit_policy().unwrap();
+    let limits = policy.limits_for("partner-acme", "openai");
+    assert_eq!(limits.len(), 3);
+    assert_eq!(limits[0].scope, RateLimitScope::Key("partner-*".into()));
+    assert_eq!(limits[0].requests_per_sec, 10.0);
+    assert_eq!(limits[0].burst, 20);
+    assert_eq!(limits[1].burst, 50);
+    assert_eq!(limits[2].scope, RateLimitScope::Global);
+    assert_eq!(policy.limits_for("10.0.0.1", "cohere").len(), 1);
+
+    let invalid = |yaml| {
+        GatewayConfig::from_yaml(yaml)
+            .unwrap()
+            .rate_limit_policy()
+            .unwrap_err()
+    };
+    assert!(matches!(
+        invalid("rate_limits:\n  - {name: a, requests_per_minute: 0}\n"),
+        ConfigError::RateLimit(RateLimitError::InvalidRate(..))
+    ));
+    assert!(matches!(
+        invalid("rate_limits:\n  - {name: a, key: k, provider: openai, requests_per_minute: 1}\n"),
+        ConfigError::RateLimit(RateLimitError::AmbiguousScope(name)) if name == "a"
+    ));
+    assert!(matches!(
+        invalid(
+            "rate_limits:\n  - {name: a, requests_per_minute: 1}\n  - {name: a, requests_per_minute: 2}\n"
+        ),
+        ConfigError::RateLimit(RateLimitError::DuplicateName(_))
+    ));
+
+    let path = config_file("rate_limits");
+    std::fs::write(
+        &path,
+        "rate_limits:\n  - {name: gateway, requests_per_minute: 60}\n",
+    )
+    .unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    assert_eq!(pipeline.rate_limiter().policy().limits.len(), 1);
+    std::fs::write(&path, "providers: []\n").unwrap();
+    store.reload().unwrap();
+    assert!(pipeline.rate_limiter().policy().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_reload_checks_references() {
+    let path = config_file("references");
+    std::fs::write(&path, "providers: []\n").unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+
+    std::fs::write(
+        &path,
+        r#"
+providers:
+  - name: acme-llm
+    hosts: [llm.acme.internal]
+pricing:
+  models:
+    - {provider: acme-llm, model: acme-large, prompt: 1, completion: 2}
+    - {provider: acme, model: acme-small, prompt: 1, completion: 2}
+  tenants:
+    - name: globex
+      models:
+        - {provider: openia, model: gpt-4o, prompt: 1, completion: 2}
+deprecations:
+  - {model: gpt-3.5-turbo, sunset: 2026-01-01, replacement: gpt-4o-mini}
+  - {model: acme-medium, sunset: 2026-01-01, replacement: acme-xl}
+  - {model: gpt-4-32k, sunset: 2026-01-01, replacement: gpt-3.5-turbo}
+rate_limits:
+  - {name: acme, provider: acme-llm, requests_per_minute: 60}
+  - {name: typo, provider: coher, requests_per_minute: 60}
+mocks:
+  - {name: chat, path: /v1/chat/completions, content: hi}
+"#,
+    )
+    .unwrap();
+    let Err(ConfigError::References(issues)) = store.reload() else {
+        panic!("broken references are rejected");
+    };
+    let paths: Vec<&str> = issues.iter().map(|issue| issue.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        [
+            "pricing.models.acme-small",
+            "pricing.tenants.globex.models.gpt-4o",
+            "rate_limits.typo.provider",
+            "deprecations.acme-medium.replacement",
+            "deprecations.gpt-4-32k.replacement",
+        ]
+    );
+    assert!(issues[3].message.contains("has no price"));
+    assert!(issues[4].message.contains("itself deprecated"));
+    let report = ConfigError::References(issues).to_string();
+    assert!(report.starts_with("invalid config: 5 broken references"));
+    assert!(report.contains("\n- rate_limits.typo.provider: unknown provider 'coher'"));
+
+    // Nothing of the rejected version was applied
+    assert_eq!(store.current().version, 1);
+    assert!(pipeline.pricing().is_none());
+    assert!(pipeline.mock_routes().is_empty());
+    assert!(pipeline.rate_limiter().policy().is_empty());
+
+    std::fs::write(
+        &path,
+        "budgets:\n  - {tenant: acme, limit_usd: 1, window_secs: 60}\n",
+    )
+    .unwrap();
+    assert!(matches!(
+        store.reload(),
+        Err(ConfigError::References(issues)) if issues[0].path == "budgets.acme"
+    ));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(feature = "virtual-keys")]
+fn test_config_virtual_keys() {
+    use langspec::proxy::virtual_keys::{
+        KeyRejection, VirtualKeyError, VirtualKeys, hash_key, request_key,
+    };
+
+    let issued = VirtualKeys::issue();
+    assert!(issued.key.starts_with("lsk_"));
+    assert_eq!(issued.key_hash, hash_key(&issued.key));
+    assert_ne!(VirtualKeys::issue().key, issued.key);
+
+    let yaml = format!(
+        r#"
+pricing: {{}}
+virtual_keys:
+  - name: checkout
+    key_hash: {}
+    models: [gpt-4o-mini, "claude-*"]
+    requests_per_minute: 120
+    budget: {{limit_usd: 25, window_secs: 86400}}
+  - name: batch
+    key_hash: {}
+rate_limits:
+  - {{name: everyone, key: "*", requests_per_minute: 60}}
+"#,
+        issued.key_hash,
+        hash_key("lsk_batch")
+    );
+    let config = GatewayConfig::from_yaml(&yaml).unwrap();
+
+    // A key's own rate limit and budget apply to the tenant it makes requests as
+    let policy = config.rate_limit_policy().unwrap();
+    let limits = policy.limits_for("checkout", "openai");
+    assert_eq!(limits.len(), 1);
+    assert_eq!(limits[0].name, "virtual_key:checkout");
+    assert_eq!(limits[0].requests_per_sec, 2.0);
+    assert_eq!(policy.limits_for("batch", "openai")[0].name, "everyone");
+    let budgets = config.budget_policy().unwrap();
+    assert_eq!(budgets.budgets_for("checkout")[0].limit_usd, 25.0);
+    assert!(budgets.budgets_for("batch").is_empty());
+
+    let keys = config.virtual_keys().unwrap();
+    let key = keys.authenticate(&issued.key).unwrap();
+    assert_eq!(key.name, "checkout");
+    assert!(key.credential.is_none());
+    assert_eq!(key.authorize(Some("claude-3-5-haiku")), Ok(()));
+    assert_eq!(
+        key.authorize(Some("gpt-4o")),
+        Err(KeyRejection::ModelNotAllowed("gpt-4o".to_string()))
+    );
+    assert_eq!(
+        keys.authenticate("lsk_batch").unwrap().authorize(None),
+        Ok(())
+    );
+    assert_eq!(
+        keys.authenticate("lsk_unknown").unwrap_err(),
+        KeyRejection::UnknownKey
+    );
+    let names: Vec<String> = keys.summaries().into_iter().map(|key| key.name).collect();
+    assert_eq!(names, ["batch", "checkout"]);
+
+    // Presented as the SDK's API key or in the gateway's own header
+    let mut request = RequestHeader::build("POST", b"/v1/chat/completions", None).unwrap();
+    request
+        .insert_header("Authorization", "Bearer sk-provider-key")
+        .unwrap();
+    assert_eq!(request_key(&request), None);
+    request
+        .insert_header("Authorization", format!("Bearer {}", issued.key))
+        .unwrap();
+    assert_eq!(request_key(&request), Some(issued.key.as_str()));
+    request
+        .insert_header("X-Langspec-Key", "lsk_batch")
+        .unwrap();
+    assert_eq!(request_key(&request), Some("lsk_batch"));
+
+    // Upstream keys come from the environment, never from the config file
+    let with_upstream_key = |header: &str| {
+        GatewayConfig::from_yaml(&format!(
+            "virtual_keys:\n  - name: batch\n    key_hash: {}\n    upstream_key: {{env: BATCH_OPENAI_KEY{}}}\n",
+            hash_key("lsk_batch"),
+            header
+        ))
+        .unwrap()
+        .virtual_keys
+    };
+    let env = |name: &str| (name == "BATCH_OPENAI_KEY").then(|| "sk-batch".to_string());
+    let keys = VirtualKeys::compile_with_env(&with_upstream_key(""), env).unwrap();
+    let credential = keys
+        .authenticate("lsk_batch")
+        .unwrap()
+        .credential
+        .clone()
+        .unwrap();
+    assert_eq!(credential.header(), ("authorization", "Bearer sk-batch"));
+    assert!(!format!("{:?}", credential).contains("sk-batch"));
+    let keys =
+        VirtualKeys::compile_with_env(&with_upstream_key(", header: x-api-key"), env).unwrap();
+    let credential = keys
+        .authenticate("lsk_batch")
+        .unwrap()
+        .credential
+        .clone()
+        .unwrap();
+    assert_eq!(credential.header(), ("x-api-key", "sk-batch"));
+    assert_eq!(
+        VirtualKeys::compile_with_env(&with_upstream_key(""), |_| None).unwrap_err(),
+        VirtualKeyError::MissingUpstreamKey("batch".to_string(), "BATCH_OPENAI_KEY".to_string())
+    );
+
+    let invalid = |yaml: &str| {
+        GatewayConfig::from_yaml(yaml)
+            .unwrap()
+            .virtual_keys()
+            .unwrap_err()
+    };
+    assert!(matches!(
+        invalid("virtual_keys:\n  - {name: a, key_hash: abc}\n"),
+        ConfigError::VirtualKey(VirtualKeyError::InvalidHash(name)) if name == "a"
+    ));
+    let hash = hash_key("lsk_a");
+    assert!(matches!(
+        invalid(&format!(
+            "virtual_keys:\n  - {{name: \"team-*\", key_hash: {}}}\n",
+            hash
+        )),
+        ConfigError::VirtualKey(VirtualKeyError::InvalidName(_))
+    ));
+    assert!(matches!(
+        invalid(&format!(
+            "virtual_keys:\n  - {{name: a, key_hash: {0}}}\n  - {{name: b, key_hash: {0}}}\n",
+            hash
+        )),
+        ConfigError::VirtualKey(VirtualKeyError::DuplicateHash(name)) if name == "b"
+    ));
+
+    // Applied with the rest of the config, and budgets of keys need prices too
+    let path = config_file("virtual_keys");
+    std::fs::write(&path, &yaml).unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    assert!(pipeline.virtual_keys().authenticate(&issued.key).is_ok());
+    std::fs::write(&path, yaml.replace("pricing: {}\n", "")).unwrap();
+    assert!(matches!(
+        store.reload(),
+        Err(ConfigError::References(issues)) if issues[0].path == "virtual_keys.checkout.budget"
+    ));
+    std::fs::write(&path, "providers: []\n").unwrap();
+    store.reload().unwrap();
+    assert!(pipeline.virtual_keys().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_response_transforms() {
+    let config = GatewayConfig::from_yaml(
+        r#"
+response_transforms:
+  - name: house-style
+    path: /v1/chat/*
+    tenant: acme
+    model: gpt-4o*
+    steps:
+      - remove: $.system_fingerprint
+      - remove: $.choices[*].logprobs
+      - rename: {from: $.usage, to: $.token_usage}
+      - wrap: data
+      - set:
+          path: $.meta
+          value: {gateway: langspec, tenant: "{tenant}", served_by: "{provider}/{model}", version: 2}
+"#,
+    )
+    .unwrap();
+    let transforms = config.response_transforms().unwrap();
+    assert!(
+        transforms
+            .lookup("/v1/chat/completions", Some("globex"), Some("gpt-4o"))
+            .is_none()
+    );
+    assert!(
+        transforms
+            .lookup("/v1/embeddings", Some("acme"), Some("gpt-4o"))
+            .is_none()
+    );
+    let transform = transforms
+        .lookup("/v1/chat/completions", Some("acme"), Some("gpt-4o-mini"))
+        .unwrap();
+
+    let response = serde_json::json!({
+        "id": "chatcmpl-1",
+        "system_fingerprint": "fp_1",
+        "choices": [
+            {"index": 0, "message": {"content": "hi"}, "logprobs": null},
+            {"index": 1, "message": {"content": "hey"}, "logprobs": null}
+        ],
+        "usage": {"prompt_tokens": 3, "completion_tokens": 1}
+    });
+    let vars = TemplateVars {
+        tenant: Some("acme"),
+        provider: Some("openai"),
+        model: Some("gpt-4o-mini"),
+        ..TemplateVars::default()
+    };
+    let transformed = transform
+        .apply_to_body(&serde_json::to_vec(&response).unwrap(), &vars)
+        .unwrap();
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&transformed).unwrap(),
+        serde_json::json!({
+            "data": {
+                "id": "chatcmpl-1",
+                "choices": [
+                    {"index": 0, "message": {"content": "hi"}},
+                    {"index": 1, "message": {"content": "hey"}}
+                ],
+                "token_usage": {"prompt_tokens": 3, "completion_tokens": 1}
+            },
+            "meta": {
+                "gateway": "langspec",
+                "tenant": "acme",
+                "served_by": "openai/gpt-4o-mini",
+                "version": 2
+            }
+        })
+    );
+    assert!(transform.apply_to_body(b"not json", &vars).is_none());
+
+    let invalid = |yaml: &str| {
+        GatewayConfig::from_yaml(yaml)
+            .unwrap()
+            .response_transforms()
+            .unwrap_err()
+    };
+    assert!(matches!(
+        invalid("response_transforms:\n  - {name: a, path: v1, steps: []}\n"),
+        ConfigError::Transform(TransformError::InvalidPath(..))
+    ));
+    assert!(matches!(
+        invalid("response_transforms:\n  - {name: a, path: /v1, steps: [{remove: usage}]}\n"),
+        ConfigError::Transform(TransformError::InvalidJsonPath(_, path, _)) if path == "usage"
+    ));
+    assert!(matches!(
+        invalid("response_transforms:\n  - {name: a, path: /v1, steps: [{remove: $}]}\n"),
+        ConfigError::Transform(TransformError::InvalidStep(..))
+    ));
+    assert!(matches!(
+        invalid(
+            "response_transforms:\n  - {name: a, path: /v1, steps: [{rename: {from: \"$.choices[*].text\", to: $.text}}]}\n"
+        ),
+        ConfigError::Transform(TransformError::InvalidStep(..))
+    ));
+    assert!(matches!(
+        invalid(
+            "response_transforms:\n  - {name: a, path: /v1, steps: [{set: {path: $.x, value: \"{user}\"}}]}\n"
+        ),
+        ConfigError::Transform(TransformError::InvalidTemplate(..))
+    ));
+    assert!(matches!(
+        invalid("response_transforms:\n  - {name: a, path: /v1, steps: []}\n  - {name: a, path: /v2, steps: []}\n"),
+        ConfigError::Transform(TransformError::DuplicateName(name)) if name == "a"
+    ));
+
+    // Applied with the config, and replaced on reload
+    let path = config_file("response_transforms");
+    std::fs::write(
+        &path,
+        "response_transforms:\n  - {name: a, path: /v1/chat/completions, steps: [{wrap: data}]}\n",
+    )
+    .unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    assert!(!pipeline.response_transforms().is_empty());
+    std::fs::write(&path, "response_transforms: []\n").unwrap();
+    store.reload().unwrap();
+    assert!(pipeline.response_transforms().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_credential_routes() {
+    use langspec::upstream::{
+        CredentialRouteError, SecretBackend, SecretError, SecretRef, SecretStore,
+    };
+
+    let key_file = config_file("cohere-key");
+    std::fs::write(&key_file, "co-file\n").unwrap();
+    let yaml = format!(
+        r#"
+credentials:
+  - name: cohere
+    provider: cohere
+    secret: "file:{}"
+    header: x-client-key
+  - name: batch
+    path: /v1/batches*
+    secret: vault:openai/batch
+  - name: openai
+    provider: openai
+    secret: env:LANGSPEC_TEST_OPENAI_KEY
+"#,
+        key_file.display()
+    );
+    let config = GatewayConfig::from_yaml(&yaml).unwrap();
+    let routes = config.credential_routes().unwrap();
+    let route = |path: &str, provider: &str| {
+        routes
+            .lookup(path, provider)
+            .map(|route| route.name.as_str())
+    };
+    assert_eq!(route("/v2/chat", "cohere"), Some("cohere"));
+    assert_eq!(route("/v1/batches/b_1", "openai"), Some("batch"));
+    assert_eq!(route("/v1/chat/completions", "openai"), Some("openai"));
+    assert_eq!(route("/v1/chat/completions", "mistral"), None);
+
+    struct Vault;
+    impl SecretBackend for Vault {
+        fn fetch(&self, name: &str) -> Result<String, String> {
+            match name {
+                "openai/batch" => Ok("sk-batch".to_string()),
+                _ => Err(format!("no secret at '{}'", name)),
+            }
+        }
+    }
+    let store = SecretStore::new().with_backend("vault", Vault);
+    let cohere = routes.lookup("/v2/chat", "cohere").unwrap();
+    let credential = cohere.credential(&store.resolve(&cohere.secret).unwrap());
+    assert_eq!(credential.header(), ("x-client-key", "co-file"));
+    let batch = routes.lookup("/v1/batches", "openai").unwrap();
+    let credential = batch.credential(&store.resolve(&batch.secret).unwrap());
+    assert_eq!(credential.header(), ("authorization", "Bearer sk-batch"));
+    assert_eq!(
+        store.resolve(&SecretRef::parse("vault:missing").unwrap()),
+        Err(SecretError::Backend(
+            "vault".to_string(),
+            "no secret at 'missing'".to_string()
+        ))
+    );
+    assert_eq!(
+        store.resolve(&SecretRef::parse("aws:openai").unwrap()),
+        Err(SecretError::UnknownBackend("aws".to_string()))
+    );
+    let openai = routes.lookup("/v1/chat/completions", "openai").unwrap();
+    assert_eq!(
+        store.resolve(&openai.secret),
+        Err(SecretError::EnvNotSet(
+            "LANGSPEC_TEST_OPENAI_KEY".to_string()
+        ))
+    );
+
+    // Rotated files are picked up once the cached key expires
+    let store = SecretStore::new().with_ttl(std::time::Duration::ZERO);
+    std::fs::write(&key_file, "co-rotated").unwrap();
+    assert_eq!(&*store.resolve(&cohere.secret).unwrap(), "co-rotated");
+    std::fs::write(&key_file, " ").unwrap();
+    assert!(matches!(
+        store.resolve(&cohere.secret),
+        Err(SecretError::Empty(_))
+    ));
+    std::fs::remove_file(&key_file).unwrap();
+
+    // Keys are only ever referenced, never written in the file
+    let invalid = |route: &str| {
+        let yaml = format!("credentials:\n  - {}\n", route);
+        match GatewayConfig::from_yaml(&yaml).unwrap().credential_routes() {
+            Err(ConfigError::Credentials(e)) => e,
+            other => panic!("expected a credentials error, got {:?}", other),
+        }
+    };
+    assert_eq!(
+        invalid("{name: openai, secret: sk-live-123}"),
+        CredentialRouteError::Secret(
+            "openai".to_string(),
+            SecretError::InvalidReference("sk-live-123".to_string())
+        )
+    );
+    assert!(matches!(
+        invalid("{name: openai, path: v1/chat, secret: env:KEY}"),
+        CredentialRouteError::InvalidPath(..)
+    ));
+
+    // Applied with the rest of the config; routes for unknown providers are broken
+    // references
+    let path = config_file("credentials");
+    std::fs::write(&path, &yaml).unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    assert_eq!(pipeline.credential_routes().routes.len(), 3);
+    std::fs::write(
+        &path,
+        "credentials:\n  - {name: acme, provider: acme-llm, secret: env:ACME_KEY}\n",
+    )
+    .unwrap();
+    assert!(matches!(
+        store.reload(),
+        Err(ConfigError::References(issues)) if issues[0].path == "credentials.acme.provider"
+    ));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_header_rules() {
+    use langspec::proxy::headers::{HeaderPolicy, HeaderRuleError};
+    use pingora_http::ResponseHeader;
+
+    let config = GatewayConfig::from_yaml(
+        r#"
+headers:
+  strip_request: [x-debug-token, Cookie]
+  remove_response: [openai-organization]
+  routes:
+    - path: /v1/chat/*
+      request: {X-Team: ml, X-Route: chat}
+      response: {Cache-Control: no-store}
+    - path: /v1/chat/completions
+      request: {X-Route: completions}
+"#,
+    )
+    .unwrap();
+    let rules = config.header_rules().unwrap();
+    let policy = HeaderPolicy::new();
+    let header = |request: &RequestHeader, name: &str| {
+        request
+            .headers
+            .get(name)
+            .map(|value| value.to_str().unwrap().to_string())
+    };
+
+    let mut request = RequestHeader::build("POST", b"/v1/chat/completions", None).unwrap();
+    request.insert_header("x-debug-token", "t0k3n").unwrap();
+    request.insert_header("cookie", "session=1").unwrap();
+    request.insert_header("x-tenant", "acme").unwrap();
+    policy
+        .apply_request_rules(&mut request, &rules, "/v1/chat/completions")
+        .unwrap();
+    assert_eq!(header(&request, "x-debug-token"), None);
+    assert_eq!(header(&request, "cookie"), None);
+    assert_eq!(header(&request, "x-tenant").as_deref(), Some("acme"));
+    assert_eq!(header(&request, "x-team").as_deref(), Some("ml"));
+    // Later routes override earlier ones
+    assert_eq!(header(&request, "x-route").as_deref(), Some("completions"));
+
+    let mut request = RequestHeader::build("POST", b"/v1/embeddings", None).unwrap();
+    policy
+        .apply_request_rules(&mut request, &rules, "/v1/embeddings")
+        .unwrap();
+    assert_eq!(header(&request, "x-team"), None);
+
+    let mut response = ResponseHeader::build(200, None).unwrap();
+    response
+        .insert_header("openai-organization", "org-123")
+        .unwrap();
+    response.insert_header("x-request-id", "req_1").unwrap();
+    policy
+        .apply_response_rules(&mut response, &rules, "/v1/chat/completions")
+        .unwrap();
+    assert!(response.headers.get("openai-organization").is_none());
+    assert!(response.headers.get("x-request-id").is_some());
+    assert_eq!(response.headers.get("cache-control").unwrap(), "no-store");
+
+    let invalid = |yaml: &str| {
+        GatewayConfig::from_yaml(yaml)
+            .unwrap()
+            .header_rules()
+            .unwrap_err()
+    };
+    assert!(matches!(
+        invalid("headers: {strip_request: [\"x debug\"]}\n"),
+        ConfigError::Headers(HeaderRuleError::InvalidName(name)) if name == "x debug"
+    ));
+    assert!(matches!(
+        invalid("headers: {remove_response: [Content-Length]}\n"),
+        ConfigError::Headers(HeaderRuleError::Framing(name)) if name == "content-length"
+    ));
+    assert!(matches!(
+        invalid("headers: {routes: [{path: v1, request: {x-team: ml}}]}\n"),
+        ConfigError::Headers(HeaderRuleError::InvalidPath(..))
+    ));
+    assert!(matches!(
+        invalid("headers: {routes: [{path: /v1, response: {x-team: \"a\\nb\"}}]}\n"),
+        ConfigError::Headers(HeaderRuleError::InvalidValue(name, _)) if name == "x-team"
+    ));
+
+    // Applied with the config, and replaced on reload
+    let path = config_file("headers");
+    std::fs::write(&path, "headers: {strip_request: [x-debug-token]}\n").unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    assert!(!pipeline.header_rules().is_empty());
+    std::fs::write(&path, "providers: []\n").unwrap();
+    store.reload().unwrap();
+    assert!(pipeline.header_rules().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_upstream_pools() {
+    use langspec::upstream::PoolError;
+
+    let config = GatewayConfig::from_yaml(
+        r#"
+upstream_pools:
+  - name: embeddings
+    upstreams: [embed1:80]
+    paths: [/v1/embeddings]
+  - name: chat
+    upstreams: [chat1:80, chat2:80]
+    paths: [/v1/chat/*, /v1/*]
+  - name: realtime
+    upstreams: [chat2:80]
+    paths: [/v1/chat/realtime*]
+"#,
+    )
+    .unwrap();
+    let pools = config.upstream_pools().unwrap();
+    let pool = |path: &str| pools.pool_for(path).map(|pool| pool.name.as_str());
+    // An exact path wins over any prefix, a longer prefix over a shorter one
+    assert_eq!(pool("/v1/embeddings"), Some("embeddings"));
+    assert_eq!(pool("/v1/embeddings/batch"), Some("chat"));
+    assert_eq!(pool("/v1/chat/completions"), Some("chat"));
+    assert_eq!(pool("/v1/chat/realtime/sessions"), Some("realtime"));
+    assert_eq!(pool("/health"), None);
+    assert!(pools.pool_for("/v1/models").unwrap().contains("chat2:80"));
+
+    let invalid = |yaml: &str| {
+        GatewayConfig::from_yaml(yaml)
+            .unwrap()
+            .upstream_pools()
+            .unwrap_err()
+    };
+    assert!(matches!(
+        invalid("upstream_pools:\n  - {name: a, upstreams: [], paths: [/v1]}\n"),
+        ConfigError::Pools(PoolError::Empty(name)) if name == "a"
+    ));
+    assert!(matches!(
+        invalid("upstream_pools:\n  - {name: a, upstreams: [x:80], paths: [v1]}\n"),
+        ConfigError::Pools(PoolError::InvalidPath(..))
+    ));
+    assert!(matches!(
+        invalid("upstream_pools:\n  - {name: a, upstreams: [x:80], paths: [/v1]}\n  - {name: a, upstreams: [y:80], paths: [/v2]}\n"),
+        ConfigError::Pools(PoolError::DuplicateName(name)) if name == "a"
+    ));
+    assert!(matches!(
+        invalid("upstream_pools:\n  - {name: a, upstreams: [x:80], paths: [/v1/*]}\n  - {name: b, upstreams: [y:80], paths: [/v1/*]}\n"),
+        ConfigError::Pools(PoolError::DuplicatePath(name, _, other)) if name == "b" && other == "a"
+    ));
+
+    // Pools may only name the gateway's upstreams, on load and on reload
+    let path = config_file("upstream_pools");
+    let upstreams = vec!["chat1:80".to_string(), "chat2:80".to_string()];
+    std::fs::write(
+        &path,
+        "upstream_pools:\n  - {name: chat, upstreams: [chat1:80, embed1:80], paths: [/v1/chat/*]}\n",
+    )
+    .unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    match ConfigStore::load_for_upstreams(&path, Arc::clone(&pipeline), upstreams.clone()) {
+        Err(ConfigError::References(issues)) => {
+            assert_eq!(issues.len(), 1);
+            assert_eq!(issues[0].path, "upstream_pools.chat.upstreams");
+        }
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+    std::fs::write(
+        &path,
+        "upstream_pools:\n  - {name: chat, upstreams: [chat1:80], paths: [/v1/chat/*]}\n",
+    )
+    .unwrap();
+    let store = ConfigStore::load_for_upstreams(&path, Arc::clone(&pipeline), upstreams).unwrap();
+    assert!(!pipeline.upstream_pools().is_empty());
+    std::fs::write(
+        &path,
+        "upstream_pools:\n  - {name: chat, upstreams: [embed1:80], paths: [/v1/chat/*]}\n",
+    )
+    .unwrap();
+    assert!(matches!(store.reload(), Err(ConfigError::References(_))));
+    std::fs::write(&path, "upstream_pools: []\n").unwrap();
+    store.reload().unwrap();
+    assert!(pipeline.upstream_pools().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_config_canary_pools() {
+    use langspec::upstream::{PoolArm, PoolError};
+
+    let config = GatewayConfig::from_yaml(
+        r#"
+upstream_pools:
+  - name: chat
+    upstreams: [chat1:80, chat2:80]
+    paths: [/v1/chat/*]
+    canary: {pool: chat-canary, percent: 0}
+  - name: chat-canary
+    upstreams: [chat3:80]
+"#,
+    )
+    .unwrap();
+    let pools = config.upstream_pools().unwrap();
+    let arm = |pools: &langspec::upstream::UpstreamPools| {
+        let route = pools.route("/v1/chat/completions").unwrap();
+        (route.pool.name.clone(), route.arm)
+    };
+    assert_eq!(arm(&pools), ("chat".to_string(), PoolArm::Stable));
+    // A canary pool without paths claims no requests of its own
+    assert!(pools.route("/v1/embeddings").is_none());
+
+    pools.set_canary_percent("chat", 100.0).unwrap();
+    assert_eq!(arm(&pools), ("chat-canary".to_string(), PoolArm::Canary));
+    pools.set_canary_percent("chat", 25.0).unwrap();
+    let canaries = (0..2000)
+        .filter(|_| arm(&pools).1 == PoolArm::Canary)
+        .count();
+    assert!((350..650).contains(&canaries), "{}", canaries);
+    assert_eq!(
+        pools.get("chat").unwrap().config().canary.unwrap().percent,
+        25.0
+    );
+
+    assert_eq!(
+        pools.set_canary_percent("chat", 101.0),
+        Err(PoolError::InvalidPercent("chat".to_string(), 101.0))
+    );
+    assert_eq!(
+        pools.set_canary_percent("chat-canary", 5.0),
+        Err(PoolError::NoCanary("chat-canary".to_string()))
+    );
+    assert_eq!(
+        pools.set_canary_percent("batch", 5.0),
+        Err(PoolError::UnknownPool("batch".to_string()))
+    );
+
+    let invalid = |yaml: &str| {
+        GatewayConfig::from_yaml(yaml)
+            .unwrap()
+            .upstream_pools()
+            .unwrap_err()
+    };
+    assert!(matches!(
+        invalid("upstream_pools:\n  - {name: a, upstreams: [x:80], paths: [/v1], canary: {pool: b, percent: 5}}\n"),
+        ConfigError::Pools(PoolError::InvalidCanary(name, canary)) if name == "a" && canary == "b"
+    ));
+    assert!(matches!(
+        invalid(
+            "upstream_pools:\n  - {name: a, upstreams: [x:80], paths: [/v1], canary: {pool: a, percent: 5}}\n"
+        ),
+        ConfigError::Pools(PoolError::InvalidCanary(..))
+    ));
+    assert!(matches!(
+        invalid(
+            "upstream_pools:\n  - {name: a, upstreams: [x:80], paths: [/v1], canary: {pool: b, percent: -1}}\n  - {name: b, upstreams: [y:80]}\n"
+        ),
+        ConfigError::Pools(PoolError::InvalidPercent(..))
+    ));
+}
+
+#[test]
+fn test_config_fallback_pools() {
+    use langspec::upstream::{PoolArm, PoolError};
+
+    let config = GatewayConfig::from_yaml(
+        r#"
+upstream_pools:
+  - name: openai
+    upstreams: [openai:443]
+    paths: [/v1/chat/*]
+    fallback: [azure, anthropic]
+  - name: azure
+    upstreams: [azure:443]
+  - name: anthropic
+    upstreams: [anthropic:443]
+"#,
+    )
+    .unwrap();
+    let pools = config.upstream_pools().unwrap();
+    let route = pools.route("/v1/chat/completions").unwrap();
+    assert_eq!((route.pool.name.as_str(), route.hop), ("openai", 0));
+    let hop = pools.fallback(&route).unwrap();
+    assert_eq!((hop.pool.name.as_str(), hop.hop), ("azure", 1));
+    assert_eq!(hop.arm, PoolArm::Stable);
+    let hop = pools.fallback(&hop).unwrap();
+    assert_eq!((hop.pool.name.as_str(), hop.hop), ("anthropic", 2));
+    // The chain is the routed pool's; a fallback pool's own chain is not followed
+    assert!(pools.fallback(&hop).is_none());
+    assert_eq!(
+        pools.get("openai").unwrap().config().fallback,
+        vec!["azure".to_string(), "anthropic".to_string()]
+    );
+
+    let invalid = |yaml: &str| {
+        GatewayConfig::from_yaml(yaml)
+            .unwrap()
+            .upstream_pools()
+            .unwrap_err()
+    };
+    assert!(matches!(
+        invalid("upstream_pools:\n  - {name: a, upstreams: [x:80], paths: [/v1], fallback: [b]}\n"),
+        ConfigError::Pools(PoolError::InvalidFallback(name, fallback)) if name == "a" && fallback == "b"
+    ));
+    assert!(matches!(
+        invalid("upstream_pools:\n  - {name: a, upstreams: [x:80], paths: [/v1], fallback: [a]}\n"),
+        ConfigError::Pools(PoolError::InvalidFallback(..))
+    ));
+    assert!(matches!(
+        invalid(
+            "upstream_pools:\n  - {name: a, upstreams: [x:80], paths: [/v1], fallback: [b, b]}\n  - {name: b, upstreams: [y:80]}\n"
+        ),
+        ConfigError::Pools(PoolError::InvalidFallback(..))
+    ));
+}
+
+#[test]
+fn test_config_pool_timeouts() {
+    use langspec::upstream::{PoolError, PoolTimeouts};
+    use std::time::Duration;
+
+    let config = GatewayConfig::from_yaml(
+        r#"
+upstream_pools:
+  - name: chat
+    upstreams: [chat1:80]
+    paths: [/v1/chat/*]
+    timeouts: {connect_ms: 2000, ttfb_ms: 300000, idle_ms: 30000, total_ms: 600000}
+  - name: health
+    upstreams: [chat1:80]
+    paths: [/health]
+    timeouts: {connect_ms: 50, ttfb_ms: 200}
+  - name: batch
+    upstreams: [batch1:80]
+    paths: [/v1/batches]
+"#,
+    )
+    .unwrap();
+    let pools = config.upstream_pools().unwrap();
+    let chat = pools.get("chat").unwrap().timeouts;
+    assert_eq!(chat.connect, Some(Duration::from_millis(2000)));
+    // Reads wait as long as the header may take, the idle timeout being shorter
+    assert_eq!(chat.read(), Some(Duration::from_secs(300)));
+    assert_eq!(
+        pools.get("health").unwrap().timeouts.read(),
+        Some(Duration::from_millis(200))
+    );
+    assert_eq!(
+        pools.get("batch").unwrap().timeouts,
+        PoolTimeouts::default()
+    );
+    assert_eq!(pools.get("batch").unwrap().config().timeouts, None);
+    assert_eq!(
+        pools
+            .get("chat")
+            .unwrap()
+            .config()
+            .timeouts
+            .unwrap()
+            .idle_ms,
+        Some(30000)
+    );
+    // The total timeout caps every read
+    let capped = PoolTimeouts::new()
+        .with_ttfb(Duration::from_secs(60))
+        .with_total(Duration::from_secs(10));
+    assert_eq!(capped.read(), Some(Duration::from_secs(10)));
+
+    let invalid = GatewayConfig::from_yaml(
+        "upstream_pools:\n  - {name: a, upstreams: [x:80], paths: [/v1], timeouts: {idle_ms: 0}}\n",
+    )
+    .unwrap()
+    .upstream_pools()
+    .unwrap_err();
+    assert!(matches!(
+        invalid,
+        ConfigError::Pools(PoolError::InvalidTimeout(name, timeout)) if name == "a" && timeout == "idle"
+    ));
+    assert!(
+        GatewayConfig::from_yaml(
+            "upstream_pools:\n  - {name: a, upstreams: [x:80], timeouts: {read_ms: 5}}\n"
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn test_config_pool_http2() {
+    use langspec::upstream::{PoolError, PoolHttp2};
+    use std::time::Duration;
+
+    let config = GatewayConfig::from_yaml(
+        r#"
+upstream_pools:
+  - name: openai
+    upstreams: [api.openai.com:443]
+    paths: [/v1/*]
+    http2: {}
+  - name: triton
+    upstreams: [triton:8001]
+    paths: [/inference.GRPCInferenceService/*]
+    http2: {required: true, max_streams: 32, ping_interval_ms: 10000}
+  - name: legacy
+    upstreams: [legacy:80]
+    paths: [/legacy]
+"#,
+    )
+    .unwrap();
+    let pools = config.upstream_pools().unwrap();
+    assert_eq!(pools.get("openai").unwrap().http2, Some(PoolHttp2::new()));
+    assert_eq!(
+        pools.get("triton").unwrap().http2,
+        Some(
+            PoolHttp2::new()
+                .with_required()
+                .with_max_streams(32)
+                .with_ping_interval(Duration::from_secs(10))
+        )
+    );
+    assert_eq!(pools.get("legacy").unwrap().http2, None);
+    // Round-trips without spelling out the defaults
+    let openai = pools.get("openai").unwrap().config().http2.unwrap();
+    assert!(!openai.required);
+    assert_eq!(openai.max_streams, None);
+    assert_eq!(
+        pools
+            .get("triton")
+            .unwrap()
+            .config()
+            .http2
+            .unwrap()
+            .max_streams,
+        Some(32)
+    );
+
+    let invalid = GatewayConfig::from_yaml(
+        "upstream_pools:\n  - {name: a, upstreams: [x:80], paths: [/v1], http2: {max_streams: 0}}\n",
+    )
+    .unwrap()
+    .upstream_pools()
+    .unwrap_err();
+    assert!(matches!(
+        invalid,
+        ConfigError::Pools(PoolError::InvalidHttp2(name, setting)) if name == "a" && setting == "max_streams"
+    ));
+    assert!(
+        GatewayConfig::from_yaml(
+            "upstream_pools:\n  - {name: a, upstreams: [x:80], http2: {alpn: h2}}\n"
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn test_config_cors() {
+    use langspec::proxy::cors::CorsError;
+    use pingora_http::ResponseHeader;
+
+    let path = config_file("cors");
+    std::fs::write(
+        &path,
+        r#"
+cors:
+  - path: /v1/chat/*
+    allowed_origins: [https://app.example.com]
+    allowed_methods: [post]
+    expose_headers: [x-request-id]
+    allow_credentials: true
+    max_age_secs: 600
+  - path: /v1/models
+    allowed_origins: ["*"]
+    allowed_headers: ["*"]
+"#,
+    )
+    .unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let cors = pipeline.cors_rules();
+
+    let preflight = |path: &str, origin: &str, method: &str, headers: Option<&str>| {
+        let mut request = RequestHeader::build("OPTIONS", path.as_bytes(), None).unwrap();
+        request.insert_header("origin", origin).unwrap();
+        request
+            .insert_header("access-control-request-method", method)
+            .unwrap();
+        if let Some(headers) = headers {
+            request
+                .insert_header("access-control-request-headers", headers)
+                .unwrap();
+        }
+        cors.preflight_response(&request).map(Result::unwrap)
+    };
+    let header = |response: &ResponseHeader, name: &str| {
+        response
+            .headers
+            .get(name)
+            .map(|value| value.to_str().unwrap().to_string())
+    };
+
+    let allowed = preflight(
+        "/v1/chat/completions",
+        "https://app.example.com",
+        "POST",
+        Some("Content-Type, Authorization"),
+    )
+    .unwrap();
+    assert_eq!(allowed.status, 204);
+    assert_eq!(
+        header(&allowed, "access-control-allow-origin").as_deref(),
+        Some("https://app.example.com")
+    );
+    assert_eq!(header(&allowed, "vary").as_deref(), Some("Origin"));
+    assert_eq!(
+        header(&allowed, "access-control-allow-methods").as_deref(),
+        Some("POST")
+    );
+    assert_eq!(
+        header(&allowed, "access-control-allow-headers").as_deref(),
+        Some("authorization, content-type")
+    );
+    assert_eq!(
+        header(&allowed, "access-control-allow-credentials").as_deref(),
+        Some("true")
+    );
+    assert_eq!(
+        header(&allowed, "access-control-max-age").as_deref(),
+        Some("600")
+    );
+    // Another origin, method or header is refused
+    let denied =
+        |path, origin, method, headers| preflight(path, origin, method, headers).unwrap().status;
+    assert_eq!(
+        denied(
+            "/v1/chat/completions",
+            "https://evil.example.com",
+            "POST",
+            None
+        ),
+        403
+    );
+    assert_eq!(
+        denied(
+            "/v1/chat/completions",
+            "https://app.example.com",
+            "DELETE",
+            None
+        ),
+        403
+    );
+    assert_eq!(
+        denied(
+            "/v1/chat/completions",
+            "https://app.example.com",
+            "POST",
+            Some("x-secret")
+        ),
+        403
+    );
+    // Any origin and header: the requested headers are echoed
+    let any = preflight(
+        "/v1/models",
+        "https://other.example.com",
+        "GET",
+        Some("x-trace"),
+    )
+    .unwrap();
+    assert_eq!(any.status, 204);
+    assert_eq!(
+        header(&any, "access-control-allow-origin").as_deref(),
+        Some("*")
+    );
+    assert_eq!(
+        header(&any, "access-control-allow-headers").as_deref(),
+        Some("x-trace")
+    );
+    // Preflights of other routes are forwarded
+    assert!(preflight("/v1/embeddings", "https://app.example.com", "POST", None).is_none());
+
+    // Responses to allowed origins get the headers, others none
+    let respond = |origin: Option<&str>| {
+        let mut request = RequestHeader::build("POST", b"/v1/chat/completions", None).unwrap();
+        if let Some(origin) = origin {
+            request.insert_header("origin", origin).unwrap();
+        }
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        cors.apply(&request, &mut response).unwrap();
+        response
+    };
+    let response = respond(Some("https://app.example.com"));
+    assert_eq!(
+        header(&response, "access-control-allow-origin").as_deref(),
+        Some("https://app.example.com")
+    );
+    assert_eq!(
+        header(&response, "access-control-expose-headers").as_deref(),
+        Some("x-request-id")
+    );
+    assert!(header(&response, "access-control-allow-methods").is_none());
+    let response = respond(Some("https://evil.example.com"));
+    assert!(header(&response, "access-control-allow-origin").is_none());
+    assert!(respond(None).headers.is_empty());
+
+    let invalid = |yaml: &str| {
+        GatewayConfig::from_yaml(yaml)
+            .unwrap()
+            .cors_rules()
+            .unwrap_err()
+    };
+    assert!(matches!(
+        invalid("cors:\n  - {path: v1, allowed_origins: [\"*\"]}\n"),
+        ConfigError::Cors(CorsError::InvalidPath(..))
+    ));
+    assert!(matches!(
+        invalid("cors:\n  - {path: /v1, allowed_origins: [https://app.example.com/]}\n"),
+        ConfigError::Cors(CorsError::InvalidOrigin(..))
+    ));
+    assert!(matches!(
+        invalid("cors:\n  - {path: /v1, allowed_origins: [\"*\"], allowed_methods: [\"GE T\"]}\n"),
+        ConfigError::Cors(CorsError::InvalidMethod(..))
+    ));
+    assert!(matches!(
+        invalid("cors:\n  - {path: /v1, allowed_origins: [\"*\"], expose_headers: [\"x id\"]}\n"),
+        ConfigError::Cors(CorsError::InvalidHeader(..))
+    ));
+    assert!(matches!(
+        invalid("cors:\n  - {path: /v1, allowed_origins: [\"*\"], allow_credentials: true}\n"),
+        ConfigError::Cors(CorsError::WildcardCredentials(path)) if path == "/v1"
+    ));
+}
+
+#[cfg(feature = "jwt")]
+#[test]
+fn test_config_jwt() {
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+    use langspec::proxy::jwt::{Jwks, JwtConfigError, JwtError, JwtValidator, request_token};
+    use openssl::bn::BigNumContext;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::ecdsa::EcdsaSig;
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::sign::Signer;
+    use serde_json::json;
+
+    let rsa = Rsa::generate(2048).unwrap();
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let ec = EcKey::generate(&group).unwrap();
+    let (mut x, mut y) = (
+        openssl::bn::BigNum::new().unwrap(),
+        openssl::bn::BigNum::new().unwrap(),
+    );
+    ec.public_key()
+        .affine_coordinates(&group, &mut x, &mut y, &mut BigNumContext::new().unwrap())
+        .unwrap();
+    let b64 = |bytes: &[u8]| BASE64URL.encode(bytes);
+    let jwks = json!({"keys": [
+        {"kty": "RSA", "kid": "rsa-1", "use": "sig", "n": b64(&rsa.n().to_vec()), "e": b64(&rsa.e().to_vec())},
+        {"kty": "EC", "kid": "ec-1", "crv": "P-256", "x": b64(&x.to_vec_padded(32).unwrap()), "y": b64(&y.to_vec_padded(32).unwrap())},
+        {"kty": "RSA", "kid": "enc-1", "use": "enc", "n": b64(&rsa.n().to_vec()), "e": b64(&rsa.e().to_vec())},
+        {"kty": "oct", "kid": "hmac-1", "k": "c2VjcmV0"},
+    ]});
+    let keys = Jwks::from_json(jwks.to_string().as_bytes()).unwrap();
+    assert_eq!(keys.keys.len(), 2);
+
+    let rsa = PKey::from_rsa(rsa).unwrap();
+    let ec = PKey::from_ec_key(ec).unwrap();
+    let token = |header: serde_json::Value, claims: serde_json::Value| {
+        let signed = format!(
+            "{}.{}",
+            b64(header.to_string().as_bytes()),
+            b64(claims.to_string().as_bytes())
+        );
+        let signature = match header["alg"].as_str() {
+            Some("ES256") => {
+                let mut signer = Signer::new(MessageDigest::sha256(), &ec).unwrap();
+                let der = signer.sign_oneshot_to_vec(signed.as_bytes()).unwrap();
+                let sig = EcdsaSig::from_der(&der).unwrap();
+                let mut raw = sig.r().to_vec_padded(32).unwrap();
+                raw.extend(sig.s().to_vec_padded(32).unwrap());
+                raw
+            }
+            _ => {
+                let mut signer = Signer::new(MessageDigest::sha256(), &rsa).unwrap();
+                signer.sign_oneshot_to_vec(signed.as_bytes()).unwrap()
+            }
+        };
+        format!("{}.{}", signed, b64(&signature))
+    };
+
+    let config = GatewayConfig::from_yaml(
+        r#"
+jwt:
+  jwks_url: https://idp.example.com/.well-known/jwks.json
+  issuer: https://idp.example.com/
+  audience: langspec
+  org_claim: org_id
+"#,
+    )
+    .unwrap();
+    let validator = config.jwt_validator().unwrap().unwrap();
+    let now = 1_800_000_000;
+    let claims = json!({
+        "iss": "https://idp.example.com/",
+        "aud": ["billing", "langspec"],
+        "sub": "user-42",
+        "org_id": "acme",
+        "exp": now + 600,
+        "nbf": now - 10,
+    });
+    let rs256 = json!({"alg": "RS256", "kid": "rsa-1", "typ": "JWT"});
+    let valid = validator
+        .validate(&token(rs256.clone(), claims.clone()), &keys, now)
+        .unwrap();
+    assert_eq!(valid.sub.as_deref(), Some("user-42"));
+    assert_eq!(valid.org.as_deref(), Some("acme"));
+    let es256 = json!({"alg": "ES256", "kid": "ec-1"});
+    assert!(
+        validator
+            .validate(&token(es256, claims.clone()), &keys, now)
+            .is_ok()
+    );
+
+    let invalid = |header: &serde_json::Value, change: serde_json::Value| {
+        let mut claims = claims.clone();
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(change.as_object().unwrap().clone());
+        validator
+            .validate(&token(header.clone(), claims), &keys, now)
+            .unwrap_err()
+    };
+    assert_eq!(invalid(&rs256, json!({"exp": now - 61})), JwtError::Expired);
+    // Within the clock skew allowance
+    assert!(
+        validator
+            .validate(
+                &token(rs256.clone(), {
+                    let mut claims = claims.clone();
+                    claims["exp"] = json!(now - 30);
+                    claims
+                }),
+                &keys,
+                now
+            )
+            .is_ok()
+    );
+    assert_eq!(
+        invalid(&rs256, json!({"nbf": now + 300})),
+        JwtError::NotYetValid
+    );
+    assert_eq!(
+        invalid(&rs256, json!({"iss": "https://evil.example.com/"})),
+        JwtError::WrongIssuer
+    );
+    assert_eq!(
+        invalid(&rs256, json!({"aud": "billing"})),
+        JwtError::WrongAudience
+    );
+    assert_eq!(
+        invalid(&json!({"alg": "RS256", "kid": "rsa-2"}), json!({})),
+        JwtError::UnknownKey(Some("rsa-2".to_string()))
+    );
+    // Keys of another algorithm do not verify, and neither do encryption keys
+    assert_eq!(
+        invalid(&json!({"alg": "RS256", "kid": "ec-1"}), json!({})),
+        JwtError::UnknownKey(Some("ec-1".to_string()))
+    );
+    assert_eq!(
+        invalid(&json!({"alg": "RS256", "kid": "enc-1"}), json!({})),
+        JwtError::UnknownKey(Some("enc-1".to_string()))
+    );
+    assert_eq!(
+        invalid(&json!({"alg": "HS256", "kid": "hmac-1"}), json!({})),
+        JwtError::UnsupportedAlgorithm("HS256".to_string())
+    );
+
+    // Unsigned and tampered tokens
+    let signed = token(rs256.clone(), claims.clone());
+    let (header, rest) = signed.split_once('.').unwrap();
+    let (_, signature) = rest.split_once('.').unwrap();
+    let mut elevated = claims.clone();
+    elevated["org_id"] = json!("globex");
+    let tampered = format!(
+        "{}.{}.{}",
+        header,
+        b64(elevated.to_string().as_bytes()),
+        signature
+    );
+    assert_eq!(
+        validator.validate(&tampered, &keys, now),
+        Err(JwtError::BadSignature)
+    );
+    let unsigned = format!(
+        "{}.{}.",
+        b64(json!({"alg": "none"}).to_string().as_bytes()),
+        b64(claims.to_string().as_bytes())
+    );
+    assert_eq!(
+        validator.validate(&unsigned, &keys, now),
+        Err(JwtError::UnsupportedAlgorithm("none".to_string()))
+    );
+    assert_eq!(
+        validator.validate("not-a-jwt", &keys, now),
+        Err(JwtError::Malformed)
+    );
+    assert_eq!(
+        JwtValidator::header("e30.e30.sig"),
+        Err(JwtError::Malformed)
+    );
+
+    // Only bearer values shaped like a JWT are taken as one
+    let mut request = RequestHeader::build("POST", b"/v1/chat/completions", None).unwrap();
+    request
+        .insert_header("Authorization", "Bearer sk-provider-key")
+        .unwrap();
+    assert_eq!(request_token(&request), None);
+    request
+        .insert_header("Authorization", format!("Bearer {}", signed))
+        .unwrap();
+    assert_eq!(request_token(&request), Some(signed.as_str()));
+
+    // Fetched keys are cached until the refresh interval
+    assert!(validator.fresh_keys().is_none());
+    assert!(validator.may_refetch());
+    validator.set_keys(keys);
+    assert_eq!(validator.fresh_keys().unwrap().keys.len(), 2);
+    assert!(!validator.may_refetch());
+
+    let invalid_config = |yaml: &str| match GatewayConfig::from_yaml(yaml).unwrap().jwt_validator()
+    {
+        Err(ConfigError::Jwt(e)) => e,
+        other => panic!("expected a JWT config error, got {:?}", other),
+    };
+    assert_eq!(
+        invalid_config("jwt: {jwks_url: idp.example.com/jwks, issuer: idp, audience: gw}"),
+        JwtConfigError::InvalidJwksUrl("idp.example.com/jwks".to_string())
+    );
+    assert_eq!(
+        invalid_config("jwt: {jwks_url: 'https://idp/jwks', issuer: idp, audience: ''}"),
+        JwtConfigError::EmptyAudience
+    );
+}
+
+#[test]
+fn test_config_tenant_import() {
+    let path = config_file("tenant_import");
+    std::fs::write(
+        &path,
+        "pricing: {}\nbudgets:\n  - {tenant: acme, limit_usd: 1, window_secs: 60}\n",
+    )
+    .unwrap();
+    let pipeline = Arc::new(Pipeline::new());
+    let store = ConfigStore::load(&path, Arc::clone(&pipeline)).unwrap();
+    let exported = store.current().config.tenant_sections();
+    assert_eq!(exported.budgets.len(), 1);
+    assert!(exported.rate_limits.is_empty());
+
+    let sections: TenantSections = serde_json::from_value(serde_json::json!({
+        "budgets": [{"tenant": "globex", "limit_usd": 5, "window_secs": 3600}],
+        "rate_limits": [{"name": "globex", "key": "globex-*", "requests_per_minute": 60}],
+    }))
+    .unwrap();
+    let import = store.import_tenants(sections.clone(), true).unwrap();
+    assert!(import.dry_run);
+    assert_eq!(import.version, 1);
+    let mut paths: Vec<&str> = import
+        .diff
+        .changes
+        .iter()
+        .map(|change| change.path.as_str())
+        .collect();
+    paths.sort();
+    assert_eq!(paths, ["budgets", "rate_limits"]);
+    assert_eq!(
+        pipeline
+            .spend_budgets()
+            .policy()
+            .budgets_for("globex")
+            .len(),
+        0
+    );
+    assert!(pipeline.rate_limiter().policy().is_empty());
+
+    let import = store.import_tenants(sections.clone(), false).unwrap();
+    assert!(!import.dry_run);
+    assert_eq!(import.version, 2);
+    assert_eq!(
+        pipeline
+            .spend_budgets()
+            .policy()
+            .budgets_for("globex")
+            .len(),
+        1
+    );
+    assert_eq!(pipeline.rate_limiter().policy().limits.len(), 1);
+    assert_eq!(store.current().config.tenant_sections(), sections);
+    // Importing the same sections again changes nothing
+    assert_eq!(store.import_tenants(sections, false).unwrap().version, 2);
+
+    // An invalid import is rejected as a whole, leaving the applied version in place
+    let invalid: TenantSections = serde_json::from_value(serde_json::json!({
+        "budgets": [{"tenant": "initech", "limit_usd": 5, "window_secs": 3600}],
+        "rate_limits": [{"name": "initech", "provider": "nope", "requests_per_minute": 60}],
+    }))
+    .unwrap();
+    let Err(ConfigError::References(issues)) = store.import_tenants(invalid, true) else {
+        panic!("expected broken references");
+    };
+    assert_eq!(issues[0].path, "rate_limits.initech.provider");
+    assert_eq!(store.current().version, 2);
+    assert_eq!(
+        pipeline
+            .spend_budgets()
+            .policy()
+            .budgets_for("initech")
+            .len(),
+        0
+    );
+
+    store.rollback().unwrap();
+    assert_eq!(
+        pipeline
+            .spend_budgets()
+            .policy()
+            .budgets_for("globex")
+            .len(),
+        0
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "admin")]
+#[test]
+fn test_admin_tenant_import_endpoints() {
+    use langspec::proxy::GatewayProxy;
+
+    let path = config_file("admin_tenants");
+    std::fs::write(&path, "pricing: {}\n").unwrap();
+    let proxy = GatewayProxy::new(vec!["127.0.0.1:8001".to_string()])
+        .with_config_file(&path)
+        .unwrap();
+    let admin = proxy.admin_app();
+
+    let response = admin.handle("GET", "/config/tenants");
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["budgets"], serde_json::json!([]));
+
+    let import = br#"{"budgets": [{"tenant": "acme", "limit_usd": 1, "window_secs": 60}]}"#;
+    let response = admin.handle_request("POST", "/config/tenants/dry-run", import);
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["dry_run"], true);
+    assert_eq!(body["diff"][0]["path"], "budgets");
+
+    let response = admin.handle_request("PUT", "/config/tenants", import);
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["version"], 2);
+    let response = admin.handle("GET", "/config/tenants");
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["budgets"][0]["tenant"], "acme");
+
+    let response = admin.handle_request("PUT", "/config/tenants", br#"{"upstreams": []}"#);
+    assert_eq!(response.status(), 422);
+    assert_eq!(admin.handle("DELETE", "/config/tenants").status(), 405);
+
+    std::fs::remove_file(&path).unwrap();
+}
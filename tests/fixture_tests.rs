@@ -0,0 +1,89 @@
+use langspec::provider::ProviderRegistry;
+use langspec::provider::fixtures::{DetectionFixture, FixtureError};
+
+const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/detection");
+
+#[test]
+fn test_detection_fixtures() {
+    let fixtures = DetectionFixture::load(FIXTURE_DIR).unwrap();
+    assert!(!fixtures.is_empty(), "no fixtures found in {}", FIXTURE_DIR);
+
+    let registry = ProviderRegistry::new();
+    let failures: Vec<String> = fixtures
+        .iter()
+        .filter_map(|fixture| {
+            let detected = fixture.detect(&registry).unwrap();
+            (!fixture.matches(&detected)).then(|| {
+                format!(
+                    "{}: expected {}, detected {}",
+                    fixture.name, fixture.expect, detected
+                )
+            })
+        })
+        .collect();
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} fixtures failed:\n{}",
+        failures.len(),
+        fixtures.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn test_fixture_expectation_matching() {
+    let fixtures: Vec<DetectionFixture> = load_inline(
+        "
+- name: only provider checked
+  request:
+    path: /v1/chat/completions
+    host: api.openai.com
+  expect:
+    provider: OpenAI
+- name: wrong signal
+  request:
+    path: /v1/chat/completions
+    host: api.openai.com
+  expect:
+    provider: openai
+    signal: path
+",
+    );
+    let registry = ProviderRegistry::new();
+
+    let detected = fixtures[0].detect(&registry).unwrap();
+    assert_eq!(detected.to_string(), "openai (high via host)");
+    assert!(fixtures[0].matches(&detected));
+    assert!(!fixtures[1].matches(&fixtures[1].detect(&registry).unwrap()));
+}
+
+#[test]
+fn test_fixture_load_errors() {
+    let missing = DetectionFixture::load("tests/fixtures/detection/missing.yaml");
+    assert!(matches!(missing, Err(FixtureError::Io(..))));
+
+    let dir = std::env::temp_dir().join(format!("langspec-fixtures-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let invalid = dir.join("invalid.yaml");
+    std::fs::write(&invalid, "- name: no request\n").unwrap();
+    let result = DetectionFixture::load(&dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    match result {
+        Err(FixtureError::Yaml(path, _)) => assert_eq!(path, invalid),
+        other => panic!("expected a YAML error, got {:?}", other),
+    }
+}
+
+fn load_inline(yaml: &str) -> Vec<DetectionFixture> {
+    let path = std::env::temp_dir().join(format!(
+        "langspec-fixture-{}-{}.yaml",
+        std::process::id(),
+        yaml.len()
+    ));
+    std::fs::write(&path, yaml).unwrap();
+    let fixtures = DetectionFixture::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    fixtures
+}
@@ -1,7 +1,7 @@
 use langspec::pipeline::views::RequestView;
-use langspec::provider::{ProviderKind, ProviderRegistry};
+use langspec::provider::{Confidence, ProviderKind, ProviderRegistry};
 use langspec::proxy::ctx::Ctx;
-use pingora::http::RequestHeader;
+use pingora_http::RequestHeader;
 
 fn create_test_request(
     method: &str,
@@ -93,7 +93,7 @@ fn test_unknown_detection() {
 fn test_ctx_defaults() {
     let ctx = Ctx::default();
     assert_eq!(ctx.provider, ProviderKind::Unknown);
-    assert!(ctx.start.is_none());
+    assert!(ctx.timer.breakdown().is_empty());
 }
 
 #[test]
@@ -107,7 +107,113 @@ fn test_provider_detection_with_pipeline() {
     pipeline.on_request(&request, &mut ctx);
 
     assert_eq!(ctx.provider, ProviderKind::OpenAI);
-    assert!(ctx.start.is_some());
+    assert!(ctx.timer.between("detect_start", "detect_done").is_some());
+    assert_eq!(ctx.model, None);
+}
+
+#[test]
+fn test_pipeline_records_model_from_path() {
+    use langspec::pipeline::Pipeline;
+
+    let request = create_test_request(
+        "POST",
+        "/model/anthropic.claude-3-haiku-20240307-v1:0/invoke",
+        Some("bedrock-runtime.us-east-1.amazonaws.com"),
+        &[],
+    );
+    let mut ctx = Ctx::default();
+    Pipeline::new().on_request(&request, &mut ctx);
+
+    assert_eq!(ctx.provider, ProviderKind::Bedrock);
+    assert_eq!(
+        ctx.model.as_deref(),
+        Some("anthropic.claude-3-haiku-20240307-v1:0")
+    );
+}
+
+#[test]
+fn test_pipeline_records_websocket_upgrade() {
+    use langspec::pipeline::Pipeline;
+
+    let request = create_test_request(
+        "GET",
+        "/v1/realtime?model=gpt-4o-realtime-preview",
+        Some("example.com"),
+        &[
+            ("Upgrade", "websocket"),
+            ("Connection", "keep-alive, Upgrade"),
+            ("Authorization", "Bearer sk-test"),
+        ],
+    );
+    let mut ctx = Ctx::default();
+    Pipeline::new().on_request(&request, &mut ctx);
+
+    assert_eq!(ctx.provider, ProviderKind::OpenAI);
+    assert_eq!(ctx.model.as_deref(), Some("gpt-4o-realtime-preview"));
+    assert!(
+        ctx.websocket
+            .as_ref()
+            .is_some_and(|socket| !socket.is_open())
+    );
+
+    // Without both headers it is a plain request
+    let request = create_test_request(
+        "GET",
+        "/v1/realtime",
+        Some("example.com"),
+        &[("Upgrade", "websocket")],
+    );
+    let mut ctx = Ctx::default();
+    Pipeline::new().on_request(&request, &mut ctx);
+    assert!(ctx.websocket.is_none());
+    assert_eq!(ctx.model, None);
+}
+
+#[test]
+fn test_grpc_call_status() {
+    use langspec::proxy::grpc::{GrpcCall, GrpcStatus};
+
+    let request = create_test_request(
+        "POST",
+        "/inference.GRPCInferenceService/ModelInfer",
+        Some("triton.internal:8001"),
+        &[
+            ("Content-Type", "application/grpc+proto"),
+            ("TE", "trailers"),
+        ],
+    );
+    let mut call = GrpcCall::from_request(&request).unwrap();
+    assert_eq!(call.service, "inference.GRPCInferenceService");
+    assert_eq!(call.method, "ModelInfer");
+    assert_eq!(call.status_label(), "none");
+
+    // Headers without a status (the call's first response) leave it unset
+    let mut headers = http::HeaderMap::new();
+    headers.insert("content-type", "application/grpc".parse().unwrap());
+    call.observe(&headers);
+    assert_eq!(call.status, None);
+
+    let mut trailers = http::HeaderMap::new();
+    trailers.insert("grpc-status", "14".parse().unwrap());
+    trailers.insert("grpc-message", "model%20not%20ready".parse().unwrap());
+    call.observe(&trailers);
+    assert_eq!(call.status, Some(GrpcStatus(14)));
+    assert_eq!(call.status_label(), "UNAVAILABLE");
+    assert_eq!(
+        call.to_string(),
+        "inference.GRPCInferenceService/ModelInfer status: 14 (UNAVAILABLE) message: \"model not ready\""
+    );
+
+    // gRPC-Web and JSON requests are not gRPC calls
+    for content_type in ["application/grpc-web+proto", "application/json"] {
+        let request = create_test_request(
+            "POST",
+            "/inference.GRPCInferenceService/ModelInfer",
+            None,
+            &[("Content-Type", content_type)],
+        );
+        assert_eq!(GrpcCall::from_request(&request), None);
+    }
 }
 
 #[test]
@@ -126,8 +232,9 @@ fn test_openai_path_patterns() {
         ("/v1/chat/completions", true),
         ("/v1/completions", true),
         ("/v1/responses", true),
+        ("/v1/realtime", true),
         ("/v1/models", false),
-        ("/api/chat", false),
+        ("/api/v1/chat", false),
         ("/v2/chat/completions", false),
     ];
 
@@ -257,6 +364,180 @@ fn test_false_positive_guards() {
     assert_eq!(registry.detect(&request_view), ProviderKind::Unknown);
 }
 
+#[test]
+fn test_cohere_detection() {
+    let registry = ProviderRegistry::new();
+
+    let request = create_test_request("POST", "/v2/chat", Some("api.cohere.com"), &[]);
+    let request_view = RequestView::new(&request);
+    assert_eq!(registry.detect(&request_view), ProviderKind::Cohere);
+
+    // Bearer tokens corroborated by a Cohere path, without the Cohere host
+    let request = create_test_request(
+        "POST",
+        "/v1/generate",
+        Some("llm.internal"),
+        &[("Authorization", "Bearer co-test")],
+    );
+    let request_view = RequestView::new(&request);
+    let result = registry.detect_result(&request_view).unwrap();
+    assert_eq!(result.kind, ProviderKind::Cohere);
+    assert_eq!(result.signal, "auth");
+
+    // OpenAI's /v1/chat paths are not mistaken for Cohere's /v2/chat
+    let request = create_test_request("POST", "/v1/chat/completions", Some("example.com"), &[]);
+    let request_view = RequestView::new(&request);
+    assert_eq!(registry.detect(&request_view), ProviderKind::OpenAI);
+}
+
+#[test]
+fn test_ollama_detection() {
+    let registry = ProviderRegistry::new();
+
+    for path in ["/api/chat", "/api/generate", "/api/embeddings"] {
+        let request = create_test_request("POST", path, Some("gpu-box.internal"), &[]);
+        let request_view = RequestView::new(&request);
+        assert_eq!(
+            registry.detect(&request_view),
+            ProviderKind::Ollama,
+            "{}",
+            path
+        );
+    }
+
+    // The default port identifies Ollama on any path, including its OpenAI-compatible API
+    let request = create_test_request("POST", "/v1/chat/completions", Some("localhost:11434"), &[]);
+    let request_view = RequestView::new(&request);
+    let result = registry.detect_result(&request_view).unwrap();
+    assert_eq!(result.kind, ProviderKind::Ollama);
+    assert_eq!(result.signal, "host");
+
+    // Local runtimes take no credentials
+    let request = create_test_request(
+        "POST",
+        "/api/chat",
+        Some("gpu-box.internal"),
+        &[("Authorization", "Bearer token123")],
+    );
+    let request_view = RequestView::new(&request);
+    assert_eq!(registry.detect(&request_view), ProviderKind::Unknown);
+}
+
+#[test]
+fn test_deepseek_detection() {
+    let registry = ProviderRegistry::new();
+
+    // The DeepSeek host beats OpenAI's bearer token + /v1 path corroboration
+    let request = create_test_request(
+        "POST",
+        "/v1/chat/completions",
+        Some("api.deepseek.com"),
+        &[("Authorization", "Bearer sk-test")],
+    );
+    let result = registry.detect_result(&RequestView::new(&request)).unwrap();
+    assert_eq!(result.kind, ProviderKind::DeepSeek);
+    assert_eq!(result.signal, "host");
+
+    let request = create_test_request("POST", "/beta/completions", Some("proxy.internal"), &[]);
+    let result = registry.detect_result(&RequestView::new(&request)).unwrap();
+    assert_eq!(result.kind, ProviderKind::DeepSeek);
+    assert_eq!(result.confidence, Confidence::Medium);
+
+    // The OpenAI API is unaffected
+    let request = create_test_request("POST", "/v1/chat/completions", Some("api.openai.com"), &[]);
+    assert_eq!(
+        registry.detect(&RequestView::new(&request)),
+        ProviderKind::OpenAI
+    );
+}
+
+#[test]
+fn test_self_hosted_detection() {
+    let registry = ProviderRegistry::new();
+
+    for path in ["/generate", "/generate_stream"] {
+        let request = create_test_request("POST", path, Some("tgi.internal:8080"), &[]);
+        let result = registry.detect_result(&RequestView::new(&request)).unwrap();
+        assert_eq!(result.kind, ProviderKind::SelfHosted, "{}", path);
+        assert_eq!(result.signal, "path");
+    }
+
+    // The OpenAI-compatible API is only claimed on allowlisted hosts, even with a
+    // bearer token
+    let request = create_test_request(
+        "POST",
+        "/v1/chat/completions",
+        Some("vllm.internal:8000"),
+        &[("Authorization", "Bearer token123")],
+    );
+    let request_view = RequestView::new(&request);
+    assert_eq!(registry.detect(&request_view), ProviderKind::OpenAI);
+
+    let registry = ProviderRegistry::new().with_self_hosted_hosts(["vllm.internal", "tgi:8080"]);
+    let result = registry.detect_result(&request_view).unwrap();
+    assert_eq!(result.kind, ProviderKind::SelfHosted);
+    assert_eq!(result.signal, "host");
+
+    // Allowlist entries with a port only match that port
+    for (host, expected) in [
+        ("tgi:8080", ProviderKind::SelfHosted),
+        ("tgi:9090", ProviderKind::OpenAI),
+        ("api.openai.com", ProviderKind::OpenAI),
+    ] {
+        let request = create_test_request("POST", "/v1/chat/completions", Some(host), &[]);
+        assert_eq!(
+            registry.detect(&RequestView::new(&request)),
+            expected,
+            "{}",
+            host
+        );
+    }
+}
+
+#[test]
+fn test_stream_formats() {
+    use langspec::provider::StreamFormat;
+
+    let registry = ProviderRegistry::new();
+    let format = |kind, path| {
+        let request = create_test_request("POST", path, None, &[]);
+        registry.stream_format(kind, &RequestView::new(&request))
+    };
+
+    assert_eq!(
+        format(ProviderKind::OpenAI, "/v1/chat/completions"),
+        StreamFormat::ServerSentEvents
+    );
+    assert_eq!(
+        format(
+            ProviderKind::Bedrock,
+            "/model/anthropic.claude-v2/invoke-with-response-stream"
+        ),
+        StreamFormat::AwsEventStream
+    );
+    // Cohere streams newline-delimited JSON on v1 and SSE on v2
+    assert_eq!(
+        format(ProviderKind::Cohere, "/v1/generate"),
+        StreamFormat::JsonLines
+    );
+    assert_eq!(
+        format(ProviderKind::Cohere, "/v2/chat"),
+        StreamFormat::ServerSentEvents
+    );
+    assert_eq!(
+        format(ProviderKind::Ollama, "/api/chat"),
+        StreamFormat::JsonLines
+    );
+    assert_eq!(
+        format(ProviderKind::Ollama, "/v1/chat/completions"),
+        StreamFormat::ServerSentEvents
+    );
+    assert_eq!(
+        format(ProviderKind::Unknown, "/generate"),
+        StreamFormat::ServerSentEvents
+    );
+}
+
 #[test]
 fn test_explicit_override_header() {
     let registry = ProviderRegistry::new();
@@ -281,6 +562,26 @@ fn test_explicit_override_header() {
     let request_view = RequestView::new(&request);
     assert_eq!(registry.detect(&request_view), ProviderKind::Bedrock);
 
+    // Override to Cohere
+    let request = create_test_request(
+        "POST",
+        "/v1/chat/completions",
+        Some("api.openai.com"),
+        &[("X-Langspec-Provider", "cohere")],
+    );
+    let request_view = RequestView::new(&request);
+    assert_eq!(registry.detect(&request_view), ProviderKind::Cohere);
+
+    // Override to a self-hosted server
+    let request = create_test_request(
+        "POST",
+        "/v1/chat/completions",
+        Some("api.openai.com"),
+        &[("X-Langspec-Provider", "self-hosted")],
+    );
+    let request_view = RequestView::new(&request);
+    assert_eq!(registry.detect(&request_view), ProviderKind::SelfHosted);
+
     // Override to Unknown
     let request = create_test_request(
         "POST",
@@ -402,3 +703,238 @@ fn test_enhanced_logging_signals() {
     let request_view = RequestView::new(&request);
     assert_eq!(registry.detect(&request_view), ProviderKind::Bedrock);
 }
+
+#[test]
+fn test_conflicts_are_recorded_with_fingerprint() {
+    // Medium-confidence OpenAI path vs medium-confidence Bedrock path with AWS headers
+    let request = create_test_request(
+        "POST",
+        "/v1/chat/invoke?api-version=1",
+        Some("llm.internal"),
+        &[("x-amzn-trace-id", "Root=1-abc")],
+    );
+    let request_view = RequestView::new(&request);
+    let registry = ProviderRegistry::new();
+    registry.detect(&request_view);
+
+    let recent = registry.conflict_log().recent();
+    assert_eq!(recent.len(), 1);
+    let record = &recent[0];
+    assert_eq!(record.method, "POST");
+    assert_eq!(record.host.as_deref(), Some("llm.internal"));
+    // The query string is never kept
+    assert_eq!(record.path, "/v1/chat/invoke");
+    let providers: Vec<&str> = record.candidates.iter().map(|c| c.provider).collect();
+    assert_eq!(providers, vec!["openai", "bedrock"]);
+    assert!(record.candidates.iter().all(|c| c.confidence == "medium"));
+}
+
+#[test]
+fn test_conflict_log_keeps_most_recent() {
+    use langspec::provider::conflicts::ConflictLog;
+    use langspec::provider::{DetectionResult, ProviderKind};
+
+    let log = ConflictLog::new(2);
+    let candidates = [
+        DetectionResult::medium_confidence(ProviderKind::OpenAI, "test", "path"),
+        DetectionResult::low_confidence(ProviderKind::Bedrock, "test", "header"),
+    ];
+    for path in ["/first", "/second", "/third"] {
+        let request = create_test_request("POST", path, None, &[]);
+        log.record(&RequestView::new(&request), &candidates);
+    }
+
+    let paths: Vec<String> = log.recent().into_iter().map(|r| r.path).collect();
+    assert_eq!(paths, vec!["/third", "/second"]);
+}
+
+#[test]
+fn test_no_conflict_for_agreeing_detection() {
+    let request = create_test_request("POST", "/v1/chat/completions", Some("api.openai.com"), &[]);
+    let registry = ProviderRegistry::new();
+    registry.detect(&RequestView::new(&request));
+
+    assert!(registry.conflict_log().recent().is_empty());
+}
+
+#[cfg(feature = "admin")]
+#[test]
+fn test_admin_conflicts_endpoint() {
+    use langspec::proxy::GatewayProxy;
+
+    let proxy = GatewayProxy::new(vec!["127.0.0.1:8001".to_string()]);
+    let admin = proxy.admin_app();
+
+    let response = admin.handle("GET", "/conflicts");
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body, serde_json::json!([]));
+
+    assert_eq!(admin.handle("POST", "/conflicts").status(), 405);
+    assert_eq!(admin.handle("GET", "/unknown").status(), 404);
+}
+
+#[test]
+fn test_custom_provider_detection() {
+    use langspec::provider::custom::{CustomProvider, CustomProviderConfig, CustomProviderError};
+
+    let config: CustomProviderConfig = serde_json::from_value(serde_json::json!({
+        "name": "acme-llm",
+        "hosts": ["llm.acme.internal", "*.models.acme.internal"],
+        "paths": ["^/api/v\\d+/generate$"],
+        "auth": [{"header": "authorization", "prefix": "acme_"}],
+        "confidence": {"path": "low"}
+    }))
+    .unwrap();
+    let registry =
+        ProviderRegistry::new().with_custom_provider(CustomProvider::compile(config).unwrap());
+    let acme = ProviderKind::Custom("acme-llm");
+    let detect = |path: &str, host: &str, headers: &[(&str, &str)]| {
+        let request = create_test_request("POST", path, Some(host), headers);
+        registry.detect_result(&RequestView::new(&request))
+    };
+
+    // Host rule beats the OpenAI bearer token and path
+    let result = detect(
+        "/v1/chat/completions",
+        "llm.acme.internal:8443",
+        &[("authorization", "Bearer sk-test")],
+    )
+    .unwrap();
+    assert_eq!(
+        (result.kind, result.confidence, result.signal),
+        (acme, Confidence::High, "host")
+    );
+    assert_eq!(result.kind.as_str(), "acme-llm");
+    let result = detect("/v1/chat/completions", "eu.models.acme.internal", &[]).unwrap();
+    assert_eq!(result.signal, "host");
+    // The wildcard needs a subdomain
+    let result = detect("/v1/chat/completions", "models.acme.internal", &[]).unwrap();
+    assert_eq!(result.kind, ProviderKind::OpenAI);
+
+    let result = detect(
+        "/api/v2/generate",
+        "gateway.local",
+        &[("authorization", "Bearer acme_123")],
+    )
+    .unwrap();
+    assert_eq!(
+        (result.kind, result.confidence, result.signal),
+        (acme, Confidence::Medium, "auth")
+    );
+    let result = detect("/api/v2/generate", "gateway.local", &[]).unwrap();
+    assert_eq!(
+        (result.kind, result.confidence, result.signal),
+        (acme, Confidence::Low, "path")
+    );
+
+    // Custom names are accepted as overrides
+    let result = detect(
+        "/anything",
+        "gateway.local",
+        &[("x-langspec-provider", "ACME-LLM")],
+    )
+    .unwrap();
+    assert_eq!((result.kind, result.signal), (acme, "override"));
+
+    let invalid = |value: serde_json::Value| {
+        CustomProvider::compile(serde_json::from_value(value).unwrap()).unwrap_err()
+    };
+    assert!(matches!(
+        invalid(serde_json::json!({"name": "openai", "hosts": ["x"]})),
+        CustomProviderError::ReservedName(_)
+    ));
+    assert!(matches!(
+        invalid(serde_json::json!({"name": "Acme LLM", "hosts": ["x"]})),
+        CustomProviderError::InvalidName(_)
+    ));
+    assert!(matches!(
+        invalid(serde_json::json!({"name": "acme"})),
+        CustomProviderError::NoRules(_)
+    ));
+    assert!(matches!(
+        invalid(serde_json::json!({"name": "acme", "paths": ["("]})),
+        CustomProviderError::InvalidPath(..)
+    ));
+}
+
+#[test]
+fn test_provider_registry_snapshots() {
+    use langspec::pipeline::Pipeline;
+    use langspec::provider::snapshots::{RegistryConfig, RegistryError, RegistrySource};
+
+    let config: RegistryConfig = serde_json::from_value(serde_json::json!({
+        "providers": [{"name": "acme", "hosts": ["llm.acme.internal"]}],
+        "detection": [
+            {"provider": "openai", "hosts": ["openai.proxy.internal"], "paths": ["^/openai/"]},
+            {"provider": "self-hosted", "hosts": ["vllm.internal"]}
+        ]
+    }))
+    .unwrap();
+    let registry = config.compile().unwrap();
+    let detect = |registry: &ProviderRegistry, path: &str, host: &str| {
+        let request = create_test_request("POST", path, Some(host), &[]);
+        registry.detect_result(&RequestView::new(&request))
+    };
+
+    // Tuned hosts claim requests; tuned paths are Medium confidence candidates
+    let result = detect(&registry, "/anything", "openai.proxy.internal").unwrap();
+    assert_eq!(
+        (result.kind, result.confidence, result.signal),
+        (ProviderKind::OpenAI, Confidence::High, "host")
+    );
+    let result = detect(&registry, "/openai/generate", "gateway.local").unwrap();
+    assert_eq!(
+        (result.kind, result.confidence, result.signal),
+        (ProviderKind::OpenAI, Confidence::Medium, "path")
+    );
+    let result = detect(&registry, "/v1/chat/completions", "vllm.internal").unwrap();
+    assert_eq!(result.kind, ProviderKind::SelfHosted);
+    // Built-in rules still apply
+    let result = detect(&registry, "/v1/chat/completions", "api.openai.com").unwrap();
+    assert_eq!(result.reason, "api.openai.com exact match");
+    assert_eq!(registry.config(), &config);
+
+    let invalid = |detection: serde_json::Value| {
+        serde_json::from_value::<RegistryConfig>(serde_json::json!({ "detection": detection }))
+            .unwrap()
+            .compile()
+    };
+    assert!(matches!(
+        invalid(serde_json::json!([{"provider": "acme", "hosts": ["a"]}])),
+        Err(RegistryError::UnknownProvider(name)) if name == "acme"
+    ));
+    assert!(matches!(
+        invalid(serde_json::json!([{"provider": "openai"}, {"provider": "openai"}])),
+        Err(RegistryError::DuplicateTuning(_))
+    ));
+    assert!(matches!(
+        invalid(serde_json::json!([{"provider": "cohere", "paths": ["("]}])),
+        Err(RegistryError::InvalidPath(..))
+    ));
+
+    // Each registry config applied is a version; the same config keeps the version
+    let pipeline = Pipeline::new();
+    let snapshots = pipeline.provider_registry_snapshots();
+    assert_eq!(snapshots.current().version, 1);
+    let version = pipeline.set_provider_registry(registry, RegistrySource::Admin);
+    assert_eq!(version.version, 2);
+    assert_eq!(version.provider_names[0], "acme");
+    let again = pipeline.set_provider_registry(config.compile().unwrap(), RegistrySource::Config);
+    assert_eq!((again.version, again.source), (2, RegistrySource::Admin));
+    assert_eq!(
+        detect(&pipeline.provider_registry(), "/", "llm.acme.internal")
+            .unwrap()
+            .kind,
+        ProviderKind::Custom("acme")
+    );
+
+    let rollback = snapshots.rollback().unwrap();
+    assert_eq!((rollback.from, rollback.to), (2, 1));
+    assert!(detect(&pipeline.provider_registry(), "/", "llm.acme.internal").is_none());
+    assert_eq!(snapshots.versions().len(), 1);
+    assert!(matches!(
+        snapshots.rollback(),
+        Err(RegistryError::NoPreviousVersion)
+    ));
+}
@@ -16,7 +16,7 @@ fn test_gateway_proxy_round_robin() {
     // Track selections in order
     let mut selections = Vec::new();
     for _ in 0..9 {
-        selections.push(proxy.select_upstream().to_string());
+        selections.push(proxy.select_upstream().unwrap().to_string());
     }
 
     // Check that we cycle through all three backends three times
@@ -38,7 +38,7 @@ fn test_single_upstream() {
 
     // With a single upstream, it should always select the same one
     for _ in 0..5 {
-        assert_eq!(proxy.select_upstream(), "single-backend:8080");
+        assert_eq!(proxy.select_upstream(), Some("single-backend:8080"));
     }
 }
 
@@ -141,3 +141,152 @@ async fn test_multiple_header_operations() {
         "application/json"
     );
 }
+
+/// A local port nothing listens on
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Serve `proxy` on a local port in the background, returning the port
+fn serve(proxy: GatewayProxy) -> u16 {
+    let port = free_port();
+    serve_on(proxy, port);
+    port
+}
+
+/// Serve `proxy` on local `port` in the background
+fn serve_on(proxy: GatewayProxy, port: u16) {
+    use pingora::prelude::*;
+    use pingora::server::RunArgs;
+
+    let mut server = Server::new(None).unwrap();
+    server.bootstrap();
+    let mut service = http_proxy_service(&server.configuration, proxy);
+    service.add_tcp(&format!("127.0.0.1:{}", port));
+    server.add_service(service);
+    std::thread::spawn(move || server.run(RunArgs::default()));
+}
+
+/// Status the gateway on `port` answers a raw HTTP/1.1 `request` with
+fn response_status(port: u16, request: &str) -> u16 {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    let mut stream = (0..100)
+        .find_map(|_| {
+            TcpStream::connect(("127.0.0.1", port))
+                .inspect_err(|_| std::thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .expect("gateway did not start listening");
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut status_line = [0u8; 12];
+    stream.read_exact(&mut status_line).unwrap();
+    std::str::from_utf8(&status_line[9..12])
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[test]
+#[cfg(feature = "jwt")]
+fn test_passthrough_is_authenticated() {
+    use langspec::proxy::jwt::{JwtConfig, JwtValidator};
+    use langspec::proxy::passthrough::PassthroughAllowlist;
+
+    let config = JwtConfig::new(
+        "https://idp.example/.well-known/jwks.json",
+        "https://idp.example",
+        "langspec",
+    );
+    let proxy = GatewayProxy::new(vec!["127.0.0.1:1".to_string()])
+        .with_passthrough(PassthroughAllowlist::new().with_path_prefix("/internal/"))
+        .with_jwt(JwtValidator::compile(&config).unwrap());
+    let port = serve(proxy);
+
+    // Allowlisted paths skip the LLM pipeline, not authentication
+    let status = response_status(
+        port,
+        "GET /internal/status HTTP/1.1\r\nHost: metadata.internal\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(status, 401);
+}
+
+#[test]
+#[cfg(feature = "jwt")]
+fn test_grpc_call_is_authenticated() {
+    use langspec::proxy::jwt::{JwtConfig, JwtValidator};
+
+    let config = JwtConfig::new(
+        "https://idp.example/.well-known/jwks.json",
+        "https://idp.example",
+        "langspec",
+    );
+    let proxy = GatewayProxy::new(vec!["127.0.0.1:1".to_string()])
+        .with_grpc()
+        .with_jwt(JwtValidator::compile(&config).unwrap());
+    let port = serve(proxy);
+
+    // gRPC calls skip the stages reading the body, not authentication
+    let status = response_status(
+        port,
+        "POST /inference.Predictor/Predict HTTP/1.1\r\nHost: gateway\r\n\
+         Content-Type: application/grpc\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(status, 401);
+}
+
+#[test]
+fn test_budgets_charge_authenticated_tenants() {
+    use langspec::budget::BudgetPolicy;
+    use langspec::proxy::listeners::TenantListeners;
+    use std::time::Duration;
+
+    // acme has nothing left to spend
+    let budgets = || BudgetPolicy::new().with_budget("acme", 0.0, Duration::from_secs(3600));
+    let request = "POST /v1/chat/completions HTTP/1.1\r\nHost: gateway\r\n\
+                   X-Langspec-Tenant: acme\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+    // A client naming the tenant is not made as it, so is not held to its budget
+    let port =
+        serve(GatewayProxy::new(vec!["127.0.0.1:1".to_string()]).with_spend_budgets(budgets()));
+    assert_eq!(response_status(port, request), 502);
+
+    // A request arriving on the tenant's listener is
+    let port = free_port();
+    let listeners = TenantListeners::parse(&format!("acme=127.0.0.1:{}", port)).unwrap();
+    serve_on(
+        GatewayProxy::new(vec!["127.0.0.1:1".to_string()])
+            .with_tenant_listeners(listeners)
+            .with_spend_budgets(budgets()),
+        port,
+    );
+    assert_eq!(response_status(port, request), 402);
+}
+
+#[test]
+fn test_rate_limits_apply_per_caller() {
+    use langspec::pipeline::rate_limit::{RateLimit, RateLimitPolicy, RateLimitScope};
+
+    let limits = RateLimitPolicy::new().with_limit(
+        RateLimit::new("per-client", RateLimitScope::Key("*".into()), 0.001).with_burst(1),
+    );
+    let port = serve(GatewayProxy::new(vec!["127.0.0.1:1".to_string()]).with_rate_limits(limits));
+    let request = |key: &str, tenant: &str| {
+        format!(
+            "POST /v1/chat/completions HTTP/1.1\r\nHost: gateway\r\nAuthorization: Bearer {}\r\n\
+             X-Langspec-Tenant: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            key, tenant
+        )
+    };
+
+    // Callers are told apart by their credentials, not by the tenant they name
+    assert_eq!(response_status(port, &request("sk-alice", "acme")), 502);
+    assert_eq!(response_status(port, &request("sk-alice", "globex")), 429);
+    assert_eq!(response_status(port, &request("sk-bob", "acme")), 502);
+}
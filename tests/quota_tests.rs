@@ -0,0 +1,72 @@
+use langspec::quota::{QuotaWindow, WindowClock};
+use std::time::{Duration, Instant};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+#[test]
+fn test_window_clock_follows_small_drift() {
+    let clock = WindowClock::with_tolerance(Duration::from_secs(2));
+    let start = clock.now();
+    assert!(!clock.is_skewed());
+
+    // The wall clock is slewed one second ahead: followed without a skew event
+    let slewed = clock.observe(start + Duration::from_secs(1), Instant::now());
+    assert!(!clock.is_skewed());
+    assert_eq!(slewed, start + Duration::from_secs(1));
+}
+
+#[test]
+fn test_window_clock_ignores_jumps() {
+    let clock = WindowClock::with_tolerance(Duration::from_secs(2));
+    let mono = Instant::now();
+    let start = clock.observe(clock.now(), mono);
+
+    // Wall clock jumps a day ahead while only 10s pass on the monotonic clock
+    let later = mono + Duration::from_secs(10);
+    let jumped = clock.observe(start + Duration::from_secs(86_400), later);
+    assert!(clock.is_skewed());
+    assert!(jumped < start + Duration::from_secs(12));
+    assert!(jumped >= start + Duration::from_secs(10));
+
+    // Wall clock jumps back an hour: time never goes backwards
+    let back = clock.observe(
+        start - Duration::from_secs(3_600),
+        later + Duration::from_secs(1),
+    );
+    assert!(back >= jumped);
+
+    // Once the wall clock agrees again, it is followed
+    let agreed = start + Duration::from_secs(12);
+    let now = clock.observe(agreed, later + Duration::from_secs(2));
+    assert!(!clock.is_skewed());
+    assert_eq!(now, agreed);
+}
+
+#[test]
+fn test_quota_window_consumption_and_reset() {
+    let window = QuotaWindow::new(MINUTE);
+    let t0 = Duration::from_secs(60 * 1_000);
+
+    assert!(window.try_consume(t0, 6, 10));
+    assert!(!window.try_consume(t0 + Duration::from_secs(5), 5, 10));
+    assert!(window.try_consume(t0 + Duration::from_secs(5), 4, 10));
+    assert_eq!(window.used(t0), 10);
+    assert_eq!(window.window_start(t0 + Duration::from_secs(30)), t0);
+
+    // A new window starts with a fresh budget
+    let next = t0 + MINUTE;
+    assert_eq!(window.used(next), 0);
+    assert!(window.try_consume(next, 10, 10));
+    assert_eq!(window.window_start(next), next);
+
+    // Time appearing to go back never reopens the previous window
+    assert_eq!(window.used(t0 + Duration::from_secs(59)), 10);
+    window.record(t0, 3);
+    assert_eq!(window.used(next), 13);
+}
+
+#[test]
+#[should_panic(expected = "Quota window length must be positive")]
+fn test_quota_window_rejects_zero_length() {
+    QuotaWindow::new(Duration::ZERO);
+}
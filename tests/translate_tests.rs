@@ -0,0 +1,964 @@
+use langspec::translate::stream::StreamTranslator;
+use langspec::translate::unified::{
+    ModelRoute, UnifiedApi, is_model_list, model_not_found, unknown_url,
+};
+use langspec::translate::{
+    Dialect, Endpoint, TranslationConfig, TranslationError, is_chat_completions,
+};
+use serde_json::{Value, json};
+
+fn converse(request: Value) -> (String, Value) {
+    let translated = Dialect::BedrockConverse
+        .translate_chat_request(request.to_string().as_bytes())
+        .unwrap();
+    let body = serde_json::from_slice(&translated.body).unwrap();
+    (translated.path, body)
+}
+
+fn converse_error(request: Value) -> TranslationError {
+    Dialect::BedrockConverse
+        .translate_chat_request(request.to_string().as_bytes())
+        .unwrap_err()
+}
+
+#[test]
+fn test_bedrock_translation_of_basic_chat() {
+    let request = json!({
+        "model": "anthropic.claude-3-5-sonnet-20240620-v1:0",
+        "messages": [
+            {"role": "system", "content": "Be brief."},
+            {"role": "user", "content": "Hello"},
+            {"role": "assistant", "content": "Hi!"},
+            {"role": "user", "content": [{"type": "text", "text": "How are you?"}]}
+        ],
+        "max_tokens": 256,
+        "temperature": 0.2,
+        "top_p": 0.9,
+        "stop": "END",
+        "user": "end-user-1"
+    });
+    let translated = Dialect::BedrockConverse
+        .translate_chat_request(request.to_string().as_bytes())
+        .unwrap();
+    assert_eq!(
+        translated.path,
+        "/model/anthropic.claude-3-5-sonnet-20240620-v1:0/converse"
+    );
+    assert_eq!(
+        translated.model,
+        "anthropic.claude-3-5-sonnet-20240620-v1:0"
+    );
+    assert!(
+        translated
+            .headers
+            .contains(&("accept", "application/json".to_string()))
+    );
+    assert!(translated.removed_headers.contains(&"openai-organization"));
+
+    let body: Value = serde_json::from_slice(&translated.body).unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "system": [{"text": "Be brief."}],
+            "messages": [
+                {"role": "user", "content": [{"text": "Hello"}]},
+                {"role": "assistant", "content": [{"text": "Hi!"}]},
+                {"role": "user", "content": [{"text": "How are you?"}]}
+            ],
+            "inferenceConfig": {
+                "maxTokens": 256,
+                "temperature": 0.2,
+                "topP": 0.9,
+                "stopSequences": ["END"]
+            }
+        })
+    );
+}
+
+#[test]
+fn test_bedrock_translation_of_streams_and_arns() {
+    let (path, _) = converse(json!({
+        "model": "arn:aws:bedrock:us-east-1:123456789012:inference-profile/us.meta.llama3-1-8b",
+        "stream": true,
+        "messages": [{"role": "user", "content": "Hi"}]
+    }));
+    assert_eq!(
+        path,
+        "/model/arn:aws:bedrock:us-east-1:123456789012:inference-profile%2Fus.meta.llama3-1-8b/converse-stream"
+    );
+}
+
+#[test]
+fn test_bedrock_translation_merges_consecutive_roles() {
+    let (_, body) = converse(json!({
+        "model": "amazon.nova-lite-v1:0",
+        "messages": [
+            {"role": "developer", "content": "Rule one."},
+            {"role": "system", "content": "Rule two."},
+            {"role": "user", "content": "First"},
+            {"role": "user", "content": "Second"}
+        ]
+    }));
+    assert_eq!(
+        body["system"],
+        json!([{"text": "Rule one."}, {"text": "Rule two."}])
+    );
+    assert_eq!(
+        body["messages"],
+        json!([{"role": "user", "content": [{"text": "First"}, {"text": "Second"}]}])
+    );
+    assert!(body.get("inferenceConfig").is_none());
+}
+
+#[test]
+fn test_bedrock_translation_of_tools() {
+    let (_, body) = converse(json!({
+        "model": "anthropic.claude-3-haiku-20240307-v1:0",
+        "messages": [
+            {"role": "user", "content": "Weather in Paris?"},
+            {"role": "assistant", "content": null, "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+            }]},
+            {"role": "tool", "tool_call_id": "call_1", "content": "18°C"},
+            {"role": "user", "content": "Thanks"}
+        ],
+        "tools": [{
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "description": "Current weather",
+                "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}
+            }
+        }],
+        "tool_choice": {"type": "function", "function": {"name": "get_weather"}}
+    }));
+    assert_eq!(
+        body["messages"],
+        json!([
+            {"role": "user", "content": [{"text": "Weather in Paris?"}]},
+            {"role": "assistant", "content": [{"toolUse": {
+                "toolUseId": "call_1", "name": "get_weather", "input": {"city": "Paris"}
+            }}]},
+            {"role": "user", "content": [
+                {"toolResult": {"toolUseId": "call_1", "content": [{"text": "18°C"}]}},
+                {"text": "Thanks"}
+            ]}
+        ])
+    );
+    assert_eq!(
+        body["toolConfig"],
+        json!({
+            "tools": [{"toolSpec": {
+                "name": "get_weather",
+                "description": "Current weather",
+                "inputSchema": {"json": {"type": "object", "properties": {"city": {"type": "string"}}}}
+            }}],
+            "toolChoice": {"tool": {"name": "get_weather"}}
+        })
+    );
+
+    let tools = json!([{"type": "function", "function": {"name": "noop"}}]);
+    let (_, body) = converse(json!({
+        "model": "m",
+        "messages": [{"role": "user", "content": "Hi"}],
+        "tools": tools,
+        "tool_choice": "required"
+    }));
+    assert_eq!(body["toolConfig"]["toolChoice"], json!({"any": {}}));
+    assert_eq!(
+        body["toolConfig"]["tools"][0]["toolSpec"]["inputSchema"],
+        json!({"json": {"type": "object", "properties": {}}})
+    );
+
+    // Converse cannot offer tools while forbidding them: leave them out
+    let (_, body) = converse(json!({
+        "model": "m",
+        "messages": [{"role": "user", "content": "Hi"}],
+        "tools": tools,
+        "tool_choice": "none"
+    }));
+    assert!(body.get("toolConfig").is_none());
+}
+
+#[test]
+fn test_bedrock_translation_of_images() {
+    let (_, body) = converse(json!({
+        "model": "m",
+        "messages": [{"role": "user", "content": [
+            {"type": "text", "text": "What is this?"},
+            {"type": "image_url", "image_url": {"url": "data:image/jpg;base64,aGVsbG8="}}
+        ]}]
+    }));
+    assert_eq!(
+        body["messages"][0]["content"][1],
+        json!({"image": {"format": "jpeg", "source": {"bytes": "aGVsbG8="}}})
+    );
+
+    let remote = converse_error(json!({
+        "model": "m",
+        "messages": [{"role": "user", "content": [
+            {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}
+        ]}]
+    }));
+    assert!(matches!(remote, TranslationError::Unsupported(_)));
+}
+
+#[test]
+fn test_bedrock_translation_rejects_invalid_requests() {
+    let not_json = Dialect::BedrockConverse.translate_chat_request(b"{\"model\":");
+    assert!(matches!(not_json, Err(TranslationError::InvalidJson(_))));
+
+    let messages = json!([{"role": "user", "content": "Hi"}]);
+    assert!(matches!(
+        converse_error(json!({"messages": messages})),
+        TranslationError::InvalidField("model")
+    ));
+    assert!(matches!(
+        converse_error(json!({"model": "m", "messages": []})),
+        TranslationError::InvalidField("messages")
+    ));
+    assert!(matches!(
+        converse_error(json!({"model": "m", "messages": messages, "n": 2})),
+        TranslationError::Unsupported(_)
+    ));
+    assert!(matches!(
+        converse_error(json!({"model": "m", "messages": [{"role": "system", "content": "x"}]})),
+        TranslationError::Unsupported(_)
+    ));
+    assert!(matches!(
+        converse_error(json!({"model": "m", "messages": messages, "max_tokens": "many"})),
+        TranslationError::InvalidField("max_tokens")
+    ));
+    assert!(matches!(
+        converse_error(json!({"model": "m", "messages": [{
+            "role": "assistant",
+            "tool_calls": [{"id": "c", "function": {"name": "f", "arguments": "{not json"}}]
+        }]})),
+        TranslationError::InvalidField("messages.tool_calls.arguments")
+    ));
+}
+
+#[test]
+fn test_translation_targets() {
+    let config = TranslationConfig::new().with_upstream("127.0.0.1:9000", Dialect::BedrockConverse);
+    assert_eq!(
+        config.dialect_for(
+            "bedrock-runtime.us-east-1.amazonaws.com:443",
+            "bedrock-runtime.us-east-1.amazonaws.com"
+        ),
+        Some(Dialect::BedrockConverse)
+    );
+    assert_eq!(
+        config.dialect_for("127.0.0.1:9000", "127.0.0.1"),
+        Some(Dialect::BedrockConverse)
+    );
+    assert_eq!(
+        config.dialect_for("api.openai.com:443", "api.openai.com"),
+        None
+    );
+    assert_eq!(
+        config.dialect_for(
+            "bedrock.us-east-1.amazonaws.com:443",
+            "bedrock.us-east-1.amazonaws.com"
+        ),
+        None
+    );
+
+    assert!(is_chat_completions("/v1/chat/completions"));
+    assert!(is_chat_completions("/v1/chat/completions/?x=1"));
+    assert!(!is_chat_completions("/v1/completions"));
+    assert!(!is_chat_completions("/openai/v1/chat/completions"));
+}
+
+#[test]
+fn test_anthropic_translation_of_requests() {
+    let request = json!({
+        "model": "claude-sonnet-4-5",
+        "messages": [
+            {"role": "system", "content": "Be brief."},
+            {"role": "user", "content": [
+                {"type": "text", "text": "Describe"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,aGVsbG8="}},
+                {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}
+            ]},
+            {"role": "assistant", "content": "Looking.", "tool_calls": [{
+                "id": "toolu_1",
+                "type": "function",
+                "function": {"name": "zoom", "arguments": "{\"factor\":2}"}
+            }]},
+            {"role": "tool", "tool_call_id": "toolu_1", "content": "zoomed"}
+        ],
+        "temperature": 0.5,
+        "stop": ["###"],
+        "stream": false,
+        "user": "end-user-1",
+        "tools": [{"type": "function", "function": {"name": "zoom", "parameters": {"type": "object"}}}],
+        "tool_choice": "auto"
+    });
+    let translated = Dialect::AnthropicMessages
+        .translate_chat_request(request.to_string().as_bytes())
+        .unwrap();
+    assert_eq!(translated.path, "/v1/messages");
+    assert_eq!(translated.api_key_header, Some("x-api-key"));
+    assert_eq!(
+        translated.default_headers,
+        [("anthropic-version", "2023-06-01".to_string())]
+    );
+
+    let body: Value = serde_json::from_slice(&translated.body).unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "model": "claude-sonnet-4-5",
+            // Required by Messages, defaulted when the client leaves it out
+            "max_tokens": 4096,
+            "system": [{"type": "text", "text": "Be brief."}],
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "text", "text": "Describe"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "aGVsbG8="}},
+                    {"type": "image", "source": {"type": "url", "url": "https://example.com/cat.png"}}
+                ]},
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "Looking."},
+                    {"type": "tool_use", "id": "toolu_1", "name": "zoom", "input": {"factor": 2}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": [{"type": "text", "text": "zoomed"}]}
+                ]}
+            ],
+            "temperature": 0.5,
+            "stop_sequences": ["###"],
+            "stream": false,
+            "metadata": {"user_id": "end-user-1"},
+            "tools": [{"name": "zoom", "input_schema": {"type": "object"}}],
+            "tool_choice": {"type": "auto"}
+        })
+    );
+
+    let translated = Dialect::AnthropicMessages
+        .translate_chat_request(
+            json!({
+                "model": "claude-haiku-4-5",
+                "max_completion_tokens": 100,
+                "max_tokens": 50,
+                "messages": [{"role": "user", "content": "Hi"}]
+            })
+            .to_string()
+            .as_bytes(),
+        )
+        .unwrap();
+    let body: Value = serde_json::from_slice(&translated.body).unwrap();
+    assert_eq!(body["max_tokens"], 100);
+}
+
+#[test]
+fn test_anthropic_translation_of_responses() {
+    let response = json!({
+        "id": "msg_01",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-sonnet-4-5",
+        "content": [
+            {"type": "text", "text": "Let me check."},
+            {"type": "tool_use", "id": "toolu_2", "name": "get_weather", "input": {"city": "Oslo"}}
+        ],
+        "stop_reason": "tool_use",
+        "usage": {"input_tokens": 20, "output_tokens": 12}
+    });
+    let translated = Dialect::AnthropicMessages
+        .translate_chat_response(response.to_string().as_bytes(), "claude-sonnet-4-5")
+        .unwrap();
+    let completion: Value = serde_json::from_slice(&translated).unwrap();
+    assert_eq!(completion["id"], "msg_01");
+    assert_eq!(completion["object"], "chat.completion");
+    assert_eq!(completion["model"], "claude-sonnet-4-5");
+    assert_eq!(
+        completion["choices"],
+        json!([{
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": "Let me check.",
+                "tool_calls": [{
+                    "id": "toolu_2",
+                    "type": "function",
+                    "function": {"name": "get_weather", "arguments": "{\"city\":\"Oslo\"}"}
+                }]
+            },
+            "finish_reason": "tool_calls"
+        }])
+    );
+    assert_eq!(
+        completion["usage"],
+        json!({"prompt_tokens": 20, "completion_tokens": 12, "total_tokens": 32})
+    );
+
+    let truncated = json!({
+        "id": "msg_02", "type": "message", "model": "m",
+        "content": [{"type": "text", "text": "Once upon"}],
+        "stop_reason": "max_tokens", "usage": {"input_tokens": 5, "output_tokens": 2}
+    });
+    let completion: Value = serde_json::from_slice(
+        &Dialect::AnthropicMessages
+            .translate_chat_response(truncated.to_string().as_bytes(), "m")
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(completion["choices"][0]["finish_reason"], "length");
+
+    let error = json!({
+        "type": "error",
+        "error": {"type": "overloaded_error", "message": "Overloaded"}
+    });
+    let body: Value = serde_json::from_slice(
+        &Dialect::AnthropicMessages
+            .translate_chat_response(error.to_string().as_bytes(), "m")
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        body,
+        json!({"error": {"message": "Overloaded", "type": "overloaded_error", "param": null, "code": null}})
+    );
+
+    assert!(matches!(
+        Dialect::AnthropicMessages.translate_chat_response(b"{}", "m"),
+        Err(TranslationError::InvalidField("type"))
+    ));
+}
+
+#[test]
+fn test_translation_routes() {
+    let config = TranslationConfig::new()
+        .with_route("/anthropic/", Dialect::AnthropicMessages)
+        .with_upstream("10.0.0.5:8080", Dialect::BedrockConverse);
+
+    // Routes win over the upstream's dialect
+    assert_eq!(
+        config.dialect_for_request(
+            "/anthropic/v1/chat/completions",
+            "10.0.0.5:8080",
+            "10.0.0.5"
+        ),
+        Some(Dialect::AnthropicMessages)
+    );
+    assert_eq!(
+        config.dialect_for_request("/v1/chat/completions", "10.0.0.5:8080", "10.0.0.5"),
+        Some(Dialect::BedrockConverse)
+    );
+    assert_eq!(
+        config.dialect_for_request(
+            "/v1/chat/completions",
+            "api.anthropic.com:443",
+            "api.anthropic.com"
+        ),
+        Some(Dialect::AnthropicMessages)
+    );
+    assert_eq!(
+        config.dialect_for_request(
+            "/v1/chat/completions",
+            "api.openai.com:443",
+            "api.openai.com"
+        ),
+        None
+    );
+    assert_eq!(
+        config.dialect_for_request(
+            "/anthropic/v1/models",
+            "api.openai.com:443",
+            "api.openai.com"
+        ),
+        None
+    );
+    // Embeddings are translated for Bedrock only
+    assert_eq!(
+        config.dialect_for_request("/v1/embeddings", "10.0.0.5:8080", "10.0.0.5"),
+        Some(Dialect::BedrockConverse)
+    );
+    assert_eq!(
+        config.dialect_for_request("/anthropic/v1/embeddings", "10.0.0.5:8080", "10.0.0.5"),
+        None
+    );
+}
+
+#[test]
+fn test_bedrock_translation_of_responses() {
+    let response = json!({
+        "output": {"message": {"role": "assistant", "content": [
+            {"text": "Checking the weather."},
+            {"toolUse": {"toolUseId": "tooluse_1", "name": "get_weather", "input": {"city": "Oslo"}}}
+        ]}},
+        "stopReason": "tool_use",
+        "usage": {"inputTokens": 30, "outputTokens": 9, "totalTokens": 39},
+        "metrics": {"latencyMs": 412}
+    });
+    let completion: Value = serde_json::from_slice(
+        &Dialect::BedrockConverse
+            .translate_chat_response(response.to_string().as_bytes(), "claude-sonnet")
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(completion["id"].as_str().unwrap().starts_with("chatcmpl-"));
+    assert_eq!(completion["object"], "chat.completion");
+    // Converse names no model: the requested one is reported
+    assert_eq!(completion["model"], "claude-sonnet");
+    assert_eq!(
+        completion["choices"],
+        json!([{
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": "Checking the weather.",
+                "tool_calls": [{
+                    "id": "tooluse_1",
+                    "type": "function",
+                    "function": {"name": "get_weather", "arguments": "{\"city\":\"Oslo\"}"}
+                }]
+            },
+            "finish_reason": "tool_calls"
+        }])
+    );
+    assert_eq!(
+        completion["usage"],
+        json!({"prompt_tokens": 30, "completion_tokens": 9, "total_tokens": 39})
+    );
+
+    let filtered = json!({
+        "output": {"message": {"role": "assistant", "content": [{"text": "Sorry."}]}},
+        "stopReason": "guardrail_intervened",
+        "usage": {"inputTokens": 3, "outputTokens": 1}
+    });
+    let completion: Value = serde_json::from_slice(
+        &Dialect::BedrockConverse
+            .translate_chat_response(filtered.to_string().as_bytes(), "m")
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(completion["choices"][0]["finish_reason"], "content_filter");
+
+    let error = json!({"message": "Malformed input request"});
+    let body: Value = serde_json::from_slice(
+        &Dialect::BedrockConverse
+            .translate_chat_response(error.to_string().as_bytes(), "m")
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(body["error"]["message"], "Malformed input request");
+
+    assert!(matches!(
+        Dialect::BedrockConverse.translate_chat_response(b"{}", "m"),
+        Err(TranslationError::InvalidField("output"))
+    ));
+}
+
+#[test]
+fn test_unified_api_model_routes() {
+    let api = UnifiedApi::new()
+        .with_model(
+            ModelRoute::new(
+                "claude-sonnet",
+                "bedrock-runtime.us-east-1.amazonaws.com:443",
+            )
+            .with_upstream("bedrock-runtime.us-west-2.amazonaws.com:443")
+            .with_upstream_model("anthropic.claude-sonnet-4-5-20250929-v1:0"),
+        )
+        .with_model(
+            ModelRoute::new("anthropic/*", "api.anthropic.com:443")
+                .with_dialect(Dialect::AnthropicMessages)
+                .with_upstream_model("*"),
+        )
+        .with_model(ModelRoute::new("gpt-4o", "api.openai.com:443"));
+
+    let route = api.route_for("claude-sonnet").unwrap();
+    assert_eq!(route.upstreams.len(), 2);
+    assert_eq!(
+        route.upstream_model_for("claude-sonnet").as_deref(),
+        Some("anthropic.claude-sonnet-4-5-20250929-v1:0")
+    );
+    let route = api.route_for("anthropic/claude-haiku-4-5").unwrap();
+    assert_eq!(route.dialect, Some(Dialect::AnthropicMessages));
+    assert_eq!(
+        route
+            .upstream_model_for("anthropic/claude-haiku-4-5")
+            .as_deref(),
+        Some("claude-haiku-4-5")
+    );
+    // A prefix alone names no model
+    assert!(api.route_for("anthropic/").is_none());
+    assert_eq!(
+        api.route_for("gpt-4o")
+            .unwrap()
+            .upstream_model_for("gpt-4o"),
+        None
+    );
+    assert!(api.route_for("gpt-4o-mini").is_none());
+
+    let request = json!({"model": "anthropic/claude-haiku-4-5", "messages": []});
+    let rewritten = api
+        .route_for("anthropic/claude-haiku-4-5")
+        .unwrap()
+        .rewrite_request(request.to_string().as_bytes())
+        .unwrap()
+        .unwrap();
+    let rewritten: Value = serde_json::from_slice(&rewritten).unwrap();
+    assert_eq!(
+        rewritten,
+        json!({"model": "claude-haiku-4-5", "messages": []})
+    );
+    let request = json!({"model": "gpt-4o", "messages": []});
+    assert_eq!(
+        api.route_for("gpt-4o")
+            .unwrap()
+            .rewrite_request(request.to_string().as_bytes())
+            .unwrap(),
+        None
+    );
+
+    // Prefix routes are not listed
+    let list = api.model_list();
+    assert_eq!(list["object"], "list");
+    let ids: Vec<&str> = list["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|model| model["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, ["claude-sonnet", "gpt-4o"]);
+    assert_eq!(list["data"][1]["owned_by"], "openai");
+
+    assert_eq!(model_not_found("o9")["error"]["code"], "model_not_found");
+    assert!(is_model_list("/v1/models"));
+    assert!(!is_model_list("/v1/models/gpt-4o"));
+}
+
+#[test]
+fn test_bedrock_facade() {
+    let api = UnifiedApi::bedrock_facade(
+        "bedrock-runtime.us-east-1.amazonaws.com:443",
+        [
+            ("gpt-4o", "anthropic.claude-sonnet-4-5-20250929-v1:0"),
+            ("text-embedding-3-small", "amazon.titan-embed-text-v2:0"),
+        ],
+    );
+    assert!(api.openai_only);
+    assert!(api.serves("/v1/chat/completions"));
+    assert!(api.serves("/v1/embeddings"));
+    assert!(api.serves("/v1/models"));
+    assert!(!api.serves("/v1/responses"));
+    assert!(!api.serves("/model/amazon.titan-embed-text-v2:0/invoke"));
+    assert_eq!(
+        unknown_url("POST", "/v1/responses")["error"]["code"],
+        "unknown_url"
+    );
+    assert_eq!(
+        api.route_for("gpt-4o")
+            .unwrap()
+            .upstream_model_for("gpt-4o")
+            .as_deref(),
+        Some("anthropic.claude-sonnet-4-5-20250929-v1:0")
+    );
+    // Bedrock model IDs are served as named, but only aliases are listed
+    let route = api.route_for("cohere.embed-english-v3").unwrap();
+    assert_eq!(route.dialect, Some(Dialect::BedrockConverse));
+    assert_eq!(route.upstream_model_for("cohere.embed-english-v3"), None);
+    assert_eq!(api.model_list()["data"].as_array().unwrap().len(), 2);
+    assert_eq!(api.model_list()["data"][0]["owned_by"], "bedrock");
+
+    assert_eq!(Endpoint::of("/v1/embeddings"), Some(Endpoint::Embeddings));
+    assert_eq!(
+        Endpoint::under("/bedrock/v1/embeddings"),
+        Some(Endpoint::Embeddings)
+    );
+
+    // Titan embeds one input
+    let request =
+        json!({"model": "amazon.titan-embed-text-v2:0", "input": "hello", "dimensions": 256});
+    let translated = Dialect::BedrockConverse
+        .translate_embeddings_request(request.to_string().as_bytes())
+        .unwrap();
+    assert_eq!(
+        translated.path,
+        "/model/amazon.titan-embed-text-v2:0/invoke"
+    );
+    let body: Value = serde_json::from_slice(&translated.body).unwrap();
+    assert_eq!(body, json!({"inputText": "hello", "dimensions": 256}));
+    let request = json!({"model": "amazon.titan-embed-text-v2:0", "input": ["a", "b"]});
+    assert!(matches!(
+        Dialect::BedrockConverse.translate_embeddings_request(request.to_string().as_bytes()),
+        Err(TranslationError::Unsupported(_))
+    ));
+    let response = json!({"embedding": [0.25, -0.5], "inputTextTokenCount": 2});
+    let embeddings: Value = serde_json::from_slice(
+        &Dialect::BedrockConverse
+            .translate_embeddings_response(
+                response.to_string().as_bytes(),
+                "text-embedding-3-small",
+            )
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        embeddings,
+        json!({
+            "object": "list",
+            "data": [{"object": "embedding", "index": 0, "embedding": [0.25, -0.5]}],
+            "model": "text-embedding-3-small",
+            "usage": {"prompt_tokens": 2, "total_tokens": 2}
+        })
+    );
+
+    // Cohere embeds a list
+    let request = json!({"model": "cohere.embed-english-v3", "input": ["a", "b"]});
+    let translated = Dialect::BedrockConverse
+        .translate_embeddings_request(request.to_string().as_bytes())
+        .unwrap();
+    let body: Value = serde_json::from_slice(&translated.body).unwrap();
+    assert_eq!(body["texts"], json!(["a", "b"]));
+    let response = json!({"id": "e1", "embeddings": [[0.5], [1.0]], "texts": ["a", "b"]});
+    let embeddings: Value = serde_json::from_slice(
+        &Dialect::BedrockConverse
+            .translate_embeddings_response(response.to_string().as_bytes(), "m")
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(embeddings["data"][1]["index"], 1);
+    assert_eq!(embeddings["data"][1]["embedding"], json!([1.0]));
+    assert!(matches!(
+        Dialect::AnthropicMessages.translate_embeddings_request(request.to_string().as_bytes()),
+        Err(TranslationError::Unsupported(_))
+    ));
+
+    // Errors are typed by their exception, or else their status
+    let error = |body: Value, error_type: Option<&str>, status: u16| -> Value {
+        let body = Dialect::BedrockConverse
+            .translate_error_response(body.to_string().as_bytes(), error_type, status)
+            .unwrap();
+        serde_json::from_slice::<Value>(&body).unwrap()["error"].clone()
+    };
+    let throttled = error(
+        json!({"message": "Too many requests"}),
+        Some("ThrottlingException:http://internal.amazon.com/coral/com.amazon.bedrock/"),
+        429,
+    );
+    assert_eq!(throttled["type"], "rate_limit_error");
+    assert_eq!(throttled["code"], "rate_limit_exceeded");
+    assert_eq!(throttled["message"], "Too many requests");
+    let not_found = error(
+        json!({"message": "Model not found"}),
+        Some("ResourceNotFoundException"),
+        404,
+    );
+    assert_eq!(not_found["code"], "model_not_found");
+    let denied = error(json!({"Message": "Denied"}), None, 403);
+    assert_eq!(denied["type"], "permission_error");
+    let unknown = error(json!({"message": "Boom"}), None, 502);
+    assert_eq!(unknown["type"], "api_error");
+}
+
+/// `data:` payloads of translated chunks, `[DONE]` as a string
+fn sse_events(body: &[u8]) -> Vec<Value> {
+    std::str::from_utf8(body)
+        .unwrap()
+        .split("\n\n")
+        .filter_map(|event| event.strip_prefix("data: "))
+        .map(|data| serde_json::from_str(data).unwrap_or_else(|_| Value::from(data)))
+        .collect()
+}
+
+#[test]
+fn test_anthropic_stream_translation() {
+    let upstream = concat!(
+        "event: message_start\n",
+        "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_7\",\"model\":\"claude-sonnet-4-5\",\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n",
+        "event: content_block_start\n",
+        "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+        "event: ping\n",
+        "data: {\"type\":\"ping\"}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n",
+        "event: content_block_start\n",
+        "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_9\",\"name\":\"lookup\",\"input\":{}}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"q\\\":\"}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"1}\"}}\n\n",
+        "event: message_delta\n",
+        "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":14}}\n\n",
+        "event: message_stop\n",
+        "data: {\"type\":\"message_stop\"}\n\n",
+    );
+
+    // Chunk boundaries fall anywhere, including inside lines
+    let mut translator = StreamTranslator::new(Dialect::AnthropicMessages, "claude");
+    let mut body = Vec::new();
+    for chunk in upstream.as_bytes().chunks(7) {
+        body.extend(translator.feed(chunk));
+    }
+    assert!(translator.is_done());
+    body.extend(translator.finish());
+
+    let events = sse_events(&body);
+    assert!(events[..events.len() - 2].iter().all(|event| {
+        event["id"] == "msg_7"
+            && event["object"] == "chat.completion.chunk"
+            && event["model"] == "claude-sonnet-4-5"
+    }));
+    let deltas: Vec<&Value> = events
+        .iter()
+        .filter_map(|event| event["choices"].get(0))
+        .collect();
+    assert_eq!(
+        deltas[0]["delta"],
+        json!({"role": "assistant", "content": ""})
+    );
+    assert_eq!(deltas[1]["delta"], json!({"content": "Hello"}));
+    assert_eq!(
+        deltas[2]["delta"]["tool_calls"],
+        json!([{"index": 0, "id": "toolu_9", "type": "function", "function": {"name": "lookup", "arguments": ""}}])
+    );
+    assert_eq!(
+        deltas[3]["delta"]["tool_calls"][0]["function"]["arguments"],
+        "{\"q\":"
+    );
+    assert_eq!(
+        deltas[4]["delta"]["tool_calls"][0]["function"]["arguments"],
+        "1}"
+    );
+    assert_eq!(deltas[5]["finish_reason"], "tool_calls");
+    assert_eq!(deltas.len(), 6);
+
+    let usage = &events[events.len() - 2];
+    assert_eq!(usage["choices"], json!([]));
+    assert_eq!(
+        usage["usage"],
+        json!({"prompt_tokens": 25, "completion_tokens": 14, "total_tokens": 39})
+    );
+    assert_eq!(events.last().unwrap(), "[DONE]");
+
+    // Errors mid-stream end it
+    let mut translator = StreamTranslator::new(Dialect::AnthropicMessages, "claude");
+    let body = translator.feed(
+        b"event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+    );
+    assert_eq!(
+        sse_events(&body),
+        [
+            json!({"error": {"message": "Overloaded", "type": "overloaded_error", "param": null, "code": null}}),
+            Value::from("[DONE]")
+        ]
+    );
+    assert!(translator.finish().is_empty());
+}
+
+#[test]
+fn test_bedrock_stream_translation() {
+    // Prelude (total length, headers length, CRC), headers, payload, CRC; CRCs are not checked
+    fn frame(headers: &[(&str, &str)], payload: &str) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for (name, value) in headers {
+            encoded.push(name.len() as u8);
+            encoded.extend_from_slice(name.as_bytes());
+            encoded.push(7);
+            encoded.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            encoded.extend_from_slice(value.as_bytes());
+        }
+        let total = 12 + encoded.len() + payload.len() + 4;
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&(total as u32).to_be_bytes());
+        frame.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        frame.extend_from_slice(&encoded);
+        frame.extend_from_slice(payload.as_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        frame
+    }
+    fn event(event_type: &str, payload: &str) -> Vec<u8> {
+        frame(
+            &[
+                (":event-type", event_type),
+                (":content-type", "application/json"),
+                (":message-type", "event"),
+            ],
+            payload,
+        )
+    }
+
+    let upstream = [
+        event("messageStart", r#"{"role":"assistant"}"#),
+        event("contentBlockDelta", r#"{"contentBlockIndex":0,"delta":{"text":"Hi"}}"#),
+        event("contentBlockDelta", r#"{"contentBlockIndex":0,"delta":{"text":" there"}}"#),
+        event("contentBlockStop", r#"{"contentBlockIndex":0}"#),
+        event(
+            "contentBlockStart",
+            r#"{"contentBlockIndex":1,"start":{"toolUse":{"toolUseId":"tooluse_3","name":"lookup"}}}"#,
+        ),
+        event(
+            "contentBlockDelta",
+            r#"{"contentBlockIndex":1,"delta":{"toolUse":{"input":"{\"q\":1}"}}}"#,
+        ),
+        event("messageStop", r#"{"stopReason":"tool_use"}"#),
+        event(
+            "metadata",
+            r#"{"usage":{"inputTokens":11,"outputTokens":6,"totalTokens":17},"metrics":{"latencyMs":300}}"#,
+        ),
+    ]
+    .concat();
+
+    let mut translator = StreamTranslator::new(Dialect::BedrockConverse, "claude-sonnet");
+    let mut body = Vec::new();
+    for chunk in upstream.chunks(5) {
+        body.extend(translator.feed(chunk));
+    }
+    body.extend(translator.finish());
+
+    let events = sse_events(&body);
+    // Converse names no model: the requested one is reported
+    assert!(
+        events[..events.len() - 1]
+            .iter()
+            .all(|event| event["model"] == "claude-sonnet")
+    );
+    let deltas: Vec<&Value> = events
+        .iter()
+        .filter_map(|event| event["choices"].get(0))
+        .collect();
+    assert_eq!(deltas[0]["delta"]["role"], "assistant");
+    assert_eq!(deltas[1]["delta"], json!({"content": "Hi"}));
+    assert_eq!(deltas[2]["delta"], json!({"content": " there"}));
+    assert_eq!(deltas[3]["delta"]["tool_calls"][0]["id"], "tooluse_3");
+    assert_eq!(
+        deltas[4]["delta"]["tool_calls"][0]["function"]["arguments"],
+        "{\"q\":1}"
+    );
+    assert_eq!(deltas[5]["finish_reason"], "tool_calls");
+    assert_eq!(
+        events[events.len() - 2]["usage"],
+        json!({"prompt_tokens": 11, "completion_tokens": 6, "total_tokens": 17})
+    );
+    assert_eq!(events.last().unwrap(), "[DONE]");
+
+    let mut translator = StreamTranslator::new(Dialect::BedrockConverse, "claude-sonnet");
+    let body = translator.feed(&frame(
+        &[
+            (":exception-type", "throttlingException"),
+            (":content-type", "application/json"),
+            (":message-type", "exception"),
+        ],
+        r#"{"message":"Too many requests"}"#,
+    ));
+    let events = sse_events(&body);
+    // Typed as OpenAI types the error, so SDKs retry it as a rate limit
+    assert_eq!(events[0]["error"]["type"], "rate_limit_error");
+    assert_eq!(events[0]["error"]["code"], "rate_limit_exceeded");
+    assert_eq!(events[0]["error"]["message"], "Too many requests");
+    assert_eq!(events[1], "[DONE]");
+
+    // A stream cut short still ends for the client
+    let mut translator = StreamTranslator::new(Dialect::BedrockConverse, "claude-sonnet");
+    translator.feed(&event("messageStart", r#"{"role":"assistant"}"#));
+    let events = sse_events(&translator.finish());
+    assert_eq!(events.last().unwrap(), "[DONE]");
+}